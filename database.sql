@@ -1,30 +1,118 @@
 CREATE TABLE users (
     user_id BIGSERIAL PRIMARY KEY,
     password_hash TEXT,
-    email TEXT NOT NULL
+    email TEXT NOT NULL,
+    -- Whether to refer to the user in reminders without mentioning them.
+    mentions_disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Whether to DM the user a summary of their week's reminders on Monday
+    -- mornings, and the timezone to use for it.
+    weekly_summary BOOLEAN NOT NULL DEFAULT FALSE,
+    weekly_summary_timezone TEXT,
+    -- The DM room we send the weekly summary and other notices to, once
+    -- created.
+    weekly_summary_room_id TEXT,
+    -- The Monday of the last week we sent a summary for.
+    weekly_summary_sent_for DATE,
+    -- The user's timezone, for showing them event start times in reminders.
+    timezone TEXT,
+    -- When the user asked for their account to be deleted. The account is
+    -- deleted once the grace period has passed, unless they cancel.
+    deletion_requested_at TIMESTAMP WITH TIME ZONE,
+    -- Whether the user can use the admin pages and APIs.
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE UNIQUE INDEX ON users(email);
 
+CREATE TABLE user_emails (
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    email TEXT NOT NULL,
+    verified BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE UNIQUE INDEX ON user_emails(email);
+CREATE INDEX ON user_emails(user_id);
+
 CREATE TABLE calendars (
     calendar_id BIGSERIAL PRIMARY KEY,
     user_id bigint NOT NULL REFERENCES users(user_id),
     name TEXT NOT NULL,
-    url text NOT NULL
+    url text NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- How to fetch the calendar: 'caldav', 'ics' (a plain .ics feed), 'ews'
+    -- (Exchange Web Services), 'google' (the Google Calendar API) or 'static'
+    -- (an uploaded .ics file, stored in `calendar_files`).
+    kind TEXT NOT NULL DEFAULT 'caldav',
+    deleted_at TIMESTAMP WITH TIME ZONE,
+    -- The RFC 6578 sync token of CalDAV calendars, if the server supports it,
+    -- and when we last refetched the whole calendar.
+    sync_token TEXT,
+    last_full_sync TIMESTAMP WITH TIME ZONE,
+    -- The timezone to assume for floating events, e.g. 'Europe/London'.
+    timezone TEXT,
+    -- Extra PEM encoded root certificates to trust when fetching the calendar.
+    ca_certificate TEXT,
+    -- Whether to copy reminders onto new events that look like they replace
+    -- an old one (e.g. FastMail creates a new event when editing the times of
+    -- future occurrences).
+    port_duplicate_reminders BOOLEAN NOT NULL DEFAULT TRUE,
+    -- How many days ahead to sync events for, and back to fetch them from, if
+    -- different from the config.
+    sync_lookahead_days BIGINT,
+    sync_lookback_days BIGINT,
+    -- The period the stored `next_dates` cover, as of the last sync.
+    synced_from TIMESTAMP WITH TIME ZONE,
+    synced_until TIMESTAMP WITH TIME ZONE,
+    -- Whether to skip events marked as free (`TRANSP:TRANSPARENT`).
+    ignore_transparent BOOLEAN NOT NULL DEFAULT FALSE,
+    -- When the calendar's reminders are muted, e.g. during an offsite. If
+    -- `muted_from` is NULL they're muted from now until `muted_until`.
+    muted_from TIMESTAMP WITH TIME ZONE,
+    muted_until TIMESTAMP WITH TIME ZONE
 );
 
 CREATE TABLE calendar_passwords (
     calendar_id BIGINT NOT NULL REFERENCES calendars(calendar_id),
     user_name TEXT NOT NULL,
-    password TEXT NOT NULL
+    password TEXT NOT NULL,
+    -- Whether to use Digest rather than Basic auth.
+    digest BOOLEAN NOT NULL DEFAULT FALSE
 );
 
 CREATE UNIQUE INDEX ON calendar_passwords(calendar_id);
 
+-- The uploaded .ics files of static calendars.
+CREATE TABLE calendar_files (
+    calendar_id BIGINT PRIMARY KEY REFERENCES calendars(calendar_id),
+    body TEXT NOT NULL,
+    uploaded_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+-- The objects in CalDAV calendars that we incrementally sync, by href.
+CREATE TABLE calendar_objects (
+    calendar_id BIGINT NOT NULL REFERENCES calendars(calendar_id),
+    href TEXT NOT NULL,
+    body TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ON calendar_objects(calendar_id, href);
+
+
+CREATE TABLE calendar_errors (
+    calendar_id BIGINT NOT NULL REFERENCES calendars(calendar_id),
+    uid TEXT,
+    message TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX ON calendar_errors(calendar_id);
+
 
 CREATE TYPE "Attendee" AS (
     email TEXT,
-    common_name TEXT
+    common_name TEXT,
+    -- The attendee's PARTSTAT, e.g. ACCEPTED or DECLINED, if known.
+    status TEXT
 );
 
 
@@ -35,7 +123,10 @@ CREATE TABLE events (
     description text,
     location text,
     organizer "Attendee",
-    attendees "Attendee"[] NOT NULL
+    attendees "Attendee"[] NOT NULL,
+    conference_url text,
+    duration_minutes bigint,
+    last_occurrence timestamp with time zone
 );
 
 CREATE UNIQUE INDEX ON events USING btree (calendar_id, event_id);
@@ -52,6 +143,19 @@ CREATE TABLE next_dates (
 CREATE INDEX ON next_dates USING btree (calendar_id, event_id);
 
 
+-- Named templates that users can pick for their reminders, optionally shared
+-- with everyone else.
+CREATE TABLE templates (
+    template_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    name TEXT NOT NULL,
+    template TEXT NOT NULL,
+    shared BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+CREATE INDEX ON templates(user_id);
+
+
 CREATE TABLE reminders (
     reminder_id BIGSERIAL PRIMARY KEY,
     user_id bigint NOT NULL REFERENCES users(user_id),
@@ -61,21 +165,246 @@ CREATE TABLE reminders (
     minutes_before bigint NOT NULL,
     template text,
     attendee_editable boolean NOT NULL,
+    extra_attendees TEXT[] NOT NULL DEFAULT '{}',
+    excluded_attendees TEXT[] NOT NULL DEFAULT '{}',
+    threaded BOOLEAN NOT NULL DEFAULT FALSE,
+    thread_root_event_id TEXT,
+    poll BOOLEAN NOT NULL DEFAULT FALSE,
+    msgtype TEXT,
+    sender TEXT,
+    redact_previous BOOLEAN NOT NULL DEFAULT FALSE,
+    attach_ics BOOLEAN NOT NULL DEFAULT FALSE,
+    holiday_region TEXT,
+    skip_if_declined BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Whether to leave out attendees who have only tentatively accepted, or
+    -- haven't responded, when mentioning people.
+    exclude_tentative BOOLEAN NOT NULL DEFAULT FALSE,
+    exclude_needs_action BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Whether to keep editing the sent reminder with a countdown until the
+    -- event starts.
+    live_countdown BOOLEAN NOT NULL DEFAULT FALSE,
+    -- Paused reminders aren't sent, but keep their settings.
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    -- The days of the week to send the reminder on, as a bitmask where bit 0
+    -- is Monday, or NULL for every day.
+    weekdays INTEGER,
+    -- The rule that created the reminder, if any.
+    rule_id BIGINT,
+    -- The saved template to use, which takes precedence over `template`.
+    template_id BIGINT REFERENCES templates(template_id) ON DELETE SET NULL,
+    -- The language to render the reminder in, e.g. `de`, or NULL for the
+    -- room's language.
+    locale TEXT,
+    deleted_at TIMESTAMP WITH TIME ZONE,
     FOREIGN KEY (calendar_id, event_id) REFERENCES events (calendar_id, event_id)
 );
 
 CREATE INDEX ON reminders(event_id);
+CREATE UNIQUE INDEX ON reminders(calendar_id, event_id, rule_id);
+
+
+-- Images uploaded to the media repo for a reminder's template to include.
+CREATE TABLE reminder_images (
+    reminder_id BIGINT NOT NULL,
+    -- The name the template refers to the image by, e.g. `logo`.
+    name TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    -- The `mxc://` URI of the uploaded image.
+    content_uri TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ON reminder_images(reminder_id, name);
+
+
+-- Rules that automatically add reminders to events whose summary matches the
+-- pattern.
+CREATE TABLE reminder_rules (
+    rule_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    -- The calendar the rule applies to, or all the user's calendars if NULL.
+    calendar_id BIGINT REFERENCES calendars(calendar_id),
+    summary_pattern TEXT NOT NULL,
+    room TEXT NOT NULL,
+    minutes_before BIGINT NOT NULL
+);
+
+CREATE INDEX ON reminder_rules(user_id);
+
+
+-- Rooms that are told about new events appearing in a calendar.
+CREATE TABLE event_subscriptions (
+    subscription_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    calendar_id BIGINT NOT NULL REFERENCES calendars(calendar_id),
+    room TEXT NOT NULL,
+    -- Only events whose summary matches the pattern are posted, or all events
+    -- if NULL.
+    summary_pattern TEXT
+);
+
+CREATE INDEX ON event_subscriptions(user_id);
+CREATE INDEX ON event_subscriptions(calendar_id);
+
+
+-- One-off reminders that aren't for a calendar event. These share IDs with
+-- `reminders`, so that they can be sent, recorded and snoozed in the same way.
+CREATE TABLE adhoc_reminders (
+    reminder_id BIGINT PRIMARY KEY DEFAULT nextval('reminders_reminder_id_seq'),
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    room TEXT NOT NULL,
+    message TEXT NOT NULL,
+    remind_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE INDEX ON adhoc_reminders(remind_at);
+CREATE INDEX ON adhoc_reminders(user_id);
+
+
+-- Reminders that have been sent for events that haven't started yet, so that
+-- we can edit them if the event changes.
+CREATE TABLE sent_reminders (
+    reminder_id BIGINT NOT NULL,
+    "timestamp" TIMESTAMP WITH TIME ZONE NOT NULL,
+    room_id TEXT NOT NULL,
+    matrix_event_id TEXT NOT NULL,
+    summary TEXT,
+    location TEXT,
+    -- The configured Matrix account that sent the reminder, or NULL for the
+    -- main account.
+    sender TEXT
+);
+
+CREATE UNIQUE INDEX ON sent_reminders(reminder_id, "timestamp");
+CREATE INDEX ON sent_reminders(matrix_event_id);
+
+
+-- Every attempt to send a reminder, whether it succeeded or not, so that users
+-- can debug missing reminders.
+CREATE TABLE reminder_log (
+    reminder_id BIGINT NOT NULL,
+    -- The start of the event instance the reminder was for.
+    "timestamp" TIMESTAMP WITH TIME ZONE NOT NULL,
+    attempted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    room TEXT NOT NULL,
+    -- Set if the reminder was sent.
+    matrix_event_id TEXT,
+    -- Set if the reminder failed to send.
+    error TEXT
+);
+
+CREATE INDEX ON reminder_log(reminder_id, attempted_at);
+
+
+-- Reminders that failed to send and are waiting to be retried.
+CREATE TABLE reminder_retries (
+    reminder_id BIGINT NOT NULL,
+    "timestamp" TIMESTAMP WITH TIME ZONE NOT NULL,
+    first_failed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    attempts INTEGER NOT NULL DEFAULT 1,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL,
+    last_error TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ON reminder_retries(reminder_id, "timestamp");
+CREATE INDEX ON reminder_retries(next_attempt_at);
+
+
+-- URLs that get POSTed signed JSON payloads when the given types of events
+-- happen, e.g. a reminder is sent.
+CREATE TABLE webhooks (
+    webhook_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    url TEXT NOT NULL,
+    -- The key used to sign the payloads.
+    secret TEXT NOT NULL,
+    event_types TEXT[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX ON webhooks(user_id);
+
+-- Payloads waiting to be delivered to webhooks, including those being
+-- retried.
+CREATE TABLE webhook_deliveries (
+    delivery_id BIGSERIAL PRIMARY KEY,
+    webhook_id BIGINT NOT NULL REFERENCES webhooks(webhook_id) ON DELETE CASCADE,
+    event_type TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_error TEXT
+);
+
+CREATE INDEX ON webhook_deliveries(next_attempt_at);
+CREATE INDEX ON webhook_deliveries(webhook_id);
+
+
+-- The most recent message sent for each reminder, so that it can be redacted
+-- when the next one is sent.
+CREATE TABLE last_sent_reminders (
+    reminder_id BIGINT PRIMARY KEY,
+    room_id TEXT NOT NULL,
+    matrix_event_id TEXT NOT NULL,
+    sender TEXT
+);
+
+
+-- Who has said they will or won't attend an event, by reacting to its
+-- reminder.
+CREATE TABLE attendance (
+    reminder_id BIGINT NOT NULL,
+    "timestamp" TIMESTAMP WITH TIME ZONE NOT NULL,
+    matrix_id TEXT NOT NULL,
+    attending BOOLEAN NOT NULL,
+    reaction_event_id TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ON attendance(reminder_id, "timestamp", matrix_id);
+CREATE INDEX ON attendance(reaction_event_id);
+
+
+-- Attendance polls posted along with reminders.
+CREATE TABLE reminder_polls (
+    reminder_id BIGINT NOT NULL,
+    "timestamp" TIMESTAMP WITH TIME ZONE NOT NULL,
+    room_id TEXT NOT NULL,
+    poll_event_id TEXT NOT NULL
+);
+
+CREATE UNIQUE INDEX ON reminder_polls(poll_event_id);
+CREATE INDEX ON reminder_polls(reminder_id);
+
+-- The latest answer of each person that has responded to a poll.
+CREATE TABLE poll_responses (
+    poll_event_id TEXT NOT NULL,
+    matrix_id TEXT NOT NULL,
+    answer TEXT NOT NULL,
+    responded_at TIMESTAMP WITH TIME ZONE NOT NULL
+);
+
+CREATE UNIQUE INDEX ON poll_responses(poll_event_id, matrix_id);
 
 
 
 
 CREATE TABLE email_to_matrix_id (
-    email TEXT PRIMARY KEY,
-    matrix_id TEXT NOT NULL
+    email TEXT NOT NULL,
+    matrix_id TEXT NOT NULL,
+    preferred BOOLEAN NOT NULL DEFAULT FALSE
 );
 
+CREATE UNIQUE INDEX ON email_to_matrix_id(email, matrix_id);
+CREATE UNIQUE INDEX ON email_to_matrix_id(email) WHERE preferred;
 CREATE INDEX ON email_to_matrix_id(matrix_id);
 
+-- Failed authentication attempts, when rate limiting with the database
+-- backend.
+CREATE TABLE rate_limits (
+    key TEXT PRIMARY KEY,
+    window_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    attempts INTEGER NOT NULL
+);
+
 CREATE TABLE access_tokens (
     access_token_id BIGSERIAL PRIMARY KEY,
     user_id BIGINT NOT NULL REFERENCES users(user_id),
@@ -84,6 +413,81 @@ CREATE TABLE access_tokens (
 );
 
 CREATE UNIQUE INDEX ON access_tokens (token);
+CREATE INDEX ON access_tokens (expiry);
+
+-- Long-lived personal access tokens for the API, limited to a set of scopes.
+-- Only a hash of the token is stored.
+CREATE TABLE api_tokens (
+    api_token_id BIGSERIAL PRIMARY KEY,
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    last_used TIMESTAMP WITH TIME ZONE
+);
+
+CREATE UNIQUE INDEX ON api_tokens (token_hash);
+CREATE INDEX ON api_tokens (user_id);
+
+
+-- The secret token in the URL of each user's ICS feed of the events they
+-- have reminders for. Calendar clients can't send cookies.
+CREATE TABLE feed_tokens (
+    user_id BIGINT PRIMARY KEY REFERENCES users(user_id),
+    token TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE UNIQUE INDEX ON feed_tokens (token);
+
+-- Tokens that give read only access to the widget for a room, as widgets
+-- can't rely on the login cookie. Each user has at most one per room.
+CREATE TABLE widget_tokens (
+    user_id BIGINT NOT NULL REFERENCES users(user_id),
+    room TEXT NOT NULL,
+    token TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX ON widget_tokens (token);
+CREATE UNIQUE INDEX ON widget_tokens (user_id, room);
+
+
+-- Public holidays, fetched from the configured feed for each region.
+CREATE TABLE public_holidays (
+    region TEXT NOT NULL,
+    date DATE NOT NULL,
+    name TEXT
+);
+
+CREATE UNIQUE INDEX ON public_holidays (region, date);
+
+
+-- Quiet hours set for a room, overriding the ones in the config.
+CREATE TABLE room_quiet_hours (
+    room TEXT NOT NULL PRIMARY KEY,
+    start_time TIME,
+    end_time TIME,
+    weekends BOOLEAN NOT NULL DEFAULT FALSE,
+    timezone TEXT NOT NULL DEFAULT 'UTC',
+    suppress BOOLEAN NOT NULL DEFAULT FALSE
+);
+
+
+-- The default template for reminders sent to a room, used for reminders that
+-- don't set their own.
+CREATE TABLE room_templates (
+    room TEXT NOT NULL PRIMARY KEY,
+    template TEXT NOT NULL
+);
+
+-- The language to render reminders sent to a room in, for reminders that
+-- don't pick one.
+CREATE TABLE room_locales (
+    room TEXT NOT NULL PRIMARY KEY,
+    locale TEXT NOT NULL
+);
 
 
 CREATE TABLE out_today (
@@ -96,7 +500,9 @@ CREATE UNIQUE INDEX ON out_today ( email );
 CREATE TABLE sso_sessions (
     crsf_token TEXT NOT NULL,
     nonce TEXT NOT NULL,
-    code_verifier TEXT NOT NULL
+    code_verifier TEXT NOT NULL,
+    -- Used to clean up sessions where the user never finished logging in.
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
 );
 
 CREATE UNIQUE INDEX ON sso_sessions(crsf_token);
@@ -106,7 +512,8 @@ CREATE TABLE oauth2_sessions (
     user_id BIGINT NOT NULL REFERENCES users(user_id),
     crsf_token TEXT NOT NULL,
     code_verifier TEXT NOT NULL,
-    path TEXT NOT NULL
+    path TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW()
 );
 
 CREATE UNIQUE INDEX ON oauth2_sessions(crsf_token);
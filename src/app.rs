@@ -1,18 +1,19 @@
 //! The high level app.
 
 use std::{
-    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
     error::Error as StdError,
     ops::Deref,
     panic::AssertUnwindSafe,
     sync::{Arc, Mutex},
 };
 
-use anyhow::{bail, Context, Error};
-use chrono::{DateTime, Duration, NaiveDate, Utc};
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike, Utc, Weekday};
 use chrono_humanize::{Accuracy, HumanTime, Tense};
+use chrono_tz::Tz;
 use comrak::{markdown_to_html, ComrakOptions};
-use futures::{future, Future, FutureExt};
+use futures::{future, stream, Future, FutureExt, StreamExt};
 use handlebars::Handlebars;
 use ics_parser::property::EndCondition;
 use itertools::Itertools;
@@ -26,10 +27,11 @@ use openidconnect::{
 use rand::{distributions::Alphanumeric, Rng};
 use sentry::integrations::anyhow::capture_anyhow;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
 use tera::Tera;
 use tokio::{
     sync::Notify,
+    task::spawn_local,
     time::{interval, sleep},
 };
 use tracing::{error, info, instrument, warn, Span};
@@ -37,12 +39,79 @@ use url::Url;
 use urlencoding::encode;
 
 use crate::{
-    calendar::{fetch_calendars, parse_calendars_to_events},
-    config::HiBobConfig,
-    database::{OAuth2Result, ReminderInstance},
+    auth::hash_api_token,
+    calendar::{
+        build_calendar_client, decode_calendars, fetch_caldav_objects, fetch_calendars,
+        parse_calendars_to_events, parse_location, read_response_text, reminder_to_ics,
+        sync_caldav_objects, EventLocation, FetchedCalendars, SyncWindow,
+        DEFAULT_SYNC_LOOKAHEAD_DAYS, DEFAULT_SYNC_LOOKBACK_DAYS,
+    },
+    config::{HiBobConfig, MatrixCredentials, PublicHolidaysConfig},
+    database::{
+        Attendee, BulkReminderResult, CalendarError, CalendarKind, Event, EventInstance,
+        OAuth2Result, Reminder, ReminderInstance, ReminderRetry, SentReminder, WebhookDelivery,
+        WeeklySummaryUser,
+    },
+    email::{render_email, Mailer},
+    holidays::parse_public_holidays,
+    locale::{default_locale, get_locale},
+    quiet_hours::{QuietHours, QuietHoursAction},
+    rate_limit::RateLimiter,
+    reminder_export::{
+        pick_calendar, ExportedReminder, ImportResult, ReminderExport, SkippedReminder,
+        REMINDER_EXPORT_VERSION,
+    },
+    rules::compile_summary_pattern,
+    template_helpers::{attendee_times, reminder_handlebars},
+    webhooks::{
+        sign_webhook_payload, webhook_client, webhook_retry_backoff, WEBHOOK_MAX_ATTEMPTS,
+        WEBHOOK_SIGNATURE_HEADER,
+    },
 };
-use crate::{config::Config, database::Database};
-use crate::{database::Calendar, DEFAULT_TEMPLATE};
+use crate::{
+    config::Config,
+    database::Database,
+    ews::fetch_ews_events,
+    google::{fetch_google_events, google_events_url},
+    systemd,
+};
+use crate::{
+    database::Calendar, DEFAULT_TEMPLATE, EVENT_CHANGED_TEMPLATE, WEEKLY_SUMMARY_TEMPLATE,
+};
+
+/// The event types for polls. We use the unstable types as not all clients
+/// support the stable ones yet.
+const POLL_START_TYPE: &str = "org.matrix.msc3381.poll.start";
+const POLL_RESPONSE_TYPE: &str = "org.matrix.msc3381.poll.response";
+
+/// The reactions people can use on a reminder to say whether they'll attend.
+const ATTENDING_REACTION: &str = "✅";
+const NOT_ATTENDING_REACTION: &str = "❌";
+
+/// The possible answers to attendance polls, as `(id, text)`.
+const POLL_ANSWERS: &[(&str, &str)] = &[("yes", "Yes"), ("no", "No"), ("late", "Late")];
+
+/// How often we refetch the whole of a CalDAV calendar, even if the server
+/// supports incremental sync, in case we've missed any changes.
+const FULL_CALDAV_SYNC_INTERVAL_HOURS: i64 = 24;
+
+/// The local hour on Mondays after which we send weekly summaries.
+const WEEKLY_SUMMARY_HOUR: u32 = 9;
+
+/// How long we keep entries in the reminder log for.
+const REMINDER_LOG_RETENTION_DAYS: i64 = 30;
+
+/// How long users have to come back from an SSO or OAuth2 provider before we
+/// forget the login attempt.
+const LOGIN_SESSION_TIMEOUT_HOURS: i64 = 1;
+
+/// How long we wait before retrying a reminder that failed to send. This
+/// doubles after every failed attempt.
+const REMINDER_RETRY_INITIAL_BACKOFF_SECONDS: i64 = 30;
+
+/// How long after a reminder first fails to send we keep retrying it, after
+/// which we give up.
+const REMINDER_RETRY_WINDOW_MINUTES: i64 = 30;
 
 /// The type of the OpenID Connect client.
 type OpenIDClient = openidconnect::Client<
@@ -83,6 +152,19 @@ type ReminderInner = Arc<Mutex<VecDeque<(DateTime<Utc>, ReminderInstance)>>>;
 #[derive(Debug, Clone, Default)]
 pub struct Reminders {
     inner: ReminderInner,
+    /// Reminders that have been snoozed. These are kept separately so that
+    /// they survive the reminders being reloaded from the DB.
+    snoozed: ReminderInner,
+}
+
+/// How an event changed when its calendar was synced, see
+/// [`detect_event_changes`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventChange {
+    /// The old and new start times of the next instance, if it moved.
+    pub moved: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// The new location, if it changed.
+    pub location: Option<Option<String>>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -149,11 +231,22 @@ struct GoogleUserInfoResponse {
 }
 
 impl Reminders {
+    /// The number of reminders waiting to be sent, including snoozed ones.
+    fn count(&self) -> usize {
+        self.inner.lock().expect("poisoned").len() + self.snoozed.lock().expect("poisoned").len()
+    }
+
     /// Get how long until the next reminder needs to be sent.
     fn get_time_to_next(&self) -> Option<Duration> {
         let inner = self.inner.lock().expect("poisoned");
-
-        inner.front().map(|(t, _)| *t - Utc::now())
+        let snoozed = self.snoozed.lock().expect("poisoned");
+
+        inner
+            .front()
+            .into_iter()
+            .chain(snoozed.front())
+            .map(|(t, _)| *t - Utc::now())
+            .min()
     }
 
     /// Pop all reminders that are ready to be sent now.
@@ -173,9 +266,49 @@ impl Reminders {
             }
         }
 
+        let mut snoozed = self.snoozed.lock().expect("poisoned");
+        while let Some((date, reminder)) = snoozed.pop_front() {
+            if date <= now {
+                due_reminders.push(reminder);
+            } else {
+                snoozed.push_front((date, reminder));
+                break;
+            }
+        }
+
         due_reminders
     }
 
+    /// Snooze the reminder, so that it gets sent again at the given time.
+    /// Returns false if the reminder has already been snoozed.
+    fn snooze(&self, date: DateTime<Utc>, mut reminder: ReminderInstance) -> bool {
+        let mut snoozed = self.snoozed.lock().expect("poisoned");
+
+        if snoozed.iter().any(|(_, r)| {
+            r.reminder_id == reminder.reminder_id && r.timestamp == reminder.timestamp
+        }) {
+            return false;
+        }
+
+        reminder.snoozed_until = Some(date);
+
+        let index = snoozed.partition_point(|(t, _)| *t <= date);
+        snoozed.insert(index, (date, reminder));
+
+        true
+    }
+
+    /// Put off sending the reminder until the given time, e.g. because it's
+    /// due during quiet hours.
+    ///
+    /// Unlike snoozing, the reminder is only sent once.
+    fn defer(&self, date: DateTime<Utc>, reminder: ReminderInstance) {
+        let mut snoozed = self.snoozed.lock().expect("poisoned");
+
+        let index = snoozed.partition_point(|(t, _)| *t <= date);
+        snoozed.insert(index, (date, reminder));
+    }
+
     /// Replace the current set of reminders
     fn replace(&self, reminders: VecDeque<(DateTime<Utc>, ReminderInstance)>) {
         let mut inner = self.inner.lock().expect("poisoned");
@@ -184,22 +317,85 @@ impl Reminders {
     }
 }
 
+#[derive(Debug, Deserialize)]
+struct MatrixCreateRoomResponse {
+    room_id: String,
+}
+
 #[derive(Debug, Deserialize)]
 struct MatrixJoinResponse {
     room_id: String,
 }
 
+#[derive(Debug, Deserialize)]
+struct MatrixSendResponse {
+    event_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixUploadResponse {
+    content_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixWhoamiResponse {
+    user_id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct MatrixPowerLevels {
+    #[serde(default)]
+    users: HashMap<String, i64>,
+    #[serde(default)]
+    users_default: i64,
+    #[serde(default)]
+    events: HashMap<String, i64>,
+    #[serde(default)]
+    events_default: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixHierarchyResponse {
+    rooms: Vec<MatrixHierarchyRoom>,
+    next_batch: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MatrixHierarchyRoom {
+    room_id: String,
+    name: Option<String>,
+    canonical_alias: Option<String>,
+    room_type: Option<String>,
+}
+
+/// A room in the configured space.
+#[derive(Debug, Clone, Serialize)]
+pub struct SpaceRoom {
+    pub room_id: String,
+    pub name: Option<String>,
+    pub canonical_alias: Option<String>,
+}
+
 /// The high level app.
 #[derive(Debug, Clone)]
 pub struct App {
     pub config: Config,
     pub http_client: reqwest::Client,
+    /// The client used to fetch calendars, with the configured TLS options.
+    pub calendar_http_client: reqwest::Client,
     pub database: Database,
     pub notify_db_update: Arc<Notify>,
     pub reminders: Reminders,
-    pub email_to_matrix_id: Arc<Mutex<BTreeMap<String, String>>>,
+    /// When the reminder loop last woke up, used to check that it hasn't got
+    /// stuck before pinging the systemd watchdog.
+    pub reminder_loop_heartbeat: Arc<Mutex<DateTime<Utc>>>,
+    /// Maps emails to their Matrix IDs, with the preferred Matrix ID first.
+    pub email_to_matrix_id: Arc<Mutex<BTreeMap<String, Vec<String>>>>,
     pub hibob_id_to_email: Arc<Mutex<BTreeMap<String, String>>>,
     pub templates: Tera,
+    pub rate_limiter: RateLimiter,
+    /// Sends emails, if an SMTP server is configured.
+    pub mailer: Option<Mailer>,
     sso_client: Option<OpenIDClient>,
     google_client: Option<BasicClient>,
 }
@@ -208,9 +404,25 @@ impl App {
     pub async fn new(config: Config, database: Database, templates: Tera) -> Result<Self, Error> {
         let notify_db_update = Default::default();
         let reminders = Default::default();
+        let reminder_loop_heartbeat = Arc::new(Mutex::new(Utc::now()));
         let email_to_matrix_id = Default::default();
         let hibob_id_to_email = Default::default();
+        let rate_limiter = RateLimiter::new(config.rate_limit.clone());
+        let mailer = config
+            .email
+            .as_ref()
+            .map(Mailer::new)
+            .transpose()
+            .context("Invalid email config")?;
         let http_client = Default::default();
+        let calendar_http_client = build_calendar_client(&config.tls, &config.fetch, None)
+            .context("Failed to build calendar HTTP client")?;
+
+        if let Some(quiet_hours) = &config.quiet_hours {
+            quiet_hours
+                .validate()
+                .context("Invalid quiet_hours config")?;
+        }
 
         // Set up SSO
         let sso_client = if let Some(sso_config) = &config.sso {
@@ -260,11 +472,15 @@ impl App {
         Ok(Self {
             config,
             http_client,
+            calendar_http_client,
             database,
             notify_db_update,
             reminders,
+            reminder_loop_heartbeat,
             email_to_matrix_id,
             templates,
+            rate_limiter,
+            mailer,
             sso_client,
             hibob_id_to_email,
             google_client,
@@ -273,12 +489,29 @@ impl App {
 
     /// Start the background jobs, including sending reminders and updating calendars.
     pub async fn run(self) {
+        if let Err(err) = self.register_appservice_users().await {
+            capture_anyhow(&err);
+            error!(
+                error = err.deref() as &dyn StdError,
+                "Failed to register appservice users"
+            );
+        }
+
         tokio::select!(
             _ = self.update_calendar_loop() => { error!("Update calendar loop exited!") },
             _ = self.reminder_loop() => { error!("Reminder loop exited!") },
+            _ = self.reminder_retry_loop() => { error!("Reminder retry loop exited!") },
+            _ = self.webhook_delivery_loop() => { error!("Webhook delivery loop exited!") },
             _ = self.update_mappings_loop() => { error!("Update mappings loop exited!") },
             _ = self.hibob_loop() => { error!("Hibob loop exited!") },
             _ = self.refresh_oauth2_tokens() => { error!("Refresh oauth2 token loop exited!") },
+            _ = self.purge_deleted_loop() => { error!("Purge deleted loop exited!") },
+            _ = self.cleanup_expired_loop() => { error!("Cleanup expired loop exited!") },
+            _ = self.watchdog_loop() => { error!("Watchdog loop exited!") },
+            _ = self.sync_loop() => { error!("Sync loop exited!") },
+            _ = self.presence_loop() => { error!("Presence loop exited!") },
+            _ = self.public_holidays_loop() => { error!("Public holidays loop exited!") },
+            _ = self.weekly_summary_loop() => { error!("Weekly summary loop exited!") },
         );
 
         // One of the infinite loops terminated, immediately shutdown (so that
@@ -291,43 +524,383 @@ impl App {
     pub async fn update_calendars(&self) -> Result<(), Error> {
         let db_calendars = self.database.get_calendars().await?;
 
-        for db_calendar in db_calendars {
-            let calendar_id = db_calendar.calendar_id;
-            if let Err(error) = self.update_calendar(db_calendar).await {
-                capture_anyhow(&error);
+        // We fetch calendars concurrently so that one slow server doesn't
+        // delay everyone else's calendars.
+        let max_concurrent_fetches = self.config.fetch.max_concurrent_fetches();
+
+        stream::iter(db_calendars)
+            .for_each_concurrent(max_concurrent_fetches, |db_calendar| {
+                self.update_calendar_and_record_errors(db_calendar)
+            })
+            .await;
+
+        Ok(())
+    }
+
+    /// Update the calendar, recording any failure against the calendar rather
+    /// than returning it.
+    pub async fn update_calendar_and_record_errors(&self, db_calendar: Calendar) {
+        let calendar_id = db_calendar.calendar_id;
+        if !db_calendar.enabled {
+            info!(calendar_id, "Skipping paused calendar");
+            return;
+        }
+
+        if let Err(error) = self.update_calendar(db_calendar).await {
+            capture_anyhow(&error);
+            error!(
+                error = error.deref() as &dyn StdError,
+                calendar_id, "Failed to update calendar"
+            );
+
+            // Record the failure against the calendar so that its owner
+            // can see why it's not updating.
+            let calendar_error = CalendarError {
+                uid: None,
+                message: format!("Failed to fetch calendar: {:#}", error),
+            };
+            if let Err(error) = self
+                .database
+                .set_calendar_errors(calendar_id, &[calendar_error])
+                .await
+            {
                 error!(
                     error = error.deref() as &dyn StdError,
-                    calendar_id, "Failed to update calendar"
+                    calendar_id, "Failed to record calendar error"
                 );
             }
         }
+    }
+
+    /// Add reminders to the calendar's events that match the user's rules, or
+    /// update them if the rule has changed.
+    pub async fn apply_reminder_rules(
+        &self,
+        calendar_id: i64,
+        event_summaries: &[(String, Option<String>)],
+    ) -> Result<(), Error> {
+        let rules = self
+            .database
+            .get_reminder_rules_for_calendar(calendar_id)
+            .await?;
+
+        for rule in rules {
+            let pattern = match compile_summary_pattern(&rule.summary_pattern) {
+                Ok(pattern) => pattern,
+                Err(error) => {
+                    // We validate patterns when rules are added, so this
+                    // shouldn't happen.
+                    warn!(
+                        error = error.deref() as &dyn StdError,
+                        rule_id = rule.rule_id,
+                        "Invalid reminder rule pattern"
+                    );
+                    continue;
+                }
+            };
+
+            for (event_id, summary) in event_summaries {
+                let summary = if let Some(summary) = summary {
+                    summary
+                } else {
+                    continue;
+                };
+
+                if !pattern.is_match(summary) {
+                    continue;
+                }
+
+                if self
+                    .database
+                    .upsert_rule_reminder(&rule, calendar_id, event_id)
+                    .await?
+                {
+                    info!(
+                        calendar_id,
+                        event_id = event_id.deref(),
+                        rule_id = rule.rule_id,
+                        "Added reminder from rule"
+                    );
+                }
+            }
+        }
 
         Ok(())
     }
 
+    /// Add a reminder in the room to each of the user's upcoming events,
+    /// optionally only those in the calendar and whose summary matches the
+    /// pattern, or update the user's existing reminders in the room.
+    pub async fn bulk_upsert_reminders(
+        &self,
+        user_id: i64,
+        calendar_id: Option<i64>,
+        summary_pattern: Option<&str>,
+        room: &str,
+        minutes_before: i64,
+    ) -> Result<BulkReminderResult, Error> {
+        let pattern = summary_pattern.map(compile_summary_pattern).transpose()?;
+
+        let events = self
+            .database
+            .get_events_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|(event, _)| event)
+            .filter(|event| calendar_id.is_none_or(|id| id == event.calendar_id))
+            .filter(|event| match (&pattern, &event.summary) {
+                (Some(pattern), Some(summary)) => pattern.is_match(summary),
+                (Some(_), None) => false,
+                (None, _) => true,
+            })
+            .map(|event| (event.calendar_id, event.event_id))
+            .collect_vec();
+
+        let result = self
+            .database
+            .bulk_upsert_reminders(user_id, &events, room, minutes_before)
+            .await?;
+
+        info!(
+            user_id,
+            created = result.created.len(),
+            updated = result.updated.len(),
+            "Bulk updated reminders"
+        );
+
+        self.update_reminders().await?;
+
+        Ok(result)
+    }
+
+    /// Get the HTTP client to fetch the calendar with.
+    fn calendar_client(&self, db_calendar: &Calendar) -> Result<reqwest::Client, Error> {
+        if let Some(pem) = &db_calendar.ca_certificate {
+            build_calendar_client(&self.config.tls, &self.config.fetch, Some(pem))
+        } else {
+            Ok(self.calendar_http_client.clone())
+        }
+    }
+
+    /// Get the window to sync the calendar's events over, using the
+    /// calendar's settings if it has any and the config's otherwise.
+    pub fn sync_window(&self, db_calendar: &Calendar) -> SyncWindow {
+        let lookback_days = db_calendar
+            .sync_lookback_days
+            .or(self.config.app.sync_lookback_days)
+            .unwrap_or(DEFAULT_SYNC_LOOKBACK_DAYS);
+        let lookahead_days = db_calendar
+            .sync_lookahead_days
+            .or(self.config.app.sync_lookahead_days)
+            .unwrap_or(DEFAULT_SYNC_LOOKAHEAD_DAYS);
+
+        SyncWindow::new(Utc::now(), lookback_days, lookahead_days)
+    }
+
+    /// Fetch the changes to a CalDAV calendar since we last synced it, falling
+    /// back to fetching everything if the server doesn't support sync tokens
+    /// or we haven't done so in a while.
+    async fn sync_caldav_calendar(
+        &self,
+        client: &reqwest::Client,
+        db_calendar: &Calendar,
+        window: &SyncWindow,
+        timezone: Option<Tz>,
+    ) -> Result<FetchedCalendars, Error> {
+        let calendar_id = db_calendar.calendar_id;
+
+        let sync_token = self
+            .database
+            .get_calendar_sync_token(calendar_id)
+            .await?
+            .filter(|(_, last_full_sync)| {
+                Utc::now() - *last_full_sync < Duration::hours(FULL_CALDAV_SYNC_INTERVAL_HOURS)
+            });
+
+        let mut changes = None;
+        if let Some((sync_token, _)) = sync_token {
+            changes = sync_caldav_objects(
+                client,
+                &db_calendar.url,
+                &db_calendar.authentication,
+                &sync_token,
+                self.config.fetch.max_response_bytes(),
+            )
+            .await?;
+        }
+
+        if let Some(changes) = changes {
+            info!(
+                calendar_id,
+                changed = changes.changed.len(),
+                removed = changes.removed.len(),
+                "Incrementally synced calendar"
+            );
+
+            self.database
+                .update_calendar_objects(
+                    calendar_id,
+                    changes.sync_token.as_deref(),
+                    &changes.changed,
+                    &changes.removed,
+                )
+                .await?;
+
+            let bodies = self.database.get_calendar_objects(calendar_id).await?;
+
+            Ok(decode_calendars(
+                bodies.iter().map(String::as_str),
+                timezone,
+            ))
+        } else {
+            let objects = fetch_caldav_objects(
+                client,
+                &db_calendar.url,
+                &db_calendar.authentication,
+                window,
+                self.config.fetch.max_response_bytes(),
+            )
+            .await?;
+
+            // There's no point storing the objects if we can't sync them.
+            let stored: &[_] = if objects.sync_token.is_some() {
+                &objects.changed
+            } else {
+                &[]
+            };
+
+            self.database
+                .replace_calendar_objects(calendar_id, objects.sync_token.as_deref(), stored)
+                .await?;
+
+            Ok(decode_calendars(
+                objects.changed.iter().map(|(_, body)| body.as_str()),
+                timezone,
+            ))
+        }
+    }
+
     /// Update the given calendar we fetched from the DB.
     #[instrument(skip(self))]
     pub async fn update_calendar(&self, db_calendar: Calendar) -> Result<(), Error> {
-        let calendars = fetch_calendars(
-            &self.http_client,
-            &db_calendar.url,
-            &db_calendar.authentication,
-        )
-        .await?;
+        // EWS and Google give us events directly, rather than ICS calendars.
+        let mut direct_events = None;
+
+        let client = self.calendar_client(&db_calendar)?;
+        let window = self.sync_window(&db_calendar);
+
+        // The timezone to use for floating events, and for events with a
+        // timezone we don't recognise.
+        let timezone: Option<Tz> = db_calendar
+            .timezone
+            .as_deref()
+            .and_then(|timezone| timezone.parse().ok());
+
+        let fetched = match db_calendar.kind {
+            CalendarKind::Static => {
+                // Static calendars are never refetched, but we still reparse
+                // them so that we pick up the next instances of recurring
+                // events.
+                let body = self
+                    .database
+                    .get_calendar_file(db_calendar.calendar_id)
+                    .await?
+                    .unwrap_or_default();
+
+                decode_calendars(std::iter::once(body.as_str()), timezone)
+            }
+            CalendarKind::Ews => {
+                direct_events = Some(
+                    fetch_ews_events(
+                        &client,
+                        &db_calendar.url,
+                        &db_calendar.authentication,
+                        db_calendar.calendar_id,
+                        &window,
+                        self.config.fetch.max_response_bytes(),
+                        db_calendar.ignore_transparent,
+                    )
+                    .await?,
+                );
+
+                FetchedCalendars::default()
+            }
+            CalendarKind::Google => {
+                direct_events = Some(
+                    fetch_google_events(
+                        &client,
+                        &db_calendar.url,
+                        &db_calendar.authentication,
+                        db_calendar.calendar_id,
+                        &window,
+                        self.config.fetch.max_response_bytes(),
+                        db_calendar.ignore_transparent,
+                    )
+                    .await?,
+                );
+
+                FetchedCalendars::default()
+            }
+            CalendarKind::CalDav => {
+                self.sync_caldav_calendar(&client, &db_calendar, &window, timezone)
+                    .await?
+            }
+            CalendarKind::Ics => {
+                fetch_calendars(
+                    &client,
+                    &db_calendar.url,
+                    &db_calendar.authentication,
+                    db_calendar.kind,
+                    &window,
+                    timezone,
+                    self.config.fetch.max_response_bytes(),
+                )
+                .await?
+            }
+        };
+
+        let errors = &fetched.errors;
+        let cancelled = &fetched.cancelled;
+
+        self.database
+            .set_calendar_errors(db_calendar.calendar_id, errors)
+            .await?;
 
         let mut vcalendar_by_id = HashMap::new();
         let mut vevents_by_id = HashMap::new();
-        for calendar in &calendars {
-            vevents_by_id.extend(&calendar.events);
+        for calendar in &fetched.calendars {
+            vevents_by_id.extend(
+                calendar
+                    .events
+                    .iter()
+                    .filter(|(event_id, _)| !cancelled.contains(*event_id)),
+            );
             vcalendar_by_id.extend(calendar.events.keys().map(|event_id| (event_id, calendar)));
         }
 
-        let (events, next_dates) = parse_calendars_to_events(db_calendar.calendar_id, &calendars)?;
+        let (mut events, mut next_dates) = if let Some(direct_events) = direct_events {
+            direct_events
+        } else {
+            parse_calendars_to_events(
+                db_calendar.calendar_id,
+                &fetched,
+                timezone,
+                &window,
+                db_calendar.ignore_transparent,
+            )?
+        };
+
+        // We treat cancelled events as if they had been removed from the
+        // calendar.
+        events.retain(|event| !cancelled.contains(&event.event_id));
+        next_dates.retain(|instance| !cancelled.contains(&instance.event_id));
 
         // Some calendar systems (read: FastMail) create new events when people
         // edit the times for future events. Since we want the reminders to
         // apply to the new event we add some heuristics to detect this case and
-        // copy across the reminders.
+        // copy across the reminders. This can misfire for unrelated events
+        // that happen to share a summary and organizer, so can be turned off
+        // per calendar.
         let previous_events = self
             .database
             .get_events_in_calendar(db_calendar.calendar_id)
@@ -349,8 +922,27 @@ impl App {
         }
 
         let mut new_reminders = Vec::new();
+        let mut ported_event_ids = HashSet::new();
+
+        let candidate_events = if db_calendar.port_duplicate_reminders {
+            &previous_events[..]
+        } else {
+            info!(
+                calendar_id = db_calendar.calendar_id,
+                "Porting reminders to duplicate events is disabled for calendar."
+            );
+            &[]
+        };
+
+        for (previous_event, _) in candidate_events {
+            // We can only tell if events without a VEVENT (i.e. from EWS or
+            // Google) have been replaced if they've disappeared.
+            if !vevents_by_id.contains_key(&previous_event.event_id)
+                && events_by_id.contains_key(&previous_event.event_id)
+            {
+                continue;
+            }
 
-        for (previous_event, _) in &previous_events {
             // Figure out if we should attempt to deduplicated based on this
             // event. We're either expecting it to not appear in the calendar or
             // for it to be a recurring event that has an end date.
@@ -430,12 +1022,17 @@ impl App {
 
                 info!(
                     calendar_id = db_calendar.calendar_id,
+                    user_id = db_calendar.user_id,
                     prev_event = previous_event.event_id.deref(),
                     new_event = new_event.event_id.deref(),
-                    reminders = reminders.len(),
+                    summary = ?new_event.summary,
+                    organizer = ?new_event.organizer.as_ref().map(|o| &o.email),
+                    reminder_ids = ?reminders.iter().map(|r| r.reminder_id).collect::<Vec<_>>(),
                     "Found event duplicate, porting reminders."
                 );
 
+                ported_event_ids.insert(previous_event.event_id.clone());
+
                 for mut reminder in reminders {
                     reminder.event_id.clone_from(&new_event.event_id);
 
@@ -444,10 +1041,112 @@ impl App {
             }
         }
 
-        self.database
+        // Find any upcoming reminders for events that have been removed from the
+        // calendar (and haven't been replaced by a new event), so that we can
+        // tell the room that the meeting has been cancelled. We only send one
+        // notice per event and room, even if it's recurring.
+        //
+        // Events that we failed to parse will also be missing, so we're careful
+        // not to treat them as cancelled.
+        let failed_event_ids: HashSet<_> = errors.iter().filter_map(|e| e.uid.as_ref()).collect();
+        let unknown_failures = errors.iter().any(|e| e.uid.is_none());
+
+        let upcoming_reminders = self
+            .database
+            .get_next_reminders_for_calendar(db_calendar.calendar_id)
+            .await?;
+
+        let mut cancelled_reminders = BTreeMap::new();
+        for (reminder_time, reminder) in &upcoming_reminders {
+            if unknown_failures
+                || events_by_id.contains_key(&reminder.event_id)
+                || ported_event_ids.contains(&reminder.event_id)
+                || failed_event_ids.contains(&reminder.event_id)
+            {
+                continue;
+            }
+
+            let event_time = *reminder_time + Duration::minutes(reminder.minutes_before);
+            cancelled_reminders
+                .entry((reminder.room.clone(), reminder.event_id.clone()))
+                .or_insert((event_time, reminder.clone()));
+        }
+
+        // Similarly, give rooms a heads up if an event they have an upcoming
+        // reminder for moves or changes location.
+        let event_changes =
+            detect_event_changes(&previous_events, &events, &next_dates, Utc::now());
+
+        let mut changed_reminders = BTreeMap::new();
+        for (_, reminder) in upcoming_reminders {
+            if let Some(change) = event_changes.get(&reminder.event_id) {
+                changed_reminders
+                    .entry((reminder.room.clone(), reminder.event_id.clone()))
+                    .or_insert((change.clone(), reminder));
+            }
+        }
+
+        let event_summaries = events
+            .iter()
+            .map(|event| (event.event_id.clone(), event.summary.clone()))
+            .collect_vec();
+        let event_summary_by_id: HashMap<_, _> = event_summaries
+            .iter()
+            .map(|(event_id, summary)| (event_id.as_str(), summary))
+            .collect();
+
+        // When each event next starts, for telling subscribed rooms about new
+        // events.
+        let now = Utc::now();
+        let mut next_event_dates: HashMap<String, DateTime<Utc>> = HashMap::new();
+        for instance in &next_dates {
+            let date = instance.date.with_timezone(&Utc);
+            if date < now {
+                continue;
+            }
+
+            next_event_dates
+                .entry(instance.event_id.clone())
+                .and_modify(|next| *next = (*next).min(date))
+                .or_insert(date);
+        }
+
+        let new_event_ids = self
+            .database
             .insert_events(db_calendar.calendar_id, events, next_dates)
             .await?;
 
+        // We don't post every event the first time we sync a calendar, nor
+        // events that have just replaced a duplicate.
+        let ported_to_event_ids: HashSet<_> = new_reminders
+            .iter()
+            .map(|reminder| reminder.event_id.clone())
+            .collect();
+        let new_events = if previous_events.is_empty() {
+            Vec::new()
+        } else {
+            event_summaries
+                .iter()
+                .filter(|(event_id, _)| {
+                    new_event_ids.contains(event_id) && !ported_to_event_ids.contains(event_id)
+                })
+                .filter_map(|(event_id, summary)| {
+                    next_event_dates
+                        .get(event_id)
+                        .map(|date| (summary.clone(), *date))
+                })
+                .collect_vec()
+        };
+
+        self.apply_reminder_rules(db_calendar.calendar_id, &event_summaries)
+            .await?;
+
+        // Record the window the instances cover, so we can tell people how far
+        // ahead their calendar has been synced.
+        self.database
+            .set_calendar_synced_window(db_calendar.calendar_id, window.from, window.until)
+            .await?;
+
         for reminder in new_reminders {
             self.database.add_reminder(&reminder).await?;
 
@@ -459,25 +1158,92 @@ impl App {
 
         self.update_reminders().await?;
 
-        Ok(())
-    }
+        self.edit_sent_reminders(db_calendar.calendar_id).await?;
 
-    /// Queries the DB and updates the reminders
-    #[instrument(skip(self))]
-    pub async fn update_reminders(&self) -> Result<(), Error> {
-        let reminders = self.database.get_next_reminders().await?;
+        for (event_time, reminder) in cancelled_reminders.into_values() {
+            info!(
+                calendar_id = db_calendar.calendar_id,
+                event_id = reminder.event_id.deref(),
+                room = reminder.room.deref(),
+                "Event with upcoming reminder cancelled"
+            );
 
-        info!(num = reminders.len(), "Updated reminders");
+            if let Err(error) = self.send_cancellation_notice(&reminder, event_time).await {
+                capture_anyhow(&error);
+                error!(
+                    error = error.deref() as &dyn StdError,
+                    "Failed to send cancellation notice"
+                );
+            }
+        }
 
-        self.reminders.replace(reminders);
-        self.notify_db_update.notify_one();
+        for (change, reminder) in changed_reminders.into_values() {
+            info!(
+                calendar_id = db_calendar.calendar_id,
+                event_id = reminder.event_id.deref(),
+                room = reminder.room.deref(),
+                "Event with upcoming reminder changed"
+            );
 
-        Ok(())
-    }
+            if let Err(error) = self
+                .send_event_change_notice(&reminder, &change, timezone.unwrap_or(Tz::UTC))
+                .await
+            {
+                capture_anyhow(&error);
+                error!(
+                    error = error.deref() as &dyn StdError,
+                    "Failed to send event change notice"
+                );
+            }
+        }
+
+        for (event_id, change) in &event_changes {
+            self.queue_webhook_event(
+                db_calendar.user_id,
+                "event.changed",
+                json!({
+                    "calendar_id": db_calendar.calendar_id,
+                    "event_id": event_id,
+                    "summary": event_summary_by_id.get(event_id.as_str()),
+                    "moved": change.moved.map(|(from, to)| json!({
+                        "from": from.to_rfc3339(),
+                        "to": to.to_rfc3339(),
+                    })),
+                    "location_changed": change.location.is_some(),
+                    "location": change.location.clone().flatten(),
+                }),
+            )
+            .await;
+        }
+
+        if !new_events.is_empty() {
+            self.notify_event_subscriptions(
+                db_calendar.calendar_id,
+                &new_events,
+                timezone.unwrap_or(Tz::UTC),
+            )
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Queries the DB and updates the reminders
+    #[instrument(skip(self))]
+    pub async fn update_reminders(&self) -> Result<(), Error> {
+        let reminders = self.database.get_next_reminders().await?;
+
+        info!(num = reminders.len(), "Updated reminders");
+
+        self.reminders.replace(reminders);
+        self.notify_db_update.notify_one();
+
+        Ok(())
+    }
 
     /// Update the email to matrix ID mapping cache.
     #[instrument(skip(self))]
-    async fn update_mappings(&self) -> Result<(), Error> {
+    pub async fn update_mappings(&self) -> Result<(), Error> {
         let mapping = self.database.get_user_mappings().await?;
 
         *self.email_to_matrix_id.lock().expect("poisoned") = mapping;
@@ -503,231 +1269,3073 @@ impl App {
         .await;
     }
 
-    /// Loop that handle sending the reminders.
-    async fn reminder_loop(&self) {
-        loop {
-            let next_wakeup = self
-                .reminders
-                .get_time_to_next()
-                .unwrap_or_else(|| Duration::minutes(5))
-                .min(Duration::minutes(5));
+    /// The public URL of the web page for the event, if a public base URL
+    /// has been configured.
+    pub fn event_url(&self, calendar_id: i64, event_id: &str) -> Option<String> {
+        let base_url = self.config.app.public_base_url.as_deref()?;
+
+        Some(format!(
+            "{}/event/{}/{}",
+            base_url.trim_end_matches('/'),
+            calendar_id,
+            encode(event_id)
+        ))
+    }
 
-            info!(
-                time_to_next = ?next_wakeup,
-                "Next reminder"
-            );
+    /// The URL of a user's ICS feed, if we know our public URL.
+    pub fn feed_url(&self, token: &str) -> Option<String> {
+        let base_url = self.config.app.public_base_url.as_deref()?;
 
-            // `to_std` will fail if the duration is negative, but if that is
-            // the case then we have due reminders that we can process
-            // immediately.
-            if let Ok(dur) = next_wakeup.to_std() {
-                info!(
-                    next_wakeup = ?next_wakeup,
-                    "Sleeping for"
-                );
+        Some(format!(
+            "{}/feed/{}.ics",
+            base_url.trim_end_matches('/'),
+            encode(token)
+        ))
+    }
 
-                tokio::pin! {
-                    let sleep_fut = sleep(dur);
-                    let notify = self.notify_db_update.notified();
-                }
+    /// How long a login lasts without being used.
+    pub fn session_lifetime(&self) -> Duration {
+        Duration::days(self.config.app.session_lifetime_days.unwrap_or(7))
+    }
 
-                future::select(sleep_fut, notify).await;
-            }
+    /// How long deleted calendars and reminders can be restored for.
+    pub fn deletion_grace_period(&self) -> Duration {
+        Duration::days(self.config.app.deletion_grace_period_days.unwrap_or(30))
+    }
 
-            let reminders = self.reminders.pop_due_reminders();
+    /// Permanently delete calendars, reminders and accounts whose grace
+    /// period has expired.
+    #[instrument(skip(self))]
+    async fn purge_deleted(&self) -> Result<(), Error> {
+        let (num_calendars, num_reminders) = self
+            .database
+            .purge_deleted(Utc::now() - self.deletion_grace_period())
+            .await?;
 
-            info!(count = reminders.len(), "Due reminders");
+        info!(
+            num_calendars,
+            num_reminders, "Purged deleted calendars and reminders"
+        );
 
-            futures::future::join_all(reminders.into_iter().map(|reminder| async {
-                info!(event_id = reminder.event_id.deref(), "Sending reminder");
-                if let Err(err) = self.send_reminder(reminder).await {
-                    capture_anyhow(&err);
-                    error!(
-                        error = err.deref() as &dyn StdError,
-                        "Failed to send reminder"
-                    );
-                }
-            }))
-            .await;
+        let user_ids = self
+            .database
+            .get_users_pending_deletion(Utc::now() - self.deletion_grace_period())
+            .await?;
+
+        for user_id in &user_ids {
+            self.database.delete_user(*user_id).await?;
         }
+
+        info!(num_users = user_ids.len(), "Deleted accounts");
+
+        // We only need to keep sent reminders until the event has started.
+        let num_sent = self
+            .database
+            .delete_old_sent_reminders(Utc::now() - Duration::days(1))
+            .await?;
+
+        info!(num_sent, "Purged old sent reminders");
+
+        let num_adhoc = self
+            .database
+            .delete_old_adhoc_reminders(Utc::now() - Duration::days(1))
+            .await?;
+
+        info!(num_adhoc, "Purged old one-off reminders");
+
+        let num_log_entries = self
+            .database
+            .delete_old_reminder_log(Utc::now() - Duration::days(REMINDER_LOG_RETENTION_DAYS))
+            .await?;
+
+        info!(num_log_entries, "Purged old reminder log entries");
+
+        Ok(())
     }
 
-    /// Send the reminder to the appropriate room.
-    #[instrument(skip(self), fields(status))]
-    async fn send_reminder(&self, reminder: ReminderInstance) -> Result<(), Error> {
-        // Join the room, making sure we retry requests that fail with a 5xx error.
-        let mut retry_counter = 0;
-        let body = loop {
-            let join_url = format!(
-                "{}/_matrix/client/r0/join/{}",
+    /// An infinite loop that periodically purges deleted calendars and
+    /// reminders.
+    async fn purge_deleted_loop(&self) {
+        interval_process("purge_deleted", Duration::hours(1), || {
+            AssertUnwindSafe(self.purge_deleted())
+        })
+        .await;
+    }
+
+    /// Delete expired login sessions, abandoned SSO and OAuth2 logins, and
+    /// old rate limit windows.
+    pub async fn cleanup_expired(&self) -> Result<(), Error> {
+        let num_access_tokens = self.database.delete_expired_access_tokens().await?;
+
+        let (num_sso_sessions, num_oauth2_sessions) = self
+            .database
+            .delete_stale_sso_sessions(Utc::now() - Duration::hours(LOGIN_SESSION_TIMEOUT_HOURS))
+            .await?;
+
+        let num_rate_limits = self
+            .database
+            .delete_old_rate_limits(Utc::now() - self.config.rate_limit.window())
+            .await?;
+
+        info!(
+            num_access_tokens,
+            num_sso_sessions, num_oauth2_sessions, num_rate_limits, "Cleaned up expired sessions"
+        );
+
+        Ok(())
+    }
+
+    /// An infinite loop that periodically deletes expired sessions.
+    async fn cleanup_expired_loop(&self) {
+        interval_process("cleanup_expired", Duration::hours(1), || {
+            AssertUnwindSafe(self.cleanup_expired())
+        })
+        .await;
+    }
+
+    /// Check that we can talk to the homeserver with the configured access
+    /// token.
+    pub async fn check_matrix_connection(&self) -> Result<(), Error> {
+        let user_id = self.whoami(None).await?;
+
+        info!(user_id = user_id.deref(), "Connected to homeserver");
+
+        Ok(())
+    }
+
+    /// Get the Matrix ID of the given sender, or the main account if None.
+    async fn whoami(&self, sender: Option<&str>) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        let whoami_url = format!(
+            "{}/_matrix/client/r0/account/whoami",
+            credentials.homeserver_url
+        );
+
+        let resp = credentials
+            .authenticate(self.http_client.get(&whoami_url))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP /whoami request")?;
+
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from /whoami response: {}", resp.status());
+        }
+
+        let body: MatrixWhoamiResponse = resp.json().await?;
+
+        Ok(body.user_id)
+    }
+
+    /// List the rooms in the configured space (including in any subspaces),
+    /// or None if no space is configured.
+    pub async fn get_space_rooms(&self) -> Result<Option<Vec<SpaceRoom>>, Error> {
+        let space = if let Some(space) = &self.config.app.space {
+            space
+        } else {
+            return Ok(None);
+        };
+
+        // We need to be in the space to see its rooms, and this also resolves
+        // the alias if one was given.
+        let space_id = self.join_room(None, space).await?;
+
+        let mut rooms = Vec::new();
+        let mut from: Option<String> = None;
+
+        // Bound the number of pages we fetch in case of misbehaving servers.
+        for _ in 0..10 {
+            let mut url = format!(
+                "{}/_matrix/client/v1/rooms/{}/hierarchy?limit=50",
                 self.config.matrix.homeserver_url,
-                encode(&reminder.room),
+                encode(&space_id),
             );
+            if let Some(from) = &from {
+                url.push_str(&format!("&from={}", encode(from)));
+            }
 
             let resp = self
                 .http_client
-                .post(&join_url)
+                .get(&url)
                 .bearer_auth(&self.config.matrix.access_token)
-                .json(&json!({}))
                 .send()
                 .await
-                .with_context(|| "Sending HTTP /join request")?;
+                .with_context(|| "Sending HTTP /hierarchy request")?;
 
             if !resp.status().is_success() {
-                warn!("Got non-2xx from /join response: {}", resp.status());
-                if resp.status().is_server_error() && retry_counter < 5 {
-                    retry_counter += 1;
-                    continue;
+                bail!("Got non-2xx from /hierarchy response: {}", resp.status());
+            }
+
+            let body: MatrixHierarchyResponse = resp.json().await?;
+
+            rooms.extend(
+                body.rooms
+                    .into_iter()
+                    .filter(|room| room.room_type.as_deref() != Some("m.space"))
+                    .map(|room| SpaceRoom {
+                        room_id: room.room_id,
+                        name: room.name,
+                        canonical_alias: room.canonical_alias,
+                    }),
+            );
+
+            from = body.next_batch;
+            if from.is_none() {
+                break;
+            }
+        }
+
+        Ok(Some(rooms))
+    }
+
+    /// Loop that syncs with the homeserver, so that we can watch for people
+    /// reacting to reminders to snooze them, and responding to polls.
+    async fn sync_loop(&self) {
+        if self.config.matrix.appservice.is_some() {
+            // The homeserver pushes events to us instead.
+            return future::pending().await;
+        }
+
+        // Reminders can be sent from any of the accounts, so we sync each of
+        // them to see reactions in rooms the main account isn't in.
+        let senders = std::iter::once(None).chain(
+            self.config
+                .matrix
+                .senders
+                .iter()
+                .map(|sender| Some(sender.name.as_str())),
+        );
+
+        future::join_all(senders.map(|sender| self.sync_account_loop(sender))).await;
+    }
+
+    /// Loop that syncs the given sender, or the main account if None.
+    async fn sync_account_loop(&self, sender: Option<&str>) {
+        let mut user_id = None;
+        let mut since = None;
+
+        loop {
+            let result = async {
+                if user_id.is_none() {
+                    user_id = Some(self.whoami(sender).await?);
                 }
+                let user_id = user_id.as_deref().expect("user ID is set");
 
-                bail!("Got non-2xx from /join response: {}", resp.status());
+                let next_batch = self.sync_once(sender, user_id, since.as_deref()).await?;
+                since = Some(next_batch);
+
+                Ok::<_, Error>(())
             }
+            .await;
 
-            let body: MatrixJoinResponse = resp.json().await?;
+            if let Err(err) = result {
+                capture_anyhow(&err);
+                error!(
+                    error = err.deref() as &dyn StdError,
+                    sender, "Failed to sync"
+                );
+                sleep(std::time::Duration::from_secs(10)).await;
+            }
+        }
+    }
 
-            break body;
-        };
+    /// Do a single sync request as the given sender, or the main account if
+    /// None, handling any reactions and poll responses. Returns the token for
+    /// the next sync.
+    ///
+    /// If this is the initial sync (i.e. `since` is None) we ignore any
+    /// events, as they will be from before we started.
+    #[instrument(skip(self))]
+    pub async fn sync_once(
+        &self,
+        sender: Option<&str>,
+        user_id: &str,
+        since: Option<&str>,
+    ) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        // We only care about reactions.
+        let filter = json!({
+            "presence": { "types": [] },
+            "account_data": { "types": [] },
+            "room": {
+                "state": { "types": [] },
+                "ephemeral": { "types": [] },
+                "account_data": { "types": [] },
+                "timeline": {
+                    "types": ["m.reaction", "m.room.redaction", POLL_RESPONSE_TYPE, "m.poll.response"],
+                },
+            },
+        });
+
+        let mut sync_url = format!(
+            "{}/_matrix/client/r0/sync?filter={}",
+            credentials.homeserver_url,
+            encode(&filter.to_string()),
+        );
 
-        let markdown_template = reminder.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+        if let Some(since) = since {
+            sync_url.push_str(&format!("&timeout=30000&since={}", encode(since)));
+        } else {
+            sync_url.push_str("&timeout=0");
+        }
 
-        // We fetch both the emails and matrix IDs of people on holiday as a)
-        // not everyone has an associated matrix ID and b) the attendee email
-        // may not be using the person's canonical email.
-        let out_today_emails = self.database.get_out_today_emails().await?;
-        let out_today_matrix_ids = self.database.get_out_today_matrix_ids().await?;
+        let resp = credentials
+            .authenticate(self.http_client.get(&sync_url))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP /sync request")?;
 
-        let attendees = reminder
-            .attendees
-            .iter()
-            .filter(|attendee| !out_today_emails.contains(&attendee.email))
-            .filter_map(|attendee| {
-                // Map attendee email to a markdown string, filtering out matrix
-                // IDs that we know are on holiday.
-                if let Some(matrix_id) = self
-                    .email_to_matrix_id
-                    .lock()
-                    .expect("poisoned")
-                    .get(&attendee.email)
-                {
-                    if out_today_matrix_ids.contains(matrix_id) {
-                        None
-                    } else {
-                        Some(format!(
-                            "[{}](https://matrix.to/#/{})",
-                            attendee.common_name.as_ref().unwrap_or(matrix_id),
-                            matrix_id,
-                        ))
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from /sync response: {}", resp.status());
+        }
+
+        let body: Value = resp.json().await?;
+
+        let next_batch = body["next_batch"]
+            .as_str()
+            .context("missing next_batch in sync response")?
+            .to_string();
+
+        if since.is_none() {
+            return Ok(next_batch);
+        }
+
+        if let Some(rooms) = body["rooms"]["join"].as_object() {
+            for (room_id, room) in rooms {
+                for event in room["timeline"]["events"].as_array().into_iter().flatten() {
+                    if event["sender"] == user_id {
+                        continue;
                     }
-                } else {
-                    Some(
-                        attendee
-                            .common_name
-                            .as_ref()
-                            .unwrap_or(&attendee.email)
-                            .to_string(),
-                    )
+
+                    self.handle_room_event(room_id, event).await;
                 }
-            })
-            .join(", ");
+            }
+        }
 
-        // The description may be in HTML so we first render the template with a
-        // unique token that we can later replace with the actual description
-        let description_token: String = rand::thread_rng()
-            .sample_iter(Alphanumeric)
-            .take(16)
-            .map(char::from)
-            .collect();
+        Ok(next_batch)
+    }
 
-        let handlebars = Handlebars::new();
-        let human = HumanTime::from(Duration::minutes(reminder.minutes_before));
-        let markdown = handlebars
-            .render_template(
-                markdown_template,
-                &json!({
-                    "event_id": &reminder.event_id,
-                    "summary": &reminder.summary,
-                    "description": reminder.description.as_ref().map(|_| &description_token),
-                    "location": &reminder.location,
-                    "minutes_before": &reminder.minutes_before,
-                    "duration": human.to_text_en(Accuracy::Precise, Tense::Present),
-                    "attendees": attendees,
+    /// Register the users the appservice sends as, so that we can masquerade
+    /// as them. Users that already exist are skipped.
+    async fn register_appservice_users(&self) -> Result<(), Error> {
+        if self.config.matrix.appservice.is_none() {
+            return Ok(());
+        }
+
+        let register_url = format!(
+            "{}/_matrix/client/r0/register",
+            self.config.matrix.homeserver_url
+        );
+
+        for user_id in self.config.matrix.appservice_user_ids() {
+            let localpart = user_id
+                .strip_prefix('@')
+                .and_then(|user_id| user_id.split(':').next())
+                .with_context(|| format!("Invalid user ID {}", user_id))?;
+
+            let resp = self
+                .http_client
+                .post(&register_url)
+                .bearer_auth(&self.config.matrix.access_token)
+                .json(&json!({
+                    "type": "m.login.application_service",
+                    "username": localpart,
+                }))
+                .send()
+                .await
+                .with_context(|| "Sending HTTP /register request")?;
+
+            let status = resp.status();
+            if status.is_success() {
+                info!(user_id, "Registered appservice user");
+                continue;
+            }
+
+            let body: Value = resp.json().await.unwrap_or_default();
+            if body["errcode"] != "M_USER_IN_USE" {
+                bail!("Got non-2xx from /register response: {}", status);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle a transaction of events pushed to us by the homeserver when
+    /// running as an appservice.
+    ///
+    /// Transactions may be retried, but handling reactions is idempotent so
+    /// we don't need to track which ones we've seen.
+    pub async fn handle_appservice_transaction(&self, events: &[Value]) -> Result<(), Error> {
+        let appservice = self
+            .config
+            .matrix
+            .appservice
+            .as_ref()
+            .context("appservice not configured")?;
+
+        let bot_prefix = format!("@{}:", appservice.sender_localpart);
+
+        for event in events {
+            let sender = event["sender"].as_str().unwrap_or_default();
+            if sender.starts_with(&bot_prefix) {
+                continue;
+            }
+
+            let room_id = if let Some(room_id) = event["room_id"].as_str() {
+                room_id
+            } else {
+                continue;
+            };
+
+            self.handle_room_event(room_id, event).await;
+        }
+
+        Ok(())
+    }
+
+    /// Handle an event someone sent in a room, e.g. a reaction or poll
+    /// response.
+    async fn handle_room_event(&self, room_id: &str, event: &Value) {
+        let result = match event["type"].as_str() {
+            Some("m.reaction") => self.handle_reaction(room_id, event).await,
+            Some("m.room.redaction") => self.handle_redaction(event).await,
+            Some(POLL_RESPONSE_TYPE) | Some("m.poll.response") => {
+                self.handle_poll_response(room_id, event).await
+            }
+            _ => return,
+        };
+
+        if let Err(err) = result {
+            capture_anyhow(&err);
+            error!(
+                error = err.deref() as &dyn StdError,
+                event_type = event["type"].as_str(),
+                "Failed to handle event"
+            );
+        }
+    }
+
+    /// Record someone's answer to one of our attendance polls.
+    async fn handle_poll_response(&self, room_id: &str, event: &Value) -> Result<(), Error> {
+        let content = &event["content"];
+        if content["m.relates_to"]["rel_type"] != "m.reference" {
+            return Ok(());
+        }
+
+        let poll_event_id = content["m.relates_to"]["event_id"]
+            .as_str()
+            .context("missing poll event ID")?;
+        let sender = event["sender"].as_str().context("missing sender")?;
+
+        let response = if content[POLL_RESPONSE_TYPE].is_object() {
+            &content[POLL_RESPONSE_TYPE]
+        } else {
+            &content["m.selections"]
+        };
+
+        // We only allow a single selection, and ignore anything that isn't
+        // one of our answers.
+        let answer = response["answers"]
+            .as_array()
+            .and_then(|answers| answers.first())
+            .or_else(|| response.as_array().and_then(|answers| answers.first()))
+            .and_then(Value::as_str);
+        let answer = match answer {
+            Some(answer) if POLL_ANSWERS.iter().any(|(id, _)| *id == answer) => answer,
+            _ => return Ok(()),
+        };
+
+        let responded_at = event["origin_server_ts"]
+            .as_i64()
+            .and_then(DateTime::from_timestamp_millis)
+            .unwrap_or_else(Utc::now);
+
+        self.database
+            .add_poll_response(room_id, poll_event_id, sender, answer, responded_at)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Handle a reaction to a reminder: either record whether the sender will
+    /// attend, or snooze the reminder if it's the snooze emoji.
+    async fn handle_reaction(&self, room_id: &str, event: &Value) -> Result<(), Error> {
+        let relates_to = &event["content"]["m.relates_to"];
+        if relates_to["rel_type"] != "m.annotation" {
+            return Ok(());
+        }
+
+        // Either an RSVP, or None if it's a snooze.
+        let attending = match relates_to["key"].as_str() {
+            Some(ATTENDING_REACTION) => Some(true),
+            Some(NOT_ATTENDING_REACTION) => Some(false),
+            Some(key) if key == self.snooze_reaction() => None,
+            _ => return Ok(()),
+        };
+
+        let reacted_event_id = if let Some(event_id) = relates_to["event_id"].as_str() {
+            event_id
+        } else {
+            return Ok(());
+        };
+
+        let sent = if let Some(sent) = self
+            .database
+            .get_sent_reminder_by_event_id(room_id, reacted_event_id)
+            .await?
+        {
+            sent
+        } else {
+            // Not a reminder we sent.
+            return Ok(());
+        };
+
+        if let Some(attending) = attending {
+            let sender = event["sender"].as_str().context("missing sender")?;
+            let reaction_event_id = event["event_id"].as_str().context("missing event ID")?;
+
+            info!(
+                reminder_id = sent.reminder_id,
+                sender, attending, "Recording attendance"
+            );
+
+            self.database
+                .set_attendance(
+                    sent.reminder_id,
+                    sent.timestamp,
+                    sender,
+                    attending,
+                    reaction_event_id,
+                )
+                .await?;
+
+            return Ok(());
+        }
+
+        let reminder = if let Some(reminder) = self
+            .database
+            .get_reminder_instance(sent.reminder_id, sent.timestamp)
+            .await?
+        {
+            reminder
+        } else {
+            return Ok(());
+        };
+
+        let snooze_until = Utc::now() + self.snooze_duration();
+
+        info!(
+            reminder_id = reminder.reminder_id,
+            event_id = reminder.event_id.deref(),
+            ?snooze_until,
+            "Snoozing reminder"
+        );
+
+        if self.reminders.snooze(snooze_until, reminder) {
+            self.notify_db_update.notify_one();
+        }
+
+        Ok(())
+    }
+
+    /// Handle a redaction, removing any attendance recorded by a redacted
+    /// reaction.
+    async fn handle_redaction(&self, event: &Value) -> Result<(), Error> {
+        // Newer room versions put `redacts` in the content.
+        let redacts = event["redacts"]
+            .as_str()
+            .or_else(|| event["content"]["redacts"].as_str());
+
+        if let Some(redacts) = redacts {
+            self.database.delete_attendance_by_reaction(redacts).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the credentials for the given sender, or the main account if None.
+    fn matrix_credentials(&self, sender: Option<&str>) -> Result<MatrixCredentials<'_>, Error> {
+        self.config
+            .matrix
+            .credentials(sender)
+            .with_context(|| format!("Unknown Matrix sender {:?}", sender))
+    }
+
+    /// The msgtype to send messages about the reminder as.
+    fn msgtype<'a>(&'a self, reminder: &'a ReminderInstance) -> &'a str {
+        reminder
+            .msgtype
+            .as_deref()
+            .unwrap_or_else(|| self.default_msgtype())
+    }
+
+    /// The msgtype to send messages as, if not overridden by the reminder.
+    fn default_msgtype(&self) -> &str {
+        self.config.app.msgtype.as_deref().unwrap_or("m.text")
+    }
+
+    /// The reaction that snoozes a reminder.
+    fn snooze_reaction(&self) -> &str {
+        self.config.app.snooze_reaction.as_deref().unwrap_or("💤")
+    }
+
+    /// How long to snooze reminders for.
+    fn snooze_duration(&self) -> Duration {
+        Duration::minutes(self.config.app.snooze_minutes.unwrap_or(10))
+    }
+
+    /// Loop that pings the systemd watchdog, if enabled, so long as the
+    /// reminder loop is still making progress.
+    async fn watchdog_loop(&self) {
+        let watchdog_interval = if let Some(watchdog_interval) = systemd::watchdog_interval() {
+            watchdog_interval
+        } else {
+            // We don't return, as that would cause the app to exit.
+            return future::pending().await;
+        };
+
+        // The reminder loop wakes up at least every five minutes.
+        let max_heartbeat_age = Duration::minutes(10);
+
+        let mut interval = interval((watchdog_interval / 2).to_std().expect("std duration"));
+
+        loop {
+            interval.tick().await;
+
+            let last_heartbeat = *self.reminder_loop_heartbeat.lock().expect("poisoned");
+            if Utc::now() - last_heartbeat > max_heartbeat_age {
+                warn!(
+                    ?last_heartbeat,
+                    "Reminder loop appears stuck, not pinging watchdog"
+                );
+                continue;
+            }
+
+            if let Err(err) = systemd::notify("WATCHDOG=1") {
+                warn!(
+                    error = err.deref() as &dyn StdError,
+                    "Failed to ping watchdog"
+                );
+            }
+        }
+    }
+
+    /// Loop that periodically sets the bot's presence, if enabled, so that
+    /// admins can see that it's still running.
+    async fn presence_loop(&self) {
+        if !self.config.matrix.presence.unwrap_or(false) {
+            // We don't return, as that would cause the app to exit.
+            return future::pending().await;
+        }
+
+        // Homeservers mark users as offline after a few minutes without
+        // activity.
+        let mut interval = interval(std::time::Duration::from_secs(60));
+        let mut user_id = None;
+
+        loop {
+            interval.tick().await;
+
+            if user_id.is_none() {
+                match self.whoami(None).await {
+                    Ok(id) => user_id = Some(id),
+                    Err(err) => {
+                        warn!(
+                            error = err.deref() as &dyn StdError,
+                            "Failed to get bot user ID"
+                        );
+                        continue;
+                    }
+                }
+            }
+
+            let user_id = user_id.as_deref().expect("user ID set above");
+            if let Err(err) = self.set_presence(user_id).await {
+                warn!(
+                    error = err.deref() as &dyn StdError,
+                    "Failed to set presence"
+                );
+            }
+        }
+    }
+
+    /// Set the bot as online, with a status message of how many reminders
+    /// we're tracking.
+    async fn set_presence(&self, user_id: &str) -> Result<(), Error> {
+        let url = format!(
+            "{}/_matrix/client/r0/presence/{}/status",
+            self.config.matrix.homeserver_url,
+            encode(user_id),
+        );
+
+        let count = self.reminders.count();
+        let status_msg = if count == 1 {
+            "Tracking 1 reminder".to_string()
+        } else {
+            format!("Tracking {} reminders", count)
+        };
+
+        let resp = self
+            .http_client
+            .put(&url)
+            .bearer_auth(&self.config.matrix.access_token)
+            .json(&json!({
+                "presence": "online",
+                "status_msg": status_msg,
+            }))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP presence request")?;
+
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from presence response: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    /// Loop that handle sending the reminders.
+    async fn reminder_loop(&self) {
+        loop {
+            *self.reminder_loop_heartbeat.lock().expect("poisoned") = Utc::now();
+
+            let next_wakeup = self
+                .reminders
+                .get_time_to_next()
+                .unwrap_or_else(|| Duration::minutes(5))
+                .min(Duration::minutes(5));
+
+            info!(
+                time_to_next = ?next_wakeup,
+                "Next reminder"
+            );
+
+            // `to_std` will fail if the duration is negative, but if that is
+            // the case then we have due reminders that we can process
+            // immediately.
+            if let Ok(dur) = next_wakeup.to_std() {
+                info!(
+                    next_wakeup = ?next_wakeup,
+                    "Sleeping for"
+                );
+
+                tokio::pin! {
+                    let sleep_fut = sleep(dur);
+                    let notify = self.notify_db_update.notified();
+                }
+
+                future::select(sleep_fut, notify).await;
+            }
+
+            let reminders = self.reminders.pop_due_reminders();
+
+            info!(count = reminders.len(), "Due reminders");
+
+            let reminders = skip_declined_events(reminders);
+            let reminders = self.skip_public_holidays(reminders).await;
+            let reminders = self.apply_quiet_hours(reminders).await;
+            let reminders = dedup_reminders(reminders);
+
+            if self
+                .config
+                .app
+                .combine_simultaneous_reminders
+                .unwrap_or(false)
+            {
+                // Threaded reminders and those with polls, attachments or which
+                // redact previous reminders are sent individually, as they need
+                // to go into their own thread, have the poll or attachment
+                // follow them or be redacted on their own.
+                let mut reminders_by_room: BTreeMap<
+                    (String, Option<String>, Option<i64>),
+                    Vec<ReminderInstance>,
+                > = BTreeMap::new();
+                for reminder in reminders {
+                    let individual_key = (reminder.threaded
+                        || reminder.poll
+                        || reminder.attach_ics
+                        || reminder.redact_previous)
+                        .then_some(reminder.reminder_id);
+                    reminders_by_room
+                        .entry((
+                            reminder.room.clone(),
+                            reminder.sender.clone(),
+                            individual_key,
+                        ))
+                        .or_default()
+                        .push(reminder);
+                }
+
+                futures::future::join_all(reminders_by_room.into_iter().map(
+                    |((room, sender, _), mut reminders)| async move {
+                        let attempted = reminders
+                            .iter()
+                            .map(|reminder| (reminder.reminder_id, reminder.timestamp))
+                            .collect_vec();
+                        let credited = reminders.iter().flat_map(credited_reminders).collect_vec();
+
+                        let result = if reminders.len() == 1 {
+                            let reminder = reminders.pop().expect("non-empty");
+                            info!(event_id = reminder.event_id.deref(), "Sending reminder");
+                            self.send_reminder(reminder).await
+                        } else {
+                            info!(
+                                room = room.deref(),
+                                count = reminders.len(),
+                                "Sending combined reminders"
+                            );
+                            self.send_combined_reminders(sender.as_deref(), &room, reminders)
+                                .await
+                        };
+
+                        if let Err(err) = &result {
+                            capture_anyhow(err);
+                            error!(
+                                error = err.deref() as &dyn StdError,
+                                "Failed to send reminder"
+                            );
+
+                            self.queue_reminder_retries(&attempted, err).await;
+                        }
+
+                        self.log_send_attempt(&credited, &room, &result).await;
+                    },
+                ))
+                .await;
+            } else {
+                futures::future::join_all(reminders.into_iter().map(|reminder| async {
+                    info!(event_id = reminder.event_id.deref(), "Sending reminder");
+
+                    let attempted = [(reminder.reminder_id, reminder.timestamp)];
+                    let credited = credited_reminders(&reminder);
+                    let room = reminder.room.clone();
+
+                    let result = self.send_reminder(reminder).await;
+                    if let Err(err) = &result {
+                        capture_anyhow(err);
+                        error!(
+                            error = err.deref() as &dyn StdError,
+                            "Failed to send reminder"
+                        );
+
+                        self.queue_reminder_retries(&attempted, err).await;
+                    }
+
+                    self.log_send_attempt(&credited, &room, &result).await;
+                }))
+                .await;
+            }
+        }
+    }
+
+    /// Record an attempt to send the given reminder instances in the reminder
+    /// log, so that users can see why a reminder didn't arrive.
+    async fn log_send_attempt(
+        &self,
+        attempted: &[(i64, DateTime<Utc>)],
+        room: &str,
+        result: &Result<String, Error>,
+    ) {
+        let (matrix_event_id, error) = match result {
+            Ok(matrix_event_id) => (Some(matrix_event_id.as_str()), None),
+            Err(err) => (None, Some(format!("{:#}", err))),
+        };
+
+        for (reminder_id, timestamp) in attempted {
+            if let Err(err) = self
+                .database
+                .add_reminder_log_entry(
+                    *reminder_id,
+                    *timestamp,
+                    room,
+                    matrix_event_id,
+                    error.as_deref(),
+                )
+                .await
+            {
+                error!(
+                    error = err.deref() as &dyn StdError,
+                    reminder_id, "Failed to record reminder send attempt"
+                );
+            }
+
+            let user_id = match self.database.get_reminder_owner(*reminder_id).await {
+                Ok(Some(user_id)) => user_id,
+                Ok(None) => continue,
+                Err(err) => {
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        reminder_id, "Failed to get reminder owner for webhooks"
+                    );
+                    continue;
+                }
+            };
+
+            let event_type = if error.is_some() {
+                "reminder.failed"
+            } else {
+                "reminder.sent"
+            };
+
+            self.queue_webhook_event(
+                user_id,
+                event_type,
+                json!({
+                    "reminder_id": reminder_id,
+                    "timestamp": timestamp.to_rfc3339(),
+                    "room": room,
+                    "matrix_event_id": matrix_event_id,
+                    "error": error,
+                }),
+            )
+            .await;
+        }
+    }
+
+    /// Queue a payload to be sent to the user's webhooks that subscribe to
+    /// the event type. Failures are logged rather than returned, as webhooks
+    /// shouldn't stop whatever triggered them.
+    pub async fn queue_webhook_event(&self, user_id: i64, event_type: &str, data: Value) {
+        let payload = json!({
+            "type": event_type,
+            "created_at": Utc::now().to_rfc3339(),
+            "data": data,
+        })
+        .to_string();
+
+        if let Err(err) = self
+            .database
+            .queue_webhook_deliveries(user_id, event_type, &payload)
+            .await
+        {
+            capture_anyhow(&err);
+            error!(
+                error = err.deref() as &dyn StdError,
+                user_id, event_type, "Failed to queue webhook deliveries"
+            );
+        }
+    }
+
+    /// Add a webhook for the user with a freshly generated signing secret,
+    /// returning its ID and the secret.
+    pub async fn add_webhook(
+        &self,
+        user_id: i64,
+        url: &str,
+        event_types: &[String],
+    ) -> Result<(i64, String), Error> {
+        let secret: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        let webhook_id = self
+            .database
+            .add_webhook(user_id, url, &secret, event_types)
+            .await?;
+
+        Ok((webhook_id, secret))
+    }
+
+    /// An infinite loop that delivers queued webhook payloads.
+    async fn webhook_delivery_loop(&self) {
+        interval_process("webhook_delivery", Duration::seconds(15), || {
+            AssertUnwindSafe(self.deliver_webhooks(Utc::now()))
+        })
+        .await;
+    }
+
+    /// Deliver the webhook payloads that are due, rescheduling those that
+    /// fail and giving up on those that have failed too many times.
+    #[instrument(skip(self))]
+    pub async fn deliver_webhooks(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let deliveries = self.database.get_due_webhook_deliveries(now, 100).await?;
+
+        let results = futures::future::join_all(deliveries.into_iter().map(|delivery| async {
+            let result = self.deliver_webhook(&delivery).await;
+            (delivery, result)
+        }))
+        .await;
+
+        for (delivery, result) in results {
+            let err = match result {
+                Ok(()) => {
+                    self.database
+                        .delete_webhook_delivery(delivery.delivery_id)
+                        .await?;
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            let attempts = delivery.attempts + 1;
+            if attempts < WEBHOOK_MAX_ATTEMPTS {
+                self.database
+                    .update_webhook_delivery(
+                        delivery.delivery_id,
+                        attempts,
+                        now + webhook_retry_backoff(attempts),
+                        &format!("{:#}", err),
+                    )
+                    .await?;
+                continue;
+            }
+
+            warn!(
+                error = err.deref() as &dyn StdError,
+                webhook_id = delivery.webhook_id,
+                attempts,
+                "Giving up on delivering webhook"
+            );
+
+            self.database
+                .delete_webhook_delivery(delivery.delivery_id)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// POST the signed payload to the webhook's URL.
+    async fn deliver_webhook(&self, delivery: &WebhookDelivery) -> Result<(), Error> {
+        // Check the URL again, as what the host resolves to may have changed.
+        let client = webhook_client(&delivery.url, &self.config.webhooks.allowed_hosts).await?;
+
+        let resp = client
+            .post(&delivery.url)
+            .timeout(std::time::Duration::from_secs(10))
+            .header("Content-Type", "application/json")
+            .header("X-Calbot-Event", &delivery.event_type)
+            .header("X-Calbot-Delivery", delivery.delivery_id.to_string())
+            .header(
+                WEBHOOK_SIGNATURE_HEADER,
+                sign_webhook_payload(&delivery.secret, &delivery.payload),
+            )
+            .body(delivery.payload.clone())
+            .send()
+            .await?;
+
+        // Redirects aren't followed, so count them as failures too.
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from webhook: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    /// Queue up the reminder instances that failed to send to be retried.
+    async fn queue_reminder_retries(&self, attempted: &[(i64, DateTime<Utc>)], error: &Error) {
+        let next_attempt_at = Utc::now() + reminder_retry_backoff(1);
+        let error = format!("{:#}", error);
+
+        for (reminder_id, timestamp) in attempted {
+            if let Err(err) = self
+                .database
+                .add_reminder_retry(*reminder_id, *timestamp, next_attempt_at, &error)
+                .await
+            {
+                capture_anyhow(&err);
+                error!(
+                    error = err.deref() as &dyn StdError,
+                    reminder_id, "Failed to queue reminder retry"
+                );
+            }
+        }
+    }
+
+    /// An infinite loop that retries sending reminders that failed to send.
+    async fn reminder_retry_loop(&self) {
+        interval_process("reminder_retry", Duration::seconds(15), || {
+            AssertUnwindSafe(self.retry_failed_reminders(Utc::now()))
+        })
+        .await;
+    }
+
+    /// Retry sending the queued reminders that are due, giving up on those
+    /// that have been failing for longer than the retry window.
+    #[instrument(skip(self))]
+    pub async fn retry_failed_reminders(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let retries = self.database.get_due_reminder_retries(now).await?;
+
+        for mut retry in retries {
+            let reminder_id = retry.reminder_id;
+
+            let reminder = if let Some(reminder) = self
+                .database
+                .get_reminder_instance(reminder_id, retry.timestamp)
+                .await?
+            {
+                reminder
+            } else {
+                // The reminder (or event) has been deleted since.
+                self.database
+                    .delete_reminder_retry(reminder_id, retry.timestamp)
+                    .await?;
+                continue;
+            };
+
+            let room = reminder.room.clone();
+            let summary = reminder.summary.clone();
+
+            info!(
+                reminder_id,
+                attempts = retry.attempts,
+                "Retrying failed reminder"
+            );
+
+            let result = self.send_reminder(reminder).await;
+            self.log_send_attempt(&[(reminder_id, retry.timestamp)], &room, &result)
+                .await;
+
+            let err = match result {
+                Ok(_) => {
+                    self.database
+                        .delete_reminder_retry(reminder_id, retry.timestamp)
+                        .await?;
+                    continue;
+                }
+                Err(err) => err,
+            };
+
+            retry.attempts += 1;
+            retry.last_error = format!("{:#}", err);
+            retry.next_attempt_at = now + reminder_retry_backoff(retry.attempts);
+
+            if retry.next_attempt_at
+                <= retry.first_failed_at + Duration::minutes(REMINDER_RETRY_WINDOW_MINUTES)
+            {
+                self.database.update_reminder_retry(&retry).await?;
+                continue;
+            }
+
+            warn!(
+                error = err.deref() as &dyn StdError,
+                reminder_id,
+                attempts = retry.attempts,
+                "Giving up on sending reminder"
+            );
+
+            self.database
+                .delete_reminder_retry(reminder_id, retry.timestamp)
+                .await?;
+
+            if self.config.app.notify_failed_reminders.unwrap_or(false) {
+                if let Err(err) = self
+                    .notify_failed_reminder(&retry, &room, summary.as_deref())
+                    .await
+                {
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        reminder_id, "Failed to notify owner of failed reminder"
+                    );
+                }
+
+                if let Err(err) = self
+                    .email_failed_reminder(&retry, &room, summary.as_deref())
+                    .await
+                {
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        reminder_id, "Failed to email owner of failed reminder"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// DM the owner of the reminder that we've given up trying to send it.
+    async fn notify_failed_reminder(
+        &self,
+        retry: &ReminderRetry,
+        room: &str,
+        summary: Option<&str>,
+    ) -> Result<(), Error> {
+        let user_id = self
+            .database
+            .get_reminder_owner(retry.reminder_id)
+            .await?
+            .context("Reminder has no owner")?;
+
+        let room_id = self.get_or_create_dm_room(user_id).await?;
+
+        let markdown = format!(
+            "Failed to send the reminder for **{}** to `{}` after {} attempts: {}",
+            summary.unwrap_or("Untitled event"),
+            room,
+            retry.attempts,
+            retry.last_error,
+        );
+
+        let event_json = json!({
+            "msgtype": "m.notice",
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+            "m.mentions": {},
+        });
+
+        self.send_event_with_txn_id(
+            None,
+            &room_id,
+            "m.room.message",
+            &format!(
+                "reminder-failed-{}-{}",
+                retry.reminder_id,
+                retry.timestamp.timestamp()
+            ),
+            &event_json,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Email the owner of the reminder that we've given up trying to send
+    /// it, if email is configured.
+    async fn email_failed_reminder(
+        &self,
+        retry: &ReminderRetry,
+        room: &str,
+        summary: Option<&str>,
+    ) -> Result<(), Error> {
+        if self.mailer.is_none() {
+            return Ok(());
+        }
+
+        let user_id = self
+            .database
+            .get_reminder_owner(retry.reminder_id)
+            .await?
+            .context("Reminder has no owner")?;
+
+        let summary = summary.unwrap_or("Untitled event");
+
+        let context = tera::Context::from_serialize(json!({
+            "summary": summary,
+            "room": room,
+            "attempts": retry.attempts,
+            "last_error": &retry.last_error,
+            "reminders_url": self.config.app.public_base_url.as_deref().map(|base_url| {
+                format!("{}/reminders", base_url.trim_end_matches('/'))
+            }),
+        }))?;
+
+        self.email_user(
+            user_id,
+            "reminder_failed",
+            &format!("Failed to send reminder for {}", summary),
+            &context,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Render the named email templates and send the email to the user.
+    ///
+    /// Returns false if email isn't configured.
+    pub async fn email_user(
+        &self,
+        user_id: i64,
+        template: &str,
+        subject: &str,
+        context: &tera::Context,
+    ) -> Result<bool, Error> {
+        let mailer = if let Some(mailer) = &self.mailer {
+            mailer
+        } else {
+            return Ok(false);
+        };
+
+        let to = self.database.get_email(user_id).await?;
+        let email = render_email(&self.templates, template, subject, context)?;
+
+        mailer.send(&to, &email).await?;
+
+        Ok(true)
+    }
+
+    /// Filter out the due reminders for events on a public holiday in the
+    /// reminder's holiday region.
+    async fn skip_public_holidays(
+        &self,
+        reminders: Vec<ReminderInstance>,
+    ) -> Vec<ReminderInstance> {
+        let mut to_send = Vec::with_capacity(reminders.len());
+        for reminder in reminders {
+            match self.is_public_holiday_for_reminder(&reminder).await {
+                Ok(true) => {
+                    info!(
+                        event_id = reminder.event_id.deref(),
+                        holiday_region = reminder.holiday_region.as_deref(),
+                        "Skipping reminder on public holiday"
+                    );
+                }
+                Ok(false) => to_send.push(reminder),
+                Err(err) => {
+                    // Better to send the reminder than to lose it.
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        "Failed to check public holidays"
+                    );
+                    to_send.push(reminder);
+                }
+            }
+        }
+
+        to_send
+    }
+
+    /// Whether the reminder's event is on a public holiday in the reminder's
+    /// holiday region.
+    async fn is_public_holiday_for_reminder(
+        &self,
+        reminder: &ReminderInstance,
+    ) -> Result<bool, Error> {
+        let region = if let Some(region) = &reminder.holiday_region {
+            region
+        } else {
+            return Ok(false);
+        };
+
+        let config = if let Some(config) = self.config.public_holidays.get(region) {
+            config
+        } else {
+            // The region has been removed from the config since the
+            // reminder was set.
+            return Ok(false);
+        };
+
+        let timezone: Tz = config
+            .timezone
+            .as_deref()
+            .unwrap_or("UTC")
+            .parse()
+            .map_err(|_| anyhow!("Unknown timezone for public holidays in {}", region))?;
+
+        let date = reminder.timestamp.with_timezone(&timezone).date_naive();
+
+        self.database.is_public_holiday(region, date).await
+    }
+
+    /// Get the quiet hours for the room, preferring ones set for the room over
+    /// the config's.
+    pub async fn quiet_hours_for_room(&self, room: &str) -> Result<Option<QuietHours>, Error> {
+        if let Some(quiet_hours) = self.database.get_room_quiet_hours(room).await? {
+            return Ok(Some(quiet_hours));
+        }
+
+        Ok(self.config.quiet_hours.clone())
+    }
+
+    /// Filter out the due reminders that are in their room's quiet hours,
+    /// deferring or suppressing them as configured.
+    async fn apply_quiet_hours(&self, reminders: Vec<ReminderInstance>) -> Vec<ReminderInstance> {
+        let now = Utc::now();
+
+        let mut to_send = Vec::with_capacity(reminders.len());
+        for reminder in reminders {
+            let quiet_hours = match self.quiet_hours_for_room(&reminder.room).await {
+                Ok(Some(quiet_hours)) => quiet_hours,
+                Ok(None) => {
+                    to_send.push(reminder);
+                    continue;
+                }
+                Err(err) => {
+                    // Better to send the reminder than to lose it.
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        "Failed to get quiet hours"
+                    );
+                    to_send.push(reminder);
+                    continue;
+                }
+            };
+
+            if !quiet_hours.is_quiet(now) {
+                to_send.push(reminder);
+                continue;
+            }
+
+            match (quiet_hours.action, quiet_hours.next_allowed(now)) {
+                (QuietHoursAction::Defer, Some(next_allowed)) => {
+                    info!(
+                        event_id = reminder.event_id.deref(),
+                        room = reminder.room.deref(),
+                        %next_allowed,
+                        "Deferring reminder until the end of quiet hours"
+                    );
+                    self.reminders.defer(next_allowed, reminder);
+                }
+                _ => {
+                    info!(
+                        event_id = reminder.event_id.deref(),
+                        room = reminder.room.deref(),
+                        "Suppressing reminder during quiet hours"
+                    );
+                }
+            }
+        }
+
+        to_send
+    }
+
+    /// Send the reminder to the appropriate room, returning the ID of the
+    /// Matrix event.
+    #[instrument(skip(self), fields(status))]
+    async fn send_reminder(&self, reminder: ReminderInstance) -> Result<String, Error> {
+        let room_id = self
+            .join_room(reminder.sender.as_deref(), &reminder.room)
+            .await?;
+
+        let mut event_json = self.render_reminder(&reminder).await?;
+
+        if reminder.threaded {
+            let thread_root = self.get_or_create_thread_root(&room_id, &reminder).await?;
+
+            event_json["m.relates_to"] = json!({
+                "rel_type": "m.thread",
+                "event_id": thread_root,
+                // Clients without thread support will show this as a reply.
+                "is_falling_back": true,
+                "m.in_reply_to": {
+                    "event_id": thread_root,
+                },
+            });
+        }
+
+        let matrix_event_id = self
+            .send_event_with_txn_id(
+                reminder.sender.as_deref(),
+                &room_id,
+                "m.room.message",
+                &reminder_txn_id(&reminder),
+                &event_json,
+            )
+            .await?;
+
+        info!(
+            event_id = reminder.event_id.deref(),
+            room_id = room_id.deref(),
+            "Sent reminder"
+        );
+
+        if self.config.app.send_location.unwrap_or(false) {
+            if let Some(location) = reminder.location.as_deref().and_then(parse_location) {
+                // The reminder has already gone out, so we don't want to fail
+                // (and retry) if this doesn't work.
+                if let Err(err) = self
+                    .send_location(&room_id, &location, &reminder, &event_json["m.relates_to"])
+                    .await
+                {
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        "Failed to send location"
+                    );
+                }
+            }
+        }
+
+        if reminder.poll {
+            if let Err(err) = self
+                .send_poll(&room_id, &reminder, &event_json["m.relates_to"])
+                .await
+            {
+                capture_anyhow(&err);
+                error!(error = err.deref() as &dyn StdError, "Failed to send poll");
+            }
+        }
+
+        if reminder.attach_ics {
+            if let Err(err) = self
+                .send_ics(&room_id, &reminder, &event_json["m.relates_to"])
+                .await
+            {
+                capture_anyhow(&err);
+                error!(
+                    error = err.deref() as &dyn StdError,
+                    "Failed to send .ics file"
+                );
+            }
+        }
+
+        if reminder.redact_previous {
+            let previous = self
+                .database
+                .replace_last_sent_reminder(
+                    reminder.reminder_id,
+                    &room_id,
+                    &matrix_event_id,
+                    reminder.sender.as_deref(),
+                )
+                .await?;
+
+            if let Some((previous_room_id, previous_event_id, previous_sender)) = previous {
+                if let Err(err) = self
+                    .redact_event(
+                        previous_sender.as_deref(),
+                        &previous_room_id,
+                        &previous_event_id,
+                    )
+                    .await
+                {
+                    capture_anyhow(&err);
+                    error!(
+                        error = err.deref() as &dyn StdError,
+                        "Failed to redact previous reminder"
+                    );
+                }
+            }
+        }
+
+        if reminder.live_countdown && reminder.minutes_before > 0 {
+            spawn_local(self.clone().live_countdown(
+                reminder.clone(),
+                room_id.clone(),
+                matrix_event_id.clone(),
+            ));
+        }
+
+        // Record the sent reminder so that we can edit it if the event changes.
+        // (We don't do this for combined reminders, as editing them would
+        // require re-rendering all the other reminders in the message.)
+        self.database
+            .add_sent_reminder(&SentReminder {
+                reminder_id: reminder.reminder_id,
+                timestamp: reminder.timestamp,
+                room_id,
+                matrix_event_id: matrix_event_id.clone(),
+                summary: reminder.summary,
+                location: reminder.location,
+                sender: reminder.sender,
+            })
+            .await?;
+
+        Ok(matrix_event_id)
+    }
+
+    /// Send the location of the event after its reminder, as an `m.location`
+    /// event if we have coordinates or otherwise as a map link.
+    async fn send_location(
+        &self,
+        room_id: &str,
+        location: &EventLocation,
+        reminder: &ReminderInstance,
+        relates_to: &Value,
+    ) -> Result<(), Error> {
+        let mut event_json = match location {
+            EventLocation::Geo {
+                latitude,
+                longitude,
+            } => {
+                let geo_uri = format!("geo:{},{}", latitude, longitude);
+                json!({
+                    "msgtype": "m.location",
+                    "body": format!("Location: {}", geo_uri),
+                    "geo_uri": geo_uri,
+                    "org.matrix.msc3488.location": {
+                        "uri": geo_uri,
+                        "description": reminder.summary,
+                    },
+                })
+            }
+            EventLocation::Address(address) => {
+                let markdown = format!("📍 [{}]({})", address, location.map_url());
+                json!({
+                    "msgtype": self.msgtype(reminder),
+                    "body": markdown,
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+                })
+            }
+        };
+
+        // Keep the location in the same thread as the reminder.
+        if !relates_to.is_null() {
+            event_json["m.relates_to"] = relates_to.clone();
+        }
+
+        self.send_message(reminder.sender.as_deref(), room_id, &event_json)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Upload an .ics file of the event and send it as a file after the
+    /// reminder.
+    async fn send_ics(
+        &self,
+        room_id: &str,
+        reminder: &ReminderInstance,
+        relates_to: &Value,
+    ) -> Result<(), Error> {
+        let ics = reminder_to_ics(reminder);
+        let size = ics.len();
+
+        let content_uri = self
+            .upload_media(
+                reminder.sender.as_deref(),
+                "event.ics",
+                "text/calendar",
+                ics.into_bytes(),
+            )
+            .await?;
+
+        let mut event_json = json!({
+            "msgtype": "m.file",
+            "body": "event.ics",
+            "filename": "event.ics",
+            "url": content_uri,
+            "info": {
+                "mimetype": "text/calendar",
+                "size": size,
+            },
+        });
+
+        if !relates_to.is_null() {
+            event_json["m.relates_to"] = relates_to.clone();
+        }
+
+        self.send_message(reminder.sender.as_deref(), room_id, &event_json)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Upload a file to the media repository, returning its `mxc://` URI.
+    pub async fn upload_media(
+        &self,
+        sender: Option<&str>,
+        filename: &str,
+        content_type: &str,
+        body: Vec<u8>,
+    ) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        let url = format!(
+            "{}/_matrix/media/r0/upload?filename={}",
+            credentials.homeserver_url,
+            encode(filename),
+        );
+
+        let resp = credentials
+            .authenticate(self.http_client.post(&url))
+            .header("Content-Type", content_type)
+            .body(body)
+            .send()
+            .await
+            .with_context(|| "Sending HTTP upload request")?;
+
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from /upload response: {}", resp.status());
+        }
+
+        let body: MatrixUploadResponse = resp.json().await?;
+
+        Ok(body.content_uri)
+    }
+
+    /// Post an attendance poll for the reminder.
+    async fn send_poll(
+        &self,
+        room_id: &str,
+        reminder: &ReminderInstance,
+        relates_to: &Value,
+    ) -> Result<(), Error> {
+        let question = format!(
+            "Attending {}?",
+            reminder.summary.as_deref().unwrap_or("the meeting")
+        );
+
+        // Clients without poll support will show the text fallback.
+        let fallback = std::iter::once(question.clone())
+            .chain(
+                POLL_ANSWERS
+                    .iter()
+                    .enumerate()
+                    .map(|(i, (_, text))| format!("{}. {}", i + 1, text)),
+            )
+            .join("\n");
+
+        let mut event_json = json!({
+            POLL_START_TYPE: {
+                "kind": "org.matrix.msc3381.poll.disclosed",
+                "max_selections": 1,
+                "question": {
+                    "org.matrix.msc1767.text": question,
+                },
+                "answers": POLL_ANSWERS.iter().map(|(id, text)| json!({
+                    "id": id,
+                    "org.matrix.msc1767.text": text,
+                })).collect_vec(),
+            },
+            "org.matrix.msc1767.text": fallback,
+        });
+
+        if !relates_to.is_null() {
+            event_json["m.relates_to"] = relates_to.clone();
+        }
+
+        let poll_event_id = self
+            .send_event(
+                reminder.sender.as_deref(),
+                room_id,
+                POLL_START_TYPE,
+                &event_json,
+            )
+            .await?;
+
+        self.database
+            .add_reminder_poll(
+                reminder.reminder_id,
+                reminder.timestamp,
+                room_id,
+                &poll_event_id,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the root message of the reminder's thread, posting a new one if we
+    /// haven't yet.
+    async fn get_or_create_thread_root(
+        &self,
+        room_id: &str,
+        reminder: &ReminderInstance,
+    ) -> Result<String, Error> {
+        if let Some(thread_root) = self
+            .database
+            .get_reminder_thread_root(reminder.reminder_id)
+            .await?
+        {
+            return Ok(thread_root);
+        }
+
+        let markdown = format!(
+            "Reminders for **{}**",
+            reminder.summary.as_deref().unwrap_or("Meeting"),
+        );
+
+        let event_json = json!({
+            "msgtype": self.msgtype(reminder),
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+        });
+
+        let thread_root = self
+            .send_message(reminder.sender.as_deref(), room_id, &event_json)
+            .await?;
+
+        self.database
+            .set_reminder_thread_root(reminder.reminder_id, &thread_root)
+            .await?;
+
+        Ok(thread_root)
+    }
+
+    /// Tell the room that an event it had an upcoming reminder for has been
+    /// cancelled.
+    #[instrument(skip(self), fields(status))]
+    async fn send_cancellation_notice(
+        &self,
+        reminder: &ReminderInstance,
+        event_time: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let room_id = self
+            .join_room(reminder.sender.as_deref(), &reminder.room)
+            .await?;
+
+        let human = HumanTime::from(event_time - Utc::now());
+        let markdown = format!(
+            "**{}** ({}) has been cancelled.",
+            reminder.summary.as_deref().unwrap_or("Meeting"),
+            human.to_text_en(Accuracy::Rough, Tense::Future),
+        );
+
+        let event_json = json!({
+            "msgtype": self.msgtype(reminder),
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+        });
+
+        self.send_message(reminder.sender.as_deref(), &room_id, &event_json)
+            .await?;
+
+        info!(
+            event_id = reminder.event_id.deref(),
+            room_id = room_id.deref(),
+            "Sent cancellation notice"
+        );
+
+        Ok(())
+    }
+
+    /// Tell the room that an event it has an upcoming reminder for has moved
+    /// or changed location.
+    #[instrument(skip(self), fields(status))]
+    async fn send_event_change_notice(
+        &self,
+        reminder: &ReminderInstance,
+        change: &EventChange,
+        timezone: Tz,
+    ) -> Result<(), Error> {
+        let room_id = self
+            .join_room(reminder.sender.as_deref(), &reminder.room)
+            .await?;
+
+        let markdown = render_event_change_notice(reminder.summary.as_deref(), change, timezone)?;
+
+        let event_json = json!({
+            "msgtype": self.msgtype(reminder),
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+        });
+
+        self.send_message(reminder.sender.as_deref(), &room_id, &event_json)
+            .await?;
+
+        info!(
+            event_id = reminder.event_id.deref(),
+            room_id = room_id.deref(),
+            "Sent event change notice"
+        );
+
+        Ok(())
+    }
+
+    /// Tell the rooms subscribed to the calendar about the new events that
+    /// match their filters.
+    async fn notify_event_subscriptions(
+        &self,
+        calendar_id: i64,
+        new_events: &[(Option<String>, DateTime<Utc>)],
+        timezone: Tz,
+    ) -> Result<(), Error> {
+        let subscriptions = self
+            .database
+            .get_event_subscriptions_for_calendar(calendar_id)
+            .await?;
+
+        for subscription in subscriptions {
+            let pattern = match subscription
+                .summary_pattern
+                .as_deref()
+                .map(compile_summary_pattern)
+                .transpose()
+            {
+                Ok(pattern) => pattern,
+                Err(error) => {
+                    // We validate patterns when subscriptions are added, so
+                    // this shouldn't happen.
+                    warn!(
+                        error = error.deref() as &dyn StdError,
+                        subscription_id = subscription.subscription_id,
+                        "Invalid event subscription pattern"
+                    );
+                    continue;
+                }
+            };
+
+            let matching_events = new_events
+                .iter()
+                .filter(|(summary, _)| match (&pattern, summary) {
+                    (None, _) => true,
+                    (Some(pattern), Some(summary)) => pattern.is_match(summary),
+                    (Some(_), None) => false,
+                })
+                .cloned()
+                .collect_vec();
+            if matching_events.is_empty() {
+                continue;
+            }
+
+            let markdown = render_new_events_notice(&matching_events, timezone);
+
+            let result = async {
+                let room_id = self.join_room(None, &subscription.room).await?;
+
+                let event_json = json!({
+                    "msgtype": self.default_msgtype(),
+                    "body": markdown,
+                    "format": "org.matrix.custom.html",
+                    "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+                });
+
+                self.send_message(None, &room_id, &event_json).await
+            }
+            .await;
+
+            match result {
+                Ok(_) => info!(
+                    calendar_id,
+                    subscription_id = subscription.subscription_id,
+                    num_events = matching_events.len(),
+                    "Sent new event notice"
+                ),
+                Err(error) => {
+                    capture_anyhow(&error);
+                    error!(
+                        error = error.deref() as &dyn StdError,
+                        subscription_id = subscription.subscription_id,
+                        "Failed to send new event notice"
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a set of reminders that are due at the same time in the same room
+    /// as a single message.
+    #[instrument(skip(self, reminders), fields(status))]
+    async fn send_combined_reminders(
+        &self,
+        sender: Option<&str>,
+        room: &str,
+        reminders: Vec<ReminderInstance>,
+    ) -> Result<String, Error> {
+        let room_id = self.join_room(sender, room).await?;
+
+        let mut bodies = Vec::with_capacity(reminders.len());
+        let mut formatted_bodies = Vec::with_capacity(reminders.len());
+        let mut mentioned_user_ids = BTreeSet::new();
+        for reminder in &reminders {
+            let event_json = self.render_reminder(reminder).await?;
+            bodies.push(event_json["body"].as_str().unwrap_or_default().to_string());
+            if let Some(user_ids) = event_json["m.mentions"]["user_ids"].as_array() {
+                mentioned_user_ids.extend(user_ids.iter().filter_map(Value::as_str));
+            }
+            formatted_bodies.push(
+                event_json["formatted_body"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .to_string(),
+            );
+        }
+
+        // Only use the reminders' msgtype if they all agree.
+        let msgtype = reminders
+            .iter()
+            .map(|reminder| self.msgtype(reminder))
+            .dedup()
+            .exactly_one()
+            .unwrap_or_else(|_| self.default_msgtype());
+
+        let event_json = json!({
+            "msgtype": msgtype,
+            "body": bodies.join("\n\n---\n\n"),
+            "format": "org.matrix.custom.html",
+            "formatted_body": formatted_bodies.join("<hr>"),
+            "m.mentions": { "user_ids": mentioned_user_ids },
+        });
+
+        let txn_id = format!(
+            "combined-{}",
+            reminders.iter().map(reminder_txn_id).join("_")
+        );
+
+        let matrix_event_id = self
+            .send_event_with_txn_id(sender, &room_id, "m.room.message", &txn_id, &event_json)
+            .await?;
+
+        info!(
+            count = reminders.len(),
+            room_id = room_id.deref(),
+            "Sent combined reminders"
+        );
+
+        Ok(matrix_event_id)
+    }
+
+    /// Join the given room (if we haven't already), returning the room ID.
+    async fn join_room(&self, sender: Option<&str>, room: &str) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        // Join the room, making sure we retry requests that fail with a 5xx error.
+        let mut retry_counter = 0;
+        let body = loop {
+            let join_url = format!(
+                "{}/_matrix/client/r0/join/{}",
+                credentials.homeserver_url,
+                encode(room),
+            );
+
+            let resp = credentials
+                .authenticate(self.http_client.post(&join_url))
+                .json(&json!({}))
+                .send()
+                .await
+                .with_context(|| "Sending HTTP /join request")?;
+
+            if !resp.status().is_success() {
+                warn!("Got non-2xx from /join response: {}", resp.status());
+                if resp.status().is_server_error() && retry_counter < 5 {
+                    retry_counter += 1;
+                    continue;
+                }
+
+                bail!("Got non-2xx from /join response: {}", resp.status());
+            }
+
+            let body: MatrixJoinResponse = resp.json().await?;
+
+            break body;
+        };
+
+        Ok(body.room_id)
+    }
+
+    /// Check that the reminder's settings are valid, and that it can be sent
+    /// to its room.
+    ///
+    /// Returns a description of the problem if not, suitable for showing to
+    /// the user.
+    pub async fn validate_reminder(&self, reminder: &Reminder) -> Result<Option<String>, Error> {
+        if !matches!(
+            reminder.msgtype.as_deref(),
+            None | Some("m.text" | "m.notice")
+        ) {
+            return Ok(Some("Invalid msgtype".to_string()));
+        }
+
+        if let Some(sender) = &reminder.sender {
+            if self.config.matrix.credentials(Some(sender)).is_none() {
+                return Ok(Some("Unknown sender".to_string()));
+            }
+        }
+
+        if let Some(region) = &reminder.holiday_region {
+            if !self.config.public_holidays.contains_key(region) {
+                return Ok(Some("Unknown holiday region".to_string()));
+            }
+        }
+
+        if let Some(locale) = &reminder.locale {
+            if get_locale(locale).is_none() {
+                return Ok(Some("Unknown language".to_string()));
+            }
+        }
+
+        if reminder.weekdays == Some(0) {
+            return Ok(Some("Pick at least one day to send on".to_string()));
+        }
+
+        self.validate_room(reminder.sender.as_deref(), &reminder.room)
+            .await
+    }
+
+    /// Check that reminders can be sent to the room by the given sender,
+    /// joining it if we haven't already.
+    ///
+    /// Returns a description of the problem if not, suitable for showing to
+    /// the user.
+    pub async fn validate_room(
+        &self,
+        sender: Option<&str>,
+        room: &str,
+    ) -> Result<Option<String>, Error> {
+        if !room.starts_with('!') && !room.starts_with('#') {
+            return Ok(Some(format!(
+                "{} is not a room ID or alias, e.g. #room:example.com",
+                room
+            )));
+        }
+
+        let credentials = self.matrix_credentials(sender)?;
+
+        let join_url = format!(
+            "{}/_matrix/client/r0/join/{}",
+            credentials.homeserver_url,
+            encode(room)
+        );
+
+        let resp = credentials
+            .authenticate(self.http_client.post(&join_url))
+            .json(&json!({}))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP /join request")?;
+
+        match resp.status().as_u16() {
+            200..=299 => {}
+            403 => {
+                return Ok(Some(format!(
+                    "The bot isn't allowed to join {}. Try inviting it first.",
+                    room
+                )))
+            }
+            404 => return Ok(Some(format!("Couldn't find the room {}.", room))),
+            status => bail!("Got non-2xx from /join response: {}", status),
+        }
+
+        let room_id = resp.json::<MatrixJoinResponse>().await?.room_id;
+
+        let power_levels_url = format!(
+            "{}/_matrix/client/r0/rooms/{}/state/m.room.power_levels/",
+            credentials.homeserver_url,
+            encode(&room_id),
+        );
+
+        let resp = credentials
+            .authenticate(self.http_client.get(&power_levels_url))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP power levels request")?;
+
+        // Rooms without power levels let everyone send messages.
+        let power_levels: MatrixPowerLevels = if resp.status().as_u16() == 404 {
+            MatrixPowerLevels::default()
+        } else if resp.status().is_success() {
+            resp.json().await?
+        } else {
+            bail!("Got non-2xx from power levels response: {}", resp.status());
+        };
+
+        let user_id = self.whoami(sender).await?;
+
+        let user_level = power_levels
+            .users
+            .get(&user_id)
+            .copied()
+            .unwrap_or(power_levels.users_default);
+        let required_level = power_levels
+            .events
+            .get("m.room.message")
+            .copied()
+            .unwrap_or(power_levels.events_default);
+
+        if user_level < required_level {
+            return Ok(Some(format!(
+                "The bot doesn't have permission to send messages in {}.",
+                room
+            )));
+        }
+
+        Ok(None)
+    }
+
+    /// Render a reminder with the given settings for the next instance of the
+    /// event, returning the Markdown and HTML of the message. If the event
+    /// has no upcoming instances then a made up one an hour from now is used.
+    ///
+    /// Returns `None` if the event doesn't exist.
+    pub async fn preview_reminder(
+        &self,
+        calendar_id: i64,
+        event_id: &str,
+        template: Option<String>,
+        locale: Option<String>,
+        minutes_before: i64,
+        room: String,
+    ) -> Result<Option<(String, String)>, Error> {
+        let (event, instances) = if let Some(res) = self
+            .database
+            .get_event_in_calendar(calendar_id, event_id)
+            .await?
+        {
+            res
+        } else {
+            return Ok(None);
+        };
+
+        let (timestamp, attendees) = if let Some(instance) = instances.into_iter().next() {
+            (instance.date.with_timezone(&Utc), instance.attendees)
+        } else {
+            (Utc::now() + Duration::hours(1), event.attendees)
+        };
+
+        let reminder = ReminderInstance {
+            reminder_id: 0,
+            calendar_id,
+            event_id: event.event_id,
+            timestamp,
+            summary: event.summary,
+            description: event.description,
+            location: event.location,
+            conference_url: event.conference_url,
+            template,
+            locale,
+            minutes_before,
+            room,
+            attendees,
+            extra_attendees: Vec::new(),
+            excluded_attendees: Vec::new(),
+            threaded: false,
+            poll: false,
+            msgtype: None,
+            sender: None,
+            snoozed_until: None,
+            redact_previous: false,
+            attach_ics: false,
+            holiday_region: None,
+            skip_if_declined: false,
+            exclude_tentative: false,
+            exclude_needs_action: false,
+            live_countdown: false,
+            last_occurrence: event.last_occurrence == Some(timestamp),
+            adhoc: false,
+            duplicate_reminder_ids: Vec::new(),
+        };
+
+        let event_json = self.render_reminder(&reminder).await?;
+
+        let markdown = event_json["body"].as_str().unwrap_or_default().to_string();
+        let html = event_json["formatted_body"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+
+        Ok(Some((markdown, html)))
+    }
+
+    /// Render the reminder into the content of a Matrix message.
+    ///
+    /// The reminder's own template is used if it has one, falling back to the
+    /// room's default template and then the global default. The same goes for
+    /// the language it's rendered in.
+    async fn render_reminder(&self, reminder: &ReminderInstance) -> Result<Value, Error> {
+        let room_template = if reminder.template.is_none() {
+            self.database.get_room_template(&reminder.room).await?
+        } else {
+            None
+        };
+
+        let markdown_template = reminder
+            .template
+            .as_deref()
+            .or(room_template.as_deref())
+            .unwrap_or(DEFAULT_TEMPLATE);
+
+        let room_locale = if reminder.locale.is_none() {
+            self.database.get_room_locale(&reminder.room).await?
+        } else {
+            None
+        };
+
+        let locale = reminder
+            .locale
+            .as_deref()
+            .or(room_locale.as_deref())
+            .and_then(get_locale)
+            .unwrap_or_else(default_locale);
+
+        // We fetch both the emails and matrix IDs of people on holiday as a)
+        // not everyone has an associated matrix ID and b) the attendee email
+        // may not be using the person's canonical email.
+        let out_today_emails = self.database.get_out_today_emails().await?;
+        let out_today_matrix_ids = self.database.get_out_today_matrix_ids().await?;
+        let mentions_disabled_matrix_ids = self.database.get_mentions_disabled_matrix_ids().await?;
+
+        let reminder_attendees = apply_attendee_overrides(
+            &reminder.attendees_to_mention(),
+            &reminder.extra_attendees,
+            &reminder.excluded_attendees,
+            &self.email_to_matrix_id.lock().expect("poisoned"),
+        );
+
+        let out_today_count = reminder_attendees
+            .iter()
+            .filter(|attendee| {
+                out_today_emails.contains(&attendee.email)
+                    || out_today_matrix_ids.contains(&attendee.email)
+                    || self
+                        .email_to_matrix_id
+                        .lock()
+                        .expect("poisoned")
+                        .get(&attendee.email)
+                        .is_some_and(|matrix_ids| {
+                            matrix_ids.iter().any(|m| out_today_matrix_ids.contains(m))
+                        })
+            })
+            .count();
+
+        // One-off reminders aren't for an event in a calendar.
+        let (event, calendar) = if reminder.adhoc {
+            (None, None)
+        } else {
+            let event = self
+                .database
+                .get_event_in_calendar(reminder.calendar_id, &reminder.event_id)
+                .await?
+                .map(|(event, _)| event);
+            let calendar = self.database.get_calendar(reminder.calendar_id).await?;
+            (event, calendar)
+        };
+
+        let timezone: Tz = calendar
+            .as_ref()
+            .and_then(|calendar| calendar.timezone.as_deref())
+            .and_then(|timezone| timezone.parse().ok())
+            .unwrap_or(Tz::UTC);
+
+        let start = reminder.timestamp.with_timezone(&timezone);
+        let end = event
+            .as_ref()
+            .and_then(|event| event.duration_minutes)
+            .map(|duration| start + Duration::minutes(duration));
+
+        // Show the start time in the timezones of attendees that have set
+        // one, for teams spread across the world.
+        let attendee_timezones = self
+            .database
+            .get_attendee_timezones(
+                &reminder_attendees
+                    .iter()
+                    .map(|attendee| attendee.email.clone())
+                    .collect_vec(),
+            )
+            .await?
+            .into_iter()
+            .filter_map(|timezone| timezone.parse::<Tz>().ok())
+            .collect_vec();
+        let attendee_times = attendee_times(reminder.timestamp, &attendee_timezones);
+
+        // Images uploaded for the template to include, by name. One-off
+        // reminders can't have any, and their IDs are from a different table.
+        let images: BTreeMap<String, String> = if reminder.adhoc {
+            BTreeMap::new()
+        } else {
+            self.database
+                .get_reminder_images(reminder.reminder_id)
+                .await?
+                .into_iter()
+                .map(|image| (image.name, image.content_uri))
+                .collect()
+        };
+
+        let organizer = event
+            .as_ref()
+            .and_then(|event| event.organizer.as_ref())
+            .map(|organizer| organizer.common_name.as_ref().unwrap_or(&organizer.email));
+
+        // The users we mention, for the `m.mentions` metadata.
+        let mut mentioned_user_ids = Vec::new();
+
+        let attendees = reminder_attendees
+            .iter()
+            .filter(|attendee| !out_today_emails.contains(&attendee.email))
+            .filter_map(|attendee| {
+                // Map attendee email to a markdown string, filtering out people
+                // with any matrix ID that we know is on holiday, and mentioning
+                // their preferred matrix ID. Manually added attendees may be
+                // given directly as a Matrix ID.
+                let mut matrix_ids = self
+                    .email_to_matrix_id
+                    .lock()
+                    .expect("poisoned")
+                    .get(&attendee.email)
+                    .cloned()
+                    .unwrap_or_default();
+
+                if matrix_ids.is_empty() && is_likely_a_valid_user_id(&attendee.email) {
+                    matrix_ids.push(attendee.email.clone());
+                }
+
+                if let Some(matrix_id) = matrix_ids.first() {
+                    if matrix_ids.iter().any(|m| out_today_matrix_ids.contains(m)) {
+                        None
+                    } else if matrix_ids
+                        .iter()
+                        .any(|m| mentions_disabled_matrix_ids.contains(m))
+                    {
+                        // They don't want to be pinged, so we just use their name.
+                        Some(
+                            attendee
+                                .common_name
+                                .as_ref()
+                                .unwrap_or(matrix_id)
+                                .to_string(),
+                        )
+                    } else {
+                        mentioned_user_ids.push(matrix_id.clone());
+
+                        Some(format!(
+                            "[{}](https://matrix.to/#/{})",
+                            attendee.common_name.as_ref().unwrap_or(matrix_id),
+                            matrix_id,
+                        ))
+                    }
+                } else {
+                    Some(
+                        attendee
+                            .common_name
+                            .as_ref()
+                            .unwrap_or(&attendee.email)
+                            .to_string(),
+                    )
+                }
+            })
+            .join(", ");
+
+        // The description may be in HTML so we first render the template with a
+        // unique token that we can later replace with the actual description
+        let description_token: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+
+        let handlebars = reminder_handlebars(
+            timezone,
+            locale,
+            reminder.description.as_ref().map(|description| {
+                (
+                    description_token.clone(),
+                    ammonia::Builder::empty().clean(description).to_string(),
+                )
+            }),
+        );
+        let markdown = handlebars
+            .render_template(
+                markdown_template,
+                &json!({
+                    "event_id": &reminder.event_id,
+                    "summary": &reminder.summary,
+                    "description": reminder.description.as_ref().map(|_| &description_token),
+                    "location": &reminder.location,
+                    "minutes_before": &reminder.minutes_before,
+                    "duration": locale.format_duration(reminder.minutes_before),
+                    "attendees": attendees,
+                    "event_url": if reminder.adhoc {
+                        None
+                    } else {
+                        self.event_url(reminder.calendar_id, &reminder.event_id)
+                    },
+                    "conference_url": &reminder.conference_url,
+                    "organizer": organizer,
+                    "start": start.to_rfc3339(),
+                    "end": end.map(|end| end.to_rfc3339()),
+                    "start_time": start.format("%H:%M").to_string(),
+                    "end_time": end.map(|end| end.format("%H:%M").to_string()),
+                    "timezone": timezone.name(),
+                    "locale": locale.code,
+                    "attendee_count": reminder_attendees.len(),
+                    "out_today_count": out_today_count,
+                    "accepted_count": count_attendees_with_status(&reminder.attendees, "ACCEPTED"),
+                    "tentative_count": count_attendees_with_status(&reminder.attendees, "TENTATIVE"),
+                    "declined_count": count_attendees_with_status(&reminder.attendees, "DECLINED"),
+                    "needs_action_count": count_attendees_with_status(&reminder.attendees, "NEEDS-ACTION"),
+                    "attendee_times": attendee_times,
+                    "last_occurrence": reminder.last_occurrence,
+                    "images": images,
+                    "calendar_name": calendar.as_ref().map(|calendar| &calendar.name),
+                    "room": &reminder.room,
+                }),
+            )
+            .with_context(|| "Rendering body template")?;
+
+        let event_json = if let Some(desc) = &reminder.description {
+            let cleaned_html = ammonia::clean(desc);
+
+            json!({
+                "msgtype": self.msgtype(reminder),
+                "body": markdown.replace(&description_token, desc),
+                "format": "org.matrix.custom.html",
+                "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()).replace(&description_token, &cleaned_html),
+                "m.mentions": { "user_ids": mentioned_user_ids },
+            })
+        } else {
+            json!({
+                "msgtype": self.msgtype(reminder),
+                "body": markdown,
+                "format": "org.matrix.custom.html",
+                "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+                "m.mentions": { "user_ids": mentioned_user_ids },
+            })
+        };
+
+        Ok(event_json)
+    }
+
+    /// Send a message into the given room.
+    async fn send_message(
+        &self,
+        sender: Option<&str>,
+        room_id: &str,
+        event_json: &Value,
+    ) -> Result<String, Error> {
+        self.send_event(sender, room_id, "m.room.message", event_json)
+            .await
+    }
+
+    /// Send an event of the given type into the room.
+    async fn send_event(
+        &self,
+        sender: Option<&str>,
+        room_id: &str,
+        event_type: &str,
+        event_json: &Value,
+    ) -> Result<String, Error> {
+        let txn_id: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(16)
+            .map(char::from)
+            .collect();
+
+        self.send_event_with_txn_id(sender, room_id, event_type, &txn_id, event_json)
+            .await
+    }
+
+    /// Send an event into the room with the given transaction ID. The
+    /// homeserver won't send the event again if it has already seen the
+    /// transaction ID, so it is safe to retry requests.
+    async fn send_event_with_txn_id(
+        &self,
+        sender: Option<&str>,
+        room_id: &str,
+        event_type: &str,
+        txn_id: &str,
+        event_json: &Value,
+    ) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/send/{}/{}",
+            credentials.homeserver_url,
+            encode(room_id),
+            encode(event_type),
+            encode(txn_id),
+        );
+
+        // Retry requests that fail due to network errors or 5xx errors.
+        let mut retry_counter = 0;
+        let resp = loop {
+            let result = credentials
+                .authenticate(self.http_client.put(&url))
+                .json(event_json)
+                .send()
+                .await
+                .with_context(|| "Sending HTTP send message request");
+
+            let retryable = match &result {
+                Ok(resp) => resp.status().is_server_error(),
+                Err(_) => true,
+            };
+
+            if retryable && retry_counter < 5 {
+                warn!(txn_id, retry_counter, "Failed to send event, retrying");
+                retry_counter += 1;
+                sleep(std::time::Duration::from_secs(1 << retry_counter)).await;
+                continue;
+            }
+
+            break result?;
+        };
+
+        Span::current().record("status", resp.status().as_u16());
+
+        info!(status = resp.status().as_u16(), room_id, "Sent message");
+
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from /send response: {}", resp.status());
+        }
+
+        let body: MatrixSendResponse = resp.json().await?;
+
+        Ok(body.event_id)
+    }
+
+    /// Redact an event we previously sent.
+    async fn redact_event(
+        &self,
+        sender: Option<&str>,
+        room_id: &str,
+        event_id: &str,
+    ) -> Result<(), Error> {
+        let credentials = self.matrix_credentials(sender)?;
+
+        // We'll only ever redact an event once, so we can use its ID for the
+        // transaction ID.
+        let url = format!(
+            "{}/_matrix/client/r0/rooms/{}/redact/{}/{}",
+            credentials.homeserver_url,
+            encode(room_id),
+            encode(event_id),
+            encode(&format!("redact-{}", event_id)),
+        );
+
+        let resp = credentials
+            .authenticate(self.http_client.put(&url))
+            .json(&json!({ "reason": "Superseded by a newer reminder" }))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP redact request")?;
+
+        info!(
+            status = resp.status().as_u16(),
+            room_id, event_id, "Redacted event"
+        );
+
+        if !resp.status().is_success() {
+            bail!("Got non-2xx from /redact response: {}", resp.status());
+        }
+
+        Ok(())
+    }
+
+    /// Replace the content of a message we previously sent.
+    async fn edit_message(
+        &self,
+        sender: Option<&str>,
+        room_id: &str,
+        matrix_event_id: &str,
+        new_content: &Value,
+    ) -> Result<String, Error> {
+        // Clients that don't support edits will show the fallback body.
+        let event_json = json!({
+            "msgtype": new_content["msgtype"],
+            "body": format!("* {}", new_content["body"].as_str().unwrap_or_default()),
+            "format": "org.matrix.custom.html",
+            "formatted_body": format!("* {}", new_content["formatted_body"].as_str().unwrap_or_default()),
+            "m.new_content": new_content,
+            // Don't ping people again just because the reminder was edited.
+            "m.mentions": {},
+            "m.relates_to": {
+                "rel_type": "m.replace",
+                "event_id": matrix_event_id,
+            },
+        });
+
+        self.send_message(sender, room_id, &event_json).await
+    }
+
+    /// Edit a sent reminder every minute with the time left until the event
+    /// starts, and then one last time without the countdown once it has.
+    ///
+    /// Stops early if the reminder is deleted or the event moves, as then the
+    /// countdown would be wrong.
+    #[instrument(skip(self, reminder), fields(reminder_id = reminder.reminder_id))]
+    async fn live_countdown(
+        self,
+        reminder: ReminderInstance,
+        room_id: String,
+        matrix_event_id: String,
+    ) {
+        loop {
+            let seconds_left = (reminder.timestamp - Utc::now()).num_seconds();
+            if seconds_left <= 0 {
+                break;
+            }
+
+            // Wake up on the whole minutes before the event starts.
+            let sleep_seconds = match seconds_left % 60 {
+                0 => 60,
+                seconds => seconds,
+            };
+            sleep(std::time::Duration::from_secs(sleep_seconds as u64)).await;
+
+            let instance = match self
+                .database
+                .get_reminder_instance(reminder.reminder_id, reminder.timestamp)
+                .await
+            {
+                Ok(Some(instance)) => instance,
+                Ok(None) => {
+                    info!("Reminder or event gone, stopping countdown");
+                    return;
+                }
+                Err(error) => {
+                    capture_anyhow(&error);
+                    error!(
+                        error = error.deref() as &dyn StdError,
+                        "Failed to fetch reminder for countdown"
+                    );
+                    return;
+                }
+            };
+
+            let minutes_left = countdown_minutes(instance.timestamp, Utc::now());
+
+            if let Err(error) = self
+                .edit_countdown(&instance, minutes_left, &room_id, &matrix_event_id)
+                .await
+            {
+                capture_anyhow(&error);
+                error!(
+                    error = error.deref() as &dyn StdError,
+                    "Failed to edit countdown"
+                );
+                return;
+            }
+
+            if minutes_left == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Edit a sent reminder to say the event starts in the given number of
+    /// minutes, or to drop the countdown if zero.
+    async fn edit_countdown(
+        &self,
+        reminder: &ReminderInstance,
+        minutes_left: i64,
+        room_id: &str,
+        matrix_event_id: &str,
+    ) -> Result<(), Error> {
+        let reminder = ReminderInstance {
+            minutes_before: minutes_left,
+            ..reminder.clone()
+        };
+
+        let content = self.render_reminder(&reminder).await?;
+
+        self.edit_message(
+            reminder.sender.as_deref(),
+            room_id,
+            matrix_event_id,
+            &content,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Edit any reminders we've sent for events in the calendar that haven't
+    /// started yet, if the time, summary or location of the event has
+    /// changed.
+    #[instrument(skip(self))]
+    async fn edit_sent_reminders(&self, calendar_id: i64) -> Result<(), Error> {
+        let sent_reminders = self
+            .database
+            .get_upcoming_sent_reminders(calendar_id)
+            .await?;
+
+        for sent in sent_reminders {
+            // If the event has been moved we won't find an instance at the same
+            // time, so we look for the nearest instance within a day.
+            let instances = self
+                .database
+                .get_reminder_instances_between(
+                    sent.reminder_id,
+                    sent.timestamp - Duration::days(1),
+                    sent.timestamp + Duration::days(1),
+                )
+                .await?;
+
+            let instance = if let Some(instance) = instances
+                .into_iter()
+                .min_by_key(|i| (i.timestamp - sent.timestamp).num_seconds().abs())
+            {
+                instance
+            } else {
+                continue;
+            };
+
+            if instance.timestamp == sent.timestamp
+                && instance.summary == sent.summary
+                && instance.location == sent.location
+            {
+                continue;
+            }
+
+            info!(
+                reminder_id = sent.reminder_id,
+                event_id = instance.event_id.deref(),
+                "Event changed since reminder was sent, editing"
+            );
+
+            let content = self.render_reminder(&instance).await?;
+
+            if let Err(error) = self
+                .edit_message(
+                    sent.sender.as_deref(),
+                    &sent.room_id,
+                    &sent.matrix_event_id,
+                    &content,
+                )
+                .await
+            {
+                capture_anyhow(&error);
+                error!(
+                    error = error.deref() as &dyn StdError,
+                    "Failed to edit sent reminder"
+                );
+                continue;
+            }
+
+            self.database
+                .update_sent_reminder(
+                    sent.reminder_id,
+                    sent.timestamp,
+                    instance.timestamp,
+                    instance.summary.as_deref(),
+                    instance.location.as_deref(),
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// An infinite loop that periodically pulls email to Matrix ID mappings and
+    /// holidays from HiBob.
+    async fn hibob_loop(&self) {
+        let config = if let Some(config) = &self.config.hibob {
+            config
+        } else {
+            return;
+        };
+
+        interval_process("hibob_loop", Duration::minutes(5), || {
+            AssertUnwindSafe(async {
+                if let Err(error) = self.update_email_mappings(config).await {
+                    capture_anyhow(&error);
+                    error!(
+                        error = error.deref() as &dyn StdError,
+                        "Failed to update email mappings"
+                    );
+                }
+
+                if let Err(error) = self.update_holidays(config).await {
+                    capture_anyhow(&error);
+                    error!(
+                        error = error.deref() as &dyn StdError,
+                        "Failed to update holidays"
+                    );
+                }
+                Ok(())
+            })
+        })
+        .await;
+    }
+
+    /// An infinite loop that sends the weekly summaries to users who have
+    /// opted in, once they're due.
+    async fn weekly_summary_loop(&self) {
+        interval_process("weekly_summary", Duration::minutes(15), || {
+            AssertUnwindSafe(self.send_due_weekly_summaries(Utc::now()))
+        })
+        .await;
+    }
+
+    /// Send the weekly summaries that are due, i.e. to users for whom it is
+    /// Monday morning and who haven't had this week's summary yet.
+    #[instrument(skip(self))]
+    pub async fn send_due_weekly_summaries(&self, now: DateTime<Utc>) -> Result<(), Error> {
+        let users = self.database.get_weekly_summary_users().await?;
+
+        for user in users {
+            let timezone: Tz = user
+                .timezone
+                .as_deref()
+                .and_then(|timezone| timezone.parse().ok())
+                .unwrap_or(Tz::UTC);
+
+            let local_now = now.with_timezone(&timezone);
+            if local_now.weekday() != Weekday::Mon || local_now.hour() < WEEKLY_SUMMARY_HOUR {
+                continue;
+            }
+
+            let week_start = local_now.date_naive();
+            if user.sent_for >= Some(week_start) {
+                continue;
+            }
+
+            let user_id = user.user_id;
+            if let Err(error) = self.send_weekly_summary(&user, week_start, timezone).await {
+                capture_anyhow(&error);
+                error!(
+                    error = error.deref() as &dyn StdError,
+                    user_id, "Failed to send weekly summary"
+                );
+                continue;
+            }
+
+            self.database
+                .set_weekly_summary_sent_for(user_id, week_start)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// DM the user a summary of their events with reminders in the week
+    /// starting on the given Monday.
+    async fn send_weekly_summary(
+        &self,
+        user: &WeeklySummaryUser,
+        week_start: NaiveDate,
+        timezone: Tz,
+    ) -> Result<(), Error> {
+        let room_id = if let Some(room_id) = &user.room_id {
+            room_id.clone()
+        } else {
+            self.get_or_create_dm_room(user.user_id).await?
+        };
+
+        let markdown = self
+            .render_weekly_summary(user.user_id, week_start, timezone)
+            .await?;
+
+        let event_json = json!({
+            "msgtype": "m.notice",
+            "body": markdown,
+            "format": "org.matrix.custom.html",
+            "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
+            "m.mentions": {},
+        });
+
+        // Use a fixed transaction ID so that retries don't post the summary
+        // twice.
+        self.send_event_with_txn_id(
+            None,
+            &room_id,
+            "m.room.message",
+            &format!("weekly-summary-{}-{}", user.user_id, week_start),
+            &event_json,
+        )
+        .await?;
+
+        info!(user_id = user.user_id, %week_start, "Sent weekly summary");
+
+        Ok(())
+    }
+
+    /// Render the markdown of the user's weekly summary, with their events
+    /// grouped by day.
+    pub async fn render_weekly_summary(
+        &self,
+        user_id: i64,
+        week_start: NaiveDate,
+        timezone: Tz,
+    ) -> Result<String, Error> {
+        let events = self.database.get_events_with_reminders(user_id).await?;
+
+        let start = week_start
+            .and_hms_opt(0, 0, 0)
+            .and_then(|start| timezone.from_local_datetime(&start).earliest())
+            .context("Invalid start of week")?;
+        let end = start + Duration::days(7);
+
+        let mut days: BTreeMap<NaiveDate, Vec<_>> = BTreeMap::new();
+        for (event, instances) in &events {
+            for instance in instances {
+                let date = instance.date.with_timezone(&timezone);
+                if date < start || date >= end {
+                    continue;
+                }
+
+                days.entry(date.date_naive()).or_default().push((
+                    date,
+                    json!({
+                        "time": date.format("%H:%M").to_string(),
+                        "summary": event.summary.as_deref().unwrap_or("Untitled event"),
+                        "event_url": self.event_url(event.calendar_id, &event.event_id),
+                    }),
+                ));
+            }
+        }
+
+        let days = days
+            .into_iter()
+            .map(|(day, mut events)| {
+                events.sort_by_key(|(date, _)| *date);
+                json!({
+                    "day": day.format("%A %-d %B").to_string(),
+                    "events": events.into_iter().map(|(_, event)| event).collect_vec(),
+                })
+            })
+            .collect_vec();
+
+        let markdown = Handlebars::new()
+            .render_template(
+                WEEKLY_SUMMARY_TEMPLATE,
+                &json!({
+                    "week_start": week_start.format("%-d %B").to_string(),
+                    "days": days,
                 }),
             )
-            .with_context(|| "Rendering body template")?;
+            .with_context(|| "Rendering weekly summary template")?;
 
-        let event_json = if let Some(desc) = &reminder.description {
-            let cleaned_html = ammonia::clean(desc);
+        Ok(markdown)
+    }
 
-            json!({
-                "msgtype": "m.text",
-                "body": markdown.replace(&description_token, desc),
-                "format": "org.matrix.custom.html",
-                "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()).replace(&description_token, &cleaned_html),
-            })
-        } else {
-            json!({
-                "msgtype": "m.text",
-                "body": markdown,
-                "format": "org.matrix.custom.html",
-                "formatted_body": markdown_to_html(&markdown, &ComrakOptions::default()),
-            })
-        };
+    /// Get the DM room we send the user notices to, creating it if we haven't
+    /// already.
+    async fn get_or_create_dm_room(&self, user_id: i64) -> Result<String, Error> {
+        if let Some(room_id) = self.database.get_dm_room_id(user_id).await? {
+            return Ok(room_id);
+        }
 
-        let url = format!(
-            "{}/_matrix/client/r0/rooms/{}/send/m.room.message",
-            self.config.matrix.homeserver_url, body.room_id
-        );
+        let matrix_id = self
+            .database
+            .get_matrix_ids(user_id)
+            .await?
+            .into_iter()
+            .next()
+            .context("User has no Matrix ID")?
+            .matrix_id;
 
-        let resp = self
-            .http_client
-            .post(&url)
-            .bearer_auth(&self.config.matrix.access_token)
-            .json(&event_json)
-            .send()
-            .await
-            .with_context(|| "Sending HTTP send message request")?;
+        let room_id = self.create_direct_room(&matrix_id).await?;
+        self.database
+            .set_weekly_summary_room_id(user_id, &room_id)
+            .await?;
 
-        Span::current().record("status", resp.status().as_u16());
+        Ok(room_id)
+    }
 
-        info!(
-            status = resp.status().as_u16(),
-            event_id = reminder.event_id.deref(),
-            room_id = body.room_id.deref(),
-            "Sent reminder"
-        );
+    /// Create a DM room with the given user, returning its room ID.
+    async fn create_direct_room(&self, matrix_id: &str) -> Result<String, Error> {
+        let credentials = self.matrix_credentials(None)?;
+
+        let resp = credentials
+            .authenticate(self.http_client.post(format!(
+                "{}/_matrix/client/r0/createRoom",
+                credentials.homeserver_url
+            )))
+            .json(&json!({
+                "preset": "trusted_private_chat",
+                "is_direct": true,
+                "invite": [matrix_id],
+            }))
+            .send()
+            .await
+            .with_context(|| "Sending HTTP /createRoom request")?;
 
         if !resp.status().is_success() {
-            bail!("Got non-2xx from /send response: {}", resp.status());
+            bail!("Got non-2xx from /createRoom response: {}", resp.status());
         }
 
-        Ok(())
+        let body: MatrixCreateRoomResponse = resp.json().await?;
+
+        info!(room_id = body.room_id.deref(), matrix_id, "Created DM room");
+
+        Ok(body.room_id)
     }
 
-    /// An infinite loop that periodically pulls email to Matrix ID mappings and
-    /// holidays from HiBob.
-    async fn hibob_loop(&self) {
-        let config = if let Some(config) = &self.config.hibob {
-            config
-        } else {
-            return;
-        };
+    /// An infinite loop that periodically refreshes the configured public
+    /// holidays.
+    async fn public_holidays_loop(&self) {
+        if self.config.public_holidays.is_empty() {
+            return future::pending().await;
+        }
 
-        interval_process("hibob_loop", Duration::minutes(5), || {
+        interval_process("public_holidays", Duration::hours(6), || {
             AssertUnwindSafe(async {
-                if let Err(error) = self.update_email_mappings(config).await {
-                    capture_anyhow(&error);
-                    error!(
-                        error = error.deref() as &dyn StdError,
-                        "Failed to update email mappings"
-                    );
-                }
-
-                if let Err(error) = self.update_holidays(config).await {
-                    capture_anyhow(&error);
-                    error!(
-                        error = error.deref() as &dyn StdError,
-                        "Failed to update holidays"
-                    );
+                for (region, config) in &self.config.public_holidays {
+                    if let Err(error) = self.update_public_holidays(region, config).await {
+                        capture_anyhow(&error);
+                        error!(
+                            error = error.deref() as &dyn StdError,
+                            region = region.deref(),
+                            "Failed to update public holidays"
+                        );
+                    }
                 }
                 Ok(())
             })
@@ -735,6 +4343,31 @@ impl App {
         .await;
     }
 
+    /// Fetch and store the public holidays for the region.
+    #[instrument(skip(self, config))]
+    pub async fn update_public_holidays(
+        &self,
+        region: &str,
+        config: &PublicHolidaysConfig,
+    ) -> Result<(), Error> {
+        let resp = self.calendar_http_client.get(&config.url).send().await?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            bail!("Got {} result fetching public holidays", status.as_u16());
+        }
+
+        let body = read_response_text(resp, self.config.fetch.max_response_bytes()).await?;
+
+        let holidays = parse_public_holidays(&body);
+
+        info!(count = holidays.len(), "Fetched public holidays");
+
+        self.database.set_public_holidays(region, &holidays).await?;
+
+        Ok(())
+    }
+
     /// An infinite loop that checks for any oauth2 tokens that need refreshing
     async fn refresh_oauth2_tokens(&self) {
         loop {
@@ -1011,12 +4644,258 @@ impl App {
             .collect();
 
         self.database
-            .add_access_token(user_id, &token, Utc::now() + Duration::days(7))
+            .add_access_token(user_id, &token, Utc::now() + self.session_lifetime())
+            .await?;
+
+        Ok(token)
+    }
+
+    /// Generate a new personal access token for the API, limited to the
+    /// given scopes. Only the hash is persisted, so the returned token can't
+    /// be retrieved again.
+    pub async fn add_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        scopes: &[String],
+    ) -> Result<(i64, String), Error> {
+        let random: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+        let token = format!("calbot_{}", random);
+
+        let api_token_id = self
+            .database
+            .add_api_token(user_id, name, &hash_api_token(&token), scopes)
+            .await?;
+
+        Ok((api_token_id, token))
+    }
+
+    /// Whether the user can use the admin pages and APIs, either because
+    /// they've been made an admin or they're listed as one in the config.
+    pub async fn is_admin(&self, user_id: i64) -> Result<bool, Error> {
+        if self.database.is_admin(user_id).await? {
+            return Ok(true);
+        }
+
+        let email = self.database.get_email(user_id).await?;
+
+        Ok(self.config.app.admins.contains(&email))
+    }
+
+    /// Gather everything we store about the user, for them to download.
+    ///
+    /// Secrets, such as calendar passwords and webhook signing keys, are left
+    /// out.
+    pub async fn export_user_data(&self, user_id: i64) -> Result<serde_json::Value, Error> {
+        let email = self.database.get_email(user_id).await?;
+        let aliases = self.database.get_user_emails(user_id).await?;
+        let matrix_ids = self.database.get_matrix_ids(user_id).await?;
+        let calendars = self.database.get_calendars_for_user(user_id).await?;
+        let reminders = self.database.get_reminders_for_user(user_id).await?;
+        let adhoc_reminders = self.database.get_adhoc_reminders_for_user(user_id).await?;
+        let rules = self.database.get_reminder_rules_for_user(user_id).await?;
+        let templates = self.database.get_templates_for_user(user_id).await?;
+        let subscriptions = self
+            .database
+            .get_event_subscriptions_for_user(user_id)
             .await?;
+        let webhooks = self.database.get_webhooks(user_id).await?;
+        let api_tokens = self.database.get_api_tokens(user_id).await?;
+        let send_history = self.database.get_reminder_log_for_user(user_id).await?;
+
+        Ok(json!({
+            "exported_at": Utc::now(),
+            "email": email,
+            "email_aliases": aliases,
+            "matrix_ids": matrix_ids,
+            "calendars": calendars,
+            "reminders": reminders,
+            "adhoc_reminders": adhoc_reminders,
+            "reminder_rules": rules,
+            "templates": templates,
+            "event_subscriptions": subscriptions,
+            "webhooks": webhooks.iter().map(|webhook| json!({
+                "webhook_id": webhook.webhook_id,
+                "url": &webhook.url,
+                "event_types": &webhook.event_types,
+                "created_at": webhook.created_at,
+            })).collect::<Vec<_>>(),
+            "api_tokens": api_tokens,
+            "send_history": send_history,
+        }))
+    }
+
+    /// Export the user's reminders, so that they can be imported again into a
+    /// different deployment.
+    pub async fn export_reminders(&self, user_id: i64) -> Result<ReminderExport, Error> {
+        let calendars: HashMap<_, _> = self
+            .database
+            .get_calendars_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|calendar| (calendar.calendar_id, calendar))
+            .collect();
+
+        let summaries: HashMap<_, _> = self
+            .database
+            .get_reminders_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|reminder| (reminder.reminder_id, reminder.summary))
+            .collect();
+
+        let reminders = self
+            .database
+            .get_reminder_settings_for_user(user_id)
+            .await?
+            .into_iter()
+            .filter_map(|reminder| {
+                // Reminders the user added to other people's calendars can't
+                // be matched up again, so we leave them out.
+                let calendar = calendars.get(&reminder.calendar_id)?;
+                let summary = summaries.get(&reminder.reminder_id).cloned().flatten();
+                Some(ExportedReminder::new(&reminder, calendar, summary))
+            })
+            .collect();
+
+        Ok(ReminderExport {
+            version: REMINDER_EXPORT_VERSION,
+            exported_at: Utc::now(),
+            reminders,
+        })
+    }
+
+    /// Import reminders from an export, adding each to the event with the
+    /// same UID in the user's calendars.
+    ///
+    /// Reminders for events the user doesn't have, or that the user already
+    /// has, are skipped. Senders, holiday regions and languages that aren't
+    /// configured here fall back to the defaults.
+    pub async fn import_reminders(
+        &self,
+        user_id: i64,
+        export: &ReminderExport,
+    ) -> Result<ImportResult, Error> {
+        if export.version != REMINDER_EXPORT_VERSION {
+            bail!("Unsupported export version {}", export.version);
+        }
+
+        let calendars = self.database.get_calendars_for_user(user_id).await?;
+
+        let mut existing: HashSet<_> = self
+            .database
+            .get_reminder_settings_for_user(user_id)
+            .await?
+            .into_iter()
+            .map(|r| (r.calendar_id, r.event_id, r.room, r.minutes_before))
+            .collect();
+
+        let mut result = ImportResult::default();
+
+        for exported in &export.reminders {
+            let candidates = self
+                .database
+                .get_calendars_with_event(user_id, &exported.event_uid)
+                .await?;
+
+            let calendar_id = match pick_calendar(exported, &candidates, &calendars) {
+                Some(calendar_id) => calendar_id,
+                None => {
+                    result.skipped.push(SkippedReminder::new(
+                        exported,
+                        "None of your calendars have this event",
+                    ));
+                    continue;
+                }
+            };
+
+            let key = (
+                calendar_id,
+                exported.event_uid.clone(),
+                exported.room.clone(),
+                exported.minutes_before,
+            );
+            if existing.contains(&key) {
+                result.skipped.push(SkippedReminder::new(
+                    exported,
+                    "You already have this reminder",
+                ));
+                continue;
+            }
+
+            let mut reminder = exported.to_reminder(user_id, calendar_id);
+
+            // Senders, holiday regions and languages depend on how this
+            // instance is configured, so we drop unknown ones rather than
+            // skipping the reminder.
+            if let Some(sender) = &reminder.sender {
+                if self.config.matrix.credentials(Some(sender)).is_none() {
+                    reminder.sender = None;
+                }
+            }
+            if let Some(region) = &reminder.holiday_region {
+                if !self.config.public_holidays.contains_key(region) {
+                    reminder.holiday_region = None;
+                }
+            }
+            if let Some(locale) = &reminder.locale {
+                if get_locale(locale).is_none() {
+                    reminder.locale = None;
+                }
+            }
+
+            if let Some(problem) = self.validate_reminder(&reminder).await? {
+                result.skipped.push(SkippedReminder::new(exported, problem));
+                continue;
+            }
+
+            let reminder_id = self.database.add_reminder(&reminder).await?;
+            result.imported.push(reminder_id);
+            existing.insert(key);
+        }
+
+        info!(
+            user_id,
+            imported = result.imported.len(),
+            skipped = result.skipped.len(),
+            "Imported reminders"
+        );
+
+        self.update_reminders().await?;
+
+        Ok(result)
+    }
+
+    /// Generate a new token for the user's ICS feed, which stops the old feed
+    /// URL from working.
+    pub async fn reset_feed_token(&self, user_id: i64) -> Result<String, Error> {
+        let token: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        self.database.set_feed_token(user_id, &token).await?;
 
         Ok(token)
     }
 
+    /// Create a token that gives access to the widget for the room, or get
+    /// the existing one if the user already has one.
+    pub async fn add_widget_token(&self, user_id: i64, room: &str) -> Result<String, Error> {
+        let token: String = rand::thread_rng()
+            .sample_iter(Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect();
+
+        self.database.add_widget_token(user_id, room, &token).await
+    }
+
     pub async fn get_google_calendars(
         &self,
         _path: &str,
@@ -1095,12 +4974,15 @@ impl App {
         calendars.sort_by_key(|c| !c.primary);
 
         for calendar in &mut calendars {
-            let url = format!(
+            // Calendars added before we used the Calendar API use the CalDAV
+            // URL.
+            let caldav_url = format!(
                 "https://apidata.googleusercontent.com/caldav/v2/{}/events",
                 encode(&calendar.id)
             );
 
-            calendar.have_added_to_calbot = urls_have_added.contains(&url);
+            calendar.have_added_to_calbot = urls_have_added.contains(&caldav_url)
+                || urls_have_added.contains(&google_events_url(&calendar.id));
         }
 
         Ok((token_id, calendars))
@@ -1207,6 +5089,145 @@ impl App {
     }
 }
 
+/// A transaction ID for sending the reminder, which is the same every time we
+/// try and send it so that retries don't post it twice.
+fn reminder_txn_id(reminder: &ReminderInstance) -> String {
+    let mut txn_id = format!(
+        "reminder-{}-{}",
+        reminder.reminder_id,
+        reminder.timestamp.timestamp()
+    );
+
+    // Snoozed reminders are deliberately sent again.
+    if let Some(snoozed_until) = reminder.snoozed_until {
+        txn_id.push_str(&format!("-snoozed-{}", snoozed_until.timestamp()));
+    }
+
+    txn_id
+}
+
+/// Filter out the due reminders that are set to be skipped if nobody is
+/// attending the event.
+fn skip_declined_events(reminders: Vec<ReminderInstance>) -> Vec<ReminderInstance> {
+    reminders
+        .into_iter()
+        .filter(|reminder| {
+            if reminder.skip_if_declined && reminder.nobody_attending() {
+                info!(
+                    event_id = reminder.event_id.deref(),
+                    "Skipping reminder as nobody is attending"
+                );
+                false
+            } else {
+                true
+            }
+        })
+        .collect()
+}
+
+/// Collapse due reminders for the same event instance that are going to the
+/// same room at the same time, e.g. because several people have set up the
+/// same reminder. The first reminder is kept, and the others are recorded
+/// against it as duplicates.
+pub fn dedup_reminders(reminders: Vec<ReminderInstance>) -> Vec<ReminderInstance> {
+    let mut deduped: Vec<ReminderInstance> = Vec::with_capacity(reminders.len());
+    let mut seen: HashMap<(String, DateTime<Utc>, String, i64), usize> = HashMap::new();
+
+    for reminder in reminders {
+        let key = (
+            reminder.event_id.clone(),
+            reminder.timestamp,
+            reminder.room.clone(),
+            reminder.minutes_before,
+        );
+
+        if let Some(&index) = seen.get(&key) {
+            info!(
+                event_id = reminder.event_id.deref(),
+                reminder_id = reminder.reminder_id,
+                kept_reminder_id = deduped[index].reminder_id,
+                "Skipping duplicate reminder"
+            );
+            deduped[index]
+                .duplicate_reminder_ids
+                .push(reminder.reminder_id);
+            continue;
+        }
+
+        seen.insert(key, deduped.len());
+        deduped.push(reminder);
+    }
+
+    deduped
+}
+
+/// The reminders that sending the given reminder counts as sending, i.e. it
+/// and any duplicates that were folded into it.
+fn credited_reminders(reminder: &ReminderInstance) -> Vec<(i64, DateTime<Utc>)> {
+    std::iter::once(reminder.reminder_id)
+        .chain(reminder.duplicate_reminder_ids.iter().copied())
+        .map(|reminder_id| (reminder_id, reminder.timestamp))
+        .collect()
+}
+
+/// Apply a reminder's manual attendee overrides to the attendees of an event,
+/// dropping anyone who has declined.
+///
+/// Overrides can be either emails or Matrix IDs. Excluded entries are matched
+/// against both the attendee's email and their mapped Matrix IDs, and extra
+/// entries are only added if they're not already attending.
+fn apply_attendee_overrides(
+    attendees: &[Attendee],
+    extra_attendees: &[String],
+    excluded_attendees: &[String],
+    email_to_matrix_id: &BTreeMap<String, Vec<String>>,
+) -> Vec<Attendee> {
+    let matches = |attendee: &Attendee, entry: &String| {
+        attendee.email == *entry
+            || email_to_matrix_id
+                .get(&attendee.email)
+                .is_some_and(|matrix_ids| matrix_ids.contains(entry))
+    };
+
+    let mut merged: Vec<Attendee> = attendees
+        .iter()
+        .filter(|attendee| !attendee.is_declined())
+        .filter(|attendee| !excluded_attendees.iter().any(|e| matches(attendee, e)))
+        .cloned()
+        .collect();
+
+    for extra in extra_attendees {
+        if excluded_attendees.contains(extra) || merged.iter().any(|a| matches(a, extra)) {
+            continue;
+        }
+
+        merged.push(Attendee {
+            email: extra.clone(),
+            common_name: None,
+            status: None,
+        });
+    }
+
+    merged
+}
+
+/// The number of whole minutes until the event starts, for live countdowns,
+/// rounding up so that we never say it has started before it has.
+pub fn countdown_minutes(start: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    let seconds_left = (start - now).num_seconds().max(0);
+
+    (seconds_left + 59) / 60
+}
+
+/// Count the event's attendees with the given participation status, e.g.
+/// `TENTATIVE`.
+fn count_attendees_with_status(attendees: &[Attendee], status: &str) -> usize {
+    attendees
+        .iter()
+        .filter(|attendee| attendee.status.as_deref() == Some(status))
+        .count()
+}
+
 /// Checks if the string is likely a valid user ID.
 ///
 /// Doesn't bother to fully check the domain part is valid
@@ -1248,6 +5269,159 @@ pub(crate) fn is_likely_a_valid_user_id(user_id: &str) -> bool {
     true
 }
 
+/// How long to wait before the next attempt at sending a reminder that has
+/// failed to send the given number of times.
+pub fn reminder_retry_backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 16) - 1;
+    Duration::seconds(REMINDER_RETRY_INITIAL_BACKOFF_SECONDS << exponent)
+}
+
+/// Compare the events we had stored for a calendar with the freshly synced
+/// ones, returning which events' next instance has moved or whose location
+/// has changed, by event ID.
+///
+/// Only the next upcoming instance of each event is compared, so that
+/// recurring events don't look like they've moved as the sync window
+/// advances. If the next instance has been cancelled rather than moved the
+/// following one will already be known, and so isn't reported as a move.
+pub fn detect_event_changes(
+    previous_events: &[(Event, Vec<EventInstance>)],
+    events: &[Event],
+    next_dates: &[EventInstance],
+    now: DateTime<Utc>,
+) -> BTreeMap<String, EventChange> {
+    let events_by_id: HashMap<_, _> = events
+        .iter()
+        .map(|event| (event.event_id.as_str(), event))
+        .collect();
+
+    let mut new_dates: HashMap<&str, BTreeSet<DateTime<Utc>>> = HashMap::new();
+    for instance in next_dates {
+        let date = instance.date.with_timezone(&Utc);
+        if date > now {
+            new_dates
+                .entry(instance.event_id.as_str())
+                .or_default()
+                .insert(date);
+        }
+    }
+
+    let mut changes = BTreeMap::new();
+    for (previous_event, previous_instances) in previous_events {
+        let event = if let Some(event) = events_by_id.get(previous_event.event_id.as_str()) {
+            event
+        } else {
+            continue;
+        };
+
+        let old_dates: BTreeSet<_> = previous_instances
+            .iter()
+            .map(|instance| instance.date.with_timezone(&Utc))
+            .filter(|date| *date > now)
+            .collect();
+        let new_dates = new_dates
+            .get(previous_event.event_id.as_str())
+            .cloned()
+            .unwrap_or_default();
+
+        let moved = match (old_dates.iter().next(), new_dates.iter().next()) {
+            (Some(old), Some(new)) if !new_dates.contains(old) && !old_dates.contains(new) => {
+                Some((*old, *new))
+            }
+            _ => None,
+        };
+
+        let location = if previous_event.location != event.location {
+            Some(event.location.clone())
+        } else {
+            None
+        };
+
+        if moved.is_some() || location.is_some() {
+            changes.insert(
+                previous_event.event_id.clone(),
+                EventChange { moved, location },
+            );
+        }
+    }
+
+    changes
+}
+
+/// Render the notice telling a room that an event has moved or changed
+/// location, with times shown in the given timezone.
+pub fn render_event_change_notice(
+    summary: Option<&str>,
+    change: &EventChange,
+    timezone: Tz,
+) -> Result<String, Error> {
+    let times = change.moved.map(|(old, new)| {
+        let old = old.with_timezone(&timezone);
+        let new = new.with_timezone(&timezone);
+
+        // We only need to mention the day if it's changed.
+        if old.date_naive() == new.date_naive() {
+            (
+                old.format("%H:%M").to_string(),
+                new.format("%H:%M").to_string(),
+            )
+        } else {
+            (
+                old.format("%a %-d %b %H:%M").to_string(),
+                new.format("%a %-d %b %H:%M").to_string(),
+            )
+        }
+    });
+
+    let markdown = Handlebars::new()
+        .render_template(
+            EVENT_CHANGED_TEMPLATE,
+            &json!({
+                "summary": summary.unwrap_or("Meeting"),
+                "old_time": times.as_ref().map(|(old, _)| old),
+                "new_time": times.as_ref().map(|(_, new)| new),
+                "location_changed": change.location.is_some(),
+                "location": change.location.clone().flatten(),
+            }),
+        )
+        .with_context(|| "Rendering event change template")?;
+
+    Ok(markdown)
+}
+
+/// Render the notice telling a subscribed room about new events, given their
+/// summaries and when they next start, e.g. "New event: **Planning** on
+/// Friday 19 January 14:00".
+pub fn render_new_events_notice(
+    events: &[(Option<String>, DateTime<Utc>)],
+    timezone: Tz,
+) -> String {
+    let mut events = events
+        .iter()
+        .map(|(summary, date)| {
+            (
+                date,
+                format!(
+                    "**{}** on {}",
+                    summary.as_deref().unwrap_or("Meeting"),
+                    date.with_timezone(&timezone).format("%A %-d %B %H:%M"),
+                ),
+            )
+        })
+        .collect_vec();
+    events.sort();
+
+    if let [(_, event)] = &events[..] {
+        format!("New event: {}", event)
+    } else {
+        let list = events
+            .iter()
+            .map(|(_, event)| format!("- {}", event))
+            .join("\n");
+        format!("New events:\n\n{}", list)
+    }
+}
+
 async fn interval_process<F, Fut>(name: &str, duration: Duration, func: F)
 where
     F: Fn() -> Fut,
@@ -1,12 +1,75 @@
 use std::{fmt::Display, ops::Deref, pin::Pin};
 
 use actix_web::{
-    error::ErrorInternalServerError, web::Data, Error, FromRequest, HttpResponse, ResponseError,
+    cookie::{Cookie, SameSite},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    error::{ErrorForbidden, ErrorInternalServerError, ErrorTooManyRequests, ErrorUnauthorized},
+    http::header::AUTHORIZATION,
+    web::Data,
+    Error, FromRequest, HttpResponse, ResponseError,
 };
+use chrono::{Duration, Utc};
 use futures::{Future, FutureExt};
+use sha2::{Digest, Sha256};
 
 use crate::app::App;
 
+/// The name of the cookie that holds the user's access token.
+const SESSION_COOKIE: &str = "token";
+
+/// Build the cookie holding the user's access token.
+pub fn session_cookie(app: &App, token: String) -> Cookie<'static> {
+    Cookie::build(SESSION_COOKIE, token)
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::seconds(
+            app.session_lifetime().num_seconds(),
+        ))
+        .http_only(true)
+        .finish()
+}
+
+/// Added to the request extensions when the expiry of the session has been
+/// pushed back, so that [`refresh_session_cookie`] can do the same for the
+/// cookie.
+#[derive(Debug, Clone)]
+struct RefreshedSession(String);
+
+/// Middleware that updates the max age of the session cookie when the
+/// session has been refreshed.
+pub fn refresh_session_cookie<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+{
+    let fut = srv.call(req);
+
+    async move {
+        let mut res = fut.await?;
+
+        let refreshed = res
+            .request()
+            .extensions()
+            .get::<RefreshedSession>()
+            .cloned();
+
+        if let Some(RefreshedSession(token)) = refreshed {
+            let app = res
+                .request()
+                .app_data::<Data<App>>()
+                .expect("no app")
+                .clone();
+
+            res.response_mut()
+                .add_cookie(&session_cookie(&app, token))
+                .map_err(ErrorInternalServerError)?;
+        }
+
+        Ok(res)
+    }
+}
+
 /// Extractor that gets the authenticated user.
 #[derive(Debug, Clone, Copy)]
 pub struct AuthedUser(pub i64);
@@ -32,17 +95,30 @@ impl FromRequest for AuthedUser {
         let req = req.clone();
 
         async move {
-            let cookie = req.cookie("token").ok_or(NotAuthedError)?;
+            let cookie = req.cookie(SESSION_COOKIE).ok_or(NotAuthedError)?;
 
             let token = cookie.value();
 
-            let user_id_opt = app
+            let (user_id, expiry) = app
                 .database
                 .get_user_from_token(token)
                 .await
-                .map_err(ErrorInternalServerError)?;
+                .map_err(ErrorInternalServerError)?
+                .ok_or(NotAuthedError)?;
+
+            // Sessions expire after a period of inactivity, so we push back
+            // the expiry when they're used. To avoid writing to the DB on
+            // every request we only do so once the expiry is an hour stale.
+            let new_expiry = Utc::now() + app.session_lifetime();
+            if expiry < new_expiry - Duration::hours(1) {
+                app.database
+                    .refresh_access_token(token, new_expiry)
+                    .await
+                    .map_err(ErrorInternalServerError)?;
 
-            let user_id = user_id_opt.ok_or(NotAuthedError)?;
+                req.extensions_mut()
+                    .insert(RefreshedSession(token.to_string()));
+            }
 
             Ok(AuthedUser(user_id))
         }
@@ -50,6 +126,151 @@ impl FromRequest for AuthedUser {
     }
 }
 
+/// The scopes that personal access tokens can be limited to.
+pub const API_TOKEN_SCOPES: &[&str] = &[
+    "calendars:read",
+    "calendars:write",
+    "reminders:read",
+    "reminders:write",
+    "rooms:read",
+    "rooms:write",
+    "webhooks:read",
+    "webhooks:write",
+    "admin",
+];
+
+/// Hash a personal access token for storing in the DB.
+///
+/// The tokens are random, so there's no need for a slow or salted hash.
+pub fn hash_api_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Extractor for API endpoints, that authenticates the user either with a
+/// personal access token in the `Authorization: Bearer` header or with the
+/// login cookie.
+#[derive(Debug, Clone)]
+pub struct ApiUser {
+    user: AuthedUser,
+    /// The scopes of the personal access token, or `None` if logged in with
+    /// the cookie (which can do anything).
+    scopes: Option<Vec<String>>,
+}
+
+impl ApiUser {
+    /// Check that the user is allowed to make requests needing the scope.
+    pub fn require(&self, scope: &str) -> Result<AuthedUser, Error> {
+        match &self.scopes {
+            Some(scopes) if !scopes.iter().any(|s| s == scope) => Err(ErrorForbidden(format!(
+                "Token is missing the {} scope",
+                scope
+            ))),
+            _ => Ok(self.user),
+        }
+    }
+}
+
+impl FromRequest for ApiUser {
+    type Error = Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let header = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned);
+
+        let header = match header {
+            Some(header) => header,
+            None => {
+                return AuthedUser::from_request(req, payload)
+                    .map(|res| res.map(|user| ApiUser { user, scopes: None }))
+                    .boxed_local();
+            }
+        };
+
+        let app = req.app_data::<Data<App>>().expect("no app").deref().clone();
+        let rate_limit_key = format!("api_token:ip:{}", app.rate_limiter.client_ip(req));
+
+        async move {
+            let token = header
+                .strip_prefix("Bearer ")
+                .ok_or_else(|| ErrorUnauthorized("Invalid Authorization header"))?;
+
+            if app
+                .rate_limiter
+                .is_limited(&app.database, &rate_limit_key)
+                .await
+                .map_err(ErrorInternalServerError)?
+            {
+                return Err(ErrorTooManyRequests("Too many invalid access tokens"));
+            }
+
+            let result = app
+                .database
+                .get_user_from_api_token(&hash_api_token(token.trim()))
+                .await
+                .map_err(ErrorInternalServerError)?;
+
+            let (user_id, scopes) = if let Some(result) = result {
+                result
+            } else {
+                app.rate_limiter
+                    .record_failure(&app.database, &rate_limit_key)
+                    .await
+                    .map_err(ErrorInternalServerError)?;
+
+                return Err(ErrorUnauthorized("Invalid access token"));
+            };
+
+            Ok(ApiUser {
+                user: AuthedUser(user_id),
+                scopes: Some(scopes),
+            })
+        }
+        .boxed_local()
+    }
+}
+
+/// Extractor for admin-only pages and APIs. Authenticates the same way as
+/// [`ApiUser`], with tokens needing the `admin` scope.
+#[derive(Debug, Clone, Copy)]
+pub struct AdminUser(pub AuthedUser);
+
+impl FromRequest for AdminUser {
+    type Error = Error;
+
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(
+        req: &actix_web::HttpRequest,
+        payload: &mut actix_web::dev::Payload,
+    ) -> Self::Future {
+        let app = req.app_data::<Data<App>>().expect("no app").deref().clone();
+        let api_user = ApiUser::from_request(req, payload);
+
+        async move {
+            let user = api_user.await?.require("admin")?;
+
+            if !app
+                .is_admin(user.0)
+                .await
+                .map_err(ErrorInternalServerError)?
+            {
+                return Err(ErrorForbidden("Not an admin"));
+            }
+
+            Ok(AdminUser(user))
+        }
+        .boxed_local()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct NotAuthedError;
 
@@ -1,20 +1,432 @@
 //! Helper functions for parsing and dealing with ICS calendars.
 
-use std::{convert::TryInto, ops::Deref, str::FromStr};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    ops::Deref,
+    str::FromStr,
+};
 
 use anyhow::{anyhow, bail, Context, Error};
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use ics_parser::{
     components::{VCalendar, VEvent},
     parser,
-    property::PropertyValue,
+    property::{EndCondition, PropertyValue},
+};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{
+    header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE},
+    Certificate, Client, Method, StatusCode,
 };
-use reqwest::Method;
 use sentry::integrations::anyhow::capture_anyhow;
+use serde::Serialize;
 use tracing::{error, info, instrument, Span};
 use url::Url;
 
-use crate::database::{Attendee, CalendarAuthentication, Event, EventInstance};
+use crate::config::{FetchConfig, TlsConfig};
+use crate::database::{
+    Attendee, CalendarAuthentication, CalendarError, CalendarKind, Event, EventInstance, FeedEvent,
+    ReminderInstance,
+};
+use crate::digest::DigestChallenge;
+use crate::timezones::normalize_tzids;
+
+/// The maximum number of parse errors we keep per calendar.
+const MAX_CALENDAR_ERRORS: usize = 50;
+
+/// Hosts of video conferencing services, along with the path prefix of their
+/// meeting links.
+const CONFERENCE_HOSTS: &[(&str, &str)] = &[
+    ("meet.google.com", "/"),
+    ("zoom.us", "/j/"),
+    ("meet.jit.si", "/"),
+    ("teams.microsoft.com", "/l/meetup-join/"),
+    ("teams.live.com", "/meet/"),
+];
+
+/// Find the first video conference link (Google Meet, Zoom, Jitsi or Teams) in
+/// the text.
+pub fn find_conference_url(text: &str) -> Option<String> {
+    text.match_indices("https://").find_map(|(start, _)| {
+        let candidate = &text[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '<' | '>' | '"' | '\'' | ')' | ']'))
+            .unwrap_or(candidate.len());
+
+        let url = Url::parse(&candidate[..end]).ok()?;
+        let host = url.host_str()?;
+
+        let is_conference = CONFERENCE_HOSTS.iter().any(|(conference_host, prefix)| {
+            (host == *conference_host || host.ends_with(&format!(".{}", conference_host)))
+                && url.path().starts_with(prefix)
+                && url.path().len() > prefix.len()
+        });
+
+        is_conference.then(|| url.to_string())
+    })
+}
+
+/// A property of an ICS component, e.g.
+/// `DTSTART;TZID=Europe/London:20240101T100000`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IcsProperty<'a> {
+    /// The whole (unfolded) content line.
+    pub line: &'a str,
+    pub name: &'a str,
+    pub value: &'a str,
+}
+
+impl<'a> IcsProperty<'a> {
+    /// Split a content line into its name and value, returning `None` if it
+    /// doesn't have a value.
+    fn parse(line: &'a str) -> Option<Self> {
+        // The parameters end at the first colon that isn't in a quoted string.
+        let mut in_quotes = false;
+        let value_start = line.char_indices().find_map(|(idx, c)| match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                None
+            }
+            ':' if !in_quotes => Some(idx),
+            _ => None,
+        })?;
+
+        let name_end = line[..value_start].find(';').unwrap_or(value_start);
+
+        Some(IcsProperty {
+            line,
+            name: &line[..name_end],
+            value: line[value_start + 1..].trim(),
+        })
+    }
+}
+
+/// The properties of an ICS component, excluding those of any components
+/// nested in it (e.g. the `VALARM`s of a `VEVENT`).
+pub(crate) struct IcsComponent<'a> {
+    pub properties: Vec<IcsProperty<'a>>,
+}
+
+impl<'a> IcsComponent<'a> {
+    /// Get the first property with the given name.
+    pub fn get(&self, name: &str) -> Option<&IcsProperty<'a>> {
+        self.properties
+            .iter()
+            .find(|prop| prop.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Get all the properties with the given name.
+    pub fn get_all<'b>(&'b self, name: &'b str) -> impl Iterator<Item = &'b IcsProperty<'a>> {
+        self.properties
+            .iter()
+            .filter(move |prop| prop.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Get the value of the first property with the given name.
+    pub fn value(&self, name: &str) -> Option<&'a str> {
+        self.get(name).map(|prop| prop.value)
+    }
+
+    /// Whether the first property with the given name has the given value.
+    fn has_value(&self, name: &str, value: &str) -> bool {
+        self.value(name)
+            .is_some_and(|found| found.eq_ignore_ascii_case(value))
+    }
+
+    /// Whether this is an override of a single occurrence of a recurring
+    /// event.
+    fn is_override(&self) -> bool {
+        self.get("RECURRENCE-ID").is_some()
+    }
+}
+
+/// Unfold the long lines of an ICS body, which are split by a line break
+/// followed by a space or tab.
+pub(crate) fn unfold_ics(cal_body: &str) -> String {
+    cal_body
+        .replace("\r\n ", "")
+        .replace("\r\n\t", "")
+        .replace("\n ", "")
+        .replace("\n\t", "")
+}
+
+/// Get the components with the given name (e.g. `VEVENT`) from an unfolded
+/// ICS body, for when we need properties that the parser doesn't give us.
+pub(crate) fn ics_components<'a>(unfolded: &'a str, name: &str) -> Vec<IcsComponent<'a>> {
+    let mut components = Vec::new();
+
+    let mut current: Option<IcsComponent> = None;
+    // How deeply nested we are within the current component.
+    let mut depth = 0;
+
+    for line in unfolded.lines() {
+        let line = line.trim_end();
+
+        if let Some(begin) = line.strip_prefix("BEGIN:") {
+            if current.is_some() {
+                depth += 1;
+            } else if begin.trim().eq_ignore_ascii_case(name) {
+                current = Some(IcsComponent {
+                    properties: Vec::new(),
+                });
+                depth = 0;
+            }
+        } else if line.starts_with("END:") {
+            if depth > 0 {
+                depth -= 1;
+            } else {
+                components.extend(current.take());
+            }
+        } else if depth == 0 {
+            if let (Some(component), Some(prop)) = (&mut current, IcsProperty::parse(line)) {
+                component.properties.push(prop);
+            }
+        }
+    }
+
+    components
+}
+
+/// Find the conference links Google adds to events in the
+/// `X-GOOGLE-CONFERENCE` property, by UID.
+fn find_google_conference_urls(cal_body: &str) -> Vec<(String, String)> {
+    let unfolded = unfold_ics(cal_body);
+
+    ics_components(&unfolded, "VEVENT")
+        .iter()
+        .filter_map(|event| {
+            Some((
+                event.value("UID")?.to_string(),
+                event.value("X-GOOGLE-CONFERENCE")?.to_string(),
+            ))
+        })
+        .collect()
+}
+
+/// A parsed event LOCATION that refers to somewhere physical.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventLocation {
+    /// A `geo:` URI.
+    Geo { latitude: f64, longitude: f64 },
+    /// Something that looks like a street address.
+    Address(String),
+}
+
+impl EventLocation {
+    /// A link to the location on a map.
+    pub fn map_url(&self) -> String {
+        match self {
+            EventLocation::Geo {
+                latitude,
+                longitude,
+            } => format!(
+                "https://www.openstreetmap.org/?mlat={lat}&mlon={lon}#map=17/{lat}/{lon}",
+                lat = latitude,
+                lon = longitude,
+            ),
+            EventLocation::Address(address) => format!(
+                "https://www.openstreetmap.org/search?query={}",
+                urlencoding::encode(address)
+            ),
+        }
+    }
+}
+
+/// Parse an event's LOCATION, returning None if it doesn't look like a
+/// physical location (e.g. it's a video call link or a meeting room name).
+pub fn parse_location(location: &str) -> Option<EventLocation> {
+    let location = location.trim();
+
+    if let Some(geo) = location.strip_prefix("geo:") {
+        // geo URIs look like `geo:lat,lon[,alt][;params]`
+        let coords = geo.split(';').next()?;
+        let mut parts = coords.split(',').map(|part| part.trim().parse::<f64>());
+
+        let latitude = parts.next()?.ok()?;
+        let longitude = parts.next()?.ok()?;
+
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+
+        return Some(EventLocation::Geo {
+            latitude,
+            longitude,
+        });
+    }
+
+    if location.contains("://") {
+        return None;
+    }
+
+    // Addresses generally have multiple comma separated parts, and a house
+    // number or post code.
+    if location.contains(',') && location.chars().any(|c| c.is_ascii_digit()) {
+        return Some(EventLocation::Address(location.to_string()));
+    }
+
+    None
+}
+
+/// An event instance to serialize as a VEVENT.
+struct IcsEvent<'a> {
+    event_id: &'a str,
+    start: DateTime<Utc>,
+    end: Option<DateTime<Utc>>,
+    summary: Option<&'a str>,
+    location: Option<&'a str>,
+    description: Option<&'a str>,
+    url: Option<&'a str>,
+    comments: Vec<String>,
+}
+
+/// Format a UTC time as an ICS DATE-TIME value.
+fn format_ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Serialize the events as an ICS calendar, with the given extra calendar
+/// properties (e.g. its name).
+fn events_to_ics(properties: &[String], events: &[IcsEvent]) -> String {
+    let now = format_ics_time(Utc::now());
+
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//calendar_bot//EN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+    ];
+    lines.extend(properties.iter().cloned());
+
+    for event in events {
+        lines.extend([
+            "BEGIN:VEVENT".to_string(),
+            // Each instance gets its own UID so that it doesn't clash with the
+            // original (possibly recurring) event.
+            format!(
+                "UID:{}",
+                escape_ics_text(&format!(
+                    "{}-{}",
+                    event.event_id,
+                    format_ics_time(event.start)
+                ))
+            ),
+            format!("DTSTAMP:{}", now),
+            format!("DTSTART:{}", format_ics_time(event.start)),
+        ]);
+
+        if let Some(end) = event.end {
+            lines.push(format!("DTEND:{}", format_ics_time(end)));
+        }
+        if let Some(summary) = event.summary {
+            lines.push(format!("SUMMARY:{}", escape_ics_text(summary)));
+        }
+        if let Some(location) = event.location {
+            lines.push(format!("LOCATION:{}", escape_ics_text(location)));
+        }
+        if let Some(description) = event.description {
+            lines.push(format!("DESCRIPTION:{}", escape_ics_text(description)));
+        }
+        if let Some(url) = event.url {
+            lines.push(format!("URL:{}", url));
+        }
+        for comment in &event.comments {
+            lines.push(format!("COMMENT:{}", escape_ics_text(comment)));
+        }
+
+        lines.push("END:VEVENT".to_string());
+    }
+
+    lines.push("END:VCALENDAR".to_string());
+
+    lines
+        .iter()
+        .map(String::as_str)
+        .map(fold_ics_line)
+        .collect()
+}
+
+/// Serialize the event instance of a reminder as a standalone ICS calendar,
+/// so that people can add it to their own calendars.
+pub fn reminder_to_ics(reminder: &ReminderInstance) -> String {
+    let event = IcsEvent {
+        event_id: &reminder.event_id,
+        start: reminder.timestamp,
+        end: None,
+        summary: reminder.summary.as_deref(),
+        location: reminder.location.as_deref(),
+        description: reminder.description.as_deref(),
+        url: reminder.conference_url.as_deref(),
+        comments: Vec::new(),
+    };
+
+    events_to_ics(&[], &[event])
+}
+
+/// Serialize the events a user has reminders for as an ICS calendar, for
+/// subscribing to in their own calendar client.
+///
+/// Each event notes the reminders that will be sent for it.
+pub fn feed_to_ics(events: &[FeedEvent]) -> String {
+    let events = events
+        .iter()
+        .map(|event| IcsEvent {
+            event_id: &event.event_id,
+            start: event.timestamp,
+            end: event
+                .duration_minutes
+                .map(|minutes| event.timestamp + Duration::minutes(minutes)),
+            summary: event.summary.as_deref(),
+            location: event.location.as_deref(),
+            description: event.description.as_deref(),
+            url: event.conference_url.as_deref(),
+            comments: event
+                .reminders
+                .iter()
+                .map(|(minutes_before, room)| {
+                    format!("Reminder {} minutes before in {}", minutes_before, room)
+                })
+                .collect(),
+        })
+        .collect::<Vec<_>>();
+
+    events_to_ics(
+        &[
+            "X-WR-CALNAME:Calendar Bot reminders".to_string(),
+            "X-PUBLISHED-TTL:PT1H".to_string(),
+        ],
+        &events,
+    )
+}
+
+/// Escape a TEXT value as per RFC 5545.
+fn escape_ics_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace('\n', "\\n")
+}
+
+/// Fold a content line so that no line is longer than 75 octets, terminating
+/// each with CRLF.
+fn fold_ics_line(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut line_len = 0;
+    for c in line.chars() {
+        if line_len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            line_len = 1;
+        }
+        folded.push(c);
+        line_len += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+
+    folded
+}
 
 /// Parse a ICS encoded calendar.
 fn decode_calendar(cal_body: &str) -> Result<Vec<VCalendar>, Error> {
@@ -27,36 +439,551 @@ fn decode_calendar(cal_body: &str) -> Result<Vec<VCalendar>, Error> {
         .collect()
 }
 
-/// Fetch a calendar from a CalDAV URL and parse the returned set of calendars.
+/// Best effort extraction of the UID of an ICS encoded event, for use in error
+/// messages when we fail to parse it.
+fn find_uid(cal_body: &str) -> Option<String> {
+    cal_body
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("UID:"))
+        .map(|uid| uid.trim().to_string())
+}
+
+/// Find the `EXDATE` and `RDATE` properties of recurring events, by UID.
 ///
-/// Note that CalDAV returns a calendar per event, rather than one calendar with
-/// many events.
+/// Overrides that cancel a single occurrence are treated as an `EXDATE` of
+/// that occurrence. Dates (rather than date-times) and periods are ignored, as
+/// we skip all day events anyway.
+fn find_recurrence_dates(cal_body: &str) -> Vec<(String, RecurrenceDates)> {
+    let unfolded = unfold_ics(cal_body);
+
+    let mut found = Vec::new();
+
+    for event in ics_components(&unfolded, "VEVENT") {
+        let uid = if let Some(uid) = event.value("UID") {
+            uid.to_string()
+        } else {
+            continue;
+        };
+
+        // Overrides of a single occurrence share the UID, but don't carry the
+        // recurrence set.
+        if let Some(recurrence_id) = event.get("RECURRENCE-ID") {
+            if event.has_value("STATUS", "CANCELLED") {
+                let cancelled = RecurrenceDates {
+                    excluded: parse_recurrence_dates(recurrence_id.line),
+                    added: Vec::new(),
+                };
+                found.push((uid, cancelled));
+            }
+            continue;
+        }
+
+        let dates = RecurrenceDates {
+            excluded: event
+                .get_all("EXDATE")
+                .flat_map(|prop| parse_recurrence_dates(prop.line))
+                .collect(),
+            added: event
+                .get_all("RDATE")
+                .flat_map(|prop| parse_recurrence_dates(prop.line))
+                .collect(),
+        };
+
+        if !dates.excluded.is_empty() || !dates.added.is_empty() {
+            found.push((uid, dates));
+        }
+    }
+
+    found
+}
+
+/// Parse the date-times of an `EXDATE`, `RDATE` or `RECURRENCE-ID` line, e.g.
+/// `EXDATE;TZID=Europe/London:20240101T100000,20240108T100000`.
+fn parse_recurrence_dates(line: &str) -> Vec<RecurrenceDate> {
+    let (name_and_params, values) = if let Some(split) = line.split_once(':') {
+        split
+    } else {
+        return Vec::new();
+    };
+
+    let mut timezone = None;
+    for param in name_and_params.split(';').skip(1) {
+        match param.split_once('=') {
+            Some(("TZID", tzid)) => {
+                if let Ok(tz) = tzid.trim_matches('"').parse::<Tz>() {
+                    timezone = Some(tz);
+                } else {
+                    // We don't know the timezone, so can't tell when these
+                    // dates are.
+                    return Vec::new();
+                }
+            }
+            Some(("VALUE", "DATE" | "PERIOD")) => return Vec::new(),
+            _ => {}
+        }
+    }
+
+    values
+        .split(',')
+        .filter_map(|value| {
+            let value = value.trim();
+            if let Some(utc) = value.strip_suffix('Z') {
+                let date = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").ok()?;
+                Some(RecurrenceDate {
+                    date,
+                    timezone: Some(Tz::UTC),
+                })
+            } else {
+                let date = NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S").ok()?;
+                Some(RecurrenceDate { date, timezone })
+            }
+        })
+        .collect()
+}
+
+/// Find how long events last in minutes, by UID, from either their `DTEND` or
+/// `DURATION`. Overrides of a single occurrence are ignored.
+fn find_event_durations(cal_body: &str) -> Vec<(String, i64)> {
+    let unfolded = unfold_ics(cal_body);
+
+    let resolve = |prop: &IcsProperty| {
+        parse_recurrence_dates(prop.line)
+            .first()
+            .and_then(|date| date.resolve(None))
+    };
+
+    ics_components(&unfolded, "VEVENT")
+        .iter()
+        .filter(|event| !event.is_override())
+        .filter_map(|event| {
+            let uid = event.value("UID")?;
+
+            let duration = event
+                .value("DURATION")
+                .and_then(parse_ics_duration)
+                .or_else(|| {
+                    let start = resolve(event.get("DTSTART")?)?;
+                    let end = resolve(event.get("DTEND")?)?;
+                    Some(end - start)
+                })?;
+
+            Some((uid.to_string(), duration.num_minutes()))
+        })
+        .collect()
+}
+
+/// Parse an RFC 5545 `DURATION` value, e.g. `PT1H30M` or `P1W`.
+pub fn parse_ics_duration(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let value = value.strip_prefix('P')?;
+
+    let mut duration = Duration::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+
+    for c in value.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() => in_time = true,
+            _ => {
+                let n: i64 = number.parse().ok()?;
+                number.clear();
+
+                duration = duration
+                    + match (c, in_time) {
+                        ('W', false) => Duration::weeks(n),
+                        ('D', false) => Duration::days(n),
+                        ('H', true) => Duration::hours(n),
+                        ('M', true) => Duration::minutes(n),
+                        ('S', true) => Duration::seconds(n),
+                        _ => return None,
+                    };
+            }
+        }
+    }
+
+    if !number.is_empty() {
+        return None;
+    }
+
+    Some(if negative { -duration } else { duration })
+}
+
+/// Find the UIDs of any events that have been cancelled, i.e. have
+/// `STATUS:CANCELLED` set on the event itself rather than on an override of a
+/// particular occurrence.
+fn find_cancelled_uids(cal_body: &str) -> Vec<String> {
+    find_uids_with_value(cal_body, "STATUS", "CANCELLED")
+}
+
+/// Find the UIDs of any events that are marked as `TRANSP:TRANSPARENT`, i.e.
+/// that don't block time on a calendar.
+fn find_transparent_uids(cal_body: &str) -> Vec<String> {
+    find_uids_with_value(cal_body, "TRANSP", "TRANSPARENT")
+}
+
+/// Find the UIDs of events (ignoring overrides of single occurrences) where
+/// the given property has the given value.
+fn find_uids_with_value(cal_body: &str, name: &str, value: &str) -> Vec<String> {
+    let unfolded = unfold_ics(cal_body);
+
+    ics_components(&unfolded, "VEVENT")
+        .iter()
+        .filter(|event| !event.is_override() && event.has_value(name, value))
+        .filter_map(|event| event.value("UID"))
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+/// The calendars returned by a CalDAV server or .ics feed.
+#[derive(Default)]
+pub struct FetchedCalendars {
+    pub calendars: Vec<VCalendar>,
+    /// Errors for events that we failed to parse.
+    pub errors: Vec<CalendarError>,
+    /// The UIDs of events that have been cancelled.
+    pub cancelled: HashSet<String>,
+    /// The UIDs of events that are marked as free (`TRANSP:TRANSPARENT`).
+    pub transparent: HashSet<String>,
+    /// Conference links from the `X-GOOGLE-CONFERENCE` property, by UID.
+    pub conference_urls: HashMap<String, String>,
+    /// The `EXDATE`s and `RDATE`s of recurring events, by UID.
+    pub recurrence_dates: HashMap<String, RecurrenceDates>,
+    /// How long events last in minutes, by UID.
+    pub durations: HashMap<String, i64>,
+}
+
+/// The occurrences of a recurring event that have been excluded (`EXDATE`)
+/// or added (`RDATE`), on top of those generated by its `RRULE`.
+#[derive(Debug, Clone, Default)]
+pub struct RecurrenceDates {
+    pub excluded: Vec<RecurrenceDate>,
+    pub added: Vec<RecurrenceDate>,
+}
+
+/// A date-time from an `EXDATE` or `RDATE` property.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecurrenceDate {
+    pub date: NaiveDateTime,
+    /// The timezone of the date, or `None` if it's floating.
+    pub timezone: Option<Tz>,
+}
+
+impl RecurrenceDate {
+    /// Resolve the date, using the given timezone if it's floating (or UTC
+    /// if that isn't set either).
+    fn resolve(&self, floating_timezone: Option<Tz>) -> Option<DateTime<FixedOffset>> {
+        let timezone = self.timezone.or(floating_timezone).unwrap_or(Tz::UTC);
+
+        let resolved = timezone.from_local_datetime(&self.date).earliest()?;
+
+        Some(resolved.fixed_offset())
+    }
+}
+
+/// How many days ahead we store event instances for, by default.
+pub const DEFAULT_SYNC_LOOKAHEAD_DAYS: i64 = 30;
+
+/// How many days back we fetch CalDAV events from, by default.
+pub const DEFAULT_SYNC_LOOKBACK_DAYS: i64 = 180;
+
+/// The period of time we sync a calendar's events over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncWindow {
+    /// How far back to fetch events from CalDAV servers. This is well before
+    /// `from` to try and mitigate a bug where the returned calendar doesn't
+    /// include a base event for a recurring override.
+    pub fetch_from: DateTime<Utc>,
+    /// The start of the period we store event instances for.
+    pub from: DateTime<Utc>,
+    /// The end of the period we store event instances for.
+    pub until: DateTime<Utc>,
+}
+
+impl SyncWindow {
+    /// Create a window around `now`, looking back and ahead the given number
+    /// of days.
+    pub fn new(now: DateTime<Utc>, lookback_days: i64, lookahead_days: i64) -> SyncWindow {
+        SyncWindow {
+            fetch_from: now - Duration::days(lookback_days),
+            // We keep the last week of instances, so that recent reminders
+            // can still be edited.
+            from: now - Duration::days(7),
+            until: now + Duration::days(lookahead_days),
+        }
+    }
+}
+
+impl Default for SyncWindow {
+    fn default() -> SyncWindow {
+        SyncWindow::new(
+            Utc::now(),
+            DEFAULT_SYNC_LOOKBACK_DAYS,
+            DEFAULT_SYNC_LOOKAHEAD_DAYS,
+        )
+    }
+}
+
+/// Fetch a calendar and parse the returned set of calendars.
 #[instrument(skip(client), fields(status))]
 pub async fn fetch_calendars(
     client: &reqwest::Client,
     url: &str,
     authentication: &CalendarAuthentication,
-) -> Result<Vec<VCalendar>, Error> {
-    let mut req = client
-        .request(Method::from_str("REPORT").expect("method"), url)
-        .header("Content-Type", "application/xml");
+    kind: CalendarKind,
+    window: &SyncWindow,
+    default_timezone: Option<Tz>,
+    max_response_bytes: usize,
+) -> Result<FetchedCalendars, Error> {
+    match kind {
+        CalendarKind::CalDav => {
+            fetch_caldav_calendars(
+                client,
+                url,
+                authentication,
+                window,
+                default_timezone,
+                max_response_bytes,
+            )
+            .await
+        }
+        CalendarKind::Ics => {
+            fetch_ics_calendar(
+                client,
+                url,
+                authentication,
+                default_timezone,
+                max_response_bytes,
+            )
+            .await
+        }
+        CalendarKind::Static | CalendarKind::Ews | CalendarKind::Google => {
+            bail!("{:?} calendars aren't fetched as ICS", kind)
+        }
+    }
+}
 
-    match authentication {
-        CalendarAuthentication::None => {}
+/// Parse the certificates in a PEM bundle.
+pub fn parse_pem_certificates(pem: &str) -> Result<Vec<Certificate>, Error> {
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut certificates = Vec::new();
+    for block in pem.split_inclusive(END) {
+        if !block.contains(END) {
+            continue;
+        }
+
+        certificates.push(Certificate::from_pem(block.trim().as_bytes())?);
+    }
+
+    if certificates.is_empty() {
+        bail!("No certificates found");
+    }
+
+    Ok(certificates)
+}
+
+/// Build the HTTP client used to fetch calendars, optionally trusting extra
+/// root certificates (in addition to any in the config).
+pub fn build_calendar_client(
+    tls: &TlsConfig,
+    fetch: &FetchConfig,
+    extra_pem: Option<&str>,
+) -> Result<Client, Error> {
+    let mut builder = Client::builder();
+
+    if let Some(path) = &tls.ca_certificates {
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read CA certificates from {}", path))?;
+        for certificate in parse_pem_certificates(&pem)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if let Some(pem) = extra_pem {
+        for certificate in parse_pem_certificates(pem)? {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    if tls.danger_accept_invalid_certs.unwrap_or(false) {
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+
+    // A hung server would otherwise hold up syncing every other calendar.
+    builder = builder.timeout(fetch.timeout());
+
+    Ok(builder.build()?)
+}
+
+/// Read the body of a response as text, failing if it's larger than
+/// `max_bytes` rather than reading it all into memory.
+pub(crate) async fn read_response_text(
+    mut resp: reqwest::Response,
+    max_bytes: usize,
+) -> Result<String, Error> {
+    if let Some(length) = resp.content_length() {
+        if length > max_bytes as u64 {
+            bail!(
+                "Response is too large ({} bytes, the limit is {})",
+                length,
+                max_bytes
+            );
+        }
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > max_bytes {
+            bail!("Response is too large (the limit is {} bytes)", max_bytes);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Send the request with the calendar's authentication.
+///
+/// For Digest auth we first need to send the request without authentication
+/// to get the server's challenge, and then retry it with the response.
+pub(crate) async fn send_authenticated(
+    req: reqwest::RequestBuilder,
+    authentication: &CalendarAuthentication,
+) -> Result<reqwest::Response, Error> {
+    let (user_name, password) = match authentication {
+        CalendarAuthentication::None => return Ok(req.send().await?),
         CalendarAuthentication::Basic {
             user_name,
             password,
-        } => req = req.basic_auth(user_name, Some(password)),
-        CalendarAuthentication::Bearer { access_token } => req = req.bearer_auth(access_token),
+        } => return Ok(req.basic_auth(user_name, Some(password)).send().await?),
+        CalendarAuthentication::Bearer { access_token } => {
+            return Ok(req.bearer_auth(access_token).send().await?)
+        }
+        CalendarAuthentication::Digest {
+            user_name,
+            password,
+        } => (user_name, password),
+    };
+
+    let retry = req
+        .try_clone()
+        .context("Request can't be retried for digest auth")?;
+
+    let resp = req.send().await?;
+    if resp.status() != StatusCode::UNAUTHORIZED {
+        return Ok(resp);
+    }
+
+    let challenge = resp
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .find_map(DigestChallenge::parse)
+        .context("Server didn't offer digest authentication")?;
+
+    let (client, request) = retry.build_split();
+    let mut request = request?;
+
+    let mut uri = request.url().path().to_string();
+    if let Some(query) = request.url().query() {
+        uri.push('?');
+        uri.push_str(query);
     }
 
-    // We fetch all calendar events from the previous N months and following, to
-    // try and mitigate a bug where the returned calendar doesn't include a base
-    // event for a recurring override.
-    let start = Utc::now() - Duration::days(30) * 6;
+    let cnonce: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect();
+
+    let authorization = challenge.authorization(
+        request.method().as_str(),
+        &uri,
+        user_name,
+        password,
+        &cnonce,
+    )?;
+
+    request
+        .headers_mut()
+        .insert(AUTHORIZATION, HeaderValue::from_str(&authorization)?);
+
+    Ok(client.execute(request).await?)
+}
+
+/// Fetch a calendar from a CalDAV URL.
+///
+/// Note that CalDAV returns a calendar per event, rather than one calendar with
+/// many events. Events that fail to parse are skipped and returned as errors.
+async fn fetch_caldav_calendars(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    window: &SyncWindow,
+    default_timezone: Option<Tz>,
+    max_response_bytes: usize,
+) -> Result<FetchedCalendars, Error> {
+    let objects =
+        fetch_caldav_objects(client, url, authentication, window, max_response_bytes).await?;
+
+    Ok(decode_calendars(
+        objects.changed.iter().map(|(_, body)| body.as_str()),
+        default_timezone,
+    ))
+}
+
+/// The objects in a CalDAV collection that have changed, or all of them if
+/// we've done a full fetch.
+#[derive(Debug, Default)]
+pub struct CalDavChanges {
+    /// The token to use to fetch later changes, if the server supports RFC
+    /// 6578.
+    pub sync_token: Option<String>,
+    /// The href and ICS body of each changed object.
+    pub changed: Vec<(String, String)>,
+    /// The hrefs of objects that have been removed.
+    pub removed: Vec<String>,
+}
 
-    let resp = req
-        .body(format!(
+/// Fetch all objects in a CalDAV collection, along with a sync token (if the
+/// server supports them) to fetch changes with [`sync_caldav_objects`].
+#[instrument(skip(client), fields(status))]
+pub async fn fetch_caldav_objects(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    window: &SyncWindow,
+    max_response_bytes: usize,
+) -> Result<CalDavChanges, Error> {
+    // We get the sync token before fetching the objects, so that we don't miss
+    // any changes made in between.
+    let sync_token = match Url::parse(url) {
+        Ok(parsed_url) => propfind(
+            client,
+            &parsed_url,
+            authentication,
+            "0",
+            "<d:sync-token />",
+            max_response_bytes,
+        )
+        .await
+        .ok()
+        .and_then(|body| find_sync_token(&body)),
+        Err(_) => None,
+    };
+
+    let (status, body) = report(
+        client,
+        url,
+        authentication,
+        "1",
+        format!(
             r#"
         <c:calendar-query xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
             <d:prop>
@@ -72,68 +999,515 @@ pub async fn fetch_calendars(
             </c:filter>
         </c:calendar-query>
         "#,
-            start = start.format("%Y%m%dT%H%M%SZ"),
-        ))
-        .send()
+            start = window.fetch_from.format("%Y%m%dT%H%M%SZ"),
+        ),
+        max_response_bytes,
+    )
+    .await?;
+
+    if !status.is_success() {
+        bail!("Got {} result from CalDAV", status.as_u16());
+    }
+
+    let (mut changes, _) = parse_multistatus(&body)?;
+    changes.sync_token = sync_token;
+
+    Ok(changes)
+}
+
+/// Fetch the objects in a CalDAV collection that have changed since the sync
+/// token was returned, using an RFC 6578 `sync-collection` report.
+///
+/// Returns `None` if the server rejected the token, in which case we need to
+/// fetch everything again.
+#[instrument(skip(client), fields(status))]
+pub async fn sync_caldav_objects(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    sync_token: &str,
+    max_response_bytes: usize,
+) -> Result<Option<CalDavChanges>, Error> {
+    let (status, body) = report(
+        client,
+        url,
+        authentication,
+        "0",
+        format!(
+            r#"
+        <d:sync-collection xmlns:d="DAV:">
+            <d:sync-token>{sync_token}</d:sync-token>
+            <d:sync-level>1</d:sync-level>
+            <d:prop>
+                <d:getetag />
+            </d:prop>
+        </d:sync-collection>
+        "#,
+            sync_token = escape_xml(sync_token),
+        ),
+        max_response_bytes,
+    )
+    .await?;
+
+    // Servers should return a 403 for invalid tokens, but not all do.
+    if matches!(status.as_u16(), 403 | 409 | 410 | 412) {
+        info!(status = status.as_u16(), "Sync token rejected");
+        return Ok(None);
+    }
+
+    if !status.is_success() {
+        bail!("Got {} result from CalDAV", status.as_u16());
+    }
+
+    let (mut changes, to_fetch) = parse_multistatus(&body)?;
+
+    if changes.sync_token.is_none() {
+        bail!("CalDAV server didn't return a new sync token");
+    }
+
+    if !to_fetch.is_empty() {
+        let hrefs: String = to_fetch
+            .iter()
+            .map(|href| format!("<d:href>{}</d:href>", escape_xml(href)))
+            .collect();
+
+        let (status, body) = report(
+            client,
+            url,
+            authentication,
+            "1",
+            format!(
+                r#"
+            <c:calendar-multiget xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+                <d:prop>
+                    <d:getetag />
+                    <c:calendar-data />
+                </d:prop>
+                {hrefs}
+            </c:calendar-multiget>
+            "#,
+                hrefs = hrefs,
+            ),
+            max_response_bytes,
+        )
         .await?;
 
+        if !status.is_success() {
+            bail!("Got {} result from CalDAV", status.as_u16());
+        }
+
+        let (fetched, _) = parse_multistatus(&body)?;
+        changes.changed.extend(fetched.changed);
+    }
+
+    Ok(Some(changes))
+}
+
+/// Make a REPORT request, returning the status and body.
+async fn report(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    depth: &str,
+    body: String,
+    max_response_bytes: usize,
+) -> Result<(StatusCode, String), Error> {
+    let req = client
+        .request(Method::from_str("REPORT").expect("method"), url)
+        .header("Content-Type", "application/xml")
+        .header("Depth", depth);
+
+    let resp = send_authenticated(req.body(body), authentication).await?;
+
     let status = resp.status();
 
-    let body = resp.text().await?;
+    let body = read_response_text(resp, max_response_bytes).await?;
 
     info!(status = status.as_u16(), "Got result from CalDAV");
     Span::current().record("status", status.as_u16());
 
-    if !status.is_success() {
-        bail!("Got {} result from CalDAV", status.as_u16());
+    Ok((status, body))
+}
+
+/// Parse a multistatus response into the objects that have calendar data,
+/// those that have been removed and the hrefs of those that don't include
+/// calendar data (and so need to be fetched).
+fn parse_multistatus(body: &str) -> Result<(CalDavChanges, Vec<String>), Error> {
+    let doc = roxmltree::Document::parse(body)
+        .map_err(|e| anyhow!(e))
+        .with_context(|| "decoding xml")?;
+
+    let mut changes = CalDavChanges {
+        sync_token: find_sync_token(body),
+        ..Default::default()
+    };
+    let mut to_fetch = Vec::new();
+
+    for response in doc
+        .descendants()
+        .filter(|node| node.tag_name().name() == "response")
+    {
+        let href = if let Some(href) = find_text(response, "href") {
+            href.to_string()
+        } else {
+            continue;
+        };
+
+        // Removed objects have a status directly on the response, rather than
+        // in a propstat.
+        let removed = response
+            .children()
+            .find(|node| node.tag_name().name() == "status")
+            .and_then(|node| node.text())
+            .is_some_and(|status| status.contains(" 404"));
+
+        if removed {
+            changes.removed.push(href);
+        } else if let Some(cal_body) = find_text(response, "calendar-data") {
+            changes.changed.push((href, cal_body.to_string()));
+        } else {
+            to_fetch.push(href);
+        }
     }
 
+    Ok((changes, to_fetch))
+}
+
+/// Find the sync token in a multistatus response.
+fn find_sync_token(body: &str) -> Option<String> {
+    let doc = roxmltree::Document::parse(body).ok()?;
+
+    let sync_token = find_text(doc.root(), "sync-token")?;
+
+    Some(sync_token.to_string())
+}
+
+/// Escape text to be included in an XML element.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// A calendar collection found in a CalDAV account.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarCollection {
+    pub url: String,
+    pub name: Option<String>,
+}
+
+/// Find all the calendar collections in the CalDAV account at the given URL.
+///
+/// The URL can be the server's root, the user's principal or their calendar
+/// home. We follow `current-user-principal` and `calendar-home-set` where we
+/// can, falling back to treating the URL as the calendar home.
+#[instrument(skip(client))]
+pub async fn discover_calendars(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    max_response_bytes: usize,
+) -> Result<Vec<CalendarCollection>, Error> {
+    let base_url = Url::parse(url).with_context(|| "Invalid URL")?;
+
+    let principal_url = propfind_href(
+        client,
+        &base_url,
+        authentication,
+        "<d:current-user-principal />",
+        "current-user-principal",
+        max_response_bytes,
+    )
+    .await?
+    .unwrap_or_else(|| base_url.clone());
+
+    let home_url = propfind_href(
+        client,
+        &principal_url,
+        authentication,
+        "<c:calendar-home-set />",
+        "calendar-home-set",
+        max_response_bytes,
+    )
+    .await?
+    .unwrap_or(principal_url);
+
+    let body = propfind(
+        client,
+        &home_url,
+        authentication,
+        "1",
+        "<d:resourcetype /><d:displayname />",
+        max_response_bytes,
+    )
+    .await?;
+
     let doc = roxmltree::Document::parse(&body)
         .map_err(|e| anyhow!(e))
         .with_context(|| "decoding xml")?;
 
-    let mut calendars = Vec::new();
-
-    for node in doc.descendants() {
-        if node.tag_name().name() != "calendar-data" {
+    let mut collections = Vec::new();
+    for response in doc
+        .descendants()
+        .filter(|node| node.tag_name().name() == "response")
+    {
+        let is_calendar = response
+            .descendants()
+            .filter(|node| node.tag_name().name() == "resourcetype")
+            .flat_map(|node| node.children())
+            .any(|node| node.tag_name().name() == "calendar");
+        if !is_calendar {
             continue;
         }
 
-        let cal_body = if let Some(t) = node.text() {
-            t
+        let href = if let Some(href) = find_text(response, "href") {
+            href
         } else {
             continue;
         };
 
+        collections.push(CalendarCollection {
+            url: home_url.join(href)?.to_string(),
+            name: find_text(response, "displayname").map(ToOwned::to_owned),
+        });
+    }
+
+    Ok(collections)
+}
+
+/// Get the WebDAV URL of a Nextcloud server from its base URL, e.g.
+/// `https://cloud.example.com` becomes
+/// `https://cloud.example.com/remote.php/dav/`.
+///
+/// URLs that already point at the WebDAV endpoint are left alone.
+pub fn nextcloud_dav_url(base_url: &str) -> Result<String, Error> {
+    let mut url = Url::parse(base_url.trim()).with_context(|| "Invalid URL")?;
+
+    if !url.path().contains("/remote.php/dav") {
+        // Nextcloud may be installed in a sub-directory, so we append to the
+        // path rather than replacing it.
+        let path = format!("{}/remote.php/dav/", url.path().trim_end_matches('/'));
+        url.set_path(&path);
+    }
+
+    url.set_query(None);
+    url.set_fragment(None);
+
+    Ok(url.to_string())
+}
+
+/// Get the text of the first descendant of the node with the given tag name.
+fn find_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.descendants()
+        .find(|node| node.tag_name().name() == name)
+        .and_then(|node| node.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+}
+
+/// Look up a property of the resource that contains a URL, e.g.
+/// `calendar-home-set`.
+async fn propfind_href(
+    client: &reqwest::Client,
+    url: &Url,
+    authentication: &CalendarAuthentication,
+    prop: &str,
+    prop_name: &str,
+    max_response_bytes: usize,
+) -> Result<Option<Url>, Error> {
+    let body = propfind(client, url, authentication, "0", prop, max_response_bytes).await?;
+
+    let doc = roxmltree::Document::parse(&body)
+        .map_err(|e| anyhow!(e))
+        .with_context(|| "decoding xml")?;
+
+    let href = doc
+        .descendants()
+        .find(|node| node.tag_name().name() == prop_name)
+        .and_then(|node| find_text(node, "href"));
+
+    Ok(href.map(|href| url.join(href)).transpose()?)
+}
+
+/// Make a PROPFIND request for the given properties.
+async fn propfind(
+    client: &reqwest::Client,
+    url: &Url,
+    authentication: &CalendarAuthentication,
+    depth: &str,
+    props: &str,
+    max_response_bytes: usize,
+) -> Result<String, Error> {
+    let req = client
+        .request(Method::from_str("PROPFIND").expect("method"), url.clone())
+        .header("Content-Type", "application/xml")
+        .header("Depth", depth);
+
+    let req = req.body(format!(
+        r#"
+        <d:propfind xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+            <d:prop>{}</d:prop>
+        </d:propfind>
+        "#,
+        props
+    ));
+
+    let resp = send_authenticated(req, authentication).await?;
+
+    let status = resp.status();
+
+    info!(status = status.as_u16(), %url, "Got PROPFIND result");
+
+    if !status.is_success() {
+        bail!("Got {} result from PROPFIND", status.as_u16());
+    }
+
+    read_response_text(resp, max_response_bytes).await
+}
+
+/// Fetch a plain .ics feed, which contains all the events in one calendar.
+async fn fetch_ics_calendar(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    default_timezone: Option<Tz>,
+    max_response_bytes: usize,
+) -> Result<FetchedCalendars, Error> {
+    // Feeds are often given as `webcal://` links, which are just HTTPS.
+    let url = match url.strip_prefix("webcal://") {
+        Some(rest) => format!("https://{}", rest),
+        None => url.to_string(),
+    };
+
+    let resp = send_authenticated(client.get(&url), authentication).await?;
+
+    let status = resp.status();
+
+    let body = read_response_text(resp, max_response_bytes).await?;
+
+    info!(status = status.as_u16(), "Got result from ICS feed");
+    Span::current().record("status", status.as_u16());
+
+    if !status.is_success() {
+        bail!("Got {} result from ICS feed", status.as_u16());
+    }
+
+    Ok(decode_calendars(
+        std::iter::once(body.as_str()),
+        default_timezone,
+    ))
+}
+
+/// Parse an uploaded .ics file.
+pub fn parse_ics_file(body: &str) -> FetchedCalendars {
+    decode_calendars(std::iter::once(body), None)
+}
+
+/// Decode the given ICS encoded calendars. Calendars that fail to parse are
+/// skipped and returned as errors.
+///
+/// TZIDs are normalized to IANA timezones first, with any we don't recognise
+/// (and that the calendar doesn't define) replaced by `default_timezone`.
+pub fn decode_calendars<'a>(
+    cal_bodies: impl Iterator<Item = &'a str>,
+    default_timezone: Option<Tz>,
+) -> FetchedCalendars {
+    let mut calendars = Vec::new();
+    let mut errors = Vec::new();
+    let mut cancelled = HashSet::new();
+    let mut transparent = HashSet::new();
+    let mut conference_urls = HashMap::new();
+    let mut recurrence_dates: HashMap<String, RecurrenceDates> = HashMap::new();
+    let mut durations = HashMap::new();
+
+    for cal_body in cal_bodies {
+        let normalized = normalize_tzids(cal_body, default_timezone);
+        let cal_body: &str = &normalized;
+
         match decode_calendar(cal_body) {
-            Ok(cals) => calendars.extend(cals),
+            Ok(cals) => {
+                calendars.extend(cals);
+                cancelled.extend(find_cancelled_uids(cal_body));
+                transparent.extend(find_transparent_uids(cal_body));
+                conference_urls.extend(find_google_conference_urls(cal_body));
+                durations.extend(find_event_durations(cal_body));
+
+                // Cancelled occurrences come from separate overrides, so we
+                // need to merge them with the event's other dates.
+                for (uid, dates) in find_recurrence_dates(cal_body) {
+                    let entry = recurrence_dates.entry(uid).or_default();
+                    entry.excluded.extend(dates.excluded);
+                    entry.added.extend(dates.added);
+                }
+            }
             Err(e) => {
                 capture_anyhow(&e);
                 error!(
                     error = e.deref() as &dyn std::error::Error,
                     "Failed to parse calendar"
-                )
+                );
+
+                if errors.len() < MAX_CALENDAR_ERRORS {
+                    errors.push(CalendarError {
+                        uid: find_uid(cal_body),
+                        message: format!("{:#}", e),
+                    });
+                }
             }
         }
     }
 
-    Ok(calendars)
+    FetchedCalendars {
+        calendars,
+        errors,
+        cancelled,
+        transparent,
+        conference_urls,
+        recurrence_dates,
+        durations,
+    }
 }
 
-/// Parse the calendars into events and event instances.
+/// Parse the fetched calendars into events and event instances.
+///
+/// Conference links are taken from the `X-GOOGLE-CONFERENCE` property if given
+/// for the event, otherwise from the location or description. Occurrences are
+/// excluded or added based on the event's `EXDATE`s and `RDATE`s, and only
+/// those within the sync window are returned. If a recurring event ends within
+/// the window, its final occurrence is recorded on the event. Cancelled events
+/// are skipped, as are free (transparent) ones if `ignore_transparent` is set.
 pub fn parse_calendars_to_events(
     calendar_id: i64,
-    calendars: &[VCalendar],
+    fetched: &FetchedCalendars,
+    timezone: Option<Tz>,
+    window: &SyncWindow,
+    ignore_transparent: bool,
 ) -> Result<(Vec<Event>, Vec<EventInstance>), Error> {
-    let now = Utc::now();
     let mut events: Vec<Event> = Vec::new();
     let mut next_dates = Vec::new();
-    for calendar in calendars {
+    for calendar in &fetched.calendars {
         for (uid, event) in &calendar.events {
-            if event.base_event.is_full_day_event() || event.base_event.is_floating_event() {
+            if event.base_event.is_full_day_event() || fetched.cancelled.contains(uid) {
                 continue;
             }
 
+            if ignore_transparent && fetched.transparent.contains(uid) {
+                continue;
+            }
+
+            // Floating events happen at the same wall clock time in every
+            // timezone, so we can only use them if we know which timezone to
+            // assume.
+            let floating_timezone = if event.base_event.is_floating_event() {
+                if let Some(timezone) = timezone {
+                    Some(timezone)
+                } else {
+                    continue;
+                }
+            } else {
+                None
+            };
+
             let mut organizer = None;
             for prop in &event.base_event.properties {
                 if let ics_parser::property::Property::Organizer(prop) = prop {
@@ -141,23 +1515,46 @@ pub fn parse_calendars_to_events(
                 }
             }
 
-            events.push(Event {
-                calendar_id,
-                event_id: uid.clone(),
-                summary: event.base_event.summary.clone(),
-                description: event.base_event.description.clone(),
-                location: event.base_event.location.clone(),
-                organizer,
-                attendees: get_attendees(&event.base_event),
+            let conference_url = fetched.conference_urls.get(uid).cloned().or_else(|| {
+                event
+                    .base_event
+                    .location
+                    .iter()
+                    .chain(&event.base_event.description)
+                    .find_map(|text| find_conference_url(text))
             });
 
-            // Loop through all occurrences of the event in the next N days and
+            let default_dates = RecurrenceDates::default();
+            let dates = fetched.recurrence_dates.get(uid).unwrap_or(&default_dates);
+            let excluded: Vec<_> = dates
+                .excluded
+                .iter()
+                .filter_map(|date| date.resolve(floating_timezone))
+                .collect();
+
+            let first_instance = next_dates.len();
+
+            // Loop through all occurrences of the event in the sync window and
             // generate `EventInstance` for them.
             for (date, recur_event) in event
                 .recur_iter(calendar)?
-                .skip_while(|(d, _)| *d < now - Duration::days(7))
-                .take_while(|(d, _)| *d < now + Duration::days(30))
+                .skip_while(|(d, _)| *d < window.from)
+                .take_while(|(d, _)| *d < window.until)
             {
+                let date = if let Some(timezone) = floating_timezone {
+                    if let Some(date) = resolve_floating_date(date, timezone) {
+                        date
+                    } else {
+                        continue;
+                    }
+                } else {
+                    date
+                };
+
+                if excluded.contains(&date) {
+                    continue;
+                }
+
                 // Loop over all the properties to pull out the attendee info.
 
                 next_dates.push(EventInstance {
@@ -166,11 +1563,88 @@ pub fn parse_calendars_to_events(
                     attendees: get_attendees(recur_event),
                 });
             }
+
+            // Add any extra occurrences that the `RRULE` didn't generate.
+            for date in dates
+                .added
+                .iter()
+                .filter_map(|date| date.resolve(floating_timezone))
+            {
+                if date < window.from
+                    || date >= window.until
+                    || excluded.contains(&date)
+                    || next_dates[first_instance..]
+                        .iter()
+                        .any(|instance: &EventInstance| instance.date == date)
+                {
+                    continue;
+                }
+
+                next_dates.push(EventInstance {
+                    event_id: uid.into(),
+                    date,
+                    attendees: get_attendees(&event.base_event),
+                });
+            }
+
+            // If the recurrence ends within the sync window then the last
+            // instance we've generated is the final one.
+            let has_end = matches!(
+                event
+                    .base_event
+                    .recur
+                    .as_ref()
+                    .map(|recur| &recur.end_condition),
+                Some(EndCondition::Count(_) | EndCondition::Until(_) | EndCondition::UntilUtc(_))
+            );
+            let last_occurrence = if has_end
+                && event.recur_iter(calendar)?.all(|(d, _)| d < window.until)
+                && dates
+                    .added
+                    .iter()
+                    .filter_map(|date| date.resolve(floating_timezone))
+                    .all(|date| date < window.until)
+            {
+                next_dates[first_instance..]
+                    .iter()
+                    .map(|instance| instance.date.with_timezone(&Utc))
+                    .max()
+            } else {
+                None
+            };
+
+            events.push(Event {
+                calendar_id,
+                event_id: uid.clone(),
+                summary: event.base_event.summary.clone(),
+                description: event.base_event.description.clone(),
+                location: event.base_event.location.clone(),
+                organizer,
+                attendees: get_attendees(&event.base_event),
+                conference_url,
+                duration_minutes: fetched.durations.get(uid).copied(),
+                last_occurrence,
+            });
         }
     }
     Ok((events, next_dates))
 }
 
+/// Resolve the wall clock time of a floating date in the given timezone.
+///
+/// Returns `None` if the time doesn't exist in the timezone, e.g. if it falls
+/// in the gap when the clocks go forward.
+fn resolve_floating_date(
+    date: DateTime<FixedOffset>,
+    timezone: Tz,
+) -> Option<DateTime<FixedOffset>> {
+    let resolved = timezone
+        .from_local_datetime(&date.naive_local())
+        .earliest()?;
+
+    Some(resolved.fixed_offset())
+}
+
 /// Parse the attendees from the event.
 fn get_attendees(event: &VEvent) -> Vec<Attendee> {
     let mut attendees = Vec::new();
@@ -195,19 +1669,22 @@ fn parse_to_attendee(prop: &PropertyValue<Url>) -> Option<Attendee> {
     let email = prop.value.path().to_string();
 
     let mut common_name = None;
+    let mut status = None;
     for param in prop.parameters.parameters() {
         match param {
             ics_parser::parameters::Parameter::CN(cn) => {
                 common_name = Some(cn.clone());
             }
-            ics_parser::parameters::Parameter::ParticipationStatus(status)
-                if status == "DECLINED" =>
-            {
-                return None
+            ics_parser::parameters::Parameter::ParticipationStatus(partstat) => {
+                status = Some(partstat.to_ascii_uppercase());
             }
             _ => {}
         }
     }
 
-    Some(Attendee { email, common_name })
+    Some(Attendee {
+        email,
+        common_name,
+        status,
+    })
 }
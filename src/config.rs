@@ -1,7 +1,11 @@
 //! Config file structures.
 
+use std::{collections::BTreeMap, time::Duration};
+
 use serde::Deserialize;
 
+use crate::quiet_hours::QuietHours;
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub database: DatabaseConfig,
@@ -19,23 +23,393 @@ pub struct Config {
 
     #[serde(default)]
     pub sentry: Option<SentryConfig>,
+
+    #[serde(default)]
+    pub password_policy: PasswordPolicyConfig,
+
+    #[serde(default)]
+    pub tls: TlsConfig,
+
+    #[serde(default)]
+    pub fetch: FetchConfig,
+
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+
+    #[serde(default)]
+    pub webhooks: WebhookConfig,
+
+    /// The SMTP server to send emails through. Emails aren't sent if unset.
+    #[serde(default)]
+    pub email: Option<EmailConfig>,
+
+    /// The keys to encrypt calendar passwords and OAuth2 tokens with in the
+    /// database. They're stored in plaintext if unset.
+    #[serde(default)]
+    pub encryption: Option<EncryptionConfig>,
+
+    /// Times when reminders aren't posted, unless the room has its own quiet
+    /// hours.
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+
+    /// Feeds of public holidays, keyed by region, that reminders can be set
+    /// to not send on.
+    #[serde(default)]
+    pub public_holidays: BTreeMap<String, PublicHolidaysConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct DatabaseConfig {
     pub connection_string: String,
+    /// Whether to connect over TLS, and how to check the server's
+    /// certificate. Overrides any `sslmode` in the connection string, which
+    /// defaults to `prefer`.
+    pub sslmode: Option<PostgresSslMode>,
+    /// A PEM file of extra root certificates to trust for the database
+    /// server, e.g. a managed provider's CA.
+    pub ca_certificates: Option<String>,
+}
+
+/// How to use TLS when connecting to Postgres, with the same meanings as
+/// libpq's `sslmode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PostgresSslMode {
+    /// Never use TLS.
+    Disable,
+    /// Use TLS if the server supports it, without checking its certificate.
+    Prefer,
+    /// Always use TLS. The server's certificate is only checked if
+    /// `ca_certificates` is set.
+    Require,
+    /// Always use TLS, and check the server's certificate is signed by a
+    /// trusted CA.
+    VerifyCa,
+    /// Like `verify-ca`, but also check the certificate matches the host.
+    VerifyFull,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct MatrixConfig {
     pub homeserver_url: String,
+    /// The access token of the bot. When running as an appservice this is
+    /// the `as_token`.
     pub access_token: String,
+    /// If set, run as an application service rather than a plain bot.
+    pub appservice: Option<AppserviceConfig>,
+    /// Additional accounts that reminders can be sent from.
+    #[serde(default)]
+    pub senders: Vec<MatrixSenderConfig>,
+    /// Whether to periodically set the bot's presence to online, with a
+    /// status message saying how many reminders it's tracking. Defaults to
+    /// false.
+    pub presence: Option<bool>,
+}
+
+impl MatrixConfig {
+    /// Get the credentials to use for the given sender, or the main account
+    /// if None.
+    pub fn credentials(&self, sender: Option<&str>) -> Option<MatrixCredentials<'_>> {
+        let sender = if let Some(sender) = sender {
+            sender
+        } else {
+            return Some(MatrixCredentials {
+                homeserver_url: &self.homeserver_url,
+                access_token: &self.access_token,
+                user_id: None,
+            });
+        };
+
+        let config = self.senders.iter().find(|config| config.name == sender)?;
+        let homeserver_url = config
+            .homeserver_url
+            .as_deref()
+            .unwrap_or(&self.homeserver_url);
+
+        match (config.access_token.as_deref(), config.user_id.as_deref()) {
+            (Some(access_token), _) => Some(MatrixCredentials {
+                homeserver_url,
+                access_token,
+                user_id: None,
+            }),
+            (None, Some(user_id)) if self.appservice.is_some() => Some(MatrixCredentials {
+                homeserver_url,
+                access_token: &self.access_token,
+                user_id: Some(user_id),
+            }),
+            _ => None,
+        }
+    }
+
+    /// The users that the appservice sends as, i.e. the senders without an
+    /// access token of their own.
+    pub fn appservice_user_ids(&self) -> impl Iterator<Item = &str> {
+        self.senders
+            .iter()
+            .filter(|config| config.access_token.is_none())
+            .filter_map(|config| config.user_id.as_deref())
+    }
+}
+
+/// The credentials to make requests to the homeserver as one of the bot's
+/// accounts with.
+#[derive(Debug, Clone, Copy)]
+pub struct MatrixCredentials<'a> {
+    pub homeserver_url: &'a str,
+    pub access_token: &'a str,
+    /// The user the appservice is sending as, if any.
+    pub user_id: Option<&'a str>,
+}
+
+impl MatrixCredentials<'_> {
+    /// Add the credentials to the request, masquerading as the user if we're
+    /// sending as one.
+    pub fn authenticate(&self, req: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let req = req.bearer_auth(self.access_token);
+
+        if let Some(user_id) = self.user_id {
+            req.query(&[("user_id", user_id)])
+        } else {
+            req
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MatrixSenderConfig {
+    /// The name reminders use to pick this account.
+    pub name: String,
+    /// The access token of the account. Not needed if `user_id` is set and
+    /// we're running as an appservice.
+    pub access_token: Option<String>,
+    /// When running as an appservice, the user to send as using the
+    /// appservice's token. It's added to the registration's user namespace,
+    /// and registered on startup.
+    pub user_id: Option<String>,
+    /// Defaults to the main account's homeserver.
+    pub homeserver_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppserviceConfig {
+    /// The unique ID of the appservice registration.
+    pub id: String,
+    /// The token the homeserver uses when pushing transactions to us.
+    pub hs_token: String,
+    /// The localpart of the bot user.
+    pub sender_localpart: String,
+    /// The URL the homeserver can reach the web server on.
+    pub url: String,
+}
+
+impl AppserviceConfig {
+    /// Generate the registration file to give to the homeserver.
+    ///
+    /// The users namespace claims the senders the appservice sends as. The
+    /// bot doesn't manage any aliases or rooms, so those namespaces are
+    /// empty.
+    pub fn registration(&self, matrix: &MatrixConfig) -> String {
+        // JSON strings are valid YAML strings, so we use them to avoid having
+        // to worry about escaping.
+        let quote = |s: &str| serde_json::Value::from(s).to_string();
+
+        let mut users = String::new();
+        for user_id in matrix.appservice_user_ids() {
+            users.push_str(&format!(
+                "\n    - exclusive: true\n      regex: {}",
+                quote(&format!("^{}$", regex::escape(user_id)))
+            ));
+        }
+        if users.is_empty() {
+            users.push_str(" []");
+        }
+
+        format!(
+            "id: {}\n\
+             url: {}\n\
+             as_token: {}\n\
+             hs_token: {}\n\
+             sender_localpart: {}\n\
+             rate_limited: false\n\
+             namespaces:\n  users:{}\n  aliases: []\n  rooms: []\n",
+            quote(&self.id),
+            quote(&self.url),
+            quote(&matrix.access_token),
+            quote(&self.hs_token),
+            quote(&self.sender_localpart),
+            users,
+        )
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct AppConfig {
+    /// The address to listen on, either `host:port` or `unix:/path/to/socket`.
     pub bind_addr: Option<String>,
+    /// The permissions to set on the unix socket, in octal (e.g. "660").
+    /// Only used if `bind_addr` is a unix socket.
+    pub socket_permissions: Option<String>,
     pub resource_directory: Option<String>,
+    /// How many days a login lasts without being used. Defaults to 7.
+    pub session_lifetime_days: Option<i64>,
+    /// How many days deleted calendars and reminders are kept (and can be
+    /// restored) before being purged. Defaults to 30.
+    pub deletion_grace_period_days: Option<i64>,
+    /// The URL the web UI is publicly reachable at, used to link back to
+    /// events from reminders.
+    pub public_base_url: Option<String>,
+    /// Whether to combine reminders that are due at the same time in the same
+    /// room into a single message. Defaults to false.
+    pub combine_simultaneous_reminders: Option<bool>,
+    /// The reaction people can use on a reminder to have it reposted later.
+    /// Defaults to 💤.
+    pub snooze_reaction: Option<String>,
+    /// How long snoozed reminders are snoozed for. Defaults to 10 minutes.
+    pub snooze_minutes: Option<i64>,
+    /// Whether to follow up reminders for events at a physical location with
+    /// the location (or a map link). Defaults to false.
+    pub send_location: Option<bool>,
+    /// The msgtype to send reminders as, either `m.text` or `m.notice`.
+    /// Defaults to `m.text`, and can be overridden per reminder.
+    pub msgtype: Option<String>,
+    /// A space (ID or alias) whose rooms are suggested when picking the room
+    /// to send reminders to.
+    pub space: Option<String>,
+    /// How many days ahead to sync events for. Defaults to 30, and can be
+    /// overridden per calendar.
+    pub sync_lookahead_days: Option<i64>,
+    /// How many days back to fetch events from CalDAV calendars. Defaults to
+    /// 180, and can be overridden per calendar.
+    pub sync_lookback_days: Option<i64>,
+    /// Whether to DM the owner of a reminder when it still fails to send
+    /// after being retried. Defaults to false.
+    pub notify_failed_reminders: Option<bool>,
+    /// The emails of users that are always admins, in addition to those made
+    /// admins through the admin API.
+    #[serde(default)]
+    pub admins: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct PasswordPolicyConfig {
+    /// The minimum number of characters in a password. Defaults to 8.
+    pub min_length: Option<usize>,
+    /// The minimum number of character classes (lowercase, uppercase, digits
+    /// and symbols) a password must use. Defaults to 1.
+    pub min_character_classes: Option<usize>,
+    /// Whether to reject passwords that have appeared in data breaches, using
+    /// the haveibeenpwned API. Defaults to false.
+    pub check_pwned: Option<bool>,
+}
+
+impl PasswordPolicyConfig {
+    pub fn min_length(&self) -> usize {
+        self.min_length.unwrap_or(8)
+    }
+
+    pub fn min_character_classes(&self) -> usize {
+        self.min_character_classes.unwrap_or(1)
+    }
+}
+
+/// Limits on failed logins and API token attempts, per IP address and per
+/// account, to slow down guessing passwords.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct RateLimitConfig {
+    /// Where to count attempts. Defaults to `memory`, which only works when
+    /// running a single instance.
+    pub backend: Option<RateLimitBackend>,
+    /// How many failed attempts are allowed in each window. Defaults to 10.
+    pub max_attempts: Option<i32>,
+    /// How long the window is, in seconds. Defaults to 300.
+    pub window_seconds: Option<i64>,
+    /// The IP addresses of reverse proxies to trust the `X-Forwarded-For`
+    /// header from, or `unix` for connections over the Unix socket.
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+}
+
+impl RateLimitConfig {
+    pub fn backend(&self) -> RateLimitBackend {
+        self.backend.unwrap_or(RateLimitBackend::Memory)
+    }
+
+    pub fn max_attempts(&self) -> i32 {
+        self.max_attempts.unwrap_or(10)
+    }
+
+    pub fn window(&self) -> chrono::Duration {
+        chrono::Duration::seconds(self.window_seconds.unwrap_or(300))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitBackend {
+    /// Count attempts in memory.
+    Memory,
+    /// Count attempts in the database, so that they're shared between
+    /// instances.
+    Database,
+}
+
+/// TLS options for fetching calendars.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct TlsConfig {
+    /// A PEM file of extra root certificates to trust, e.g. for self-hosted
+    /// CalDAV servers using an internal CA.
+    pub ca_certificates: Option<String>,
+    /// Whether to accept invalid certificates. This is dangerous, so should
+    /// only be used for testing. Defaults to false.
+    pub danger_accept_invalid_certs: Option<bool>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublicHolidaysConfig {
+    /// The URL of an ICS feed of the region's public holidays.
+    pub url: String,
+    /// The timezone the region's days are in. Defaults to UTC.
+    pub timezone: Option<String>,
+}
+
+/// Limits on fetching calendars, so that misbehaving servers don't hold up
+/// syncing other calendars.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct FetchConfig {
+    /// How long each request to a calendar server can take, in seconds.
+    /// Defaults to 60.
+    pub timeout_seconds: Option<u64>,
+    /// The largest response we'll accept from a calendar server, in bytes.
+    /// Defaults to 50MiB.
+    pub max_response_bytes: Option<usize>,
+    /// How many calendars to fetch at once. Defaults to 4.
+    pub max_concurrent_fetches: Option<usize>,
+}
+
+impl FetchConfig {
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_seconds.unwrap_or(60))
+    }
+
+    pub fn max_response_bytes(&self) -> usize {
+        self.max_response_bytes.unwrap_or(50 * 1024 * 1024)
+    }
+
+    pub fn max_concurrent_fetches(&self) -> usize {
+        // A limit of zero would mean no limit, which isn't what anyone wants.
+        self.max_concurrent_fetches.unwrap_or(4).max(1)
+    }
+}
+
+/// Options for outgoing webhooks.
+#[derive(Debug, Clone, Deserialize, Default)]
+pub struct WebhookConfig {
+    /// Hosts that webhooks can be sent to even if they resolve to loopback,
+    /// private or link-local addresses, e.g. an internal automation service.
+    /// Other hosts must only resolve to public addresses.
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
 }
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -84,6 +458,69 @@ impl std::fmt::Debug for GoogleConfig {
     }
 }
 
+#[derive(Clone, Deserialize)]
+pub struct EmailConfig {
+    pub smtp_host: String,
+    /// Defaults to the standard port for the TLS mode.
+    pub smtp_port: Option<u16>,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// How to secure the connection to the SMTP server. Defaults to
+    /// `starttls`.
+    pub tls: Option<SmtpTls>,
+    /// The address emails are sent from, e.g. `CalBot <calbot@example.com>`.
+    pub from: String,
+}
+
+impl std::fmt::Debug for EmailConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EmailConfig")
+            .field("smtp_host", &self.smtp_host)
+            .field("smtp_port", &self.smtp_port)
+            .field("smtp_username", &self.smtp_username)
+            .field("smtp_password", &self.smtp_password.is_some())
+            .field("tls", &self.tls)
+            .field("from", &self.from)
+            .finish()
+    }
+}
+
+/// Keys for encrypting secrets stored in the database, each given as 64 hex
+/// characters, i.e. 32 bytes.
+#[derive(Clone, Deserialize)]
+pub struct EncryptionConfig {
+    /// The key to encrypt with.
+    pub key: Option<String>,
+    /// A file to read the key from instead, e.g. one written by a secrets
+    /// manager or KMS.
+    pub key_file: Option<String>,
+    /// Keys that secrets may still be encrypted with, so that they can be
+    /// read until re-encrypted with `rotate-secrets`.
+    #[serde(default)]
+    pub previous_keys: Vec<String>,
+}
+
+impl std::fmt::Debug for EncryptionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionConfig")
+            .field("key", &self.key.is_some())
+            .field("key_file", &self.key_file)
+            .field("previous_keys", &self.previous_keys.len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Connect over TLS, by default to port 465.
+    Tls,
+    /// Upgrade the connection with STARTTLS, by default on port 587.
+    Starttls,
+    /// Don't use TLS. Only suitable for a local relay.
+    None,
+}
+
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct SentryConfig {
     pub dsn: String,
@@ -1,25 +1,121 @@
 //! Module for talking to the database
 
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
 use std::ops::Deref;
 
 use anyhow::{ensure, Context, Error};
-use chrono::{DateTime, Duration, FixedOffset, Utc};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use itertools::Itertools;
+use native_tls::{Certificate, TlsConnector};
+use postgres_native_tls::MakeTlsConnector;
 use postgres_types::{FromSql, ToSql};
 use serde::{Deserialize, Serialize};
-use tokio_postgres::NoTls;
+use tokio_postgres::{config::SslMode, Row};
 use tracing::info;
 
+use crate::config::{DatabaseConfig, PostgresSslMode};
+use crate::holidays::PublicHoliday;
+use crate::quiet_hours::{QuietHours, QuietHoursAction};
+use crate::secrets::Secrets;
+
 /// Async database pool for PostgreSQL.
-pub type PostgresPool = bb8::Pool<bb8_postgres::PostgresConnectionManager<NoTls>>;
+pub type PostgresPool = bb8::Pool<bb8_postgres::PostgresConnectionManager<MakeTlsConnector>>;
+
+/// Parse the connection string in the config, applying the configured
+/// `sslmode`, and build the TLS connector to connect with.
+pub fn postgres_connection_config(
+    config: &DatabaseConfig,
+) -> Result<(tokio_postgres::Config, MakeTlsConnector), Error> {
+    let mut pg_config: tokio_postgres::Config = config
+        .connection_string
+        .parse()
+        .context("Invalid database connection string")?;
+
+    let sslmode = config
+        .sslmode
+        .unwrap_or_else(|| match pg_config.get_ssl_mode() {
+            SslMode::Disable => PostgresSslMode::Disable,
+            SslMode::Require => PostgresSslMode::Require,
+            _ => PostgresSslMode::Prefer,
+        });
+
+    // tokio-postgres leaves checking certificates to the connector, so the
+    // verify modes just need TLS.
+    pg_config.ssl_mode(match sslmode {
+        PostgresSslMode::Disable => SslMode::Disable,
+        PostgresSslMode::Prefer => SslMode::Prefer,
+        PostgresSslMode::Require | PostgresSslMode::VerifyCa | PostgresSslMode::VerifyFull => {
+            SslMode::Require
+        }
+    });
+
+    let mut builder = TlsConnector::builder();
+
+    if let Some(path) = &config.ca_certificates {
+        let pem = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read CA certificates from {}", path))?;
+
+        const END: &str = "-----END CERTIFICATE-----";
+        let mut found = false;
+        for block in pem.split_inclusive(END).filter(|block| block.contains(END)) {
+            builder.add_root_certificate(Certificate::from_pem(block.trim().as_bytes())?);
+            found = true;
+        }
+        ensure!(found, "No certificates found in {}", path);
+    }
+
+    // As with libpq, `require` only checks the certificate if we've been
+    // given a CA to check it against.
+    let verify_certs = match sslmode {
+        PostgresSslMode::Disable | PostgresSslMode::Prefer => false,
+        PostgresSslMode::Require => config.ca_certificates.is_some(),
+        PostgresSslMode::VerifyCa | PostgresSslMode::VerifyFull => true,
+    };
+    builder.danger_accept_invalid_certs(!verify_certs);
+    builder.danger_accept_invalid_hostnames(sslmode != PostgresSslMode::VerifyFull);
+
+    let connector = MakeTlsConnector::new(builder.build()?);
+
+    Ok((pg_config, connector))
+}
 
 /// An attendee of the meeting.
 ///
-/// Includes people who haven't responded, or are tentative/confirmed.
+/// Includes people who have declined, see [`Attendee::is_declined`].
 #[derive(Debug, Clone, PartialEq, Eq, Hash, ToSql, FromSql)]
 pub struct Attendee {
     pub email: String,
     pub common_name: Option<String>,
+    /// The attendee's participation status (`PARTSTAT`), e.g. `ACCEPTED`,
+    /// `TENTATIVE`, `DECLINED` or `NEEDS-ACTION`.
+    pub status: Option<String>,
+}
+
+impl Attendee {
+    /// Whether the attendee has declined the meeting.
+    pub fn is_declined(&self) -> bool {
+        self.status.as_deref() == Some("DECLINED")
+    }
+
+    /// Whether the attendee has only tentatively accepted the meeting.
+    pub fn is_tentative(&self) -> bool {
+        self.status.as_deref() == Some("TENTATIVE")
+    }
+
+    /// Whether the attendee has been invited but hasn't responded yet.
+    ///
+    /// Attendees without a known status (e.g. those added to a reminder by
+    /// hand) don't count.
+    pub fn needs_action(&self) -> bool {
+        self.status.as_deref() == Some("NEEDS-ACTION")
+    }
+
+    /// Whether the attendee has accepted the meeting, or tentatively
+    /// accepted it.
+    pub fn is_attending(&self) -> bool {
+        matches!(self.status.as_deref(), Some("ACCEPTED" | "TENTATIVE"))
+    }
 }
 
 #[derive(Clone, Serialize)]
@@ -27,6 +123,7 @@ pub struct Attendee {
 pub enum CalendarAuthentication {
     None,
     Basic { user_name: String, password: String },
+    Digest { user_name: String, password: String },
     Bearer { access_token: String },
 }
 
@@ -42,6 +139,14 @@ impl std::fmt::Debug for CalendarAuthentication {
                 .field("user_name", user_name)
                 .field("password", &"<password>")
                 .finish(),
+            CalendarAuthentication::Digest {
+                user_name,
+                password: _,
+            } => f
+                .debug_struct("Digest")
+                .field("user_name", user_name)
+                .field("password", &"<password>")
+                .finish(),
             CalendarAuthentication::Bearer { .. } => f.debug_struct("Bearer").finish(),
         }
     }
@@ -54,13 +159,141 @@ pub struct Calendar {
     pub calendar_id: i64,
     pub name: String,
     pub url: String,
+    /// Whether the calendar is synced and its reminders sent.
+    pub enabled: bool,
+    pub kind: CalendarKind,
+    /// The timezone to assume for floating events, which are skipped if
+    /// unset.
+    pub timezone: Option<String>,
+    /// Extra PEM encoded root certificates to trust when fetching the
+    /// calendar.
+    pub ca_certificate: Option<String>,
+    /// Whether to copy reminders from events that have ended onto new events
+    /// with the same summary and organizer.
+    pub port_duplicate_reminders: bool,
+    /// How many days ahead to sync events for, if different from the config.
+    pub sync_lookahead_days: Option<i64>,
+    /// How many days back to fetch events from, if different from the config.
+    pub sync_lookback_days: Option<i64>,
+    /// The end of the period the calendar's event instances cover, as of the
+    /// last sync.
+    pub synced_until: Option<DateTime<Utc>>,
+    /// Whether to skip events that are marked as free.
+    pub ignore_transparent: bool,
+    /// When reminders stop being sent, if muted. `None` means immediately.
+    pub muted_from: Option<DateTime<Utc>>,
+    /// When reminders start being sent again, if muted.
+    pub muted_until: Option<DateTime<Utc>>,
 
     #[serde(skip)]
     pub authentication: CalendarAuthentication,
 }
 
+/// How a calendar is fetched.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CalendarKind {
+    /// A CalDAV collection, queried with a `REPORT` request.
+    #[default]
+    CalDav,
+    /// A single .ics file, fetched with a plain `GET`.
+    Ics,
+    /// An uploaded .ics file, which never changes.
+    Static,
+    /// An Exchange calendar, fetched with Exchange Web Services.
+    Ews,
+    /// A Google calendar, fetched with the Google Calendar API.
+    Google,
+}
+
+impl CalendarKind {
+    /// The name of the kind as stored in the DB.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CalendarKind::CalDav => "caldav",
+            CalendarKind::Ics => "ics",
+            CalendarKind::Static => "static",
+            CalendarKind::Ews => "ews",
+            CalendarKind::Google => "google",
+        }
+    }
+
+    fn from_db(kind: &str) -> Result<CalendarKind, Error> {
+        match kind {
+            "caldav" => Ok(CalendarKind::CalDav),
+            "ics" => Ok(CalendarKind::Ics),
+            "static" => Ok(CalendarKind::Static),
+            "ews" => Ok(CalendarKind::Ews),
+            "google" => Ok(CalendarKind::Google),
+            _ => Err(anyhow::anyhow!("Unknown calendar kind {:?}", kind)),
+        }
+    }
+}
+
+/// A calendar that has been deleted, but not yet purged.
+#[derive(Debug, Clone, Serialize)]
+pub struct DeletedCalendar {
+    pub calendar_id: i64,
+    pub name: String,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// An upcoming instance of an event that has a reminder in a particular room.
+#[derive(Debug, Clone, Serialize)]
+pub struct UpcomingRoomEvent {
+    pub calendar_id: i64,
+    pub event_id: String,
+    pub summary: Option<String>,
+    pub location: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    pub reminder_id: i64,
+    pub minutes_before: i64,
+}
+
+/// An event instance that the user has reminders for, for their ICS feed.
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedEvent {
+    pub calendar_id: i64,
+    pub event_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub summary: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub conference_url: Option<String>,
+    pub duration_minutes: Option<i64>,
+    /// The minutes before and room of each of the user's reminders for the
+    /// instance.
+    pub reminders: Vec<(i64, String)>,
+}
+
+/// A Matrix ID mapped to a user's email.
+#[derive(Debug, Clone, Serialize)]
+pub struct MatrixId {
+    pub matrix_id: String,
+    /// Whether this is the Matrix ID to use when mentioning the user.
+    pub preferred: bool,
+}
+
+/// An additional email address for a user.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserEmail {
+    pub email: String,
+    /// Whether we've verified that the user owns the address. Only verified
+    /// addresses are used when matching attendees.
+    pub verified: bool,
+}
+
+/// An error encountered while parsing a calendar, e.g. an event we couldn't
+/// decode.
+#[derive(Debug, Clone, Serialize)]
+pub struct CalendarError {
+    /// The UID of the offending event, if we could figure it out.
+    pub uid: Option<String>,
+    pub message: String,
+}
+
 /// Basic info for an event.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Event {
     pub calendar_id: i64,
     pub event_id: String,
@@ -69,27 +302,212 @@ pub struct Event {
     pub location: Option<String>,
     pub organizer: Option<Attendee>,
     pub attendees: Vec<Attendee>,
+    /// A link to join the event's video call, if any.
+    pub conference_url: Option<String>,
+    /// How long the event lasts, if known.
+    pub duration_minutes: Option<i64>,
+    /// When the final occurrence of a recurring event is, if the recurrence
+    /// ends within the sync window.
+    pub last_occurrence: Option<DateTime<Utc>>,
 }
 
 /// A particular instance of an event, with date/time and attendees.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct EventInstance {
     pub event_id: String,
     pub date: DateTime<FixedOffset>,
     pub attendees: Vec<Attendee>,
 }
 
+/// The attendee lists of a batch of rows, flattened into one array per field
+/// so that they can be passed to `UNNEST`, which can't handle arrays of
+/// arrays.
+///
+/// `index` is the (1-based) position of the row each attendee belongs to,
+/// matching `WITH ORDINALITY`.
+struct AttendeeArrays<'a> {
+    index: Vec<i64>,
+    email: Vec<&'a str>,
+    common_name: Vec<Option<&'a str>>,
+    status: Vec<Option<&'a str>>,
+}
+
+impl<'a> AttendeeArrays<'a> {
+    fn new(rows: impl Iterator<Item = &'a [Attendee]>) -> Self {
+        let mut arrays = AttendeeArrays {
+            index: Vec::new(),
+            email: Vec::new(),
+            common_name: Vec::new(),
+            status: Vec::new(),
+        };
+
+        for (index, attendees) in (1..).zip(rows) {
+            for attendee in attendees {
+                arrays.index.push(index);
+                arrays.email.push(&attendee.email);
+                arrays.common_name.push(attendee.common_name.as_deref());
+                arrays.status.push(attendee.status.as_deref());
+            }
+        }
+
+        arrays
+    }
+}
+
 /// A reminder for a particular [`EventInstance`]
 #[derive(Debug, Clone)]
 pub struct ReminderInstance {
+    pub reminder_id: i64,
+    pub calendar_id: i64,
     pub event_id: String,
+    /// When this instance of the event starts.
+    pub timestamp: DateTime<Utc>,
     pub summary: Option<String>,
     pub description: Option<String>,
     pub location: Option<String>,
+    pub conference_url: Option<String>,
     pub template: Option<String>,
+    /// The language to render the reminder in, if set on the reminder.
+    pub locale: Option<String>,
     pub minutes_before: i64,
     pub room: String,
     pub attendees: Vec<Attendee>,
+    pub extra_attendees: Vec<String>,
+    pub excluded_attendees: Vec<String>,
+    pub threaded: bool,
+    pub poll: bool,
+    pub msgtype: Option<String>,
+    pub sender: Option<String>,
+    /// If this is a snoozed reminder being reposted, when it was snoozed
+    /// until.
+    pub snoozed_until: Option<DateTime<Utc>>,
+    pub redact_previous: bool,
+    pub attach_ics: bool,
+    pub holiday_region: Option<String>,
+    pub skip_if_declined: bool,
+    pub exclude_tentative: bool,
+    pub exclude_needs_action: bool,
+    pub live_countdown: bool,
+    /// Whether this is the final occurrence of a recurring event.
+    pub last_occurrence: bool,
+    /// Whether this is a one-off reminder that isn't for a calendar event, in
+    /// which case the calendar and event IDs are meaningless.
+    pub adhoc: bool,
+    /// Other reminders for the same event instance, room and time (e.g. set
+    /// by other users) that have been folded into this one, so that the room
+    /// only gets pinged once.
+    pub duplicate_reminder_ids: Vec<i64>,
+}
+
+impl ReminderInstance {
+    /// Whether the event has attendees, but none of them have accepted or
+    /// tentatively accepted it.
+    pub fn nobody_attending(&self) -> bool {
+        !self.attendees.is_empty() && !self.attendees.iter().any(Attendee::is_attending)
+    }
+
+    /// The event's attendees, leaving out those who have only tentatively
+    /// accepted or haven't responded if the reminder asks us to.
+    pub fn attendees_to_mention(&self) -> Vec<Attendee> {
+        self.attendees
+            .iter()
+            .filter(|attendee| !(self.exclude_tentative && attendee.is_tentative()))
+            .filter(|attendee| !(self.exclude_needs_action && attendee.needs_action()))
+            .cloned()
+            .collect()
+    }
+}
+
+/// An attempt to send a reminder, from the reminder log.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReminderLogEntry {
+    pub reminder_id: i64,
+    /// The start of the event instance the reminder was for.
+    pub timestamp: DateTime<Utc>,
+    pub attempted_at: DateTime<Utc>,
+    pub room: String,
+    /// The ID of the sent message, if it was sent.
+    pub matrix_event_id: Option<String>,
+    /// Why the reminder failed to send, if it did.
+    pub error: Option<String>,
+}
+
+/// A reminder instance that failed to send and is waiting to be retried.
+#[derive(Debug, Clone)]
+pub struct ReminderRetry {
+    pub reminder_id: i64,
+    /// The start of the event instance the reminder is for.
+    pub timestamp: DateTime<Utc>,
+    pub first_failed_at: DateTime<Utc>,
+    /// How many times we've tried to send the reminder so far.
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: String,
+}
+
+/// A URL that gets sent signed JSON payloads when the given types of events
+/// happen.
+#[derive(Debug, Clone, Serialize)]
+pub struct Webhook {
+    pub webhook_id: i64,
+    pub user_id: i64,
+    pub url: String,
+    /// The key used to sign the payloads.
+    pub secret: String,
+    pub event_types: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    /// How many payloads are waiting to be delivered, e.g. because the URL is
+    /// failing.
+    pub pending_deliveries: i64,
+    /// The error from the most recent failed delivery that's still pending.
+    pub last_error: Option<String>,
+}
+
+/// A payload that is due to be delivered to a webhook.
+#[derive(Debug, Clone)]
+pub struct WebhookDelivery {
+    pub delivery_id: i64,
+    pub webhook_id: i64,
+    pub url: String,
+    pub secret: String,
+    pub event_type: String,
+    pub payload: String,
+    /// How many times we've tried to deliver the payload so far.
+    pub attempts: i32,
+}
+
+/// A reminder that has been sent to a room, for an event that hasn't started
+/// yet.
+#[derive(Debug, Clone)]
+pub struct SentReminder {
+    pub reminder_id: i64,
+    /// The start of the event instance the reminder was for.
+    pub timestamp: DateTime<Utc>,
+    pub room_id: String,
+    pub matrix_event_id: String,
+    pub summary: Option<String>,
+    pub location: Option<String>,
+    /// The configured Matrix account that sent the reminder.
+    pub sender: Option<String>,
+}
+
+/// Whether someone has said they will attend an instance of an event.
+#[derive(Debug, Clone, Serialize)]
+pub struct Attendance {
+    /// The start of the event instance.
+    pub timestamp: DateTime<Utc>,
+    pub matrix_id: String,
+    pub attending: bool,
+}
+
+/// A response to an attendance poll.
+#[derive(Debug, Clone, Serialize)]
+pub struct PollResponse {
+    /// The start of the event instance the poll was for.
+    pub timestamp: DateTime<Utc>,
+    pub matrix_id: String,
+    pub answer: String,
+    pub responded_at: DateTime<Utc>,
 }
 
 /// A configured reminder
@@ -103,6 +521,257 @@ pub struct Reminder {
     pub minutes_before: i64,
     pub room: String,
     pub attendee_editable: bool,
+    /// Emails or Matrix IDs to always mention, even if they're not attendees.
+    pub extra_attendees: Vec<String>,
+    /// Emails or Matrix IDs to never mention, even if they're attendees.
+    pub excluded_attendees: Vec<String>,
+    /// Whether to post the reminders as replies in a thread, rather than in
+    /// the main timeline.
+    pub threaded: bool,
+    /// Whether to post an attendance poll along with the reminders.
+    pub poll: bool,
+    /// The msgtype to send reminders as, e.g. `m.notice`. Defaults to the
+    /// global config.
+    pub msgtype: Option<String>,
+    /// The name of the configured Matrix account to send the reminders from.
+    /// Defaults to the main account.
+    pub sender: Option<String>,
+    /// Whether to redact the previous reminder when sending a new one.
+    pub redact_previous: bool,
+    /// Whether to attach an .ics file of the event to reminders.
+    pub attach_ics: bool,
+    /// The region whose public holidays the reminder isn't sent on, if any.
+    pub holiday_region: Option<String>,
+    /// Whether to skip sending the reminder when the event has attendees but
+    /// none of them have accepted or tentatively accepted.
+    pub skip_if_declined: bool,
+    /// Whether to leave attendees who have only tentatively accepted out of
+    /// the mentions.
+    pub exclude_tentative: bool,
+    /// Whether to leave attendees who haven't responded out of the mentions.
+    pub exclude_needs_action: bool,
+    /// Whether to edit the reminder every minute with an updated countdown
+    /// until the event starts.
+    pub live_countdown: bool,
+    /// Whether the reminder is sent, i.e. it hasn't been paused.
+    pub enabled: bool,
+    /// The days of the week the reminder is sent on, see
+    /// [`weekday_in_mask`]. `None` means every day.
+    pub weekdays: Option<i32>,
+    /// The saved template the reminder uses, if any, which takes precedence
+    /// over `template`.
+    pub template_id: Option<i64>,
+    /// The language to render the reminder in, see [`crate::locale`]. `None`
+    /// means the room's language.
+    pub locale: Option<String>,
+}
+
+/// A weekday mask containing every day of the week.
+pub const ALL_WEEKDAYS: i32 = 0b111_1111;
+
+/// Whether the weekday is in a reminder's weekday mask, where bit 0 is Monday
+/// and `None` means every day.
+pub fn weekday_in_mask(mask: Option<i32>, weekday: Weekday) -> bool {
+    mask.is_none_or(|mask| mask & (1 << weekday.num_days_from_monday()) != 0)
+}
+
+/// Escape the `LIKE` wildcards in the text, so that it's matched literally.
+pub fn escape_like_pattern(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// A named template that can be picked for reminders.
+#[derive(Debug, Clone, Serialize)]
+pub struct SavedTemplate {
+    pub template_id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub template: String,
+    /// Whether other users can use the template too.
+    pub shared: bool,
+}
+
+/// An image uploaded for a reminder's template to include, e.g. a team logo.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReminderImage {
+    pub reminder_id: i64,
+    /// The name the template refers to the image by, as `images.<name>`.
+    pub name: String,
+    pub content_type: String,
+    /// The `mxc://` URI of the image in the media repo.
+    pub content_uri: String,
+}
+
+/// A long-lived personal access token for the API. The token itself is only
+/// shown when it's created.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub api_token_id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+}
+
+/// A token giving access to the widget for a room.
+#[derive(Debug, Clone, Serialize)]
+pub struct WidgetToken {
+    pub room: String,
+    pub token: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An account, as listed in the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserSummary {
+    pub user_id: i64,
+    pub email: String,
+    pub has_password: bool,
+    pub is_admin: bool,
+    pub num_calendars: i64,
+    pub num_reminders: i64,
+}
+
+/// Selects the columns for a [`UserSummary`] from the users table.
+const USER_SUMMARY_QUERY: &str = r#"
+    SELECT
+        user_id, email, password_hash IS NOT NULL AS has_password, is_admin,
+        (
+            SELECT count(*) FROM calendars AS c
+            WHERE c.user_id = u.user_id AND deleted_at IS NULL
+        ) AS num_calendars,
+        (
+            SELECT count(*) FROM reminders AS r
+            WHERE r.user_id = u.user_id AND deleted_at IS NULL
+        ) AS num_reminders
+    FROM users AS u
+"#;
+
+fn user_summary_from_row(row: &Row) -> Result<UserSummary, Error> {
+    Ok(UserSummary {
+        user_id: row.try_get("user_id")?,
+        email: row.try_get("email")?,
+        has_password: row.try_get("has_password")?,
+        is_admin: row.try_get("is_admin")?,
+        num_calendars: row.try_get("num_calendars")?,
+        num_reminders: row.try_get("num_reminders")?,
+    })
+}
+
+/// A reminder on one of the user's calendars, as shown in the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserReminder {
+    pub reminder_id: i64,
+    pub calendar_id: i64,
+    pub event_id: String,
+    pub summary: Option<String>,
+    pub room: String,
+    pub minutes_before: i64,
+    pub enabled: bool,
+}
+
+/// The reminders added and updated by a bulk reminder operation.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BulkReminderResult {
+    pub created: Vec<i64>,
+    pub updated: Vec<i64>,
+}
+
+/// A rule that adds reminders to the events whose summary matches the
+/// pattern.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReminderRule {
+    pub rule_id: i64,
+    pub user_id: i64,
+    /// The calendar the rule applies to, or all the user's calendars if
+    /// `None`.
+    pub calendar_id: Option<i64>,
+    /// A regex, see [`crate::rules::compile_summary_pattern`].
+    pub summary_pattern: String,
+    pub room: String,
+    pub minutes_before: i64,
+}
+
+/// A room that is told about new events appearing in a calendar.
+#[derive(Debug, Clone, Serialize)]
+pub struct EventSubscription {
+    pub subscription_id: i64,
+    pub user_id: i64,
+    pub calendar_id: i64,
+    pub room: String,
+    /// Only events whose summary matches are posted, see
+    /// [`crate::rules::compile_summary_pattern`]. `None` means all events.
+    pub summary_pattern: Option<String>,
+}
+
+/// A one-off reminder posted to a room at a given time.
+#[derive(Debug, Clone, Serialize)]
+pub struct AdhocReminder {
+    pub reminder_id: i64,
+    pub user_id: i64,
+    pub room: String,
+    pub message: String,
+    pub remind_at: DateTime<Utc>,
+}
+
+impl AdhocReminder {
+    /// Convert to a [`ReminderInstance`], so that it can be sent like any
+    /// other reminder.
+    pub fn to_instance(&self) -> ReminderInstance {
+        ReminderInstance {
+            reminder_id: self.reminder_id,
+            calendar_id: 0,
+            event_id: format!("adhoc-{}", self.reminder_id),
+            timestamp: self.remind_at,
+            summary: Some(self.message.clone()),
+            description: None,
+            location: None,
+            conference_url: None,
+            template: None,
+            locale: None,
+            minutes_before: 0,
+            room: self.room.clone(),
+            attendees: Vec::new(),
+            extra_attendees: Vec::new(),
+            excluded_attendees: Vec::new(),
+            threaded: false,
+            poll: false,
+            msgtype: None,
+            sender: None,
+            snoozed_until: None,
+            redact_previous: false,
+            attach_ics: false,
+            holiday_region: None,
+            skip_if_declined: false,
+            exclude_tentative: false,
+            exclude_needs_action: false,
+            live_countdown: false,
+            last_occurrence: false,
+            adhoc: true,
+            duplicate_reminder_ids: Vec::new(),
+        }
+    }
+}
+
+/// A user's weekly summary settings.
+#[derive(Debug, Clone, Serialize)]
+pub struct WeeklySummarySettings {
+    pub enabled: bool,
+    pub timezone: Option<String>,
+}
+
+/// A user that has opted in to weekly summaries.
+#[derive(Debug, Clone)]
+pub struct WeeklySummaryUser {
+    pub user_id: i64,
+    pub timezone: Option<String>,
+    /// The DM room to send the summary to, if we've created it.
+    pub room_id: Option<String>,
+    /// The Monday of the last week we sent a summary for.
+    pub sent_for: Option<NaiveDate>,
 }
 
 /// Result of requesting an OAuth2 access token from the DB.
@@ -129,12 +798,22 @@ pub struct OAuth2Account {
 #[derive(Debug, Clone)]
 pub struct Database {
     db_pool: PostgresPool,
+    /// Encrypts calendar passwords and OAuth2 tokens.
+    secrets: Secrets,
 }
 
 impl Database {
     /// Create a new `Database` from a PostgreSQL connection pool.
     pub fn from_pool(db_pool: PostgresPool) -> Database {
-        Database { db_pool }
+        Database {
+            db_pool,
+            secrets: Secrets::default(),
+        }
+    }
+
+    /// Encrypt secrets stored in the database with the given keys.
+    pub fn with_secrets(self, secrets: Secrets) -> Database {
+        Database { secrets, ..self }
     }
 
     async fn get_calendars_with_filter(
@@ -149,15 +828,18 @@ impl Database {
                 &format!(
                     r#"
                     SELECT DISTINCT ON (c.calendar_id)
-                        c.user_id, c.calendar_id, c.name, c.url,
-                        cp.user_name, cp.password,
+                        c.user_id, c.calendar_id, c.name, c.url, c.enabled, c.kind, c.timezone,
+                        c.ca_certificate, c.port_duplicate_reminders,
+                        c.sync_lookahead_days, c.sync_lookback_days, c.synced_until,
+                        c.ignore_transparent, c.muted_from, c.muted_until,
+                        cp.user_name, cp.password, cp.digest,
                         at.access_token
                     FROM calendars AS c
                     LEFT JOIN calendar_passwords AS cp USING (calendar_id)
                     LEFT JOIN calendar_oauth2 AS co USING (calendar_id)
                     LEFT JOIN oauth2_accounts AS ac USING (account_id)
                     LEFT JOIN oauth2_tokens AS at USING (account_id)
-                    {extra_sql}
+                    WHERE c.deleted_at IS NULL {extra_sql}
                     ORDER BY c.calendar_id, expiry DESC
                     "#,
                 ),
@@ -171,15 +853,40 @@ impl Database {
             let calendar_id = row.try_get("calendar_id")?;
             let name = row.try_get("name")?;
             let url = row.try_get("url")?;
+            let enabled = row.try_get("enabled")?;
+            let kind = CalendarKind::from_db(row.try_get("kind")?)?;
+            let timezone = row.try_get("timezone")?;
+            let ca_certificate = row.try_get("ca_certificate")?;
+            let port_duplicate_reminders = row.try_get("port_duplicate_reminders")?;
+            let sync_lookahead_days = row.try_get("sync_lookahead_days")?;
+            let sync_lookback_days = row.try_get("sync_lookback_days")?;
+            let synced_until = row.try_get("synced_until")?;
+            let ignore_transparent = row.try_get("ignore_transparent")?;
+            let muted_from = row.try_get("muted_from")?;
+            let muted_until = row.try_get("muted_until")?;
             let user_name = row.try_get("user_name")?;
-            let password = row.try_get("password")?;
+            let password: Option<String> = row.try_get("password")?;
+            let password = password
+                .map(|password| self.secrets.decrypt(&password))
+                .transpose()?;
+            let digest: Option<bool> = row.try_get("digest")?;
 
-            let access_token = row.try_get("access_token")?;
+            let access_token: Option<String> = row.try_get("access_token")?;
+            let access_token = access_token
+                .map(|access_token| self.secrets.decrypt(&access_token))
+                .transpose()?;
 
             let authentication = if let (Some(user_name), Some(password)) = (user_name, password) {
-                CalendarAuthentication::Basic {
-                    user_name,
-                    password,
+                if digest.unwrap_or(false) {
+                    CalendarAuthentication::Digest {
+                        user_name,
+                        password,
+                    }
+                } else {
+                    CalendarAuthentication::Basic {
+                        user_name,
+                        password,
+                    }
                 }
             } else if let Some(access_token) = access_token {
                 CalendarAuthentication::Bearer { access_token }
@@ -192,6 +899,17 @@ impl Database {
                 calendar_id,
                 name,
                 url,
+                enabled,
+                kind,
+                timezone,
+                ca_certificate,
+                port_duplicate_reminders,
+                sync_lookahead_days,
+                sync_lookback_days,
+                synced_until,
+                ignore_transparent,
+                muted_from,
+                muted_until,
                 authentication,
             })
         }
@@ -208,7 +926,7 @@ impl Database {
     /// Get all calendars for a given user.
     pub async fn get_calendars_for_user(&self, user_id: i64) -> Result<Vec<Calendar>, Error> {
         let calendars = self
-            .get_calendars_with_filter("WHERE c.user_id = $1", &[&user_id])
+            .get_calendars_with_filter("AND c.user_id = $1", &[&user_id])
             .await?;
 
         Ok(calendars)
@@ -217,7 +935,7 @@ impl Database {
     /// Get a calendar by ID.
     pub async fn get_calendar(&self, calendar_id: i64) -> Result<Option<Calendar>, Error> {
         let mut calendars = self
-            .get_calendars_with_filter("WHERE c.calendar_id = $1", &[&calendar_id])
+            .get_calendars_with_filter("AND c.calendar_id = $1", &[&calendar_id])
             .await?;
 
         Ok(calendars.pop())
@@ -229,8 +947,10 @@ impl Database {
         calendar_id: i64,
         name: String,
         url: String,
+        kind: CalendarKind,
         user_name: Option<String>,
         password: Option<String>,
+        digest: bool,
     ) -> Result<(), Error> {
         let mut db_conn = self.db_pool.get().await?;
 
@@ -239,21 +959,34 @@ impl Database {
         txn.execute(
             r#"
                     UPDATE calendars
-                    SET name = $2, url = $3
+                    SET name = $2, url = $3, kind = $4, sync_token = NULL
+                    WHERE calendar_id = $1
+                "#,
+            &[&calendar_id, &name, &url, &kind.as_str()],
+        )
+        .await?;
+
+        // The calendar may have changed, so we need to refetch everything.
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_objects
                     WHERE calendar_id = $1
                 "#,
-            &[&calendar_id, &name, &url],
+            &[&calendar_id],
         )
         .await?;
 
         if let (Some(user_name), Some(password)) = (user_name, password) {
+            let password = self.secrets.encrypt(&password)?;
+
             txn.execute(
                 r#"
-                        UPDATE calendar_passwords
-                        SET user_name = $2, password = $3
-                        WHERE calendar_id = $1
+                        INSERT INTO calendar_passwords (calendar_id, user_name, password, digest)
+                        VALUES ($1, $2, $3, $4)
+                        ON CONFLICT (calendar_id) DO UPDATE
+                        SET user_name = $2, password = $3, digest = $4
                     "#,
-                &[&calendar_id, &user_name, &password],
+                &[&calendar_id, &user_name, &password, &digest],
             )
             .await?;
         } else {
@@ -272,963 +1005,5094 @@ impl Database {
         Ok(())
     }
 
-    /// Delete a calendar.
-    pub async fn delete_calendar(&self, calendar_id: i64) -> Result<(), Error> {
-        let mut db_conn = self.db_pool.get().await?;
+    /// Set the timezone to assume for the calendar's floating events.
+    pub async fn set_calendar_timezone(
+        &self,
+        calendar_id: i64,
+        timezone: Option<&str>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let txn = db_conn.transaction().await?;
+        db_conn
+            .execute(
+                "UPDATE calendars SET timezone = $2 WHERE calendar_id = $1",
+                &[&calendar_id, &timezone],
+            )
+            .await?;
 
-        txn.execute(
-            r#"
-                    DELETE FROM next_dates
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
+        Ok(())
+    }
 
-        txn.execute(
-            r#"
-                    DELETE FROM reminders
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
+    /// Set the extra root certificates to trust when fetching the calendar.
+    pub async fn set_calendar_ca_certificate(
+        &self,
+        calendar_id: i64,
+        ca_certificate: Option<&str>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        txn.execute(
-            r#"
-                    DELETE FROM events
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
-
-        txn.execute(
-            r#"
-                    DELETE FROM calendar_passwords
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
-
-        txn.execute(
-            r#"
-                    DELETE FROM calendar_oauth2
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
-
-        txn.execute(
-            r#"
-                    DELETE FROM calendars
-                    WHERE calendar_id = $1
-                "#,
-            &[&calendar_id],
-        )
-        .await?;
-
-        txn.commit().await?;
+        db_conn
+            .execute(
+                "UPDATE calendars SET ca_certificate = $2 WHERE calendar_id = $1",
+                &[&calendar_id, &ca_certificate],
+            )
+            .await?;
 
         Ok(())
     }
 
-    /// Add a new calendar.
-    pub async fn add_calendar_basic_auth(
+    /// Set whether to port reminders onto events that look like they replace
+    /// an old one.
+    pub async fn set_calendar_port_duplicate_reminders(
         &self,
-        user_id: i64,
-        name: String,
-        url: String,
-        user_name: Option<String>,
-        password: Option<String>,
-    ) -> Result<i64, Error> {
-        let mut db_conn = self.db_pool.get().await?;
-
-        let txn = db_conn.transaction().await?;
+        calendar_id: i64,
+        port_duplicate_reminders: bool,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let row = txn
-            .query_one(
-                r#"
-                    INSERT INTO calendars (user_id, name, url)
-                    VALUES ($1, $2, $3)
-                    RETURNING calendar_id
-                "#,
-                &[&user_id, &name, &url],
+        db_conn
+            .execute(
+                "UPDATE calendars SET port_duplicate_reminders = $2 WHERE calendar_id = $1",
+                &[&calendar_id, &port_duplicate_reminders],
             )
             .await?;
 
-        let calendar_id = row.try_get(0)?;
+        Ok(())
+    }
 
-        if let (Some(user_name), Some(password)) = (user_name, password) {
-            txn.execute(
+    /// Set how many days ahead to sync the calendar's events for, and back to
+    /// fetch them from, or `None` to use the config's defaults.
+    pub async fn set_calendar_sync_window_days(
+        &self,
+        calendar_id: i64,
+        lookahead_days: Option<i64>,
+        lookback_days: Option<i64>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
                 r#"
-                    INSERT INTO calendar_passwords (calendar_id, user_name, password)
-                    VALUES ($1, $2, $3)
+                    UPDATE calendars
+                    SET sync_lookahead_days = $2, sync_lookback_days = $3
+                    WHERE calendar_id = $1
                 "#,
-                &[&calendar_id, &user_name, &password],
+                &[&calendar_id, &lookahead_days, &lookback_days],
             )
             .await?;
-        }
 
-        txn.commit().await?;
-
-        Ok(calendar_id)
+        Ok(())
     }
 
-    /// Add a new OAuth2.
-    pub async fn add_calendar_oauth2(
+    /// Set whether to skip the calendar's events that are marked as free.
+    pub async fn set_calendar_ignore_transparent(
         &self,
-        user_id: i64,
-        name: String,
-        url: String,
-        account_id: i64,
-    ) -> Result<i64, Error> {
-        let mut db_conn = self.db_pool.get().await?;
-
-        let txn = db_conn.transaction().await?;
+        calendar_id: i64,
+        ignore_transparent: bool,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let row = txn
-            .query_one(
-                r#"
-                    INSERT INTO calendars (user_id, name, url)
-                    VALUES ($1, $2, $3)
-                    RETURNING calendar_id
-                "#,
-                &[&user_id, &name, &url],
+        db_conn
+            .execute(
+                "UPDATE calendars SET ignore_transparent = $2 WHERE calendar_id = $1",
+                &[&calendar_id, &ignore_transparent],
             )
             .await?;
 
-        let calendar_id = row.try_get(0)?;
-
-        txn.execute(
-            r#"
-                INSERT INTO calendar_oauth2 (calendar_id, account_id)
-                VALUES ($1, $2)
-            "#,
-            &[&calendar_id, &account_id],
-        )
-        .await?;
-
-        txn.commit().await?;
-
-        Ok(calendar_id)
+        Ok(())
     }
 
-    /// Insert events and the next instances of the event.
-    ///
-    /// Not all event instances are stored (since they might be infinite),
-    /// instead only the instances in the next, say, month are typically stored.
-    pub async fn insert_events(
+    /// Record the period that the calendar's event instances were last synced
+    /// for.
+    pub async fn set_calendar_synced_window(
         &self,
         calendar_id: i64,
-        events: Vec<Event>,
-        instances: Vec<EventInstance>,
+        from: DateTime<Utc>,
+        until: DateTime<Utc>,
     ) -> Result<(), Error> {
-        let mut db_conn = self.db_pool.get().await?;
-        let txn = db_conn.transaction().await?;
-
-        futures::future::try_join_all(events.iter().map(|event| {
-            txn.execute_raw(
-                r#"
-                    INSERT INTO events (calendar_id, event_id, summary, description, location, organizer, attendees)
-                    VALUES ($1, $2, $3, $4, $5, $6, $7)
-                    ON CONFLICT (calendar_id, event_id)
-                    DO UPDATE SET
-                        summary = EXCLUDED.summary,
-                        description = EXCLUDED.description,
-                        location = EXCLUDED.location,
-                        attendees = EXCLUDED.attendees
-                "#,
-                vec![
-                    &calendar_id as &dyn ToSql,
-                    &event.event_id,
-                    &event.summary,
-                    &event.description,
-                    &event.location,
-                    &event.organizer,
-                    &event.attendees,
-                ],
-            )
-        }))
-        .await?;
-
-        txn.execute(
-            "DELETE FROM next_dates WHERE calendar_id = $1",
-            &[&calendar_id],
-        )
-        .await?;
+        let db_conn = self.db_pool.get().await?;
 
-        futures::future::try_join_all(instances.iter().map(|instance| {
-            txn.execute_raw(
-                r#"
-                            INSERT INTO next_dates (calendar_id, event_id, timestamp, attendees)
-                            VALUES ($1, $2, $3, $4)
-                        "#,
-                vec![
-                    &calendar_id as &dyn ToSql,
-                    &instance.event_id,
-                    &instance.date,
-                    &instance.attendees,
-                ],
+        db_conn
+            .execute(
+                "UPDATE calendars SET synced_from = $2, synced_until = $3 WHERE calendar_id = $1",
+                &[&calendar_id, &from, &until],
             )
-        }))
-        .await?;
-
-        txn.commit().await?;
+            .await?;
 
         Ok(())
     }
 
-    /// Persist a new reminder.
-    pub async fn add_reminder(&self, reminder: &Reminder) -> Result<(), Error> {
+    /// Mark a calendar as deleted.
+    ///
+    /// The calendar and its reminders are kept until they get purged by
+    /// [`Database::purge_deleted`], so that the deletion can be undone with
+    /// [`Database::restore_calendar`].
+    pub async fn delete_calendar(&self, calendar_id: i64) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
         db_conn
             .execute(
                 r#"
-                    INSERT INTO reminders (
-                        user_id, calendar_id, event_id, room,
-                        minutes_before, template, attendee_editable
-                    )
-                    VALUES ($1, $2, $3, $4, $5, $6, $7)
-            "#,
-                &[
-                    &reminder.user_id,
-                    &reminder.calendar_id,
-                    &reminder.event_id,
-                    &reminder.room,
-                    &reminder.minutes_before,
-                    &reminder.template,
-                    &reminder.attendee_editable,
-                ],
+                    UPDATE calendars
+                    SET deleted_at = now()
+                    WHERE calendar_id = $1 AND deleted_at IS NULL
+                "#,
+                &[&calendar_id],
             )
             .await?;
 
         Ok(())
     }
 
-    /// Update an existing reminder.
-    pub async fn update_reminder(
+    /// Mute the calendar's reminders between the given times, or unmute them
+    /// if `muted_until` is `None`.
+    pub async fn set_calendar_muted(
         &self,
         calendar_id: i64,
-        reminder_id: i64,
-        room: &'_ str,
-        minutes_before: i64,
-        template: Option<&'_ str>,
-        attendee_editable: bool,
+        muted_from: Option<DateTime<Utc>>,
+        muted_until: Option<DateTime<Utc>>,
     ) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
         db_conn
             .execute(
                 r#"
-                    UPDATE reminders
-                    SET room = $1, minutes_before = $2, template = $3,
-                    attendee_editable = $4
-                    WHERE calendar_id = $5 AND reminder_id = $6
-            "#,
-                &[
-                    &room,
-                    &minutes_before,
-                    &template,
-                    &attendee_editable,
-                    &calendar_id,
-                    &reminder_id,
-                ],
+                    UPDATE calendars
+                    SET muted_from = $2, muted_until = $3
+                    WHERE calendar_id = $1
+                "#,
+                &[&calendar_id, &muted_from, &muted_until],
             )
             .await?;
 
         Ok(())
     }
 
-    /// Delete a specific reminder.
-    pub async fn delete_reminder_in_calendar(
-        &self,
-        calendar_id: i64,
-        reminder_id: i64,
-    ) -> Result<(), Error> {
+    /// Pause or resume syncing a calendar and sending its reminders.
+    pub async fn set_calendar_enabled(&self, calendar_id: i64, enabled: bool) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
         db_conn
             .execute(
                 r#"
-                    DELETE FROM reminders
-                    WHERE calendar_id = $1 AND reminder_id = $2
-            "#,
-                &[&calendar_id, &reminder_id],
+                    UPDATE calendars
+                    SET enabled = $2
+                    WHERE calendar_id = $1
+                "#,
+                &[&calendar_id, &enabled],
             )
             .await?;
 
         Ok(())
     }
 
-    /// Get the reminders needed to be sent out.
-    pub async fn get_next_reminders(
-        &self,
-    ) -> Result<VecDeque<(DateTime<Utc>, ReminderInstance)>, Error> {
+    /// Undo the deletion of a calendar. Returns false if the user has no such
+    /// deleted calendar.
+    pub async fn restore_calendar(&self, user_id: i64, calendar_id: i64) -> Result<bool, Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let rows = db_conn
-            .query(
+        let count = db_conn
+            .execute(
                 r#"
-                    SELECT event_id, summary, description, location, timestamp, room, minutes_before, template, i.attendees
-                    FROM reminders
-                    INNER JOIN events USING (calendar_id, event_id)
-                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
-                    WHERE timestamp > now() + '-5 minutes'
-                    ORDER BY timestamp - make_interval(mins => minutes_before::int)
+                    UPDATE calendars
+                    SET deleted_at = NULL
+                    WHERE user_id = $1 AND calendar_id = $2 AND deleted_at IS NOT NULL
                 "#,
-                &[],
+                &[&user_id, &calendar_id],
             )
             .await?;
 
-        let mut reminders = VecDeque::with_capacity(rows.len());
-        let now = Utc::now();
-
-        for row in rows {
-            let event_id: String = row.get(0);
-            let summary: Option<String> = row.get(1);
-            let description: Option<String> = row.get(2);
-            let location: Option<String> = row.get(3);
-            let timestamp: DateTime<Utc> = row.get(4);
-            let room: String = row.get(5);
-            let minutes_before: i64 = row.get(6);
-            let template: Option<String> = row.get(7);
-            let attendees: Vec<Attendee> = row.get(8);
-
-            let reminder_time = timestamp - Duration::minutes(minutes_before);
-            if reminder_time < now {
-                // XXX: There's technically a race here if we reload the
-                // reminders just as we're about to send out a reminder.
-                info!(now = ?now, reminder_time =?reminder_time, event_id = event_id.deref(), "Ignoring old reminder");
-                continue;
-            }
-
-            let reminder = ReminderInstance {
-                event_id,
-                summary,
-                description,
-                location,
-                template,
-                minutes_before,
-                room,
-                attendees,
-            };
-
-            reminders.push_back((reminder_time, reminder));
-        }
-
-        reminders.make_contiguous().sort_by_key(|(t, _)| *t);
-
-        Ok(reminders)
+        Ok(count > 0)
     }
 
-    /// Get all events in a calendar
-    pub async fn get_events_in_calendar(
+    /// Get the user's calendars that have been deleted but not yet purged.
+    pub async fn get_deleted_calendars_for_user(
         &self,
-        calendar_id: i64,
-    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
+        user_id: i64,
+    ) -> Result<Vec<DeletedCalendar>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let rows = db_conn
             .query(
                 r#"
-                    SELECT DISTINCT ON (event_id) event_id, summary, description, location, timestamp,
-                        organizer, e.attendees AS event_attendees, i.attendees AS instance_attendees
-                    FROM events AS e
-                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
-                    WHERE calendar_id = $1 AND timestamp > now()
-                    ORDER BY event_id, timestamp
+                    SELECT calendar_id, name, deleted_at
+                    FROM calendars
+                    WHERE user_id = $1 AND deleted_at IS NOT NULL
+                    ORDER BY deleted_at DESC
                 "#,
-                &[&calendar_id],
+                &[&user_id],
             )
             .await?;
 
-        let mut events: Vec<(Event, Vec<EventInstance>)> = Vec::with_capacity(rows.len());
-
+        let mut calendars = Vec::with_capacity(rows.len());
         for row in rows {
-            let event_id: String = row.try_get("event_id")?;
-            let summary = row.try_get("summary")?;
-            let description = row.try_get("description")?;
-            let location = row.try_get("location")?;
-            let date = row.try_get("timestamp")?;
-            let organizer = row.try_get("organizer")?;
+            calendars.push(DeletedCalendar {
+                calendar_id: row.try_get("calendar_id")?,
+                name: row.try_get("name")?,
+                deleted_at: row.try_get("deleted_at")?,
+            });
+        }
+
+        Ok(calendars)
+    }
+
+    /// Permanently delete all calendars and reminders that were deleted before
+    /// the given time.
+    ///
+    /// Returns the number of calendars and reminders that were purged.
+    pub async fn purge_deleted(&self, before: DateTime<Utc>) -> Result<(u64, u64), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM next_dates
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        let num_reminders = txn
+            .execute(
+                r#"
+                    DELETE FROM reminders
+                    WHERE deleted_at < $1 OR calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+                &[&before],
+            )
+            .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM reminder_images
+                    WHERE reminder_id NOT IN (SELECT reminder_id FROM reminders)
+                "#,
+            &[],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM poll_responses
+                    WHERE poll_event_id IN (
+                        SELECT poll_event_id FROM reminder_polls
+                        WHERE reminder_id NOT IN (SELECT reminder_id FROM reminders)
+                    )
+                "#,
+            &[],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM reminder_polls
+                    WHERE reminder_id NOT IN (SELECT reminder_id FROM reminders)
+                "#,
+            &[],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM attendance
+                    WHERE reminder_id NOT IN (SELECT reminder_id FROM reminders)
+                "#,
+            &[],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM last_sent_reminders
+                    WHERE reminder_id NOT IN (SELECT reminder_id FROM reminders)
+                "#,
+            &[],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM events
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_errors
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_passwords
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_oauth2
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM reminder_rules
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM event_subscriptions
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_files
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                    DELETE FROM calendar_objects
+                    WHERE calendar_id IN (
+                        SELECT calendar_id FROM calendars WHERE deleted_at < $1
+                    )
+                "#,
+            &[&before],
+        )
+        .await?;
+
+        let num_calendars = txn
+            .execute(
+                r#"
+                    DELETE FROM calendars
+                    WHERE deleted_at < $1
+                "#,
+                &[&before],
+            )
+            .await?;
+
+        txn.commit().await?;
+
+        Ok((num_calendars, num_reminders))
+    }
+
+    /// Add a new calendar.
+    pub async fn add_calendar_basic_auth(
+        &self,
+        user_id: i64,
+        name: String,
+        url: String,
+        kind: CalendarKind,
+        user_name: Option<String>,
+        password: Option<String>,
+        digest: bool,
+    ) -> Result<i64, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let row = txn
+            .query_one(
+                r#"
+                    INSERT INTO calendars (user_id, name, url, kind)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING calendar_id
+                "#,
+                &[&user_id, &name, &url, &kind.as_str()],
+            )
+            .await?;
+
+        let calendar_id = row.try_get(0)?;
+
+        if let (Some(user_name), Some(password)) = (user_name, password) {
+            let password = self.secrets.encrypt(&password)?;
+
+            txn.execute(
+                r#"
+                    INSERT INTO calendar_passwords (calendar_id, user_name, password, digest)
+                    VALUES ($1, $2, $3, $4)
+                "#,
+                &[&calendar_id, &user_name, &password, &digest],
+            )
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(calendar_id)
+    }
+
+    /// Add a new static calendar from an uploaded .ics file.
+    pub async fn add_static_calendar(
+        &self,
+        user_id: i64,
+        name: String,
+        body: String,
+    ) -> Result<i64, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let row = txn
+            .query_one(
+                r#"
+                    INSERT INTO calendars (user_id, name, url, kind)
+                    VALUES ($1, $2, '', $3)
+                    RETURNING calendar_id
+                "#,
+                &[&user_id, &name, &CalendarKind::Static.as_str()],
+            )
+            .await?;
+
+        let calendar_id = row.try_get(0)?;
+
+        txn.execute(
+            r#"
+                INSERT INTO calendar_files (calendar_id, body)
+                VALUES ($1, $2)
+            "#,
+            &[&calendar_id, &body],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(calendar_id)
+    }
+
+    /// Get the uploaded .ics file of a static calendar.
+    pub async fn get_calendar_file(&self, calendar_id: i64) -> Result<Option<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT body FROM calendar_files WHERE calendar_id = $1",
+                &[&calendar_id],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    /// Get the CalDAV sync token of the calendar, and when we last did a full
+    /// sync.
+    pub async fn get_calendar_sync_token(
+        &self,
+        calendar_id: i64,
+    ) -> Result<Option<(String, DateTime<Utc>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT sync_token, last_full_sync FROM calendars
+                    WHERE calendar_id = $1
+                        AND sync_token IS NOT NULL AND last_full_sync IS NOT NULL
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(Some((row.try_get(0)?, row.try_get(1)?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Replace the stored objects of a CalDAV calendar after fetching all of
+    /// them.
+    pub async fn replace_calendar_objects(
+        &self,
+        calendar_id: i64,
+        sync_token: Option<&str>,
+        objects: &[(String, String)],
+    ) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        txn.execute(
+            "DELETE FROM calendar_objects WHERE calendar_id = $1",
+            &[&calendar_id],
+        )
+        .await?;
+
+        for (href, body) in objects {
+            txn.execute(
+                r#"
+                    INSERT INTO calendar_objects (calendar_id, href, body)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (calendar_id, href) DO UPDATE SET body = EXCLUDED.body
+                "#,
+                &[&calendar_id, href, body],
+            )
+            .await?;
+        }
+
+        txn.execute(
+            r#"
+                UPDATE calendars SET sync_token = $2, last_full_sync = now()
+                WHERE calendar_id = $1
+            "#,
+            &[&calendar_id, &sync_token],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Apply the changes from an incremental sync of a CalDAV calendar.
+    pub async fn update_calendar_objects(
+        &self,
+        calendar_id: i64,
+        sync_token: Option<&str>,
+        changed: &[(String, String)],
+        removed: &[String],
+    ) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        for (href, body) in changed {
+            txn.execute(
+                r#"
+                    INSERT INTO calendar_objects (calendar_id, href, body)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (calendar_id, href) DO UPDATE SET body = EXCLUDED.body
+                "#,
+                &[&calendar_id, href, body],
+            )
+            .await?;
+        }
+
+        txn.execute(
+            "DELETE FROM calendar_objects WHERE calendar_id = $1 AND href = ANY($2)",
+            &[&calendar_id, &removed],
+        )
+        .await?;
+
+        txn.execute(
+            "UPDATE calendars SET sync_token = $2 WHERE calendar_id = $1",
+            &[&calendar_id, &sync_token],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the stored objects of a CalDAV calendar.
+    pub async fn get_calendar_objects(&self, calendar_id: i64) -> Result<Vec<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                "SELECT body FROM calendar_objects WHERE calendar_id = $1",
+                &[&calendar_id],
+            )
+            .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| row.try_get(0))
+            .collect::<Result<_, _>>()?)
+    }
+
+    /// Add a new OAuth2.
+    pub async fn add_calendar_oauth2(
+        &self,
+        user_id: i64,
+        name: String,
+        url: String,
+        account_id: i64,
+    ) -> Result<i64, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let row = txn
+            .query_one(
+                r#"
+                    INSERT INTO calendars (user_id, name, url, kind)
+                    VALUES ($1, $2, $3, 'google')
+                    RETURNING calendar_id
+                "#,
+                &[&user_id, &name, &url],
+            )
+            .await?;
+
+        let calendar_id = row.try_get(0)?;
+
+        txn.execute(
+            r#"
+                INSERT INTO calendar_oauth2 (calendar_id, account_id)
+                VALUES ($1, $2)
+            "#,
+            &[&calendar_id, &account_id],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(calendar_id)
+    }
+
+    /// Insert events and the next instances of the event.
+    ///
+    /// Not all event instances are stored (since they might be infinite),
+    /// instead only the instances in the next, say, month are typically stored.
+    ///
+    /// Only the differences from what is already stored are written, as
+    /// calendars rarely change between syncs, and they're written in a few
+    /// batched queries rather than one per row.
+    ///
+    /// Returns the IDs of the events that we hadn't stored before.
+    pub async fn insert_events(
+        &self,
+        calendar_id: i64,
+        events: Vec<Event>,
+        instances: Vec<EventInstance>,
+    ) -> Result<Vec<String>, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+        let txn = db_conn.transaction().await?;
+
+        let rows = txn
+            .query(
+                r#"
+                    SELECT event_id, summary, description, location, organizer, attendees,
+                        conference_url, duration_minutes, last_occurrence
+                    FROM events
+                    WHERE calendar_id = $1
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        let mut existing_events = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let event = Event {
+                calendar_id,
+                event_id: row.try_get("event_id")?,
+                summary: row.try_get("summary")?,
+                description: row.try_get("description")?,
+                location: row.try_get("location")?,
+                organizer: row.try_get("organizer")?,
+                attendees: row.try_get("attendees")?,
+                conference_url: row.try_get("conference_url")?,
+                duration_minutes: row.try_get("duration_minutes")?,
+                last_occurrence: row.try_get("last_occurrence")?,
+            };
+            existing_events.insert(event.event_id.clone(), event);
+        }
+
+        // A calendar can have more than one object with the same UID, but an
+        // upsert can't touch the same row twice, so the last one wins.
+        let last_index: HashMap<&str, usize> = events
+            .iter()
+            .enumerate()
+            .map(|(idx, event)| (event.event_id.as_str(), idx))
+            .collect();
+
+        let changed_events = events
+            .iter()
+            .enumerate()
+            .filter(|(idx, event)| last_index[event.event_id.as_str()] == *idx)
+            .map(|(_, event)| event)
+            .filter(|event| existing_events.get(&event.event_id) != Some(*event))
+            .collect_vec();
+
+        let new_event_ids = changed_events
+            .iter()
+            .filter(|event| !existing_events.contains_key(&event.event_id))
+            .map(|event| event.event_id.clone())
+            .collect_vec();
+
+        if !changed_events.is_empty() {
+            let attendees = AttendeeArrays::new(changed_events.iter().map(|e| &e.attendees[..]));
+            // Multi-argument `UNNEST` expands composite types into their
+            // fields, so the organizer is passed as parallel arrays too.
+            let organizers = changed_events
+                .iter()
+                .map(|e| e.organizer.as_ref())
+                .collect_vec();
+
+            txn.execute(
+                r#"
+                    INSERT INTO events (calendar_id, event_id, summary, description, location, organizer, attendees, conference_url, duration_minutes, last_occurrence)
+                    SELECT
+                        $1, e.event_id, e.summary, e.description, e.location,
+                        CASE WHEN e.organizer_email IS NOT NULL THEN
+                            ROW(e.organizer_email, e.organizer_common_name, e.organizer_status)::"Attendee"
+                        END,
+                        COALESCE(a.attendees, '{}'), e.conference_url, e.duration_minutes,
+                        e.last_occurrence
+                    FROM UNNEST(
+                        $2::text[], $3::text[], $4::text[], $5::text[],
+                        $6::text[], $7::text[], $8::text[],
+                        $9::text[], $10::bigint[], $11::timestamptz[]
+                    ) WITH ORDINALITY AS e(
+                        event_id, summary, description, location,
+                        organizer_email, organizer_common_name, organizer_status,
+                        conference_url, duration_minutes, last_occurrence, idx
+                    )
+                    LEFT JOIN (
+                        SELECT idx, array_agg(ROW(email, common_name, status)::"Attendee" ORDER BY pos) AS attendees
+                        FROM UNNEST($12::bigint[], $13::text[], $14::text[], $15::text[])
+                            WITH ORDINALITY AS a(idx, email, common_name, status, pos)
+                        GROUP BY idx
+                    ) AS a USING (idx)
+                    ON CONFLICT (calendar_id, event_id)
+                    DO UPDATE SET
+                        summary = EXCLUDED.summary,
+                        description = EXCLUDED.description,
+                        location = EXCLUDED.location,
+                        organizer = EXCLUDED.organizer,
+                        attendees = EXCLUDED.attendees,
+                        conference_url = EXCLUDED.conference_url,
+                        duration_minutes = EXCLUDED.duration_minutes,
+                        last_occurrence = EXCLUDED.last_occurrence
+                "#,
+                &[
+                    &calendar_id,
+                    &changed_events.iter().map(|e| &e.event_id).collect_vec(),
+                    &changed_events.iter().map(|e| &e.summary).collect_vec(),
+                    &changed_events.iter().map(|e| &e.description).collect_vec(),
+                    &changed_events.iter().map(|e| &e.location).collect_vec(),
+                    &organizers.iter().map(|o| o.map(|o| &o.email)).collect_vec(),
+                    &organizers
+                        .iter()
+                        .map(|o| o.and_then(|o| o.common_name.as_deref()))
+                        .collect_vec(),
+                    &organizers
+                        .iter()
+                        .map(|o| o.and_then(|o| o.status.as_deref()))
+                        .collect_vec(),
+                    &changed_events.iter().map(|e| &e.conference_url).collect_vec(),
+                    &changed_events.iter().map(|e| e.duration_minutes).collect_vec(),
+                    &changed_events.iter().map(|e| e.last_occurrence).collect_vec(),
+                    &attendees.index,
+                    &attendees.email,
+                    &attendees.common_name,
+                    &attendees.status,
+                ],
+            )
+            .await?;
+        }
+
+        let rows = txn
+            .query(
+                r#"
+                    SELECT event_id, timestamp, attendees
+                    FROM next_dates
+                    WHERE calendar_id = $1
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        let mut existing_instances = HashSet::with_capacity(rows.len());
+        for row in rows {
+            existing_instances.insert(EventInstance {
+                event_id: row.try_get("event_id")?,
+                date: row.try_get("timestamp")?,
+                attendees: row.try_get("attendees")?,
+            });
+        }
+
+        let new_instances: HashSet<&EventInstance> = instances.iter().collect();
+
+        let removed_instances = existing_instances
+            .iter()
+            .filter(|instance| !new_instances.contains(instance))
+            .collect_vec();
+
+        let added_instances = new_instances
+            .iter()
+            .filter(|instance| !existing_instances.contains(**instance))
+            .collect_vec();
+
+        if !removed_instances.is_empty() {
+            txn.execute(
+                r#"
+                    DELETE FROM next_dates AS n
+                    USING UNNEST($2::text[], $3::timestamptz[]) AS r(event_id, ts)
+                    WHERE n.calendar_id = $1 AND n.event_id = r.event_id AND n.timestamp = r.ts
+                "#,
+                &[
+                    &calendar_id,
+                    &removed_instances.iter().map(|i| &i.event_id).collect_vec(),
+                    &removed_instances.iter().map(|i| &i.date).collect_vec(),
+                ],
+            )
+            .await?;
+        }
+
+        if !added_instances.is_empty() {
+            let attendees = AttendeeArrays::new(added_instances.iter().map(|i| &i.attendees[..]));
+
+            txn.execute(
+                r#"
+                    INSERT INTO next_dates (calendar_id, event_id, timestamp, attendees)
+                    SELECT $1, i.event_id, i.ts, COALESCE(a.attendees, '{}')
+                    FROM UNNEST($2::text[], $3::timestamptz[])
+                        WITH ORDINALITY AS i(event_id, ts, idx)
+                    LEFT JOIN (
+                        SELECT idx, array_agg(ROW(email, common_name, status)::"Attendee" ORDER BY pos) AS attendees
+                        FROM UNNEST($4::bigint[], $5::text[], $6::text[], $7::text[])
+                            WITH ORDINALITY AS a(idx, email, common_name, status, pos)
+                        GROUP BY idx
+                    ) AS a USING (idx)
+                "#,
+                &[
+                    &calendar_id,
+                    &added_instances.iter().map(|i| &i.event_id).collect_vec(),
+                    &added_instances.iter().map(|i| &i.date).collect_vec(),
+                    &attendees.index,
+                    &attendees.email,
+                    &attendees.common_name,
+                    &attendees.status,
+                ],
+            )
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        info!(
+            calendar_id,
+            changed_events = changed_events.len(),
+            removed_instances = removed_instances.len(),
+            added_instances = added_instances.len(),
+            "Persisted changes to events"
+        );
+
+        Ok(new_event_ids)
+    }
+
+    /// Replace the stored errors from the most recent sync of the calendar.
+    pub async fn set_calendar_errors(
+        &self,
+        calendar_id: i64,
+        errors: &[CalendarError],
+    ) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+        let txn = db_conn.transaction().await?;
+
+        txn.execute(
+            "DELETE FROM calendar_errors WHERE calendar_id = $1",
+            &[&calendar_id],
+        )
+        .await?;
+
+        futures::future::try_join_all(errors.iter().map(|error| {
+            txn.execute_raw(
+                r#"
+                    INSERT INTO calendar_errors (calendar_id, uid, message)
+                    VALUES ($1, $2, $3)
+                "#,
+                vec![&calendar_id as &dyn ToSql, &error.uid, &error.message],
+            )
+        }))
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the errors from the most recent sync of the calendar.
+    pub async fn get_calendar_errors(&self, calendar_id: i64) -> Result<Vec<CalendarError>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT uid, message FROM calendar_errors
+                    WHERE calendar_id = $1
+                    ORDER BY uid
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        let mut errors = Vec::with_capacity(rows.len());
+        for row in rows {
+            errors.push(CalendarError {
+                uid: row.try_get("uid")?,
+                message: row.try_get("message")?,
+            });
+        }
+
+        Ok(errors)
+    }
+
+    /// Persist a new reminder, returning its ID.
+    pub async fn add_reminder(&self, reminder: &Reminder) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO reminders (
+                        user_id, calendar_id, event_id, room,
+                        minutes_before, template, attendee_editable,
+                        extra_attendees, excluded_attendees, threaded, poll, msgtype, sender, redact_previous, attach_ics,
+                        holiday_region, skip_if_declined, enabled, weekdays, template_id, locale,
+                        exclude_tentative, exclude_needs_action, live_countdown
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20, $21, $22, $23, $24)
+                    RETURNING reminder_id
+            "#,
+                &[
+                    &reminder.user_id,
+                    &reminder.calendar_id,
+                    &reminder.event_id,
+                    &reminder.room,
+                    &reminder.minutes_before,
+                    &reminder.template,
+                    &reminder.attendee_editable,
+                    &reminder.extra_attendees,
+                    &reminder.excluded_attendees,
+                    &reminder.threaded,
+                    &reminder.poll,
+                    &reminder.msgtype,
+                    &reminder.sender,
+                    &reminder.redact_previous,
+                    &reminder.attach_ics,
+                    &reminder.holiday_region,
+                    &reminder.skip_if_declined,
+                    &reminder.enabled,
+                    &reminder.weekdays,
+                    &reminder.template_id,
+                    &reminder.locale,
+                    &reminder.exclude_tentative,
+                    &reminder.exclude_needs_action,
+                    &reminder.live_countdown,
+                ],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Update an existing reminder.
+    ///
+    /// Only the settings of the reminder are updated, i.e. it can't be moved
+    /// to a different event or user.
+    pub async fn update_reminder(&self, reminder: &Reminder) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE reminders
+                    SET room = $1, minutes_before = $2, template = $3,
+                    attendee_editable = $4, extra_attendees = $5,
+                    excluded_attendees = $6, threaded = $9, poll = $10, msgtype = $11, sender = $12, redact_previous = $13, attach_ics = $14,
+                    holiday_region = $15, skip_if_declined = $16, weekdays = $17, template_id = $18,
+                    locale = $19, exclude_tentative = $20, exclude_needs_action = $21,
+                    live_countdown = $22,
+                    -- The thread root is in the old room, so we need a new one.
+                    thread_root_event_id = CASE WHEN room = $1 THEN thread_root_event_id END
+                    WHERE calendar_id = $7 AND reminder_id = $8
+            "#,
+                &[
+                    &reminder.room,
+                    &reminder.minutes_before,
+                    &reminder.template,
+                    &reminder.attendee_editable,
+                    &reminder.extra_attendees,
+                    &reminder.excluded_attendees,
+                    &reminder.calendar_id,
+                    &reminder.reminder_id,
+                    &reminder.threaded,
+                    &reminder.poll,
+                    &reminder.msgtype,
+                    &reminder.sender,
+                    &reminder.redact_previous,
+                    &reminder.attach_ics,
+                    &reminder.holiday_region,
+                    &reminder.skip_if_declined,
+                    &reminder.weekdays,
+                    &reminder.template_id,
+                    &reminder.locale,
+                    &reminder.exclude_tentative,
+                    &reminder.exclude_needs_action,
+                    &reminder.live_countdown,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the user's reminder rules.
+    pub async fn get_reminder_rules_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<ReminderRule>, Error> {
+        self.get_reminder_rules_with_filter("user_id = $1", &[&user_id])
+            .await
+    }
+
+    /// Get the rules that apply to the calendar.
+    pub async fn get_reminder_rules_for_calendar(
+        &self,
+        calendar_id: i64,
+    ) -> Result<Vec<ReminderRule>, Error> {
+        self.get_reminder_rules_with_filter(
+            r#"
+                user_id = (SELECT user_id FROM calendars WHERE calendar_id = $1)
+                AND (calendar_id IS NULL OR calendar_id = $1)
+            "#,
+            &[&calendar_id],
+        )
+        .await
+    }
+
+    async fn get_reminder_rules_with_filter(
+        &self,
+        where_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<ReminderRule>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &format!(
+                    r#"
+                    SELECT rule_id, user_id, calendar_id, summary_pattern, room, minutes_before
+                    FROM reminder_rules
+                    WHERE {where_sql}
+                    ORDER BY rule_id
+                "#,
+                ),
+                params,
+            )
+            .await?;
+
+        let mut rules = Vec::with_capacity(rows.len());
+        for row in rows {
+            rules.push(ReminderRule {
+                rule_id: row.try_get("rule_id")?,
+                user_id: row.try_get("user_id")?,
+                calendar_id: row.try_get("calendar_id")?,
+                summary_pattern: row.try_get("summary_pattern")?,
+                room: row.try_get("room")?,
+                minutes_before: row.try_get("minutes_before")?,
+            });
+        }
+
+        Ok(rules)
+    }
+
+    /// Add a new reminder rule, returning its ID.
+    pub async fn add_reminder_rule(&self, rule: &ReminderRule) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO reminder_rules (user_id, calendar_id, summary_pattern, room, minutes_before)
+                    VALUES ($1, $2, $3, $4, $5)
+                    RETURNING rule_id
+                "#,
+                &[
+                    &rule.user_id,
+                    &rule.calendar_id,
+                    &rule.summary_pattern,
+                    &rule.room,
+                    &rule.minutes_before,
+                ],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Get the user's event subscriptions.
+    pub async fn get_event_subscriptions_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<EventSubscription>, Error> {
+        self.get_event_subscriptions_with_filter("user_id = $1", &[&user_id])
+            .await
+    }
+
+    /// Get the subscriptions to new events in the calendar.
+    pub async fn get_event_subscriptions_for_calendar(
+        &self,
+        calendar_id: i64,
+    ) -> Result<Vec<EventSubscription>, Error> {
+        self.get_event_subscriptions_with_filter("calendar_id = $1", &[&calendar_id])
+            .await
+    }
+
+    async fn get_event_subscriptions_with_filter(
+        &self,
+        where_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<EventSubscription>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &format!(
+                    r#"
+                    SELECT subscription_id, user_id, calendar_id, room, summary_pattern
+                    FROM event_subscriptions
+                    WHERE {where_sql}
+                    ORDER BY subscription_id
+                "#,
+                ),
+                params,
+            )
+            .await?;
+
+        let mut subscriptions = Vec::with_capacity(rows.len());
+        for row in rows {
+            subscriptions.push(EventSubscription {
+                subscription_id: row.try_get("subscription_id")?,
+                user_id: row.try_get("user_id")?,
+                calendar_id: row.try_get("calendar_id")?,
+                room: row.try_get("room")?,
+                summary_pattern: row.try_get("summary_pattern")?,
+            });
+        }
+
+        Ok(subscriptions)
+    }
+
+    /// Subscribe a room to new events in a calendar, returning the
+    /// subscription's ID.
+    pub async fn add_event_subscription(
+        &self,
+        subscription: &EventSubscription,
+    ) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO event_subscriptions (user_id, calendar_id, room, summary_pattern)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING subscription_id
+                "#,
+                &[
+                    &subscription.user_id,
+                    &subscription.calendar_id,
+                    &subscription.room,
+                    &subscription.summary_pattern,
+                ],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Delete the user's event subscription.
+    ///
+    /// Returns false if the user has no such subscription.
+    pub async fn delete_event_subscription(
+        &self,
+        user_id: i64,
+        subscription_id: i64,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM event_subscriptions WHERE user_id = $1 AND subscription_id = $2",
+                &[&user_id, &subscription_id],
+            )
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Delete the user's reminder rule, along with the reminders it created.
+    ///
+    /// Returns false if the user has no such rule.
+    pub async fn delete_reminder_rule(&self, user_id: i64, rule_id: i64) -> Result<bool, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let deleted = txn
+            .execute(
+                "DELETE FROM reminder_rules WHERE user_id = $1 AND rule_id = $2",
+                &[&user_id, &rule_id],
+            )
+            .await?;
+
+        if deleted == 0 {
+            return Ok(false);
+        }
+
+        txn.execute(
+            r#"
+                UPDATE reminders SET deleted_at = COALESCE(deleted_at, NOW()), rule_id = NULL
+                WHERE rule_id = $1
+            "#,
+            &[&rule_id],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Make sure the event has a reminder from the rule, with the rule's
+    /// settings.
+    ///
+    /// Reminders from the rule that the user has deleted aren't recreated.
+    /// Returns whether anything changed.
+    pub async fn upsert_rule_reminder(
+        &self,
+        rule: &ReminderRule,
+        calendar_id: i64,
+        event_id: &str,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let changed = db_conn
+            .execute(
+                r#"
+                    INSERT INTO reminders (
+                        user_id, calendar_id, event_id, room, minutes_before, attendee_editable, rule_id
+                    )
+                    VALUES ($1, $2, $3, $4, $5, FALSE, $6)
+                    ON CONFLICT (calendar_id, event_id, rule_id) DO UPDATE
+                    SET room = EXCLUDED.room, minutes_before = EXCLUDED.minutes_before
+                    WHERE reminders.deleted_at IS NULL AND (
+                        reminders.room != EXCLUDED.room
+                        OR reminders.minutes_before != EXCLUDED.minutes_before
+                    )
+                "#,
+                &[
+                    &rule.user_id,
+                    &calendar_id,
+                    &event_id,
+                    &rule.room,
+                    &rule.minutes_before,
+                    &rule.rule_id,
+                ],
+            )
+            .await?;
+
+        Ok(changed > 0)
+    }
+
+    /// Make sure each of the events has a reminder from the user in the room,
+    /// sent the given number of minutes before.
+    ///
+    /// The user's existing reminder in the room is updated if there is one,
+    /// otherwise a new one is added. Reminders added by rules are left alone,
+    /// as the rule would overwrite them on the next sync. All the changes are
+    /// made in one transaction.
+    pub async fn bulk_upsert_reminders(
+        &self,
+        user_id: i64,
+        events: &[(i64, String)],
+        room: &str,
+        minutes_before: i64,
+    ) -> Result<BulkReminderResult, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+        let txn = db_conn.transaction().await?;
+
+        let mut result = BulkReminderResult::default();
+
+        for (calendar_id, event_id) in events {
+            let existing = txn
+                .query_opt(
+                    r#"
+                        SELECT reminder_id FROM reminders
+                        WHERE user_id = $1 AND calendar_id = $2 AND event_id = $3 AND room = $4
+                            AND rule_id IS NULL AND deleted_at IS NULL
+                        ORDER BY reminder_id
+                        LIMIT 1
+                    "#,
+                    &[&user_id, calendar_id, event_id, &room],
+                )
+                .await?;
+
+            if let Some(row) = existing {
+                let reminder_id: i64 = row.try_get("reminder_id")?;
+
+                txn.execute(
+                    "UPDATE reminders SET minutes_before = $1 WHERE reminder_id = $2",
+                    &[&minutes_before, &reminder_id],
+                )
+                .await?;
+
+                result.updated.push(reminder_id);
+            } else {
+                let row = txn
+                    .query_one(
+                        r#"
+                            INSERT INTO reminders (
+                                user_id, calendar_id, event_id, room, minutes_before, attendee_editable
+                            )
+                            VALUES ($1, $2, $3, $4, $5, FALSE)
+                            RETURNING reminder_id
+                        "#,
+                        &[&user_id, calendar_id, event_id, &room, &minutes_before],
+                    )
+                    .await?;
+
+                result.created.push(row.try_get("reminder_id")?);
+            }
+        }
+
+        txn.commit().await?;
+
+        Ok(result)
+    }
+
+    /// Delete a specific reminder.
+    pub async fn delete_reminder_in_calendar(
+        &self,
+        calendar_id: i64,
+        reminder_id: i64,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    DELETE FROM reminders
+                    WHERE calendar_id = $1 AND reminder_id = $2
+            "#,
+                &[&calendar_id, &reminder_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Mark a reminder as deleted, so that it can later be restored with
+    /// [`Database::restore_reminder_in_calendar`].
+    pub async fn soft_delete_reminder_in_calendar(
+        &self,
+        calendar_id: i64,
+        reminder_id: i64,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE reminders
+                    SET deleted_at = now()
+                    WHERE calendar_id = $1 AND reminder_id = $2 AND deleted_at IS NULL
+            "#,
+                &[&calendar_id, &reminder_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Undo the deletion of a reminder. Returns false if there is no such
+    /// deleted reminder.
+    pub async fn restore_reminder_in_calendar(
+        &self,
+        calendar_id: i64,
+        reminder_id: i64,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                r#"
+                    UPDATE reminders
+                    SET deleted_at = NULL
+                    WHERE calendar_id = $1 AND reminder_id = $2 AND deleted_at IS NOT NULL
+            "#,
+                &[&calendar_id, &reminder_id],
+            )
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Pause or resume sending a reminder. Returns false if there is no such
+    /// reminder.
+    pub async fn set_reminder_enabled(
+        &self,
+        reminder_id: i64,
+        enabled: bool,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                r#"
+                    UPDATE reminders
+                    SET enabled = $2
+                    WHERE reminder_id = $1 AND deleted_at IS NULL
+            "#,
+                &[&reminder_id, &enabled],
+            )
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Point all of the user's reminders for `old_room` at `new_room` instead.
+    ///
+    /// Returns the number of reminders that were updated.
+    pub async fn move_reminders_to_room(
+        &self,
+        user_id: i64,
+        old_room: &str,
+        new_room: &str,
+    ) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                r#"
+                    UPDATE reminders
+                    SET room = $3, thread_root_event_id = NULL
+                    WHERE user_id = $1 AND room = $2 AND deleted_at IS NULL
+            "#,
+                &[&user_id, &old_room, &new_room],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Get the rooms the user has reminders in, along with how many reminders
+    /// target each room.
+    pub async fn get_reminder_rooms_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT room, COUNT(*) FROM reminders
+                    WHERE user_id = $1 AND deleted_at IS NULL
+                    GROUP BY room
+                    ORDER BY room
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut rooms = Vec::with_capacity(rows.len());
+        for row in rows {
+            let room: String = row.try_get(0)?;
+            let count: i64 = row.try_get(1)?;
+            rooms.push((room, count));
+        }
+
+        Ok(rooms)
+    }
+
+    /// Get the reminders needed to be sent out.
+    pub async fn get_next_reminders(
+        &self,
+    ) -> Result<VecDeque<(DateTime<Utc>, ReminderInstance)>, Error> {
+        let mut reminders = self.get_next_reminders_with_filter("", &[]).await?;
+
+        let adhoc_reminders = self
+            .get_adhoc_reminders_with_filter("remind_at > now()", &[])
+            .await?;
+        reminders.extend(
+            adhoc_reminders
+                .iter()
+                .map(|reminder| (reminder.remind_at, reminder.to_instance())),
+        );
+
+        reminders.make_contiguous().sort_by_key(|(t, _)| *t);
+
+        Ok(reminders)
+    }
+
+    /// Get the upcoming reminders for events in the calendar.
+    pub async fn get_next_reminders_for_calendar(
+        &self,
+        calendar_id: i64,
+    ) -> Result<VecDeque<(DateTime<Utc>, ReminderInstance)>, Error> {
+        self.get_next_reminders_with_filter("AND calendar_id = $1", &[&calendar_id])
+            .await
+    }
+
+    async fn get_next_reminders_with_filter(
+        &self,
+        extra_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<VecDeque<(DateTime<Utc>, ReminderInstance)>, Error> {
+        let instances = self
+            .get_reminder_instances_with_filter(
+                &format!(
+                    r#"
+                    timestamp > now() + '-5 minutes'
+                        AND reminders.deleted_at IS NULL
+                        AND reminders.enabled
+                        AND c.deleted_at IS NULL
+                        AND c.enabled
+                        AND (
+                            c.muted_until IS NULL
+                            OR NOT tstzrange(c.muted_from, c.muted_until)
+                                @> timestamp - minutes_before * interval '1 minute'
+                        )
+                        {extra_sql}
+                "#
+                ),
+                params,
+            )
+            .await?;
+
+        let weekday_masks = self.get_reminder_weekday_masks().await?;
+
+        let mut reminders = VecDeque::with_capacity(instances.len());
+        let now = Utc::now();
+
+        for reminder in instances {
+            let reminder_time = reminder.timestamp - Duration::minutes(reminder.minutes_before);
+            if reminder_time < now {
+                // XXX: There's technically a race here if we reload the
+                // reminders just as we're about to send out a reminder.
+                info!(now = ?now, reminder_time =?reminder_time, event_id = reminder.event_id.deref(), "Ignoring old reminder");
+                continue;
+            }
+
+            if let Some((mask, timezone)) = weekday_masks.get(&reminder.reminder_id) {
+                let weekday = reminder.timestamp.with_timezone(timezone).weekday();
+                if !weekday_in_mask(Some(*mask), weekday) {
+                    continue;
+                }
+            }
+
+            reminders.push_back((reminder_time, reminder));
+        }
+
+        reminders.make_contiguous().sort_by_key(|(t, _)| *t);
+
+        Ok(reminders)
+    }
+
+    /// Get the weekday masks of the reminders that have one, along with the
+    /// timezone to work out the weekday of their events in.
+    ///
+    /// We use the calendar's timezone if it has one, and UTC otherwise.
+    async fn get_reminder_weekday_masks(&self) -> Result<HashMap<i64, (i32, Tz)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, weekdays, c.timezone
+                    FROM reminders
+                    INNER JOIN calendars AS c USING (calendar_id)
+                    WHERE weekdays IS NOT NULL AND reminders.deleted_at IS NULL
+                "#,
+                &[],
+            )
+            .await?;
+
+        let mut masks = HashMap::with_capacity(rows.len());
+        for row in rows {
+            let reminder_id: i64 = row.try_get("reminder_id")?;
+            let mask: i32 = row.try_get("weekdays")?;
+            let timezone: Option<String> = row.try_get("timezone")?;
+
+            let timezone = timezone
+                .and_then(|timezone| timezone.parse().ok())
+                .unwrap_or(Tz::UTC);
+
+            masks.insert(reminder_id, (mask, timezone));
+        }
+
+        Ok(masks)
+    }
+
+    /// Get the reminder for a particular instance of an event, whether or not
+    /// the reminder has already been sent.
+    pub async fn get_reminder_instance(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<ReminderInstance>, Error> {
+        let mut instances = self
+            .get_reminder_instances_with_filter(
+                "reminder_id = $1 AND timestamp = $2 AND reminders.deleted_at IS NULL",
+                &[&reminder_id, &timestamp],
+            )
+            .await?;
+
+        if let Some(instance) = instances.pop() {
+            return Ok(Some(instance));
+        }
+
+        let adhoc_reminder = self
+            .get_adhoc_reminders_with_filter(
+                "reminder_id = $1 AND remind_at = $2",
+                &[&reminder_id, &timestamp],
+            )
+            .await?
+            .pop();
+
+        Ok(adhoc_reminder.map(|reminder| reminder.to_instance()))
+    }
+
+    /// Get the reminder instances for the reminder that start within the
+    /// given range.
+    pub async fn get_reminder_instances_between(
+        &self,
+        reminder_id: i64,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<ReminderInstance>, Error> {
+        self.get_reminder_instances_with_filter(
+            "reminder_id = $1 AND timestamp BETWEEN $2 AND $3 AND reminders.deleted_at IS NULL",
+            &[&reminder_id, &from, &to],
+        )
+        .await
+    }
+
+    /// Get the root message of the thread that the reminder posts into, if
+    /// one has been created.
+    pub async fn get_reminder_thread_root(
+        &self,
+        reminder_id: i64,
+    ) -> Result<Option<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT thread_root_event_id FROM reminders WHERE reminder_id = $1",
+                &[&reminder_id],
+            )
+            .await?;
+
+        Ok(row.and_then(|row| row.get(0)))
+    }
+
+    /// Set the root message of the thread that the reminder posts into.
+    pub async fn set_reminder_thread_root(
+        &self,
+        reminder_id: i64,
+        thread_root_event_id: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "UPDATE reminders SET thread_root_event_id = $2 WHERE reminder_id = $1",
+                &[&reminder_id, &thread_root_event_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record the most recent message sent for a reminder, returning the
+    /// previous one (if any) as `(room_id, matrix_event_id, sender)`.
+    pub async fn replace_last_sent_reminder(
+        &self,
+        reminder_id: i64,
+        room_id: &str,
+        matrix_event_id: &str,
+        sender: Option<&str>,
+    ) -> Result<Option<(String, String, Option<String>)>, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let previous = txn
+            .query_opt(
+                r#"
+                    SELECT room_id, matrix_event_id, sender FROM last_sent_reminders
+                    WHERE reminder_id = $1
+                    FOR UPDATE
+                "#,
+                &[&reminder_id],
+            )
+            .await?
+            .map(|row| -> Result<_, Error> {
+                Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?))
+            })
+            .transpose()?;
+
+        txn.execute(
+            r#"
+                INSERT INTO last_sent_reminders (reminder_id, room_id, matrix_event_id, sender)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (reminder_id) DO UPDATE SET
+                    room_id = EXCLUDED.room_id,
+                    matrix_event_id = EXCLUDED.matrix_event_id,
+                    sender = EXCLUDED.sender
+            "#,
+            &[&reminder_id, &room_id, &matrix_event_id, &sender],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(previous)
+    }
+
+    /// Record that we've sent a reminder.
+    pub async fn add_sent_reminder(&self, sent: &SentReminder) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO sent_reminders (
+                        reminder_id, timestamp, room_id, matrix_event_id, summary, location,
+                        sender
+                    )
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT (reminder_id, timestamp) DO UPDATE SET
+                        room_id = EXCLUDED.room_id,
+                        matrix_event_id = EXCLUDED.matrix_event_id,
+                        summary = EXCLUDED.summary,
+                        location = EXCLUDED.location,
+                        sender = EXCLUDED.sender
+                "#,
+                &[
+                    &sent.reminder_id,
+                    &sent.timestamp,
+                    &sent.room_id,
+                    &sent.matrix_event_id,
+                    &sent.summary,
+                    &sent.location,
+                    &sent.sender,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the sent reminder with the given Matrix event ID, if any.
+    pub async fn get_sent_reminder_by_event_id(
+        &self,
+        room_id: &str,
+        matrix_event_id: &str,
+    ) -> Result<Option<SentReminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT reminder_id, timestamp, room_id, matrix_event_id, summary, location,
+                        sender
+                    FROM sent_reminders
+                    WHERE room_id = $1 AND matrix_event_id = $2
+                "#,
+                &[&room_id, &matrix_event_id],
+            )
+            .await?;
+
+        let row = if let Some(row) = row {
+            row
+        } else {
+            return Ok(None);
+        };
+
+        Ok(Some(SentReminder {
+            reminder_id: row.try_get(0)?,
+            timestamp: row.try_get(1)?,
+            room_id: row.try_get(2)?,
+            matrix_event_id: row.try_get(3)?,
+            summary: row.try_get(4)?,
+            location: row.try_get(5)?,
+            sender: row.try_get(6)?,
+        }))
+    }
+
+    /// Get the sent reminders for events in the calendar that haven't started
+    /// yet.
+    pub async fn get_upcoming_sent_reminders(
+        &self,
+        calendar_id: i64,
+    ) -> Result<Vec<SentReminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, timestamp, room_id, matrix_event_id,
+                        sent_reminders.summary, sent_reminders.location, sent_reminders.sender
+                    FROM sent_reminders
+                    INNER JOIN reminders USING (reminder_id)
+                    WHERE calendar_id = $1 AND timestamp > now()
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        let mut sent_reminders = Vec::with_capacity(rows.len());
+        for row in rows {
+            sent_reminders.push(SentReminder {
+                reminder_id: row.try_get(0)?,
+                timestamp: row.try_get(1)?,
+                room_id: row.try_get(2)?,
+                matrix_event_id: row.try_get(3)?,
+                summary: row.try_get(4)?,
+                location: row.try_get(5)?,
+                sender: row.try_get(6)?,
+            });
+        }
+
+        Ok(sent_reminders)
+    }
+
+    /// Update a sent reminder after we've edited it to match the event.
+    pub async fn update_sent_reminder(
+        &self,
+        reminder_id: i64,
+        old_timestamp: DateTime<Utc>,
+        new_timestamp: DateTime<Utc>,
+        summary: Option<&str>,
+        location: Option<&str>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE sent_reminders
+                    SET timestamp = $3, summary = $4, location = $5
+                    WHERE reminder_id = $1 AND timestamp = $2
+                "#,
+                &[
+                    &reminder_id,
+                    &old_timestamp,
+                    &new_timestamp,
+                    &summary,
+                    &location,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete records of sent reminders for events that started before the
+    /// given time.
+    pub async fn delete_old_sent_reminders(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM sent_reminders WHERE timestamp < $1",
+                &[&before],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Add a one-off reminder, returning its ID.
+    pub async fn add_adhoc_reminder(
+        &self,
+        user_id: i64,
+        room: &str,
+        message: &str,
+        remind_at: DateTime<Utc>,
+    ) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO adhoc_reminders (user_id, room, message, remind_at)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING reminder_id
+                "#,
+                &[&user_id, &room, &message, &remind_at],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Get all of the user's one-off reminders.
+    pub async fn get_adhoc_reminders_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<AdhocReminder>, Error> {
+        self.get_adhoc_reminders_with_filter("user_id = $1", &[&user_id])
+            .await
+    }
+
+    /// Get the user's one-off reminders that haven't been sent yet.
+    pub async fn get_upcoming_adhoc_reminders_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<AdhocReminder>, Error> {
+        self.get_adhoc_reminders_with_filter("user_id = $1 AND remind_at > now()", &[&user_id])
+            .await
+    }
+
+    async fn get_adhoc_reminders_with_filter(
+        &self,
+        where_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<AdhocReminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &format!(
+                    r#"
+                    SELECT reminder_id, user_id, room, message, remind_at
+                    FROM adhoc_reminders
+                    WHERE {where_sql}
+                    ORDER BY remind_at, reminder_id
+                "#,
+                ),
+                params,
+            )
+            .await?;
+
+        let mut reminders = Vec::with_capacity(rows.len());
+        for row in rows {
+            reminders.push(AdhocReminder {
+                reminder_id: row.try_get("reminder_id")?,
+                user_id: row.try_get("user_id")?,
+                room: row.try_get("room")?,
+                message: row.try_get("message")?,
+                remind_at: row.try_get("remind_at")?,
+            });
+        }
+
+        Ok(reminders)
+    }
+
+    /// Delete the user's one-off reminder.
+    ///
+    /// Returns false if the user has no such reminder.
+    pub async fn delete_adhoc_reminder(
+        &self,
+        user_id: i64,
+        reminder_id: i64,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let deleted = db_conn
+            .execute(
+                "DELETE FROM adhoc_reminders WHERE user_id = $1 AND reminder_id = $2",
+                &[&user_id, &reminder_id],
+            )
+            .await?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Delete one-off reminders that were due before the given time.
+    pub async fn delete_old_adhoc_reminders(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM adhoc_reminders WHERE remind_at < $1",
+                &[&before],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Record an attempt to send a reminder in the reminder log.
+    pub async fn add_reminder_log_entry(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+        room: &str,
+        matrix_event_id: Option<&str>,
+        error: Option<&str>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO reminder_log (reminder_id, timestamp, room, matrix_event_id, error)
+                    VALUES ($1, $2, $3, $4, $5)
+                "#,
+                &[&reminder_id, &timestamp, &room, &matrix_event_id, &error],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the most recent attempts to send the reminder, newest first.
+    pub async fn get_reminder_log(
+        &self,
+        reminder_id: i64,
+        limit: i64,
+    ) -> Result<Vec<ReminderLogEntry>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, timestamp, attempted_at, room, matrix_event_id, error
+                    FROM reminder_log
+                    WHERE reminder_id = $1
+                    ORDER BY attempted_at DESC
+                    LIMIT $2
+                "#,
+                &[&reminder_id, &limit],
+            )
+            .await?;
+
+        let mut entries = Vec::with_capacity(rows.len());
+        for row in rows {
+            entries.push(ReminderLogEntry {
+                reminder_id: row.try_get("reminder_id")?,
+                timestamp: row.try_get("timestamp")?,
+                attempted_at: row.try_get("attempted_at")?,
+                room: row.try_get("room")?,
+                matrix_event_id: row.try_get("matrix_event_id")?,
+                error: row.try_get("error")?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Get all the attempts to send the user's reminders, newest first.
+    pub async fn get_reminder_log_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<ReminderLogEntry>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, timestamp, attempted_at, room, matrix_event_id, error
+                    FROM reminder_log
+                    WHERE reminder_id IN (
+                        SELECT reminder_id FROM reminders WHERE user_id = $1
+                        UNION
+                        SELECT reminder_id FROM adhoc_reminders WHERE user_id = $1
+                    )
+                    ORDER BY attempted_at DESC
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut entries = Vec::with_capacity(rows.len());
+        for row in rows {
+            entries.push(ReminderLogEntry {
+                reminder_id: row.try_get("reminder_id")?,
+                timestamp: row.try_get("timestamp")?,
+                attempted_at: row.try_get("attempted_at")?,
+                room: row.try_get("room")?,
+                matrix_event_id: row.try_get("matrix_event_id")?,
+                error: row.try_get("error")?,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Delete reminder log entries for attempts made before the given time.
+    pub async fn delete_old_reminder_log(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM reminder_log WHERE attempted_at < $1",
+                &[&before],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Queue up a reminder instance that failed to send to be retried. Does
+    /// nothing if it's already queued.
+    pub async fn add_reminder_retry(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+        next_attempt_at: DateTime<Utc>,
+        error: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO reminder_retries (reminder_id, timestamp, next_attempt_at, last_error)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (reminder_id, timestamp) DO NOTHING
+                "#,
+                &[&reminder_id, &timestamp, &next_attempt_at, &error],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the queued reminder retries that are due by the given time.
+    pub async fn get_due_reminder_retries(
+        &self,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<ReminderRetry>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, timestamp, first_failed_at, attempts, next_attempt_at, last_error
+                    FROM reminder_retries
+                    WHERE next_attempt_at <= $1
+                    ORDER BY next_attempt_at
+                "#,
+                &[&now],
+            )
+            .await?;
+
+        let mut retries = Vec::with_capacity(rows.len());
+        for row in rows {
+            retries.push(ReminderRetry {
+                reminder_id: row.try_get("reminder_id")?,
+                timestamp: row.try_get("timestamp")?,
+                first_failed_at: row.try_get("first_failed_at")?,
+                attempts: row.try_get("attempts")?,
+                next_attempt_at: row.try_get("next_attempt_at")?,
+                last_error: row.try_get("last_error")?,
+            });
+        }
+
+        Ok(retries)
+    }
+
+    /// Add a webhook for the user, returning its ID.
+    pub async fn add_webhook(
+        &self,
+        user_id: i64,
+        url: &str,
+        secret: &str,
+        event_types: &[String],
+    ) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO webhooks (user_id, url, secret, event_types)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING webhook_id
+                "#,
+                &[&user_id, &url, &secret, &event_types],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Get the user's webhooks, along with how their deliveries are going.
+    pub async fn get_webhooks(&self, user_id: i64) -> Result<Vec<Webhook>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT webhook_id, user_id, url, secret, event_types, created_at,
+                        (
+                            SELECT COUNT(*) FROM webhook_deliveries AS d
+                            WHERE d.webhook_id = w.webhook_id
+                        ) AS pending_deliveries,
+                        (
+                            SELECT last_error FROM webhook_deliveries AS d
+                            WHERE d.webhook_id = w.webhook_id AND last_error IS NOT NULL
+                            ORDER BY delivery_id DESC
+                            LIMIT 1
+                        ) AS last_error
+                    FROM webhooks AS w
+                    WHERE user_id = $1
+                    ORDER BY webhook_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut webhooks = Vec::with_capacity(rows.len());
+        for row in rows {
+            webhooks.push(Webhook {
+                webhook_id: row.try_get("webhook_id")?,
+                user_id: row.try_get("user_id")?,
+                url: row.try_get("url")?,
+                secret: row.try_get("secret")?,
+                event_types: row.try_get("event_types")?,
+                created_at: row.try_get("created_at")?,
+                pending_deliveries: row.try_get("pending_deliveries")?,
+                last_error: row.try_get("last_error")?,
+            });
+        }
+
+        Ok(webhooks)
+    }
+
+    /// Delete one of the user's webhooks, along with any pending deliveries.
+    pub async fn delete_webhook(&self, user_id: i64, webhook_id: i64) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM webhooks WHERE user_id = $1 AND webhook_id = $2",
+                &[&user_id, &webhook_id],
+            )
+            .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Queue the payload for delivery to each of the user's webhooks that
+    /// subscribe to the event type, returning how many were queued.
+    pub async fn queue_webhook_deliveries(
+        &self,
+        user_id: i64,
+        event_type: &str,
+        payload: &str,
+    ) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                r#"
+                    INSERT INTO webhook_deliveries (webhook_id, event_type, payload)
+                    SELECT webhook_id, $2, $3 FROM webhooks
+                    WHERE user_id = $1 AND $2 = ANY(event_types)
+                "#,
+                &[&user_id, &event_type, &payload],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Get the webhook payloads that are due to be delivered.
+    pub async fn get_due_webhook_deliveries(
+        &self,
+        now: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<WebhookDelivery>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT delivery_id, webhook_id, url, secret, event_type, payload, attempts
+                    FROM webhook_deliveries
+                    INNER JOIN webhooks USING (webhook_id)
+                    WHERE next_attempt_at <= $1
+                    ORDER BY next_attempt_at, delivery_id
+                    LIMIT $2
+                "#,
+                &[&now, &limit],
+            )
+            .await?;
+
+        let mut deliveries = Vec::with_capacity(rows.len());
+        for row in rows {
+            deliveries.push(WebhookDelivery {
+                delivery_id: row.try_get("delivery_id")?,
+                webhook_id: row.try_get("webhook_id")?,
+                url: row.try_get("url")?,
+                secret: row.try_get("secret")?,
+                event_type: row.try_get("event_type")?,
+                payload: row.try_get("payload")?,
+                attempts: row.try_get("attempts")?,
+            });
+        }
+
+        Ok(deliveries)
+    }
+
+    /// Record a failed attempt at delivering a webhook payload.
+    pub async fn update_webhook_delivery(
+        &self,
+        delivery_id: i64,
+        attempts: i32,
+        next_attempt_at: DateTime<Utc>,
+        last_error: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE webhook_deliveries
+                    SET attempts = $2, next_attempt_at = $3, last_error = $4
+                    WHERE delivery_id = $1
+                "#,
+                &[&delivery_id, &attempts, &next_attempt_at, &last_error],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove a webhook payload from the queue, either because it's been
+    /// delivered or we've given up.
+    pub async fn delete_webhook_delivery(&self, delivery_id: i64) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "DELETE FROM webhook_deliveries WHERE delivery_id = $1",
+                &[&delivery_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record another failed attempt at sending a queued reminder.
+    pub async fn update_reminder_retry(&self, retry: &ReminderRetry) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE reminder_retries
+                    SET attempts = $3, next_attempt_at = $4, last_error = $5
+                    WHERE reminder_id = $1 AND timestamp = $2
+                "#,
+                &[
+                    &retry.reminder_id,
+                    &retry.timestamp,
+                    &retry.attempts,
+                    &retry.next_attempt_at,
+                    &retry.last_error,
+                ],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove a reminder instance from the retry queue.
+    pub async fn delete_reminder_retry(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "DELETE FROM reminder_retries WHERE reminder_id = $1 AND timestamp = $2",
+                &[&reminder_id, &timestamp],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the user who owns the reminder, which may be a one-off reminder.
+    pub async fn get_reminder_owner(&self, reminder_id: i64) -> Result<Option<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT user_id FROM reminders WHERE reminder_id = $1
+                    UNION ALL
+                    SELECT user_id FROM adhoc_reminders WHERE reminder_id = $1
+                "#,
+                &[&reminder_id],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.try_get("user_id")).transpose()?)
+    }
+
+    /// Record whether someone will attend the event instance, replacing any
+    /// previous answer.
+    pub async fn set_attendance(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+        matrix_id: &str,
+        attending: bool,
+        reaction_event_id: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO attendance (reminder_id, timestamp, matrix_id, attending, reaction_event_id)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT (reminder_id, timestamp, matrix_id) DO UPDATE SET
+                        attending = EXCLUDED.attending,
+                        reaction_event_id = EXCLUDED.reaction_event_id
+                "#,
+                &[&reminder_id, &timestamp, &matrix_id, &attending, &reaction_event_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove the attendance recorded by the given reaction, e.g. because the
+    /// reaction was redacted.
+    pub async fn delete_attendance_by_reaction(
+        &self,
+        reaction_event_id: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "DELETE FROM attendance WHERE reaction_event_id = $1",
+                &[&reaction_event_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get who has said whether they'll attend the upcoming (or recent)
+    /// instances of the event.
+    pub async fn get_attendance_for_event(
+        &self,
+        calendar_id: i64,
+        event_id: &str,
+    ) -> Result<Vec<Attendance>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT DISTINCT ON (timestamp, matrix_id) timestamp, matrix_id, attending
+                    FROM attendance
+                    INNER JOIN reminders USING (reminder_id)
+                    WHERE calendar_id = $1 AND event_id = $2
+                        AND timestamp > now() - interval '1 day'
+                    ORDER BY timestamp, matrix_id
+                "#,
+                &[&calendar_id, &event_id],
+            )
+            .await?;
+
+        let mut attendance = Vec::with_capacity(rows.len());
+        for row in rows {
+            attendance.push(Attendance {
+                timestamp: row.try_get(0)?,
+                matrix_id: row.try_get(1)?,
+                attending: row.try_get(2)?,
+            });
+        }
+
+        Ok(attendance)
+    }
+
+    /// Record that we posted an attendance poll for the reminder.
+    pub async fn add_reminder_poll(
+        &self,
+        reminder_id: i64,
+        timestamp: DateTime<Utc>,
+        room_id: &str,
+        poll_event_id: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO reminder_polls (reminder_id, timestamp, room_id, poll_event_id)
+                    VALUES ($1, $2, $3, $4)
+                "#,
+                &[&reminder_id, &timestamp, &room_id, &poll_event_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Record someone's answer to a poll, replacing any previous answer. Does
+    /// nothing if the poll isn't one of ours.
+    pub async fn add_poll_response(
+        &self,
+        room_id: &str,
+        poll_event_id: &str,
+        matrix_id: &str,
+        answer: &str,
+        responded_at: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO poll_responses (poll_event_id, matrix_id, answer, responded_at)
+                    SELECT poll_event_id, $3, $4, $5 FROM reminder_polls
+                    WHERE room_id = $1 AND poll_event_id = $2
+                    ON CONFLICT (poll_event_id, matrix_id) DO UPDATE
+                    SET answer = EXCLUDED.answer, responded_at = EXCLUDED.responded_at
+                    WHERE poll_responses.responded_at <= EXCLUDED.responded_at
+                "#,
+                &[&room_id, &poll_event_id, &matrix_id, &answer, &responded_at],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the responses to the reminder's polls, most recent event first.
+    pub async fn get_poll_responses(&self, reminder_id: i64) -> Result<Vec<PollResponse>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT timestamp, matrix_id, answer, responded_at
+                    FROM reminder_polls
+                    INNER JOIN poll_responses USING (poll_event_id)
+                    WHERE reminder_id = $1
+                    ORDER BY timestamp DESC, responded_at
+                "#,
+                &[&reminder_id],
+            )
+            .await?;
+
+        let mut responses = Vec::with_capacity(rows.len());
+        for row in rows {
+            responses.push(PollResponse {
+                timestamp: row.try_get(0)?,
+                matrix_id: row.try_get(1)?,
+                answer: row.try_get(2)?,
+                responded_at: row.try_get(3)?,
+            });
+        }
+
+        Ok(responses)
+    }
+
+    async fn get_reminder_instances_with_filter(
+        &self,
+        where_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<ReminderInstance>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &format!(
+                    r#"
+                    SELECT event_id, summary, description, location, timestamp, room, minutes_before,
+                        COALESCE(t.template, reminders.template) AS template, i.attendees,
+                        extra_attendees, excluded_attendees, calendar_id, reminder_id, threaded, poll, msgtype, sender, redact_previous,
+                        conference_url, attach_ics, holiday_region, skip_if_declined, reminders.locale,
+                        COALESCE(events.last_occurrence = i.timestamp, FALSE) AS is_last_occurrence,
+                        exclude_tentative, exclude_needs_action, live_countdown
+                    FROM reminders
+                    INNER JOIN calendars AS c USING (calendar_id)
+                    INNER JOIN events USING (calendar_id, event_id)
+                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                    LEFT JOIN templates AS t USING (template_id)
+                    WHERE {where_sql}
+                "#,
+                ),
+                params,
+            )
+            .await?;
+
+        let mut reminders = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let event_id: String = row.get(0);
+            let summary: Option<String> = row.get(1);
+            let description: Option<String> = row.get(2);
+            let location: Option<String> = row.get(3);
+            let timestamp: DateTime<Utc> = row.get(4);
+            let room: String = row.get(5);
+            let minutes_before: i64 = row.get(6);
+            let template: Option<String> = row.get(7);
+            let attendees: Vec<Attendee> = row.get(8);
+            let extra_attendees: Vec<String> = row.get(9);
+            let excluded_attendees: Vec<String> = row.get(10);
+            let calendar_id: i64 = row.get(11);
+            let reminder_id: i64 = row.get(12);
+            let threaded: bool = row.get(13);
+            let poll: bool = row.get(14);
+            let msgtype: Option<String> = row.get(15);
+            let sender: Option<String> = row.get(16);
+            let redact_previous: bool = row.get(17);
+            let conference_url: Option<String> = row.get(18);
+            let attach_ics: bool = row.get(19);
+            let holiday_region: Option<String> = row.get(20);
+            let skip_if_declined: bool = row.get(21);
+            let locale: Option<String> = row.get(22);
+            let last_occurrence: bool = row.get(23);
+            let exclude_tentative: bool = row.get(24);
+            let exclude_needs_action: bool = row.get(25);
+            let live_countdown: bool = row.get(26);
+
+            let reminder = ReminderInstance {
+                reminder_id,
+                calendar_id,
+                event_id,
+                timestamp,
+                summary,
+                description,
+                location,
+                conference_url,
+                template,
+                locale,
+                minutes_before,
+                room,
+                attendees,
+                extra_attendees,
+                excluded_attendees,
+                threaded,
+                poll,
+                msgtype,
+                sender,
+                redact_previous,
+                attach_ics,
+                holiday_region,
+                skip_if_declined,
+                exclude_tentative,
+                exclude_needs_action,
+                live_countdown,
+                last_occurrence,
+                snoozed_until: None,
+                adhoc: false,
+                duplicate_reminder_ids: Vec::new(),
+            };
+
+            reminders.push(reminder);
+        }
+
+        Ok(reminders)
+    }
+
+    /// Get the next upcoming events that the user has reminders for in the
+    /// given room.
+    pub async fn get_upcoming_events_in_room(
+        &self,
+        user_id: i64,
+        room: &str,
+        limit: i64,
+    ) -> Result<Vec<UpcomingRoomEvent>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT calendar_id, event_id, summary, location, timestamp, reminder_id, minutes_before
+                    FROM reminders
+                    INNER JOIN calendars AS c USING (calendar_id)
+                    INNER JOIN events USING (calendar_id, event_id)
+                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                    WHERE reminders.user_id = $1
+                        AND room = $2
+                        AND timestamp > now()
+                        AND reminders.deleted_at IS NULL
+                        AND reminders.enabled
+                        AND c.deleted_at IS NULL
+                    ORDER BY timestamp, reminder_id
+                    LIMIT $3
+                "#,
+                &[&user_id, &room, &limit],
+            )
+            .await?;
+
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            events.push(UpcomingRoomEvent {
+                calendar_id: row.try_get("calendar_id")?,
+                event_id: row.try_get("event_id")?,
+                summary: row.try_get("summary")?,
+                location: row.try_get("location")?,
+                timestamp: row.try_get("timestamp")?,
+                reminder_id: row.try_get("reminder_id")?,
+                minutes_before: row.try_get("minutes_before")?,
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Get the recent and upcoming event instances that the user has enabled
+    /// reminders for, for their ICS feed.
+    pub async fn get_feed_events(&self, user_id: i64) -> Result<Vec<FeedEvent>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT calendar_id, event_id, timestamp, summary, description, location,
+                        conference_url, duration_minutes,
+                        array_agg(minutes_before ORDER BY minutes_before DESC, room) AS minutes_before,
+                        array_agg(room ORDER BY minutes_before DESC, room) AS rooms
+                    FROM reminders
+                    INNER JOIN calendars AS c USING (calendar_id)
+                    INNER JOIN events USING (calendar_id, event_id)
+                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                    WHERE reminders.user_id = $1
+                        AND timestamp > now() - interval '7 days'
+                        AND reminders.deleted_at IS NULL
+                        AND reminders.enabled
+                        AND c.deleted_at IS NULL
+                    GROUP BY calendar_id, event_id, timestamp, summary, description, location,
+                        conference_url, duration_minutes
+                    ORDER BY timestamp, calendar_id, event_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            let minutes_before: Vec<i64> = row.try_get("minutes_before")?;
+            let rooms: Vec<String> = row.try_get("rooms")?;
+
+            events.push(FeedEvent {
+                calendar_id: row.try_get("calendar_id")?,
+                event_id: row.try_get("event_id")?,
+                timestamp: row.try_get("timestamp")?,
+                summary: row.try_get("summary")?,
+                description: row.try_get("description")?,
+                location: row.try_get("location")?,
+                conference_url: row.try_get("conference_url")?,
+                duration_minutes: row.try_get("duration_minutes")?,
+                reminders: minutes_before.into_iter().zip(rooms).collect(),
+            });
+        }
+
+        Ok(events)
+    }
+
+    /// Get the token for the user's ICS feed, if they've created one.
+    pub async fn get_feed_token(&self, user_id: i64) -> Result<Option<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT token FROM feed_tokens WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.try_get("token")).transpose()?)
+    }
+
+    /// Set the token for the user's ICS feed, replacing (and so revoking) any
+    /// existing one.
+    pub async fn set_feed_token(&self, user_id: i64, token: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO feed_tokens (user_id, token) VALUES ($1, $2)
+                    ON CONFLICT (user_id) DO UPDATE SET token = EXCLUDED.token, created_at = now()
+                "#,
+                &[&user_id, &token],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Remove the token for the user's ICS feed, turning the feed off.
+    pub async fn delete_feed_token(&self, user_id: i64) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute("DELETE FROM feed_tokens WHERE user_id = $1", &[&user_id])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the user whose ICS feed has the given token.
+    pub async fn get_user_from_feed_token(&self, token: &str) -> Result<Option<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT user_id FROM feed_tokens WHERE token = $1",
+                &[&token],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.try_get("user_id")).transpose()?)
+    }
+
+    /// Get all events in a calendar
+    pub async fn get_events_in_calendar(
+        &self,
+        calendar_id: i64,
+    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT DISTINCT ON (event_id) event_id, summary, description, location, timestamp,
+                        organizer, e.attendees AS event_attendees, i.attendees AS instance_attendees,
+                        conference_url, duration_minutes, last_occurrence
+                    FROM events AS e
+                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                    WHERE calendar_id = $1 AND timestamp > now()
+                    ORDER BY event_id, timestamp
+                "#,
+                &[&calendar_id],
+            )
+            .await?;
+
+        let mut events: Vec<(Event, Vec<EventInstance>)> = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let event_id: String = row.try_get("event_id")?;
+            let summary = row.try_get("summary")?;
+            let description = row.try_get("description")?;
+            let location = row.try_get("location")?;
+            let date = row.try_get("timestamp")?;
+            let organizer = row.try_get("organizer")?;
+            let instance_attendees = row.try_get("instance_attendees")?;
+            let event_attendees = row.try_get("event_attendees")?;
+            let conference_url = row.try_get("conference_url")?;
+            let duration_minutes = row.try_get("duration_minutes")?;
+            let last_occurrence = row.try_get("last_occurrence")?;
+
+            if date < Utc::now() {
+                // ignore events in the past
+                continue;
+            }
+
+            let instance = EventInstance {
+                event_id: event_id.clone(),
+                date,
+                attendees: instance_attendees,
+            };
+
+            if let Some((event, instances)) = events.last_mut() {
+                if event.event_id == event_id {
+                    instances.push(instance);
+                    continue;
+                }
+            }
+
+            let event = Event {
+                calendar_id,
+                event_id,
+                summary,
+                description,
+                location,
+                organizer,
+                attendees: event_attendees,
+                conference_url,
+                duration_minutes,
+                last_occurrence,
+            };
+            events.push((event, vec![instance]));
+        }
+
+        events.sort_by_key(|(_, i)| i[0].date);
+
+        Ok(events)
+    }
+
+    /// Get all events from all a given user's calendars.
+    pub async fn get_events_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT DISTINCT ON (calendar_id, event_id) calendar_id, event_id, summary, description, location, timestamp,
+                        organizer, e.attendees AS event_attendees, i.attendees AS instance_attendees,
+                        conference_url, duration_minutes, last_occurrence
+                    FROM calendars
+                    INNER JOIN events AS e USING (calendar_id)
+                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                    WHERE user_id = $1 AND timestamp > now() AND deleted_at IS NULL
+                    ORDER BY calendar_id, event_id, timestamp
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut events: Vec<(Event, Vec<EventInstance>)> = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let calendar_id = row.try_get("calendar_id")?;
+            let event_id: String = row.try_get("event_id")?;
+            let summary = row.try_get("summary")?;
+            let description = row.try_get("description")?;
+            let location = row.try_get("location")?;
+            let date = row.try_get("timestamp")?;
+            let organizer = row.try_get("organizer")?;
             let instance_attendees = row.try_get("instance_attendees")?;
             let event_attendees = row.try_get("event_attendees")?;
+            let conference_url = row.try_get("conference_url")?;
+            let duration_minutes = row.try_get("duration_minutes")?;
+            let last_occurrence = row.try_get("last_occurrence")?;
+
+            if date < Utc::now() {
+                // ignore events in the past
+                continue;
+            }
+
+            let instance = EventInstance {
+                event_id: event_id.clone(),
+                date,
+                attendees: instance_attendees,
+            };
+
+            if let Some((event, instances)) = events.last_mut() {
+                if event.event_id == event_id {
+                    instances.push(instance);
+                    continue;
+                }
+            }
+
+            let event = Event {
+                calendar_id,
+                event_id,
+                summary,
+                description,
+                location,
+                organizer,
+                attendees: event_attendees,
+                conference_url,
+                duration_minutes,
+                last_occurrence,
+            };
+            events.push((event, vec![instance]));
+        }
+
+        events.sort_by_key(|(_, i)| i[0].date);
+
+        Ok(events)
+    }
+
+    /// Get a page of the upcoming events from the user's calendars, ordered
+    /// by when they next start, along with their next instance.
+    ///
+    /// If a search is given only events whose summary, description,
+    /// organizer or attendee emails contain it (case insensitively) are
+    /// returned.
+    pub async fn get_events_page_for_user(
+        &self,
+        user_id: i64,
+        search: Option<&str>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<(Event, EventInstance)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let pattern = search.map(|search| format!("%{}%", escape_like_pattern(search)));
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT * FROM (
+                        SELECT DISTINCT ON (calendar_id, event_id) calendar_id, event_id, summary, description, location, timestamp,
+                            organizer, e.attendees AS event_attendees, i.attendees AS instance_attendees,
+                            conference_url, duration_minutes, last_occurrence
+                        FROM calendars
+                        INNER JOIN events AS e USING (calendar_id)
+                        INNER JOIN next_dates AS i USING (calendar_id, event_id)
+                        WHERE user_id = $1 AND timestamp > now() AND deleted_at IS NULL
+                            AND (
+                                $4::TEXT IS NULL
+                                OR summary ILIKE $4
+                                OR description ILIKE $4
+                                OR (organizer).email ILIKE $4
+                                OR EXISTS (
+                                    SELECT 1 FROM unnest(e.attendees) AS a WHERE a.email ILIKE $4
+                                )
+                            )
+                        ORDER BY calendar_id, event_id, timestamp
+                    ) AS next_events
+                    ORDER BY timestamp, calendar_id, event_id
+                    LIMIT $2 OFFSET $3
+                "#,
+                &[&user_id, &limit, &offset, &pattern],
+            )
+            .await?;
+
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows {
+            let event_id: String = row.try_get("event_id")?;
+
+            let instance = EventInstance {
+                event_id: event_id.clone(),
+                date: row.try_get("timestamp")?,
+                attendees: row.try_get("instance_attendees")?,
+            };
+
+            let event = Event {
+                calendar_id: row.try_get("calendar_id")?,
+                event_id,
+                summary: row.try_get("summary")?,
+                description: row.try_get("description")?,
+                location: row.try_get("location")?,
+                organizer: row.try_get("organizer")?,
+                attendees: row.try_get("event_attendees")?,
+                conference_url: row.try_get("conference_url")?,
+                duration_minutes: row.try_get("duration_minutes")?,
+                last_occurrence: row.try_get("last_occurrence")?,
+            };
+
+            events.push((event, instance));
+        }
+
+        Ok(events)
+    }
+
+    /// Get all events for user that have reminders
+    pub async fn get_events_with_reminders(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
+        let events = self.get_events_for_user(user_id).await?;
+
+        let mut filtered_events = Vec::with_capacity(events.len());
+        for (event, instance) in events {
+            let reminders = self
+                .get_reminders_for_event(event.calendar_id, &event.event_id)
+                .await?;
+
+            if !reminders.is_empty() {
+                filtered_events.push((event, instance))
+            }
+        }
+
+        Ok(filtered_events)
+    }
+
+    /// Get the specified event
+    pub async fn get_event_in_calendar(
+        &self,
+        calendar_id: i64,
+        event_id: &str,
+    ) -> Result<Option<(Event, Vec<EventInstance>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT DISTINCT ON (event_id) event_id, summary, description, location,
+                        organizer, attendees, conference_url, duration_minutes, last_occurrence
+                    FROM events
+                    WHERE calendar_id = $1 AND event_id = $2
+                "#,
+                &[&calendar_id, &event_id],
+            )
+            .await?;
+
+        let row = if let Some(row) = row {
+            row
+        } else {
+            return Ok(None);
+        };
+
+        let event_id: String = row.try_get("event_id")?;
+        let summary = row.try_get("summary")?;
+        let description = row.try_get("description")?;
+        let location = row.try_get("location")?;
+        let attendees = row.try_get("attendees")?;
+        let organizer = row.try_get("organizer")?;
+        let conference_url = row.try_get("conference_url")?;
+        let duration_minutes = row.try_get("duration_minutes")?;
+        let last_occurrence = row.try_get("last_occurrence")?;
+
+        let event = Event {
+            calendar_id,
+            event_id: event_id.clone(),
+            summary,
+            description,
+            location,
+            attendees,
+            organizer,
+            conference_url,
+            duration_minutes,
+            last_occurrence,
+        };
+
+        let mut instances = Vec::new();
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT timestamp, attendees
+                    FROM next_dates
+                    WHERE calendar_id = $1 AND event_id = $2
+                    ORDER BY timestamp
+                "#,
+                &[&calendar_id, &event_id],
+            )
+            .await?;
+
+        for row in rows {
+            let date: DateTime<FixedOffset> = row.get("timestamp");
+            let attendees: Vec<Attendee> = row.get("attendees");
+
+            if date < Utc::now() {
+                // ignore events in the past
+                continue;
+            }
+
+            let instance = EventInstance {
+                event_id: event_id.clone(),
+                date,
+                attendees,
+            };
+
+            instances.push(instance);
+        }
+
+        instances.sort_by_key(|i| i.date);
+
+        Ok(Some((event, instances)))
+    }
+
+    /// Get reminders for the event, including reminders in other people's
+    /// calendars that are shared.
+    pub async fn get_reminders_for_event(
+        &self,
+        calendar_id: i64,
+        event_id: &str,
+    ) -> Result<Vec<Reminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT DISTINCT ON (reminder_id) reminders.calendar_id, reminders.user_id, reminder_id, room,
+                        minutes_before, attendee_editable, template, extra_attendees, excluded_attendees,
+                        threaded, poll, msgtype, sender, redact_previous, attach_ics, holiday_region,
+                        skip_if_declined, enabled, weekdays, template_id, locale,
+                        exclude_tentative, exclude_needs_action, live_countdown
+                    FROM (
+                        SELECT user_id, calendar_id, event_id, attendees
+                        FROM events
+                        INNER JOIN calendars USING (calendar_id)
+                        WHERE event_id = $1 AND deleted_at IS NULL
+                    ) AS c
+                    INNER JOIN users USING (user_id)
+                    INNER JOIN reminders USING (event_id)
+                    WHERE
+                        c.calendar_id = $2
+                        AND reminders.deleted_at IS NULL
+                        AND (
+                            -- Either the event is in their own calendar...
+                            reminders.calendar_id = $2
+                            --- Or the reminder is attendee editable and they are an attendee
+                            OR (attendee_editable AND EXISTS (
+                                SELECT 1 FROM UNNEST(attendees) AS a
+                                WHERE a.status IS DISTINCT FROM 'DECLINED' AND (
+                                    a.email = users.email OR a.email IN (
+                                        SELECT user_emails.email FROM user_emails
+                                        WHERE user_emails.user_id = users.user_id AND verified
+                                    )
+                                )
+                            ))
+                        )
+                    "#,
+                &[&event_id, &calendar_id],
+            )
+            .await?;
+
+        let mut reminders = Vec::with_capacity(rows.len());
+        for row in rows {
+            let reminder_calendar_id = row.try_get("calendar_id")?;
+            let user_id = row.try_get("user_id")?;
+            let reminder_id = row.try_get("reminder_id")?;
+            let room = row.try_get("room")?;
+            let minutes_before = row.try_get("minutes_before")?;
+            let template = row.try_get("template")?;
+            let attendee_editable = row.try_get("attendee_editable")?;
+            let extra_attendees = row.try_get("extra_attendees")?;
+            let excluded_attendees = row.try_get("excluded_attendees")?;
+            let threaded = row.try_get("threaded")?;
+            let poll = row.try_get("poll")?;
+            let msgtype = row.try_get("msgtype")?;
+            let sender = row.try_get("sender")?;
+            let redact_previous = row.try_get("redact_previous")?;
+            let attach_ics = row.try_get("attach_ics")?;
+            let holiday_region = row.try_get("holiday_region")?;
+            let skip_if_declined = row.try_get("skip_if_declined")?;
+            let exclude_tentative = row.try_get("exclude_tentative")?;
+            let exclude_needs_action = row.try_get("exclude_needs_action")?;
+            let live_countdown = row.try_get("live_countdown")?;
+            let enabled = row.try_get("enabled")?;
+            let weekdays = row.try_get("weekdays")?;
+            let template_id = row.try_get("template_id")?;
+            let locale = row.try_get("locale")?;
+
+            let reminder = Reminder {
+                reminder_id,
+                user_id,
+                calendar_id: reminder_calendar_id,
+                event_id: event_id.to_string(),
+                room,
+                minutes_before,
+                template,
+                attendee_editable,
+                extra_attendees,
+                excluded_attendees,
+                threaded,
+                poll,
+                msgtype,
+                sender,
+                redact_previous,
+                attach_ics,
+                holiday_region,
+                skip_if_declined,
+                exclude_tentative,
+                exclude_needs_action,
+                live_countdown,
+                enabled,
+                weekdays,
+                template_id,
+                locale,
+            };
+            reminders.push(reminder)
+        }
+
+        Ok(reminders)
+    }
+
+    /// Get the list of users that can edit an event.
+    ///
+    /// This is the owner of the reminder, and if the `attendee_editable` flag
+    /// is set, all attendees.
+    pub async fn get_users_who_can_edit_reminder(
+        &self,
+        reminder_id: i64,
+    ) -> Result<Vec<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT c.user_id FROM (
+                        SELECT user_id, calendar_id, event_id, attendees
+                        FROM events
+                        INNER JOIN calendars USING (calendar_id)
+                    ) AS c
+                    INNER JOIN users USING (user_id)
+                    INNER JOIN reminders USING (event_id)
+                    WHERE reminder_id = $1
+                        AND (
+                            -- Either the event is in their own calendar...
+                            reminders.calendar_id = c.calendar_id
+                            --- Or the reminder is attendee editable and they are an attendee
+                            OR (attendee_editable AND EXISTS (
+                                SELECT 1 FROM UNNEST(attendees) AS a
+                                WHERE a.status IS DISTINCT FROM 'DECLINED' AND (
+                                    a.email = users.email OR a.email IN (
+                                        SELECT user_emails.email FROM user_emails
+                                        WHERE user_emails.user_id = users.user_id AND verified
+                                    )
+                                )
+                            ))
+                        )
+                    "#,
+                &[&reminder_id],
+            )
+            .await?;
+
+        let mut users = Vec::with_capacity(rows.len());
+
+        for row in rows {
+            let user_id = row.try_get("user_id")?;
+            users.push(user_id);
+        }
+
+        Ok(users)
+    }
+
+    /// Get a reminder for event
+    pub async fn get_reminder_in_calendar(
+        &self,
+        calendar_id: i64,
+        reminder_id: i64,
+    ) -> Result<Option<Reminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT calendar_id, event_id, user_id, reminder_id, room, minutes_before,
+                        template, attendee_editable, extra_attendees, excluded_attendees, threaded, poll, msgtype, sender, redact_previous, attach_ics,
+                        holiday_region, skip_if_declined, enabled, weekdays, template_id, locale,
+                        exclude_tentative, exclude_needs_action, live_countdown
+                    FROM reminders
+                    WHERE calendar_id = $1 AND reminder_id = $2 AND deleted_at IS NULL
+                "#,
+                &[&calendar_id, &reminder_id],
+            )
+            .await?;
+
+        let row = if let Some(row) = row {
+            row
+        } else {
+            return Ok(None);
+        };
+
+        let calendar_id = row.try_get("calendar_id")?;
+        let reminder_id = row.try_get("reminder_id")?;
+        let user_id = row.try_get("user_id")?;
+        let event_id = row.try_get("event_id")?;
+        let room = row.try_get("room")?;
+        let minutes_before = row.try_get("minutes_before")?;
+        let template = row.try_get("template")?;
+        let attendee_editable = row.try_get("attendee_editable")?;
+        let extra_attendees = row.try_get("extra_attendees")?;
+        let excluded_attendees = row.try_get("excluded_attendees")?;
+        let threaded = row.try_get("threaded")?;
+        let poll = row.try_get("poll")?;
+        let msgtype = row.try_get("msgtype")?;
+        let sender = row.try_get("sender")?;
+        let redact_previous = row.try_get("redact_previous")?;
+        let attach_ics = row.try_get("attach_ics")?;
+        let holiday_region = row.try_get("holiday_region")?;
+        let skip_if_declined = row.try_get("skip_if_declined")?;
+        let exclude_tentative = row.try_get("exclude_tentative")?;
+        let exclude_needs_action = row.try_get("exclude_needs_action")?;
+        let live_countdown = row.try_get("live_countdown")?;
+        let enabled = row.try_get("enabled")?;
+        let weekdays = row.try_get("weekdays")?;
+        let template_id = row.try_get("template_id")?;
+        let locale = row.try_get("locale")?;
+
+        let reminder = Reminder {
+            reminder_id,
+            calendar_id,
+            user_id,
+            event_id,
+            template,
+            minutes_before,
+            room,
+            attendee_editable,
+            extra_attendees,
+            excluded_attendees,
+            threaded,
+            poll,
+            msgtype,
+            sender,
+            redact_previous,
+            attach_ics,
+            holiday_region,
+            skip_if_declined,
+            exclude_tentative,
+            exclude_needs_action,
+            live_countdown,
+            enabled,
+            weekdays,
+            template_id,
+            locale,
+        };
+
+        Ok(Some(reminder))
+    }
+
+    /// Get the stored mappings from email to matrix IDs. The preferred
+    /// Matrix ID for each email (if any) comes first.
+    ///
+    /// Users' verified email aliases map to the same Matrix IDs as their
+    /// primary email, so that people invited under an alias are recognised.
+    pub async fn get_user_mappings(&self) -> Result<BTreeMap<String, Vec<String>>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT email, matrix_id, preferred FROM email_to_matrix_id
+                    UNION
+                    SELECT user_emails.email, m.matrix_id, m.preferred
+                    FROM user_emails
+                    INNER JOIN users USING (user_id)
+                    INNER JOIN email_to_matrix_id AS m ON m.email = users.email
+                    WHERE user_emails.verified
+                    ORDER BY email, preferred DESC, matrix_id
+                "#,
+                &[],
+            )
+            .await?;
+
+        let mut mapping: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for row in rows {
+            let matrix_ids = mapping.entry(row.get(0)).or_default();
+
+            // The alias may also have been mapped directly.
+            let matrix_id: String = row.get(1);
+            if !matrix_ids.contains(&matrix_id) {
+                matrix_ids.push(matrix_id);
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// Return the email of this user, or an error if the user does
+    /// not exist.
+    pub async fn get_email(&self, user_id: i64) -> Result<String, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt("SELECT email FROM users WHERE user_id = $1", &[&user_id])
+            .await?;
+
+        if let Some(row) = row {
+            let email: String = row.try_get(0)?;
+            Ok(email)
+        } else {
+            Err(anyhow::anyhow!("No user with that user_id"))
+        }
+    }
+
+    /// Get the email aliases for the user, in addition to their primary email.
+    pub async fn get_user_emails(&self, user_id: i64) -> Result<Vec<UserEmail>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                "SELECT email, verified FROM user_emails WHERE user_id = $1 ORDER BY email",
+                &[&user_id],
+            )
+            .await?;
+
+        let mut emails = Vec::with_capacity(rows.len());
+        for row in rows {
+            emails.push(UserEmail {
+                email: row.try_get("email")?,
+                verified: row.try_get("verified")?,
+            });
+        }
+
+        Ok(emails)
+    }
+
+    /// Remove an email alias from the user.
+    pub async fn delete_user_email(&self, user_id: i64, email: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "DELETE FROM user_emails WHERE user_id = $1 AND email = $2",
+                &[&user_id, &email],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Return the Matrix IDs mapped to this user, with the preferred one
+    /// first.
+    pub async fn get_matrix_ids(&self, user_id: i64) -> Result<Vec<MatrixId>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT email_to_matrix_id.matrix_id, preferred
+                    FROM email_to_matrix_id
+                    INNER JOIN users USING (email)
+                    WHERE users.user_id = $1
+                    ORDER BY preferred DESC, matrix_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut matrix_ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            matrix_ids.push(MatrixId {
+                matrix_id: row.try_get("matrix_id")?,
+                preferred: row.try_get("preferred")?,
+            });
+        }
+
+        Ok(matrix_ids)
+    }
+
+    /// Check the password matches the hash in the DB for the user with given
+    /// Matrix ID.
+    pub async fn check_password(&self, email: &str, password: &str) -> Result<Option<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT user_id, password_hash FROM users WHERE email = $1",
+                &[&email],
+            )
+            .await?;
+
+        let (user_id, hash) = if let Some(row) = row {
+            let user_id: i64 = row.try_get(0)?;
+            let hash: Option<String> = row.try_get(1)?;
+            (user_id, hash.context("No password found")?)
+        } else {
+            return Ok(None);
+        };
+
+        if bcrypt::verify(password, &hash)? {
+            Ok(Some(user_id))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Check password matches the hash in the DB of the given user.
+    pub async fn check_password_user_id(
+        &self,
+        user_id: i64,
+        password: &str,
+    ) -> Result<Option<()>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT password_hash FROM users WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+
+        let hash = if let Some(row) = row {
+            let hash: Option<String> = row.try_get("password_hash")?;
+
+            hash.context("No password found")?
+        } else {
+            return Ok(None);
+        };
+
+        if bcrypt::verify(password, &hash)? {
+            Ok(Some(()))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Update the password for the users.
+    pub async fn change_password(&self, user_id: i64, password: &str) -> Result<(), Error> {
+        let password = password.to_string();
+        let password_hash = tokio::task::spawn_blocking(|| bcrypt::hash(password, 12)).await??;
+
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "UPDATE users SET password_hash = $1 WHERE user_id = $2",
+                &[&password_hash, &user_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get a page of all users, ordered by user ID.
+    pub async fn get_users(&self, limit: i64, offset: i64) -> Result<Vec<UserSummary>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &*format!("{} ORDER BY user_id LIMIT $1 OFFSET $2", USER_SUMMARY_QUERY),
+                &[&limit, &offset],
+            )
+            .await?;
+
+        rows.iter().map(user_summary_from_row).collect()
+    }
+
+    /// Get the summary of a single user, if they exist.
+    pub async fn get_user(&self, user_id: i64) -> Result<Option<UserSummary>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                &*format!("{} WHERE user_id = $1", USER_SUMMARY_QUERY),
+                &[&user_id],
+            )
+            .await?;
+
+        row.as_ref().map(user_summary_from_row).transpose()
+    }
+
+    /// Get all the reminders the user has created.
+    pub async fn get_reminders_for_user(&self, user_id: i64) -> Result<Vec<UserReminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT reminder_id, r.calendar_id, r.event_id, e.summary, room,
+                        minutes_before, enabled
+                    FROM reminders AS r
+                    LEFT JOIN events AS e USING (calendar_id, event_id)
+                    WHERE r.user_id = $1 AND r.deleted_at IS NULL
+                    ORDER BY reminder_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut reminders = Vec::with_capacity(rows.len());
+        for row in rows {
+            reminders.push(UserReminder {
+                reminder_id: row.try_get("reminder_id")?,
+                calendar_id: row.try_get("calendar_id")?,
+                event_id: row.try_get("event_id")?,
+                summary: row.try_get("summary")?,
+                room: row.try_get("room")?,
+                minutes_before: row.try_get("minutes_before")?,
+                enabled: row.try_get("enabled")?,
+            });
+        }
+
+        Ok(reminders)
+    }
+
+    /// Get the full settings of the reminders the user has created, leaving
+    /// out those added by reminder rules.
+    pub async fn get_reminder_settings_for_user(
+        &self,
+        user_id: i64,
+    ) -> Result<Vec<Reminder>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT calendar_id, event_id, user_id, reminder_id, room, minutes_before,
+                        template, attendee_editable, extra_attendees, excluded_attendees, threaded, poll, msgtype, sender, redact_previous, attach_ics,
+                        holiday_region, skip_if_declined, enabled, weekdays, template_id, locale,
+                        exclude_tentative, exclude_needs_action, live_countdown
+                    FROM reminders
+                    WHERE user_id = $1 AND rule_id IS NULL AND deleted_at IS NULL
+                    ORDER BY reminder_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut reminders = Vec::with_capacity(rows.len());
+        for row in rows {
+            reminders.push(Reminder {
+                reminder_id: row.try_get("reminder_id")?,
+                calendar_id: row.try_get("calendar_id")?,
+                user_id: row.try_get("user_id")?,
+                event_id: row.try_get("event_id")?,
+                template: row.try_get("template")?,
+                minutes_before: row.try_get("minutes_before")?,
+                room: row.try_get("room")?,
+                attendee_editable: row.try_get("attendee_editable")?,
+                extra_attendees: row.try_get("extra_attendees")?,
+                excluded_attendees: row.try_get("excluded_attendees")?,
+                threaded: row.try_get("threaded")?,
+                poll: row.try_get("poll")?,
+                msgtype: row.try_get("msgtype")?,
+                sender: row.try_get("sender")?,
+                redact_previous: row.try_get("redact_previous")?,
+                attach_ics: row.try_get("attach_ics")?,
+                holiday_region: row.try_get("holiday_region")?,
+                skip_if_declined: row.try_get("skip_if_declined")?,
+                exclude_tentative: row.try_get("exclude_tentative")?,
+                exclude_needs_action: row.try_get("exclude_needs_action")?,
+                live_countdown: row.try_get("live_countdown")?,
+                enabled: row.try_get("enabled")?,
+                weekdays: row.try_get("weekdays")?,
+                template_id: row.try_get("template_id")?,
+                locale: row.try_get("locale")?,
+            });
+        }
+
+        Ok(reminders)
+    }
+
+    /// Get the IDs of the user's calendars that have an event with the given
+    /// ID, i.e. the event's UID.
+    pub async fn get_calendars_with_event(
+        &self,
+        user_id: i64,
+        event_id: &str,
+    ) -> Result<Vec<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT calendar_id FROM events
+                    INNER JOIN calendars USING (calendar_id)
+                    WHERE user_id = $1 AND event_id = $2 AND deleted_at IS NULL
+                    ORDER BY calendar_id
+                "#,
+                &[&user_id, &event_id],
+            )
+            .await?;
+
+        let calendar_ids = rows
+            .into_iter()
+            .map(|row| row.try_get("calendar_id"))
+            .collect::<Result<_, _>>()?;
+
+        Ok(calendar_ids)
+    }
+
+    /// Whether the user has been made an admin.
+    pub async fn is_admin(&self, user_id: i64) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt("SELECT is_admin FROM users WHERE user_id = $1", &[&user_id])
+            .await?;
+
+        if let Some(row) = row {
+            Ok(row.try_get("is_admin")?)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Make the user an admin, or stop them being one. Returns whether the
+    /// user exists.
+    pub async fn set_admin(&self, user_id: i64, is_admin: bool) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .execute(
+                "UPDATE users SET is_admin = $2 WHERE user_id = $1",
+                &[&user_id, &is_admin],
+            )
+            .await?;
+
+        Ok(rows > 0)
+    }
+
+    /// Schedule the user's account for deletion.
+    pub async fn request_user_deletion(
+        &self,
+        user_id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    UPDATE users SET deletion_requested_at = $2
+                    WHERE user_id = $1 AND deletion_requested_at IS NULL
+                "#,
+                &[&user_id, &now],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Cancel a pending deletion of the user's account.
+    pub async fn cancel_user_deletion(&self, user_id: i64) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "UPDATE users SET deletion_requested_at = NULL WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get when the user asked for their account to be deleted, if they have.
+    pub async fn get_user_deletion_requested_at(
+        &self,
+        user_id: i64,
+    ) -> Result<Option<DateTime<Utc>>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT deletion_requested_at FROM users WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(row.try_get("deletion_requested_at")?)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Get the users who asked for their account to be deleted before the
+    /// given time.
+    pub async fn get_users_pending_deletion(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<Vec<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                "SELECT user_id FROM users WHERE deletion_requested_at < $1",
+                &[&before],
+            )
+            .await?;
+
+        let mut user_ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            user_ids.push(row.try_get("user_id")?);
+        }
+
+        Ok(user_ids)
+    }
+
+    /// Permanently delete the user and everything they own, including their
+    /// calendars and any reminders (of any user) on them.
+    ///
+    /// This includes the Matrix IDs mapped to their email addresses, so they
+    /// are no longer mentioned as an attendee in other people's reminders.
+    ///
+    /// Returns whether the user existed.
+    pub async fn delete_user(&self, user_id: i64) -> Result<bool, Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        let exists = txn
+            .query_opt("SELECT 1 FROM users WHERE user_id = $1", &[&user_id])
+            .await?
+            .is_some();
+        if !exists {
+            return Ok(false);
+        }
+
+        let calendar_ids: Vec<i64> = txn
+            .query(
+                "SELECT calendar_id FROM calendars WHERE user_id = $1",
+                &[&user_id],
+            )
+            .await?
+            .iter()
+            .map(|row| row.try_get("calendar_id"))
+            .collect::<Result<_, _>>()?;
+
+        let reminder_ids: Vec<i64> = txn
+            .query(
+                r#"
+                    SELECT reminder_id FROM reminders
+                    WHERE user_id = $1 OR calendar_id = ANY($2)
+                    UNION
+                    SELECT reminder_id FROM adhoc_reminders WHERE user_id = $1
+                "#,
+                &[&user_id, &calendar_ids],
+            )
+            .await?
+            .iter()
+            .map(|row| row.try_get("reminder_id"))
+            .collect::<Result<_, _>>()?;
+
+        txn.execute(
+            r#"
+                DELETE FROM poll_responses
+                WHERE poll_event_id IN (
+                    SELECT poll_event_id FROM reminder_polls WHERE reminder_id = ANY($1)
+                )
+            "#,
+            &[&reminder_ids],
+        )
+        .await?;
+
+        for table in &[
+            "reminder_images",
+            "reminder_polls",
+            "attendance",
+            "last_sent_reminders",
+            "sent_reminders",
+            "reminder_log",
+            "reminder_retries",
+            "reminders",
+            "adhoc_reminders",
+        ] {
+            txn.execute(
+                &*format!("DELETE FROM {} WHERE reminder_id = ANY($1)", table),
+                &[&reminder_ids],
+            )
+            .await?;
+        }
+
+        for table in &["reminder_rules", "event_subscriptions"] {
+            txn.execute(
+                &*format!(
+                    "DELETE FROM {} WHERE user_id = $1 OR calendar_id = ANY($2)",
+                    table
+                ),
+                &[&user_id, &calendar_ids],
+            )
+            .await?;
+        }
+
+        for table in &[
+            "next_dates",
+            "events",
+            "calendar_errors",
+            "calendar_passwords",
+            "calendar_oauth2",
+            "calendar_files",
+            "calendar_objects",
+            "calendars",
+        ] {
+            txn.execute(
+                &*format!("DELETE FROM {} WHERE calendar_id = ANY($1)", table),
+                &[&calendar_ids],
+            )
+            .await?;
+        }
+
+        // The user's Matrix IDs are keyed by their email addresses, so go
+        // before the aliases do.
+        txn.execute(
+            r#"
+                DELETE FROM email_to_matrix_id
+                WHERE email IN (
+                    SELECT email FROM users WHERE user_id = $1
+                    UNION
+                    SELECT email FROM user_emails WHERE user_id = $1 AND verified
+                )
+            "#,
+            &[&user_id],
+        )
+        .await?;
+
+        for table in &[
+            "oauth2_tokens",
+            "oauth2_accounts",
+            "oauth2_sessions",
+            "templates",
+            "webhooks",
+            "access_tokens",
+            "api_tokens",
+            "feed_tokens",
+            "widget_tokens",
+            "user_emails",
+        ] {
+            txn.execute(
+                &*format!("DELETE FROM {} WHERE user_id = $1", table),
+                &[&user_id],
+            )
+            .await?;
+        }
+
+        txn.execute("DELETE FROM users WHERE user_id = $1", &[&user_id])
+            .await?;
+
+        txn.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Add an access token for the user.
+    pub async fn add_access_token(
+        &self,
+        user_id: i64,
+        token: &str,
+        expiry: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "INSERT INTO access_tokens (user_id, token, expiry) VALUES ($1, $2, $3)",
+                &[&user_id, &token, &expiry],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the user associated with the access token, and when the token
+    /// expires.
+    pub async fn get_user_from_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<(i64, DateTime<Utc>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT user_id, expiry FROM access_tokens WHERE token = $1 AND expiry > NOW()",
+                &[&token],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(Some((row.try_get("user_id")?, row.try_get("expiry")?)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Push back when the access token expires.
+    pub async fn refresh_access_token(
+        &self,
+        token: &str,
+        expiry: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "UPDATE access_tokens SET expiry = $2 WHERE token = $1",
+                &[&token, &expiry],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Revoke the access token, e.g. when the user logs out. Returns whether
+    /// the token existed.
+    pub async fn revoke_access_token(&self, token: &str) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .execute("DELETE FROM access_tokens WHERE token = $1", &[&token])
+            .await?;
+
+        Ok(rows > 0)
+    }
+
+    /// Get the number of failed attempts for the rate limit key in the window
+    /// that started after `since`.
+    pub async fn get_rate_limit_attempts(
+        &self,
+        key: &str,
+        since: DateTime<Utc>,
+    ) -> Result<i32, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT attempts FROM rate_limits WHERE key = $1 AND window_start > $2",
+                &[&key, &since],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(row.try_get("attempts")?)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Record a failed attempt for the rate limit key, starting a new window
+    /// if the current one started before `since`.
+    pub async fn record_rate_limit_attempt(
+        &self,
+        key: &str,
+        now: DateTime<Utc>,
+        since: DateTime<Utc>,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO rate_limits (key, window_start, attempts)
+                    VALUES ($1, $2, 1)
+                    ON CONFLICT (key) DO UPDATE SET
+                        attempts = CASE
+                            WHEN rate_limits.window_start > $3 THEN rate_limits.attempts + 1
+                            ELSE 1
+                        END,
+                        window_start = CASE
+                            WHEN rate_limits.window_start > $3 THEN rate_limits.window_start
+                            ELSE EXCLUDED.window_start
+                        END
+                "#,
+                &[&key, &now, &since],
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Forget the failed attempts for the rate limit key.
+    pub async fn delete_rate_limit(&self, key: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute("DELETE FROM rate_limits WHERE key = $1", &[&key])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Delete rate limit windows that started before the given time, and so
+    /// no longer count.
+    pub async fn delete_old_rate_limits(&self, before: DateTime<Utc>) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                "DELETE FROM rate_limits WHERE window_start < $1",
+                &[&before],
+            )
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Delete login sessions that have expired.
+    pub async fn delete_expired_access_tokens(&self) -> Result<u64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute("DELETE FROM access_tokens WHERE expiry < NOW()", &[])
+            .await?;
+
+        Ok(count)
+    }
+
+    /// Delete in flight SSO and OAuth2 sessions started before the given
+    /// time, i.e. where the user never came back from the provider.
+    pub async fn delete_stale_sso_sessions(
+        &self,
+        before: DateTime<Utc>,
+    ) -> Result<(u64, u64), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let num_sso = db_conn
+            .execute("DELETE FROM sso_sessions WHERE created_at < $1", &[&before])
+            .await?;
+
+        let num_oauth2 = db_conn
+            .execute(
+                "DELETE FROM oauth2_sessions WHERE created_at < $1",
+                &[&before],
+            )
+            .await?;
+
+        Ok((num_sso, num_oauth2))
+    }
+
+    /// Add a personal access token for the user, returning its ID.
+    pub async fn add_api_token(
+        &self,
+        user_id: i64,
+        name: &str,
+        token_hash: &str,
+        scopes: &[String],
+    ) -> Result<i64, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO api_tokens (user_id, name, token_hash, scopes)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING api_token_id
+                "#,
+                &[&user_id, &name, &token_hash, &scopes],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Get the user's personal access tokens.
+    pub async fn get_api_tokens(&self, user_id: i64) -> Result<Vec<ApiToken>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT api_token_id, user_id, name, scopes, created_at, last_used
+                    FROM api_tokens
+                    WHERE user_id = $1
+                    ORDER BY created_at, api_token_id
+                "#,
+                &[&user_id],
+            )
+            .await?;
+
+        let mut tokens = Vec::with_capacity(rows.len());
+        for row in rows {
+            tokens.push(ApiToken {
+                api_token_id: row.try_get("api_token_id")?,
+                user_id: row.try_get("user_id")?,
+                name: row.try_get("name")?,
+                scopes: row.try_get("scopes")?,
+                created_at: row.try_get("created_at")?,
+                last_used: row.try_get("last_used")?,
+            });
+        }
 
-            if date < Utc::now() {
-                // ignore events in the past
-                continue;
-            }
+        Ok(tokens)
+    }
 
-            let instance = EventInstance {
-                event_id: event_id.clone(),
-                date,
-                attendees: instance_attendees,
-            };
+    /// Revoke one of the user's personal access tokens.
+    pub async fn delete_api_token(&self, user_id: i64, api_token_id: i64) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
 
-            if let Some((event, instances)) = events.last_mut() {
-                if event.event_id == event_id {
-                    instances.push(instance);
-                    continue;
-                }
-            }
+        let count = db_conn
+            .execute(
+                "DELETE FROM api_tokens WHERE user_id = $1 AND api_token_id = $2",
+                &[&user_id, &api_token_id],
+            )
+            .await?;
 
-            let event = Event {
-                calendar_id,
-                event_id,
-                summary,
-                description,
-                location,
-                organizer,
-                attendees: event_attendees,
-            };
-            events.push((event, vec![instance]));
-        }
+        Ok(count > 0)
+    }
 
-        events.sort_by_key(|(_, i)| i[0].date);
+    /// Get the user and scopes associated with the hash of a personal access
+    /// token, marking the token as used.
+    pub async fn get_user_from_api_token(
+        &self,
+        token_hash: &str,
+    ) -> Result<Option<(i64, Vec<String>)>, Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        Ok(events)
+        let row = db_conn
+            .query_opt(
+                r#"
+                    UPDATE api_tokens SET last_used = NOW()
+                    WHERE token_hash = $1
+                    RETURNING user_id, scopes
+                "#,
+                &[&token_hash],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(Some((row.try_get("user_id")?, row.try_get("scopes")?)))
+        } else {
+            Ok(None)
+        }
     }
 
-    /// Get all events from all a given user's calendars.
-    pub async fn get_events_for_user(
+    /// Add a token that gives access to the widget for the room, unless the
+    /// user already has one for the room.
+    ///
+    /// Returns the token that is stored.
+    pub async fn add_widget_token(
         &self,
         user_id: i64,
-    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
+        room: &str,
+        token: &str,
+    ) -> Result<String, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_one(
+                r#"
+                    INSERT INTO widget_tokens (user_id, room, token) VALUES ($1, $2, $3)
+                    ON CONFLICT (user_id, room) DO UPDATE SET token = widget_tokens.token
+                    RETURNING token
+                "#,
+                &[&user_id, &room, &token],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Get the user's widget tokens.
+    pub async fn get_widget_tokens(&self, user_id: i64) -> Result<Vec<WidgetToken>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let rows = db_conn
             .query(
                 r#"
-                    SELECT DISTINCT ON (calendar_id, event_id) calendar_id, event_id, summary, description, location, timestamp,
-                        organizer, e.attendees AS event_attendees, i.attendees AS instance_attendees
-                    FROM calendars
-                    INNER JOIN events AS e USING (calendar_id)
-                    INNER JOIN next_dates AS i USING (calendar_id, event_id)
-                    WHERE user_id = $1 AND timestamp > now()
-                    ORDER BY calendar_id, event_id, timestamp
+                    SELECT room, token, created_at
+                    FROM widget_tokens
+                    WHERE user_id = $1
+                    ORDER BY room
                 "#,
                 &[&user_id],
             )
             .await?;
 
-        let mut events: Vec<(Event, Vec<EventInstance>)> = Vec::with_capacity(rows.len());
-
+        let mut tokens = Vec::with_capacity(rows.len());
         for row in rows {
-            let calendar_id = row.try_get("calendar_id")?;
-            let event_id: String = row.try_get("event_id")?;
-            let summary = row.try_get("summary")?;
-            let description = row.try_get("description")?;
-            let location = row.try_get("location")?;
-            let date = row.try_get("timestamp")?;
-            let organizer = row.try_get("organizer")?;
-            let instance_attendees = row.try_get("instance_attendees")?;
-            let event_attendees = row.try_get("event_attendees")?;
+            tokens.push(WidgetToken {
+                room: row.try_get("room")?,
+                token: row.try_get("token")?,
+                created_at: row.try_get("created_at")?,
+            });
+        }
 
-            if date < Utc::now() {
-                // ignore events in the past
-                continue;
-            }
+        Ok(tokens)
+    }
 
-            let instance = EventInstance {
-                event_id: event_id.clone(),
-                date,
-                attendees: instance_attendees,
-            };
+    /// Revoke the user's widget token for the room.
+    pub async fn delete_widget_token(&self, user_id: i64, room: &str) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
 
-            if let Some((event, instances)) = events.last_mut() {
-                if event.event_id == event_id {
-                    instances.push(instance);
-                    continue;
-                }
-            }
+        let count = db_conn
+            .execute(
+                "DELETE FROM widget_tokens WHERE user_id = $1 AND room = $2",
+                &[&user_id, &room],
+            )
+            .await?;
 
-            let event = Event {
-                calendar_id,
-                event_id,
-                summary,
-                description,
-                location,
-                organizer,
-                attendees: event_attendees,
-            };
-            events.push((event, vec![instance]));
+        Ok(count > 0)
+    }
+
+    /// Get the user associated with the widget token, if it is for the room.
+    pub async fn get_user_from_widget_token(
+        &self,
+        token: &str,
+        room: &str,
+    ) -> Result<Option<i64>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT user_id FROM widget_tokens WHERE token = $1 AND room = $2",
+                &[&token, &room],
+            )
+            .await?;
+
+        if let Some(row) = row {
+            Ok(Some(row.try_get(0)?))
+        } else {
+            Ok(None)
         }
+    }
 
-        events.sort_by_key(|(_, i)| i[0].date);
+    /// Replace the stored public holidays for the region.
+    pub async fn set_public_holidays(
+        &self,
+        region: &str,
+        holidays: &[PublicHoliday],
+    ) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
 
-        Ok(events)
+        let txn = db_conn.transaction().await?;
+
+        txn.execute("DELETE FROM public_holidays WHERE region = $1", &[&region])
+            .await?;
+
+        for holiday in holidays {
+            txn.execute(
+                r#"
+                    INSERT INTO public_holidays (region, date, name) VALUES ($1, $2, $3)
+                    ON CONFLICT (region, date) DO NOTHING
+                "#,
+                &[&region, &holiday.date, &holiday.name],
+            )
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(())
     }
 
-    /// Get all events for user that have reminders
-    pub async fn get_events_with_reminders(
+    /// Check if the date is a public holiday in the region.
+    pub async fn is_public_holiday(&self, region: &str, date: NaiveDate) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                "SELECT 1 FROM public_holidays WHERE region = $1 AND date = $2",
+                &[&region, &date],
+            )
+            .await?;
+
+        Ok(row.is_some())
+    }
+
+    /// Get the quiet hours set for the room, if any.
+    pub async fn get_room_quiet_hours(&self, room: &str) -> Result<Option<QuietHours>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let row = db_conn
+            .query_opt(
+                r#"
+                    SELECT start_time, end_time, weekends, timezone, suppress
+                    FROM room_quiet_hours
+                    WHERE room = $1
+                "#,
+                &[&room],
+            )
+            .await?;
+
+        let row = if let Some(row) = row {
+            row
+        } else {
+            return Ok(None);
+        };
+
+        let timezone: String = row.try_get("timezone")?;
+        let suppress: bool = row.try_get("suppress")?;
+
+        Ok(Some(QuietHours {
+            start: row.try_get("start_time")?,
+            end: row.try_get("end_time")?,
+            weekends: row.try_get("weekends")?,
+            timezone: timezone
+                .parse()
+                .map_err(|_| anyhow::anyhow!("Unknown timezone '{}'", timezone))?,
+            action: if suppress {
+                QuietHoursAction::Suppress
+            } else {
+                QuietHoursAction::Defer
+            },
+        }))
+    }
+
+    /// Set the quiet hours for the room, replacing any existing ones.
+    pub async fn set_room_quiet_hours(
         &self,
-        user_id: i64,
-    ) -> Result<Vec<(Event, Vec<EventInstance>)>, Error> {
-        let events = self.get_events_for_user(user_id).await?;
+        room: &str,
+        quiet_hours: &QuietHours,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let mut filtered_events = Vec::with_capacity(events.len());
-        for (event, instance) in events {
-            let reminders = self
-                .get_reminders_for_event(event.calendar_id, &event.event_id)
-                .await?;
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO room_quiet_hours (room, start_time, end_time, weekends, timezone, suppress)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT (room) DO UPDATE SET
+                        start_time = EXCLUDED.start_time,
+                        end_time = EXCLUDED.end_time,
+                        weekends = EXCLUDED.weekends,
+                        timezone = EXCLUDED.timezone,
+                        suppress = EXCLUDED.suppress
+                "#,
+                &[
+                    &room,
+                    &quiet_hours.start,
+                    &quiet_hours.end,
+                    &quiet_hours.weekends,
+                    &quiet_hours.timezone.name(),
+                    &(quiet_hours.action == QuietHoursAction::Suppress),
+                ],
+            )
+            .await?;
 
-            if !reminders.is_empty() {
-                filtered_events.push((event, instance))
-            }
+        Ok(())
+    }
+
+    /// Remove the quiet hours for the room, so that the ones in the config
+    /// apply.
+    pub async fn delete_room_quiet_hours(&self, room: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute("DELETE FROM room_quiet_hours WHERE room = $1", &[&room])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the saved templates the user can use, i.e. their own and those
+    /// shared by others.
+    pub async fn get_templates_for_user(&self, user_id: i64) -> Result<Vec<SavedTemplate>, Error> {
+        self.get_templates_with_filter("user_id = $1 OR shared", &[&user_id])
+            .await
+    }
+
+    /// Get a saved template.
+    pub async fn get_template(&self, template_id: i64) -> Result<Option<SavedTemplate>, Error> {
+        let mut templates = self
+            .get_templates_with_filter("template_id = $1", &[&template_id])
+            .await?;
+
+        Ok(templates.pop())
+    }
+
+    async fn get_templates_with_filter(
+        &self,
+        where_sql: &str,
+        params: &[&(dyn ToSql + Sync)],
+    ) -> Result<Vec<SavedTemplate>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                &format!(
+                    r#"
+                    SELECT template_id, user_id, name, template, shared
+                    FROM templates
+                    WHERE {where_sql}
+                    ORDER BY name, template_id
+                "#
+                ),
+                params,
+            )
+            .await?;
+
+        let mut templates = Vec::with_capacity(rows.len());
+        for row in rows {
+            templates.push(SavedTemplate {
+                template_id: row.try_get("template_id")?,
+                user_id: row.try_get("user_id")?,
+                name: row.try_get("name")?,
+                template: row.try_get("template")?,
+                shared: row.try_get("shared")?,
+            });
         }
 
-        Ok(filtered_events)
+        Ok(templates)
     }
 
-    /// Get the specified event
-    pub async fn get_event_in_calendar(
+    /// Save a new template, returning its ID.
+    pub async fn add_template(
         &self,
-        calendar_id: i64,
-        event_id: &str,
-    ) -> Result<Option<(Event, Vec<EventInstance>)>, Error> {
+        user_id: i64,
+        name: &str,
+        template: &str,
+        shared: bool,
+    ) -> Result<i64, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let row = db_conn
-            .query_opt(
+            .query_one(
                 r#"
-                    SELECT DISTINCT ON (event_id) event_id, summary, description, location,
-                        organizer, attendees
-                    FROM events
-                    WHERE calendar_id = $1 AND event_id = $2
+                    INSERT INTO templates (user_id, name, template, shared)
+                    VALUES ($1, $2, $3, $4)
+                    RETURNING template_id
                 "#,
-                &[&calendar_id, &event_id],
+                &[&user_id, &name, &template, &shared],
+            )
+            .await?;
+
+        Ok(row.try_get(0)?)
+    }
+
+    /// Update one of the user's saved templates. Returns false if the user
+    /// doesn't have a template with that ID.
+    pub async fn update_template(
+        &self,
+        user_id: i64,
+        template_id: i64,
+        name: &str,
+        template: &str,
+        shared: bool,
+    ) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let count = db_conn
+            .execute(
+                r#"
+                    UPDATE templates SET name = $3, template = $4, shared = $5
+                    WHERE user_id = $1 AND template_id = $2
+                "#,
+                &[&user_id, &template_id, &name, &template, &shared],
             )
             .await?;
 
-        let row = if let Some(row) = row {
-            row
-        } else {
-            return Ok(None);
-        };
+        Ok(count > 0)
+    }
 
-        let event_id: String = row.try_get("event_id")?;
-        let summary = row.try_get("summary")?;
-        let description = row.try_get("description")?;
-        let location = row.try_get("location")?;
-        let attendees = row.try_get("attendees")?;
-        let organizer = row.try_get("organizer")?;
+    /// Delete one of the user's saved templates. Reminders using it go back to
+    /// their own template. Returns false if the user doesn't have a template
+    /// with that ID.
+    pub async fn delete_template(&self, user_id: i64, template_id: i64) -> Result<bool, Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let event = Event {
-            calendar_id,
-            event_id: event_id.clone(),
-            summary,
-            description,
-            location,
-            attendees,
-            organizer,
-        };
+        let count = db_conn
+            .execute(
+                "DELETE FROM templates WHERE user_id = $1 AND template_id = $2",
+                &[&user_id, &template_id],
+            )
+            .await?;
 
-        let mut instances = Vec::new();
+        Ok(count > 0)
+    }
+
+    /// Get the images uploaded for the reminder.
+    pub async fn get_reminder_images(&self, reminder_id: i64) -> Result<Vec<ReminderImage>, Error> {
+        let db_conn = self.db_pool.get().await?;
 
         let rows = db_conn
             .query(
                 r#"
-                    SELECT timestamp, attendees
-                    FROM next_dates
-                    WHERE calendar_id = $1 AND event_id = $2
-                    ORDER BY timestamp
+                    SELECT reminder_id, name, content_type, content_uri
+                    FROM reminder_images
+                    WHERE reminder_id = $1
+                    ORDER BY name
                 "#,
-                &[&calendar_id, &event_id],
+                &[&reminder_id],
             )
             .await?;
 
+        let mut images = Vec::with_capacity(rows.len());
         for row in rows {
-            let date: DateTime<FixedOffset> = row.get("timestamp");
-            let attendees: Vec<Attendee> = row.get("attendees");
-
-            if date < Utc::now() {
-                // ignore events in the past
-                continue;
-            }
+            images.push(ReminderImage {
+                reminder_id: row.try_get("reminder_id")?,
+                name: row.try_get("name")?,
+                content_type: row.try_get("content_type")?,
+                content_uri: row.try_get("content_uri")?,
+            });
+        }
 
-            let instance = EventInstance {
-                event_id: event_id.clone(),
-                date,
-                attendees,
-            };
+        Ok(images)
+    }
 
-            instances.push(instance);
-        }
+    /// Add an image to the reminder, replacing any existing image with the
+    /// same name.
+    pub async fn add_reminder_image(&self, image: &ReminderImage) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        instances.sort_by_key(|i| i.date);
+        db_conn
+            .execute(
+                r#"
+                    INSERT INTO reminder_images (reminder_id, name, content_type, content_uri)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT (reminder_id, name) DO UPDATE
+                    SET content_type = EXCLUDED.content_type, content_uri = EXCLUDED.content_uri
+                "#,
+                &[
+                    &image.reminder_id,
+                    &image.name,
+                    &image.content_type,
+                    &image.content_uri,
+                ],
+            )
+            .await?;
 
-        Ok(Some((event, instances)))
+        Ok(())
     }
 
-    /// Get reminders for the event, including reminders in other people's
-    /// calendars that are shared.
-    pub async fn get_reminders_for_event(
-        &self,
-        calendar_id: i64,
-        event_id: &str,
-    ) -> Result<Vec<Reminder>, Error> {
+    /// Remove an image from the reminder. Returns false if there was no such
+    /// image.
+    pub async fn delete_reminder_image(&self, reminder_id: i64, name: &str) -> Result<bool, Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let rows = db_conn
-            .query(
-                r#"
-                    SELECT DISTINCT ON (reminder_id) reminders.calendar_id, reminders.user_id, reminder_id, room,
-                        minutes_before, attendee_editable, template
-                    FROM (
-                        SELECT user_id, calendar_id, event_id, attendees
-                        FROM events
-                        INNER JOIN calendars USING (calendar_id)
-                        WHERE event_id = $1
-                    ) AS c
-                    INNER JOIN users USING (user_id)
-                    INNER JOIN reminders USING (event_id)
-                    WHERE
-                        c.calendar_id = $2
-                        AND (
-                            -- Either the event is in their own calendar...
-                            reminders.calendar_id = $2
-                            --- Or the reminder is attendee editable and they are an attendee
-                            OR (attendee_editable AND users.email IN (
-                                SELECT email FROM UNNEST(attendees)
-                            ))
-                        )
-                    "#,
-                &[&event_id, &calendar_id],
+        let count = db_conn
+            .execute(
+                "DELETE FROM reminder_images WHERE reminder_id = $1 AND name = $2",
+                &[&reminder_id, &name],
             )
             .await?;
 
-        let mut reminders = Vec::with_capacity(rows.len());
-        for row in rows {
-            let reminder_calendar_id = row.try_get("calendar_id")?;
-            let user_id = row.try_get("user_id")?;
-            let reminder_id = row.try_get("reminder_id")?;
-            let room = row.try_get("room")?;
-            let minutes_before = row.try_get("minutes_before")?;
-            let template = row.try_get("template")?;
-            let attendee_editable = row.try_get("attendee_editable")?;
+        Ok(count > 0)
+    }
 
-            let reminder = Reminder {
-                reminder_id,
-                user_id,
-                calendar_id: reminder_calendar_id,
-                event_id: event_id.to_string(),
-                room,
-                minutes_before,
-                template,
-                attendee_editable,
-            };
-            reminders.push(reminder)
-        }
+    /// Get the default template for reminders sent to the room, if any.
+    pub async fn get_room_template(&self, room: &str) -> Result<Option<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        Ok(reminders)
+        let row = db_conn
+            .query_opt(
+                "SELECT template FROM room_templates WHERE room = $1",
+                &[&room],
+            )
+            .await?;
+
+        Ok(row.map(|row| row.try_get("template")).transpose()?)
     }
 
-    /// Get the list of users that can edit an event.
-    ///
-    /// This is the owner of the reminder, and if the `attendee_editable` flag
-    /// is set, all attendees.
-    pub async fn get_users_who_can_edit_reminder(
-        &self,
-        reminder_id: i64,
-    ) -> Result<Vec<i64>, Error> {
+    /// Set the default template for reminders sent to the room, replacing any
+    /// existing one.
+    pub async fn set_room_template(&self, room: &str, template: &str) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let rows = db_conn
-            .query(
+        db_conn
+            .execute(
                 r#"
-                    SELECT c.user_id FROM (
-                        SELECT user_id, calendar_id, event_id, attendees
-                        FROM events
-                        INNER JOIN calendars USING (calendar_id)
-                    ) AS c
-                    INNER JOIN users USING (user_id)
-                    INNER JOIN reminders USING (event_id)
-                    WHERE reminder_id = $1
-                        AND (
-                            -- Either the event is in their own calendar...
-                            reminders.calendar_id = c.calendar_id
-                            --- Or the reminder is attendee editable and they are an attendee
-                            OR (attendee_editable AND users.email IN (
-                                SELECT email FROM UNNEST(attendees)
-                            ))
-                        )
-                    "#,
-                &[&reminder_id],
+                    INSERT INTO room_templates (room, template) VALUES ($1, $2)
+                    ON CONFLICT (room) DO UPDATE SET template = EXCLUDED.template
+                "#,
+                &[&room, &template],
             )
             .await?;
 
-        let mut users = Vec::with_capacity(rows.len());
+        Ok(())
+    }
 
-        for row in rows {
-            let user_id = row.try_get("user_id")?;
-            users.push(user_id);
-        }
+    /// Remove the default template for the room, so that the global default
+    /// applies.
+    pub async fn delete_room_template(&self, room: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        Ok(users)
+        db_conn
+            .execute("DELETE FROM room_templates WHERE room = $1", &[&room])
+            .await?;
+
+        Ok(())
     }
 
-    /// Get a reminder for event
-    pub async fn get_reminder_in_calendar(
-        &self,
-        calendar_id: i64,
-        reminder_id: i64,
-    ) -> Result<Option<Reminder>, Error> {
+    /// Get the language to render reminders sent to the room in, if set.
+    pub async fn get_room_locale(&self, room: &str) -> Result<Option<String>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let row = db_conn
-            .query_opt(
+            .query_opt("SELECT locale FROM room_locales WHERE room = $1", &[&room])
+            .await?;
+
+        Ok(row.map(|row| row.try_get("locale")).transpose()?)
+    }
+
+    /// Set the language to render reminders sent to the room in, replacing
+    /// any existing one.
+    pub async fn set_room_locale(&self, room: &str, locale: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
                 r#"
-                    SELECT calendar_id, event_id, user_id, reminder_id, room, minutes_before,
-                        template, attendee_editable
-                    FROM reminders
-                    WHERE calendar_id = $1 AND reminder_id = $2
+                    INSERT INTO room_locales (room, locale) VALUES ($1, $2)
+                    ON CONFLICT (room) DO UPDATE SET locale = EXCLUDED.locale
                 "#,
-                &[&calendar_id, &reminder_id],
+                &[&room, &locale],
             )
             .await?;
 
-        let row = if let Some(row) = row {
-            row
-        } else {
-            return Ok(None);
-        };
+        Ok(())
+    }
 
-        let calendar_id = row.try_get("calendar_id")?;
-        let reminder_id = row.try_get("reminder_id")?;
-        let user_id = row.try_get("user_id")?;
-        let event_id = row.try_get("event_id")?;
-        let room = row.try_get("room")?;
-        let minutes_before = row.try_get("minutes_before")?;
-        let template = row.try_get("template")?;
-        let attendee_editable = row.try_get("attendee_editable")?;
+    /// Remove the room's language, so that the default applies.
+    pub async fn delete_room_locale(&self, room: &str) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        let reminder = Reminder {
-            reminder_id,
-            calendar_id,
-            user_id,
-            event_id,
-            template,
-            minutes_before,
-            room,
-            attendee_editable,
-        };
+        db_conn
+            .execute("DELETE FROM room_locales WHERE room = $1", &[&room])
+            .await?;
+
+        Ok(())
+    }
+
+    /// Persist all emails that are on holiday today.
+    pub async fn set_out_today(&self, emails: &[String]) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+
+        let txn = db_conn.transaction().await?;
+
+        txn.execute("TRUNCATE out_today", &[]).await?;
+
+        futures::future::try_join_all(
+            emails
+                .iter()
+                .map(|email| txn.execute_raw("INSERT INTO out_today VALUES ($1)", vec![email])),
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get all emails that are on holiday today.
+    pub async fn get_out_today_emails(&self) -> Result<BTreeSet<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn.query("SELECT email FROM out_today", &[]).await?;
+
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+    }
+
+    /// Get all matrix IDs that are on holiday today.
+    pub async fn get_out_today_matrix_ids(&self) -> Result<BTreeSet<String>, Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        let rows = db_conn
+            .query(
+                r#"
+                SELECT matrix_id FROM out_today
+                INNER JOIN email_to_matrix_id USING (email)
+                "#,
+                &[],
+            )
+            .await?;
 
-        Ok(Some(reminder))
+        Ok(rows.into_iter().map(|row| row.get(0)).collect())
     }
 
-    /// Get the stored mappings from email to matrix ID.
-    pub async fn get_user_mappings(&self) -> Result<BTreeMap<String, String>, Error> {
+    /// Get whether the user has opted out of being mentioned in reminders.
+    pub async fn get_mentions_disabled(&self, user_id: i64) -> Result<bool, Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let rows = db_conn
-            .query("SELECT email, matrix_id FROM email_to_matrix_id", &[])
+        let row = db_conn
+            .query_one(
+                "SELECT mentions_disabled FROM users WHERE user_id = $1",
+                &[&user_id],
+            )
             .await?;
 
-        let mapping: BTreeMap<String, String> = rows
-            .into_iter()
-            .map(|row| (row.get(0), row.get(1)))
-            .collect();
+        Ok(row.try_get(0)?)
+    }
 
-        Ok(mapping)
+    /// Set whether the user has opted out of being mentioned in reminders.
+    pub async fn set_mentions_disabled(&self, user_id: i64, disabled: bool) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
+
+        db_conn
+            .execute(
+                "UPDATE users SET mentions_disabled = $2 WHERE user_id = $1",
+                &[&user_id, &disabled],
+            )
+            .await?;
+
+        Ok(())
     }
 
-    /// Return the email of this user, or an error if the user does
-    /// not exist.
-    pub async fn get_email(&self, user_id: i64) -> Result<String, Error> {
+    /// Get the user's timezone, if they've set one.
+    pub async fn get_user_timezone(&self, user_id: i64) -> Result<Option<String>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let row = db_conn
-            .query_opt("SELECT email FROM users WHERE user_id = $1", &[&user_id])
+            .query_one("SELECT timezone FROM users WHERE user_id = $1", &[&user_id])
             .await?;
 
-        if let Some(row) = row {
-            let email: String = row.try_get(0)?;
-            Ok(email)
-        } else {
-            Err(anyhow::anyhow!("No user with that user_id"))
-        }
+        Ok(row.try_get(0)?)
     }
 
-    /// Return the Matrix ID of this user, or None if no Matrix ID is mapped
-    /// for this user, or an error if the user does not exist.
-    pub async fn get_matrix_id(&self, user_id: i64) -> Result<Option<String>, Error> {
+    /// Set the user's timezone, or clear it if `None`.
+    pub async fn set_user_timezone(
+        &self,
+        user_id: i64,
+        timezone: Option<&str>,
+    ) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let row = db_conn
-            .query_opt(
-                r#"
-                    SELECT email_to_matrix_id.matrix_id
-                    FROM email_to_matrix_id
-                    INNER JOIN users USING (email)
-                    WHERE users.user_id = $1
-                "#,
-                &[&user_id],
+        db_conn
+            .execute(
+                "UPDATE users SET timezone = $2 WHERE user_id = $1",
+                &[&user_id, &timezone],
             )
             .await?;
 
-        if let Some(row) = row {
-            let matrix_id: String = row.try_get(0)?;
-            Ok(Some(matrix_id))
-        } else {
-            Ok(None)
-        }
+        Ok(())
     }
 
-    /// Check the password matches the hash in the DB for the user with given
-    /// Matrix ID.
-    pub async fn check_password(&self, email: &str, password: &str) -> Result<Option<i64>, Error> {
+    /// Get the distinct timezones configured by the users matching the given
+    /// attendees, which may be emails (including verified aliases) or Matrix
+    /// IDs.
+    pub async fn get_attendee_timezones(&self, attendees: &[String]) -> Result<Vec<String>, Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let row = db_conn
-            .query_opt(
-                "SELECT user_id, password_hash FROM users WHERE email = $1",
-                &[&email],
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT DISTINCT users.timezone
+                    FROM UNNEST($1::text[]) AS a(attendee)
+                    INNER JOIN users ON users.timezone IS NOT NULL AND (
+                        users.email = a.attendee
+                        OR EXISTS (
+                            SELECT 1 FROM user_emails AS e
+                            WHERE e.user_id = users.user_id AND e.verified AND e.email = a.attendee
+                        )
+                        OR EXISTS (
+                            SELECT 1 FROM email_to_matrix_id AS m
+                            WHERE m.email = users.email AND m.matrix_id = a.attendee
+                        )
+                    )
+                "#,
+                &[&attendees],
             )
             .await?;
 
-        let (user_id, hash) = if let Some(row) = row {
-            let user_id: i64 = row.try_get(0)?;
-            let hash: Option<String> = row.try_get(1)?;
-            (user_id, hash.context("No password found")?)
-        } else {
-            return Ok(None);
-        };
-
-        if bcrypt::verify(password, &hash)? {
-            Ok(Some(user_id))
-        } else {
-            Ok(None)
+        let mut timezones = Vec::with_capacity(rows.len());
+        for row in rows {
+            timezones.push(row.try_get("timezone")?);
         }
+
+        Ok(timezones)
     }
 
-    /// Check password matches the hash in the DB of the given user.
-    pub async fn check_password_user_id(
+    /// Get the user's weekly summary settings.
+    pub async fn get_weekly_summary_settings(
         &self,
         user_id: i64,
-        password: &str,
-    ) -> Result<Option<()>, Error> {
+    ) -> Result<WeeklySummarySettings, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let row = db_conn
-            .query_opt(
-                "SELECT password_hash FROM users WHERE user_id = $1",
+            .query_one(
+                "SELECT weekly_summary, weekly_summary_timezone FROM users WHERE user_id = $1",
                 &[&user_id],
             )
             .await?;
 
-        let hash = if let Some(row) = row {
-            let hash: Option<String> = row.try_get("password_hash")?;
-
-            hash.context("No password found")?
-        } else {
-            return Ok(None);
-        };
-
-        if bcrypt::verify(password, &hash)? {
-            Ok(Some(()))
-        } else {
-            Ok(None)
-        }
+        Ok(WeeklySummarySettings {
+            enabled: row.try_get("weekly_summary")?,
+            timezone: row.try_get("weekly_summary_timezone")?,
+        })
     }
 
-    /// Update the password for the users.
-    pub async fn change_password(&self, user_id: i64, password: &str) -> Result<(), Error> {
-        let password = password.to_string();
-        let password_hash = tokio::task::spawn_blocking(|| bcrypt::hash(password, 12)).await??;
-
+    /// Set the user's weekly summary settings.
+    pub async fn set_weekly_summary_settings(
+        &self,
+        user_id: i64,
+        settings: &WeeklySummarySettings,
+    ) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
         db_conn
             .execute(
-                "UPDATE users SET password_hash = $1 WHERE user_id = $2",
-                &[&password_hash, &user_id],
+                r#"
+                    UPDATE users SET weekly_summary = $2, weekly_summary_timezone = $3
+                    WHERE user_id = $1
+                "#,
+                &[&user_id, &settings.enabled, &settings.timezone],
             )
             .await?;
 
         Ok(())
     }
 
-    /// Add an access token for the user.
-    pub async fn add_access_token(
-        &self,
-        user_id: i64,
-        token: &str,
-        expiry: DateTime<Utc>,
-    ) -> Result<(), Error> {
+    /// Get the users that have opted in to weekly summaries.
+    pub async fn get_weekly_summary_users(&self) -> Result<Vec<WeeklySummaryUser>, Error> {
         let db_conn = self.db_pool.get().await?;
 
-        db_conn
-            .execute(
-                "INSERT INTO access_tokens (user_id, token, expiry) VALUES ($1, $2, $3)",
-                &[&user_id, &token, &expiry],
+        let rows = db_conn
+            .query(
+                r#"
+                    SELECT user_id, weekly_summary_timezone, weekly_summary_room_id,
+                        weekly_summary_sent_for
+                    FROM users
+                    WHERE weekly_summary
+                "#,
+                &[],
             )
             .await?;
 
-        Ok(())
+        let mut users = Vec::with_capacity(rows.len());
+        for row in rows {
+            users.push(WeeklySummaryUser {
+                user_id: row.try_get("user_id")?,
+                timezone: row.try_get("weekly_summary_timezone")?,
+                room_id: row.try_get("weekly_summary_room_id")?,
+                sent_for: row.try_get("weekly_summary_sent_for")?,
+            });
+        }
+
+        Ok(users)
     }
 
-    /// Get the user associated with the access token.
-    pub async fn get_user_from_token(&self, token: &str) -> Result<Option<i64>, Error> {
+    /// Get the DM room that we send the user's weekly summaries and other
+    /// notices to, if we've created one.
+    pub async fn get_dm_room_id(&self, user_id: i64) -> Result<Option<String>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let row = db_conn
             .query_opt(
-                "SELECT user_id FROM access_tokens WHERE token = $1 AND expiry > NOW()",
-                &[&token],
+                "SELECT weekly_summary_room_id FROM users WHERE user_id = $1",
+                &[&user_id],
             )
             .await?;
 
-        if let Some(row) = row {
-            Ok(Some(row.try_get(0)?))
-        } else {
-            Ok(None)
-        }
+        Ok(row
+            .map(|row| row.try_get("weekly_summary_room_id"))
+            .transpose()?
+            .flatten())
     }
 
-    /// Persist all emails that are on holiday today.
-    pub async fn set_out_today(&self, emails: &[String]) -> Result<(), Error> {
-        let mut db_conn = self.db_pool.get().await?;
-
-        let txn = db_conn.transaction().await?;
-
-        txn.execute("TRUNCATE out_today", &[]).await?;
-
-        futures::future::try_join_all(
-            emails
-                .iter()
-                .map(|email| txn.execute_raw("INSERT INTO out_today VALUES ($1)", vec![email])),
-        )
-        .await?;
+    /// Store the DM room that we send the user's weekly summaries to.
+    pub async fn set_weekly_summary_room_id(
+        &self,
+        user_id: i64,
+        room_id: &str,
+    ) -> Result<(), Error> {
+        let db_conn = self.db_pool.get().await?;
 
-        txn.commit().await?;
+        db_conn
+            .execute(
+                "UPDATE users SET weekly_summary_room_id = $2 WHERE user_id = $1",
+                &[&user_id, &room_id],
+            )
+            .await?;
 
         Ok(())
     }
 
-    /// Get all emails that are on holiday today.
-    pub async fn get_out_today_emails(&self) -> Result<BTreeSet<String>, Error> {
+    /// Mark the user's weekly summary as sent for the week starting on the
+    /// given Monday.
+    pub async fn set_weekly_summary_sent_for(
+        &self,
+        user_id: i64,
+        week_start: NaiveDate,
+    ) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
-        let rows = db_conn.query("SELECT email FROM out_today", &[]).await?;
+        db_conn
+            .execute(
+                "UPDATE users SET weekly_summary_sent_for = $2 WHERE user_id = $1",
+                &[&user_id, &week_start],
+            )
+            .await?;
 
-        Ok(rows.into_iter().map(|row| row.get(0)).collect())
+        Ok(())
     }
 
-    /// Get all matrix IDs that are on holiday today.
-    pub async fn get_out_today_matrix_ids(&self) -> Result<BTreeSet<String>, Error> {
+    /// Get the Matrix IDs of users that have opted out of being mentioned in
+    /// reminders.
+    pub async fn get_mentions_disabled_matrix_ids(&self) -> Result<BTreeSet<String>, Error> {
         let db_conn = self.db_pool.get().await?;
 
         let rows = db_conn
             .query(
                 r#"
-                SELECT matrix_id FROM out_today
+                SELECT matrix_id FROM users
                 INNER JOIN email_to_matrix_id USING (email)
+                WHERE mentions_disabled
                 "#,
                 &[],
             )
@@ -1239,8 +6103,8 @@ impl Database {
 
     /// Persist an email to matrix ID mapping.
     ///
-    /// This does *not* overwrite existing mappings. Returns true if the new
-    /// mapping was added.
+    /// This does *not* change the preferred Matrix ID for the email. Returns
+    /// true if the new mapping was added.
     pub async fn add_matrix_id(&self, email: &str, matrix_id: &str) -> Result<bool, Error> {
         let db_conn = self.db_pool.get().await?;
 
@@ -1258,20 +6122,41 @@ impl Database {
         Ok(ret.is_some())
     }
 
-    /// Persist an email to matrix ID mapping.
-    ///
-    /// This *does* overwrite existing mappings.
-    pub async fn replace_matrix_id(&self, email: &str, matrix_id: &str) -> Result<(), Error> {
+    /// Persist an email to matrix ID mapping and mark it as the preferred
+    /// Matrix ID for the email.
+    pub async fn set_preferred_matrix_id(&self, email: &str, matrix_id: &str) -> Result<(), Error> {
+        let mut db_conn = self.db_pool.get().await?;
+        let txn = db_conn.transaction().await?;
+
+        txn.execute(
+            "UPDATE email_to_matrix_id SET preferred = FALSE WHERE email = $1",
+            &[&email],
+        )
+        .await?;
+
+        txn.execute(
+            r#"
+                INSERT INTO email_to_matrix_id (email, matrix_id, preferred) VALUES ($1, $2, TRUE)
+                ON CONFLICT (email, matrix_id)
+                DO UPDATE SET
+                    preferred = TRUE
+            "#,
+            &[&email, &matrix_id],
+        )
+        .await?;
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Remove an email to matrix ID mapping.
+    pub async fn delete_matrix_id(&self, email: &str, matrix_id: &str) -> Result<(), Error> {
         let db_conn = self.db_pool.get().await?;
 
         db_conn
-            .query_opt(
-                r#"
-                INSERT INTO email_to_matrix_id (email, matrix_id) VALUES ($1, $2)
-                ON CONFLICT (email)
-                DO UPDATE SET
-                    matrix_id = EXCLUDED.matrix_id
-                "#,
+            .execute(
+                "DELETE FROM email_to_matrix_id WHERE email = $1 AND matrix_id = $2",
                 &[&email, &matrix_id],
             )
             .await?;
@@ -1367,6 +6252,9 @@ impl Database {
         refresh_token: &str,
         expiry: DateTime<Utc>,
     ) -> Result<(), Error> {
+        let access_token = self.secrets.encrypt(access_token)?;
+        let refresh_token = self.secrets.encrypt(refresh_token)?;
+
         let mut db_conn = self.db_pool.get().await?;
 
         let txn = db_conn.transaction().await?;
@@ -1380,6 +6268,20 @@ impl Database {
         )
         .await?;
 
+        // The user has proven they own the account, so we can treat the email
+        // as a verified alias (unless it belongs to someone else).
+        txn.execute(
+            r#"
+            INSERT INTO user_emails (user_id, email, verified)
+            SELECT $1, $2, TRUE
+            WHERE NOT EXISTS (SELECT 1 FROM users WHERE email = $2)
+            ON CONFLICT (email) DO UPDATE SET verified = TRUE
+            WHERE user_emails.user_id = EXCLUDED.user_id
+        "#,
+            &[&user_id, &email],
+        )
+        .await?;
+
         // We only want one oauth2 token per user provisioned at a time, so we
         // delete any existing ones.
         let row_count = txn
@@ -1406,6 +6308,8 @@ impl Database {
         access_token: &str,
         expiry: DateTime<Utc>,
     ) -> Result<(), Error> {
+        let access_token = self.secrets.encrypt(access_token)?;
+
         let db_conn = self.db_pool.get().await?;
 
         db_conn
@@ -1495,10 +6399,11 @@ impl Database {
 
         for row in ret {
             let token_id = row.try_get("token_id")?;
-            let refresh_token = row.try_get("refresh_token")?;
+            let refresh_token: String = row.try_get("refresh_token")?;
             let expiry = row.try_get("expiry")?;
 
             if expiry < Utc::now() {
+                let refresh_token = self.secrets.decrypt(&refresh_token)?;
                 results.push((token_id, refresh_token, expiry));
             }
         }
@@ -1565,12 +6470,12 @@ impl Database {
 
             if expiry > Utc::now() {
                 Ok(OAuth2Result::AccessToken {
-                    access_token,
+                    access_token: self.secrets.decrypt(&access_token)?,
                     token_id,
                 })
             } else {
                 Ok(OAuth2Result::RefreshToken {
-                    refresh_token,
+                    refresh_token: self.secrets.decrypt(&refresh_token)?,
                     token_id,
                 })
             }
@@ -1579,6 +6484,76 @@ impl Database {
         }
     }
 
+    /// Re-encrypt all stored secrets with the current key, including any
+    /// stored in plaintext. Returns the number of values updated.
+    pub async fn reencrypt_secrets(&self) -> Result<usize, Error> {
+        ensure!(self.secrets.is_enabled(), "No encryption key is configured");
+
+        let mut db_conn = self.db_pool.get().await?;
+        let txn = db_conn.transaction().await?;
+
+        let mut updated = 0;
+
+        let rows = txn
+            .query(
+                "SELECT calendar_id, password FROM calendar_passwords FOR UPDATE",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let calendar_id: i64 = row.try_get("calendar_id")?;
+            let password: String = row.try_get("password")?;
+
+            if self.secrets.is_current(&password) {
+                continue;
+            }
+
+            let password = self.secrets.encrypt(&self.secrets.decrypt(&password)?)?;
+            txn.execute(
+                "UPDATE calendar_passwords SET password = $2 WHERE calendar_id = $1",
+                &[&calendar_id, &password],
+            )
+            .await?;
+            updated += 1;
+        }
+
+        let rows = txn
+            .query(
+                "SELECT token_id, access_token, refresh_token FROM oauth2_tokens FOR UPDATE",
+                &[],
+            )
+            .await?;
+        for row in rows {
+            let token_id: i64 = row.try_get("token_id")?;
+            let access_token: String = row.try_get("access_token")?;
+            let refresh_token: String = row.try_get("refresh_token")?;
+
+            if self.secrets.is_current(&access_token) && self.secrets.is_current(&refresh_token) {
+                continue;
+            }
+
+            let access_token = self
+                .secrets
+                .encrypt(&self.secrets.decrypt(&access_token)?)?;
+            let refresh_token = self
+                .secrets
+                .encrypt(&self.secrets.decrypt(&refresh_token)?)?;
+            txn.execute(
+                r#"
+                    UPDATE oauth2_tokens SET access_token = $2, refresh_token = $3
+                    WHERE token_id = $1
+                "#,
+                &[&token_id, &access_token, &refresh_token],
+            )
+            .await?;
+            updated += 1;
+        }
+
+        txn.commit().await?;
+
+        Ok(updated)
+    }
+
     pub async fn get_oauth2_calendars(
         &self,
         user_id: i64,
@@ -1586,7 +6561,7 @@ impl Database {
     ) -> Result<Vec<Calendar>, Error> {
         let calendars = self
             .get_calendars_with_filter(
-                "WHERE c.user_id = $1 AND ac.account_id = $2",
+                "AND c.user_id = $1 AND ac.account_id = $2",
                 &[&&user_id, &account_id],
             )
             .await?;
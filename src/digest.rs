@@ -0,0 +1,173 @@
+//! HTTP Digest authentication (RFC 7616), which some CalDAV servers require
+//! instead of Basic auth.
+
+use anyhow::{bail, Error};
+use md5::{Digest, Md5};
+
+/// A `WWW-Authenticate: Digest ...` challenge from the server.
+#[derive(Debug, Clone, Default)]
+pub struct DigestChallenge {
+    pub realm: String,
+    pub nonce: String,
+    pub opaque: Option<String>,
+    pub algorithm: Option<String>,
+    /// The quality of protection options offered, e.g. `auth`.
+    pub qop: Vec<String>,
+}
+
+impl DigestChallenge {
+    /// Parse a `WWW-Authenticate` header value, returning `None` if it isn't a
+    /// digest challenge.
+    pub fn parse(header: &str) -> Option<DigestChallenge> {
+        let (scheme, params) = header.trim().split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let mut challenge = DigestChallenge::default();
+        let mut has_nonce = false;
+
+        for (key, value) in parse_params(params) {
+            match key.to_ascii_lowercase().as_str() {
+                "realm" => challenge.realm = value,
+                "nonce" => {
+                    challenge.nonce = value;
+                    has_nonce = true;
+                }
+                "opaque" => challenge.opaque = Some(value),
+                "algorithm" => challenge.algorithm = Some(value),
+                "qop" => {
+                    challenge.qop = value.split(',').map(|qop| qop.trim().to_string()).collect()
+                }
+                _ => {}
+            }
+        }
+
+        if !has_nonce {
+            return None;
+        }
+
+        Some(challenge)
+    }
+
+    /// Compute the `Authorization` header value for a request with the given
+    /// method and URI (path and query).
+    pub fn authorization(
+        &self,
+        method: &str,
+        uri: &str,
+        user_name: &str,
+        password: &str,
+        cnonce: &str,
+    ) -> Result<String, Error> {
+        let session = match self.algorithm.as_deref() {
+            None => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("MD5") => false,
+            Some(algorithm) if algorithm.eq_ignore_ascii_case("MD5-sess") => true,
+            Some(algorithm) => bail!("Unsupported digest algorithm {}", algorithm),
+        };
+
+        let mut ha1 = md5_hex(&format!("{}:{}:{}", user_name, self.realm, password));
+        if session {
+            ha1 = md5_hex(&format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+
+        let ha2 = md5_hex(&format!("{}:{}", method, uri));
+
+        // We only support `auth`, not `auth-int`.
+        let qop_auth = self.qop.iter().any(|qop| qop == "auth");
+        if !self.qop.is_empty() && !qop_auth {
+            bail!("Unsupported digest qop {:?}", self.qop);
+        }
+
+        let nc = "00000001";
+
+        let response = if qop_auth {
+            md5_hex(&format!(
+                "{}:{}:{}:{}:auth:{}",
+                ha1, self.nonce, nc, cnonce, ha2
+            ))
+        } else {
+            md5_hex(&format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+
+        let mut header = format!(
+            r#"Digest username="{}", realm="{}", nonce="{}", uri="{}", response="{}""#,
+            quote(user_name),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+            response
+        );
+
+        if let Some(algorithm) = &self.algorithm {
+            header.push_str(&format!(", algorithm={}", algorithm));
+        }
+
+        if qop_auth {
+            header.push_str(&format!(
+                r#", qop=auth, nc={}, cnonce="{}""#,
+                nc,
+                quote(cnonce)
+            ));
+        }
+
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(r#", opaque="{}""#, quote(opaque)));
+        }
+
+        Ok(header)
+    }
+}
+
+/// Parse the comma separated `key=value` parameters of a challenge, where
+/// values may be quoted strings containing commas.
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut parsed = Vec::new();
+    let mut chars = params.chars().peekable();
+
+    loop {
+        // Skip separators between parameters.
+        while matches!(chars.peek(), Some(c) if *c == ',' || c.is_whitespace()) {
+            chars.next();
+        }
+
+        let key: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if key.is_empty() {
+            break;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next()),
+                    '"' => break,
+                    _ => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.peek() {
+                if *c == ',' {
+                    break;
+                }
+                value.push(*c);
+                chars.next();
+            }
+        }
+
+        parsed.push((key.trim().to_string(), value.trim().to_string()));
+    }
+
+    parsed
+}
+
+/// Escape a value to be included in a quoted string.
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn md5_hex(input: &str) -> String {
+    hex::encode(Md5::digest(input.as_bytes()))
+}
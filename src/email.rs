@@ -0,0 +1,125 @@
+//! Sending emails over SMTP.
+//!
+//! Emails are rendered from a pair of templates in the resource directory:
+//! `email_<name>.txt.j2` for the plain text part and `email_<name>.html.j2`
+//! for the HTML part.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Error};
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use tera::Tera;
+
+use crate::config::{EmailConfig, SmtpTls};
+
+/// A rendered email, ready to be sent.
+#[derive(Debug, Clone)]
+pub struct Email {
+    pub subject: String,
+    pub plain: String,
+    pub html: String,
+}
+
+/// Render the plain text and HTML parts of the named email.
+pub fn render_email(
+    templates: &Tera,
+    name: &str,
+    subject: &str,
+    context: &tera::Context,
+) -> Result<Email, Error> {
+    let plain = templates
+        .render(&format!("email_{}.txt.j2", name), context)
+        .with_context(|| format!("Failed to render plain text email {}", name))?;
+
+    let html = templates
+        .render(&format!("email_{}.html.j2", name), context)
+        .with_context(|| format!("Failed to render HTML email {}", name))?;
+
+    Ok(Email {
+        subject: subject.to_string(),
+        plain,
+        html,
+    })
+}
+
+/// Sends emails through the configured SMTP server.
+#[derive(Clone)]
+pub struct Mailer {
+    transport: Arc<AsyncSmtpTransport<Tokio1Executor>>,
+    from: Mailbox,
+}
+
+impl std::fmt::Debug for Mailer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mailer").field("from", &self.from).finish()
+    }
+}
+
+impl Mailer {
+    pub fn new(config: &EmailConfig) -> Result<Self, Error> {
+        let from = config
+            .from
+            .parse()
+            .with_context(|| format!("Invalid from address {}", config.from))?;
+
+        let mut builder = match config.tls.unwrap_or(SmtpTls::Starttls) {
+            SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.smtp_host)?,
+            SmtpTls::Starttls => {
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.smtp_host)?
+            }
+            SmtpTls::None => {
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.smtp_host)
+            }
+        };
+
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+
+        if let Some(username) = &config.smtp_username {
+            builder = builder.credentials(Credentials::new(
+                username.clone(),
+                config.smtp_password.clone().unwrap_or_default(),
+            ));
+        }
+
+        Ok(Mailer {
+            transport: Arc::new(builder.build()),
+            from,
+        })
+    }
+
+    /// Build the message for the email, with both plain text and HTML parts.
+    pub fn build_message(&self, to: &str, email: &Email) -> Result<Message, Error> {
+        let to: Mailbox = to
+            .parse()
+            .with_context(|| format!("Invalid email address {}", to))?;
+
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                email.plain.clone(),
+                email.html.clone(),
+            ))?;
+
+        Ok(message)
+    }
+
+    /// Send the email to the given address.
+    pub async fn send(&self, to: &str, email: &Email) -> Result<(), Error> {
+        let message = self.build_message(to, email)?;
+
+        self.transport
+            .send(message)
+            .await
+            .context("Failed to send email")?;
+
+        Ok(())
+    }
+}
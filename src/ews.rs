@@ -0,0 +1,197 @@
+//! A minimal Exchange Web Services (EWS) client, for fetching calendars from
+//! Exchange servers that don't support CalDAV.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail, Context, Error};
+use chrono::{DateTime, SecondsFormat, Utc};
+use tracing::{info, instrument, Span};
+
+use crate::{
+    calendar::{find_conference_url, read_response_text, send_authenticated, SyncWindow},
+    database::{Attendee, CalendarAuthentication, Event, EventInstance},
+};
+
+/// Fetch the events in the user's calendar within the sync window.
+///
+/// We use a `CalendarView`, so Exchange expands recurring events into their
+/// occurrences for us. Note that attendees aren't returned when listing
+/// items, so EWS events don't have any. Occurrences that show as free are
+/// skipped if `ignore_transparent` is set.
+#[instrument(skip(client), fields(status))]
+pub async fn fetch_ews_events(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    calendar_id: i64,
+    window: &SyncWindow,
+    max_response_bytes: usize,
+    ignore_transparent: bool,
+) -> Result<(Vec<Event>, Vec<EventInstance>), Error> {
+    let req = client
+        .post(url)
+        .header("Content-Type", "text/xml; charset=utf-8")
+        .body(find_item_request(window.from, window.until));
+
+    let resp = send_authenticated(req, authentication).await?;
+
+    let status = resp.status();
+
+    let body = read_response_text(resp, max_response_bytes).await?;
+
+    info!(status = status.as_u16(), "Got result from EWS");
+    Span::current().record("status", status.as_u16());
+
+    if !status.is_success() {
+        bail!("Got {} result from EWS", status.as_u16());
+    }
+
+    parse_find_item_response(calendar_id, &body, ignore_transparent)
+}
+
+/// Build a `FindItem` request for the calendar items between the given times.
+fn find_item_request(start: DateTime<Utc>, end: DateTime<Utc>) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<soap:Envelope xmlns:soap="http://schemas.xmlsoap.org/soap/envelope/"
+    xmlns:t="http://schemas.microsoft.com/exchange/services/2006/types"
+    xmlns:m="http://schemas.microsoft.com/exchange/services/2006/messages">
+    <soap:Header>
+        <t:RequestServerVersion Version="Exchange2010_SP2" />
+    </soap:Header>
+    <soap:Body>
+        <m:FindItem Traversal="Shallow">
+            <m:ItemShape>
+                <t:BaseShape>IdOnly</t:BaseShape>
+                <t:AdditionalProperties>
+                    <t:FieldURI FieldURI="item:Subject" />
+                    <t:FieldURI FieldURI="calendar:Start" />
+                    <t:FieldURI FieldURI="calendar:End" />
+                    <t:FieldURI FieldURI="calendar:Location" />
+                    <t:FieldURI FieldURI="calendar:Organizer" />
+                    <t:FieldURI FieldURI="calendar:UID" />
+                    <t:FieldURI FieldURI="calendar:IsAllDayEvent" />
+                    <t:FieldURI FieldURI="calendar:IsCancelled" />
+                    <t:FieldURI FieldURI="calendar:LegacyFreeBusyStatus" />
+                </t:AdditionalProperties>
+            </m:ItemShape>
+            <m:CalendarView StartDate="{start}" EndDate="{end}" />
+            <m:ParentFolderIds>
+                <t:DistinguishedFolderId Id="calendar" />
+            </m:ParentFolderIds>
+        </m:FindItem>
+    </soap:Body>
+</soap:Envelope>"#,
+        start = start.to_rfc3339_opts(SecondsFormat::Secs, true),
+        end = end.to_rfc3339_opts(SecondsFormat::Secs, true),
+    )
+}
+
+/// Parse the calendar items in a `FindItem` response into events.
+///
+/// Occurrences of recurring events share a UID, so are mapped to instances of
+/// the same event.
+pub fn parse_find_item_response(
+    calendar_id: i64,
+    body: &str,
+    ignore_transparent: bool,
+) -> Result<(Vec<Event>, Vec<EventInstance>), Error> {
+    let doc = roxmltree::Document::parse(body)
+        .map_err(|e| anyhow!(e))
+        .with_context(|| "decoding xml")?;
+
+    for message in doc
+        .descendants()
+        .filter(|node| node.tag_name().name() == "FindItemResponseMessage")
+    {
+        if message.attribute("ResponseClass") == Some("Error") {
+            bail!(
+                "EWS returned an error: {}",
+                child_text(message, "MessageText").unwrap_or_default()
+            );
+        }
+    }
+
+    let mut events = BTreeMap::new();
+    let mut instances = Vec::new();
+
+    for item in doc
+        .descendants()
+        .filter(|node| node.tag_name().name() == "CalendarItem")
+    {
+        if child_text(item, "IsAllDayEvent").as_deref() == Some("true")
+            || child_text(item, "IsCancelled").as_deref() == Some("true")
+        {
+            continue;
+        }
+
+        if ignore_transparent && child_text(item, "LegacyFreeBusyStatus").as_deref() == Some("Free")
+        {
+            continue;
+        }
+
+        let uid = if let Some(uid) = child_text(item, "UID") {
+            uid
+        } else {
+            continue;
+        };
+
+        let date =
+            child_text(item, "Start").with_context(|| format!("Missing start time for {}", uid))?;
+        let date = DateTime::parse_from_rfc3339(&date)
+            .with_context(|| format!("Invalid start time for {}", uid))?;
+
+        let duration_minutes = child_text(item, "End")
+            .and_then(|end| DateTime::parse_from_rfc3339(&end).ok())
+            .map(|end| (end - date).num_minutes());
+
+        let location = child_text(item, "Location");
+
+        let organizer = item
+            .children()
+            .find(|child| child.tag_name().name() == "Organizer")
+            .and_then(|organizer| {
+                organizer
+                    .children()
+                    .find(|child| child.tag_name().name() == "Mailbox")
+            })
+            .and_then(|mailbox| {
+                Some(Attendee {
+                    email: child_text(mailbox, "EmailAddress")?,
+                    common_name: child_text(mailbox, "Name"),
+                    status: None,
+                })
+            });
+
+        instances.push(EventInstance {
+            event_id: uid.clone(),
+            date,
+            attendees: Vec::new(),
+        });
+
+        events.entry(uid.clone()).or_insert_with(|| Event {
+            calendar_id,
+            event_id: uid,
+            summary: child_text(item, "Subject"),
+            description: None,
+            conference_url: location.as_deref().and_then(find_conference_url),
+            duration_minutes,
+            last_occurrence: None,
+            location,
+            organizer,
+            attendees: Vec::new(),
+        });
+    }
+
+    Ok((events.into_values().collect(), instances))
+}
+
+/// Get the text of the node's child with the given tag name.
+fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.tag_name().name() == name)
+        .and_then(|child| child.text())
+        .map(str::trim)
+        .filter(|text| !text.is_empty())
+        .map(ToOwned::to_owned)
+}
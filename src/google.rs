@@ -0,0 +1,242 @@
+//! Fetching events with the Google Calendar API, rather than Google's CalDAV
+//! endpoint.
+
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Error};
+use chrono::{DateTime, FixedOffset, SecondsFormat};
+use serde::Deserialize;
+use tracing::{info, instrument};
+use urlencoding::encode;
+
+use crate::{
+    calendar::{find_conference_url, read_response_text, send_authenticated, SyncWindow},
+    database::{Attendee, CalendarAuthentication, Event, EventInstance},
+};
+
+/// Get the Calendar API URL for listing the events in the given Google
+/// calendar.
+pub fn google_events_url(google_id: &str) -> String {
+    format!(
+        "https://www.googleapis.com/calendar/v3/calendars/{}/events",
+        encode(google_id)
+    )
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEventsResponse {
+    #[serde(default)]
+    items: Vec<GoogleEvent>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEvent {
+    #[serde(rename = "iCalUID")]
+    ical_uid: Option<String>,
+    status: Option<String>,
+    /// `transparent` if the event doesn't block time in the calendar.
+    transparency: Option<String>,
+    summary: Option<String>,
+    description: Option<String>,
+    location: Option<String>,
+    start: GoogleEventTime,
+    end: Option<GoogleEventTime>,
+    organizer: Option<GoogleAttendee>,
+    #[serde(default)]
+    attendees: Vec<GoogleAttendee>,
+    hangout_link: Option<String>,
+    conference_data: Option<GoogleConferenceData>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEventTime {
+    /// Only set for events that aren't all day.
+    date_time: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleAttendee {
+    email: Option<String>,
+    display_name: Option<String>,
+    response_status: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleConferenceData {
+    #[serde(default)]
+    entry_points: Vec<GoogleEntryPoint>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GoogleEntryPoint {
+    entry_point_type: String,
+    uri: String,
+}
+
+impl GoogleAttendee {
+    /// Convert to an [`Attendee`], mapping Google's response status to the
+    /// equivalent `PARTSTAT`.
+    fn to_attendee(&self) -> Option<Attendee> {
+        let status = match self.response_status.as_deref() {
+            Some("accepted") => Some("ACCEPTED"),
+            Some("tentative") => Some("TENTATIVE"),
+            Some("declined") => Some("DECLINED"),
+            Some("needsAction") => Some("NEEDS-ACTION"),
+            _ => None,
+        };
+
+        Some(Attendee {
+            email: self.email.clone()?,
+            common_name: self.display_name.clone(),
+            status: status.map(ToOwned::to_owned),
+        })
+    }
+}
+
+impl GoogleEvent {
+    /// Get the link to join the event's video call, preferring the one Google
+    /// attached to the event.
+    fn conference_url(&self) -> Option<String> {
+        self.conference_data
+            .iter()
+            .flat_map(|data| &data.entry_points)
+            .find(|entry_point| entry_point.entry_point_type == "video")
+            .map(|entry_point| entry_point.uri.clone())
+            .or_else(|| self.hangout_link.clone())
+            .or_else(|| self.location.as_deref().and_then(find_conference_url))
+            .or_else(|| self.description.as_deref().and_then(find_conference_url))
+    }
+}
+
+/// Fetch the events in the calendar within the sync window.
+///
+/// We ask Google to expand recurring events (`singleEvents=true`), so we
+/// don't have to handle recurrence rules ourselves. Occurrences that show as
+/// free are skipped if `ignore_transparent` is set.
+#[instrument(skip(client))]
+pub async fn fetch_google_events(
+    client: &reqwest::Client,
+    url: &str,
+    authentication: &CalendarAuthentication,
+    calendar_id: i64,
+    window: &SyncWindow,
+    max_response_bytes: usize,
+    ignore_transparent: bool,
+) -> Result<(Vec<Event>, Vec<EventInstance>), Error> {
+    let time_min = window.from.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let time_max = window.until.to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let mut google_events = Vec::new();
+    let mut page_token = None;
+
+    loop {
+        let mut query = vec![
+            ("singleEvents", "true"),
+            ("orderBy", "startTime"),
+            ("maxResults", "2500"),
+            ("timeMin", time_min.as_str()),
+            ("timeMax", time_max.as_str()),
+        ];
+        if let Some(page_token) = &page_token {
+            query.push(("pageToken", page_token.as_str()));
+        }
+
+        let req = client.get(url).query(&query);
+        let resp = send_authenticated(req, authentication).await?;
+
+        let status = resp.status();
+        info!(status = status.as_u16(), "Got result from Google");
+
+        if !status.is_success() {
+            bail!("Got {} result from Google", status.as_u16());
+        }
+
+        let body = read_response_text(resp, max_response_bytes).await?;
+        let body: GoogleEventsResponse = serde_json::from_str(&body)?;
+        google_events.extend(body.items);
+
+        page_token = body.next_page_token;
+        if page_token.is_none() {
+            break;
+        }
+    }
+
+    Ok(google_events_to_events(
+        calendar_id,
+        google_events,
+        ignore_transparent,
+    ))
+}
+
+/// Map the expanded Google events into events and instances.
+///
+/// Instances of recurring events share an iCal UID, which is also the event ID
+/// we'd get from CalDAV, so reminders carry over.
+fn google_events_to_events(
+    calendar_id: i64,
+    google_events: Vec<GoogleEvent>,
+    ignore_transparent: bool,
+) -> (Vec<Event>, Vec<EventInstance>) {
+    let mut events = BTreeMap::new();
+    let mut instances = Vec::new();
+
+    for google_event in google_events {
+        if google_event.status.as_deref() == Some("cancelled") {
+            continue;
+        }
+
+        if ignore_transparent && google_event.transparency.as_deref() == Some("transparent") {
+            continue;
+        }
+
+        // All day events don't have a time to remind people before, so we skip
+        // them.
+        let (event_id, date) = match (&google_event.ical_uid, google_event.start.date_time) {
+            (Some(event_id), Some(date)) => (event_id.clone(), date),
+            _ => continue,
+        };
+
+        let attendees: Vec<_> = google_event
+            .attendees
+            .iter()
+            .filter_map(GoogleAttendee::to_attendee)
+            .collect();
+
+        instances.push(EventInstance {
+            event_id: event_id.clone(),
+            date,
+            attendees: attendees.clone(),
+        });
+
+        let duration_minutes = google_event
+            .end
+            .as_ref()
+            .and_then(|end| end.date_time)
+            .map(|end| (end - date).num_minutes());
+
+        events.entry(event_id.clone()).or_insert_with(|| Event {
+            calendar_id,
+            event_id,
+            conference_url: google_event.conference_url(),
+            duration_minutes,
+            last_occurrence: None,
+            summary: google_event.summary,
+            description: google_event.description,
+            location: google_event.location,
+            organizer: google_event
+                .organizer
+                .as_ref()
+                .and_then(GoogleAttendee::to_attendee),
+            attendees,
+        });
+    }
+
+    (events.into_values().collect(), instances)
+}
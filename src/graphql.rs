@@ -0,0 +1,289 @@
+//! A read-only GraphQL API over the user's calendars, events and reminders,
+//! which is easier to build dashboards on than stitching together the REST
+//! APIs.
+//!
+//! Requests are authenticated like the other APIs, and each field checks that
+//! the token has the scope it needs.
+
+use async_graphql::{
+    ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Result, Schema, SimpleObject,
+};
+
+use crate::app::App;
+use crate::auth::{ApiUser, AuthedUser};
+use crate::database::{Attendee, Calendar, Event, EventInstance, Reminder, UserReminder};
+use crate::site::{DEFAULT_API_PAGE_SIZE, MAX_API_PAGE_SIZE};
+
+/// How deeply queries can nest, to stop expensive queries.
+const MAX_QUERY_DEPTH: usize = 8;
+
+pub type CalbotSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+/// Build the schema. The [`App`] and [`ApiUser`] are added to each request.
+pub fn build_schema() -> CalbotSchema {
+    Schema::build(Query, EmptyMutation, EmptySubscription)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// Check that the user's token has the scope.
+fn require(ctx: &Context<'_>, scope: &str) -> Result<AuthedUser> {
+    ctx.data::<ApiUser>()?
+        .require(scope)
+        .map_err(|err| async_graphql::Error::new(err.to_string()))
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The user's calendars.
+    async fn calendars(&self, ctx: &Context<'_>) -> Result<Vec<CalendarNode>> {
+        let user = require(ctx, "calendars:read")?;
+        let app = ctx.data::<App>()?;
+
+        let calendars = app.database.get_calendars_for_user(user.0).await?;
+
+        Ok(calendars.into_iter().map(CalendarNode).collect())
+    }
+
+    /// One of the user's calendars.
+    async fn calendar(&self, ctx: &Context<'_>, calendar_id: i64) -> Result<Option<CalendarNode>> {
+        let user = require(ctx, "calendars:read")?;
+        let app = ctx.data::<App>()?;
+
+        let calendar = app.database.get_calendar(calendar_id).await?;
+
+        Ok(calendar
+            .filter(|calendar| calendar.user_id == user.0)
+            .map(CalendarNode))
+    }
+
+    /// The upcoming events in the user's calendars, ordered by their next
+    /// instance, optionally filtered to those matching the search.
+    async fn events(
+        &self,
+        ctx: &Context<'_>,
+        search: Option<String>,
+        limit: Option<i64>,
+        offset: Option<i64>,
+    ) -> Result<Vec<EventNode>> {
+        let user = require(ctx, "calendars:read")?;
+        let app = ctx.data::<App>()?;
+
+        let events = app
+            .database
+            .get_events_page_for_user(
+                user.0,
+                search.as_deref(),
+                limit
+                    .unwrap_or(DEFAULT_API_PAGE_SIZE)
+                    .clamp(1, MAX_API_PAGE_SIZE),
+                offset.unwrap_or(0).max(0),
+            )
+            .await?;
+
+        Ok(events
+            .into_iter()
+            .map(|(event, instance)| EventNode {
+                event,
+                instances: vec![instance],
+            })
+            .collect())
+    }
+
+    /// The reminders the user has created.
+    async fn reminders(&self, ctx: &Context<'_>) -> Result<Vec<ReminderNode>> {
+        let user = require(ctx, "reminders:read")?;
+        let app = ctx.data::<App>()?;
+
+        let reminders = app.database.get_reminders_for_user(user.0).await?;
+
+        Ok(reminders.into_iter().map(ReminderNode::from).collect())
+    }
+}
+
+pub struct CalendarNode(Calendar);
+
+#[Object(name = "Calendar")]
+impl CalendarNode {
+    async fn calendar_id(&self) -> i64 {
+        self.0.calendar_id
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn url(&self) -> &str {
+        &self.0.url
+    }
+
+    /// Whether the calendar is synced and its reminders sent.
+    async fn enabled(&self) -> bool {
+        self.0.enabled
+    }
+
+    async fn timezone(&self) -> Option<&str> {
+        self.0.timezone.as_deref()
+    }
+
+    /// The events in the calendar, with all their upcoming instances.
+    async fn events(&self, ctx: &Context<'_>) -> Result<Vec<EventNode>> {
+        let app = ctx.data::<App>()?;
+
+        let events = app
+            .database
+            .get_events_in_calendar(self.0.calendar_id)
+            .await?;
+
+        Ok(events
+            .into_iter()
+            .map(|(event, instances)| EventNode { event, instances })
+            .collect())
+    }
+}
+
+pub struct EventNode {
+    event: Event,
+    instances: Vec<EventInstance>,
+}
+
+#[Object(name = "Event")]
+impl EventNode {
+    async fn calendar_id(&self) -> i64 {
+        self.event.calendar_id
+    }
+
+    async fn event_id(&self) -> &str {
+        &self.event.event_id
+    }
+
+    async fn summary(&self) -> Option<&str> {
+        self.event.summary.as_deref()
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.event.description.as_deref()
+    }
+
+    async fn location(&self) -> Option<&str> {
+        self.event.location.as_deref()
+    }
+
+    async fn organizer(&self) -> Option<AttendeeNode> {
+        self.event.organizer.as_ref().map(AttendeeNode::from)
+    }
+
+    async fn conference_url(&self) -> Option<&str> {
+        self.event.conference_url.as_deref()
+    }
+
+    async fn duration_minutes(&self) -> Option<i64> {
+        self.event.duration_minutes
+    }
+
+    /// The upcoming instances of the event, soonest first.
+    async fn instances(&self) -> Vec<InstanceNode> {
+        self.instances
+            .iter()
+            .map(|instance| InstanceNode {
+                date: instance.date.to_rfc3339(),
+                attendees: instance.attendees.iter().map(AttendeeNode::from).collect(),
+            })
+            .collect()
+    }
+
+    /// The reminders for the event, including those other people have added.
+    async fn reminders(&self, ctx: &Context<'_>) -> Result<Vec<ReminderNode>> {
+        require(ctx, "reminders:read")?;
+        let app = ctx.data::<App>()?;
+
+        let reminders = app
+            .database
+            .get_reminders_for_event(self.event.calendar_id, &self.event.event_id)
+            .await?;
+
+        Ok(reminders.into_iter().map(ReminderNode::from).collect())
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Instance")]
+pub struct InstanceNode {
+    /// When the instance starts, as an RFC 3339 timestamp.
+    date: String,
+    attendees: Vec<AttendeeNode>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Attendee")]
+pub struct AttendeeNode {
+    email: String,
+    common_name: Option<String>,
+    /// The attendee's participation status, e.g. `ACCEPTED` or `DECLINED`.
+    status: Option<String>,
+}
+
+impl From<&Attendee> for AttendeeNode {
+    fn from(attendee: &Attendee) -> Self {
+        AttendeeNode {
+            email: attendee.email.clone(),
+            common_name: attendee.common_name.clone(),
+            status: attendee.status.clone(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "Reminder", complex)]
+pub struct ReminderNode {
+    reminder_id: i64,
+    calendar_id: i64,
+    event_id: String,
+    room: String,
+    minutes_before: i64,
+    enabled: bool,
+}
+
+#[ComplexObject]
+impl ReminderNode {
+    /// The event the reminder is for.
+    async fn event(&self, ctx: &Context<'_>) -> Result<Option<EventNode>> {
+        require(ctx, "calendars:read")?;
+        let app = ctx.data::<App>()?;
+
+        let event = app
+            .database
+            .get_event_in_calendar(self.calendar_id, &self.event_id)
+            .await?;
+
+        Ok(event.map(|(event, instances)| EventNode { event, instances }))
+    }
+}
+
+impl From<Reminder> for ReminderNode {
+    fn from(reminder: Reminder) -> Self {
+        ReminderNode {
+            reminder_id: reminder.reminder_id,
+            calendar_id: reminder.calendar_id,
+            event_id: reminder.event_id,
+            room: reminder.room,
+            minutes_before: reminder.minutes_before,
+            enabled: reminder.enabled,
+        }
+    }
+}
+
+impl From<UserReminder> for ReminderNode {
+    fn from(reminder: UserReminder) -> Self {
+        ReminderNode {
+            reminder_id: reminder.reminder_id,
+            calendar_id: reminder.calendar_id,
+            event_id: reminder.event_id,
+            room: reminder.room,
+            minutes_before: reminder.minutes_before,
+            enabled: reminder.enabled,
+        }
+    }
+}
@@ -0,0 +1,110 @@
+//! Public holidays, fetched from an ICS feed per region, so that reminders
+//! can be skipped on them.
+
+use chrono::{Duration, NaiveDate};
+
+/// The longest holiday we'll accept from a feed, to guard against bogus end
+/// dates.
+const MAX_HOLIDAY_DAYS: i64 = 31;
+
+/// A day that is a public holiday.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicHoliday {
+    pub date: NaiveDate,
+    pub name: Option<String>,
+}
+
+/// Parse the holidays out of an ICS feed.
+///
+/// Holiday feeds only contain simple all day events, so we parse them line by
+/// line rather than expanding them as full calendars. Holidays that span
+/// multiple days are returned once per day.
+pub fn parse_public_holidays(body: &str) -> Vec<PublicHoliday> {
+    let mut holidays = Vec::new();
+
+    let mut in_event = false;
+    let mut start = None;
+    let mut end = None;
+    let mut name = None;
+
+    for line in unfold_lines(body) {
+        let (property, value) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        let property_name = property.split(';').next().unwrap_or_default();
+
+        match (property_name.to_ascii_uppercase().as_str(), value.trim()) {
+            ("BEGIN", "VEVENT") => {
+                in_event = true;
+                start = None;
+                end = None;
+                name = None;
+            }
+            ("END", "VEVENT") if in_event => {
+                in_event = false;
+
+                let start = if let Some(start) = start {
+                    start
+                } else {
+                    continue;
+                };
+
+                // The end date is exclusive, and defaults to the day after the
+                // start.
+                let end = end
+                    .filter(|end| *end > start)
+                    .unwrap_or(start + Duration::days(1))
+                    .min(start + Duration::days(MAX_HOLIDAY_DAYS));
+
+                let mut date = start;
+                while date < end {
+                    holidays.push(PublicHoliday {
+                        date,
+                        name: name.clone(),
+                    });
+                    date += Duration::days(1);
+                }
+            }
+            ("DTSTART", value) if in_event => start = parse_date(value),
+            ("DTEND", value) if in_event => end = parse_date(value),
+            ("SUMMARY", value) if in_event => name = Some(unescape_text(value)),
+            _ => {}
+        }
+    }
+
+    holidays
+}
+
+/// Join folded lines back together.
+fn unfold_lines(body: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in body.lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(continuation) = line.strip_prefix([' ', '\t']) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(continuation);
+                continue;
+            }
+        }
+
+        lines.push(line.to_string());
+    }
+
+    lines
+}
+
+/// Parse the date from a `DATE` or `DATE-TIME` value.
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+fn unescape_text(value: &str) -> String {
+    value
+        .replace("\\n", "\n")
+        .replace("\\N", "\n")
+        .replace("\\,", ",")
+        .replace("\\;", ";")
+        .replace("\\\\", "\\")
+}
@@ -3,15 +3,34 @@ pub mod auth;
 pub mod calendar;
 pub mod config;
 pub mod database;
+pub mod digest;
+pub mod email;
+pub mod ews;
+pub mod google;
+pub mod graphql;
+pub mod holidays;
+pub mod locale;
+pub mod password;
+pub mod quiet_hours;
+pub mod rate_limit;
+pub mod reminder_export;
+pub mod rules;
+pub mod secrets;
 pub mod site;
+pub mod systemd;
+pub mod template_helpers;
+pub mod timezones;
+pub mod webhooks;
 
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
-use anyhow::{ensure, Context, Error};
+use anyhow::{bail, ensure, Context, Error};
 use app::App;
-use bb8_postgres::tokio_postgres::NoTls;
+use chrono::{DateTime, TimeZone, Utc};
 use clap::ArgMatches;
-use database::Database;
+use database::{postgres_connection_config, Database};
+use secrets::Secrets;
+use serde::Serialize;
 use tera::Tera;
 use tokio::task::spawn_local;
 
@@ -19,17 +38,67 @@ use crate::config::Config;
 
 /// Default markdown template used for generating reminder events.
 const DEFAULT_TEMPLATE: &str = r#"
-**{{ summary }}** {{#if (gt minutes_before 0) }}starts in {{ duration }} {{/if}}{{#if location}}at {{ location }} {{/if}}{{#if attendees}} ─ {{ attendees }}{{/if}}{{#if description}}
+**{{ summary }}** {{#if (gt minutes_before 0) }}{{t "starts_in"}} {{ duration }} {{/if}}{{#if location}}{{t "at"}} {{ location }} {{/if}}{{#if attendees}} ─ {{ attendees }}{{/if}}{{#if attendee_times}}
 
-**Description:** {{ description }}
+_{{ attendee_times }}_{{/if}}{{#if conference_url}}
+
+[{{t "join_call"}}]({{ conference_url }}){{/if}}{{#if description}}
+
+**{{t "description"}}** {{ description }}
+{{/if}}{{#if last_occurrence}}
+
+_{{t "last_occurrence"}}_{{/if}}{{#if event_url}}
+
+_[{{t "manage_reminder"}}]({{ event_url }})_
 {{/if}}
 "#;
 
+/// Markdown template for the weekly summary DM sent to users who opt in.
+const WEEKLY_SUMMARY_TEMPLATE: &str = r#"
+**Your reminders for the week of {{ week_start }}**
+{{#each days}}
+
+**{{ this.day }}**
+{{#each this.events}}
+- {{ this.time }} **{{ this.summary }}**{{#if this.event_url}} ([manage]({{ this.event_url }})){{/if}}
+{{/each}}
+{{else}}
+
+You have no events with reminders this week.
+{{/each}}
+"#;
+
+/// Markdown template for the notice sent to rooms when an event they have a
+/// reminder for moves or changes location.
+const EVENT_CHANGED_TEMPLATE: &str = r#"Heads up: **{{ summary }}** {{#if new_time}}moved to {{ new_time }} (was {{ old_time }}){{#if location_changed}} and {{/if}}{{/if}}{{#if location_changed}}{{#if location}}is now at {{ location }}{{else}}no longer has a location{{/if}}{{/if}}."#;
+
+/// Information about what version of the app is running.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub build_timestamp: Option<DateTime<Utc>>,
+}
+
+/// Get the version, git commit and build time of the app.
+pub fn build_info() -> BuildInfo {
+    let build_timestamp = env!("CALBOT_BUILD_TIMESTAMP")
+        .parse()
+        .ok()
+        .and_then(|secs| Utc.timestamp_opt(secs, 0).single());
+
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("CALBOT_GIT_COMMIT"),
+        build_timestamp,
+    }
+}
+
 pub async fn create_database(config: &Config) -> Result<Database, Error> {
-    let manager = bb8_postgres::PostgresConnectionManager::new_from_stringlike(
-        &config.database.connection_string,
-        NoTls,
-    )?;
+    let secrets = Secrets::from_config(config.encryption.as_ref())?;
+
+    let (pg_config, tls) = postgres_connection_config(&config.database)?;
+    let manager = bb8_postgres::PostgresConnectionManager::new(pg_config, tls);
     let db_pool = bb8::Pool::builder().max_size(15).build(manager).await?;
 
     {
@@ -38,24 +107,60 @@ pub async fn create_database(config: &Config) -> Result<Database, Error> {
         ensure!(row.get::<_, i32>(0) == 1, "Got invalid result from DB");
     }
 
-    Ok(Database::from_pool(db_pool))
+    Ok(Database::from_pool(db_pool).with_secrets(secrets))
+}
+
+/// Re-encrypt the secrets stored in the database with the current key, e.g.
+/// after adding or rotating the key.
+pub async fn rotate_secrets(config: Config) -> Result<(), Error> {
+    let database = create_database(&config).await?;
+
+    let updated = database.reencrypt_secrets().await?;
+    println!("Re-encrypted {} secrets", updated);
+
+    Ok(())
 }
 
 pub async fn create_user(config: Config, args: &ArgMatches) -> Result<(), Error> {
     let database = create_database(&config).await?;
     let username = args.get_one::<String>("username").unwrap();
     let password = args.get_one::<String>("password").unwrap();
+
+    if let Some(rejection) =
+        password::check_password_policy(&config.password_policy, &reqwest::Client::new(), password)
+            .await?
+    {
+        bail!("Password rejected: {}", rejection);
+    }
+
     let user_id = database.upsert_account(username).await?;
     database.change_password(user_id, password).await?;
     Ok(())
 }
 
+pub fn print_appservice_registration(config: &Config) -> Result<(), Error> {
+    let appservice = config
+        .matrix
+        .appservice
+        .as_ref()
+        .context("No appservice configured")?;
+
+    print!("{}", appservice.registration(&config.matrix));
+
+    Ok(())
+}
+
 pub async fn create_app(config: Config) -> Result<App, Error> {
     let database = create_database(&config).await?;
 
     let resource_directory = Path::new(config.app.resource_directory.as_deref().unwrap_or("res"));
 
-    let templates = Tera::new(&resource_directory.join("*").to_string_lossy())?;
+    let mut templates = Tera::new(&resource_directory.join("*").to_string_lossy())?;
+
+    // Make the build info available to all templates, e.g. for the footer.
+    templates.register_function("build_info", |_: &HashMap<String, tera::Value>| {
+        tera::to_value(build_info()).map_err(|e| tera::Error::msg(e.to_string()))
+    });
 
     let app = App::new(config, database, templates).await?;
 
@@ -65,6 +170,8 @@ pub async fn create_app(config: Config) -> Result<App, Error> {
 pub async fn start(config: Config) -> Result<(), Error> {
     let app = create_app(config).await?;
 
+    app.check_matrix_connection().await?;
+
     spawn_local(app.clone().run());
 
     site::run_server(app).await?;
@@ -0,0 +1,319 @@
+//! Translations of the reminder output, so that reminders can be sent in
+//! languages other than English.
+//!
+//! Each [`Locale`] has a catalogue of the phrases used by the default
+//! template (looked up with the `t` template helper), along with the names of
+//! days and months and the units used when formatting dates and durations.
+
+use std::fmt::{Error, Write};
+
+use chrono::{DateTime, Datelike, Duration, TimeZone};
+use chrono_humanize::{Accuracy, HumanTime, Tense};
+use serde::Serialize;
+
+/// A language that reminders can be rendered in.
+#[derive(Debug, Serialize)]
+pub struct Locale {
+    /// The language code, e.g. `de`.
+    pub code: &'static str,
+    /// The name of the language in that language, e.g. `Deutsch`.
+    pub name: &'static str,
+    #[serde(skip)]
+    messages: &'static [(&'static str, &'static str)],
+    #[serde(skip)]
+    weekdays: [&'static str; 7],
+    #[serde(skip)]
+    short_weekdays: [&'static str; 7],
+    #[serde(skip)]
+    months: [&'static str; 12],
+    #[serde(skip)]
+    short_months: [&'static str; 12],
+    /// Singular and plural names of weeks, days, hours and minutes.
+    #[serde(skip)]
+    units: [(&'static str, &'static str); 4],
+    /// The word used to join the last two parts of a duration.
+    #[serde(skip)]
+    and: &'static str,
+}
+
+/// The supported locales, the first of which is the default.
+pub static LOCALES: &[Locale] = &[
+    Locale {
+        code: "en",
+        name: "English",
+        messages: &[
+            ("starts_in", "starts in"),
+            ("at", "at"),
+            ("join_call", "Join call"),
+            ("description", "Description:"),
+            ("manage_reminder", "Manage this reminder"),
+            ("last_occurrence", "This is the last occurrence."),
+        ],
+        weekdays: [
+            "Monday",
+            "Tuesday",
+            "Wednesday",
+            "Thursday",
+            "Friday",
+            "Saturday",
+            "Sunday",
+        ],
+        short_weekdays: ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+        months: [
+            "January",
+            "February",
+            "March",
+            "April",
+            "May",
+            "June",
+            "July",
+            "August",
+            "September",
+            "October",
+            "November",
+            "December",
+        ],
+        short_months: [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ],
+        units: [
+            ("week", "weeks"),
+            ("day", "days"),
+            ("hour", "hours"),
+            ("minute", "minutes"),
+        ],
+        and: "and",
+    },
+    Locale {
+        code: "de",
+        name: "Deutsch",
+        messages: &[
+            ("starts_in", "beginnt in"),
+            ("at", "in"),
+            ("join_call", "Anruf beitreten"),
+            ("description", "Beschreibung:"),
+            ("manage_reminder", "Diese Erinnerung verwalten"),
+            ("last_occurrence", "Dies ist der letzte Termin."),
+        ],
+        weekdays: [
+            "Montag",
+            "Dienstag",
+            "Mittwoch",
+            "Donnerstag",
+            "Freitag",
+            "Samstag",
+            "Sonntag",
+        ],
+        short_weekdays: ["Mo", "Di", "Mi", "Do", "Fr", "Sa", "So"],
+        months: [
+            "Januar",
+            "Februar",
+            "März",
+            "April",
+            "Mai",
+            "Juni",
+            "Juli",
+            "August",
+            "September",
+            "Oktober",
+            "November",
+            "Dezember",
+        ],
+        short_months: [
+            "Jan", "Feb", "Mär", "Apr", "Mai", "Jun", "Jul", "Aug", "Sep", "Okt", "Nov", "Dez",
+        ],
+        // Durations mostly follow "in", so use the dative plurals.
+        units: [
+            ("Woche", "Wochen"),
+            ("Tag", "Tagen"),
+            ("Stunde", "Stunden"),
+            ("Minute", "Minuten"),
+        ],
+        and: "und",
+    },
+    Locale {
+        code: "fr",
+        name: "Français",
+        messages: &[
+            ("starts_in", "commence dans"),
+            ("at", "à"),
+            ("join_call", "Rejoindre l’appel"),
+            ("description", "Description :"),
+            ("manage_reminder", "Gérer ce rappel"),
+            ("last_occurrence", "C’est la dernière occurrence."),
+        ],
+        weekdays: [
+            "lundi", "mardi", "mercredi", "jeudi", "vendredi", "samedi", "dimanche",
+        ],
+        short_weekdays: ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+        months: [
+            "janvier",
+            "février",
+            "mars",
+            "avril",
+            "mai",
+            "juin",
+            "juillet",
+            "août",
+            "septembre",
+            "octobre",
+            "novembre",
+            "décembre",
+        ],
+        short_months: [
+            "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+            "nov.", "déc.",
+        ],
+        units: [
+            ("semaine", "semaines"),
+            ("jour", "jours"),
+            ("heure", "heures"),
+            ("minute", "minutes"),
+        ],
+        and: "et",
+    },
+    Locale {
+        code: "es",
+        name: "Español",
+        messages: &[
+            ("starts_in", "empieza en"),
+            ("at", "en"),
+            ("join_call", "Unirse a la llamada"),
+            ("description", "Descripción:"),
+            ("manage_reminder", "Gestionar este recordatorio"),
+            ("last_occurrence", "Esta es la última vez."),
+        ],
+        weekdays: [
+            "lunes",
+            "martes",
+            "miércoles",
+            "jueves",
+            "viernes",
+            "sábado",
+            "domingo",
+        ],
+        short_weekdays: ["lun", "mar", "mié", "jue", "vie", "sáb", "dom"],
+        months: [
+            "enero",
+            "febrero",
+            "marzo",
+            "abril",
+            "mayo",
+            "junio",
+            "julio",
+            "agosto",
+            "septiembre",
+            "octubre",
+            "noviembre",
+            "diciembre",
+        ],
+        short_months: [
+            "ene", "feb", "mar", "abr", "may", "jun", "jul", "ago", "sept", "oct", "nov", "dic",
+        ],
+        units: [
+            ("semana", "semanas"),
+            ("día", "días"),
+            ("hora", "horas"),
+            ("minuto", "minutos"),
+        ],
+        and: "y",
+    },
+];
+
+/// Look up a supported locale by its language code.
+pub fn get_locale(code: &str) -> Option<&'static Locale> {
+    LOCALES.iter().find(|locale| locale.code == code)
+}
+
+/// The locale used if neither the reminder nor its room pick one.
+pub fn default_locale() -> &'static Locale {
+    &LOCALES[0]
+}
+
+impl Locale {
+    /// Look up a phrase in the message catalogue.
+    pub fn message(&self, key: &str) -> Option<&'static str> {
+        self.messages
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, message)| *message)
+    }
+
+    /// Format a number of minutes as text, e.g. "1 hour and 30 minutes".
+    pub fn format_duration(&self, minutes: i64) -> String {
+        // chrono-humanize only speaks English, but we keep using it there so
+        // that existing reminders read exactly as they did.
+        if self.code == "en" {
+            return HumanTime::from(Duration::minutes(minutes))
+                .to_text_en(Accuracy::Precise, Tense::Present);
+        }
+
+        let minutes = minutes.abs();
+        let amounts = [
+            minutes / (7 * 24 * 60),
+            minutes / (24 * 60) % 7,
+            minutes / 60 % 24,
+            minutes % 60,
+        ];
+
+        let mut parts = amounts
+            .iter()
+            .zip(self.units.iter())
+            .filter(|(amount, _)| **amount > 0)
+            .map(|(amount, (singular, plural))| {
+                format!(
+                    "{} {}",
+                    amount,
+                    if *amount == 1 { singular } else { plural }
+                )
+            })
+            .collect::<Vec<_>>();
+
+        match parts.pop() {
+            None => format!("0 {}", self.units[3].1),
+            Some(last) if parts.is_empty() => last,
+            Some(last) => format!("{} {} {}", parts.join(", "), self.and, last),
+        }
+    }
+
+    /// Format a date with a `strftime` style format, using this locale's
+    /// names for days (`%A`, `%a`) and months (`%B`, `%b`).
+    ///
+    /// Returns an error if the format is invalid.
+    pub fn format_date<Tz>(&self, date: &DateTime<Tz>, format: &str) -> Result<String, Error>
+    where
+        Tz: TimeZone,
+        Tz::Offset: std::fmt::Display,
+    {
+        let weekday = date.weekday().num_days_from_monday() as usize;
+        let month = date.month0() as usize;
+
+        let mut localized_format = String::with_capacity(format.len());
+        let mut chars = format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                localized_format.push(c);
+                continue;
+            }
+
+            match chars.next() {
+                Some('A') => localized_format.push_str(self.weekdays[weekday]),
+                Some('a') => localized_format.push_str(self.short_weekdays[weekday]),
+                Some('B') => localized_format.push_str(self.months[month]),
+                Some('b') => localized_format.push_str(self.short_months[month]),
+                Some(other) => {
+                    localized_format.push('%');
+                    localized_format.push(other);
+                }
+                None => localized_format.push('%'),
+            }
+        }
+
+        // Formatting with an invalid format string errors rather than
+        // panicking if we write it out ourselves.
+        let mut formatted = String::new();
+        write!(formatted, "{}", date.format(&localized_format))?;
+
+        Ok(formatted)
+    }
+}
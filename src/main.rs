@@ -39,6 +39,14 @@ fn main() -> Result<(), Error> {
                 .arg(Arg::new("username").required(true))
                 .arg(Arg::new("password").required(true)),
         )
+        .subcommand(
+            Command::new("appservice-registration")
+                .about("Print the appservice registration file for the homeserver"),
+        )
+        .subcommand(
+            Command::new("rotate-secrets")
+                .about("Re-encrypt the secrets stored in the database with the current key"),
+        )
         .get_matches();
 
     let config_file = matches.get_one::<String>("config").unwrap();
@@ -67,6 +75,10 @@ fn main() -> Result<(), Error> {
 async fn async_main(matches: clap::ArgMatches, config: Config) -> Result<(), Error> {
     match matches.subcommand() {
         Some(("create-user", submatches)) => calendar_bot::create_user(config, submatches).await,
+        Some(("appservice-registration", _)) => {
+            calendar_bot::print_appservice_registration(&config)
+        }
+        Some(("rotate-secrets", _)) => calendar_bot::rotate_secrets(config).await,
         _ => calendar_bot::start(config).await,
     }
 }
@@ -0,0 +1,109 @@
+//! Password strength policy, applied whenever a user sets a password.
+
+use std::{error::Error as StdError, fmt, ops::Deref};
+
+use anyhow::{bail, Error};
+use sha1::{Digest, Sha1};
+use tracing::warn;
+
+use crate::config::PasswordPolicyConfig;
+
+/// Why a password was rejected by the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordRejection {
+    TooShort,
+    TooSimple,
+    Pwned,
+}
+
+impl PasswordRejection {
+    /// The `state` query param used to report the rejection in the web UI.
+    pub fn as_state(&self) -> &'static str {
+        match self {
+            PasswordRejection::TooShort => "too_short",
+            PasswordRejection::TooSimple => "too_simple",
+            PasswordRejection::Pwned => "pwned",
+        }
+    }
+}
+
+impl fmt::Display for PasswordRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordRejection::TooShort => write!(f, "password is too short"),
+            PasswordRejection::TooSimple => write!(f, "password is too simple"),
+            PasswordRejection::Pwned => write!(f, "password has appeared in a data breach"),
+        }
+    }
+}
+
+/// Check the password against the configured policy, returning why it was
+/// rejected (if it was).
+pub async fn check_password_policy(
+    policy: &PasswordPolicyConfig,
+    http_client: &reqwest::Client,
+    password: &str,
+) -> Result<Option<PasswordRejection>, Error> {
+    if password.chars().count() < policy.min_length() {
+        return Ok(Some(PasswordRejection::TooShort));
+    }
+
+    // Count how many of lowercase, uppercase, digits and symbols are used.
+    let character_classes = [
+        password.chars().any(char::is_lowercase),
+        password.chars().any(char::is_uppercase),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ]
+    .iter()
+    .filter(|used| **used)
+    .count();
+
+    if character_classes < policy.min_character_classes() {
+        return Ok(Some(PasswordRejection::TooSimple));
+    }
+
+    if policy.check_pwned.unwrap_or(false) {
+        // We don't want to stop people changing their password if the API is
+        // down, so we only log failures.
+        match is_pwned(http_client, password).await {
+            Ok(true) => return Ok(Some(PasswordRejection::Pwned)),
+            Ok(false) => {}
+            Err(err) => warn!(
+                error = err.deref() as &dyn StdError,
+                "Failed to check password against haveibeenpwned"
+            ),
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check if the password has appeared in a breach using the haveibeenpwned
+/// k-anonymity API, which means only the first five characters of the SHA-1
+/// hash of the password are sent.
+async fn is_pwned(http_client: &reqwest::Client, password: &str) -> Result<bool, Error> {
+    let hash = hex::encode_upper(Sha1::digest(password.as_bytes()));
+    let (prefix, suffix) = hash.split_at(5);
+
+    let resp = http_client
+        .get(format!("https://api.pwnedpasswords.com/range/{}", prefix))
+        .header("Add-Padding", "true")
+        .send()
+        .await?;
+
+    if !resp.status().is_success() {
+        bail!("Got non-2xx from pwnedpasswords: {}", resp.status());
+    }
+
+    let body = resp.text().await?;
+
+    // Each line is of the form `SUFFIX:COUNT`, padding entries have a count of
+    // zero.
+    let pwned = body.lines().any(|line| {
+        let mut split = line.trim().split(':');
+        split.next() == Some(suffix) && split.next().is_some_and(|count| count != "0")
+    });
+
+    Ok(pwned)
+}
@@ -0,0 +1,161 @@
+//! Quiet hours, during which reminders aren't posted, e.g. overnight or at
+//! weekends.
+
+use anyhow::{ensure, Context, Error};
+use chrono::{DateTime, Datelike, Duration, NaiveDateTime, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+/// What to do with reminders that are due during quiet hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietHoursAction {
+    /// Post the reminder at the end of the quiet hours.
+    #[default]
+    Defer,
+    /// Don't post the reminder at all.
+    Suppress,
+}
+
+/// A schedule of quiet hours, either from the config or set for a room.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct QuietHours {
+    /// When the quiet hours start each day, e.g. `20:00`. The quiet hours can
+    /// span midnight.
+    #[serde(default, with = "time_of_day")]
+    pub start: Option<NaiveTime>,
+    /// When the quiet hours end each day, e.g. `08:00`.
+    #[serde(default, with = "time_of_day")]
+    pub end: Option<NaiveTime>,
+    /// Whether the whole of Saturday and Sunday are quiet.
+    #[serde(default)]
+    pub weekends: bool,
+    /// The timezone the times and days are in. Defaults to UTC.
+    #[serde(default = "default_timezone", with = "timezone")]
+    pub timezone: Tz,
+    #[serde(default)]
+    pub action: QuietHoursAction,
+}
+
+fn default_timezone() -> Tz {
+    Tz::UTC
+}
+
+impl QuietHours {
+    /// Check that the daily quiet hours have both a start and an end, if
+    /// either is set.
+    pub fn validate(&self) -> Result<(), Error> {
+        ensure!(
+            self.start.is_some() == self.end.is_some(),
+            "Quiet hours need both a start and an end time"
+        );
+
+        Ok(())
+    }
+
+    /// Whether the given time falls in the quiet hours.
+    pub fn is_quiet(&self, at: DateTime<Utc>) -> bool {
+        let local = at.with_timezone(&self.timezone);
+
+        if self.weekends && matches!(local.weekday(), Weekday::Sat | Weekday::Sun) {
+            return true;
+        }
+
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start < end => start <= local.time() && local.time() < end,
+            // The quiet hours span midnight.
+            (Some(start), Some(end)) if start > end => start <= local.time() || local.time() < end,
+            _ => false,
+        }
+    }
+
+    /// Get the first time at or after the given time that isn't in the quiet
+    /// hours.
+    ///
+    /// Returns `None` if there isn't one, which can only happen if the
+    /// schedule is always quiet.
+    pub fn next_allowed(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if !self.is_quiet(at) {
+            return Some(at);
+        }
+
+        // Quiet hours can only end at the end of the daily window or at
+        // midnight (at the end of a weekend), so we check those over the next
+        // week.
+        let today = at.with_timezone(&self.timezone).date_naive();
+        let midnight = NaiveTime::from_hms_opt(0, 0, 0).expect("valid time");
+
+        (0..=8)
+            .map(|days| today + Duration::days(days))
+            .flat_map(|date| {
+                [Some(midnight), self.end]
+                    .into_iter()
+                    .flatten()
+                    .map(move |time| date.and_time(time))
+            })
+            .filter_map(|local| self.resolve_local(local))
+            .filter(|candidate| *candidate > at && !self.is_quiet(*candidate))
+            .min()
+    }
+
+    /// Convert a local time to UTC, skipping over times that don't exist
+    /// due to DST changes.
+    fn resolve_local(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let resolved = self
+            .timezone
+            .from_local_datetime(&local)
+            .earliest()
+            .or_else(|| {
+                self.timezone
+                    .from_local_datetime(&(local + Duration::hours(1)))
+                    .earliest()
+            })?;
+
+        Some(resolved.with_timezone(&Utc))
+    }
+}
+
+/// Parse a time of day, e.g. `20:00`.
+pub fn parse_time_of_day(time: &str) -> Result<NaiveTime, Error> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .with_context(|| format!("Invalid time '{}', expected HH:MM", time))
+}
+
+/// (De)serialize optional times of day as `HH:MM`.
+mod time_of_day {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(time: &Option<NaiveTime>, s: S) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => s.serialize_some(&time.format("%H:%M").to_string()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<NaiveTime>, D::Error> {
+        let time = if let Some(time) = Option::<String>::deserialize(d)? {
+            time
+        } else {
+            return Ok(None);
+        };
+
+        parse_time_of_day(&time)
+            .map(Some)
+            .map_err(|e| de::Error::custom(format!("{:#}", e)))
+    }
+}
+
+/// (De)serialize timezones by name.
+mod timezone {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(timezone: &Tz, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(timezone.name())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Tz, D::Error> {
+        let name = String::deserialize(d)?;
+        name.parse()
+            .map_err(|_| de::Error::custom(format!("Unknown timezone '{}'", name)))
+    }
+}
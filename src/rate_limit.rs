@@ -0,0 +1,132 @@
+//! Rate limiting of failed authentication attempts, to slow down guessing
+//! passwords and API tokens.
+//!
+//! Attempts are counted in fixed windows per key, where the key identifies
+//! the IP address or account being limited.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use actix_web::HttpRequest;
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use itertools::Itertools;
+
+use crate::config::{RateLimitBackend, RateLimitConfig};
+use crate::database::Database;
+
+/// Counts failed attempts, either in memory or in the database.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    /// The start of the current window and the number of attempts in it, for
+    /// each key. Only used with the memory backend.
+    attempts: Arc<Mutex<HashMap<String, (DateTime<Utc>, i32)>>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        RateLimiter {
+            config,
+            attempts: Default::default(),
+        }
+    }
+
+    /// The IP address the request came from.
+    ///
+    /// The `X-Forwarded-For` header is only used if the request came from one
+    /// of the configured trusted proxies, as otherwise clients could pick a
+    /// new address for every request. We take the right-most address in it
+    /// that isn't a trusted proxy, as the ones before it can be spoofed.
+    pub fn client_ip(&self, req: &HttpRequest) -> String {
+        let peer = match req.peer_addr() {
+            Some(addr) => addr.ip().to_string(),
+            // Requests over a Unix socket don't have a peer address.
+            None => "unix".to_string(),
+        };
+
+        if !self.is_trusted_proxy(&peer) {
+            return peer;
+        }
+
+        let forwarded_for = req
+            .headers()
+            .get_all("X-Forwarded-For")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .collect_vec();
+
+        forwarded_for
+            .into_iter()
+            .rev()
+            .find(|addr| !self.is_trusted_proxy(addr))
+            .map(str::to_string)
+            .unwrap_or(peer)
+    }
+
+    fn is_trusted_proxy(&self, addr: &str) -> bool {
+        self.config
+            .trusted_proxies
+            .iter()
+            .any(|proxy| proxy == addr)
+    }
+
+    /// Whether the key has used up all its attempts in the current window.
+    pub async fn is_limited(&self, database: &Database, key: &str) -> Result<bool, Error> {
+        let since = Utc::now() - self.config.window();
+
+        let attempts = match self.config.backend() {
+            RateLimitBackend::Memory => {
+                let attempts = self.attempts.lock().expect("poisoned");
+                match attempts.get(key) {
+                    Some((window_start, count)) if *window_start > since => *count,
+                    _ => 0,
+                }
+            }
+            RateLimitBackend::Database => database.get_rate_limit_attempts(key, since).await?,
+        };
+
+        Ok(attempts >= self.config.max_attempts())
+    }
+
+    /// Record a failed attempt for the key.
+    pub async fn record_failure(&self, database: &Database, key: &str) -> Result<(), Error> {
+        let now = Utc::now();
+        let since = now - self.config.window();
+
+        match self.config.backend() {
+            RateLimitBackend::Memory => {
+                let mut attempts = self.attempts.lock().expect("poisoned");
+
+                // Drop expired windows so that the map doesn't grow forever.
+                attempts.retain(|_, (window_start, _)| *window_start > since);
+
+                attempts
+                    .entry(key.to_string())
+                    .and_modify(|(_, count)| *count += 1)
+                    .or_insert((now, 1));
+            }
+            RateLimitBackend::Database => {
+                database.record_rate_limit_attempt(key, now, since).await?
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forget the failed attempts for the key, e.g. after a successful login.
+    pub async fn clear(&self, database: &Database, key: &str) -> Result<(), Error> {
+        match self.config.backend() {
+            RateLimitBackend::Memory => {
+                self.attempts.lock().expect("poisoned").remove(key);
+            }
+            RateLimitBackend::Database => database.delete_rate_limit(key).await?,
+        }
+
+        Ok(())
+    }
+}
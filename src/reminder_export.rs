@@ -0,0 +1,161 @@
+//! Exporting a user's reminders to a portable JSON document, and importing
+//! them again, e.g. when moving to a different deployment.
+//!
+//! Calendar IDs differ between deployments, so on import reminders are
+//! matched up with events by the event's UID, preferring a calendar with the
+//! same URL as the one the reminder was exported from.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::database::{Calendar, Reminder};
+
+/// The version of the export format, bumped on incompatible changes.
+pub const REMINDER_EXPORT_VERSION: u32 = 1;
+
+/// A document containing all of a user's reminders.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReminderExport {
+    pub version: u32,
+    pub exported_at: DateTime<Utc>,
+    pub reminders: Vec<ExportedReminder>,
+}
+
+/// A reminder, along with enough about its event and calendar to find them
+/// again in a different deployment.
+///
+/// Saved templates aren't included, as they're specific to the deployment.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedReminder {
+    pub calendar_name: String,
+    pub calendar_url: String,
+    pub event_uid: String,
+    /// The summary of the event, to help people recognise skipped reminders.
+    pub summary: Option<String>,
+    pub room: String,
+    pub minutes_before: i64,
+    pub template: Option<String>,
+    pub attendee_editable: bool,
+    pub extra_attendees: Vec<String>,
+    pub excluded_attendees: Vec<String>,
+    pub threaded: bool,
+    pub poll: bool,
+    pub msgtype: Option<String>,
+    pub sender: Option<String>,
+    pub redact_previous: bool,
+    pub attach_ics: bool,
+    pub holiday_region: Option<String>,
+    pub skip_if_declined: bool,
+    pub exclude_tentative: bool,
+    pub exclude_needs_action: bool,
+    pub live_countdown: bool,
+    pub enabled: bool,
+    pub weekdays: Option<i32>,
+    pub locale: Option<String>,
+}
+
+impl ExportedReminder {
+    pub fn new(reminder: &Reminder, calendar: &Calendar, summary: Option<String>) -> Self {
+        ExportedReminder {
+            calendar_name: calendar.name.clone(),
+            calendar_url: calendar.url.clone(),
+            event_uid: reminder.event_id.clone(),
+            summary,
+            room: reminder.room.clone(),
+            minutes_before: reminder.minutes_before,
+            template: reminder.template.clone(),
+            attendee_editable: reminder.attendee_editable,
+            extra_attendees: reminder.extra_attendees.clone(),
+            excluded_attendees: reminder.excluded_attendees.clone(),
+            threaded: reminder.threaded,
+            poll: reminder.poll,
+            msgtype: reminder.msgtype.clone(),
+            sender: reminder.sender.clone(),
+            redact_previous: reminder.redact_previous,
+            attach_ics: reminder.attach_ics,
+            holiday_region: reminder.holiday_region.clone(),
+            skip_if_declined: reminder.skip_if_declined,
+            exclude_tentative: reminder.exclude_tentative,
+            exclude_needs_action: reminder.exclude_needs_action,
+            live_countdown: reminder.live_countdown,
+            enabled: reminder.enabled,
+            weekdays: reminder.weekdays,
+            locale: reminder.locale.clone(),
+        }
+    }
+
+    /// Build the reminder to add for the event in the given calendar.
+    pub fn to_reminder(&self, user_id: i64, calendar_id: i64) -> Reminder {
+        Reminder {
+            reminder_id: -1, // We're inserting so we use a fake ID
+            calendar_id,
+            user_id,
+            event_id: self.event_uid.clone(),
+            template: self.template.clone(),
+            minutes_before: self.minutes_before,
+            room: self.room.clone(),
+            attendee_editable: self.attendee_editable,
+            extra_attendees: self.extra_attendees.clone(),
+            excluded_attendees: self.excluded_attendees.clone(),
+            threaded: self.threaded,
+            poll: self.poll,
+            msgtype: self.msgtype.clone(),
+            sender: self.sender.clone(),
+            redact_previous: self.redact_previous,
+            attach_ics: self.attach_ics,
+            holiday_region: self.holiday_region.clone(),
+            skip_if_declined: self.skip_if_declined,
+            exclude_tentative: self.exclude_tentative,
+            exclude_needs_action: self.exclude_needs_action,
+            live_countdown: self.live_countdown,
+            enabled: self.enabled,
+            weekdays: self.weekdays,
+            template_id: None,
+            locale: self.locale.clone(),
+        }
+    }
+}
+
+/// A reminder that couldn't be imported, and why.
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedReminder {
+    pub event_uid: String,
+    pub summary: Option<String>,
+    pub room: String,
+    pub reason: String,
+}
+
+impl SkippedReminder {
+    pub fn new(reminder: &ExportedReminder, reason: impl Into<String>) -> Self {
+        SkippedReminder {
+            event_uid: reminder.event_uid.clone(),
+            summary: reminder.summary.clone(),
+            room: reminder.room.clone(),
+            reason: reason.into(),
+        }
+    }
+}
+
+/// The outcome of importing reminders.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImportResult {
+    /// The IDs of the added reminders.
+    pub imported: Vec<i64>,
+    pub skipped: Vec<SkippedReminder>,
+}
+
+/// Pick which of the calendars with the event to import the reminder into:
+/// the one with the same URL if there is one, otherwise the first.
+pub fn pick_calendar(
+    reminder: &ExportedReminder,
+    candidates: &[i64],
+    calendars: &[Calendar],
+) -> Option<i64> {
+    calendars
+        .iter()
+        .find(|calendar| {
+            candidates.contains(&calendar.calendar_id) && calendar.url == reminder.calendar_url
+        })
+        .map(|calendar| calendar.calendar_id)
+        .or_else(|| candidates.first().copied())
+}
@@ -0,0 +1,46 @@
+//! Rules that automatically add reminders to matching events, e.g. "any event
+//! whose summary matches `/standup/i` gets a reminder 5 minutes before".
+
+use anyhow::{Context, Error};
+use regex::{Regex, RegexBuilder};
+
+/// The largest compiled pattern we accept, to stop people making the sync do
+/// lots of work.
+const MAX_PATTERN_SIZE: usize = 1 << 16;
+
+/// Compile a rule's summary pattern.
+///
+/// Patterns are regular expressions, optionally written as `/pattern/flags`
+/// where the flags are any of `i` (case insensitive), `m`, `s` and `x`.
+pub fn compile_summary_pattern(pattern: &str) -> Result<Regex, Error> {
+    let (body, flags) = split_pattern(pattern.trim());
+
+    let mut builder = RegexBuilder::new(body);
+    builder.size_limit(MAX_PATTERN_SIZE);
+
+    for flag in flags.chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            'x' => builder.ignore_whitespace(true),
+            _ => anyhow::bail!("Unknown pattern flag '{}'", flag),
+        };
+    }
+
+    builder
+        .build()
+        .with_context(|| format!("Invalid pattern '{}'", pattern))
+}
+
+/// Split a `/pattern/flags` pattern into the pattern and flags. Other
+/// patterns are returned as is.
+fn split_pattern(pattern: &str) -> (&str, &str) {
+    if let Some(rest) = pattern.strip_prefix('/') {
+        if let Some((body, flags)) = rest.rsplit_once('/') {
+            return (body, flags);
+        }
+    }
+
+    (pattern, "")
+}
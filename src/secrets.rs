@@ -0,0 +1,172 @@
+//! Encryption of secrets stored in the database, i.e. calendar passwords and
+//! OAuth2 tokens.
+//!
+//! Encrypted values are stored as `enc:<key id>:<hex nonce and ciphertext>`,
+//! where the key ID says which of the configured keys was used, so that keys
+//! can be rotated. Values without the prefix are plaintext, either because
+//! no key is configured or because they were stored before one was.
+
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, ensure, Context, Error};
+use rand::Rng;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use sha2::{Digest, Sha256};
+
+use crate::config::EncryptionConfig;
+
+/// The prefix of encrypted values.
+const PREFIX: &str = "enc:";
+
+struct SecretKey {
+    /// A short fingerprint of the key, stored alongside the values it
+    /// encrypts.
+    id: String,
+    key: LessSafeKey,
+}
+
+impl SecretKey {
+    fn from_hex(hex_key: &str) -> Result<Self, Error> {
+        let bytes = hex::decode(hex_key.trim()).context("Encryption key must be hex")?;
+        ensure!(
+            bytes.len() == AES_256_GCM.key_len(),
+            "Encryption key must be {} bytes",
+            AES_256_GCM.key_len()
+        );
+
+        let id = hex::encode(&Sha256::digest(&bytes)[..4]);
+        let key =
+            UnboundKey::new(&AES_256_GCM, &bytes).map_err(|_| anyhow!("Invalid encryption key"))?;
+
+        Ok(SecretKey {
+            id,
+            key: LessSafeKey::new(key),
+        })
+    }
+}
+
+/// Encrypts and decrypts secrets with the configured keys.
+#[derive(Clone, Default)]
+pub struct Secrets {
+    /// The key new values are encrypted with, if any.
+    current: Option<Arc<SecretKey>>,
+    /// All the keys values can be decrypted with, including the current one.
+    keys: Vec<Arc<SecretKey>>,
+}
+
+impl std::fmt::Debug for Secrets {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Secrets")
+            .field("current", &self.current.as_ref().map(|key| &key.id))
+            .field("keys", &self.keys.len())
+            .finish()
+    }
+}
+
+impl Secrets {
+    pub fn from_config(config: Option<&EncryptionConfig>) -> Result<Self, Error> {
+        let config = if let Some(config) = config {
+            config
+        } else {
+            return Ok(Secrets::default());
+        };
+
+        let current = match (&config.key, &config.key_file) {
+            (Some(_), Some(_)) => bail!("Only one of `key` and `key_file` can be set"),
+            (Some(key), None) => key.clone(),
+            (None, Some(path)) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read encryption key from {}", path))?,
+            (None, None) => bail!("One of `key` and `key_file` must be set"),
+        };
+
+        let current = Arc::new(SecretKey::from_hex(&current)?);
+
+        let mut keys = vec![current.clone()];
+        for key in &config.previous_keys {
+            keys.push(Arc::new(SecretKey::from_hex(key)?));
+        }
+
+        Ok(Secrets {
+            current: Some(current),
+            keys,
+        })
+    }
+
+    /// Whether a key is configured, i.e. new values are encrypted.
+    pub fn is_enabled(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// Encrypt the value with the current key, or return it unchanged if
+    /// there isn't one.
+    pub fn encrypt(&self, plaintext: &str) -> Result<String, Error> {
+        let current = if let Some(current) = &self.current {
+            current
+        } else {
+            return Ok(plaintext.to_string());
+        };
+
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill(&mut nonce);
+
+        let mut in_out = plaintext.as_bytes().to_vec();
+        current
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut in_out,
+            )
+            .map_err(|_| anyhow!("Failed to encrypt secret"))?;
+
+        Ok(format!(
+            "{}{}:{}{}",
+            PREFIX,
+            current.id,
+            hex::encode(nonce),
+            hex::encode(in_out)
+        ))
+    }
+
+    /// Decrypt a value from the database, which is returned unchanged if it
+    /// isn't encrypted.
+    pub fn decrypt(&self, value: &str) -> Result<String, Error> {
+        let rest = if let Some(rest) = value.strip_prefix(PREFIX) {
+            rest
+        } else {
+            return Ok(value.to_string());
+        };
+
+        let (key_id, data) = rest.split_once(':').context("Invalid encrypted secret")?;
+
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.id == key_id)
+            .with_context(|| format!("Secret is encrypted with unknown key {}", key_id))?;
+
+        let data = hex::decode(data).context("Invalid encrypted secret")?;
+        ensure!(data.len() > NONCE_LEN, "Invalid encrypted secret");
+
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce)
+            .map_err(|_| anyhow!("Invalid encrypted secret"))?;
+
+        let mut in_out = ciphertext.to_vec();
+        let plaintext = key
+            .key
+            .open_in_place(nonce, Aad::empty(), &mut in_out)
+            .map_err(|_| anyhow!("Failed to decrypt secret with key {}", key_id))?;
+
+        Ok(String::from_utf8(plaintext.to_vec())?)
+    }
+
+    /// Whether the value is already encrypted with the current key, so
+    /// doesn't need re-encrypting when rotating keys.
+    pub fn is_current(&self, value: &str) -> bool {
+        match &self.current {
+            Some(current) => value.starts_with(&format!("{}{}:", PREFIX, current.id)),
+            None => !value.starts_with(PREFIX),
+        }
+    }
+}
@@ -1,26 +1,63 @@
 //! The web site for the app.
 
+use std::{collections::HashSet, error::Error as StdError, ops::Deref};
+
+use actix_multipart::Multipart;
 use actix_web::{
     cookie::{Cookie, SameSite},
-    error::{ErrorBadRequest, ErrorForbidden, ErrorInternalServerError, ErrorNotFound},
+    delete,
+    error::{
+        ErrorBadGateway, ErrorBadRequest, ErrorConflict, ErrorForbidden, ErrorInternalServerError,
+        ErrorNotFound,
+    },
     get,
+    http::header::Accept,
     middleware::Logger,
-    post,
-    web::{Data, Form, Path, Query},
-    HttpResponse, HttpServer, Responder,
+    post, put,
+    web::{Data, Form, Json, Path, Query},
+    HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use anyhow::Error;
+use anyhow::{Context, Error};
+use async_graphql::http::GraphiQLSource;
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use futures::TryStreamExt;
 use itertools::Itertools;
+use ring::constant_time;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::task::spawn_local;
+use tracing::warn;
 use tracing_actix_web::TracingLogger;
+use url::Url;
 use urlencoding::encode;
 
-use crate::auth::AuthedUser;
-use crate::database::Reminder;
+use crate::auth::{
+    refresh_session_cookie, session_cookie, AdminUser, ApiUser, AuthedUser, API_TOKEN_SCOPES,
+};
+use crate::calendar::{
+    discover_calendars, feed_to_ics, nextcloud_dav_url, parse_ics_file, parse_pem_certificates,
+    CalendarCollection, DEFAULT_SYNC_LOOKAHEAD_DAYS, DEFAULT_SYNC_LOOKBACK_DAYS,
+};
+use crate::database::{
+    weekday_in_mask, Event, EventInstance, EventSubscription, Reminder, ReminderImage,
+    ReminderRule, SavedTemplate, WeeklySummarySettings, ALL_WEEKDAYS,
+};
+use crate::google::google_events_url;
+use crate::graphql::{build_schema, CalbotSchema};
+use crate::locale::{get_locale, LOCALES};
+use crate::password::check_password_policy;
+use crate::quiet_hours::QuietHours;
+use crate::reminder_export::{
+    ImportResult, ReminderExport, SkippedReminder, REMINDER_EXPORT_VERSION,
+};
+use crate::rules::compile_summary_pattern;
+use crate::systemd;
+use crate::webhooks::{validate_webhook_url, WEBHOOK_EVENT_TYPES, WEBHOOK_SIGNATURE_HEADER};
 use crate::{
     app::{is_likely_a_valid_user_id, App},
-    database::CalendarAuthentication,
+    database::{CalendarAuthentication, CalendarKind},
 };
 
 /// Root handler.
@@ -70,6 +107,25 @@ async fn assert_user_can_edit_reminder(
     }
 }
 
+/// Asserts that the user can use the saved template, i.e. it's either theirs
+/// or shared, returning it.
+async fn assert_user_can_use_template(
+    app: &App,
+    auth_user: AuthedUser,
+    template_id: i64,
+) -> Result<SavedTemplate, actix_web::Error> {
+    let template = app
+        .database
+        .get_template(template_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    match template {
+        Some(template) if template.user_id == *auth_user || template.shared => Ok(template),
+        _ => Err(ErrorForbidden("forbidden")),
+    }
+}
+
 /// List all events in a calendar
 #[get("/events/{calendar_id}")]
 async fn list_events_calendar_html(
@@ -123,10 +179,50 @@ async fn list_events_calendar_html(
     Ok(response)
 }
 
+/// Whether the client asked for JSON rather than HTML in its `Accept` header.
+fn wants_json(req: &HttpRequest) -> bool {
+    req.get_header::<Accept>()
+        .map(|accept| accept.preference().essence_str() == "application/json")
+        .unwrap_or(false)
+}
+
+/// Render the page template with the context, or return the context itself
+/// if the client asked for JSON.
+fn render_html_or_json(
+    app: &App,
+    req: &HttpRequest,
+    template: &str,
+    context: serde_json::Value,
+) -> Result<HttpResponse, actix_web::Error> {
+    // The response depends on the `Accept` header, so caches need to key on
+    // it.
+    if wants_json(req) {
+        return Ok(HttpResponse::Ok()
+            .insert_header(("Vary", "Accept"))
+            .json(context));
+    }
+
+    let result = app
+        .templates
+        .render(
+            template,
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    builder.insert_header(("Vary", "Accept"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
 /// List all events in all calendars for the user.
 #[get("/events")]
 async fn list_events_html(
     app: Data<App>,
+    req: HttpRequest,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
     let events = app
@@ -155,19 +251,160 @@ async fn list_events_html(
         "email": email,
     });
 
-    let result = app
-        .templates
-        .render(
-            "events.html.j2",
-            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+    render_html_or_json(&app, &req, "events.html.j2", context)
+}
+
+/// The page size used by the JSON APIs when none is given.
+pub(crate) const DEFAULT_API_PAGE_SIZE: i64 = 50;
+
+/// The largest page size the JSON APIs accept.
+pub(crate) const MAX_API_PAGE_SIZE: i64 = 500;
+
+/// Query params for paginated JSON APIs.
+#[derive(Debug, Deserialize, Clone)]
+struct PaginationQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+impl PaginationQuery {
+    /// How many results to return.
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_API_PAGE_SIZE)
+            .clamp(1, MAX_API_PAGE_SIZE)
+    }
+
+    /// How many results to skip.
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    /// Truncate the results, which were fetched with one more than the
+    /// limit, returning the offset of the next page if there is one.
+    fn paginate<T>(&self, results: &mut Vec<T>) -> Option<i64> {
+        if results.len() as i64 > self.limit() {
+            results.truncate(self.limit() as usize);
+            Some(self.offset() + self.limit())
+        } else {
+            None
+        }
+    }
+}
+
+/// The JSON representation of an event and its next instance.
+fn event_json(app: &App, event: &Event, instance: &EventInstance) -> serde_json::Value {
+    json!({
+        "calendar_id": event.calendar_id,
+        "event_id": &event.event_id,
+        "summary": &event.summary,
+        "description": &event.description,
+        "location": &event.location,
+        "organizer": event.organizer.as_ref().map(|organizer| &organizer.email),
+        "attendees": instance.attendees.iter().map(|attendee| json!({
+            "email": &attendee.email,
+            "name": &attendee.common_name,
+            "status": &attendee.status,
+        })).collect_vec(),
+        "conference_url": &event.conference_url,
+        "duration_minutes": event.duration_minutes,
+        "next_date": instance.date.to_rfc3339(),
+        "event_url": app.event_url(event.calendar_id, &event.event_id),
+    })
+}
+
+/// API for listing the upcoming events in the user's calendars, ordered by
+/// when they next start. Results are paginated with `limit` and `offset`,
+/// and `next_offset` is set if there are more.
+#[get("/api/v1/events")]
+async fn list_events_api(
+    app: Data<App>,
+    query: Query<PaginationQuery>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("calendars:read")?;
+
+    let mut events = app
+        .database
+        .get_events_page_for_user(user.0, None, query.limit() + 1, query.offset())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let next_offset = query.paginate(&mut events);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "events": events
+            .iter()
+            .map(|(event, instance)| event_json(&app, event, instance))
+            .collect_vec(),
+        "next_offset": next_offset,
+    })))
+}
+
+/// GraphQL API over the user's calendars, events and reminders.
+#[post("/graphql")]
+async fn graphql_api(
+    app: Data<App>,
+    schema: Data<CalbotSchema>,
+    user: ApiUser,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let request = request.into_inner().data(app.get_ref().clone()).data(user);
+
+    schema.execute(request).await.into()
+}
+
+/// An in-browser IDE for exploring the GraphQL API.
+#[get("/graphql")]
+async fn graphiql_html(_user: AuthedUser) -> impl Responder {
+    HttpResponse::Ok()
+        .insert_header(("Content-Type", "text/html; charset=utf-8"))
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// Query params for the event search API.
+#[derive(Debug, Deserialize, Clone)]
+struct SearchEventsQuery {
+    q: String,
+}
+
+/// API for searching the upcoming events in the user's calendars by summary,
+/// description, or organizer or attendee email. Paginated like
+/// [`list_events_api`].
+#[get("/api/v1/events/search")]
+async fn search_events_api(
+    app: Data<App>,
+    query: Query<SearchEventsQuery>,
+    pagination: Query<PaginationQuery>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("calendars:read")?;
+
+    let search = query.q.trim();
+    if search.is_empty() {
+        return Err(ErrorBadRequest("Missing search query"));
+    }
+
+    let mut events = app
+        .database
+        .get_events_page_for_user(
+            user.0,
+            Some(search),
+            pagination.limit() + 1,
+            pagination.offset(),
         )
+        .await
         .map_err(ErrorInternalServerError)?;
 
-    let mut builder = HttpResponse::Ok();
-    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
-    let response = builder.body(result);
+    let next_offset = pagination.paginate(&mut events);
 
-    Ok(response)
+    Ok(HttpResponse::Ok().json(json!({
+        "events": events
+            .iter()
+            .map(|(event, instance)| event_json(&app, event, instance))
+            .collect_vec(),
+        "next_offset": next_offset,
+    })))
 }
 
 /// List all reminders owned by the user.
@@ -221,6 +458,7 @@ async fn list_events_wit_reminders_html(
 #[get("/calendars")]
 async fn list_calendars_html(
     app: Data<App>,
+    req: HttpRequest,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
     let calendars = app
@@ -229,6 +467,12 @@ async fn list_calendars_html(
         .await
         .map_err(ErrorInternalServerError)?;
 
+    let deleted_calendars = app
+        .database
+        .get_deleted_calendars_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let email = app
         .database
         .get_email(user.0)
@@ -237,28 +481,22 @@ async fn list_calendars_html(
 
     let context = json!({
         "calendars": calendars,
+        "deleted_calendars": deleted_calendars.iter().map(|calendar| json!({
+            "calendar_id": calendar.calendar_id,
+            "name": &calendar.name,
+            "purge_at": (calendar.deleted_at + app.deletion_grace_period()).to_rfc3339(),
+        })).collect_vec(),
         "email": email,
     });
 
-    let result = app
-        .templates
-        .render(
-            "calendars.html.j2",
-            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
-        )
-        .map_err(ErrorInternalServerError)?;
-
-    let mut builder = HttpResponse::Ok();
-    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
-    let response = builder.body(result);
-
-    Ok(response)
+    render_html_or_json(&app, &req, "calendars.html.j2", context)
 }
 
 /// Used to parse url that may have a `state` query param.
 #[derive(Debug, Clone, Deserialize)]
 struct EventFormState {
     state: Option<String>,
+    reminder_id: Option<i64>,
 }
 
 /// Create a new reminder
@@ -291,6 +529,12 @@ async fn new_reminder_html(
         return Err(actix_web::error::ErrorNotFound("Couldn't find event"));
     };
 
+    let saved_templates = app
+        .database
+        .get_templates_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let email = app
         .database
         .get_email(user.0)
@@ -306,6 +550,12 @@ async fn new_reminder_html(
             "next_dates": instances.iter().map(|i| i.date.to_rfc3339()).collect_vec()
         },
         "calendar_id": calendar_id,
+        "saved_templates": saved_templates,
+        "space_configured": app.config.app.space.is_some(),
+        "senders": app.config.matrix.senders.iter().map(|sender| &sender.name).collect_vec(),
+        "holiday_regions": app.config.public_holidays.keys().collect_vec(),
+        "locales": LOCALES,
+        "weekdays": weekday_checkboxes(None),
         "default_template": crate::DEFAULT_TEMPLATE,
         "form_state": state,
         "email": email,
@@ -368,6 +618,40 @@ async fn get_reminder_html(
         return Err(actix_web::error::ErrorNotFound("Couldn't find reminder"));
     };
 
+    let poll_responses = app
+        .database
+        .get_poll_responses(reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .into_iter()
+        .group_by(|response| response.timestamp)
+        .into_iter()
+        .map(|(timestamp, responses)| {
+            json!({
+                "timestamp": timestamp.to_rfc3339(),
+                "responses": responses.collect_vec(),
+            })
+        })
+        .collect_vec();
+
+    let send_log = app
+        .database
+        .get_reminder_log(reminder_id, 10)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let saved_templates = app
+        .database
+        .get_templates_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let images = app
+        .database
+        .get_reminder_images(reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let email = app
         .database
         .get_email(user.0)
@@ -384,6 +668,15 @@ async fn get_reminder_html(
         },
         "calendar_id": calendar_id,
         "reminder": reminder,
+        "images": images,
+        "poll_responses": poll_responses,
+        "send_log": send_log,
+        "saved_templates": saved_templates,
+        "space_configured": app.config.app.space.is_some(),
+        "senders": app.config.matrix.senders.iter().map(|sender| &sender.name).collect_vec(),
+        "holiday_regions": app.config.public_holidays.keys().collect_vec(),
+        "locales": LOCALES,
+        "weekdays": weekday_checkboxes(reminder.weekdays),
         "default_template": crate::DEFAULT_TEMPLATE,
         "form_state": state,
         "email": email,
@@ -408,6 +701,7 @@ async fn get_reminder_html(
 #[get("/event/{calendar_id}/{event_id}")]
 async fn get_event_html(
     app: Data<App>,
+    req: HttpRequest,
     path: Path<(i64, String)>,
     query: Query<EventFormState>,
     user: AuthedUser,
@@ -416,9 +710,11 @@ async fn get_event_html(
 
     assert_user_owns_calendar(&app, user, calendar_id).await?;
 
-    let state = match query.into_inner().state.as_deref() {
+    let query = query.into_inner();
+    let state = match query.state.as_deref() {
         Some("saved") => Some("saved"),
         Some("deleted") => Some("deleted"),
+        Some("restored") => Some("restored"),
         _ => None,
     };
 
@@ -440,6 +736,26 @@ async fn get_event_html(
         .await
         .map_err(ErrorInternalServerError)?;
 
+    let attendance = app
+        .database
+        .get_attendance_for_event(calendar_id, &event_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .into_iter()
+        .group_by(|attendance| attendance.timestamp)
+        .into_iter()
+        .map(|(timestamp, attendance)| {
+            let (attending, not_attending): (Vec<_>, Vec<_>) =
+                attendance.partition(|attendance| attendance.attending);
+
+            json!({
+                "timestamp": timestamp.to_rfc3339(),
+                "attending": attending.into_iter().map(|a| a.matrix_id).collect_vec(),
+                "not_attending": not_attending.into_iter().map(|a| a.matrix_id).collect_vec(),
+            })
+        })
+        .collect_vec();
+
     let email = app
         .database
         .get_email(user.0)
@@ -456,24 +772,14 @@ async fn get_event_html(
         },
         "calendar_id": calendar_id,
         "reminders": reminders,
+        "attendance": attendance,
         "default_template": crate::DEFAULT_TEMPLATE,
         "form_state": state,
+        "deleted_reminder_id": query.reminder_id.filter(|_| state == Some("deleted")),
         "email": email,
     });
 
-    let result = app
-        .templates
-        .render(
-            "event.html.j2",
-            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
-        )
-        .map_err(ErrorInternalServerError)?;
-
-    let mut builder = HttpResponse::Ok();
-    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
-    let response = builder.body(result);
-
-    Ok(response)
+    render_html_or_json(&app, &req, "event.html.j2", context)
 }
 
 /// Delete a reminder
@@ -495,29 +801,218 @@ async fn delete_reminder_html(
     assert_user_can_edit_reminder(&app, user, reminder_id).await?;
 
     app.database
-        .delete_reminder_in_calendar(calendar_id, reminder_id)
+        .soft_delete_reminder_in_calendar(calendar_id, reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header((
+        "Location",
+        format!(
+            "/event/{}/{}?state=deleted&reminder_id={}",
+            calendar_id, event_id, reminder_id
+        ),
+    ));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Undo the deletion of a reminder
+#[post("/event/{calendar_id}/{event_id}/restore_reminder")]
+async fn restore_reminder_html(
+    app: Data<App>,
+    path: Path<(i64, String)>,
+    data: Form<RestoreReminderForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id, event_id) = path.into_inner();
+
+    assert_user_can_edit_reminder(&app, user, data.reminder_id).await?;
+
+    let restored = app
+        .database
+        .restore_reminder_in_calendar(calendar_id, data.reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !restored {
+        return Err(ErrorNotFound("Couldn't find reminder"));
+    }
+
+    app.update_reminders()
         .await
         .map_err(ErrorInternalServerError)?;
 
     let mut builder = HttpResponse::SeeOther();
     builder.insert_header((
         "Location",
-        format!("/event/{}/{}?state=deleted", calendar_id, event_id),
+        format!("/event/{}/{}?state=restored", calendar_id, event_id),
     ));
     let response = builder.finish();
 
     Ok(response)
 }
 
+/// Form body for restoring a deleted reminder
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RestoreReminderForm {
+    pub reminder_id: i64,
+}
+
+/// Pause or resume a reminder, redirecting back to the event page.
+async fn set_reminder_enabled(
+    app: &App,
+    user: AuthedUser,
+    calendar_id: i64,
+    event_id: &str,
+    reminder_id: i64,
+    enabled: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    assert_user_can_edit_reminder(app, user, reminder_id).await?;
+
+    let updated = app
+        .database
+        .set_reminder_enabled(reminder_id, enabled)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !updated {
+        return Err(ErrorNotFound("Couldn't find reminder"));
+    }
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/event/{}/{}", calendar_id, event_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Pause sending a reminder, without deleting it
+#[post("/event/{calendar_id}/{event_id}/pause_reminder")]
+async fn pause_reminder_html(
+    app: Data<App>,
+    path: Path<(i64, String)>,
+    data: Form<PauseReminderForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id, event_id) = path.into_inner();
+
+    set_reminder_enabled(&app, user, calendar_id, &event_id, data.reminder_id, false).await
+}
+
+/// Resume sending a paused reminder
+#[post("/event/{calendar_id}/{event_id}/resume_reminder")]
+async fn resume_reminder_html(
+    app: Data<App>,
+    path: Path<(i64, String)>,
+    data: Form<PauseReminderForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id, event_id) = path.into_inner();
+
+    set_reminder_enabled(&app, user, calendar_id, &event_id, data.reminder_id, true).await
+}
+
+/// Form body for pausing or resuming a reminder
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PauseReminderForm {
+    pub reminder_id: i64,
+}
+
 /// Form body for updating/adding a reminder
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct UpdateReminderForm {
     pub reminder_id: Option<i64>,
     pub use_default: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
     pub template: Option<String>,
+    pub template_id: Option<String>, // Empty to not use a saved template.
     pub minutes_before: i64,
     pub room: String,
     pub attendee_editable: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
+    pub threaded: Option<String>,          // A checkbox, so `Some()` if checked, `None` if not.
+    pub poll: Option<String>,              // A checkbox, so `Some()` if checked, `None` if not.
+    pub msgtype: Option<String>,           // Empty to use the default.
+    pub sender: Option<String>,            // Empty to use the main account.
+    pub redact_previous: Option<String>,   // A checkbox, so `Some()` if checked, `None` if not.
+    pub attach_ics: Option<String>,        // A checkbox, so `Some()` if checked, `None` if not.
+    pub holiday_region: Option<String>,    // Empty to send on public holidays.
+    pub locale: Option<String>,            // Empty to use the room's language.
+    pub skip_if_declined: Option<String>,  // A checkbox, so `Some()` if checked, `None` if not.
+    pub exclude_tentative: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
+    pub exclude_needs_action: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
+    pub live_countdown: Option<String>,    // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_mon: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_tue: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_wed: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_thu: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_fri: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_sat: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub weekday_sun: Option<String>,       // A checkbox, so `Some()` if checked, `None` if not.
+    pub extra_attendees: Option<String>,
+    pub excluded_attendees: Option<String>,
+}
+
+impl UpdateReminderForm {
+    /// Get the weekday mask from the weekday checkboxes, or `None` if every
+    /// day is checked.
+    fn weekday_mask(&self) -> Option<i32> {
+        let mask = [
+            &self.weekday_mon,
+            &self.weekday_tue,
+            &self.weekday_wed,
+            &self.weekday_thu,
+            &self.weekday_fri,
+            &self.weekday_sat,
+            &self.weekday_sun,
+        ]
+        .into_iter()
+        .enumerate()
+        .filter(|(_, checked)| checked.is_some())
+        .fold(0, |mask, (day, _)| mask | (1 << day));
+
+        (mask != ALL_WEEKDAYS).then_some(mask)
+    }
+}
+
+/// Get the weekday checkboxes to show in the reminder form.
+fn weekday_checkboxes(mask: Option<i32>) -> Vec<serde_json::Value> {
+    [
+        ("weekday_mon", Weekday::Mon),
+        ("weekday_tue", Weekday::Tue),
+        ("weekday_wed", Weekday::Wed),
+        ("weekday_thu", Weekday::Thu),
+        ("weekday_fri", Weekday::Fri),
+        ("weekday_sat", Weekday::Sat),
+        ("weekday_sun", Weekday::Sun),
+    ]
+    .into_iter()
+    .map(|(field, weekday)| {
+        json!({
+            "field": field,
+            "name": weekday.to_string(),
+            "checked": weekday_in_mask(mask, weekday),
+        })
+    })
+    .collect()
+}
+
+/// Split a comma or newline separated list of emails/Matrix IDs.
+fn parse_attendee_list(list: Option<&str>) -> Vec<String> {
+    list.unwrap_or_default()
+        .split([',', '\n'])
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(ToOwned::to_owned)
+        .collect()
 }
 
 /// Add or update a reminder.
@@ -532,40 +1027,80 @@ async fn upsert_reminder_html(
 
     let data = data.into_inner();
 
+    let locale = data.locale.filter(|locale| !locale.is_empty());
+    let weekdays = data.weekday_mask();
+
+    let template_id = match data.template_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(template_id) => {
+            let template_id = template_id
+                .parse()
+                .map_err(|_| ErrorBadRequest("Invalid template"))?;
+            assert_user_can_use_template(&app, user, template_id).await?;
+            Some(template_id)
+        }
+        None => None,
+    };
+
     let template = if data.use_default.is_some() {
         None
     } else {
-        data.template.as_deref()
+        data.template
     };
 
+    // Check the user can make the change before we try and join the room.
     if let Some(reminder_id) = data.reminder_id {
         assert_user_can_edit_reminder(&app, user, reminder_id).await?;
+    } else {
+        assert_user_owns_calendar(&app, user, calendar_id).await?;
+    }
+
+    let mut reminder = Reminder {
+        reminder_id: -1, // We're inserting so we use a fake ID
+        user_id: *user,
+        calendar_id,
+        event_id: event_id.clone(),
+        room: data.room,
+        minutes_before: data.minutes_before,
+        template,
+        attendee_editable: data.attendee_editable.is_some(),
+        extra_attendees: parse_attendee_list(data.extra_attendees.as_deref()),
+        excluded_attendees: parse_attendee_list(data.excluded_attendees.as_deref()),
+        threaded: data.threaded.is_some(),
+        poll: data.poll.is_some(),
+        msgtype: data.msgtype.filter(|msgtype| !msgtype.is_empty()),
+        sender: data.sender.filter(|sender| !sender.is_empty()),
+        redact_previous: data.redact_previous.is_some(),
+        attach_ics: data.attach_ics.is_some(),
+        holiday_region: data.holiday_region.filter(|region| !region.is_empty()),
+        skip_if_declined: data.skip_if_declined.is_some(),
+        exclude_tentative: data.exclude_tentative.is_some(),
+        exclude_needs_action: data.exclude_needs_action.is_some(),
+        live_countdown: data.live_countdown.is_some(),
+        // Updating a reminder doesn't change whether it's paused.
+        enabled: true,
+        weekdays,
+        template_id,
+        locale,
+    };
+
+    if let Some(problem) = app
+        .validate_reminder(&reminder)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorBadRequest(problem));
+    }
+
+    if let Some(reminder_id) = data.reminder_id {
+        reminder.reminder_id = reminder_id;
 
         app.database
-            .update_reminder(
-                calendar_id,
-                reminder_id,
-                &data.room,
-                data.minutes_before,
-                template,
-                data.attendee_editable.is_some(),
-            )
+            .update_reminder(&reminder)
             .await
             .map_err(ErrorInternalServerError)?;
     } else {
-        assert_user_owns_calendar(&app, user, calendar_id).await?;
-
         app.database
-            .add_reminder(&Reminder {
-                reminder_id: -1, // We're inserting so we use a fake ID
-                user_id: *user,
-                calendar_id,
-                event_id: event_id.clone(),
-                room: data.room,
-                minutes_before: data.minutes_before,
-                template: template.map(ToOwned::to_owned),
-                attendee_editable: data.attendee_editable.is_some(),
-            })
+            .add_reminder(&reminder)
             .await
             .map_err(ErrorInternalServerError)?;
     }
@@ -584,19 +1119,16 @@ async fn upsert_reminder_html(
     Ok(response)
 }
 
-/// Get calendar info
-#[get("/calendar/{calendar_id}")]
-async fn get_calendar_html(
+/// Page for moving all reminders from one room to another.
+#[get("/reminders/move_room")]
+async fn move_room_html(
     app: Data<App>,
-    path: Path<(i64,)>,
+    query: Query<MoveRoomFormState>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let (calendar_id,) = path.into_inner();
-    assert_user_owns_calendar(&app, user, calendar_id).await?;
-
-    let calendar = app
+    let rooms = app
         .database
-        .get_calendar(calendar_id)
+        .get_reminder_rooms_for_user(*user)
         .await
         .map_err(ErrorInternalServerError)?;
 
@@ -606,24 +1138,19 @@ async fn get_calendar_html(
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let (user_name, authentication_type) = match calendar.as_ref().map(|c| &c.authentication) {
-        Some(CalendarAuthentication::Basic { user_name, .. }) => (Some(user_name), "basic"),
-        Some(CalendarAuthentication::Bearer { .. }) => (None, "bearer"),
-        Some(CalendarAuthentication::None) => (None, "none"),
-        None => (None, "none"),
-    };
-
     let context = json!({
-        "calendar": calendar,
+        "rooms": rooms.iter().map(|(room, count)| json!({
+            "room": room,
+            "count": count,
+        })).collect_vec(),
+        "moved": query.moved,
         "email": email,
-        "user_name": user_name,
-        "authentication_type": authentication_type,
     });
 
     let result = app
         .templates
         .render(
-            "calendar.html.j2",
+            "move_room.html.j2",
             &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
         )
         .map_err(ErrorInternalServerError)?;
@@ -635,24 +1162,51 @@ async fn get_calendar_html(
     Ok(response)
 }
 
-/// Add new calendar
-#[get("/calendar/new")]
-async fn new_calendar_html(
+/// Page listing the user's reminder rules, with a form to add new ones.
+#[get("/rules")]
+async fn list_rules_html(
     app: Data<App>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
+    let rules = app
+        .database
+        .get_reminder_rules_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let calendars = app
+        .database
+        .get_calendars_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let email = app
         .database
         .get_email(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let context = json!({ "email": email });
+    let context = json!({
+        "rules": rules.iter().map(|rule| json!({
+            "rule_id": rule.rule_id,
+            "summary_pattern": &rule.summary_pattern,
+            "room": &rule.room,
+            "minutes_before": rule.minutes_before,
+            "calendar": rule.calendar_id.and_then(|calendar_id| {
+                calendars.iter().find(|calendar| calendar.calendar_id == calendar_id)
+            }).map(|calendar| &calendar.name),
+        })).collect_vec(),
+        "calendars": calendars.iter().map(|calendar| json!({
+            "calendar_id": calendar.calendar_id,
+            "name": &calendar.name,
+        })).collect_vec(),
+        "email": email,
+    });
 
     let result = app
         .templates
         .render(
-            "calendar.html.j2",
+            "rules.html.j2",
             &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
         )
         .map_err(ErrorInternalServerError)?;
@@ -664,211 +1218,291 @@ async fn new_calendar_html(
     Ok(response)
 }
 
-/// Form body for editing a calendar's config
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UpdateCalendarForm {
-    pub name: String,
-    pub url: String,
-    pub user_name: Option<String>,
-    pub password: Option<String>,
+/// Form body for adding a reminder rule.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddRuleForm {
+    pub summary_pattern: String,
+    pub room: String,
+    pub minutes_before: i64,
+    pub calendar_id: Option<String>, // Empty for all calendars.
 }
 
-/// Edit a calendar's config.
-#[post("/calendar/{calendar_id}/edit")]
-async fn edit_calendar_html(
+/// Add a reminder rule, and apply it to the existing events.
+#[post("/rules")]
+async fn add_rule_html(
     app: Data<App>,
-    path: Path<(i64,)>,
-    data: Form<UpdateCalendarForm>,
+    data: Form<AddRuleForm>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let (calendar_id,) = path.into_inner();
-
-    assert_user_owns_calendar(&app, user, calendar_id).await?;
-
-    let existing_calendar = app
-        .database
-        .get_calendar(calendar_id)
-        .await
-        .map_err(ErrorInternalServerError)?
-        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
-
-    let UpdateCalendarForm {
-        name,
-        url,
-        mut user_name,
-        mut password,
+    let AddRuleForm {
+        summary_pattern,
+        room,
+        minutes_before,
+        calendar_id,
     } = data.into_inner();
 
-    if user_name.as_deref() == Some("") {
-        user_name = None;
-    }
-    if password.as_deref() == Some("") {
-        password = None;
+    compile_summary_pattern(&summary_pattern).map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+
+    if minutes_before < 0 {
+        return Err(ErrorBadRequest("Minutes before must not be negative"));
     }
 
-    // Awful hack to keep password unchanged if left blank, but still using
-    // basic auth.
-    if password.is_none() && user_name.is_some() {
-        let existing_password = match existing_calendar.authentication {
-            CalendarAuthentication::Basic { ref password, .. } => password.clone(),
-            _ => return Err(ErrorInternalServerError("Calendar doesn't have a password")),
-        };
-        password = Some(existing_password)
+    let calendar_id = match calendar_id.as_deref().filter(|id| !id.is_empty()) {
+        Some(calendar_id) => {
+            let calendar_id = calendar_id
+                .parse()
+                .map_err(|_| ErrorBadRequest("Invalid calendar"))?;
+            assert_user_owns_calendar(&app, user, calendar_id).await?;
+            Some(calendar_id)
+        }
+        None => None,
+    };
+
+    if let Some(problem) = app
+        .validate_room(None, &room)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorBadRequest(problem));
     }
 
     app.database
-        .update_calendar(calendar_id, name, url, user_name, password)
+        .add_reminder_rule(&ReminderRule {
+            rule_id: -1, // We're inserting so we use a fake ID
+            user_id: *user,
+            calendar_id,
+            summary_pattern,
+            room,
+            minutes_before,
+        })
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let new_calendar = app
+    // Apply the new rule to the events we already have, rather than waiting
+    // for the next sync.
+    let calendars = app
         .database
-        .get_calendar(calendar_id)
+        .get_calendars_for_user(*user)
         .await
-        .map_err(ErrorInternalServerError)?
-        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+        .map_err(ErrorInternalServerError)?;
+    for calendar in calendars {
+        if calendar_id.is_some() && calendar_id != Some(calendar.calendar_id) {
+            continue;
+        }
+
+        let event_summaries = app
+            .database
+            .get_events_in_calendar(calendar.calendar_id)
+            .await
+            .map_err(ErrorInternalServerError)?
+            .into_iter()
+            .map(|(event, _)| (event.event_id, event.summary))
+            .collect_vec();
 
-    app.update_calendar(new_calendar)
+        app.apply_reminder_rules(calendar.calendar_id, &event_summaries)
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    app.update_reminders()
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let mut builder = HttpResponse::SeeOther();
-    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
-    let response = builder.finish();
-
-    Ok(response)
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/rules"))
+        .finish())
 }
 
-/// Delete a calendar
-#[post("/calendar/{calendar_id}/delete")]
-async fn delete_calendar_html(
+/// Delete a reminder rule, and the reminders it added.
+#[post("/rules/{rule_id}/delete")]
+async fn delete_rule_html(
     app: Data<App>,
     path: Path<(i64,)>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let (calendar_id,) = path.into_inner();
+    let (rule_id,) = path.into_inner();
 
-    assert_user_owns_calendar(&app, user, calendar_id).await?;
-
-    app.database
-        .delete_calendar(calendar_id)
+    let deleted = app
+        .database
+        .delete_reminder_rule(*user, rule_id)
         .await
         .map_err(ErrorInternalServerError)?;
+    if !deleted {
+        return Err(ErrorNotFound("No such rule"));
+    }
 
-    let mut builder = HttpResponse::SeeOther();
-    builder.insert_header(("Location", "/calendars"));
-    let response = builder.finish();
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
 
-    Ok(response)
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/rules"))
+        .finish())
 }
 
-/// Add a new calendar page.
-#[post("/calendar/new")]
-async fn add_new_calendar_html(
+/// Page listing the rooms the user has subscribed to new events in their
+/// calendars, with a form to add new subscriptions.
+#[get("/subscriptions")]
+async fn list_subscriptions_html(
     app: Data<App>,
-    data: Form<UpdateCalendarForm>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let UpdateCalendarForm {
-        name,
-        url,
-        mut user_name,
-        mut password,
-    } = data.into_inner();
-
-    if user_name.as_deref() == Some("") {
-        user_name = None;
-    }
-    if password.as_deref() == Some("") {
-        password = None;
-    }
-
-    let calendar_id = app
+    let subscriptions = app
         .database
-        .add_calendar_basic_auth(*user, name, url, user_name, password)
+        .get_event_subscriptions_for_user(*user)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let new_calendar = app
+    let calendars = app
         .database
-        .get_calendar(calendar_id)
+        .get_calendars_for_user(*user)
         .await
-        .map_err(ErrorInternalServerError)?
-        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+        .map_err(ErrorInternalServerError)?;
 
-    app.update_calendar(new_calendar)
+    let email = app
+        .database
+        .get_email(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let mut builder = HttpResponse::SeeOther();
-    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
-    let response = builder.finish();
+    let context = json!({
+        "subscriptions": subscriptions.iter().map(|subscription| json!({
+            "subscription_id": subscription.subscription_id,
+            "summary_pattern": &subscription.summary_pattern,
+            "room": &subscription.room,
+            "calendar": calendars
+                .iter()
+                .find(|calendar| calendar.calendar_id == subscription.calendar_id)
+                .map(|calendar| &calendar.name),
+        })).collect_vec(),
+        "calendars": calendars.iter().map(|calendar| json!({
+            "calendar_id": calendar.calendar_id,
+            "name": &calendar.name,
+        })).collect_vec(),
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "subscriptions.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
 
     Ok(response)
 }
 
-/// Form body for editing a calendar's config
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct UpdateCalendarOAuth2Form {
-    pub google_id: String,
-    pub name: String,
-    pub account_id: Option<i64>,
+/// Form body for subscribing a room to new events in a calendar.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddSubscriptionForm {
+    pub calendar_id: i64,
+    pub room: String,
+    pub summary_pattern: Option<String>, // Empty for all events.
 }
 
-/// Add a new calendar page.
-#[post("/calendar/new_oauth2")]
-async fn add_oauth2_calendar_html(
+/// Subscribe a room to new events in a calendar.
+#[post("/subscriptions")]
+async fn add_subscription_html(
     app: Data<App>,
-    data: Form<UpdateCalendarOAuth2Form>,
+    data: Form<AddSubscriptionForm>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let UpdateCalendarOAuth2Form {
-        google_id,
-        name,
-        account_id,
+    let AddSubscriptionForm {
+        calendar_id,
+        room,
+        summary_pattern,
     } = data.into_inner();
 
-    let url = format!(
-        "https://apidata.googleusercontent.com/caldav/v2/{}/events",
-        encode(&google_id)
-    );
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
 
-    let account_id = account_id.ok_or_else(|| ErrorBadRequest("Missing account ID"))?;
+    let summary_pattern = summary_pattern.filter(|pattern| !pattern.trim().is_empty());
+    if let Some(summary_pattern) = &summary_pattern {
+        compile_summary_pattern(summary_pattern)
+            .map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+    }
 
-    let calendar_id = app
-        .database
-        .add_calendar_oauth2(*user, name, url, account_id)
+    if let Some(problem) = app
+        .validate_room(None, &room)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorBadRequest(problem));
+    }
+
+    app.database
+        .add_event_subscription(&EventSubscription {
+            subscription_id: -1, // We're inserting so we use a fake ID
+            user_id: *user,
+            calendar_id,
+            room,
+            summary_pattern,
+        })
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let new_calendar = app
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/subscriptions"))
+        .finish())
+}
+
+/// Unsubscribe a room from new events in a calendar.
+#[post("/subscriptions/{subscription_id}/delete")]
+async fn delete_subscription_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (subscription_id,) = path.into_inner();
+
+    let deleted = app
         .database
-        .get_calendar(calendar_id)
+        .delete_event_subscription(*user, subscription_id)
         .await
-        .map_err(ErrorInternalServerError)?
-        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+        .map_err(ErrorInternalServerError)?;
+    if !deleted {
+        return Err(ErrorNotFound("No such subscription"));
+    }
 
-    app.update_calendar(new_calendar)
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/subscriptions"))
+        .finish())
+}
+
+/// Page listing the saved templates the user can use, with forms to add new
+/// ones and edit their own.
+#[get("/templates")]
+async fn list_templates_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let templates = app
+        .database
+        .get_templates_for_user(*user)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let mut builder = HttpResponse::SeeOther();
-    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
-    let response = builder.finish();
-
-    Ok(response)
-}
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
 
-/// Login page
-#[get("/login")]
-async fn login_get_html(app: Data<App>) -> Result<impl Responder, actix_web::Error> {
-    let sso_name = app.config.sso.as_ref().map(|s| &s.display_name);
-    let context = json!({ "sso_name": sso_name });
+    let context = json!({
+        "templates": templates,
+        "user_id": *user,
+        "default_template": crate::DEFAULT_TEMPLATE,
+        "email": email,
+    });
 
     let result = app
         .templates
         .render(
-            "login.html.j2",
+            "templates.html.j2",
             &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
         )
         .map_err(ErrorInternalServerError)?;
@@ -880,79 +1514,3424 @@ async fn login_get_html(app: Data<App>) -> Result<impl Responder, actix_web::Err
     Ok(response)
 }
 
-/// Form body for logging in.
-#[derive(Debug, Deserialize, Clone)]
-struct LoginForm {
-    user_name: String,
-    password: String,
+/// Form body for adding or updating a saved template.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SavedTemplateForm {
+    pub name: String,
+    pub template: String,
+    pub shared: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
 }
 
-/// Login
-#[post("/login")]
-async fn login_post_html(
+impl SavedTemplateForm {
+    /// Check the template has a name and compiles.
+    fn validate(&self) -> Result<(), actix_web::Error> {
+        if self.name.trim().is_empty() {
+            return Err(ErrorBadRequest("Templates need a name"));
+        }
+
+        handlebars::Template::compile(&self.template)
+            .map_err(|e| ErrorBadRequest(format!("Invalid template: {}", e)))?;
+
+        Ok(())
+    }
+}
+
+/// Save a new template.
+#[post("/templates")]
+async fn add_template_html(
     app: Data<App>,
-    data: Form<LoginForm>,
+    data: Form<SavedTemplateForm>,
+    user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let user_id = app
+    data.validate()?;
+
+    app.database
+        .add_template(
+            *user,
+            data.name.trim(),
+            &data.template,
+            data.shared.is_some(),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/templates"))
+        .finish())
+}
+
+/// Update one of the user's saved templates.
+#[post("/templates/{template_id}")]
+async fn update_template_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    data: Form<SavedTemplateForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (template_id,) = path.into_inner();
+
+    data.validate()?;
+
+    let updated = app
+        .database
+        .update_template(
+            *user,
+            template_id,
+            data.name.trim(),
+            &data.template,
+            data.shared.is_some(),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !updated {
+        return Err(ErrorNotFound("No such template"));
+    }
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/templates"))
+        .finish())
+}
+
+/// Delete one of the user's saved templates.
+#[post("/templates/{template_id}/delete")]
+async fn delete_template_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (template_id,) = path.into_inner();
+
+    let deleted = app
+        .database
+        .delete_template(*user, template_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !deleted {
+        return Err(ErrorNotFound("No such template"));
+    }
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/templates"))
+        .finish())
+}
+
+/// Page listing the user's upcoming one-off reminders, with a form to add new
+/// ones.
+#[get("/reminders/adhoc")]
+async fn list_adhoc_reminders_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let reminders = app
+        .database
+        .get_upcoming_adhoc_reminders_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "reminders": reminders,
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "adhoc_reminders.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for adding a one-off reminder.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AddAdhocReminderForm {
+    pub room: String,
+    pub message: String,
+    /// The local time to send the reminder, as given by a `datetime-local`
+    /// input.
+    pub remind_at: String,
+    pub timezone: Option<String>, // Empty to use UTC.
+}
+
+/// Parse the time given by a `datetime-local` input in the given timezone,
+/// using UTC if no timezone is given.
+fn parse_local_time_field(
+    local: &str,
+    timezone: Option<&str>,
+) -> Result<DateTime<Utc>, actix_web::Error> {
+    let timezone: Tz = match timezone.map(str::trim) {
+        Some(timezone) if !timezone.is_empty() => timezone
+            .parse()
+            .map_err(|_| ErrorBadRequest("Unknown timezone"))?,
+        _ => Tz::UTC,
+    };
+
+    let local = NaiveDateTime::parse_from_str(local.trim(), "%Y-%m-%dT%H:%M")
+        .map_err(|_| ErrorBadRequest("Invalid time"))?;
+
+    let time = timezone
+        .from_local_datetime(&local)
+        .earliest()
+        .ok_or_else(|| ErrorBadRequest("Time doesn't exist in that timezone"))?
+        .with_timezone(&Utc);
+
+    Ok(time)
+}
+
+/// Add a one-off reminder.
+#[post("/reminders/adhoc")]
+async fn add_adhoc_reminder_html(
+    app: Data<App>,
+    data: Form<AddAdhocReminderForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let AddAdhocReminderForm {
+        room,
+        message,
+        remind_at,
+        timezone,
+    } = data.into_inner();
+
+    if message.trim().is_empty() {
+        return Err(ErrorBadRequest("Message must not be empty"));
+    }
+
+    let remind_at = parse_local_time_field(&remind_at, timezone.as_deref())?;
+
+    if remind_at <= Utc::now() {
+        return Err(ErrorBadRequest("Time must be in the future"));
+    }
+
+    if let Some(problem) = app
+        .validate_room(None, &room)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorBadRequest(problem));
+    }
+
+    app.database
+        .add_adhoc_reminder(*user, &room, message.trim(), remind_at)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/reminders/adhoc"))
+        .finish())
+}
+
+/// Delete a one-off reminder before it's sent.
+#[post("/reminders/adhoc/{reminder_id}/delete")]
+async fn delete_adhoc_reminder_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (reminder_id,) = path.into_inner();
+
+    let deleted = app
+        .database
+        .delete_adhoc_reminder(*user, reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    if !deleted {
+        return Err(ErrorNotFound("No such reminder"));
+    }
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/reminders/adhoc"))
+        .finish())
+}
+
+/// Used to parse the result of moving reminders between rooms.
+#[derive(Debug, Clone, Deserialize)]
+struct MoveRoomFormState {
+    moved: Option<u64>,
+}
+
+/// Form body for moving reminders between rooms.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MoveRoomForm {
+    pub old_room: String,
+    pub new_room: String,
+}
+
+/// Move all of the user's reminders from one room to another.
+async fn move_room(
+    app: &App,
+    user: AuthedUser,
+    form: &MoveRoomForm,
+) -> Result<u64, actix_web::Error> {
+    let old_room = form.old_room.trim();
+    let new_room = form.new_room.trim();
+
+    if old_room.is_empty() || new_room.is_empty() {
+        return Err(ErrorBadRequest("Both rooms must be given."));
+    }
+
+    let count = app
+        .database
+        .move_reminders_to_room(*user, old_room, new_room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(count)
+}
+
+/// Move all of the user's reminders from one room to another.
+#[post("/reminders/move_room")]
+async fn move_room_post_html(
+    app: Data<App>,
+    data: Form<MoveRoomForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let count = move_room(&app, user, &data).await?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/reminders/move_room?moved={}", count)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// API for moving all of the user's reminders from one room to another.
+#[post("/api/v1/reminders/move_room")]
+async fn move_room_api(
+    app: Data<App>,
+    data: Json<MoveRoomForm>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:write")?;
+
+    let count = move_room(&app, user, &data).await?;
+
+    Ok(HttpResponse::Ok().json(json!({ "moved": count })))
+}
+
+/// Body for adding or updating reminders on many events at once.
+#[derive(Debug, Clone, Deserialize)]
+struct BulkRemindersRequest {
+    /// Only events in this calendar, rather than all the user's calendars.
+    calendar_id: Option<i64>,
+    /// Only events whose summary matches the pattern.
+    summary_pattern: Option<String>,
+    room: String,
+    minutes_before: i64,
+}
+
+/// API for adding a reminder in a room to every upcoming event that matches,
+/// or updating the existing reminders in the room.
+#[post("/api/v1/reminders/bulk")]
+async fn bulk_reminders_api(
+    app: Data<App>,
+    data: Json<BulkRemindersRequest>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:write")?;
+
+    let BulkRemindersRequest {
+        calendar_id,
+        summary_pattern,
+        room,
+        minutes_before,
+    } = data.into_inner();
+
+    let summary_pattern = summary_pattern.filter(|pattern| !pattern.trim().is_empty());
+    if let Some(pattern) = &summary_pattern {
+        compile_summary_pattern(pattern).map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+    }
+
+    if minutes_before < 0 {
+        return Err(ErrorBadRequest("Minutes before must not be negative"));
+    }
+
+    if let Some(calendar_id) = calendar_id {
+        assert_user_owns_calendar(&app, user, calendar_id).await?;
+    }
+
+    let room = room.trim();
+    if let Some(problem) = app
+        .validate_room(None, room)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorBadRequest(problem));
+    }
+
+    let result = app
+        .bulk_upsert_reminders(
+            *user,
+            calendar_id,
+            summary_pattern.as_deref(),
+            room,
+            minutes_before,
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "matched": result.created.len() + result.updated.len(),
+        "created": result.created,
+        "updated": result.updated,
+    })))
+}
+
+/// The reminder settings to preview.
+#[derive(Debug, Deserialize, Clone)]
+struct PreviewReminderForm {
+    /// The reminder being edited, if any, for checking permissions.
+    reminder_id: Option<i64>,
+    /// The template to render, or the default if not set.
+    template: Option<String>,
+    /// The saved template to render, which takes precedence over `template`.
+    template_id: Option<i64>,
+    /// The language to render in, or the room's language if not set.
+    locale: Option<String>,
+    minutes_before: i64,
+    #[serde(default)]
+    room: String,
+}
+
+/// API for previewing what a reminder with the given template will look
+/// like for the next instance of the event.
+#[post("/api/v1/event/{calendar_id}/{event_id}/preview")]
+async fn preview_reminder_api(
+    app: Data<App>,
+    path: Path<(i64, String)>,
+    data: Json<PreviewReminderForm>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:read")?;
+
+    let (calendar_id, event_id) = path.into_inner();
+    let data = data.into_inner();
+
+    // Attendees may be allowed to edit reminders in calendars they don't own.
+    if let Some(reminder_id) = data.reminder_id {
+        assert_user_can_edit_reminder(&app, user, reminder_id).await?;
+    } else {
+        assert_user_owns_calendar(&app, user, calendar_id).await?;
+    }
+
+    let template = if let Some(template_id) = data.template_id {
+        Some(
+            assert_user_can_use_template(&app, user, template_id)
+                .await?
+                .template,
+        )
+    } else {
+        data.template.filter(|template| !template.trim().is_empty())
+    };
+
+    let locale = data.locale.filter(|locale| !locale.is_empty());
+    if let Some(locale) = &locale {
+        if get_locale(locale).is_none() {
+            return Err(ErrorBadRequest("Unknown language"));
+        }
+    }
+
+    let (markdown, html) = app
+        .preview_reminder(
+            calendar_id,
+            &event_id,
+            template,
+            locale,
+            data.minutes_before,
+            data.room,
+        )
+        .await
+        .map_err(|e| ErrorBadRequest(format!("{:#}", e)))?
+        .ok_or_else(|| ErrorNotFound("Couldn't find event"))?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "markdown": markdown,
+        "html": html,
+    })))
+}
+
+/// API for listing the rooms in the configured space, for picking which room
+/// to send reminders to.
+#[get("/api/v1/space/rooms")]
+async fn space_rooms_api(
+    app: Data<App>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:read")?;
+
+    let rooms = app
+        .get_space_rooms()
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No space configured"))?;
+
+    Ok(HttpResponse::Ok().json(json!({ "rooms": rooms })))
+}
+
+/// Query params for the widget.
+#[derive(Debug, Deserialize, Clone)]
+struct WidgetQuery {
+    token: Option<String>,
+}
+
+/// A view of the upcoming events with reminders in a room, suitable for adding
+/// as a Matrix widget.
+///
+/// Widgets can authenticate with a token created for the room, as they don't
+/// have the login cookie.
+#[get("/widget/{room}")]
+async fn widget_html(
+    app: Data<App>,
+    path: Path<(String,)>,
+    query: Query<WidgetQuery>,
+    user: Option<AuthedUser>,
+) -> Result<impl Responder, actix_web::Error> {
+    let (room,) = path.into_inner();
+
+    let user_id = if let Some(token) = &query.token {
+        app.database
+            .get_user_from_widget_token(token, &room)
+            .await
+            .map_err(ErrorInternalServerError)?
+            .ok_or_else(|| ErrorForbidden("Invalid token"))?
+    } else if let Some(user) = &user {
+        **user
+    } else {
+        return Err(ErrorForbidden("Missing token"));
+    };
+
+    let events = app
+        .database
+        .get_upcoming_events_in_room(user_id, &room, 20)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let events = events
+        .into_iter()
+        .map(|event| {
+            json!({
+                "summary": event.summary,
+                "location": event.location,
+                "start": event.timestamp.to_rfc3339(),
+                "minutes_before": event.minutes_before,
+            })
+        })
+        .collect_vec();
+
+    let context = json!({
+        "room": room,
+        "events": events,
+        // Only show how to add the widget if they're logged in, rather than
+        // viewing it as a widget.
+        "logged_in": user.is_some(),
+        "has_token": query.token.is_some(),
+    });
+
+    let result = app
+        .templates
+        .render(
+            "widget.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+
+    Ok(builder.body(result))
+}
+
+/// Create a token for the room's widget, or reuse the existing one, and
+/// redirect to the widget URL.
+#[post("/widget/{room}/token")]
+async fn create_widget_token_html(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (room,) = path.into_inner();
+
+    let token = app
+        .add_widget_token(*user, &room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header((
+        "Location",
+        format!("/widget/{}?token={}", encode(&room), encode(&token)),
+    ));
+    Ok(builder.finish())
+}
+
+/// Revoke the token for the room's widget.
+#[post("/widget/{room}/token/delete")]
+async fn delete_widget_token_html(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (room,) = path.into_inner();
+
+    app.database
+        .delete_widget_token(*user, &room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/widgets"))
+        .finish())
+}
+
+/// List the user's widget tokens.
+#[get("/widgets")]
+async fn list_widget_tokens_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let tokens = app
+        .database
+        .get_widget_tokens(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "email": email,
+        "tokens": tokens,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "widgets.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Query params for the upcoming room events API.
+#[derive(Debug, Deserialize, Clone)]
+struct UpcomingQuery {
+    limit: Option<i64>,
+}
+
+/// API for listing the upcoming events the user has reminders for in a room,
+/// e.g. for dashboards and widgets.
+#[get("/api/v1/rooms/{room}/upcoming")]
+async fn upcoming_room_events_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    query: Query<UpcomingQuery>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:read")?;
+
+    let (room,) = path.into_inner();
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+
+    let events = app
+        .database
+        .get_upcoming_events_in_room(user.0, &room, limit)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let events: Vec<_> = events
+        .into_iter()
+        .map(|event| {
+            json!({
+                "calendar_id": event.calendar_id,
+                "event_id": event.event_id,
+                "summary": event.summary,
+                "location": event.location,
+                "start": event.timestamp.to_rfc3339(),
+                "reminder_id": event.reminder_id,
+                "minutes_before": event.minutes_before,
+                "remind_at": (event.timestamp - chrono::Duration::minutes(event.minutes_before)).to_rfc3339(),
+                "event_url": app.event_url(event.calendar_id, &event.event_id),
+            })
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "events": events,
+    })))
+}
+
+/// API for getting the quiet hours that apply to a room, and whether they
+/// were set for the room or come from the config.
+#[get("/api/v1/rooms/{room}/quiet_hours")]
+async fn get_room_quiet_hours_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:read")?;
+
+    let (room,) = path.into_inner();
+
+    let room_quiet_hours = app
+        .database
+        .get_room_quiet_hours(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let from_room = room_quiet_hours.is_some();
+    let quiet_hours = room_quiet_hours.or_else(|| app.config.quiet_hours.clone());
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "quiet_hours": quiet_hours,
+        "from_room": from_room,
+    })))
+}
+
+/// API for setting the quiet hours for a room, overriding the config's.
+#[put("/api/v1/rooms/{room}/quiet_hours")]
+async fn set_room_quiet_hours_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    data: Json<QuietHours>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    data.validate()
+        .map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+
+    app.database
+        .set_room_quiet_hours(&room, &data)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "quiet_hours": data.into_inner(),
+    })))
+}
+
+/// API for removing a room's quiet hours, so that the config's apply.
+#[delete("/api/v1/rooms/{room}/quiet_hours")]
+async fn delete_room_quiet_hours_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    app.database
+        .delete_room_quiet_hours(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// The default template for reminders in a room.
+#[derive(Debug, Deserialize, Clone)]
+struct RoomTemplateForm {
+    template: String,
+}
+
+/// API for getting the default template for reminders sent to a room.
+#[get("/api/v1/rooms/{room}/template")]
+async fn get_room_template_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:read")?;
+
+    let (room,) = path.into_inner();
+
+    let template = app
+        .database
+        .get_room_template(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "template": template,
+    })))
+}
+
+/// API for setting the default template for reminders sent to a room, used
+/// by reminders that don't have their own template.
+#[put("/api/v1/rooms/{room}/template")]
+async fn set_room_template_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    data: Json<RoomTemplateForm>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    handlebars::Template::compile(&data.template)
+        .map_err(|e| ErrorBadRequest(format!("Invalid template: {}", e)))?;
+
+    app.database
+        .set_room_template(&room, &data.template)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "template": data.into_inner().template,
+    })))
+}
+
+/// API for removing a room's default template, so that the global default
+/// applies.
+#[delete("/api/v1/rooms/{room}/template")]
+async fn delete_room_template_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    app.database
+        .delete_room_template(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// The language to render reminders sent to a room in.
+#[derive(Debug, Deserialize, Clone)]
+struct RoomLocaleForm {
+    locale: String,
+}
+
+/// API for getting the language reminders sent to a room are rendered in.
+#[get("/api/v1/rooms/{room}/locale")]
+async fn get_room_locale_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:read")?;
+
+    let (room,) = path.into_inner();
+
+    let locale = app
+        .database
+        .get_room_locale(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "locale": locale,
+    })))
+}
+
+/// API for setting the language reminders sent to a room are rendered in,
+/// used by reminders that don't pick their own.
+#[put("/api/v1/rooms/{room}/locale")]
+async fn set_room_locale_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    data: Json<RoomLocaleForm>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    if get_locale(&data.locale).is_none() {
+        return Err(ErrorBadRequest("Unknown language"));
+    }
+
+    app.database
+        .set_room_locale(&room, &data.locale)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "room": room,
+        "locale": data.into_inner().locale,
+    })))
+}
+
+/// API for removing a room's language, so that the default applies.
+#[delete("/api/v1/rooms/{room}/locale")]
+async fn delete_room_locale_api(
+    app: Data<App>,
+    path: Path<(String,)>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    user.require("rooms:write")?;
+
+    let (room,) = path.into_inner();
+
+    app.database
+        .delete_room_locale(&room)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Get calendar info
+#[get("/calendar/{calendar_id}")]
+async fn get_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    query: Query<EventFormState>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    let state = match query.into_inner().state.as_deref() {
+        Some("saved") => Some("saved"),
+        _ => None,
+    };
+
+    render_calendar_page(&app, user, calendar_id, state, None).await
+}
+
+/// Immediately fetch the calendar, rather than waiting for the next sync.
+#[post("/calendar/{calendar_id}/sync")]
+async fn sync_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    // We report the result inline, so that people can fix their config.
+    match app.update_calendar(calendar).await {
+        Ok(()) => render_calendar_page(&app, user, calendar_id, Some("synced"), None).await,
+        Err(error) => {
+            warn!(
+                error = error.deref() as &dyn StdError,
+                calendar_id, "Failed to sync calendar"
+            );
+
+            let sync_error = format!("{:#}", error);
+            render_calendar_page(&app, user, calendar_id, None, Some(&sync_error)).await
+        }
+    }
+}
+
+/// Query params for the sync calendar API.
+#[derive(Debug, Deserialize, Clone)]
+struct SyncCalendarQuery {
+    /// Whether to return straight away and sync in the background, e.g. for
+    /// push notification relays that expect a quick response.
+    #[serde(default)]
+    background: bool,
+}
+
+/// API for immediately fetching the calendar, rather than waiting for the
+/// next sync, e.g. when an external system knows the calendar has changed.
+#[post("/api/v1/calendars/{calendar_id}/sync")]
+async fn sync_calendar_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    query: Query<SyncCalendarQuery>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("calendars:write")?;
+
+    let (calendar_id,) = path.into_inner();
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    if !calendar.enabled {
+        return Err(ErrorConflict("Calendar is paused"));
+    }
+
+    if query.background {
+        let app = app.into_inner();
+        spawn_local(async move { app.update_calendar_and_record_errors(calendar).await });
+
+        return Ok(HttpResponse::Accepted().json(json!({ "calendar_id": calendar_id })));
+    }
+
+    app.update_calendar(calendar).await.map_err(|error| {
+        warn!(
+            error = error.deref() as &dyn StdError,
+            calendar_id, "Failed to sync calendar"
+        );
+
+        ErrorBadGateway(format!("Failed to sync calendar: {:#}", error))
+    })?;
+
+    Ok(HttpResponse::Ok().json(json!({ "calendar_id": calendar_id })))
+}
+
+/// Render the page for an existing calendar, with the given form state or
+/// sync error.
+async fn render_calendar_page(
+    app: &App,
+    user: AuthedUser,
+    calendar_id: i64,
+    form_state: Option<&str>,
+    sync_error: Option<&str>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let errors = app
+        .database
+        .get_calendar_errors(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let (user_name, authentication_type) = match calendar.as_ref().map(|c| &c.authentication) {
+        Some(CalendarAuthentication::Basic { user_name, .. }) => (Some(user_name), "basic"),
+        Some(CalendarAuthentication::Digest { user_name, .. }) => (Some(user_name), "digest"),
+        Some(CalendarAuthentication::Bearer { .. }) => (None, "bearer"),
+        Some(CalendarAuthentication::None) => (None, "none"),
+        None => (None, "none"),
+    };
+
+    let context = json!({
+        "calendar": calendar,
+        "email": email,
+        "user_name": user_name,
+        "authentication_type": authentication_type,
+        "errors": errors,
+        "form_state": form_state,
+        "sync_error": sync_error,
+        "muted": calendar
+            .as_ref()
+            .and_then(|calendar| calendar.muted_until)
+            .is_some_and(|muted_until| muted_until > Utc::now()),
+        "default_sync_lookahead_days": app.config.app.sync_lookahead_days.unwrap_or(DEFAULT_SYNC_LOOKAHEAD_DAYS),
+        "default_sync_lookback_days": app.config.app.sync_lookback_days.unwrap_or(DEFAULT_SYNC_LOOKBACK_DAYS),
+    });
+
+    let result = app
+        .templates
+        .render(
+            "calendar.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Add new calendar
+#[get("/calendar/new")]
+async fn new_calendar_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "email": email,
+        "default_sync_lookahead_days": app.config.app.sync_lookahead_days.unwrap_or(DEFAULT_SYNC_LOOKAHEAD_DAYS),
+        "default_sync_lookback_days": app.config.app.sync_lookback_days.unwrap_or(DEFAULT_SYNC_LOOKBACK_DAYS),
+    });
+
+    let result = app
+        .templates
+        .render(
+            "calendar.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for editing a calendar's config
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateCalendarForm {
+    pub name: String,
+    pub url: String,
+    pub kind: Option<CalendarKind>,
+    pub user_name: Option<String>,
+    pub password: Option<String>,
+    // A checkbox, so `Some()` if checked, `None` if not.
+    pub digest_auth: Option<String>,
+    /// The timezone to assume for floating events, if any.
+    pub timezone: Option<String>,
+    /// Extra PEM encoded root certificates to trust, if any.
+    pub ca_certificate: Option<String>,
+    // A checkbox, so `Some()` if checked, `None` if not.
+    pub port_duplicate_reminders: Option<String>,
+    /// How many days ahead to sync events for, if not the default.
+    pub sync_lookahead_days: Option<String>,
+    /// How many days back to fetch events from, if not the default.
+    pub sync_lookback_days: Option<String>,
+    // A checkbox, so `Some()` if checked, `None` if not.
+    pub ignore_transparent: Option<String>,
+}
+
+/// The most days we allow a calendar's sync window to extend either way.
+const MAX_SYNC_WINDOW_DAYS: i64 = 366;
+
+/// Parse a number of days from a calendar form, treating an empty field as
+/// unset.
+fn parse_days_field(days: Option<String>) -> Result<Option<i64>, actix_web::Error> {
+    let days = match days.as_deref().map(str::trim) {
+        Some("") | None => return Ok(None),
+        Some(days) => days,
+    };
+
+    let parsed: i64 = days
+        .parse()
+        .map_err(|_| ErrorBadRequest("Invalid number of days"))?;
+
+    if !(1..=MAX_SYNC_WINDOW_DAYS).contains(&parsed) {
+        return Err(ErrorBadRequest(format!(
+            "Number of days must be between 1 and {}",
+            MAX_SYNC_WINDOW_DAYS
+        )));
+    }
+
+    Ok(Some(parsed))
+}
+
+/// Parse the timezone from a calendar form, treating an empty field as
+/// unset.
+fn parse_timezone_field(timezone: Option<String>) -> Result<Option<String>, actix_web::Error> {
+    let timezone = match timezone.as_deref().map(str::trim) {
+        Some("") | None => return Ok(None),
+        Some(timezone) => timezone,
+    };
+
+    let parsed: Tz = timezone
+        .parse()
+        .map_err(|_| ErrorBadRequest("Unknown timezone"))?;
+
+    Ok(Some(parsed.name().to_string()))
+}
+
+/// Check the CA certificate from a calendar form is valid PEM, treating an
+/// empty field as unset.
+fn parse_ca_certificate_field(
+    ca_certificate: Option<String>,
+) -> Result<Option<String>, actix_web::Error> {
+    let ca_certificate = match ca_certificate.as_deref().map(str::trim) {
+        Some("") | None => return Ok(None),
+        Some(ca_certificate) => ca_certificate,
+    };
+
+    parse_pem_certificates(ca_certificate)
+        .map_err(|e| ErrorBadRequest(format!("Invalid CA certificate: {}", e)))?;
+
+    Ok(Some(ca_certificate.to_string()))
+}
+
+/// Check the calendar URL is one we can fetch, rewriting `webcal://` links
+/// (which are just ICS feeds over HTTPS) to `https://`.
+fn normalize_calendar_url(
+    url: &str,
+    kind: CalendarKind,
+) -> Result<(String, CalendarKind), actix_web::Error> {
+    let url = url.trim();
+
+    let (url, kind) = match url.get(..9) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("webcal://") => {
+            (format!("https://{}", &url[9..]), CalendarKind::Ics)
+        }
+        _ => (url.to_string(), kind),
+    };
+
+    let parsed = Url::parse(&url).map_err(|_| ErrorBadRequest("Invalid calendar URL"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(ErrorBadRequest("Calendar URL must be http(s) or webcal"));
+    }
+
+    Ok((url, kind))
+}
+
+/// Edit a calendar's config.
+#[post("/calendar/{calendar_id}/edit")]
+async fn edit_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    data: Form<UpdateCalendarForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    let existing_calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    let UpdateCalendarForm {
+        name,
+        url,
+        kind,
+        mut user_name,
+        mut password,
+        digest_auth,
+        timezone,
+        ca_certificate,
+        port_duplicate_reminders,
+        sync_lookahead_days,
+        sync_lookback_days,
+        ignore_transparent,
+    } = data.into_inner();
+
+    let timezone = parse_timezone_field(timezone)?;
+    let sync_lookahead_days = parse_days_field(sync_lookahead_days)?;
+    let sync_lookback_days = parse_days_field(sync_lookback_days)?;
+    let ca_certificate = parse_ca_certificate_field(ca_certificate)?;
+
+    if user_name.as_deref() == Some("") {
+        user_name = None;
+    }
+    if password.as_deref() == Some("") {
+        password = None;
+    }
+
+    // Uploaded calendars stay static, as there's nothing to fetch, and Google
+    // calendars keep using the Calendar API.
+    let kind = if matches!(
+        existing_calendar.kind,
+        CalendarKind::Static | CalendarKind::Google
+    ) {
+        existing_calendar.kind
+    } else {
+        kind.unwrap_or_default()
+    };
+
+    // Static calendars don't have a URL.
+    let (url, kind) = if kind == CalendarKind::Static {
+        (url, kind)
+    } else {
+        normalize_calendar_url(&url, kind)?
+    };
+
+    // Awful hack to keep password unchanged if left blank, but still using
+    // basic auth.
+    if password.is_none() && user_name.is_some() {
+        let existing_password = match existing_calendar.authentication {
+            CalendarAuthentication::Basic { ref password, .. }
+            | CalendarAuthentication::Digest { ref password, .. } => password.clone(),
+            _ => return Err(ErrorInternalServerError("Calendar doesn't have a password")),
+        };
+        password = Some(existing_password)
+    }
+
+    app.database
+        .update_calendar(
+            calendar_id,
+            name,
+            url,
+            kind,
+            user_name,
+            password,
+            digest_auth.is_some(),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_calendar_timezone(calendar_id, timezone.as_deref())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_calendar_ca_certificate(calendar_id, ca_certificate.as_deref())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_calendar_port_duplicate_reminders(calendar_id, port_duplicate_reminders.is_some())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_calendar_sync_window_days(calendar_id, sync_lookahead_days, sync_lookback_days)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_calendar_ignore_transparent(calendar_id, ignore_transparent.is_some())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let new_calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    app.update_calendar(new_calendar)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Delete a calendar
+#[post("/calendar/{calendar_id}/delete")]
+async fn delete_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    app.database
+        .delete_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", "/calendars"));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Pause or resume a calendar, redirecting back to the calendar page.
+async fn set_calendar_enabled(
+    app: &App,
+    user: AuthedUser,
+    calendar_id: i64,
+    enabled: bool,
+) -> Result<HttpResponse, actix_web::Error> {
+    assert_user_owns_calendar(app, user, calendar_id).await?;
+
+    app.database
+        .set_calendar_enabled(calendar_id, enabled)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Pause syncing a calendar and sending its reminders
+#[post("/calendar/{calendar_id}/pause")]
+async fn pause_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    set_calendar_enabled(&app, user, calendar_id, false).await
+}
+
+/// Resume syncing a paused calendar
+#[post("/calendar/{calendar_id}/resume")]
+async fn resume_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    set_calendar_enabled(&app, user, calendar_id, true).await
+}
+
+/// Form body for muting a calendar's reminders.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MuteCalendarForm {
+    /// The local time to stop sending reminders from, as given by a
+    /// `datetime-local` input. Empty to mute them straight away.
+    pub muted_from: Option<String>,
+    /// The local time to start sending reminders again.
+    pub muted_until: String,
+    pub timezone: Option<String>, // Empty to use UTC.
+}
+
+/// Stop sending a calendar's reminders for a while, e.g. during an offsite.
+#[post("/calendar/{calendar_id}/mute")]
+async fn mute_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    data: Form<MuteCalendarForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    let MuteCalendarForm {
+        muted_from,
+        muted_until,
+        timezone,
+    } = data.into_inner();
+
+    let muted_from = muted_from
+        .filter(|muted_from| !muted_from.trim().is_empty())
+        .map(|muted_from| parse_local_time_field(&muted_from, timezone.as_deref()))
+        .transpose()?;
+    let muted_until = parse_local_time_field(&muted_until, timezone.as_deref())?;
+
+    if muted_until <= Utc::now() {
+        return Err(ErrorBadRequest(
+            "End of the muted period must be in the future",
+        ));
+    }
+    if muted_from.is_some_and(|muted_from| muted_from >= muted_until) {
+        return Err(ErrorBadRequest("Muted period must end after it starts"));
+    }
+
+    app.database
+        .set_calendar_muted(calendar_id, muted_from, Some(muted_until))
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Start sending a muted calendar's reminders again.
+#[post("/calendar/{calendar_id}/unmute")]
+async fn unmute_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    assert_user_owns_calendar(&app, user, calendar_id).await?;
+
+    app.database
+        .set_calendar_muted(calendar_id, None, None)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Undo the deletion of a calendar
+#[post("/calendar/{calendar_id}/restore")]
+async fn restore_calendar_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id,) = path.into_inner();
+
+    let restored = app
+        .database
+        .restore_calendar(*user, calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !restored {
+        return Err(ErrorNotFound("No such calendar"));
+    }
+
+    app.update_reminders()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", "/calendars"));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Add a new calendar page.
+#[post("/calendar/new")]
+async fn add_new_calendar_html(
+    app: Data<App>,
+    data: Form<UpdateCalendarForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let UpdateCalendarForm {
+        name,
+        url,
+        kind,
+        mut user_name,
+        mut password,
+        digest_auth,
+        timezone,
+        ca_certificate,
+        port_duplicate_reminders,
+        sync_lookahead_days,
+        sync_lookback_days,
+        ignore_transparent,
+    } = data.into_inner();
+
+    let timezone = parse_timezone_field(timezone)?;
+    let sync_lookahead_days = parse_days_field(sync_lookahead_days)?;
+    let sync_lookback_days = parse_days_field(sync_lookback_days)?;
+    let ca_certificate = parse_ca_certificate_field(ca_certificate)?;
+    let (url, kind) = normalize_calendar_url(&url, kind.unwrap_or_default())?;
+
+    if user_name.as_deref() == Some("") {
+        user_name = None;
+    }
+    if password.as_deref() == Some("") {
+        password = None;
+    }
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            *user,
+            name,
+            url,
+            kind,
+            user_name,
+            password,
+            digest_auth.is_some(),
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if timezone.is_some() {
+        app.database
+            .set_calendar_timezone(calendar_id, timezone.as_deref())
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    if ca_certificate.is_some() {
+        app.database
+            .set_calendar_ca_certificate(calendar_id, ca_certificate.as_deref())
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    // This defaults to on, so we only need to store it if it's been unticked.
+    if port_duplicate_reminders.is_none() {
+        app.database
+            .set_calendar_port_duplicate_reminders(calendar_id, false)
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    if sync_lookahead_days.is_some() || sync_lookback_days.is_some() {
+        app.database
+            .set_calendar_sync_window_days(calendar_id, sync_lookahead_days, sync_lookback_days)
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    if ignore_transparent.is_some() {
+        app.database
+            .set_calendar_ignore_transparent(calendar_id, true)
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    let new_calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    app.update_calendar(new_calendar)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Form body for discovering the calendars in a CalDAV account.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DiscoverCalendarsForm {
+    pub url: String,
+    pub user_name: Option<String>,
+    pub password: Option<String>,
+}
+
+/// List the calendars in a CalDAV account, so the user can choose which ones
+/// to add.
+#[post("/calendar/discover")]
+async fn discover_calendars_html(
+    app: Data<App>,
+    data: Form<DiscoverCalendarsForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let DiscoverCalendarsForm {
+        url,
+        user_name,
+        password,
+    } = data.into_inner();
+
+    let user_name = user_name.filter(|user_name| !user_name.is_empty());
+    let password = password.filter(|password| !password.is_empty());
+
+    let authentication = if let (Some(user_name), Some(password)) = (&user_name, &password) {
+        CalendarAuthentication::Basic {
+            user_name: user_name.clone(),
+            password: password.clone(),
+        }
+    } else {
+        CalendarAuthentication::None
+    };
+
+    let collections = discover_calendars(
+        &app.calendar_http_client,
+        &url,
+        &authentication,
+        app.config.fetch.max_response_bytes(),
+    )
+    .await
+    .map_err(|e| ErrorBadRequest(format!("Failed to find calendars: {:#}", e)))?;
+
+    render_discovered_calendars(&app, user, collections, user_name, password).await
+}
+
+/// Render the list of discovered calendars for the user to choose from.
+async fn render_discovered_calendars(
+    app: &App,
+    user: AuthedUser,
+    collections: Vec<CalendarCollection>,
+    user_name: Option<String>,
+    password: Option<String>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let existing_urls: HashSet<_> = app
+        .database
+        .get_calendars_for_user(*user)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .into_iter()
+        .map(|calendar| calendar.url)
+        .collect();
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "calendars": collections.iter().map(|collection| json!({
+            "url": &collection.url,
+            "name": collection.name.as_deref().unwrap_or(&collection.url),
+            "have_added_to_calbot": existing_urls.contains(&collection.url),
+        })).collect_vec(),
+        "user_name": user_name,
+        "password": password,
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "discover_calendars.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Guided page for adding calendars from a Nextcloud server.
+#[get("/calendar/nextcloud")]
+async fn nextcloud_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "nextcloud.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for finding the calendars in a Nextcloud account.
+#[derive(Debug, Clone, Deserialize)]
+pub struct NextcloudForm {
+    /// The base URL of the Nextcloud server, e.g. `https://cloud.example.com`.
+    pub url: String,
+    pub user_name: String,
+    /// An app password, as Nextcloud accounts with 2FA can't use their
+    /// normal password.
+    pub password: String,
+}
+
+/// List the calendars in a Nextcloud account, so the user can choose which
+/// ones to add.
+#[post("/calendar/nextcloud")]
+async fn nextcloud_post_html(
+    app: Data<App>,
+    data: Form<NextcloudForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let NextcloudForm {
+        url,
+        user_name,
+        password,
+    } = data.into_inner();
+
+    if user_name.is_empty() || password.is_empty() {
+        return Err(ErrorBadRequest("User name and app password are required"));
+    }
+
+    let url = nextcloud_dav_url(&url).map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+
+    let authentication = CalendarAuthentication::Basic {
+        user_name: user_name.clone(),
+        password: password.clone(),
+    };
+
+    let collections = discover_calendars(
+        &app.calendar_http_client,
+        &url,
+        &authentication,
+        app.config.fetch.max_response_bytes(),
+    )
+    .await
+    .map_err(|e| ErrorBadRequest(format!("Failed to find calendars: {:#}", e)))?;
+
+    render_discovered_calendars(&app, user, collections, Some(user_name), Some(password)).await
+}
+
+/// Add the calendars the user chose from the discovered ones.
+///
+/// The form has a `url` field for each chosen calendar, with its name in a
+/// `name:<url>` field.
+#[post("/calendar/discover/add")]
+async fn add_discovered_calendars_html(
+    app: Data<App>,
+    data: Form<Vec<(String, String)>>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let fields = data.into_inner();
+
+    let get_field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+            .filter(|value| !value.is_empty())
+    };
+
+    let user_name = get_field("user_name");
+    let password = get_field("password");
+
+    for (_, url) in fields.iter().filter(|(key, _)| key == "url") {
+        let name = get_field(&format!("name:{}", url)).unwrap_or_else(|| url.clone());
+
+        let calendar_id = app
+            .database
+            .add_calendar_basic_auth(
+                *user,
+                name,
+                url.clone(),
+                CalendarKind::CalDav,
+                user_name.clone(),
+                password.clone(),
+                false,
+            )
+            .await
+            .map_err(ErrorInternalServerError)?;
+
+        let new_calendar = app
+            .database
+            .get_calendar(calendar_id)
+            .await
+            .map_err(ErrorInternalServerError)?
+            .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+        // Errors will be shown on the calendar's page, so we don't want to
+        // stop adding the other calendars.
+        if let Err(err) = app.update_calendar(new_calendar).await {
+            warn!(
+                error = err.deref() as &dyn StdError,
+                calendar_id, "Failed to fetch new calendar"
+            );
+        }
+    }
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", "/calendars"));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// The maximum size of an uploaded .ics file.
+const MAX_ICS_UPLOAD_SIZE: usize = 5 * 1024 * 1024;
+
+/// Add a static calendar from an uploaded .ics file.
+#[post("/calendar/upload")]
+async fn upload_calendar_html(
+    app: Data<App>,
+    mut payload: Multipart,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let mut name = None;
+    let mut body = Vec::new();
+
+    while let Some(mut field) = payload.try_next().await? {
+        let field_name = field.name().map(ToOwned::to_owned);
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_ICS_UPLOAD_SIZE {
+                return Err(ErrorBadRequest("File is too large"));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        match field_name.as_deref() {
+            Some("name") => {
+                name = Some(String::from_utf8(data).map_err(|_| ErrorBadRequest("Invalid name"))?)
+            }
+            Some("file") => body = data,
+            _ => {}
+        }
+    }
+
+    let name = name
+        .filter(|name| !name.trim().is_empty())
+        .ok_or_else(|| ErrorBadRequest("Missing calendar name"))?;
+
+    let body =
+        String::from_utf8(body).map_err(|_| ErrorBadRequest("File is not a valid .ics file"))?;
+
+    if parse_ics_file(&body).calendars.is_empty() {
+        return Err(ErrorBadRequest("File is not a valid .ics file"));
+    }
+
+    let calendar_id = app
+        .database
+        .add_static_calendar(*user, name, body)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let new_calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    app.update_calendar(new_calendar)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// The maximum size of an image uploaded for a reminder.
+const MAX_IMAGE_UPLOAD_SIZE: usize = 5 * 1024 * 1024;
+
+/// Upload an image for a reminder's template to include, e.g. a team logo.
+#[post("/event/{calendar_id}/{event_id}/reminder/{reminder_id}/images")]
+async fn upload_reminder_image_html(
+    app: Data<App>,
+    path: Path<(i64, String, i64)>,
+    mut payload: Multipart,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id, event_id, reminder_id) = path.into_inner();
+
+    assert_user_can_edit_reminder(&app, user, reminder_id).await?;
+
+    let reminder = app
+        .database
+        .get_reminder_in_calendar(calendar_id, reminder_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("Couldn't find reminder"))?;
+
+    let mut name = None;
+    let mut file = None;
+
+    while let Some(mut field) = payload.try_next().await? {
+        let field_name = field.name().map(ToOwned::to_owned);
+        let content_type = field
+            .content_type()
+            .map(|mime| mime.essence_str().to_string());
+        let filename = field
+            .content_disposition()
+            .and_then(|disposition| disposition.get_filename())
+            .map(ToOwned::to_owned);
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_IMAGE_UPLOAD_SIZE {
+                return Err(ErrorBadRequest("File is too large"));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        match field_name.as_deref() {
+            Some("name") => {
+                name = Some(String::from_utf8(data).map_err(|_| ErrorBadRequest("Invalid name"))?)
+            }
+            Some("file") => file = Some((filename, content_type, data)),
+            _ => {}
+        }
+    }
+
+    // The name is used as `images.<name>` in templates, so has to be a valid
+    // Handlebars identifier.
+    let name = name
+        .map(|name| name.trim().to_string())
+        .filter(|name| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        })
+        .ok_or_else(|| ErrorBadRequest("Name must be letters, numbers and underscores"))?;
+
+    let (filename, content_type, data) = match file {
+        Some((filename, Some(content_type), data))
+            if content_type.starts_with("image/") && !data.is_empty() =>
+        {
+            (filename, content_type, data)
+        }
+        _ => return Err(ErrorBadRequest("File must be an image")),
+    };
+
+    let content_uri = app
+        .upload_media(
+            reminder.sender.as_deref(),
+            filename.as_deref().unwrap_or(&name),
+            &content_type,
+            data,
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .add_reminder_image(&ReminderImage {
+            reminder_id,
+            name,
+            content_type,
+            content_uri,
+        })
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header((
+        "Location",
+        format!(
+            "/event/{}/{}/reminder/{}",
+            calendar_id, event_id, reminder_id
+        ),
+    ));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Remove an image from a reminder.
+#[post("/event/{calendar_id}/{event_id}/reminder/{reminder_id}/images/{name}/delete")]
+async fn delete_reminder_image_html(
+    app: Data<App>,
+    path: Path<(i64, String, i64, String)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (calendar_id, event_id, reminder_id, name) = path.into_inner();
+
+    assert_user_can_edit_reminder(&app, user, reminder_id).await?;
+
+    if !app
+        .database
+        .delete_reminder_image(reminder_id, &name)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such image"));
+    }
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header((
+        "Location",
+        format!(
+            "/event/{}/{}/reminder/{}",
+            calendar_id, event_id, reminder_id
+        ),
+    ));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Form body for editing a calendar's config
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UpdateCalendarOAuth2Form {
+    pub google_id: String,
+    pub name: String,
+    pub account_id: Option<i64>,
+}
+
+/// Add a new calendar page.
+#[post("/calendar/new_oauth2")]
+async fn add_oauth2_calendar_html(
+    app: Data<App>,
+    data: Form<UpdateCalendarOAuth2Form>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let UpdateCalendarOAuth2Form {
+        google_id,
+        name,
+        account_id,
+    } = data.into_inner();
+
+    let url = google_events_url(&google_id);
+
+    let account_id = account_id.ok_or_else(|| ErrorBadRequest("Missing account ID"))?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_oauth2(*user, name, url, account_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let new_calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such calendar"))?;
+
+    app.update_calendar(new_calendar)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::SeeOther();
+    builder.insert_header(("Location", format!("/calendar/{}?state=saved", calendar_id)));
+    let response = builder.finish();
+
+    Ok(response)
+}
+
+/// Login page
+#[get("/login")]
+async fn login_get_html(
+    app: Data<App>,
+    query: Query<EventFormState>,
+) -> Result<impl Responder, actix_web::Error> {
+    let state = match query.into_inner().state.as_deref() {
+        Some("invalid_password") => Some("invalid_password"),
+        Some("rate_limited") => Some("rate_limited"),
+        _ => None,
+    };
+
+    let sso_name = app.config.sso.as_ref().map(|s| &s.display_name);
+    let context = json!({ "sso_name": sso_name, "form_state": state });
+
+    let result = app
+        .templates
+        .render(
+            "login.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for logging in.
+#[derive(Debug, Deserialize, Clone)]
+struct LoginForm {
+    user_name: String,
+    password: String,
+}
+
+/// Login
+#[post("/login")]
+async fn login_post_html(
+    app: Data<App>,
+    req: HttpRequest,
+    data: Form<LoginForm>,
+) -> Result<impl Responder, actix_web::Error> {
+    // We limit failed attempts both per IP and per account, so that guessing
+    // the password of one account from many IPs is also slowed down.
+    let ip_key = format!("login:ip:{}", app.rate_limiter.client_ip(&req));
+    let account_key = format!("login:account:{}", data.user_name.to_lowercase());
+
+    for key in [&ip_key, &account_key] {
+        if app
+            .rate_limiter
+            .is_limited(&app.database, key)
+            .await
+            .map_err(ErrorInternalServerError)?
+        {
+            return Ok(HttpResponse::SeeOther()
+                .insert_header(("Location", "/login?state=rate_limited"))
+                .finish());
+        }
+    }
+
+    let user_id = app
+        .database
+        .check_password(&data.user_name, &data.password)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let response = if let Some(user_id) = user_id {
+        app.rate_limiter
+            .clear(&app.database, &account_key)
+            .await
+            .map_err(ErrorInternalServerError)?;
+
+        let token = app
+            .add_access_token(user_id)
+            .await
+            .map_err(ErrorInternalServerError)?;
+
+        let cookie = session_cookie(&app, token);
+
+        HttpResponse::SeeOther()
+            .insert_header(("Location", "/calendars"))
+            .cookie(cookie)
+            .finish()
+    } else {
+        for key in [&ip_key, &account_key] {
+            app.rate_limiter
+                .record_failure(&app.database, key)
+                .await
+                .map_err(ErrorInternalServerError)?;
+        }
+
+        HttpResponse::SeeOther()
+            .insert_header(("Location", "/login?state=invalid_password"))
+            .finish()
+    };
+
+    Ok(response)
+}
+
+/// Logout, revoking the access token in the cookie.
+#[post("/logout")]
+async fn logout_post_html(
+    app: Data<App>,
+    req: HttpRequest,
+) -> Result<impl Responder, actix_web::Error> {
+    if let Some(cookie) = req.cookie("token") {
+        app.database
+            .revoke_access_token(cookie.value())
+            .await
+            .map_err(ErrorInternalServerError)?;
+    }
+
+    let cookie = Cookie::build("token", "")
+        .same_site(SameSite::Lax)
+        .max_age(time::Duration::ZERO)
+        .http_only(true)
+        .finish();
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/login"))
+        .cookie(cookie)
+        .finish())
+}
+
+/// Change password page
+#[get("/change_password")]
+async fn change_password_html(
+    app: Data<App>,
+    user: AuthedUser,
+    query: Query<EventFormState>,
+) -> Result<impl Responder, actix_web::Error> {
+    let state = match query.into_inner().state.as_deref() {
+        Some("saved") => Some("saved"),
+        Some("wrong_password") => Some("wrong_password"),
+        Some("password_mismatch") => Some("password_mismatch"),
+        Some("too_short") => Some("too_short"),
+        Some("too_simple") => Some("too_simple"),
+        Some("pwned") => Some("pwned"),
+        _ => None,
+    };
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "form_state": state,
+        "email": email,
+        "min_length": app.config.password_policy.min_length(),
+        "min_character_classes": app.config.password_policy.min_character_classes(),
+    });
+
+    let result = app
+        .templates
+        .render(
+            "change_password.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for changing password
+#[derive(Debug, Deserialize, Clone)]
+struct ChangePasswordForm {
+    old_password: String,
+    new_password: String,
+    confirm_password: String,
+}
+
+/// Change password
+#[post("/change_password")]
+async fn change_password_post_html(
+    app: Data<App>,
+    data: Form<ChangePasswordForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    if data.new_password != data.confirm_password {
+        return Ok(HttpResponse::SeeOther()
+            .insert_header(("Location", "/change_password?state=password_mismatch"))
+            .finish());
+    }
+
+    let right_password = app
+        .database
+        .check_password_user_id(user.0, &data.old_password)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    // Check the old password first, so that the policy and breached password
+    // checks can't be used without knowing it.
+    if right_password.is_none() {
+        return Ok(HttpResponse::SeeOther()
+            .insert_header(("Location", "/change_password?state=wrong_password"))
+            .finish());
+    }
+
+    let rejection = check_password_policy(
+        &app.config.password_policy,
+        &app.http_client,
+        &data.new_password,
+    )
+    .await
+    .map_err(ErrorInternalServerError)?;
+
+    if let Some(rejection) = rejection {
+        return Ok(HttpResponse::SeeOther()
+            .insert_header((
+                "Location",
+                format!("/change_password?state={}", rejection.as_state()),
+            ))
+            .finish());
+    }
+
+    app.database
+        .change_password(user.0, &data.new_password)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_password?state=saved"))
+        .finish())
+}
+
+/// Change Matrix ID page
+#[get("/change_matrix_id")]
+async fn change_matrix_id_html(
+    app: Data<App>,
+    user: AuthedUser,
+    query: Query<EventFormState>,
+) -> Result<impl Responder, actix_web::Error> {
+    let state = query.into_inner().state;
+
+    let matrix_ids = app
+        .database
+        .get_matrix_ids(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let mentions_disabled = app
+        .database
+        .get_mentions_disabled(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let weekly_summary = app
+        .database
+        .get_weekly_summary_settings(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let timezone = app
+        .database
+        .get_user_timezone(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "form_state": state,
+        "matrix_ids": matrix_ids,
+        "mentions_disabled": mentions_disabled,
+        "weekly_summary": weekly_summary,
+        "timezone": timezone,
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "change_matrix_id.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Connect a new google account
+#[get("/add_google_account")]
+async fn add_google_account(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let redirect_url = app
+        .start_google_oauth_session(user.0, "/google_accounts")
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", redirect_url.to_string()))
+        .finish())
+}
+
+/// List google accounts
+#[get("/google_accounts")]
+async fn list_google_accounts(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let accounts = app
+        .database
+        .get_oauth2_accounts(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "accounts": accounts.into_iter().map(|i| json!({
+            "account_id": i.account_id,
+            "expired": i.expired,
+        })).collect::<Vec<_>>(),
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "list_google_accounts.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AccountId {
+    account_id: i64,
+}
+
+/// Get google calendars
+#[get("/google_calendars")]
+async fn google_calendars(
+    app: Data<App>,
+    user: AuthedUser,
+    query: Query<AccountId>,
+) -> Result<impl Responder, actix_web::Error> {
+    let (_, calendars) = app
+        .get_google_calendars("/google_calendars", user.0, query.account_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "account_id": query.account_id,
+        "calendars": calendars,
+        "email": email,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "list_google_calendars.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Form body for changing password
+#[derive(Debug, Deserialize, Clone)]
+struct ChangeMatrixIdForm {
+    new_matrix_id: String,
+}
+
+/// Change Matrix ID
+#[post("/change_matrix_id")]
+async fn change_matrix_id_post_html(
+    app: Data<App>,
+    data: Form<ChangeMatrixIdForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    if !is_likely_a_valid_user_id(&data.new_matrix_id) {
+        return Err(ErrorBadRequest("That does not look like a Matrix ID."));
+    }
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .set_preferred_matrix_id(&email, &data.new_matrix_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_mappings()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_matrix_id?state=saved"))
+        .finish())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeleteMatrixIdForm {
+    matrix_id: String,
+}
+
+/// Remove one of the user's Matrix IDs.
+#[post("/change_matrix_id/delete")]
+async fn delete_matrix_id_html(
+    app: Data<App>,
+    data: Form<DeleteMatrixIdForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.database
+        .delete_matrix_id(&email, &data.matrix_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    app.update_mappings()
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_matrix_id?state=saved"))
+        .finish())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct MentionsForm {
+    mentions_disabled: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
+}
+
+/// Set whether the user should be mentioned in reminders.
+#[post("/change_matrix_id/mentions")]
+async fn change_mentions_html(
+    app: Data<App>,
+    data: Form<MentionsForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    app.database
+        .set_mentions_disabled(user.0, data.mentions_disabled.is_some())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_matrix_id?state=saved"))
+        .finish())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct TimezoneForm {
+    timezone: Option<String>, // Empty to clear it.
+}
+
+/// Set the user's timezone, used to show event start times to them in
+/// reminders.
+#[post("/change_matrix_id/timezone")]
+async fn change_timezone_html(
+    app: Data<App>,
+    data: Form<TimezoneForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let timezone = data
+        .into_inner()
+        .timezone
+        .map(|timezone| timezone.trim().to_string())
+        .filter(|timezone| !timezone.is_empty());
+    if let Some(timezone) = &timezone {
+        if timezone.parse::<Tz>().is_err() {
+            return Err(ErrorBadRequest("Unknown timezone"));
+        }
+    }
+
+    app.database
+        .set_user_timezone(user.0, timezone.as_deref())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_matrix_id?state=saved"))
+        .finish())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct WeeklySummaryForm {
+    weekly_summary: Option<String>, // A checkbox, so `Some()` if checked, `None` if not.
+    timezone: Option<String>,       // Empty to use UTC.
+}
+
+/// Set whether the user gets a weekly summary of their reminders.
+#[post("/change_matrix_id/weekly_summary")]
+async fn change_weekly_summary_html(
+    app: Data<App>,
+    data: Form<WeeklySummaryForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let WeeklySummaryForm {
+        weekly_summary,
+        timezone,
+    } = data.into_inner();
+
+    let timezone = timezone.filter(|timezone| !timezone.trim().is_empty());
+    if let Some(timezone) = &timezone {
+        if timezone.trim().parse::<Tz>().is_err() {
+            return Err(ErrorBadRequest("Unknown timezone"));
+        }
+    }
+
+    app.database
+        .set_weekly_summary_settings(
+            user.0,
+            &WeeklySummarySettings {
+                enabled: weekly_summary.is_some(),
+                timezone: timezone.map(|timezone| timezone.trim().to_string()),
+            },
+        )
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/change_matrix_id?state=saved"))
+        .finish())
+}
+
+/// List the user's email aliases.
+#[get("/emails")]
+async fn list_emails_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let aliases = app
+        .database
+        .get_user_emails(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "email": email,
+        "aliases": aliases,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "emails.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Used to parse the query params of the account page.
+#[derive(Debug, Clone, Deserialize)]
+struct AccountFormState {
+    state: Option<String>,
+    /// The number of reminders imported, and skipped.
+    imported: Option<usize>,
+    skipped: Option<usize>,
+}
+
+/// Account page, for downloading the user's data and deleting their account.
+#[get("/account")]
+async fn account_html(
+    app: Data<App>,
+    user: AuthedUser,
+    query: Query<AccountFormState>,
+) -> Result<impl Responder, actix_web::Error> {
+    let query = query.into_inner();
+    let state = match query.state.as_deref() {
+        Some("wrong_email") => Some("wrong_email"),
+        Some("deletion_cancelled") => Some("deletion_cancelled"),
+        Some("reminders_imported") => Some("reminders_imported"),
+        _ => None,
+    };
+
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let deletion_requested_at = app
+        .database
+        .get_user_deletion_requested_at(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "form_state": state,
+        "email": email,
+        "grace_period_days": app.deletion_grace_period().num_days(),
+        "deleted_at": deletion_requested_at
+            .map(|requested_at| (requested_at + app.deletion_grace_period()).to_rfc3339()),
+        "imported": query.imported.unwrap_or_default(),
+        "skipped": query.skipped.unwrap_or_default(),
+    });
+
+    let result = app
+        .templates
+        .render(
+            "account.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// Download everything we store about the user as JSON.
+#[get("/account/export")]
+async fn export_account_data(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let data = app
+        .export_user_data(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"calbot-data.json\"",
+        ))
+        .json(data))
+}
+
+/// The maximum size of an uploaded reminder export.
+const MAX_REMINDER_IMPORT_SIZE: usize = 5 * 1024 * 1024;
+
+/// Import the reminders, skipping those in rooms the bot can't post in.
+async fn import_reminders(
+    app: &App,
+    user: AuthedUser,
+    mut export: ReminderExport,
+) -> Result<ImportResult, actix_web::Error> {
+    if export.version != REMINDER_EXPORT_VERSION {
+        return Err(ErrorBadRequest(format!(
+            "Unsupported export version {}",
+            export.version
+        )));
+    }
+
+    let mut skipped = Vec::new();
+
+    let rooms: HashSet<_> = export
+        .reminders
+        .iter()
+        .map(|reminder| reminder.room.clone())
+        .collect();
+    for room in rooms {
+        if let Some(problem) = app
+            .validate_room(None, &room)
+            .await
+            .map_err(ErrorInternalServerError)?
+        {
+            let (invalid, valid): (Vec<_>, Vec<_>) = export
+                .reminders
+                .into_iter()
+                .partition(|reminder| reminder.room == room);
+            export.reminders = valid;
+
+            skipped.extend(
+                invalid
+                    .iter()
+                    .map(|reminder| SkippedReminder::new(reminder, problem.clone())),
+            );
+        }
+    }
+
+    let mut result = app
+        .import_reminders(*user, &export)
+        .await
+        .map_err(ErrorInternalServerError)?;
+    result.skipped.extend(skipped);
+
+    Ok(result)
+}
+
+/// Download the user's reminders, for importing into a different deployment.
+#[get("/reminders/export")]
+async fn export_reminders_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let export = app
+        .export_reminders(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            "Content-Disposition",
+            "attachment; filename=\"calbot-reminders.json\"",
+        ))
+        .json(export))
+}
+
+/// Import reminders from an uploaded export.
+#[post("/reminders/import")]
+async fn import_reminders_html(
+    app: Data<App>,
+    mut payload: Multipart,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let mut body = Vec::new();
+
+    while let Some(mut field) = payload.try_next().await? {
+        let field_name = field.name().map(ToOwned::to_owned);
+
+        let mut data = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            if data.len() + chunk.len() > MAX_REMINDER_IMPORT_SIZE {
+                return Err(ErrorBadRequest("File is too large"));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        if field_name.as_deref() == Some("file") {
+            body = data;
+        }
+    }
+
+    let export: ReminderExport = serde_json::from_slice(&body)
+        .map_err(|_| ErrorBadRequest("File is not a valid reminder export"))?;
+
+    let result = import_reminders(&app, user, export).await?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header((
+            "Location",
+            format!(
+                "/account?state=reminders_imported&imported={}&skipped={}",
+                result.imported.len(),
+                result.skipped.len()
+            ),
+        ))
+        .finish())
+}
+
+/// API for exporting the user's reminders.
+#[get("/api/v1/reminders/export")]
+async fn export_reminders_api(
+    app: Data<App>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:read")?;
+
+    let export = app
+        .export_reminders(*user)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(export))
+}
+
+/// API for importing reminders from an export.
+#[post("/api/v1/reminders/import")]
+async fn import_reminders_api(
+    app: Data<App>,
+    data: Json<ReminderExport>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("reminders:write")?;
+
+    let result = import_reminders(&app, user, data.into_inner()).await?;
+
+    Ok(HttpResponse::Ok().json(result))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeleteAccountForm {
+    /// The user has to type their email to confirm.
+    confirm_email: String,
+}
+
+/// Schedule the user's account for deletion once the grace period is over.
+#[post("/account/delete")]
+async fn delete_account_html(
+    app: Data<App>,
+    user: AuthedUser,
+    data: Form<DeleteAccountForm>,
+) -> Result<impl Responder, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    if !data.confirm_email.trim().eq_ignore_ascii_case(&email) {
+        return Ok(HttpResponse::SeeOther()
+            .insert_header(("Location", "/account?state=wrong_email"))
+            .finish());
+    }
+
+    app.database
+        .request_user_deletion(user.0, Utc::now())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/account"))
+        .finish())
+}
+
+/// Cancel a pending deletion of the user's account.
+#[post("/account/delete/cancel")]
+async fn cancel_delete_account_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    app.database
+        .cancel_user_deletion(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/account?state=deletion_cancelled"))
+        .finish())
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DeleteEmailForm {
+    email: String,
+}
+
+/// Remove one of the user's email aliases.
+#[post("/emails/delete")]
+async fn delete_email_html(
+    app: Data<App>,
+    data: Form<DeleteEmailForm>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    app.database
+        .delete_user_email(user.0, &data.email)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/emails"))
+        .finish())
+}
+
+/// Check the requested name and scopes for a new personal access token.
+fn validate_api_token_request(name: &str, scopes: &[String]) -> Result<(), actix_web::Error> {
+    if name.trim().is_empty() {
+        return Err(ErrorBadRequest("Token needs a name"));
+    }
+
+    if scopes.is_empty() {
+        return Err(ErrorBadRequest("Token needs at least one scope"));
+    }
+
+    if let Some(scope) = scopes
+        .iter()
+        .find(|scope| !API_TOKEN_SCOPES.contains(&scope.as_str()))
+    {
+        return Err(ErrorBadRequest(format!("Unknown scope {}", scope)));
+    }
+
+    Ok(())
+}
+
+/// Render the page listing the user's personal access tokens, including the
+/// newly created token if there is one.
+async fn render_api_tokens_page(
+    app: &App,
+    user: AuthedUser,
+    new_token: Option<&str>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let email = app
+        .database
+        .get_email(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let tokens = app
+        .database
+        .get_api_tokens(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let context = json!({
+        "email": email,
+        "tokens": tokens,
+        "scopes": API_TOKEN_SCOPES,
+        "new_token": new_token,
+    });
+
+    let result = app
+        .templates
+        .render(
+            "tokens.html.j2",
+            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
+        )
+        .map_err(ErrorInternalServerError)?;
+
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
+    let response = builder.body(result);
+
+    Ok(response)
+}
+
+/// List the user's personal access tokens.
+#[get("/tokens")]
+async fn list_api_tokens_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    render_api_tokens_page(&app, user, None).await
+}
+
+/// Create a new personal access token.
+///
+/// The form has a `name` and a `scope` field for each checked scope, so is
+/// parsed as a list of pairs. We render the page directly rather than
+/// redirecting so that the token isn't put in the URL.
+#[post("/tokens")]
+async fn create_api_token_html(
+    app: Data<App>,
+    data: Form<Vec<(String, String)>>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let mut name = String::new();
+    let mut scopes = Vec::new();
+    for (key, value) in data.into_inner() {
+        match key.as_str() {
+            "name" => name = value,
+            "scope" => scopes.push(value),
+            _ => {}
+        }
+    }
+
+    validate_api_token_request(&name, &scopes)?;
+
+    let (_, token) = app
+        .add_api_token(user.0, name.trim(), &scopes)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    render_api_tokens_page(&app, user, Some(&token)).await
+}
+
+/// Revoke a personal access token.
+#[post("/tokens/{api_token_id}/delete")]
+async fn delete_api_token_html(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (api_token_id,) = path.into_inner();
+
+    app.database
+        .delete_api_token(user.0, api_token_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/tokens"))
+        .finish())
+}
+
+/// The name and scopes of a personal access token to create.
+#[derive(Debug, Clone, Deserialize)]
+struct CreateApiTokenRequest {
+    name: String,
+    scopes: Vec<String>,
+}
+
+/// API for listing the user's personal access tokens.
+///
+/// Managing tokens needs the login cookie, so that a token can't be used to
+/// create tokens with more scopes.
+#[get("/api/v1/tokens")]
+async fn list_api_tokens_api(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let tokens = app
+        .database
+        .get_api_tokens(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({ "tokens": tokens })))
+}
+
+/// API for creating a personal access token. The token is only returned
+/// here, and can't be retrieved later.
+#[post("/api/v1/tokens")]
+async fn create_api_token_api(
+    app: Data<App>,
+    data: Json<CreateApiTokenRequest>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    validate_api_token_request(&data.name, &data.scopes)?;
+
+    let (api_token_id, token) = app
+        .add_api_token(user.0, data.name.trim(), &data.scopes)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "api_token_id": api_token_id,
+        "token": token,
+        "scopes": data.scopes,
+    })))
+}
+
+/// API for revoking a personal access token.
+#[delete("/api/v1/tokens/{api_token_id}")]
+async fn delete_api_token_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (api_token_id,) = path.into_inner();
+
+    if !app
+        .database
+        .delete_api_token(user.0, api_token_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such token"));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Admin API for listing all users.
+#[get("/api/v1/admin/users")]
+async fn list_users_admin_api(
+    app: Data<App>,
+    query: Query<PaginationQuery>,
+    _admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let mut users = app
+        .database
+        .get_users(query.limit() + 1, query.offset())
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let next_offset = query.paginate(&mut users);
+
+    Ok(HttpResponse::Ok().json(json!({
+        "users": users,
+        "next_offset": next_offset,
+    })))
+}
+
+/// Admin API for viewing a user along with their calendars and reminders.
+#[get("/api/v1/admin/users/{user_id}")]
+async fn get_user_admin_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    _admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (user_id,) = path.into_inner();
+
+    let summary = app
+        .database
+        .get_user(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such user"))?;
+
+    let calendars = app
+        .database
+        .get_calendars_for_user(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    let reminders = app
+        .database
+        .get_reminders_for_user(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({
+        "user": summary,
+        "calendars": calendars,
+        "reminders": reminders,
+    })))
+}
+
+#[derive(Debug, Deserialize, Clone)]
+struct ResetPasswordRequest {
+    password: String,
+}
+
+/// Admin API for setting a new password for a user.
+#[post("/api/v1/admin/users/{user_id}/password")]
+async fn reset_password_admin_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    data: Json<ResetPasswordRequest>,
+    _admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (user_id,) = path.into_inner();
+
+    if app
+        .database
+        .get_user(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .is_none()
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
+
+    let rejection = check_password_policy(
+        &app.config.password_policy,
+        &app.http_client,
+        &data.password,
+    )
+    .await
+    .map_err(ErrorInternalServerError)?;
+
+    if let Some(rejection) = rejection {
+        return Err(ErrorBadRequest(rejection.to_string()));
+    }
+
+    app.database
+        .change_password(user_id, &data.password)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Admin API for making a user an admin.
+#[put("/api/v1/admin/users/{user_id}/admin")]
+async fn grant_admin_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    _admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (user_id,) = path.into_inner();
+
+    if !app
+        .database
+        .set_admin(user_id, true)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
+}
+
+/// Admin API for stopping a user being an admin. This doesn't affect admins
+/// listed in the config.
+#[delete("/api/v1/admin/users/{user_id}/admin")]
+async fn revoke_admin_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    _admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (user_id,) = path.into_inner();
+
+    if !app
         .database
-        .check_password(&data.user_name, &data.password)
+        .set_admin(user_id, false)
         .await
-        .map_err(ErrorInternalServerError)?;
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
 
-    let response = if let Some(user_id) = user_id {
-        let token = app
-            .add_access_token(user_id)
-            .await
-            .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(json!({})))
+}
 
-        let cookie = Cookie::build("token", token)
-            .same_site(SameSite::Lax)
-            .max_age(time::Duration::days(7))
-            .http_only(true)
-            .finish();
+/// Admin API for permanently deleting a user and everything they own.
+#[delete("/api/v1/admin/users/{user_id}")]
+async fn delete_user_admin_api(
+    app: Data<App>,
+    path: Path<(i64,)>,
+    admin: AdminUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let (user_id,) = path.into_inner();
 
-        HttpResponse::SeeOther()
-            .insert_header(("Location", "/calendars"))
-            .cookie(cookie)
-            .finish()
-    } else {
-        HttpResponse::SeeOther()
-            .insert_header(("Location", "/login?state=invalid_password"))
-            .finish()
-    };
+    if user_id == *admin.0 {
+        return Err(ErrorBadRequest("Can't delete your own account"));
+    }
 
-    Ok(response)
+    if !app
+        .database
+        .delete_user(user_id)
+        .await
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such user"));
+    }
+
+    Ok(HttpResponse::Ok().json(json!({})))
 }
 
-/// Change password page
-#[get("/change_password")]
-async fn change_password_html(
+/// Show the user's ICS feed of the events they have reminders for.
+#[get("/feed")]
+async fn get_feed_html(
     app: Data<App>,
     user: AuthedUser,
-    query: Query<EventFormState>,
 ) -> Result<impl Responder, actix_web::Error> {
-    let state = match query.into_inner().state.as_deref() {
-        Some("saved") => Some("saved"),
-        Some("wrong_password") => Some("wrong_password"),
-        Some("password_mismatch") => Some("password_mismatch"),
-        _ => None,
-    };
-
     let email = app
         .database
         .get_email(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
+    let token = app
+        .database
+        .get_feed_token(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
     let context = json!({
-        "form_state": state,
         "email": email,
+        "feed_path": token.as_ref().map(|token| format!("/feed/{}.ics", encode(token))),
+        "feed_url": token.as_ref().and_then(|token| app.feed_url(token)),
     });
 
     let result = app
         .templates
         .render(
-            "change_password.html.j2",
+            "feed.html.j2",
             &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
         )
         .map_err(ErrorInternalServerError)?;
@@ -964,82 +4943,123 @@ async fn change_password_html(
     Ok(response)
 }
 
-/// Form body for changing password
-#[derive(Debug, Deserialize, Clone)]
-struct ChangePasswordForm {
-    old_password: String,
-    new_password: String,
-    confirm_password: String,
+/// Create the user's ICS feed, or change its URL if it already exists.
+#[post("/feed/reset")]
+async fn reset_feed_html(
+    app: Data<App>,
+    user: AuthedUser,
+) -> Result<impl Responder, actix_web::Error> {
+    app.reset_feed_token(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
+
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/feed"))
+        .finish())
 }
 
-/// Change password
-#[post("/change_password")]
-async fn change_password_post_html(
+/// Turn off the user's ICS feed.
+#[post("/feed/delete")]
+async fn delete_feed_html(
     app: Data<App>,
-    data: Form<ChangePasswordForm>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    if data.new_password != data.confirm_password {
-        return Ok(HttpResponse::SeeOther()
-            .insert_header(("Location", "/change_password?state=password_mismatch"))
-            .finish());
-    }
+    app.database
+        .delete_feed_token(user.0)
+        .await
+        .map_err(ErrorInternalServerError)?;
 
-    let right_password = app
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/feed"))
+        .finish())
+}
+
+/// The ICS feed of the events a user has reminders for.
+///
+/// Calendar clients can't log in, so the feed is authenticated by the secret
+/// token in its URL.
+#[get("/feed/{token}.ics")]
+async fn feed_ics(
+    app: Data<App>,
+    path: Path<(String,)>,
+) -> Result<impl Responder, actix_web::Error> {
+    let (token,) = path.into_inner();
+
+    let user_id = app
         .database
-        .check_password_user_id(user.0, &data.old_password)
+        .get_user_from_feed_token(&token)
+        .await
+        .map_err(ErrorInternalServerError)?
+        .ok_or_else(|| ErrorNotFound("No such feed"))?;
+
+    let events = app
+        .database
+        .get_feed_events(user_id)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let response = if right_password.is_some() {
-        app.database
-            .change_password(user.0, &data.new_password)
-            .await
-            .map_err(ErrorInternalServerError)?;
+    let mut builder = HttpResponse::Ok();
+    builder.insert_header(("Content-Type", "text/calendar; charset=utf-8"));
 
-        HttpResponse::SeeOther()
-            .insert_header(("Location", "/change_password?state=saved"))
-            .finish()
-    } else {
-        HttpResponse::SeeOther()
-            .insert_header(("Location", "/change_password?state=wrong_password"))
-            .finish()
-    };
+    Ok(builder.body(feed_to_ics(&events)))
+}
 
-    Ok(response)
+/// Check the requested URL and event types for a new webhook.
+async fn validate_webhook_request(
+    app: &App,
+    url: &str,
+    event_types: &[String],
+) -> Result<(), actix_web::Error> {
+    validate_webhook_url(url, &app.config.webhooks.allowed_hosts)
+        .await
+        .map_err(|e| ErrorBadRequest(format!("{:#}", e)))?;
+
+    if event_types.is_empty() {
+        return Err(ErrorBadRequest("Webhook needs at least one event type"));
+    }
+
+    if let Some(event_type) = event_types
+        .iter()
+        .find(|event_type| !WEBHOOK_EVENT_TYPES.contains(&event_type.as_str()))
+    {
+        return Err(ErrorBadRequest(format!(
+            "Unknown event type {}",
+            event_type
+        )));
+    }
+
+    Ok(())
 }
 
-/// Change Matrix ID page
-#[get("/change_matrix_id")]
-async fn change_matrix_id_html(
+/// List the user's webhooks.
+#[get("/webhooks")]
+async fn list_webhooks_html(
     app: Data<App>,
     user: AuthedUser,
-    query: Query<EventFormState>,
 ) -> Result<impl Responder, actix_web::Error> {
-    let state = query.into_inner().state;
-
-    let old_matrix_id = app
+    let email = app
         .database
-        .get_matrix_id(user.0)
+        .get_email(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let email = app
+    let webhooks = app
         .database
-        .get_email(user.0)
+        .get_webhooks(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
     let context = json!({
-        "form_state": state,
-        "old_matrix_id": old_matrix_id,
         "email": email,
+        "webhooks": webhooks,
+        "event_types": WEBHOOK_EVENT_TYPES,
+        "signature_header": WEBHOOK_SIGNATURE_HEADER,
     });
 
     let result = app
         .templates
         .render(
-            "change_matrix_id.html.j2",
+            "webhooks.html.j2",
             &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
         )
         .map_err(ErrorInternalServerError)?;
@@ -1051,138 +5071,196 @@ async fn change_matrix_id_html(
     Ok(response)
 }
 
-/// Connect a new google account
-#[get("/add_google_account")]
-async fn add_google_account(
+/// Add a webhook.
+///
+/// The form has a `url` and an `event_type` field for each checked event
+/// type, so is parsed as a list of pairs.
+#[post("/webhooks")]
+async fn add_webhook_html(
     app: Data<App>,
+    data: Form<Vec<(String, String)>>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let redirect_url = app
-        .start_google_oauth_session(user.0, "/google_accounts")
+    let mut url = String::new();
+    let mut event_types = Vec::new();
+    for (key, value) in data.into_inner() {
+        match key.as_str() {
+            "url" => url = value,
+            "event_type" => event_types.push(value),
+            _ => {}
+        }
+    }
+
+    validate_webhook_request(&app, &url, &event_types).await?;
+
+    app.add_webhook(user.0, url.trim(), &event_types)
         .await
         .map_err(ErrorInternalServerError)?;
 
     Ok(HttpResponse::SeeOther()
-        .insert_header(("Location", redirect_url.to_string()))
+        .insert_header(("Location", "/webhooks"))
         .finish())
 }
 
-/// List google accounts
-#[get("/google_accounts")]
-async fn list_google_accounts(
+/// Delete a webhook.
+#[post("/webhooks/{webhook_id}/delete")]
+async fn delete_webhook_html(
     app: Data<App>,
+    path: Path<(i64,)>,
     user: AuthedUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let accounts = app
-        .database
-        .get_oauth2_accounts(user.0)
+    let (webhook_id,) = path.into_inner();
+
+    app.database
+        .delete_webhook(user.0, webhook_id)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let email = app
+    Ok(HttpResponse::SeeOther()
+        .insert_header(("Location", "/webhooks"))
+        .finish())
+}
+
+/// The URL and event types of a webhook to add.
+#[derive(Debug, Clone, Deserialize)]
+struct AddWebhookRequest {
+    url: String,
+    event_types: Vec<String>,
+}
+
+/// API for listing the user's webhooks.
+#[get("/api/v1/webhooks")]
+async fn list_webhooks_api(
+    app: Data<App>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("webhooks:read")?;
+
+    let webhooks = app
         .database
-        .get_email(user.0)
+        .get_webhooks(user.0)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let context = json!({
-        "accounts": accounts.into_iter().map(|i| json!({
-            "account_id": i.account_id,
-            "expired": i.expired,
-        })).collect::<Vec<_>>(),
-        "email": email,
-    });
+    Ok(HttpResponse::Ok().json(json!({ "webhooks": webhooks })))
+}
 
-    let result = app
-        .templates
-        .render(
-            "list_google_accounts.html.j2",
-            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
-        )
-        .map_err(ErrorInternalServerError)?;
+/// API for adding a webhook, returning the secret its payloads are signed
+/// with.
+#[post("/api/v1/webhooks")]
+async fn add_webhook_api(
+    app: Data<App>,
+    data: Json<AddWebhookRequest>,
+    user: ApiUser,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = user.require("webhooks:write")?;
 
-    let mut builder = HttpResponse::Ok();
-    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
-    let response = builder.body(result);
+    validate_webhook_request(&app, &data.url, &data.event_types).await?;
 
-    Ok(response)
-}
+    let (webhook_id, secret) = app
+        .add_webhook(user.0, data.url.trim(), &data.event_types)
+        .await
+        .map_err(ErrorInternalServerError)?;
 
-#[derive(Debug, Clone, Deserialize)]
-struct AccountId {
-    account_id: i64,
+    Ok(HttpResponse::Ok().json(json!({
+        "webhook_id": webhook_id,
+        "secret": secret,
+    })))
 }
 
-/// Get google calendars
-#[get("/google_calendars")]
-async fn google_calendars(
+/// API for deleting a webhook.
+#[delete("/api/v1/webhooks/{webhook_id}")]
+async fn delete_webhook_api(
     app: Data<App>,
-    user: AuthedUser,
-    query: Query<AccountId>,
+    path: Path<(i64,)>,
+    user: ApiUser,
 ) -> Result<impl Responder, actix_web::Error> {
-    let (_, calendars) = app
-        .get_google_calendars("/google_calendars", user.0, query.account_id)
-        .await
-        .map_err(ErrorInternalServerError)?;
+    let user = user.require("webhooks:write")?;
 
-    let email = app
+    let (webhook_id,) = path.into_inner();
+
+    if !app
         .database
-        .get_email(user.0)
+        .delete_webhook(user.0, webhook_id)
         .await
-        .map_err(ErrorInternalServerError)?;
-
-    let context = json!({
-        "account_id": query.account_id,
-        "calendars": calendars,
-        "email": email,
-    });
+        .map_err(ErrorInternalServerError)?
+    {
+        return Err(ErrorNotFound("No such webhook"));
+    }
 
-    let result = app
-        .templates
-        .render(
-            "list_google_calendars.html.j2",
-            &tera::Context::from_serialize(context).map_err(ErrorInternalServerError)?,
-        )
-        .map_err(ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(json!({})))
+}
 
-    let mut builder = HttpResponse::Ok();
-    builder.insert_header(("Content-Type", "text/html; charset=utf-8"));
-    let response = builder.body(result);
+/// Return the version and build info of the running app.
+#[get("/version")]
+async fn version() -> impl Responder {
+    HttpResponse::Ok().json(crate::build_info())
+}
 
-    Ok(response)
+/// A transaction of events pushed to us by the homeserver.
+#[derive(Debug, Deserialize, Clone)]
+struct AppserviceTransaction {
+    #[serde(default)]
+    events: Vec<serde_json::Value>,
 }
 
-/// Form body for changing password
+/// Query params the homeserver may use to authenticate itself.
 #[derive(Debug, Deserialize, Clone)]
-struct ChangeMatrixIdForm {
-    new_matrix_id: String,
+struct AppserviceAuthQuery {
+    access_token: Option<String>,
 }
 
-/// Change Matrix ID
-#[post("/change_matrix_id")]
-async fn change_matrix_id_post_html(
+/// Endpoint the homeserver pushes events to when running as an appservice.
+#[put("/_matrix/app/v1/transactions/{txn_id}")]
+async fn appservice_transaction(
     app: Data<App>,
-    data: Form<ChangeMatrixIdForm>,
-    user: AuthedUser,
+    req: HttpRequest,
+    query: Query<AppserviceAuthQuery>,
+    data: Json<AppserviceTransaction>,
 ) -> Result<impl Responder, actix_web::Error> {
-    if !is_likely_a_valid_user_id(&data.new_matrix_id) {
-        return Err(ErrorBadRequest("That does not look like a Matrix ID."));
-    }
+    let appservice = if let Some(appservice) = &app.config.matrix.appservice {
+        appservice
+    } else {
+        return Err(ErrorNotFound("not found"));
+    };
 
-    let email = app
-        .database
-        .get_email(user.0)
-        .await
-        .map_err(ErrorInternalServerError)?;
+    // Newer homeservers use the authorization header, older ones the query
+    // param.
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query.access_token.as_deref());
+
+    // Compare in constant time, so the token can't be guessed from how long
+    // it takes to reject.
+    match token {
+        Some(token)
+            if constant_time::verify_slices_are_equal(
+                token.as_bytes(),
+                appservice.hs_token.as_bytes(),
+            )
+            .is_ok() => {}
+        Some(_) => {
+            return Ok(HttpResponse::Forbidden().json(json!({
+                "errcode": "M_FORBIDDEN",
+                "error": "Invalid hs_token",
+            })))
+        }
+        None => {
+            return Ok(HttpResponse::Unauthorized().json(json!({
+                "errcode": "M_UNAUTHORIZED",
+                "error": "Missing hs_token",
+            })))
+        }
+    }
 
-    app.database
-        .replace_matrix_id(&email, &data.new_matrix_id)
+    app.handle_appservice_transaction(&data.events)
         .await
         .map_err(ErrorInternalServerError)?;
 
-    Ok(HttpResponse::SeeOther()
-        .insert_header(("Location", "/change_matrix_id?state=saved"))
-        .finish())
+    Ok(HttpResponse::Ok().json(json!({})))
 }
 
 /// Redirect to SSO for login, if configured.
@@ -1229,11 +5307,7 @@ async fn sso_auth(
         .await
         .map_err(ErrorInternalServerError)?;
 
-    let cookie = Cookie::build("token", token)
-        .same_site(SameSite::Lax)
-        .max_age(time::Duration::days(7))
-        .http_only(true)
-        .finish();
+    let cookie = session_cookie(&app, token);
 
     Ok(HttpResponse::SeeOther()
         .insert_header(("Location", "/calendars"))
@@ -1260,34 +5334,129 @@ async fn oauth2_callback(
 }
 
 pub fn add_services(cfg: &mut actix_web::web::ServiceConfig) {
+    cfg.app_data(Data::new(build_schema()));
+
     cfg.service(index)
+        .service(version)
         .service(list_events_html)
         .service(list_events_wit_reminders_html)
         .service(list_events_calendar_html)
+        .service(list_events_api)
+        .service(search_events_api)
+        .service(graphql_api)
+        .service(graphiql_html)
         .service(new_reminder_html)
         .service(get_reminder_html)
+        .service(upload_reminder_image_html)
+        .service(delete_reminder_image_html)
         .service(get_event_html)
         .service(delete_reminder_html)
+        .service(restore_reminder_html)
+        .service(pause_reminder_html)
+        .service(resume_reminder_html)
         .service(upsert_reminder_html)
+        .service(preview_reminder_api)
+        .service(move_room_html)
+        .service(move_room_post_html)
+        .service(move_room_api)
+        .service(bulk_reminders_api)
+        .service(export_reminders_html)
+        .service(import_reminders_html)
+        .service(export_reminders_api)
+        .service(import_reminders_api)
+        .service(list_rules_html)
+        .service(add_rule_html)
+        .service(delete_rule_html)
+        .service(list_subscriptions_html)
+        .service(add_subscription_html)
+        .service(delete_subscription_html)
+        .service(list_templates_html)
+        .service(add_template_html)
+        .service(update_template_html)
+        .service(delete_template_html)
+        .service(list_adhoc_reminders_html)
+        .service(add_adhoc_reminder_html)
+        .service(delete_adhoc_reminder_html)
+        .service(upcoming_room_events_api)
+        .service(get_room_quiet_hours_api)
+        .service(set_room_quiet_hours_api)
+        .service(delete_room_quiet_hours_api)
+        .service(get_room_template_api)
+        .service(set_room_template_api)
+        .service(delete_room_template_api)
+        .service(get_room_locale_api)
+        .service(set_room_locale_api)
+        .service(delete_room_locale_api)
+        .service(space_rooms_api)
+        .service(widget_html)
+        .service(create_widget_token_html)
+        .service(delete_widget_token_html)
+        .service(list_widget_tokens_html)
         .service(list_calendars_html)
         .service(new_calendar_html)
         .service(add_new_calendar_html)
+        .service(upload_calendar_html)
+        .service(discover_calendars_html)
+        .service(nextcloud_html)
+        .service(nextcloud_post_html)
+        .service(add_discovered_calendars_html)
         .service(add_oauth2_calendar_html)
         .service(get_calendar_html)
         .service(edit_calendar_html)
         .service(delete_calendar_html)
+        .service(restore_calendar_html)
+        .service(pause_calendar_html)
+        .service(sync_calendar_html)
+        .service(sync_calendar_api)
+        .service(resume_calendar_html)
+        .service(mute_calendar_html)
+        .service(unmute_calendar_html)
         .service(login_get_html)
         .service(login_post_html)
+        .service(logout_post_html)
         .service(change_password_html)
         .service(change_password_post_html)
         .service(change_matrix_id_html)
         .service(change_matrix_id_post_html)
+        .service(delete_matrix_id_html)
+        .service(change_mentions_html)
+        .service(change_weekly_summary_html)
+        .service(change_timezone_html)
+        .service(list_emails_html)
+        .service(list_api_tokens_html)
+        .service(create_api_token_html)
+        .service(delete_api_token_html)
+        .service(list_api_tokens_api)
+        .service(create_api_token_api)
+        .service(delete_api_token_api)
+        .service(account_html)
+        .service(export_account_data)
+        .service(delete_account_html)
+        .service(cancel_delete_account_html)
+        .service(list_users_admin_api)
+        .service(get_user_admin_api)
+        .service(reset_password_admin_api)
+        .service(grant_admin_api)
+        .service(revoke_admin_api)
+        .service(delete_user_admin_api)
+        .service(get_feed_html)
+        .service(reset_feed_html)
+        .service(delete_feed_html)
+        .service(feed_ics)
+        .service(list_webhooks_html)
+        .service(add_webhook_html)
+        .service(delete_webhook_html)
+        .service(list_webhooks_api)
+        .service(add_webhook_api)
+        .service(delete_webhook_api)
+        .service(delete_email_html)
         .service(sso_redirect)
         .service(sso_auth)
         .service(oauth2_callback)
         .service(google_calendars)
         .service(add_google_account)
-        .service(list_google_accounts);
+        .service(list_google_accounts)
+        .service(appservice_transaction);
 }
 
 /// Run the HTTP server.
@@ -1300,16 +5469,48 @@ pub async fn run_server(app: App) -> Result<(), Error> {
         .unwrap_or("127.0.0.1:8080")
         .to_string();
 
-    HttpServer::new(move || {
+    let socket_permissions = app.config.app.socket_permissions.clone();
+
+    let server = HttpServer::new(move || {
         actix_web::App::new()
             .app_data(Data::new(app.clone()))
             .wrap(TracingLogger::default())
             .wrap(Logger::default())
+            .wrap_fn(refresh_session_cookie)
             .configure(add_services)
-    })
-    .bind(&bind_addr)?
-    .run()
-    .await?;
+    });
+
+    let server = if let Some(socket_path) = bind_addr.strip_prefix("unix:") {
+        // Remove any socket left over from a previous run, otherwise binding
+        // will fail.
+        if std::path::Path::new(socket_path).exists() {
+            std::fs::remove_file(socket_path).with_context(|| "Removing old unix socket")?;
+        }
+
+        let server = server.bind_uds(socket_path)?;
+
+        if let Some(permissions) = socket_permissions {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = u32::from_str_radix(&permissions, 8)
+                .with_context(|| "Parsing socket_permissions")?;
+            std::fs::set_permissions(socket_path, std::fs::Permissions::from_mode(mode))
+                .with_context(|| "Setting unix socket permissions")?;
+        }
+
+        server
+    } else {
+        server.bind(&bind_addr)?
+    };
+
+    if let Err(err) = systemd::notify("READY=1") {
+        warn!(
+            error = err.deref() as &dyn StdError,
+            "Failed to notify systemd"
+        );
+    }
+
+    server.run().await?;
 
     Ok(())
 }
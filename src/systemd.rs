@@ -0,0 +1,63 @@
+//! Support for systemd's service notification protocol, used to tell systemd
+//! when we've started up and to ping the watchdog.
+
+use std::{
+    env,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    process,
+};
+
+use anyhow::Error;
+use chrono::Duration;
+
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+
+/// Send a notification (e.g. `READY=1`) to systemd. Does nothing if we're
+/// not running under systemd with `Type=notify`.
+pub fn notify(state: &str) -> Result<(), Error> {
+    let socket_path = if let Some(path) = env::var_os("NOTIFY_SOCKET") {
+        path
+    } else {
+        return Ok(());
+    };
+
+    let socket = UnixDatagram::unbound()?;
+
+    let path = socket_path.to_string_lossy();
+    let addr = if let Some(name) = path.strip_prefix('@') {
+        // Sockets starting with '@' are in the abstract namespace.
+        #[cfg(target_os = "linux")]
+        {
+            SocketAddr::from_abstract_name(name)?
+        }
+        #[cfg(not(target_os = "linux"))]
+        {
+            anyhow::bail!("Abstract notify socket {} not supported", name)
+        }
+    } else {
+        SocketAddr::from_pathname(&*path)?
+    };
+
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+
+    Ok(())
+}
+
+/// Get the interval at which systemd expects us to ping the watchdog, if the
+/// watchdog is enabled for this process. Intervals that aren't positive are
+/// ignored.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+
+    let usec: i64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec <= 0 {
+        return None;
+    }
+
+    Some(Duration::microseconds(usec))
+}
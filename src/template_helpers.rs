@@ -0,0 +1,227 @@
+//! Handlebars helpers available in reminder templates, so that templates can
+//! format dates and text without having to hack around it, e.g.
+//! `{{format_time start "%H:%M %Z"}}` or `{{truncate description 200}}`.
+
+use chrono::{DateTime, Offset, Utc};
+use chrono_tz::Tz;
+use handlebars::{
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, RenderContext, RenderError,
+    ScopedJson,
+};
+use itertools::Itertools;
+use serde_json::Value;
+
+use crate::locale::Locale;
+
+/// Create a Handlebars registry with the reminder template helpers
+/// registered. Dates are formatted in the given timezone, and dates,
+/// durations and phrases in the given locale.
+///
+/// If the template's `description` variable is a placeholder token (as it may
+/// contain HTML that we substitute in after rendering), `truncate` is applied
+/// to the given plain text version of the description instead.
+pub fn reminder_handlebars(
+    timezone: Tz,
+    locale: &'static Locale,
+    description: Option<(String, String)>,
+) -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
+
+    handlebars.register_helper(
+        "format_time",
+        Box::new(FormatTimeHelper { timezone, locale }),
+    );
+    handlebars.register_helper(
+        "humanize_duration",
+        Box::new(HumanizeDurationHelper { locale }),
+    );
+    handlebars.register_helper("pluralize", Box::new(pluralize));
+    handlebars.register_helper("truncate", Box::new(TruncateHelper { description }));
+    handlebars.register_helper("t", Box::new(TranslateHelper { locale }));
+
+    handlebars
+}
+
+handlebars_helper!(pluralize: |count: i64, singular: str, plural: str| {
+    format!("{} {}", count, if count == 1 { singular } else { plural })
+});
+
+/// `{{format_time date format}}`: format an RFC 3339 date with a `strftime`
+/// style format, defaulting to `%H:%M`.
+struct FormatTimeHelper {
+    timezone: Tz,
+    locale: &'static Locale,
+}
+
+impl HelperDef for FormatTimeHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let date = h
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or_else(|| RenderError::new("format_time: expected a date"))?;
+        let format = h
+            .param(1)
+            .and_then(|param| param.value().as_str())
+            .unwrap_or("%H:%M");
+
+        let date = DateTime::parse_from_rfc3339(date)
+            .map_err(|_| RenderError::new("format_time: invalid date"))?
+            .with_timezone(&self.timezone);
+
+        let formatted = self
+            .locale
+            .format_date(&date, format)
+            .map_err(|_| RenderError::new("format_time: invalid format"))?;
+
+        Ok(ScopedJson::Derived(Value::String(formatted)))
+    }
+}
+
+/// `{{humanize_duration minutes}}`: a number of minutes as text, e.g. "1 hour
+/// and 30 minutes".
+struct HumanizeDurationHelper {
+    locale: &'static Locale,
+}
+
+impl HelperDef for HumanizeDurationHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let minutes = h
+            .param(0)
+            .and_then(|param| param.value().as_i64())
+            .ok_or_else(|| RenderError::new("humanize_duration: expected a number"))?;
+
+        Ok(ScopedJson::Derived(Value::String(
+            self.locale.format_duration(minutes),
+        )))
+    }
+}
+
+/// `{{t "key"}}`: a phrase from the locale's message catalogue, e.g.
+/// `{{t "starts_in"}}`.
+struct TranslateHelper {
+    locale: &'static Locale,
+}
+
+impl HelperDef for TranslateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let key = h
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or_else(|| RenderError::new("t: expected a message key"))?;
+
+        let message = self
+            .locale
+            .message(key)
+            .ok_or_else(|| RenderError::new(format!("t: unknown message {:?}", key)))?;
+
+        Ok(ScopedJson::Derived(Value::String(message.to_string())))
+    }
+}
+
+/// `{{truncate text length}}`: cut the text down to `length`
+/// characters, adding an ellipsis if anything was removed.
+struct TruncateHelper {
+    /// The description's placeholder token and its plain text.
+    description: Option<(String, String)>,
+}
+
+impl HelperDef for TruncateHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let text = h
+            .param(0)
+            .map(|param| param.value().as_str().unwrap_or_default())
+            .ok_or_else(|| RenderError::new("truncate: expected some text"))?;
+        let length = h
+            .param(1)
+            .and_then(|param| param.value().as_u64())
+            .ok_or_else(|| RenderError::new("truncate: expected a length"))?;
+
+        let text = match &self.description {
+            Some((token, description)) if token == text => description,
+            _ => text,
+        };
+
+        let truncated = truncate(text, length as usize);
+
+        Ok(ScopedJson::Derived(Value::String(truncated)))
+    }
+}
+
+/// Format the start time in each of the given timezones, earliest offset
+/// first, e.g. "10:00 London / 11:00 Berlin / 05:00 New York".
+///
+/// Returns `None` unless there are at least two different timezones, as then
+/// there's nothing to convert.
+pub fn attendee_times(start: DateTime<Utc>, timezones: &[Tz]) -> Option<String> {
+    let mut timezones = timezones.to_vec();
+    timezones.sort_by_key(|timezone| {
+        (
+            start
+                .with_timezone(timezone)
+                .offset()
+                .fix()
+                .local_minus_utc(),
+            timezone.name(),
+        )
+    });
+    timezones.dedup();
+
+    if timezones.len() < 2 {
+        return None;
+    }
+
+    let times = timezones
+        .iter()
+        .map(|timezone| {
+            // Use the city, e.g. `New York` for `America/New_York`.
+            let city = timezone
+                .name()
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .replace('_', " ");
+
+            format!("{} {}", start.with_timezone(timezone).format("%H:%M"), city)
+        })
+        .join(" / ");
+
+    Some(times)
+}
+
+/// Cut the text down to `length` characters, adding an ellipsis if
+/// anything was removed.
+pub fn truncate(text: &str, length: usize) -> String {
+    if text.chars().count() <= length {
+        return text.to_string();
+    }
+
+    let mut truncated: String = text.chars().take(length).collect();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push('…');
+
+    truncated
+}
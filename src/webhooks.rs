@@ -0,0 +1,161 @@
+//! Outgoing webhooks, which POST signed JSON payloads to user registered URLs
+//! when reminders are sent or fail, or when events change.
+
+use std::net::{IpAddr, SocketAddr};
+
+use anyhow::{bail, Context, Error};
+use chrono::Duration;
+use hmac::{Hmac, Mac};
+use reqwest::redirect::Policy;
+use sha2::Sha256;
+use tokio::net::lookup_host;
+use url::{Host, Url};
+
+/// The events that webhooks can subscribe to.
+pub const WEBHOOK_EVENT_TYPES: &[&str] = &["reminder.sent", "reminder.failed", "event.changed"];
+
+/// The header containing the signature of the payload.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Calbot-Signature";
+
+/// How long we wait before retrying a delivery that failed. This doubles
+/// after every failed attempt.
+const WEBHOOK_RETRY_INITIAL_BACKOFF_SECONDS: i64 = 30;
+
+/// How many times we try to deliver a payload before giving up. With the
+/// backoff above this is a bit over two hours.
+pub const WEBHOOK_MAX_ATTEMPTS: i32 = 9;
+
+/// Sign the payload with the webhook's secret, returning the value of the
+/// [`WEBHOOK_SIGNATURE_HEADER`] header.
+///
+/// The signature is the hex encoded HMAC-SHA256 of the body, prefixed with
+/// `sha256=`, so receivers can verify the payload came from us.
+pub fn sign_webhook_payload(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body.as_bytes());
+
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// How long to wait before the next attempt to deliver a payload, given how
+/// many attempts have failed so far.
+pub fn webhook_retry_backoff(attempts: i32) -> Duration {
+    let exponent = attempts.clamp(1, 16) - 1;
+    Duration::seconds(WEBHOOK_RETRY_INITIAL_BACKOFF_SECONDS << exponent)
+}
+
+/// Check that the webhook URL is something we can POST to.
+///
+/// So that webhooks can't be used to reach internal services, the host must
+/// only resolve to public addresses, unless it's one of the `allowed_hosts`.
+pub async fn validate_webhook_url(url: &str, allowed_hosts: &[String]) -> Result<(), Error> {
+    let url = parse_webhook_url(url)?;
+    resolve_webhook_host(&url, allowed_hosts).await?;
+
+    Ok(())
+}
+
+/// Build a client to deliver payloads to the webhook URL with, after checking
+/// the URL again.
+///
+/// The client only connects to the addresses we checked, so the host can't be
+/// re-pointed at an internal address in between, and doesn't follow
+/// redirects.
+pub async fn webhook_client(url: &str, allowed_hosts: &[String]) -> Result<reqwest::Client, Error> {
+    let url = parse_webhook_url(url)?;
+    let addrs = resolve_webhook_host(&url, allowed_hosts).await?;
+
+    let mut builder = reqwest::Client::builder().redirect(Policy::none());
+    if let (Some(Host::Domain(domain)), false) = (url.host(), addrs.is_empty()) {
+        builder = builder.resolve_to_addrs(domain, &addrs);
+    }
+
+    Ok(builder.build()?)
+}
+
+/// Parse the webhook URL, checking it's http or https.
+fn parse_webhook_url(url: &str) -> Result<Url, Error> {
+    let url = Url::parse(url.trim())?;
+
+    if url.scheme() != "https" && url.scheme() != "http" {
+        bail!("Webhook URL must be http or https");
+    }
+
+    if url.host().is_none() {
+        bail!("Webhook URL must have a host");
+    }
+
+    Ok(url)
+}
+
+/// Resolve the webhook URL's host, checking that it only resolves to public
+/// addresses. Returns no addresses if the host is allowed regardless, in
+/// which case it should be resolved as normal.
+async fn resolve_webhook_host(
+    url: &Url,
+    allowed_hosts: &[String],
+) -> Result<Vec<SocketAddr>, Error> {
+    let host = url.host_str().context("Webhook URL must have a host")?;
+    if allowed_hosts
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    {
+        return Ok(Vec::new());
+    }
+
+    // Both http and https have default ports.
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Domain(domain)) => lookup_host((domain, port))
+            .await
+            .with_context(|| format!("Couldn't resolve {domain}"))?
+            .collect(),
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        None => bail!("Webhook URL must have a host"),
+    };
+
+    if addrs.is_empty() {
+        bail!("Couldn't resolve {host}");
+    }
+
+    if addrs.iter().any(|addr| is_internal_address(addr.ip())) {
+        bail!("Webhook URL must not point at a loopback, private or link-local address");
+    }
+
+    Ok(addrs)
+}
+
+/// Whether the address is one that webhooks shouldn't be able to reach, i.e.
+/// a loopback, private, link-local or unspecified address.
+fn is_internal_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+
+            ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (first == 100 && second & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_internal_address(IpAddr::V4(ip));
+            }
+
+            let first_segment = ip.segments()[0];
+
+            ip.is_loopback()
+                || ip.is_unspecified()
+                // Unique local, fc00::/7.
+                || first_segment & 0xfe00 == 0xfc00
+                // Link-local, fe80::/10.
+                || first_segment & 0xffc0 == 0xfe80
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use actix_web::test::read_body;
+use anyhow::{Context, Error};
+use calendar_bot::database::CalendarKind;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+/// Test downloading the user's data.
+#[test_log::test(actix_web::test)]
+async fn test_export_account_data() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "test calendar".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            Some("user".to_string()),
+            Some("hunter2".to_string()),
+            false,
+        )
+        .await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/account/export")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let disposition = resp
+        .headers()
+        .get("content-disposition")
+        .context("content-disposition header")?;
+    assert!(disposition.to_str()?.starts_with("attachment"));
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(!body.contains("hunter2"));
+
+    let data: Value = serde_json::from_str(&body)?;
+    assert_eq!(data["email"], "bob");
+    assert_eq!(data["calendars"][0]["calendar_id"], calendar_id);
+    assert!(data["reminders"]
+        .as_array()
+        .context("missing reminders")?
+        .is_empty());
+    assert!(data["send_history"].is_array());
+
+    Ok(())
+}
+
+/// Test requesting and cancelling the deletion of the user's account.
+#[test_log::test(actix_web::test)]
+async fn test_delete_account() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+    app.database
+        .set_preferred_matrix_id("bob", "@bob:example.com")
+        .await?;
+    app.database
+        .add_google_oauth_token(
+            user_id,
+            "bob@work.example.com",
+            "access",
+            "refresh",
+            Utc::now() + Duration::hours(1),
+        )
+        .await?;
+    app.database
+        .add_matrix_id("bob@work.example.com", "@bob:work.example.com")
+        .await?;
+
+    let delete = |confirm_email: &str| {
+        actix_web::test::TestRequest::post()
+            .uri("/account/delete")
+            .cookie(cookie.clone())
+            .set_form(json!({ "confirm_email": confirm_email }))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, delete("alice")).await;
+    let location = resp.headers().get("location").context("location header")?;
+    assert_eq!(location.to_str()?, "/account?state=wrong_email");
+    assert_eq!(
+        app.database.get_user_deletion_requested_at(user_id).await?,
+        None
+    );
+
+    let resp = actix_web::test::call_service(&actix_app, delete("bob")).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+    assert!(app
+        .database
+        .get_user_deletion_requested_at(user_id)
+        .await?
+        .is_some());
+
+    // The deletion isn't due until the grace period has passed.
+    let before = Utc::now() - Duration::days(30);
+    assert!(app
+        .database
+        .get_users_pending_deletion(before)
+        .await?
+        .is_empty());
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/account")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Keep my account"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/account/delete/cancel")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+    assert_eq!(
+        app.database.get_user_deletion_requested_at(user_id).await?,
+        None
+    );
+
+    // Once a deletion is due, the account can be deleted.
+    app.database
+        .request_user_deletion(user_id, Utc::now() - Duration::days(31))
+        .await?;
+    assert_eq!(
+        app.database.get_users_pending_deletion(before).await?,
+        vec![user_id]
+    );
+
+    app.database.delete_user(user_id).await?;
+
+    // Their Matrix IDs go too, including those of their aliases.
+    assert!(app.database.get_user_mappings().await?.is_empty());
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/tokens")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(!resp.status().is_success(), "status: {}", resp.status());
+
+    Ok(())
+}
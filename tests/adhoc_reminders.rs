@@ -0,0 +1,118 @@
+use actix_web::test::read_body;
+use anyhow::{Context, Error};
+use chrono::{Duration, DurationRound, Utc};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+/// Test that one-off reminders are queued up alongside calendar reminders,
+/// and can be deleted before they're sent.
+#[test_log::test(actix_web::test)]
+async fn test_adhoc_reminders() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let remind_at = (Utc::now() + Duration::hours(1))
+        .duration_trunc(Duration::seconds(1))
+        .context("truncating")?;
+
+    let reminder_id = app
+        .database
+        .add_adhoc_reminder(user_id, "#team:example.com", "Release today", remind_at)
+        .await?;
+
+    // Reminders in the past aren't queued.
+    app.database
+        .add_adhoc_reminder(
+            user_id,
+            "#team:example.com",
+            "Too late",
+            Utc::now() - Duration::hours(1),
+        )
+        .await?;
+
+    let reminders = app.database.get_next_reminders().await?;
+    assert_eq!(reminders.len(), 1);
+
+    let (date, reminder) = &reminders[0];
+    assert_eq!(*date, remind_at);
+    assert_eq!(reminder.reminder_id, reminder_id);
+    assert_eq!(reminder.room, "#team:example.com");
+    assert_eq!(reminder.summary.as_deref(), Some("Release today"));
+    assert!(reminder.adhoc);
+
+    // We can look the reminder up again, e.g. to snooze it.
+    let instance = app
+        .database
+        .get_reminder_instance(reminder_id, remind_at)
+        .await?
+        .context("missing reminder instance")?;
+    assert!(instance.adhoc);
+
+    let upcoming = app
+        .database
+        .get_upcoming_adhoc_reminders_for_user(user_id)
+        .await?;
+    assert_eq!(upcoming.len(), 1);
+
+    assert!(
+        app.database
+            .delete_adhoc_reminder(user_id, reminder_id)
+            .await?
+    );
+    assert!(
+        !app.database
+            .delete_adhoc_reminder(user_id, reminder_id)
+            .await?
+    );
+
+    assert!(app.database.get_next_reminders().await?.is_empty());
+
+    Ok(())
+}
+
+/// Test that the page lists the user's reminders, and that reminders can't be
+/// added in the past.
+#[test_log::test(actix_web::test)]
+async fn test_adhoc_reminders_page() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    app.database
+        .add_adhoc_reminder(
+            user_id,
+            "#team:example.com",
+            "Release today",
+            Utc::now() + Duration::hours(1),
+        )
+        .await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/reminders/adhoc")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Release today"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/reminders/adhoc")
+        .cookie(cookie)
+        .set_form([
+            ("room", "#team:example.com"),
+            ("message", "Too late"),
+            ("remind_at", "2020-01-01T15:00"),
+            ("timezone", "Europe/London"),
+        ])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
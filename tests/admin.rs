@@ -0,0 +1,172 @@
+use actix_web::test::read_body_json;
+use anyhow::{Context, Error};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app_with_config, create_user_and_login, test_event,
+    test_instance, test_reminder,
+};
+
+/// Test that only admins can use the admin API, and that they can list,
+/// view, reset the password of and delete users.
+#[test_log::test(actix_web::test)]
+async fn test_admin_api() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app_with_config(
+        r#"
+        [app]
+        admins = ["admin"]
+    "#,
+    )
+    .await?;
+
+    let admin_cookie = create_user_and_login(&app, "admin").await?;
+    let bob_cookie = create_user_and_login(&app, "bob").await?;
+    let bob_user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, bob_user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(bob_user_id, calendar_id, "event1"))
+        .await?;
+
+    // Non-admins can't use the admin API.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/admin/users")
+        .cookie(bob_cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/admin/users")
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    let users = body["users"].as_array().context("missing users")?;
+    assert_eq!(users.len(), 2);
+    assert_eq!(users[1]["email"], "bob");
+    assert_eq!(users[1]["num_calendars"], 1);
+    assert_eq!(users[1]["num_reminders"], 1);
+    assert_eq!(users[1]["has_password"], false);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri(&format!("/api/v1/admin/users/{}", bob_user_id))
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["calendars"][0]["calendar_id"], calendar_id);
+    assert_eq!(body["reminders"][0]["reminder_id"], reminder_id);
+    assert_eq!(body["reminders"][0]["summary"], "Standup");
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/api/v1/admin/users/{}/password", bob_user_id))
+        .cookie(admin_cookie.clone())
+        .set_json(json!({ "password": "correct horse battery staple" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert_eq!(
+        app.database
+            .check_password("bob", "correct horse battery staple")
+            .await?,
+        Some(bob_user_id)
+    );
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/api/v1/admin/users/{}", bob_user_id))
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert!(app.database.get_user(bob_user_id).await?.is_none());
+    assert!(app
+        .database
+        .get_calendars_for_user(bob_user_id)
+        .await?
+        .is_empty());
+
+    // Bob's login no longer works.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/tokens")
+        .cookie(bob_cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(!resp.status().is_success(), "status: {}", resp.status());
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/api/v1/admin/users/{}", bob_user_id))
+        .cookie(admin_cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    Ok(())
+}
+
+/// Test making users admins through the API.
+#[test_log::test(actix_web::test)]
+async fn test_grant_admin() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app_with_config(
+        r#"
+        [app]
+        admins = ["admin"]
+    "#,
+    )
+    .await?;
+
+    let admin_cookie = create_user_and_login(&app, "admin").await?;
+    let bob_cookie = create_user_and_login(&app, "bob").await?;
+    let bob_user_id = app.database.upsert_account("bob").await?;
+
+    let list_users = |cookie| {
+        actix_web::test::TestRequest::get()
+            .uri("/api/v1/admin/users")
+            .cookie(cookie)
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, list_users(bob_cookie.clone())).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri(&format!("/api/v1/admin/users/{}/admin", bob_user_id))
+        .cookie(admin_cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert!(app.is_admin(bob_user_id).await?);
+
+    let resp = actix_web::test::call_service(&actix_app, list_users(bob_cookie.clone())).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["users"][1]["is_admin"], true);
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/api/v1/admin/users/{}/admin", bob_user_id))
+        .cookie(admin_cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let resp = actix_web::test::call_service(&actix_app, list_users(bob_cookie)).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    Ok(())
+}
@@ -0,0 +1,130 @@
+use actix_web::test::{read_body, read_body_json};
+use anyhow::{Context, Error};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+/// Test creating personal access tokens, using them with the API, and that
+/// they're limited to their scopes.
+#[test_log::test(actix_web::test)]
+async fn test_api_tokens() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    // Unknown scopes are rejected.
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/tokens")
+        .cookie(cookie.clone())
+        .set_json(json!({ "name": "script", "scopes": ["everything"] }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/tokens")
+        .cookie(cookie.clone())
+        .set_json(json!({ "name": "script", "scopes": ["rooms:read"] }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: Value = read_body_json(resp).await;
+    let token = body["token"].as_str().context("missing token")?.to_string();
+    let api_token_id = body["api_token_id"].as_i64().context("missing ID")?;
+
+    let get_room_locale = |token: &str| {
+        actix_web::test::TestRequest::get()
+            .uri("/api/v1/rooms/%23room:example.com/locale")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, get_room_locale(&token)).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let resp = actix_web::test::call_service(&actix_app, get_room_locale("calbot_wrong")).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    // The token doesn't have the `rooms:write` scope.
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23room:example.com/locale")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .set_json(json!({ "locale": "de" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    // Tokens can't be used to manage tokens.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/tokens")
+        .insert_header(("Authorization", format!("Bearer {}", token)))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(!resp.status().is_success(), "status: {}", resp.status());
+
+    // Only the hash is stored, and we record when the token was used.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/tokens")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    let body: Value = read_body_json(resp).await;
+    let tokens = body["tokens"].as_array().context("missing tokens")?;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0]["name"], "script");
+    assert!(!tokens[0]["last_used"].is_null());
+    assert!(!body.to_string().contains(&token));
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/tokens")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("script"));
+    assert!(!body.contains(&token));
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/api/v1/tokens/{}", api_token_id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let resp = actix_web::test::call_service(&actix_app, get_room_locale(&token)).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    Ok(())
+}
+
+/// Test creating a token through the settings page shows it once.
+#[test_log::test(actix_web::test)]
+async fn test_create_api_token_html() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/tokens")
+        .cookie(cookie.clone())
+        .insert_header(("Content-Type", "application/x-www-form-urlencoded"))
+        .set_payload("name=dashboard&scope=reminders%3Aread&scope=calendars%3Aread")
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("calbot_"));
+
+    let tokens = app.database.get_api_tokens(user_id).await?;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].name, "dashboard");
+    assert_eq!(tokens[0].scopes, vec!["reminders:read", "calendars:read"]);
+
+    Ok(())
+}
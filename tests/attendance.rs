@@ -0,0 +1,148 @@
+use anyhow::Error;
+use calendar_bot::database::SentReminder;
+use chrono::{Duration, Utc};
+use httptest::{
+    matchers::{all_of, contains, request},
+    responders::json_encoded,
+    Expectation,
+};
+use serde_json::json;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app_with_homeserver, test_event, test_instance, test_reminder,
+};
+
+/// Test that reacting to a reminder with ✅ or ❌ records attendance, and that
+/// redacting the reaction removes it again.
+#[test_log::test(actix_web::test)]
+async fn test_attendance_from_reactions() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    let (app, _db, _actix_app) = create_actix_app_with_homeserver(&server.url_str(""), "").await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let date = Utc::now() + Duration::hours(1);
+    app.database
+        .insert_events(
+            calendar_id,
+            vec![test_event(calendar_id, "event1")],
+            vec![test_instance("event1", date)],
+        )
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    app.database
+        .add_sent_reminder(&SentReminder {
+            reminder_id,
+            timestamp: date,
+            room_id: "!room:example.com".to_string(),
+            matrix_event_id: "$reminder".to_string(),
+            summary: Some("Standup".to_string()),
+            location: None,
+            sender: None,
+        })
+        .await?;
+
+    let reaction = |event_id: &str, sender: &str, key: &str| {
+        json!({
+            "type": "m.reaction",
+            "event_id": event_id,
+            "sender": sender,
+            "content": {
+                "m.relates_to": {
+                    "rel_type": "m.annotation",
+                    "event_id": "$reminder",
+                    "key": key,
+                },
+            },
+        })
+    };
+
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/_matrix/client/r0/sync")).respond_with(
+            json_encoded(json!({
+                "next_batch": "s2",
+                "rooms": {
+                    "join": {
+                        "!room:example.com": {
+                            "timeline": {
+                                "events": [
+                                    reaction("$alice", "@alice:example.com", "✅"),
+                                    reaction("$bob", "@bob:example.com", "❌"),
+                                    reaction("$carol", "@carol:example.com", "✅"),
+                                    // Our own reactions are ignored.
+                                    reaction("$bot", "@bot:example.com", "✅"),
+                                    // Other reactions are ignored.
+                                    reaction("$dave", "@dave:example.com", "🎉"),
+                                    {
+                                        "type": "m.room.redaction",
+                                        "event_id": "$redaction",
+                                        "sender": "@carol:example.com",
+                                        "redacts": "$carol",
+                                        "content": {},
+                                    },
+                                ],
+                            },
+                        },
+                    },
+                },
+            })),
+        ),
+    );
+
+    let next_batch = app.sync_once(None, "@bot:example.com", Some("s1")).await?;
+    assert_eq!(next_batch, "s2");
+
+    let attendance = app
+        .database
+        .get_attendance_for_event(calendar_id, "event1")
+        .await?;
+    let attendance: Vec<_> = attendance
+        .iter()
+        .map(|a| (a.matrix_id.as_str(), a.attending))
+        .collect();
+    assert_eq!(
+        attendance,
+        [("@alice:example.com", true), ("@bob:example.com", false)]
+    );
+
+    Ok(())
+}
+
+/// Test that the other accounts reminders are sent from are synced with their
+/// own access tokens, so we see reactions in rooms only they're in.
+#[test_log::test(actix_web::test)]
+async fn test_sync_sender() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    let (app, _db, _actix_app) = create_actix_app_with_homeserver(
+        &server.url_str(""),
+        r#"
+        [[matrix.senders]]
+        name = "ops"
+        access_token = "ops_token"
+        "#,
+    )
+    .await?;
+
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/_matrix/client/r0/sync"),
+            request::headers(contains(("authorization", "Bearer ops_token"))),
+        ])
+        .respond_with(json_encoded(json!({ "next_batch": "s2" }))),
+    );
+
+    let next_batch = app
+        .sync_once(Some("ops"), "@ops:example.com", Some("s1"))
+        .await?;
+    assert_eq!(next_batch, "s2");
+
+    Ok(())
+}
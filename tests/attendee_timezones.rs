@@ -0,0 +1,123 @@
+use actix_web::test::read_body_json;
+use anyhow::{Context, Error};
+use calendar_bot::{
+    database::{Attendee, CalendarKind, Event, EventInstance},
+    template_helpers::attendee_times,
+};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance};
+
+#[test]
+fn test_attendee_times() {
+    let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+
+    assert_eq!(
+        attendee_times(
+            start,
+            &[
+                Tz::Europe__Berlin,
+                Tz::America__New_York,
+                Tz::Europe__London
+            ]
+        )
+        .as_deref(),
+        Some("05:00 New York / 10:00 London / 11:00 Berlin")
+    );
+
+    // Nothing to convert between.
+    assert_eq!(
+        attendee_times(start, &[Tz::Europe__London, Tz::Europe__London]),
+        None
+    );
+    assert_eq!(attendee_times(start, &[]), None);
+}
+
+/// Test that reminders list the start time in the timezones of attendees
+/// that have set one.
+#[test_log::test(actix_web::test)]
+async fn test_attendee_timezones() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+    let alice_id = app.database.upsert_account("alice@example.com").await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/change_matrix_id/timezone")
+        .cookie(cookie.clone())
+        .set_form([("timezone", "Nowhere/Special")])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    app.database
+        .set_user_timezone(user_id, Some("Europe/London"))
+        .await?;
+    app.database
+        .set_user_timezone(alice_id, Some("America/New_York"))
+        .await?;
+
+    let bob_email = app.database.get_email(user_id).await?;
+    let attendees = vec![
+        Attendee {
+            email: bob_email,
+            common_name: None,
+            status: None,
+        },
+        Attendee {
+            email: "alice@example.com".to_string(),
+            common_name: None,
+            status: None,
+        },
+    ];
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let event = Event {
+        attendees: attendees.clone(),
+        ..test_event(calendar_id, "event1")
+    };
+    let instance = EventInstance {
+        attendees,
+        ..test_instance(
+            "event1",
+            Utc.with_ymd_and_hms(2040, 1, 16, 15, 30, 0).unwrap(),
+        )
+    };
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+        .cookie(cookie)
+        .set_json(json!({
+            "template": "{{ attendee_times }}",
+            "minutes_before": 5,
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert_eq!(markdown, "10:30 New York / 15:30 London");
+
+    Ok(())
+}
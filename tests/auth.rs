@@ -1,10 +1,11 @@
 use actix_web::cookie::Cookie;
 use anyhow::{Context, Error};
+use chrono::{Duration, Utc};
 use serde_json::json;
 
 pub mod common;
 
-use common::create_actix_app;
+use common::{create_actix_app, create_actix_app_with_config, create_user_and_login};
 
 /// Test logging in with username and password works.
 #[test_log::test(actix_web::test)]
@@ -61,3 +62,254 @@ async fn test_password_login() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Test that logging out revokes the token and clears the cookie.
+#[test_log::test(actix_web::test)]
+async fn test_logout() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let user_id: i64 = app.database.upsert_account("bob").await?;
+    let token = app.add_access_token(user_id).await?;
+    let cookie = Cookie::build("token", token.clone()).finish();
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/logout")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+    let location = resp.headers().get("location").context("location header")?;
+    assert_eq!(location.to_str()?, "/login");
+
+    let removal = resp.headers().get("set-cookie").context("cookie")?;
+    let removal = Cookie::parse(removal.to_str()?).unwrap();
+    assert_eq!(removal.name(), "token");
+    assert_eq!(removal.value(), "");
+    assert_eq!(
+        removal.max_age(),
+        Some(actix_web::cookie::time::Duration::ZERO)
+    );
+
+    assert_eq!(app.database.get_user_from_token(&token).await?, None);
+
+    // The old cookie no longer works.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/calendars")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(!resp.status().is_success(), "status: {}", resp.status());
+
+    Ok(())
+}
+
+/// Test that failed logins are rate limited, with both backends.
+#[test_log::test(actix_web::test)]
+async fn test_login_rate_limit() -> Result<(), Error> {
+    for backend in ["memory", "database"] {
+        let (app, _db, actix_app) = create_actix_app_with_config(&format!(
+            r#"
+            [rate_limit]
+            backend = "{backend}"
+            max_attempts = 2
+        "#
+        ))
+        .await?;
+
+        let user_id: i64 = app.database.upsert_account("bob").await?;
+        app.database.change_password(user_id, "pass").await?;
+
+        let login = |password: &str| {
+            actix_web::test::TestRequest::post()
+                .uri("/login")
+                .set_form(json!({"user_name": "bob", "password": password}))
+                .to_request()
+        };
+
+        for _ in 0..2 {
+            let resp = actix_web::test::call_service(&actix_app, login("wrong")).await;
+            let location = resp.headers().get("location").context("location header")?;
+            assert_eq!(location.to_str()?, "/login?state=invalid_password");
+        }
+
+        // Even the right password is rejected now.
+        let resp = actix_web::test::call_service(&actix_app, login("pass")).await;
+        let location = resp.headers().get("location").context("location header")?;
+        assert_eq!(location.to_str()?, "/login?state=rate_limited");
+    }
+
+    Ok(())
+}
+
+/// Test that invalid API tokens are rate limited.
+#[test_log::test(actix_web::test)]
+async fn test_api_token_rate_limit() -> Result<(), Error> {
+    let (_app, _db, actix_app) = create_actix_app_with_config(
+        r#"
+        [rate_limit]
+        max_attempts = 2
+    "#,
+    )
+    .await?;
+
+    let get_locale = || {
+        actix_web::test::TestRequest::get()
+            .uri("/api/v1/rooms/%23room:example.com/locale")
+            .insert_header(("Authorization", "Bearer calbot_wrong"))
+            .to_request()
+    };
+
+    for _ in 0..2 {
+        let resp = actix_web::test::call_service(&actix_app, get_locale()).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    let resp = actix_web::test::call_service(&actix_app, get_locale()).await;
+    assert_eq!(resp.status().as_u16(), 429);
+
+    Ok(())
+}
+
+/// Test that `X-Forwarded-For` can't be used to dodge the rate limit, unless
+/// the request comes from a trusted proxy.
+#[test_log::test(actix_web::test)]
+async fn test_rate_limit_trusted_proxies() -> Result<(), Error> {
+    let (_app, _db, actix_app) = create_actix_app_with_config(
+        r#"
+        [rate_limit]
+        max_attempts = 2
+        trusted_proxies = ["10.0.0.1"]
+    "#,
+    )
+    .await?;
+
+    let get_locale = |peer: &str, forwarded_for: &str| {
+        actix_web::test::TestRequest::get()
+            .uri("/api/v1/rooms/%23room:example.com/locale")
+            .peer_addr(peer.parse().unwrap())
+            .insert_header(("Authorization", "Bearer calbot_wrong"))
+            .insert_header(("X-Forwarded-For", forwarded_for.to_string()))
+            .to_request()
+    };
+
+    // A client talking to us directly is limited whatever it claims to be.
+    for i in 0..2 {
+        let req = get_locale("192.0.2.1:1234", &format!("198.51.100.{i}"));
+        let resp = actix_web::test::call_service(&actix_app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    let req = get_locale("192.0.2.1:1234", "198.51.100.9");
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 429);
+
+    // Behind the proxy, clients are told apart by the address the proxy
+    // appended, not by anything the client sent before it.
+    for i in 0..2 {
+        let req = get_locale("10.0.0.1:1234", &format!("203.0.113.{i}, 198.51.100.1"));
+        let resp = actix_web::test::call_service(&actix_app, req).await;
+        assert_eq!(resp.status().as_u16(), 401);
+    }
+
+    let req = get_locale("10.0.0.1:1234", "198.51.100.1");
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 429);
+
+    let req = get_locale("10.0.0.1:1234", "198.51.100.2");
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 401);
+
+    Ok(())
+}
+
+/// Test that using a session pushes back when it expires, both in the DB and
+/// the cookie.
+#[test_log::test(actix_web::test)]
+async fn test_session_refresh() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app_with_config(
+        r#"
+        [app]
+        session_lifetime_days = 2
+    "#,
+    )
+    .await?;
+
+    let user_id: i64 = app.database.upsert_account("bob").await?;
+    app.database
+        .add_access_token(user_id, "some_token", Utc::now() + Duration::hours(1))
+        .await?;
+
+    let get_calendars = || {
+        actix_web::test::TestRequest::get()
+            .uri("/calendars")
+            .cookie(Cookie::new("token", "some_token"))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, get_calendars()).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let cookie = resp.headers().get("set-cookie").context("cookie")?;
+    let cookie = Cookie::parse(cookie.to_str()?)?;
+    assert_eq!(cookie.value(), "some_token");
+    assert_eq!(
+        cookie.max_age(),
+        Some(actix_web::cookie::time::Duration::days(2))
+    );
+
+    let (_, expiry) = app
+        .database
+        .get_user_from_token("some_token")
+        .await?
+        .context("missing token")?;
+    assert!(expiry > Utc::now() + Duration::days(1));
+
+    // The session was just refreshed, so isn't refreshed again.
+    let resp = actix_web::test::call_service(&actix_app, get_calendars()).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    assert!(resp.headers().get("set-cookie").is_none());
+
+    Ok(())
+}
+
+/// Test that changing password checks the old password before the new one.
+#[test_log::test(actix_web::test)]
+async fn test_change_password() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id: i64 = app.database.upsert_account("bob").await?;
+    app.database.change_password(user_id, "password").await?;
+
+    for (old_password, new_password, expected_state) in [
+        // The wrong old password is reported even if the new one is too short.
+        ("wrong", "short", "wrong_password"),
+        ("password", "short", "too_short"),
+        ("password", "new password", "saved"),
+    ] {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/change_password")
+            .cookie(cookie.clone())
+            .set_form(json!({
+                "old_password": old_password,
+                "new_password": new_password,
+                "confirm_password": new_password,
+            }))
+            .to_request();
+        let resp = actix_web::test::call_service(&actix_app, req).await;
+        assert!(resp.status().is_redirection(), "status: {}", resp.status());
+        let location = resp.headers().get("location").context("location header")?;
+        assert_eq!(
+            location.to_str()?,
+            format!("/change_password?state={expected_state}")
+        );
+    }
+
+    assert!(app
+        .database
+        .check_password_user_id(user_id, "new password")
+        .await?
+        .is_some());
+
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use anyhow::Error;
+use calendar_bot::database::{CalendarKind, Event};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+};
+
+/// Test that bulk operations add reminders to the matching events, and
+/// update them when run again.
+#[test_log::test(actix_web::test)]
+async fn test_bulk_upsert_reminders() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let mut calendar_ids = Vec::new();
+    for name in ["work", "home"] {
+        let calendar_id = app
+            .database
+            .add_calendar_basic_auth(
+                user_id,
+                name.to_string(),
+                "https://caldav.example.com".to_string(),
+                CalendarKind::CalDav,
+                None,
+                None,
+                false,
+            )
+            .await?;
+
+        let mut events = Vec::new();
+        let mut instances = Vec::new();
+        for (event_id, summary) in [("standup", "Team Standup"), ("retro", "Retro")] {
+            events.push(Event {
+                summary: Some(summary.to_string()),
+                ..test_event(calendar_id, event_id)
+            });
+            instances.push(test_instance(event_id, Utc::now() + Duration::days(1)));
+        }
+        app.database
+            .insert_events(calendar_id, events, instances)
+            .await?;
+
+        calendar_ids.push(calendar_id);
+    }
+
+    let work_calendar_id = calendar_ids[0];
+    let home_calendar_id = calendar_ids[1];
+
+    let result = app
+        .bulk_upsert_reminders(
+            user_id,
+            Some(work_calendar_id),
+            Some("/standup/i"),
+            "!room:example.com",
+            10,
+        )
+        .await?;
+    assert_eq!(result.created.len(), 1);
+    assert!(result.updated.is_empty());
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(work_calendar_id, "standup")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+    assert_eq!(reminders[0].reminder_id, result.created[0]);
+    assert_eq!(reminders[0].room, "!room:example.com");
+    assert_eq!(reminders[0].minutes_before, 10);
+
+    for (calendar_id, event_id) in [
+        (work_calendar_id, "retro"),
+        (home_calendar_id, "standup"),
+        (home_calendar_id, "retro"),
+    ] {
+        let reminders = app
+            .database
+            .get_reminders_for_event(calendar_id, event_id)
+            .await?;
+        assert!(reminders.is_empty(), "{} {}", calendar_id, event_id);
+    }
+
+    // Running it again across all calendars updates the existing reminder,
+    // rather than adding another.
+    let result = app
+        .bulk_upsert_reminders(user_id, None, Some("/standup/i"), "!room:example.com", 15)
+        .await?;
+    assert_eq!(result.created.len(), 1);
+    assert_eq!(result.updated, vec![reminders[0].reminder_id]);
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(work_calendar_id, "standup")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+    assert_eq!(reminders[0].minutes_before, 15);
+
+    // Without a pattern every event gets a reminder.
+    let result = app
+        .bulk_upsert_reminders(user_id, None, None, "!other:example.com", 5)
+        .await?;
+    assert_eq!(result.created.len(), 4);
+
+    Ok(())
+}
+
+/// Test that the bulk reminder API checks its input before making changes.
+#[test_log::test(actix_web::test)]
+async fn test_bulk_reminders_api_validation() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let alice_user_id = app.database.upsert_account("alice").await?;
+    let alice_calendar_id = add_test_calendar(&app, alice_user_id).await?;
+
+    let bulk = |body: serde_json::Value| {
+        actix_web::test::TestRequest::post()
+            .uri("/api/v1/reminders/bulk")
+            .cookie(cookie.clone())
+            .set_json(body)
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(
+        &actix_app,
+        bulk(json!({
+            "summary_pattern": "(",
+            "room": "!room:example.com",
+            "minutes_before": 10,
+        })),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = actix_web::test::call_service(
+        &actix_app,
+        bulk(json!({
+            "room": "!room:example.com",
+            "minutes_before": -10,
+        })),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = actix_web::test::call_service(
+        &actix_app,
+        bulk(json!({
+            "calendar_id": alice_calendar_id,
+            "room": "!room:example.com",
+            "minutes_before": 10,
+        })),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let resp = actix_web::test::call_service(
+        &actix_app,
+        bulk(json!({
+            "room": "not a room",
+            "minutes_before": 10,
+        })),
+    )
+    .await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use anyhow::{Context, Error};
+use calendar_bot::database::CalendarKind;
+use httptest::{
+    matchers::{all_of, matches, request},
+    responders::status_code,
+};
+
+pub mod common;
+
+use common::create_actix_app;
+
+/// Build a multistatus response with the given responses.
+fn multistatus(responses: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">{}</d:multistatus>
+"#,
+        responses
+    )
+}
+
+/// Build a response for a daily event with the given UID and summary.
+fn event_response(href: &str, uid: &str, summary: &str) -> String {
+    format!(
+        r#"<d:response><d:href>{href}</d:href><d:propstat><d:prop><d:getetag>"1"</d:getetag>
+<c:calendar-data>BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//test//EN
+BEGIN:VEVENT
+UID:{uid}
+DTSTART:20211124T100000Z
+DTEND:20211124T100500Z
+DTSTAMP:20211124T100000Z
+RRULE:FREQ=DAILY
+SUMMARY:{summary}
+END:VEVENT
+END:VCALENDAR
+</c:calendar-data></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"#,
+        href = href,
+        uid = uid,
+        summary = summary,
+    )
+}
+
+/// Test that we fetch only the changes to CalDAV calendars once we have a sync
+/// token.
+#[test_log::test(actix_web::test)]
+async fn test_incremental_sync() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let caldav_server = httptest::Server::run();
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Work".to_string(),
+            caldav_server.url("/calendar/").to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    // The first update fetches everything, along with a sync token.
+    caldav_server.expect(
+        httptest::Expectation::matching(request::method_path("PROPFIND", "/calendar/"))
+            .respond_with(status_code(207).body(multistatus(
+                r#"<d:response><d:href>/calendar/</d:href><d:propstat><d:prop>
+<d:sync-token>token1</d:sync-token>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>"#,
+            ))),
+    );
+    caldav_server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("REPORT", "/calendar/"),
+            request::body(matches("calendar-query")),
+        ])
+        .respond_with(status_code(207).body(multistatus(&event_response(
+            "/calendar/a.ics",
+            "event-a",
+            "Event A",
+        )))),
+    );
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    app.update_calendar(calendar.clone()).await?;
+    caldav_server.verify_and_clear();
+
+    let events = app.database.get_events_in_calendar(calendar_id).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0.summary.as_deref(), Some("Event A"));
+
+    // The second update only fetches what has changed since.
+    caldav_server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("REPORT", "/calendar/"),
+            request::body(matches("sync-collection")),
+            request::body(matches("token1")),
+        ])
+        .respond_with(status_code(207).body(multistatus(
+            r#"<d:response><d:href>/calendar/a.ics</d:href><d:status>HTTP/1.1 404 Not Found</d:status></d:response>
+<d:response><d:href>/calendar/b.ics</d:href><d:propstat><d:prop><d:getetag>"2"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+<d:sync-token>token2</d:sync-token>"#,
+        ))),
+    );
+    caldav_server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("REPORT", "/calendar/"),
+            request::body(matches("calendar-multiget")),
+            request::body(matches("/calendar/b.ics")),
+        ])
+        .respond_with(status_code(207).body(multistatus(&event_response(
+            "/calendar/b.ics",
+            "event-b",
+            "Event B",
+        )))),
+    );
+
+    app.update_calendar(calendar).await?;
+    caldav_server.verify_and_clear();
+
+    let events = app.database.get_events_in_calendar(calendar_id).await?;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0.summary.as_deref(), Some("Event B"));
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::create_actix_app;
+
+/// Test that expired login sessions are deleted, and unexpired ones kept.
+#[test_log::test(actix_web::test)]
+async fn test_cleanup_expired_access_tokens() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    app.database
+        .add_access_token(user_id, "expired", Utc::now() - Duration::days(1))
+        .await?;
+    app.database
+        .add_access_token(user_id, "valid", Utc::now() + Duration::days(1))
+        .await?;
+
+    app.cleanup_expired().await?;
+
+    assert_eq!(app.database.delete_expired_access_tokens().await?, 0);
+    assert!(app.database.get_user_from_token("valid").await?.is_some());
+
+    Ok(())
+}
+
+/// Test that SSO and OAuth2 sessions are deleted once they're stale.
+#[test_log::test(actix_web::test)]
+async fn test_cleanup_stale_sessions() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    app.database
+        .add_sso_session("sso-token", "nonce", "verifier")
+        .await?;
+    app.database
+        .add_oauth2_session(user_id, "oauth2-token", "verifier", "/")
+        .await?;
+
+    // Sessions that have only just started are kept.
+    app.cleanup_expired().await?;
+    assert_eq!(
+        app.database
+            .delete_stale_sso_sessions(Utc::now() - Duration::hours(1))
+            .await?,
+        (0, 0)
+    );
+
+    assert_eq!(
+        app.database
+            .delete_stale_sso_sessions(Utc::now() + Duration::minutes(1))
+            .await?,
+        (1, 1)
+    );
+    assert!(app
+        .database
+        .claim_oauth2_session("oauth2-token")
+        .await?
+        .is_none());
+
+    Ok(())
+}
+
+/// Test that rate limit windows that have passed are deleted.
+#[test_log::test(actix_web::test)]
+async fn test_cleanup_old_rate_limits() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let now = Utc::now();
+    app.database
+        .record_rate_limit_attempt(
+            "login:old",
+            now - Duration::hours(1),
+            now - Duration::hours(2),
+        )
+        .await?;
+    app.database
+        .record_rate_limit_attempt("login:new", now, now - Duration::minutes(5))
+        .await?;
+
+    assert_eq!(
+        app.database
+            .delete_old_rate_limits(now - Duration::minutes(5))
+            .await?,
+        1
+    );
+    assert_eq!(
+        app.database
+            .get_rate_limit_attempts("login:new", now - Duration::minutes(5))
+            .await?,
+        1
+    );
+
+    Ok(())
+}
@@ -8,10 +8,20 @@ use actix_web::{
     middleware::Logger,
 };
 use anyhow::{bail, Context, Error};
-use calendar_bot::config::Config;
+use calendar_bot::{
+    config::Config,
+    database::{CalendarKind, Event, EventInstance, Reminder},
+};
+use chrono::{DateTime, FixedOffset};
+use httptest::{
+    matchers::{all_of, matches, request},
+    responders::{json_encoded, status_code},
+    Expectation,
+};
 use pgtemp::PgTempDB;
 use scraper::Selector;
 use serde::Serialize;
+use serde_json::json;
 use tracing_actix_web::TracingLogger;
 
 pub async fn create_actix_app() -> Result<
@@ -21,6 +31,36 @@ pub async fn create_actix_app() -> Result<
         impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
     ),
     Error,
+> {
+    create_actix_app_with_config("").await
+}
+
+/// Like [`create_actix_app`], but with extra TOML appended to the config.
+pub async fn create_actix_app_with_config(
+    extra_config: &str,
+) -> Result<
+    (
+        calendar_bot::app::App,
+        PgTempDB,
+        impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
+    ),
+    Error,
+> {
+    create_actix_app_with_homeserver("", extra_config).await
+}
+
+/// Like [`create_actix_app_with_config`], but talking to the given Matrix
+/// homeserver.
+pub async fn create_actix_app_with_homeserver(
+    homeserver_url: &str,
+    extra_config: &str,
+) -> Result<
+    (
+        calendar_bot::app::App,
+        PgTempDB,
+        impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error>,
+    ),
+    Error,
 > {
     let db = PgTempDB::async_new().await;
     db.load_database("database.sql");
@@ -32,8 +72,10 @@ pub async fn create_actix_app() -> Result<
         connection_string = "{db_conn_str}"
 
         [matrix]
-        homeserver_url = ""
+        homeserver_url = "{homeserver_url}"
         access_token = ""
+
+        {extra_config}
     "#
     ))?;
 
@@ -43,6 +85,7 @@ pub async fn create_actix_app() -> Result<
         actix_web::App::new()
             .wrap(TracingLogger::default())
             .wrap(Logger::default())
+            .wrap_fn(calendar_bot::auth::refresh_session_cookie)
             .app_data(actix_web::web::Data::new(app.clone()))
             .configure(calendar_bot::site::add_services),
     )
@@ -63,6 +106,107 @@ pub async fn create_user_and_login(
     Ok(cookie)
 }
 
+/// Add a CalDAV calendar for the user, without credentials.
+pub async fn add_test_calendar(app: &calendar_bot::app::App, user_id: i64) -> Result<i64, Error> {
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "test calendar".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    Ok(calendar_id)
+}
+
+/// An event called "Standup" with nothing else set.
+pub fn test_event(calendar_id: i64, event_id: &str) -> Event {
+    Event {
+        calendar_id,
+        event_id: event_id.to_string(),
+        summary: Some("Standup".to_string()),
+        description: None,
+        location: None,
+        organizer: None,
+        attendees: Vec::new(),
+        conference_url: None,
+        duration_minutes: None,
+        last_occurrence: None,
+    }
+}
+
+/// An instance of the event with no attendees.
+pub fn test_instance(event_id: &str, date: impl Into<DateTime<FixedOffset>>) -> EventInstance {
+    EventInstance {
+        event_id: event_id.to_string(),
+        date: date.into(),
+        attendees: Vec::new(),
+    }
+}
+
+/// A reminder sent to `!room:example.com` five minutes before the event,
+/// with every option off.
+pub fn test_reminder(user_id: i64, calendar_id: i64, event_id: &str) -> Reminder {
+    Reminder {
+        reminder_id: 0,
+        calendar_id,
+        user_id,
+        event_id: event_id.to_string(),
+        template: None,
+        minutes_before: 5,
+        room: "!room:example.com".to_string(),
+        attendee_editable: false,
+        extra_attendees: Vec::new(),
+        excluded_attendees: Vec::new(),
+        threaded: false,
+        poll: false,
+        msgtype: None,
+        sender: None,
+        redact_previous: false,
+        attach_ics: false,
+        holiday_region: None,
+        skip_if_declined: false,
+        exclude_tentative: false,
+        exclude_needs_action: false,
+        live_countdown: false,
+        enabled: true,
+        weekdays: None,
+        template_id: None,
+        locale: None,
+    }
+}
+
+/// Make the mock homeserver let the bot join any room and send messages in
+/// it.
+pub fn expect_joinable_rooms(server: &httptest::Server) {
+    server.expect(
+        Expectation::matching(all_of![
+            request::method("POST"),
+            request::path(matches("^/_matrix/client/r0/join/")),
+        ])
+        .times(..)
+        .respond_with(json_encoded(json!({"room_id": "!room:example.com"}))),
+    );
+    server.expect(
+        Expectation::matching(request::path(matches("/state/m.room.power_levels/$")))
+            .times(..)
+            .respond_with(status_code(404)),
+    );
+    server.expect(
+        Expectation::matching(request::method_path(
+            "GET",
+            "/_matrix/client/r0/account/whoami",
+        ))
+        .times(..)
+        .respond_with(json_encoded(json!({"user_id": "@bot:example.com"}))),
+    );
+}
+
 #[macro_export]
 macro_rules! assert_html {
     ($document:expr) => {
@@ -90,8 +234,15 @@ pub struct Form {
 
 impl Form {
     pub fn from_html(document: scraper::Html) -> Result<Form, Error> {
-        let form_selector = Selector::parse("form").unwrap();
+        Form::from_html_with_selector(document, "form")
+    }
+
+    /// Parse the only form in the document that matches the selector.
+    pub fn from_html_with_selector(document: scraper::Html, selector: &str) -> Result<Form, Error> {
+        let form_selector = Selector::parse(selector).unwrap();
         let input_selector = Selector::parse("input").unwrap();
+        let select_selector = Selector::parse("select").unwrap();
+        let textarea_selector = Selector::parse("textarea").unwrap();
 
         let mut form_iter = document.select(&form_selector);
         let form = form_iter.next().context("no form")?;
@@ -100,9 +251,17 @@ impl Form {
         let mut text_elements = Vec::new();
         let mut path = None;
 
+        for element in form
+            .select(&select_selector)
+            .chain(form.select(&textarea_selector))
+        {
+            let name = element.value().attr("name").context("missing name")?;
+            text_elements.push(name.to_string());
+        }
+
         for element in form.select(&input_selector) {
             match element.value().attr("type").context("missing type")? {
-                "text" | "password" => {
+                "text" | "password" | "checkbox" | "number" => {
                     let name = element.value().attr("name").context("missing name")?;
                     text_elements.push(name.to_string());
                 }
@@ -0,0 +1,80 @@
+use actix_web::test::{read_body, read_body_json};
+use anyhow::{Context, Error};
+use chrono::{Duration, Utc};
+use serde_json::Value;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+};
+
+/// Test that pages return their context as JSON when asked to with the
+/// `Accept` header, and HTML otherwise.
+#[test_log::test(actix_web::test)]
+async fn test_pages_as_json() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let get_json = |uri: &str| {
+        actix_web::test::TestRequest::get()
+            .uri(uri)
+            .cookie(cookie.clone())
+            .insert_header(("Accept", "application/json"))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, get_json("/events")).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    assert_eq!(resp.headers().get("vary").context("vary header")?, "Accept");
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["email"], "bob");
+    assert_eq!(body["events"][0]["summary"], "Standup");
+
+    let resp = actix_web::test::call_service(&actix_app, get_json("/calendars")).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["calendars"][0]["calendar_id"], calendar_id);
+
+    let uri = format!("/event/{}/event1", calendar_id);
+    let resp = actix_web::test::call_service(&actix_app, get_json(&uri)).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["event"]["summary"], "Standup");
+    assert!(body["reminders"]
+        .as_array()
+        .context("missing reminders")?
+        .is_empty());
+
+    // Browsers prefer HTML, so still get the page.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/events")
+        .cookie(cookie.clone())
+        .insert_header((
+            "Accept",
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8",
+        ))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let content_type = resp
+        .headers()
+        .get("content-type")
+        .context("content-type header")?;
+    assert!(content_type.to_str()?.starts_with("text/html"));
+    assert_eq!(resp.headers().get("vary").context("vary header")?, "Accept");
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Standup"));
+
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::Error;
+use calendar_bot::{config::DatabaseConfig, database::postgres_connection_config};
+use tokio_postgres::config::SslMode;
+
+fn parse_config(toml_str: &str) -> Result<DatabaseConfig, Error> {
+    Ok(toml::from_str(toml_str)?)
+}
+
+/// Test that the configured `sslmode` takes precedence over the connection
+/// string's, with the verify modes requiring TLS.
+#[test]
+fn test_postgres_sslmode() -> Result<(), Error> {
+    let config = parse_config(
+        r#"
+        connection_string = "postgresql://calbot@localhost/calbotdb"
+    "#,
+    )?;
+    let (pg_config, _) = postgres_connection_config(&config)?;
+    assert_eq!(pg_config.get_ssl_mode(), SslMode::Prefer);
+
+    let config = parse_config(
+        r#"
+        connection_string = "postgresql://calbot@localhost/calbotdb?sslmode=require"
+    "#,
+    )?;
+    let (pg_config, _) = postgres_connection_config(&config)?;
+    assert_eq!(pg_config.get_ssl_mode(), SslMode::Require);
+
+    let config = parse_config(
+        r#"
+        connection_string = "postgresql://calbot@localhost/calbotdb?sslmode=disable"
+        sslmode = "verify-full"
+    "#,
+    )?;
+    let (pg_config, _) = postgres_connection_config(&config)?;
+    assert_eq!(pg_config.get_ssl_mode(), SslMode::Require);
+
+    let config = parse_config(
+        r#"
+        connection_string = "postgresql://calbot@localhost/calbotdb"
+        sslmode = "disable"
+    "#,
+    )?;
+    let (pg_config, _) = postgres_connection_config(&config)?;
+    assert_eq!(pg_config.get_ssl_mode(), SslMode::Disable);
+
+    Ok(())
+}
+
+/// Test that a CA file without any certificates is rejected.
+#[test]
+fn test_postgres_invalid_ca_certificates() -> Result<(), Error> {
+    let config = parse_config(
+        r#"
+        connection_string = "postgresql://calbot@localhost/calbotdb"
+        sslmode = "verify-ca"
+        ca_certificates = "Cargo.toml"
+    "#,
+    )?;
+    assert!(postgres_connection_config(&config).is_err());
+
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use anyhow::Error;
+use calendar_bot::{
+    calendar::{parse_calendars_to_events, parse_ics_file, SyncWindow},
+    database::{Attendee, ReminderInstance},
+};
+use chrono::{Duration, Utc};
+
+/// Build an event with the given attendee statuses, starting tomorrow.
+fn ics_with_attendees(statuses: &[&str]) -> String {
+    let start = (Utc::now() + Duration::days(1)).format("%Y%m%d");
+
+    let attendees: String = statuses
+        .iter()
+        .enumerate()
+        .map(|(i, status)| {
+            format!("ATTENDEE;CN=Person {i};PARTSTAT={status}:mailto:person{i}@example.com\r\n")
+        })
+        .collect();
+
+    format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:meeting\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+SUMMARY:Meeting\r
+{attendees}END:VEVENT\r
+END:VCALENDAR\r
+"
+    )
+}
+
+/// Parse the attendees of the single event in the ICS file, and return a
+/// reminder instance for it.
+fn reminder_for(ics: &str) -> Result<ReminderInstance, Error> {
+    let fetched = parse_ics_file(ics);
+    let (_, mut instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+    assert_eq!(instances.len(), 1);
+    let instance = instances.pop().unwrap();
+
+    Ok(ReminderInstance {
+        reminder_id: 1,
+        calendar_id: 1,
+        event_id: instance.event_id,
+        timestamp: instance.date.into(),
+        summary: Some("Meeting".to_string()),
+        description: None,
+        location: None,
+        conference_url: None,
+        template: None,
+        locale: None,
+        minutes_before: 5,
+        room: "!room:example.com".to_string(),
+        attendees: instance.attendees,
+        extra_attendees: Vec::new(),
+        excluded_attendees: Vec::new(),
+        threaded: false,
+        poll: false,
+        msgtype: None,
+        sender: None,
+        snoozed_until: None,
+        redact_previous: false,
+        attach_ics: false,
+        holiday_region: None,
+        skip_if_declined: true,
+        exclude_tentative: false,
+        exclude_needs_action: false,
+        live_countdown: false,
+        last_occurrence: false,
+        adhoc: false,
+        duplicate_reminder_ids: Vec::new(),
+    })
+}
+
+/// Test that the participation status of every attendee is parsed, including
+/// those who have declined.
+#[test]
+fn test_parse_partstat() -> Result<(), Error> {
+    let reminder = reminder_for(&ics_with_attendees(&["ACCEPTED", "DECLINED"]))?;
+
+    assert_eq!(
+        reminder.attendees,
+        vec![
+            Attendee {
+                email: "person0@example.com".to_string(),
+                common_name: Some("Person 0".to_string()),
+                status: Some("ACCEPTED".to_string()),
+            },
+            Attendee {
+                email: "person1@example.com".to_string(),
+                common_name: Some("Person 1".to_string()),
+                status: Some("DECLINED".to_string()),
+            },
+        ]
+    );
+
+    Ok(())
+}
+
+/// Test that we only consider nobody to be attending if there are attendees
+/// and none have accepted.
+#[test]
+fn test_nobody_attending() -> Result<(), Error> {
+    let reminder = reminder_for(&ics_with_attendees(&["DECLINED", "DECLINED"]))?;
+    assert!(reminder.nobody_attending());
+
+    let reminder = reminder_for(&ics_with_attendees(&["DECLINED", "NEEDS-ACTION"]))?;
+    assert!(reminder.nobody_attending());
+
+    let reminder = reminder_for(&ics_with_attendees(&["DECLINED", "TENTATIVE"]))?;
+    assert!(!reminder.nobody_attending());
+
+    let reminder = reminder_for(&ics_with_attendees(&[]))?;
+    assert!(!reminder.nobody_attending());
+
+    Ok(())
+}
+
+/// Test that tentative and non-responding attendees are only left out of the
+/// mentions if the reminder asks for it.
+#[test]
+fn test_attendees_to_mention() -> Result<(), Error> {
+    let statuses = ["ACCEPTED", "TENTATIVE", "NEEDS-ACTION"];
+    let mut reminder = reminder_for(&ics_with_attendees(&statuses))?;
+
+    let mentioned_statuses = |reminder: &ReminderInstance| {
+        reminder
+            .attendees_to_mention()
+            .into_iter()
+            .filter_map(|attendee| attendee.status)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(mentioned_statuses(&reminder), statuses);
+
+    reminder.exclude_tentative = true;
+    assert_eq!(mentioned_statuses(&reminder), ["ACCEPTED", "NEEDS-ACTION"]);
+
+    reminder.exclude_needs_action = true;
+    assert_eq!(mentioned_statuses(&reminder), ["ACCEPTED"]);
+
+    reminder.exclude_tentative = false;
+    assert_eq!(mentioned_statuses(&reminder), ["ACCEPTED", "TENTATIVE"]);
+
+    Ok(())
+}
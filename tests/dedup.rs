@@ -0,0 +1,59 @@
+use calendar_bot::{app::dedup_reminders, database::ReminderInstance};
+use chrono::{DateTime, TimeZone, Utc};
+
+fn reminder(reminder_id: i64, event_id: &str, room: &str, minutes_before: i64) -> ReminderInstance {
+    let timestamp: DateTime<Utc> = Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap();
+
+    ReminderInstance {
+        reminder_id,
+        calendar_id: reminder_id,
+        event_id: event_id.to_string(),
+        timestamp,
+        summary: Some("Meeting".to_string()),
+        description: None,
+        location: None,
+        conference_url: None,
+        template: None,
+        locale: None,
+        minutes_before,
+        room: room.to_string(),
+        attendees: Vec::new(),
+        extra_attendees: Vec::new(),
+        excluded_attendees: Vec::new(),
+        threaded: false,
+        poll: false,
+        msgtype: None,
+        sender: None,
+        snoozed_until: None,
+        redact_previous: false,
+        attach_ics: false,
+        holiday_region: None,
+        skip_if_declined: false,
+        exclude_tentative: false,
+        exclude_needs_action: false,
+        live_countdown: false,
+        last_occurrence: false,
+        adhoc: false,
+        duplicate_reminder_ids: Vec::new(),
+    }
+}
+
+/// Test that reminders for the same event, room and time are collapsed into
+/// one, and that the others are left alone.
+#[test]
+fn test_dedup_reminders() {
+    let reminders = dedup_reminders(vec![
+        reminder(1, "standup", "!room:example.com", 5),
+        reminder(2, "standup", "!room:example.com", 5),
+        reminder(3, "standup", "!other:example.com", 5),
+        reminder(4, "standup", "!room:example.com", 10),
+        reminder(5, "retro", "!room:example.com", 5),
+        reminder(6, "standup", "!room:example.com", 5),
+    ]);
+
+    let ids: Vec<_> = reminders.iter().map(|r| r.reminder_id).collect();
+    assert_eq!(ids, vec![1, 3, 4, 5]);
+
+    assert_eq!(reminders[0].duplicate_reminder_ids, vec![2, 6]);
+    assert!(reminders[1].duplicate_reminder_ids.is_empty());
+}
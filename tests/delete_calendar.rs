@@ -0,0 +1,88 @@
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app, create_user_and_login};
+
+/// Test that deleting a calendar can be undone.
+#[test_log::test(actix_web::test)]
+async fn test_delete_and_restore_calendar() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/calendar/{calendar_id}/delete"))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    // The calendar should now be hidden, but restorable.
+    assert!(app.database.get_calendar(calendar_id).await?.is_none());
+    let deleted = app.database.get_deleted_calendars_for_user(user_id).await?;
+    assert_eq!(deleted.len(), 1);
+    assert_eq!(deleted[0].calendar_id, calendar_id);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/calendar/{calendar_id}/restore"))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    assert!(app.database.get_calendar(calendar_id).await?.is_some());
+    assert!(app
+        .database
+        .get_deleted_calendars_for_user(user_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+/// Test that deleted calendars are only purged once the grace period has
+/// passed.
+#[test_log::test(actix_web::test)]
+async fn test_purge_deleted_calendar() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    app.database.delete_calendar(calendar_id).await?;
+
+    // Purging with the grace period still running leaves the calendar alone.
+    let (num_calendars, _) = app
+        .database
+        .purge_deleted(Utc::now() - app.deletion_grace_period())
+        .await?;
+    assert_eq!(num_calendars, 0);
+    assert_eq!(
+        app.database
+            .get_deleted_calendars_for_user(user_id)
+            .await?
+            .len(),
+        1
+    );
+
+    // Once it has passed the calendar can no longer be restored.
+    let (num_calendars, _) = app
+        .database
+        .purge_deleted(Utc::now() + Duration::minutes(1))
+        .await?;
+    assert_eq!(num_calendars, 1);
+    assert!(app
+        .database
+        .get_deleted_calendars_for_user(user_id)
+        .await?
+        .is_empty());
+    assert!(!app.database.restore_calendar(user_id, calendar_id).await?);
+
+    Ok(())
+}
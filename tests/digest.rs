@@ -0,0 +1,85 @@
+use anyhow::Error;
+use calendar_bot::{
+    calendar::{fetch_calendars, SyncWindow},
+    database::{CalendarAuthentication, CalendarKind},
+    digest::DigestChallenge,
+};
+use httptest::{
+    matchers::{all_of, contains, key, matches, not, request},
+    responders::status_code,
+};
+
+/// Test the example from RFC 2617.
+#[test]
+fn test_digest_authorization() -> Result<(), Error> {
+    let challenge = DigestChallenge::parse(
+        r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+    )
+    .expect("digest challenge");
+
+    assert_eq!(challenge.realm, "testrealm@host.com");
+    assert_eq!(challenge.qop, vec!["auth", "auth-int"]);
+
+    let authorization = challenge.authorization(
+        "GET",
+        "/dir/index.html",
+        "Mufasa",
+        "Circle Of Life",
+        "0a4f113b",
+    )?;
+
+    assert!(authorization.starts_with(r#"Digest username="Mufasa""#));
+    assert!(authorization.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+    assert!(authorization.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+
+    assert!(DigestChallenge::parse(r#"Basic realm="test""#).is_none());
+
+    Ok(())
+}
+
+/// Test that we retry requests with digest auth after being challenged.
+#[test_log::test(actix_web::test)]
+async fn test_fetch_with_digest() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("GET", "/calendar.ics"),
+            request::headers(not(contains(key("authorization")))),
+        ])
+        .respond_with(status_code(401).insert_header(
+            "WWW-Authenticate",
+            r#"Digest realm="calendars", qop="auth", nonce="abc""#,
+        )),
+    );
+    server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("GET", "/calendar.ics"),
+            request::headers(contains((
+                "authorization",
+                matches(
+                    r#"^Digest username="bob", realm="calendars", nonce="abc", uri="/calendar.ics""#
+                ),
+            ))),
+        ])
+        .respond_with(
+            status_code(200)
+                .body("BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//test//EN\r\nEND:VCALENDAR\r\n"),
+        ),
+    );
+
+    fetch_calendars(
+        &reqwest::Client::new(),
+        &server.url("/calendar.ics").to_string(),
+        &CalendarAuthentication::Digest {
+            user_name: "bob".to_string(),
+            password: "secret".to_string(),
+        },
+        CalendarKind::Ics,
+        &SyncWindow::default(),
+        None,
+        1024 * 1024,
+    )
+    .await?;
+
+    Ok(())
+}
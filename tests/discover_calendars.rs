@@ -0,0 +1,70 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+const PRINCIPAL_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:"><d:response><d:href>/</d:href><d:propstat><d:prop>
+<d:current-user-principal><d:href>/principals/bob/</d:href></d:current-user-principal>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>
+"#;
+
+const HOME_SET_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:response><d:href>/principals/bob/</d:href><d:propstat><d:prop>
+<c:calendar-home-set><d:href>/calendars/bob/</d:href></c:calendar-home-set>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>
+"#;
+
+const COLLECTIONS_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+<d:response><d:href>/calendars/bob/</d:href><d:propstat><d:prop>
+<d:resourcetype><d:collection /></d:resourcetype>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+<d:response><d:href>/calendars/bob/work/</d:href><d:propstat><d:prop>
+<d:resourcetype><d:collection /><c:calendar /></d:resourcetype><d:displayname>Work</d:displayname>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+<d:response><d:href>/calendars/bob/inbox/</d:href><d:propstat><d:prop>
+<d:resourcetype><d:collection /><c:schedule-inbox /></d:resourcetype><d:displayname>Inbox</d:displayname>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+</d:multistatus>
+"#;
+
+/// Test that we find the calendar collections in a CalDAV account.
+#[test_log::test(actix_web::test)]
+async fn test_discover_calendars() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let caldav_server = httptest::Server::run();
+    caldav_server.expect(
+        httptest::Expectation::matching(request::method_path("PROPFIND", "/"))
+            .respond_with(status_code(207).body(PRINCIPAL_BODY)),
+    );
+    caldav_server.expect(
+        httptest::Expectation::matching(request::method_path("PROPFIND", "/principals/bob/"))
+            .respond_with(status_code(207).body(HOME_SET_BODY)),
+    );
+    caldav_server.expect(
+        httptest::Expectation::matching(request::method_path("PROPFIND", "/calendars/bob/"))
+            .respond_with(status_code(207).body(COLLECTIONS_BODY)),
+    );
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/discover")
+        .cookie(cookie)
+        .set_form([("url", caldav_server.url("/").to_string())])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Work"));
+    assert!(body.contains(&caldav_server.url("/calendars/bob/work/").to_string()));
+    assert!(!body.contains("Inbox"));
+
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use anyhow::{Context, Error};
+use calendar_bot::database::{CalendarKind, Reminder};
+use chrono::{Duration, Utc};
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::{create_actix_app, test_event, test_instance, test_reminder};
+
+/// A feed where the old event has been replaced by one with a new UID.
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:new-event\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T110000Z\r
+DTEND:20211124T113000Z\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+/// Add a calendar containing an event with a reminder, then update it from a
+/// feed where that event has been replaced, returning the reminders on the
+/// new event.
+async fn update_replaced_event(port_duplicate_reminders: bool) -> Result<Vec<Reminder>, Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "feed".to_string(),
+            server.url("/feed.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    app.database
+        .set_calendar_port_duplicate_reminders(calendar_id, port_duplicate_reminders)
+        .await?;
+
+    let event = test_event(calendar_id, "old-event");
+    let instance = test_instance("old-event", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    app.database
+        .add_reminder(&test_reminder(user_id, calendar_id, "old-event"))
+        .await?;
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .respond_with(status_code(200).body(ICS)),
+    );
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    assert_eq!(calendar.port_duplicate_reminders, port_duplicate_reminders);
+
+    app.update_calendar(calendar).await?;
+
+    app.database
+        .get_reminders_for_event(calendar_id, "new-event")
+        .await
+}
+
+/// Test that reminders are copied onto events that replace an old one.
+#[test_log::test(actix_web::test)]
+async fn test_port_duplicate_reminders() -> Result<(), Error> {
+    let reminders = update_replaced_event(true).await?;
+    assert_eq!(reminders.len(), 1);
+
+    Ok(())
+}
+
+/// Test that reminders aren't copied if the calendar has turned that off.
+#[test_log::test(actix_web::test)]
+async fn test_port_duplicate_reminders_disabled() -> Result<(), Error> {
+    let reminders = update_replaced_event(false).await?;
+    assert!(reminders.is_empty());
+
+    Ok(())
+}
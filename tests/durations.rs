@@ -0,0 +1,79 @@
+use anyhow::Error;
+use calendar_bot::calendar::{
+    parse_calendars_to_events, parse_ics_duration, parse_ics_file, SyncWindow,
+};
+use chrono::Duration;
+
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:with-end\r
+DTSTAMP:20211124T100000Z\r
+DTSTART;TZID=Europe/London:20211124T110000\r
+DTEND;TZID=Europe/London:20211124T113000\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:with-duration\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T140000Z\r
+DURATION:PT1H15M\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Retro\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:folded-end\r
+DTSTAMP:20211124T100000Z\r
+DTSTART;TZID=Europe/London:20211124T110000\r
+DTEND;TZID=Europe/Lon\r
+ don:20211124T120000\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Planning\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:no-end\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T160000Z\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Drinks\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+#[test]
+fn test_parse_ics_duration() {
+    assert_eq!(parse_ics_duration("PT30M"), Some(Duration::minutes(30)));
+    assert_eq!(parse_ics_duration("PT1H30M"), Some(Duration::minutes(90)));
+    assert_eq!(parse_ics_duration("P1DT2H"), Some(Duration::hours(26)));
+    assert_eq!(parse_ics_duration("P2W"), Some(Duration::weeks(2)));
+    assert_eq!(parse_ics_duration("-PT5M"), Some(Duration::minutes(-5)));
+
+    assert_eq!(parse_ics_duration("1H"), None);
+    assert_eq!(parse_ics_duration("PT1D"), None);
+    assert_eq!(parse_ics_duration("PT30"), None);
+}
+
+/// Test that event durations are taken from either `DTEND` or `DURATION`,
+/// including when the line is folded.
+#[test]
+fn test_event_durations() -> Result<(), Error> {
+    let fetched = parse_ics_file(ICS);
+    let (events, _) = parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+
+    let duration = |event_id: &str| {
+        events
+            .iter()
+            .find(|event| event.event_id == event_id)
+            .expect("event")
+            .duration_minutes
+    };
+
+    assert_eq!(duration("with-end"), Some(30));
+    assert_eq!(duration("with-duration"), Some(75));
+    assert_eq!(duration("folded-end"), Some(60));
+    assert_eq!(duration("no-end"), None);
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use anyhow::{Context, Error};
+use calendar_bot::email::render_email;
+use serde_json::json;
+
+pub mod common;
+
+use common::create_actix_app_with_config;
+
+/// Test that emails are rendered from both templates, and built into a
+/// message with plain text and HTML parts.
+#[test_log::test(actix_web::test)]
+async fn test_render_email() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app_with_config(
+        r#"
+        [email]
+        smtp_host = "smtp.example.com"
+        from = "CalBot <calbot@example.com>"
+    "#,
+    )
+    .await?;
+
+    let context = tera::Context::from_serialize(json!({
+        "summary": "Standup",
+        "room": "#standup:example.com",
+        "attempts": 9,
+        "last_error": "Forbidden",
+        "reminders_url": "https://calbot.example.com/reminders",
+    }))?;
+
+    let email = render_email(
+        &app.templates,
+        "reminder_failed",
+        "Failed to send reminder for Standup",
+        &context,
+    )?;
+
+    assert!(email
+        .plain
+        .contains("\"Standup\" to #standup:example.com after 9 attempts"));
+    assert!(email.plain.contains("https://calbot.example.com/reminders"));
+    assert!(!email.plain.contains("<"));
+    assert!(email.html.contains("<strong>Standup</strong>"));
+    assert!(email
+        .html
+        .contains("href=\"https://calbot.example.com/reminders\""));
+
+    let mailer = app.mailer.as_ref().context("missing mailer")?;
+    let message = mailer.build_message("bob@example.com", &email)?;
+    let formatted = String::from_utf8(message.formatted())?;
+
+    assert!(formatted.contains("<calbot@example.com>"));
+    assert!(formatted.contains("To: bob@example.com"));
+    assert!(formatted.contains("Subject: Failed to send reminder for Standup"));
+    assert!(formatted.contains("multipart/alternative"));
+    assert!(formatted.contains("Content-Type: text/plain"));
+    assert!(formatted.contains("Content-Type: text/html"));
+
+    assert!(mailer.build_message("not an email", &email).is_err());
+
+    Ok(())
+}
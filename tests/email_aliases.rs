@@ -0,0 +1,46 @@
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::create_actix_app;
+
+/// Test that verified email aliases map to the Matrix IDs of the user's
+/// primary email, so that people invited under an alias are mentioned.
+#[test_log::test(actix_web::test)]
+async fn test_alias_mappings() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    app.database
+        .set_preferred_matrix_id("bob", "@bob:example.com")
+        .await?;
+    app.database
+        .add_matrix_id("bob", "@bob2:example.com")
+        .await?;
+
+    // Signing in with Google verifies the account's address as an alias.
+    app.database
+        .add_google_oauth_token(
+            user_id,
+            "bob@work.example.com",
+            "access",
+            "refresh",
+            Utc::now() + Duration::hours(1),
+        )
+        .await?;
+
+    let mappings = app.database.get_user_mappings().await?;
+    assert_eq!(
+        mappings.get("bob@work.example.com"),
+        Some(&vec![
+            "@bob:example.com".to_string(),
+            "@bob2:example.com".to_string()
+        ])
+    );
+
+    // Other people's addresses aren't affected.
+    assert_eq!(mappings.len(), 2);
+
+    Ok(())
+}
@@ -0,0 +1,125 @@
+use anyhow::Error;
+use calendar_bot::{
+    app::{detect_event_changes, render_event_change_notice, EventChange},
+    database::Event,
+};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+
+pub mod common;
+
+use common::{test_event, test_instance};
+
+fn event(event_id: &str, location: Option<&str>) -> Event {
+    Event {
+        summary: Some("Planning".to_string()),
+        location: location.map(str::to_string),
+        ..test_event(1, event_id)
+    }
+}
+
+#[test]
+fn test_detect_event_changes() {
+    let now = Utc.with_ymd_and_hms(2024, 1, 15, 9, 0, 0).unwrap();
+    let at = |day, hour| Utc.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap();
+
+    let previous_events = vec![
+        (
+            event("moved", None),
+            vec![test_instance("moved", at(15, 14))],
+        ),
+        (
+            event("relocated", Some("Room 1")),
+            vec![test_instance("relocated", at(15, 14))],
+        ),
+        (
+            event("recurring", None),
+            vec![
+                test_instance("recurring", at(14, 14)),
+                test_instance("recurring", at(15, 14)),
+                test_instance("recurring", at(16, 14)),
+            ],
+        ),
+        (
+            event("skipped", None),
+            vec![
+                test_instance("skipped", at(15, 14)),
+                test_instance("skipped", at(16, 14)),
+            ],
+        ),
+        (
+            event("removed", None),
+            vec![test_instance("removed", at(15, 14))],
+        ),
+    ];
+
+    let events = vec![
+        event("moved", None),
+        event("relocated", Some("Room 2")),
+        event("recurring", None),
+        event("skipped", None),
+    ];
+
+    let next_dates = vec![
+        test_instance("moved", at(15, 15)),
+        test_instance("relocated", at(15, 14)),
+        // The sync window has moved on, which isn't a change.
+        test_instance("recurring", at(15, 14)),
+        test_instance("recurring", at(16, 14)),
+        test_instance("recurring", at(17, 14)),
+        // The next instance was cancelled, not moved.
+        test_instance("skipped", at(16, 14)),
+    ];
+
+    let changes = detect_event_changes(&previous_events, &events, &next_dates, now);
+
+    assert_eq!(changes.len(), 2, "{:?}", changes);
+    assert_eq!(
+        changes["moved"],
+        EventChange {
+            moved: Some((at(15, 14), at(15, 15))),
+            location: None,
+        }
+    );
+    assert_eq!(
+        changes["relocated"],
+        EventChange {
+            moved: None,
+            location: Some(Some("Room 2".to_string())),
+        }
+    );
+}
+
+#[test]
+fn test_render_event_change_notice() -> Result<(), Error> {
+    let old = Utc.with_ymd_and_hms(2024, 1, 15, 14, 0, 0).unwrap();
+
+    let change = EventChange {
+        moved: Some((old, Utc.with_ymd_and_hms(2024, 1, 15, 15, 0, 0).unwrap())),
+        location: None,
+    };
+    assert_eq!(
+        render_event_change_notice(Some("Planning"), &change, Tz::Europe__Berlin)?,
+        "Heads up: **Planning** moved to 16:00 (was 15:00)."
+    );
+
+    let change = EventChange {
+        moved: Some((old, Utc.with_ymd_and_hms(2024, 1, 16, 14, 0, 0).unwrap())),
+        location: Some(Some("Room 2".to_string())),
+    };
+    assert_eq!(
+        render_event_change_notice(Some("Planning"), &change, Tz::UTC)?,
+        "Heads up: **Planning** moved to Tue 16 Jan 14:00 (was Mon 15 Jan 14:00) and is now at Room 2."
+    );
+
+    let change = EventChange {
+        moved: None,
+        location: Some(None),
+    };
+    assert_eq!(
+        render_event_change_notice(None, &change, Tz::UTC)?,
+        "Heads up: **Meeting** no longer has a location."
+    );
+
+    Ok(())
+}
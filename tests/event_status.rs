@@ -0,0 +1,79 @@
+use anyhow::Error;
+use calendar_bot::calendar::{parse_calendars_to_events, parse_ics_file, SyncWindow};
+use chrono::{Duration, TimeZone, Utc};
+
+/// Test that cancelled events and occurrences are skipped, and free events
+/// are only skipped if asked to.
+#[test]
+fn test_cancelled_and_transparent_events() -> Result<(), Error> {
+    let today = Utc::now().date_naive();
+    let start = today - Duration::days(30);
+    let cancelled = today + Duration::days(2);
+
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:busy\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:busy\r
+DTSTAMP:20211124T100000Z\r
+RECURRENCE-ID:{cancelled}T100000Z\r
+DTSTART:{cancelled}T100000Z\r
+DTEND:{cancelled}T103000Z\r
+STATUS:CANCELLED\r
+SUMMARY:Standup\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:free\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T120000Z\r
+DTEND:{start}T123000Z\r
+RRULE:FREQ=DAILY\r
+TRANSP:TRANSPARENT\r
+SUMMARY:Lunch\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:cancelled\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T140000Z\r
+DTEND:{start}T143000Z\r
+RRULE:FREQ=DAILY\r
+STATUS:CANCELLED\r
+SUMMARY:Retro\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = start.format("%Y%m%d"),
+        cancelled = cancelled.format("%Y%m%d"),
+    );
+
+    let fetched = parse_ics_file(&ics);
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+
+    let mut event_ids: Vec<_> = events.iter().map(|e| e.event_id.as_str()).collect();
+    event_ids.sort_unstable();
+    assert_eq!(event_ids, ["busy", "free"]);
+
+    let cancelled_date = Utc.from_utc_datetime(&cancelled.and_hms_opt(10, 0, 0).unwrap());
+    assert!(instances.iter().all(|i| i.date != cancelled_date));
+    assert!(instances.iter().any(|i| i.event_id == "busy"));
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), true)?;
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_id, "busy");
+    assert!(instances.iter().all(|i| i.event_id == "busy"));
+
+    Ok(())
+}
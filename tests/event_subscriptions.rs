@@ -0,0 +1,209 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use calendar_bot::{
+    app::render_new_events_notice,
+    database::{CalendarKind, Event, EventSubscription},
+};
+use chrono::{TimeZone, Utc};
+use chrono_tz::Tz;
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance};
+
+fn event(calendar_id: i64, event_id: &str) -> Event {
+    Event {
+        summary: Some("Planning".to_string()),
+        ..test_event(calendar_id, event_id)
+    }
+}
+
+#[test]
+fn test_render_new_events_notice() {
+    let friday = Utc.with_ymd_and_hms(2024, 1, 19, 14, 0, 0).unwrap();
+    let monday = Utc.with_ymd_and_hms(2024, 1, 22, 9, 30, 0).unwrap();
+
+    assert_eq!(
+        render_new_events_notice(&[(Some("Planning".to_string()), friday)], Tz::UTC),
+        "New event: **Planning** on Friday 19 January 14:00"
+    );
+
+    assert_eq!(
+        render_new_events_notice(
+            &[(None, monday), (Some("Planning".to_string()), friday)],
+            Tz::Europe__Berlin
+        ),
+        "New events:\n\n- **Planning** on Friday 19 January 15:00\n- **Meeting** on Monday 22 January 10:30"
+    );
+}
+
+/// Test that we can tell which events are new, and that subscriptions can be
+/// added and removed.
+#[test_log::test(actix_web::test)]
+async fn test_event_subscriptions() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let other_user_id = app.database.upsert_account("alice").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let instance = |event_id: &str| {
+        test_instance(
+            event_id,
+            Utc.with_ymd_and_hms(2040, 1, 19, 14, 0, 0).unwrap(),
+        )
+    };
+
+    let new_event_ids = app
+        .database
+        .insert_events(
+            calendar_id,
+            vec![event(calendar_id, "event1")],
+            vec![instance("event1")],
+        )
+        .await?;
+    assert_eq!(new_event_ids, vec!["event1".to_string()]);
+
+    // Changing an existing event doesn't make it new.
+    let mut changed_event = event(calendar_id, "event1");
+    changed_event.location = Some("Room 1".to_string());
+
+    let new_event_ids = app
+        .database
+        .insert_events(
+            calendar_id,
+            vec![changed_event, event(calendar_id, "event2")],
+            vec![instance("event1"), instance("event2")],
+        )
+        .await?;
+    assert_eq!(new_event_ids, vec!["event2".to_string()]);
+
+    let subscription_id = app
+        .database
+        .add_event_subscription(&EventSubscription {
+            subscription_id: -1,
+            user_id,
+            calendar_id,
+            room: "#team:example.com".to_string(),
+            summary_pattern: Some("/planning/i".to_string()),
+        })
+        .await?;
+
+    let subscriptions = app
+        .database
+        .get_event_subscriptions_for_calendar(calendar_id)
+        .await?;
+    assert_eq!(subscriptions.len(), 1);
+    assert_eq!(subscriptions[0].room, "#team:example.com");
+
+    assert!(
+        !app.database
+            .delete_event_subscription(other_user_id, subscription_id)
+            .await?
+    );
+    assert!(
+        app.database
+            .delete_event_subscription(user_id, subscription_id)
+            .await?
+    );
+    assert!(app
+        .database
+        .get_event_subscriptions_for_user(user_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
+
+/// Test that the page lists the user's subscriptions, and that they can only
+/// subscribe to their own calendars.
+#[test_log::test(actix_web::test)]
+async fn test_event_subscriptions_page() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+    let other_user_id = app.database.upsert_account("alice").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+    let other_calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            other_user_id,
+            "Private".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    app.database
+        .add_event_subscription(&EventSubscription {
+            subscription_id: -1,
+            user_id,
+            calendar_id,
+            room: "#team:example.com".to_string(),
+            summary_pattern: None,
+        })
+        .await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/subscriptions")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("#team:example.com"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/subscriptions")
+        .cookie(cookie.clone())
+        .set_form([
+            ("calendar_id", other_calendar_id.to_string()),
+            ("room", "#team:example.com".to_string()),
+        ])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/subscriptions")
+        .cookie(cookie)
+        .set_form([
+            ("calendar_id", calendar_id.to_string()),
+            ("room", "#team:example.com".to_string()),
+            ("summary_pattern", "(".to_string()),
+        ])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use actix_web::test::read_body_json;
+use anyhow::{Context, Error};
+use calendar_bot::database::{Attendee, Event, EventInstance};
+use chrono::{Duration, Utc};
+use serde_json::Value;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+};
+
+/// Test that the events API is paginated, ordered by when events next start.
+#[test_log::test(actix_web::test)]
+async fn test_events_api_pagination() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let mut events = Vec::new();
+    let mut instances = Vec::new();
+    for (event_id, days) in [("event-c", 3), ("event-a", 1), ("event-b", 2)] {
+        events.push(Event {
+            summary: Some(event_id.to_string()),
+            ..test_event(calendar_id, event_id)
+        });
+
+        // Only the next instance of recurring events is returned.
+        for extra_days in [0, 7] {
+            instances.push(test_instance(
+                event_id,
+                Utc::now() + Duration::days(days + extra_days),
+            ));
+        }
+    }
+    app.database
+        .insert_events(calendar_id, events, instances)
+        .await?;
+
+    let list_events = |query: &str| {
+        actix_web::test::TestRequest::get()
+            .uri(&format!("/api/v1/events?{}", query))
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    let event_ids = |body: &Value| -> Result<Vec<String>, Error> {
+        body["events"]
+            .as_array()
+            .context("missing events")?
+            .iter()
+            .map(|event| {
+                event["event_id"]
+                    .as_str()
+                    .map(ToOwned::to_owned)
+                    .context("missing event ID")
+            })
+            .collect()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, list_events("limit=2")).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body)?, vec!["event-a", "event-b"]);
+    assert_eq!(body["next_offset"], 2);
+
+    let resp = actix_web::test::call_service(&actix_app, list_events("limit=2&offset=2")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body)?, vec!["event-c"]);
+    assert!(body["next_offset"].is_null());
+
+    let resp = actix_web::test::call_service(&actix_app, list_events("")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body)?, vec!["event-a", "event-b", "event-c"]);
+    assert!(body["next_offset"].is_null());
+
+    Ok(())
+}
+
+/// Test searching events by summary, description and attendee email.
+#[test_log::test(actix_web::test)]
+async fn test_search_events_api() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = |event_id: &str, summary: &str, description: Option<&str>| Event {
+        summary: Some(summary.to_string()),
+        description: description.map(ToOwned::to_owned),
+        ..test_event(calendar_id, event_id)
+    };
+    let instance = |event_id: &str, attendees: Vec<Attendee>| EventInstance {
+        attendees,
+        ..test_instance(event_id, Utc::now() + Duration::days(1))
+    };
+
+    app.database
+        .insert_events(
+            calendar_id,
+            vec![
+                event("standup", "Weekly Standup", None),
+                event("planning", "Planning", Some("Agree 100% of the roadmap")),
+            ],
+            vec![
+                instance("standup", Vec::new()),
+                instance(
+                    "planning",
+                    vec![Attendee {
+                        email: "alice@example.com".to_string(),
+                        common_name: None,
+                        status: None,
+                    }],
+                ),
+            ],
+        )
+        .await?;
+
+    let search = |query: &str| {
+        actix_web::test::TestRequest::get()
+            .uri(&format!("/api/v1/events/search?q={}", query))
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    let event_ids = |body: &Value| -> Vec<String> {
+        body["events"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|event| event["event_id"].as_str().map(ToOwned::to_owned))
+            .collect()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, search("STANDUP")).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body), vec!["standup"]);
+
+    let resp = actix_web::test::call_service(&actix_app, search("alice%40example")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body), vec!["planning"]);
+
+    // Wildcards are matched literally.
+    let resp = actix_web::test::call_service(&actix_app, search("100%25")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(event_ids(&body), vec!["planning"]);
+
+    let resp = actix_web::test::call_service(&actix_app, search("_")).await;
+    let body: Value = read_body_json(resp).await;
+    assert!(event_ids(&body).is_empty());
+
+    let resp = actix_web::test::call_service(&actix_app, search("%20")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
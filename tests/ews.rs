@@ -0,0 +1,100 @@
+use calendar_bot::ews::parse_find_item_response;
+
+const FIND_ITEM_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/">
+<s:Body>
+<m:FindItemResponse xmlns:m="http://schemas.microsoft.com/exchange/services/2006/messages" xmlns:t="http://schemas.microsoft.com/exchange/services/2006/types">
+<m:ResponseMessages>
+<m:FindItemResponseMessage ResponseClass="Success">
+<m:ResponseCode>NoError</m:ResponseCode>
+<m:RootFolder TotalItemsInView="5" IncludesLastItemInRange="true">
+<t:Items>
+<t:CalendarItem>
+<t:Subject>Standup</t:Subject>
+<t:Start>2024-03-01T09:30:00Z</t:Start>
+<t:Location>https://meet.jit.si/standup</t:Location>
+<t:Organizer><t:Mailbox><t:Name>Alice</t:Name><t:EmailAddress>alice@example.com</t:EmailAddress></t:Mailbox></t:Organizer>
+<t:UID>standup-uid</t:UID>
+<t:IsAllDayEvent>false</t:IsAllDayEvent>
+<t:IsCancelled>false</t:IsCancelled>
+</t:CalendarItem>
+<t:CalendarItem>
+<t:Subject>Standup</t:Subject>
+<t:Start>2024-03-02T09:30:00Z</t:Start>
+<t:UID>standup-uid</t:UID>
+<t:IsAllDayEvent>false</t:IsAllDayEvent>
+<t:IsCancelled>false</t:IsCancelled>
+</t:CalendarItem>
+<t:CalendarItem>
+<t:Subject>Holiday</t:Subject>
+<t:Start>2024-03-03T00:00:00Z</t:Start>
+<t:UID>holiday-uid</t:UID>
+<t:IsAllDayEvent>true</t:IsAllDayEvent>
+</t:CalendarItem>
+<t:CalendarItem>
+<t:Subject>Focus time</t:Subject>
+<t:Start>2024-03-05T14:00:00Z</t:Start>
+<t:UID>focus-uid</t:UID>
+<t:IsAllDayEvent>false</t:IsAllDayEvent>
+<t:IsCancelled>false</t:IsCancelled>
+<t:LegacyFreeBusyStatus>Free</t:LegacyFreeBusyStatus>
+</t:CalendarItem>
+<t:CalendarItem>
+<t:Subject>Cancelled</t:Subject>
+<t:Start>2024-03-04T10:00:00Z</t:Start>
+<t:UID>cancelled-uid</t:UID>
+<t:IsCancelled>true</t:IsCancelled>
+</t:CalendarItem>
+</t:Items>
+</m:RootFolder>
+</m:FindItemResponseMessage>
+</m:ResponseMessages>
+</m:FindItemResponse>
+</s:Body>
+</s:Envelope>
+"#;
+
+/// Test that occurrences in an EWS `FindItem` response are mapped to events
+/// and instances.
+#[test]
+fn test_parse_find_item_response() {
+    let (events, instances) = parse_find_item_response(1, FIND_ITEM_BODY, true).unwrap();
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_id, "standup-uid");
+    assert_eq!(events[0].summary.as_deref(), Some("Standup"));
+    assert_eq!(
+        events[0].conference_url.as_deref(),
+        Some("https://meet.jit.si/standup")
+    );
+    assert_eq!(
+        events[0].organizer.as_ref().map(|o| o.email.as_str()),
+        Some("alice@example.com")
+    );
+
+    assert_eq!(instances.len(), 2);
+    assert!(instances.iter().all(|i| i.event_id == "standup-uid"));
+}
+
+/// Test that free occurrences are only kept if we're not ignoring them.
+#[test]
+fn test_parse_find_item_response_free() {
+    let (events, instances) = parse_find_item_response(1, FIND_ITEM_BODY, false).unwrap();
+
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].event_id, "focus-uid");
+    assert_eq!(instances.len(), 3);
+}
+
+/// Test that EWS errors are surfaced.
+#[test]
+fn test_parse_find_item_error() {
+    let body = r#"<?xml version="1.0" encoding="utf-8"?>
+<s:Envelope xmlns:s="http://schemas.xmlsoap.org/soap/envelope/"><s:Body>
+<m:FindItemResponse xmlns:m="http://schemas.microsoft.com/exchange/services/2006/messages"><m:ResponseMessages>
+<m:FindItemResponseMessage ResponseClass="Error"><m:MessageText>Access is denied.</m:MessageText></m:FindItemResponseMessage>
+</m:ResponseMessages></m:FindItemResponse></s:Body></s:Envelope>"#;
+
+    let err = parse_find_item_response(1, body, false).unwrap_err();
+    assert!(err.to_string().contains("Access is denied."));
+}
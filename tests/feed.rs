@@ -0,0 +1,93 @@
+use actix_web::test::read_body;
+use anyhow::{Context, Error};
+use calendar_bot::database::Reminder;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test that the feed URL serves the events the user has reminders for, and
+/// that changing the URL stops the old one working.
+#[test_log::test(actix_web::test)]
+async fn test_feed() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    app.database
+        .add_reminder(&Reminder {
+            minutes_before: 0,
+            room: "#standup:example.com".to_string(),
+            ..app
+                .database
+                .get_reminder_in_calendar(calendar_id, reminder_id)
+                .await?
+                .context("missing reminder")?
+        })
+        .await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/feed/reset")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    let token = app
+        .database
+        .get_feed_token(user_id)
+        .await?
+        .context("missing feed token")?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/feed")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains(&format!("/feed/{}.ics", token)));
+
+    let get_feed = |token: &str| {
+        actix_web::test::TestRequest::get()
+            .uri(&format!("/feed/{}.ics", token))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, get_feed(&token)).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert_eq!(body.matches("BEGIN:VEVENT").count(), 1);
+    assert!(body.contains("SUMMARY:Standup"));
+    assert!(body.contains("COMMENT:Reminder 5 minutes before in !room:example.com"));
+    assert!(body.contains("COMMENT:Reminder 0 minutes before in #standup:example.com"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/feed/reset")
+        .cookie(cookie.clone())
+        .to_request();
+    actix_web::test::call_service(&actix_app, req).await;
+
+    let resp = actix_web::test::call_service(&actix_app, get_feed(&token)).await;
+    assert_eq!(resp.status().as_u16(), 404);
+
+    Ok(())
+}
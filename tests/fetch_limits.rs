@@ -0,0 +1,73 @@
+use anyhow::Error;
+use calendar_bot::{
+    calendar::{discover_calendars, fetch_calendars, SyncWindow},
+    database::{CalendarAuthentication, CalendarKind},
+};
+use httptest::{matchers::request, responders::status_code, Expectation};
+
+const SMALL_CALENDAR: &str =
+    "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//test//EN\r\nEND:VCALENDAR\r\n";
+
+/// Test that we refuse to read calendars larger than the configured limit.
+#[test_log::test(actix_web::test)]
+async fn test_fetch_response_too_large() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("GET", "/calendar.ics"))
+            .times(2)
+            .respond_with(status_code(200).body(SMALL_CALENDAR)),
+    );
+
+    let url = server.url("/calendar.ics").to_string();
+
+    let result = fetch_calendars(
+        &reqwest::Client::new(),
+        &url,
+        &CalendarAuthentication::None,
+        CalendarKind::Ics,
+        &SyncWindow::default(),
+        None,
+        16,
+    )
+    .await;
+
+    let error = result.err().expect("fetch should fail");
+    assert!(format!("{:#}", error).contains("too large"), "{:#}", error);
+
+    // The same calendar is fine with a higher limit.
+    fetch_calendars(
+        &reqwest::Client::new(),
+        &url,
+        &CalendarAuthentication::None,
+        CalendarKind::Ics,
+        &SyncWindow::default(),
+        None,
+        1024,
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Test that calendar discovery also refuses responses larger than the limit.
+#[test_log::test(actix_web::test)]
+async fn test_discover_response_too_large() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    server.expect(
+        Expectation::matching(request::method_path("PROPFIND", "/"))
+            .respond_with(status_code(207).body("<d:multistatus xmlns:d=\"DAV:\" />")),
+    );
+
+    let result = discover_calendars(
+        &reqwest::Client::new(),
+        &server.url("/").to_string(),
+        &CalendarAuthentication::None,
+        16,
+    )
+    .await;
+
+    let error = result.err().expect("discovery should fail");
+    assert!(format!("{:#}", error).contains("too large"), "{:#}", error);
+
+    Ok(())
+}
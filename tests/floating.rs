@@ -0,0 +1,42 @@
+use anyhow::Error;
+use calendar_bot::calendar::{parse_calendars_to_events, parse_ics_file, SyncWindow};
+use chrono::Timelike;
+use chrono_tz::Europe::London;
+
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:floating\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T100000\r
+DTEND:20211124T103000\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+/// Test that floating events are only used if the calendar has a timezone,
+/// and are resolved against it.
+#[test]
+fn test_floating_events() -> Result<(), Error> {
+    let fetched = parse_ics_file(ICS);
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+    assert!(events.is_empty());
+    assert!(instances.is_empty());
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, Some(London), &SyncWindow::default(), false)?;
+    assert_eq!(events.len(), 1);
+    assert!(!instances.is_empty());
+
+    for instance in instances {
+        let local = instance.date.with_timezone(&London);
+        assert_eq!((local.hour(), local.minute()), (10, 0));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,103 @@
+use anyhow::Error;
+use calendar_bot::{
+    calendar::SyncWindow, database::CalendarAuthentication, google::fetch_google_events,
+};
+use httptest::{
+    matchers::{all_of, contains, key, not, request, url_decoded},
+    responders::json_encoded,
+};
+use serde_json::json;
+
+/// Test that events from the Google Calendar API are mapped to events and
+/// instances, following pagination.
+#[test_log::test(actix_web::test)]
+async fn test_fetch_google_events() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("GET", "/calendars/primary/events"),
+            request::headers(contains(("authorization", "Bearer token"))),
+            request::query(url_decoded(contains(("singleEvents", "true")))),
+            request::query(url_decoded(contains(("pageToken", "page2")))),
+        ])
+        .respond_with(json_encoded(json!({
+            "items": [
+                {
+                    "iCalUID": "standup-uid",
+                    "status": "confirmed",
+                    "summary": "Standup",
+                    "start": { "dateTime": "2024-03-02T09:30:00Z" },
+                }
+            ]
+        }))),
+    );
+    server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("GET", "/calendars/primary/events"),
+            request::query(url_decoded(contains(("singleEvents", "true")))),
+            request::query(url_decoded(not(contains(key("pageToken"))))),
+        ])
+        .respond_with(json_encoded(json!({
+            "items": [
+                {
+                    "iCalUID": "standup-uid",
+                    "status": "confirmed",
+                    "summary": "Standup",
+                    "start": { "dateTime": "2024-03-01T09:30:00Z" },
+                    "hangoutLink": "https://meet.google.com/abc-defg-hij",
+                    "organizer": { "email": "alice@example.com", "displayName": "Alice" },
+                    "attendees": [
+                        { "email": "alice@example.com", "responseStatus": "accepted" },
+                        { "email": "bob@example.com", "responseStatus": "declined" },
+                    ],
+                },
+                {
+                    "iCalUID": "holiday-uid",
+                    "status": "confirmed",
+                    "summary": "Holiday",
+                    "start": { "date": "2024-03-03" },
+                },
+                {
+                    "iCalUID": "focus-uid",
+                    "status": "confirmed",
+                    "transparency": "transparent",
+                    "summary": "Focus time",
+                    "start": { "dateTime": "2024-03-05T14:00:00Z" },
+                },
+                {
+                    "iCalUID": "cancelled-uid",
+                    "status": "cancelled",
+                    "start": { "dateTime": "2024-03-04T10:00:00Z" },
+                },
+            ],
+            "nextPageToken": "page2",
+        }))),
+    );
+
+    let (events, instances) = fetch_google_events(
+        &reqwest::Client::new(),
+        &server.url("/calendars/primary/events").to_string(),
+        &CalendarAuthentication::Bearer {
+            access_token: "token".to_string(),
+        },
+        1,
+        &SyncWindow::default(),
+        1024 * 1024,
+        true,
+    )
+    .await?;
+
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event_id, "standup-uid");
+    assert_eq!(
+        events[0].conference_url.as_deref(),
+        Some("https://meet.google.com/abc-defg-hij")
+    );
+    assert_eq!(events[0].attendees.len(), 2);
+    assert!(events[0].attendees[0].is_attending());
+    assert!(events[0].attendees[1].is_declined());
+
+    assert_eq!(instances.len(), 2);
+
+    Ok(())
+}
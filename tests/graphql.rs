@@ -0,0 +1,123 @@
+use actix_web::test::read_body_json;
+use anyhow::Error;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test querying calendars, events and reminders with nested GraphQL queries.
+#[test_log::test(actix_web::test)]
+async fn test_graphql() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/graphql")
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "query": r#"
+                {
+                    calendars {
+                        calendarId
+                        name
+                        events {
+                            summary
+                            instances { date }
+                            reminders { reminderId room minutesBefore }
+                        }
+                    }
+                    reminders {
+                        reminderId
+                        event { summary }
+                    }
+                }
+            "#,
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: Value = read_body_json(resp).await;
+    assert!(body.get("errors").is_none(), "errors: {}", body["errors"]);
+
+    let calendar = &body["data"]["calendars"][0];
+    assert_eq!(calendar["calendarId"], calendar_id);
+    assert_eq!(calendar["name"], "test calendar");
+    assert_eq!(calendar["events"][0]["summary"], "Standup");
+    assert_eq!(
+        calendar["events"][0]["instances"].as_array().map(Vec::len),
+        Some(1)
+    );
+    assert_eq!(
+        calendar["events"][0]["reminders"][0]["reminderId"],
+        reminder_id
+    );
+    assert_eq!(calendar["events"][0]["reminders"][0]["minutesBefore"], 5);
+
+    let reminder = &body["data"]["reminders"][0];
+    assert_eq!(reminder["reminderId"], reminder_id);
+    assert_eq!(reminder["event"]["summary"], "Standup");
+
+    // Queries need to be authenticated.
+    let req = actix_web::test::TestRequest::post()
+        .uri("/graphql")
+        .set_json(json!({ "query": "{ calendars { name } }" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(!resp.status().is_success(), "status: {}", resp.status());
+
+    Ok(())
+}
+
+/// Test that GraphQL fields check the scopes of the token.
+#[test_log::test(actix_web::test)]
+async fn test_graphql_scopes() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let (_, token) = app
+        .add_api_token(user_id, "dashboard", &["calendars:read".to_string()])
+        .await?;
+
+    let query = |query: &str| {
+        actix_web::test::TestRequest::post()
+            .uri("/graphql")
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .set_json(json!({ "query": query }))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, query("{ calendars { name } }")).await;
+    let body: Value = read_body_json(resp).await;
+    assert!(body.get("errors").is_none(), "errors: {}", body["errors"]);
+
+    let resp =
+        actix_web::test::call_service(&actix_app, query("{ reminders { reminderId } }")).await;
+    let body: Value = read_body_json(resp).await;
+    assert!(body["errors"][0]["message"]
+        .as_str()
+        .unwrap_or_default()
+        .contains("reminders:read"));
+
+    Ok(())
+}
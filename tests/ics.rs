@@ -0,0 +1,87 @@
+use calendar_bot::{
+    calendar::{feed_to_ics, reminder_to_ics},
+    database::{FeedEvent, ReminderInstance},
+};
+use chrono::{TimeZone, Utc};
+
+/// Test that reminders are serialized to a valid, escaped and folded ICS file.
+#[test]
+fn test_reminder_to_ics() {
+    let reminder = ReminderInstance {
+        reminder_id: 1,
+        calendar_id: 1,
+        event_id: "event1".to_string(),
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+        summary: Some("Standup, daily; sometimes".to_string()),
+        description: Some(format!("Agenda:\n{}", "x".repeat(100))),
+        location: None,
+        conference_url: Some("https://meet.jit.si/standup".to_string()),
+        template: None,
+        locale: None,
+        minutes_before: 5,
+        room: "!room:example.com".to_string(),
+        attendees: Vec::new(),
+        extra_attendees: Vec::new(),
+        excluded_attendees: Vec::new(),
+        threaded: false,
+        poll: false,
+        msgtype: None,
+        sender: None,
+        snoozed_until: None,
+        redact_previous: false,
+        attach_ics: true,
+        holiday_region: None,
+        skip_if_declined: false,
+        exclude_tentative: false,
+        exclude_needs_action: false,
+        live_countdown: false,
+        last_occurrence: false,
+        adhoc: false,
+        duplicate_reminder_ids: Vec::new(),
+    };
+
+    let ics = reminder_to_ics(&reminder);
+
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    assert!(ics.contains("\r\nUID:event1-20240301T093000Z\r\n"));
+    assert!(ics.contains("\r\nDTSTART:20240301T093000Z\r\n"));
+    assert!(ics.contains("\r\nSUMMARY:Standup\\, daily\\; sometimes\r\n"));
+    assert!(ics.contains("\r\nURL:https://meet.jit.si/standup\r\n"));
+    assert!(!ics.contains("LOCATION:"));
+
+    // Long lines are folded, and newlines escaped.
+    assert!(ics.contains("DESCRIPTION:Agenda:\\nxxx"));
+    for line in ics.split("\r\n") {
+        assert!(line.len() <= 75, "line too long: {}", line);
+    }
+}
+
+/// Test that the feed includes each event's end time and reminders.
+#[test]
+fn test_feed_to_ics() {
+    let event = FeedEvent {
+        calendar_id: 1,
+        event_id: "event1".to_string(),
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 9, 30, 0).unwrap(),
+        summary: Some("Standup".to_string()),
+        description: None,
+        location: Some("Room 1".to_string()),
+        conference_url: None,
+        duration_minutes: Some(15),
+        reminders: vec![
+            (10, "#team:example.com".to_string()),
+            (0, "#standup:example.com".to_string()),
+        ],
+    };
+
+    let ics = feed_to_ics(&[event.clone(), event]);
+
+    assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(ics.ends_with("END:VCALENDAR\r\n"));
+    assert_eq!(ics.matches("BEGIN:VEVENT\r\n").count(), 2);
+    assert!(ics.contains("\r\nDTEND:20240301T094500Z\r\n"));
+    assert!(ics.contains("\r\nLOCATION:Room 1\r\n"));
+    assert!(ics.contains("\r\nCOMMENT:Reminder 10 minutes before in #team:example.com\r\n"));
+    assert!(ics.contains("\r\nCOMMENT:Reminder 0 minutes before in #standup:example.com\r\n"));
+}
@@ -0,0 +1,139 @@
+use anyhow::Error;
+use calendar_bot::database::{Attendee, Event, EventInstance};
+use chrono::{Duration, TimeZone, Utc};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app, test_event, test_instance};
+
+fn attendee(email: &str, status: Option<&str>) -> Attendee {
+    Attendee {
+        email: email.to_string(),
+        common_name: None,
+        status: status.map(str::to_string),
+    }
+}
+
+/// Test that a large batch of events, and their instances, are stored and
+/// updated correctly, including the order of their attendees.
+#[test_log::test(actix_web::test)]
+async fn test_insert_events() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let start = Utc.with_ymd_and_hms(2030, 1, 1, 10, 0, 0).unwrap();
+
+    let mut events = Vec::new();
+    let mut instances = Vec::new();
+    for i in 0..1000 {
+        // Give every other event some attendees, to check they're matched up
+        // with the right event.
+        let attendees = if i % 2 == 0 {
+            vec![
+                attendee(&format!("zed{i}@example.com"), Some("ACCEPTED")),
+                attendee(&format!("amy{i}@example.com"), None),
+            ]
+        } else {
+            Vec::new()
+        };
+
+        events.push(Event {
+            summary: Some(format!("Event {i}")),
+            location: (i % 3 == 0).then(|| "Room 1".to_string()),
+            organizer: (i % 5 == 0).then(|| attendee("boss@example.com", None)),
+            attendees: attendees.clone(),
+            duration_minutes: Some(30),
+            ..test_event(calendar_id, &format!("event{i:04}"))
+        });
+        instances.push(EventInstance {
+            attendees,
+            ..test_instance(&format!("event{i:04}"), start + Duration::days(i))
+        });
+    }
+
+    let new_event_ids = app
+        .database
+        .insert_events(calendar_id, events.clone(), instances.clone())
+        .await?;
+    assert_eq!(new_event_ids.len(), 1000);
+
+    let mut stored = app.database.get_events_in_calendar(calendar_id).await?;
+    stored.sort_by(|(a, _), (b, _)| a.event_id.cmp(&b.event_id));
+    assert_eq!(stored.len(), 1000);
+    for ((event, event_instances), (expected, expected_instance)) in
+        stored.iter().zip(events.iter().zip(&instances))
+    {
+        assert_eq!(event, expected);
+        assert_eq!(event_instances, &vec![expected_instance.clone()]);
+    }
+
+    // Change one event and move one instance.
+    events[2].summary = Some("Renamed".to_string());
+    events[2].attendees.pop();
+    instances[3].date = (start - Duration::days(1)).into();
+
+    let new_event_ids = app
+        .database
+        .insert_events(calendar_id, events.clone(), instances.clone())
+        .await?;
+    assert!(new_event_ids.is_empty());
+
+    let (event, _) = app
+        .database
+        .get_event_in_calendar(calendar_id, "event0002")
+        .await?
+        .expect("event");
+    assert_eq!(event, events[2]);
+
+    let (_, event_instances) = app
+        .database
+        .get_event_in_calendar(calendar_id, "event0003")
+        .await?
+        .expect("event");
+    assert_eq!(event_instances, vec![instances[3].clone()]);
+
+    Ok(())
+}
+
+/// Test that events sharing an ID are stored once, keeping the last of them.
+#[test_log::test(actix_web::test)]
+async fn test_insert_duplicate_events() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let start = Utc.with_ymd_and_hms(2030, 1, 1, 10, 0, 0).unwrap();
+
+    let events = vec![
+        test_event(calendar_id, "event1"),
+        test_event(calendar_id, "event2"),
+        Event {
+            summary: Some("Renamed".to_string()),
+            ..test_event(calendar_id, "event1")
+        },
+    ];
+    let instances = vec![
+        test_instance("event1", start),
+        test_instance("event2", start),
+        test_instance("event1", start + Duration::days(1)),
+    ];
+
+    let new_event_ids = app
+        .database
+        .insert_events(calendar_id, events, instances)
+        .await?;
+    assert_eq!(new_event_ids, ["event2", "event1"]);
+
+    let (event, event_instances) = app
+        .database
+        .get_event_in_calendar(calendar_id, "event1")
+        .await?
+        .expect("event");
+    assert_eq!(event.summary.as_deref(), Some("Renamed"));
+    assert_eq!(event_instances.len(), 2);
+
+    Ok(())
+}
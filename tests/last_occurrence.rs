@@ -0,0 +1,173 @@
+use actix_web::test::read_body_json;
+use anyhow::{Context, Error};
+use calendar_bot::{
+    calendar::{decode_calendars, parse_calendars_to_events, SyncWindow},
+    database::{CalendarKind, Event},
+};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance, test_reminder};
+
+/// Test that we only record the last occurrence of recurring events that end
+/// within the sync window.
+#[test]
+fn test_parse_last_occurrence() -> Result<(), Error> {
+    let start = (Utc::now() + Duration::days(1)).format("%Y%m%d");
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:ending\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+RRULE:FREQ=DAILY;COUNT=3\r
+SUMMARY:Sprint\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:forever\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T110000Z\r
+DTEND:{start}T113000Z\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:later\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T120000Z\r
+DTEND:{start}T123000Z\r
+RRULE:FREQ=DAILY;COUNT=1000\r
+SUMMARY:Retro\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = start,
+    );
+
+    let fetched = decode_calendars(std::iter::once(ics.as_str()), None);
+    assert!(fetched.errors.is_empty());
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+
+    let event = |event_id: &str| events.iter().find(|event| event.event_id == event_id);
+
+    let last_date = instances
+        .iter()
+        .filter(|instance| instance.event_id == "ending")
+        .map(|instance| instance.date)
+        .max()
+        .context("missing instances")?;
+
+    let ending = event("ending").context("missing event")?;
+    assert_eq!(ending.last_occurrence, Some(last_date.into()));
+
+    let forever = event("forever").context("missing event")?;
+    assert_eq!(forever.last_occurrence, None);
+
+    let later = event("later").context("missing event")?;
+    assert_eq!(later.last_occurrence, None);
+
+    Ok(())
+}
+
+/// Test that reminders for the last occurrence say so.
+#[test_log::test(actix_web::test)]
+async fn test_last_occurrence_reminder() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let first = (Utc::now() + Duration::days(1))
+        .duration_trunc(Duration::seconds(1))
+        .context("truncating")?;
+    let last = first + Duration::days(1);
+
+    let event = Event {
+        summary: Some("Sprint".to_string()),
+        last_occurrence: Some(last),
+        ..test_event(calendar_id, "event1")
+    };
+    let instance = |date: DateTime<Utc>| test_instance("event1", date);
+    app.database
+        .insert_events(
+            calendar_id,
+            vec![event.clone()],
+            vec![instance(first), instance(last)],
+        )
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    let instance = app
+        .database
+        .get_reminder_instance(reminder_id, first)
+        .await?
+        .context("missing reminder instance")?;
+    assert!(!instance.last_occurrence);
+
+    let instance = app
+        .database
+        .get_reminder_instance(reminder_id, last)
+        .await?
+        .context("missing reminder instance")?;
+    assert!(instance.last_occurrence);
+
+    let preview = || {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+            .cookie(cookie.clone())
+            .set_json(json!({
+                "minutes_before": 5,
+                "room": "!room:example.com",
+            }))
+            .to_request()
+    };
+
+    // The preview uses the next instance, which isn't the last one.
+    let resp = actix_web::test::call_service(&actix_app, preview()).await;
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert!(
+        !markdown.contains("This is the last occurrence."),
+        "{}",
+        markdown
+    );
+
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance(last)])
+        .await?;
+
+    let resp = actix_web::test::call_service(&actix_app, preview()).await;
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert!(
+        markdown.contains("This is the last occurrence."),
+        "{}",
+        markdown
+    );
+
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use anyhow::Error;
+use calendar_bot::{app::countdown_minutes, database::Reminder};
+use chrono::{Duration, TimeZone, Utc};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app, test_event, test_instance, test_reminder};
+
+#[test]
+fn test_countdown_minutes() {
+    let start = Utc.with_ymd_and_hms(2024, 1, 19, 14, 0, 0).unwrap();
+
+    assert_eq!(countdown_minutes(start, start - Duration::minutes(5)), 5);
+    assert_eq!(countdown_minutes(start, start - Duration::seconds(241)), 5);
+    assert_eq!(countdown_minutes(start, start - Duration::seconds(1)), 1);
+    assert_eq!(countdown_minutes(start, start), 0);
+    assert_eq!(countdown_minutes(start, start + Duration::minutes(1)), 0);
+}
+
+/// Test that the live countdown setting is stored and queued up with the
+/// reminder.
+#[test_log::test(actix_web::test)]
+async fn test_live_countdown_setting() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    app.database
+        .add_reminder(&Reminder {
+            live_countdown: true,
+            ..test_reminder(user_id, calendar_id, "event1")
+        })
+        .await?;
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "event1")
+        .await?;
+    assert!(reminders[0].live_countdown);
+
+    let next_reminders = app.database.get_next_reminders().await?;
+    assert_eq!(next_reminders.len(), 1);
+    assert!(next_reminders[0].1.live_countdown);
+
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use actix_web::test::read_body_json;
+use anyhow::{Context, Error};
+use calendar_bot::{
+    database::CalendarKind,
+    locale::{default_locale, get_locale},
+    template_helpers::reminder_handlebars,
+};
+use chrono::{Duration, Utc};
+use chrono_tz::Tz;
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance};
+
+#[test]
+fn test_format_duration() -> Result<(), Error> {
+    let german = get_locale("de").context("missing locale")?;
+
+    assert_eq!(
+        default_locale().format_duration(90),
+        "1 hour and 30 minutes"
+    );
+    assert_eq!(german.format_duration(90), "1 Stunde und 30 Minuten");
+    assert_eq!(german.format_duration(5), "5 Minuten");
+    assert_eq!(
+        german.format_duration(1440 + 60 + 1),
+        "1 Tag, 1 Stunde und 1 Minute"
+    );
+
+    assert!(get_locale("xx").is_none());
+
+    Ok(())
+}
+
+/// Test that the helpers use the locale's names and phrases.
+#[test]
+fn test_localized_helpers() -> Result<(), Error> {
+    let french = get_locale("fr").context("missing locale")?;
+    let handlebars = reminder_handlebars(Tz::Europe__Paris, french, None);
+
+    let context = json!({
+        "start": "2024-07-01T09:30:00Z",
+        "minutes_before": 60,
+    });
+
+    let render = |template: &str| handlebars.render_template(template, &context);
+
+    assert_eq!(
+        render(r#"{{format_time start "%A %-d %B %H:%M"}}"#)?,
+        "lundi 1 juillet 11:30"
+    );
+    assert_eq!(render(r#"{{format_time start "%%A"}}"#)?, "%A");
+    assert_eq!(render("{{humanize_duration minutes_before}}")?, "1 heure");
+    assert_eq!(render(r#"{{t "starts_in"}}"#)?, "commence dans");
+
+    assert!(render(r#"{{t "unknown"}}"#).is_err());
+
+    Ok(())
+}
+
+/// Test that the default template is rendered in the room's language, unless
+/// the reminder picks its own.
+#[test_log::test(actix_web::test)]
+async fn test_room_locale() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23standup:example.com/locale")
+        .cookie(cookie.clone())
+        .set_json(json!({ "locale": "xx" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23standup:example.com/locale")
+        .cookie(cookie.clone())
+        .set_json(json!({ "locale": "de" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let preview = |locale: Option<&str>| {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+            .cookie(cookie.clone())
+            .set_json(json!({
+                "locale": locale,
+                "minutes_before": 5,
+                "room": "#standup:example.com",
+            }))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, preview(None)).await;
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert!(markdown.contains("beginnt in 5 Minuten"), "{}", markdown);
+
+    let resp = actix_web::test::call_service(&actix_app, preview(Some("es"))).await;
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert!(markdown.contains("empieza en 5 minutos"), "{}", markdown);
+
+    app.database
+        .delete_room_locale("#standup:example.com")
+        .await?;
+
+    let resp = actix_web::test::call_service(&actix_app, preview(None)).await;
+    let body: Value = read_body_json(resp).await;
+    let markdown = body["markdown"].as_str().context("missing markdown")?;
+    assert!(markdown.contains("starts in 5 minutes"), "{}", markdown);
+
+    Ok(())
+}
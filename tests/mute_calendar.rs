@@ -0,0 +1,91 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test that a muted calendar's reminders aren't queued up to be sent during
+/// the muted period, and are again once unmuted.
+#[test_log::test(actix_web::test)]
+async fn test_mute_calendar() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    app.database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    assert_eq!(app.database.get_next_reminders().await?.len(), 1);
+
+    // Muting a period that the reminder isn't in doesn't affect it.
+    app.database
+        .set_calendar_muted(
+            calendar_id,
+            Some(Utc::now() + Duration::days(2)),
+            Some(Utc::now() + Duration::days(3)),
+        )
+        .await?;
+    assert_eq!(app.database.get_next_reminders().await?.len(), 1);
+
+    let mute = |muted_from: &str, muted_until: &str| {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/calendar/{}/mute", calendar_id))
+            .cookie(cookie.clone())
+            .set_form([
+                ("muted_from", muted_from.to_string()),
+                ("muted_until", muted_until.to_string()),
+                ("timezone", "Europe/London".to_string()),
+            ])
+            .to_request()
+    };
+
+    let until = (Utc::now() + Duration::days(2))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string();
+    let from = (Utc::now() + Duration::days(3))
+        .format("%Y-%m-%dT%H:%M")
+        .to_string();
+
+    // The period has to end after it starts.
+    let resp = actix_web::test::call_service(&actix_app, mute(&from, &until)).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = actix_web::test::call_service(&actix_app, mute("", &until)).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    assert!(app.database.get_next_reminders().await?.is_empty());
+
+    let req = actix_web::test::TestRequest::get()
+        .uri(&format!("/calendar/{}", calendar_id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Reminders from this calendar are muted"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/calendar/{}/unmute", calendar_id))
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    assert_eq!(app.database.get_next_reminders().await?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,113 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use calendar_bot::calendar::nextcloud_dav_url;
+use httptest::{
+    matchers::{all_of, contains, key, request},
+    responders::status_code,
+};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+const PRINCIPAL_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:"><d:response><d:href>/nextcloud/remote.php/dav/</d:href><d:propstat><d:prop>
+<d:current-user-principal><d:href>/nextcloud/remote.php/dav/principals/users/bob/</d:href></d:current-user-principal>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>
+"#;
+
+const HOME_SET_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav"><d:response><d:href>/nextcloud/remote.php/dav/principals/users/bob/</d:href><d:propstat><d:prop>
+<c:calendar-home-set><d:href>/nextcloud/remote.php/dav/calendars/bob/</d:href></c:calendar-home-set>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response></d:multistatus>
+"#;
+
+const COLLECTIONS_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:c="urn:ietf:params:xml:ns:caldav">
+<d:response><d:href>/nextcloud/remote.php/dav/calendars/bob/</d:href><d:propstat><d:prop>
+<d:resourcetype><d:collection /></d:resourcetype>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+<d:response><d:href>/nextcloud/remote.php/dav/calendars/bob/personal/</d:href><d:propstat><d:prop>
+<d:resourcetype><d:collection /><c:calendar /></d:resourcetype><d:displayname>Personal</d:displayname>
+</d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>
+</d:multistatus>
+"#;
+
+#[test]
+fn test_nextcloud_dav_url() -> Result<(), Error> {
+    assert_eq!(
+        nextcloud_dav_url("https://cloud.example.com")?,
+        "https://cloud.example.com/remote.php/dav/"
+    );
+    assert_eq!(
+        nextcloud_dav_url("https://example.com/nextcloud/")?,
+        "https://example.com/nextcloud/remote.php/dav/"
+    );
+    assert_eq!(
+        nextcloud_dav_url("https://cloud.example.com/remote.php/dav/calendars/bob/")?,
+        "https://cloud.example.com/remote.php/dav/calendars/bob/"
+    );
+    assert!(nextcloud_dav_url("not a url").is_err());
+
+    Ok(())
+}
+
+/// Test that we find the calendars in a Nextcloud account from its base URL.
+#[test_log::test(actix_web::test)]
+async fn test_nextcloud_discovery() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let nextcloud_server = httptest::Server::run();
+    nextcloud_server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("PROPFIND", "/nextcloud/remote.php/dav/"),
+            request::headers(contains(key("authorization"))),
+        ])
+        .respond_with(status_code(207).body(PRINCIPAL_BODY)),
+    );
+    nextcloud_server.expect(
+        httptest::Expectation::matching(request::method_path(
+            "PROPFIND",
+            "/nextcloud/remote.php/dav/principals/users/bob/",
+        ))
+        .respond_with(status_code(207).body(HOME_SET_BODY)),
+    );
+    nextcloud_server.expect(
+        httptest::Expectation::matching(request::method_path(
+            "PROPFIND",
+            "/nextcloud/remote.php/dav/calendars/bob/",
+        ))
+        .respond_with(status_code(207).body(COLLECTIONS_BODY)),
+    );
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/calendar/nextcloud")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/nextcloud")
+        .cookie(cookie)
+        .set_form([
+            ("url", nextcloud_server.url("/nextcloud").to_string()),
+            ("user_name", "bob".to_string()),
+            ("password", "app-password".to_string()),
+        ])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Personal"));
+    assert!(body.contains(
+        &nextcloud_server
+            .url("/nextcloud/remote.php/dav/calendars/bob/personal/")
+            .to_string()
+    ));
+
+    Ok(())
+}
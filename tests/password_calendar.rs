@@ -3,7 +3,7 @@ use anyhow::{Context, Error};
 
 pub mod common;
 
-use calendar_bot::site::UpdateCalendarForm;
+use calendar_bot::{database::CalendarKind, site::UpdateCalendarForm};
 use common::{create_actix_app, create_user_and_login, Form};
 use httptest::{matchers::request, responders::status_code};
 use scraper::{Html, Selector};
@@ -70,6 +70,10 @@ async fn test_add_noauth_calendar() -> Result<(), Error> {
 
     // Set up a CALDAV test server
     let mut caldav_server = httptest::Server::run();
+    caldav_server.expect(
+        httptest::Expectation::matching(request::method_path("PROPFIND", "/calendar"))
+            .respond_with(status_code(405)),
+    );
     caldav_server.expect(
         httptest::Expectation::matching(request::method_path("REPORT", "/calendar"))
             .respond_with(status_code(200).body(BODY)),
@@ -79,11 +83,19 @@ async fn test_add_noauth_calendar() -> Result<(), Error> {
     let calendar_form = UpdateCalendarForm {
         name: "test calendar".to_string(),
         url: caldav_server.url("/calendar").to_string(),
+        kind: Some(CalendarKind::CalDav),
         user_name: None,
         password: None,
+        digest_auth: None,
+        timezone: None,
+        ca_certificate: None,
+        port_duplicate_reminders: None,
+        sync_lookahead_days: None,
+        sync_lookback_days: None,
+        ignore_transparent: None,
     };
 
-    let form = Form::from_html(document)?;
+    let form = Form::from_html_with_selector(document, "#calendar-form")?;
     let form_request = form.to_request(&calendar_form)?;
 
     let resp =
@@ -128,3 +140,90 @@ async fn test_add_noauth_calendar() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Test that `webcal://` links are added as ICS feeds over HTTPS, and that
+/// other schemes are rejected.
+#[test_log::test(actix_web::test)]
+async fn test_add_webcal_calendar() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_form = UpdateCalendarForm {
+        name: "holidays".to_string(),
+        url: "webcal://localhost:1/holidays.ics".to_string(),
+        kind: Some(CalendarKind::CalDav),
+        user_name: None,
+        password: None,
+        digest_auth: None,
+        timezone: None,
+        ca_certificate: None,
+        port_duplicate_reminders: None,
+        sync_lookahead_days: None,
+        sync_lookback_days: None,
+        ignore_transparent: None,
+    };
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/new")
+        .cookie(cookie.clone())
+        .set_form(&calendar_form)
+        .to_request();
+    // Nothing is listening, so fetching the calendar fails, but it should
+    // still have been added.
+    actix_web::test::call_service(&actix_app, req).await;
+
+    let calendars = app.database.get_calendars_for_user(user_id).await?;
+    assert_eq!(calendars.len(), 1);
+    assert_eq!(calendars[0].url, "https://localhost:1/holidays.ics");
+    assert_eq!(calendars[0].kind, CalendarKind::Ics);
+
+    let calendar_form = UpdateCalendarForm {
+        url: "ftp://localhost/holidays.ics".to_string(),
+        ..calendar_form
+    };
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/new")
+        .cookie(cookie)
+        .set_form(&calendar_form)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
+
+/// Test that invalid CA certificates are rejected.
+#[test_log::test(actix_web::test)]
+async fn test_add_calendar_invalid_ca_certificate() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let calendar_form = UpdateCalendarForm {
+        name: "internal".to_string(),
+        url: "https://caldav.internal.example.com/".to_string(),
+        kind: Some(CalendarKind::CalDav),
+        user_name: None,
+        password: None,
+        digest_auth: None,
+        timezone: None,
+        ca_certificate: Some("not a certificate".to_string()),
+        port_duplicate_reminders: None,
+        sync_lookahead_days: None,
+        sync_lookback_days: None,
+        ignore_transparent: None,
+    };
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/new")
+        .cookie(cookie)
+        .set_form(&calendar_form)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
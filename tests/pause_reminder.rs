@@ -0,0 +1,66 @@
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test that paused reminders aren't queued up to be sent, and are again once
+/// resumed.
+#[test_log::test(actix_web::test)]
+async fn test_pause_reminder() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    app.database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "event1")
+        .await?;
+    let reminder_id = reminders[0].reminder_id;
+
+    assert_eq!(app.database.get_next_reminders().await?.len(), 1);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/event/{}/event1/pause_reminder", calendar_id))
+        .cookie(cookie.clone())
+        .set_form([("reminder_id", reminder_id.to_string())])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "event1")
+        .await?;
+    assert!(!reminders[0].enabled);
+    assert!(app.database.get_next_reminders().await?.is_empty());
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/event/{}/event1/resume_reminder", calendar_id))
+        .cookie(cookie)
+        .set_form([("reminder_id", reminder_id.to_string())])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    assert_eq!(app.database.get_next_reminders().await?.len(), 1);
+
+    Ok(())
+}
@@ -0,0 +1,87 @@
+use actix_web::test::read_body_json;
+use anyhow::Error;
+use calendar_bot::database::{CalendarKind, Event};
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance};
+
+/// Test that templates can be previewed against the next instance of an
+/// event, and that only the calendar's owner can do so.
+#[test_log::test(actix_web::test)]
+async fn test_preview_reminder() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let event = Event {
+        duration_minutes: Some(15),
+        ..test_event(calendar_id, "event1")
+    };
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "template": "**{{ summary }}** in {{ calendar_name }} for {{ room }}",
+            "minutes_before": 5,
+            "room": "#team:example.com",
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(
+        body["markdown"],
+        "**Standup** in Team for #team:example.com"
+    );
+    assert!(body["html"]
+        .as_str()
+        .unwrap_or_default()
+        .contains("<strong>Standup</strong>"));
+
+    // Template errors are reported back.
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "template": "{{#if summary}}",
+            "minutes_before": 5,
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    // Other users can't preview events in the calendar.
+    let other_cookie = create_user_and_login(&app, "alice").await?;
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+        .cookie(other_cookie)
+        .set_json(json!({ "minutes_before": 5 }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use anyhow::Error;
+use calendar_bot::{
+    config::PublicHolidaysConfig,
+    holidays::{parse_public_holidays, PublicHoliday},
+};
+use chrono::NaiveDate;
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::create_actix_app;
+
+const HOLIDAYS_ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:christmas\r
+DTSTART;VALUE=DATE:20241225\r
+DTEND;VALUE=DATE:20241227\r
+SUMMARY:Christmas and Boxing\r
+  Day\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:new-year\r
+DTSTART;VALUE=DATE:20250101\r
+SUMMARY:New Year\\, again\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+/// Test that holidays are parsed from the feed, with one per day.
+#[test]
+fn test_parse_public_holidays() {
+    let holidays = parse_public_holidays(HOLIDAYS_ICS);
+
+    assert_eq!(
+        holidays,
+        vec![
+            PublicHoliday {
+                date: NaiveDate::from_ymd_opt(2024, 12, 25).unwrap(),
+                name: Some("Christmas and Boxing Day".to_string()),
+            },
+            PublicHoliday {
+                date: NaiveDate::from_ymd_opt(2024, 12, 26).unwrap(),
+                name: Some("Christmas and Boxing Day".to_string()),
+            },
+            PublicHoliday {
+                date: NaiveDate::from_ymd_opt(2025, 1, 1).unwrap(),
+                name: Some("New Year, again".to_string()),
+            },
+        ]
+    );
+}
+
+/// Test that fetched holidays are stored for the region.
+#[test_log::test(actix_web::test)]
+async fn test_update_public_holidays() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let server = httptest::Server::run();
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/holidays.ics"))
+            .respond_with(status_code(200).body(HOLIDAYS_ICS)),
+    );
+
+    app.update_public_holidays(
+        "england",
+        &PublicHolidaysConfig {
+            url: server.url("/holidays.ics").to_string(),
+            timezone: Some("Europe/London".to_string()),
+        },
+    )
+    .await?;
+
+    let christmas = NaiveDate::from_ymd_opt(2024, 12, 25).unwrap();
+    let christmas_eve = NaiveDate::from_ymd_opt(2024, 12, 24).unwrap();
+
+    assert!(app.database.is_public_holiday("england", christmas).await?);
+    assert!(
+        !app.database
+            .is_public_holiday("england", christmas_eve)
+            .await?
+    );
+    assert!(
+        !app.database
+            .is_public_holiday("scotland", christmas)
+            .await?
+    );
+
+    Ok(())
+}
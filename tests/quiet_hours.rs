@@ -0,0 +1,119 @@
+use anyhow::Error;
+use calendar_bot::quiet_hours::{QuietHours, QuietHoursAction};
+use chrono::{TimeZone, Utc};
+use serde_json::json;
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+fn overnight_quiet_hours() -> Result<QuietHours, Error> {
+    Ok(serde_json::from_value(json!({
+        "start": "20:00",
+        "end": "08:00",
+        "weekends": true,
+        "timezone": "Europe/London",
+    }))?)
+}
+
+/// Test that times are quiet overnight and at weekends, in the quiet hours'
+/// timezone.
+#[test]
+fn test_is_quiet() -> Result<(), Error> {
+    let quiet_hours = overnight_quiet_hours()?;
+    assert_eq!(quiet_hours.action, QuietHoursAction::Defer);
+
+    // Wednesday 2024-07-10, during BST.
+    assert!(!quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 10, 12, 0, 0).unwrap()));
+    assert!(!quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 10, 18, 59, 0).unwrap()));
+    assert!(quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 10, 19, 0, 0).unwrap()));
+    assert!(quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 11, 6, 59, 0).unwrap()));
+    assert!(!quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 11, 7, 0, 0).unwrap()));
+
+    // Saturday.
+    assert!(quiet_hours.is_quiet(Utc.with_ymd_and_hms(2024, 7, 13, 12, 0, 0).unwrap()));
+
+    Ok(())
+}
+
+/// Test that reminders in quiet hours are deferred to the next allowed time.
+#[test]
+fn test_next_allowed() -> Result<(), Error> {
+    let quiet_hours = overnight_quiet_hours()?;
+
+    let allowed = Utc.with_ymd_and_hms(2024, 7, 10, 12, 0, 0).unwrap();
+    assert_eq!(quiet_hours.next_allowed(allowed), Some(allowed));
+
+    // Wednesday evening is deferred to Thursday morning.
+    assert_eq!(
+        quiet_hours.next_allowed(Utc.with_ymd_and_hms(2024, 7, 10, 21, 0, 0).unwrap()),
+        Some(Utc.with_ymd_and_hms(2024, 7, 11, 7, 0, 0).unwrap())
+    );
+
+    // Friday evening is deferred to Monday morning.
+    assert_eq!(
+        quiet_hours.next_allowed(Utc.with_ymd_and_hms(2024, 7, 12, 21, 0, 0).unwrap()),
+        Some(Utc.with_ymd_and_hms(2024, 7, 15, 7, 0, 0).unwrap())
+    );
+
+    Ok(())
+}
+
+/// Test that quiet hours need both a start and an end.
+#[test]
+fn test_invalid_quiet_hours() -> Result<(), Error> {
+    let quiet_hours: QuietHours = serde_json::from_value(json!({ "start": "20:00" }))?;
+    assert!(quiet_hours.validate().is_err());
+
+    assert!(serde_json::from_value::<QuietHours>(json!({ "start": "8pm" })).is_err());
+    assert!(serde_json::from_value::<QuietHours>(json!({ "timezone": "Mars/Olympus" })).is_err());
+
+    Ok(())
+}
+
+/// Test setting a room's quiet hours via the API.
+#[test_log::test(actix_web::test)]
+async fn test_room_quiet_hours_api() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    assert_eq!(app.quiet_hours_for_room("#room:example.com").await?, None);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23room:example.com/quiet_hours")
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "start": "20:00",
+            "end": "08:00",
+            "weekends": true,
+            "timezone": "Europe/London",
+            "action": "suppress",
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let quiet_hours = app
+        .quiet_hours_for_room("#room:example.com")
+        .await?
+        .expect("quiet hours");
+    assert_eq!(
+        quiet_hours,
+        QuietHours {
+            action: QuietHoursAction::Suppress,
+            ..overnight_quiet_hours()?
+        }
+    );
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/api/v1/rooms/%23room:example.com/quiet_hours")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert_eq!(app.quiet_hours_for_room("#room:example.com").await?, None);
+
+    Ok(())
+}
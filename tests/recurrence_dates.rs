@@ -0,0 +1,57 @@
+use anyhow::Error;
+use calendar_bot::calendar::{parse_calendars_to_events, parse_ics_file, SyncWindow};
+use chrono::{Duration, TimeZone, Utc};
+
+/// Test that `EXDATE`s remove occurrences of a recurring event and `RDATE`s
+/// add extra ones.
+#[test]
+fn test_exdate_and_rdate() -> Result<(), Error> {
+    let today = Utc::now().date_naive();
+    let start = today - Duration::days(30);
+    let excluded = today + Duration::days(2);
+    let added = today + Duration::days(3);
+
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:standup\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+RRULE:FREQ=DAILY\r
+EXDATE:{excluded}T100000Z\r
+RDATE;TZID=Europe/London:{added}T150000\r
+SUMMARY:Standup\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = start.format("%Y%m%d"),
+        excluded = excluded.format("%Y%m%d"),
+        added = added.format("%Y%m%d"),
+    );
+
+    let fetched = parse_ics_file(&ics);
+    assert_eq!(fetched.recurrence_dates["standup"].excluded.len(), 1);
+    assert_eq!(fetched.recurrence_dates["standup"].added.len(), 1);
+
+    let (events, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+    assert_eq!(events.len(), 1);
+
+    let excluded_date = Utc.from_utc_datetime(&excluded.and_hms_opt(10, 0, 0).unwrap());
+    assert!(instances.iter().all(|i| i.date != excluded_date));
+
+    let added_date = chrono_tz::Europe::London
+        .from_local_datetime(&added.and_hms_opt(15, 0, 0).unwrap())
+        .unwrap();
+    assert_eq!(instances.iter().filter(|i| i.date == added_date).count(), 1);
+
+    // The other daily occurrences are still there.
+    let next_date =
+        Utc.from_utc_datetime(&(today + Duration::days(4)).and_hms_opt(10, 0, 0).unwrap());
+    assert!(instances.iter().any(|i| i.date == next_date));
+
+    Ok(())
+}
@@ -0,0 +1,246 @@
+use actix_web::test::read_body;
+use anyhow::{Context, Error};
+use calendar_bot::{
+    app::App,
+    database::{CalendarKind, Event, Reminder},
+    reminder_export::{ExportedReminder, ReminderExport},
+};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+pub mod common;
+
+use common::{
+    create_actix_app, create_actix_app_with_homeserver, create_user_and_login,
+    expect_joinable_rooms, test_event, test_instance, test_reminder,
+};
+
+/// Add a calendar for the user with the given events.
+async fn add_calendar(
+    app: &App,
+    user_id: i64,
+    url: &str,
+    event_ids: &[&str],
+) -> Result<i64, Error> {
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "test calendar".to_string(),
+            url.to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let mut events = Vec::new();
+    let mut instances = Vec::new();
+    for event_id in event_ids {
+        events.push(Event {
+            summary: Some(event_id.to_string()),
+            ..test_event(calendar_id, event_id)
+        });
+        instances.push(test_instance(event_id, Utc::now() + Duration::days(1)));
+    }
+    app.database
+        .insert_events(calendar_id, events, instances)
+        .await?;
+
+    Ok(calendar_id)
+}
+
+fn reminder(user_id: i64, calendar_id: i64, event_id: &str) -> Reminder {
+    Reminder {
+        template: Some("Time for {{ summary }}".to_string()),
+        extra_attendees: vec!["@alice:example.com".to_string()],
+        threaded: true,
+        ..test_reminder(user_id, calendar_id, event_id)
+    }
+}
+
+/// Test exporting reminders and importing them for a different user, whose
+/// calendars have different IDs.
+#[test_log::test(actix_web::test)]
+async fn test_export_import_reminders() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    expect_joinable_rooms(&server);
+
+    let (app, _db, actix_app) = create_actix_app_with_homeserver(&server.url_str(""), "").await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let bob_user_id = app.database.upsert_account("bob").await?;
+
+    let bob_calendar_id = add_calendar(
+        &app,
+        bob_user_id,
+        "https://caldav.example.com/work",
+        &["standup", "retro"],
+    )
+    .await?;
+
+    app.database
+        .add_reminder(&Reminder {
+            sender: Some("unknown".to_string()),
+            locale: Some("xx".to_string()),
+            ..reminder(bob_user_id, bob_calendar_id, "standup")
+        })
+        .await?;
+    app.database
+        .add_reminder(&reminder(bob_user_id, bob_calendar_id, "retro"))
+        .await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/reminders/export")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let disposition = resp
+        .headers()
+        .get("content-disposition")
+        .context("content-disposition header")?;
+    assert!(disposition.to_str()?.starts_with("attachment"));
+
+    let export: ReminderExport = serde_json::from_slice(&read_body(resp).await)?;
+    assert_eq!(export.version, 1);
+    assert_eq!(export.reminders.len(), 2);
+    assert_eq!(export.reminders[0].event_uid, "standup");
+    assert_eq!(export.reminders[0].summary.as_deref(), Some("standup"));
+
+    // Alice has the standup in two calendars, one of which has the same URL,
+    // but not the retro.
+    let alice_user_id = app.database.upsert_account("alice").await?;
+    add_calendar(
+        &app,
+        alice_user_id,
+        "https://caldav.example.com/other",
+        &["standup"],
+    )
+    .await?;
+    let alice_calendar_id = add_calendar(
+        &app,
+        alice_user_id,
+        "https://caldav.example.com/work",
+        &["standup"],
+    )
+    .await?;
+
+    let result = app.import_reminders(alice_user_id, &export).await?;
+    assert_eq!(result.imported.len(), 1);
+    assert_eq!(result.skipped.len(), 1);
+    assert_eq!(result.skipped[0].event_uid, "retro");
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(alice_calendar_id, "standup")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+    let imported = &reminders[0];
+    assert_eq!(imported.reminder_id, result.imported[0]);
+    assert_eq!(imported.user_id, alice_user_id);
+    assert_eq!(imported.room, "!room:example.com");
+    assert_eq!(imported.minutes_before, 5);
+    assert_eq!(imported.template.as_deref(), Some("Time for {{ summary }}"));
+    assert_eq!(imported.extra_attendees, vec!["@alice:example.com"]);
+    assert!(imported.threaded);
+    // Senders and languages that aren't configured are dropped.
+    assert_eq!(imported.sender, None);
+    assert_eq!(imported.locale, None);
+
+    // Importing again doesn't add duplicates.
+    let result = app.import_reminders(alice_user_id, &export).await?;
+    assert!(result.imported.is_empty());
+    assert_eq!(result.skipped.len(), 2);
+
+    Ok(())
+}
+
+/// Test that imported reminders are checked like ones saved through the
+/// form, and skipped if they're invalid.
+#[test_log::test(actix_web::test)]
+async fn test_import_invalid_reminders() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    expect_joinable_rooms(&server);
+
+    let (app, _db, _actix_app) = create_actix_app_with_homeserver(&server.url_str(""), "").await?;
+
+    let bob_user_id = app.database.upsert_account("bob").await?;
+    let event_ids = ["standup", "retro", "planning"];
+    let calendar_id = add_calendar(
+        &app,
+        bob_user_id,
+        "https://caldav.example.com/work",
+        &event_ids,
+    )
+    .await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("missing calendar")?;
+
+    let exported = |event_id: &str, change: fn(&mut Reminder)| {
+        let mut reminder = reminder(bob_user_id, calendar_id, event_id);
+        change(&mut reminder);
+        ExportedReminder::new(&reminder, &calendar, None)
+    };
+
+    let export = ReminderExport {
+        version: 1,
+        exported_at: Utc::now(),
+        reminders: vec![
+            exported("standup", |r| r.msgtype = Some("m.image".to_string())),
+            exported("retro", |r| r.weekdays = Some(0)),
+            exported("planning", |r| r.room = "room".to_string()),
+        ],
+    };
+
+    let result = app.import_reminders(bob_user_id, &export).await?;
+    assert!(result.imported.is_empty());
+
+    let reasons: Vec<_> = result.skipped.iter().map(|s| s.reason.as_str()).collect();
+    assert_eq!(
+        reasons,
+        vec![
+            "Invalid msgtype",
+            "Pick at least one day to send on",
+            "room is not a room ID or alias, e.g. #room:example.com",
+        ]
+    );
+
+    for event_id in event_ids {
+        let reminders = app
+            .database
+            .get_reminders_for_event(calendar_id, event_id)
+            .await?;
+        assert!(reminders.is_empty());
+    }
+
+    Ok(())
+}
+
+/// Test that exports from an unknown version of the format are rejected.
+#[test_log::test(actix_web::test)]
+async fn test_import_reminders_unknown_version() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/reminders/import")
+        .cookie(cookie)
+        .set_json(json!({
+            "version": 2,
+            "exported_at": Utc::now(),
+            "reminders": [],
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use calendar_bot::database::ReminderImage;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+const BOUNDARY: &str = "XyZzYboundary";
+
+/// Build a multipart form body with the given image name and file.
+fn multipart_body(name: &str, content_type: &str, file: &str) -> String {
+    format!(
+        "--{b}\r\n\
+        Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+        {name}\r\n\
+        --{b}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"logo.png\"\r\n\
+        Content-Type: {content_type}\r\n\r\n\
+        {file}\r\n\
+        --{b}--\r\n",
+        b = BOUNDARY,
+        name = name,
+        content_type = content_type,
+        file = file,
+    )
+}
+
+/// Test that a reminder's images are listed on its page and can be deleted,
+/// and that uploads are checked before being sent to the media repo.
+#[test_log::test(actix_web::test)]
+async fn test_reminder_images() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let reminder_id = app
+        .database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    let reminder_url = format!("/event/{}/event1/reminder/{}", calendar_id, reminder_id);
+
+    let upload = |name: &str, content_type: &str| {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("{}/images", reminder_url))
+            .cookie(cookie.clone())
+            .insert_header((
+                "Content-Type",
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            ))
+            .set_payload(multipart_body(name, content_type, "data"))
+            .to_request()
+    };
+
+    // Names have to be usable in templates.
+    let resp = actix_web::test::call_service(&actix_app, upload("team logo", "image/png")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let resp = actix_web::test::call_service(&actix_app, upload("logo", "text/plain")).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    app.database
+        .add_reminder_image(&ReminderImage {
+            reminder_id,
+            name: "logo".to_string(),
+            content_type: "image/png".to_string(),
+            content_uri: "mxc://example.com/logo".to_string(),
+        })
+        .await?;
+
+    let images = app.database.get_reminder_images(reminder_id).await?;
+    assert_eq!(images.len(), 1);
+    assert_eq!(images[0].content_uri, "mxc://example.com/logo");
+
+    let req = actix_web::test::TestRequest::get()
+        .uri(&reminder_url)
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("(image/png)"), "{}", body);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("{}/images/logo/delete", reminder_url))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    assert!(app
+        .database
+        .get_reminder_images(reminder_id)
+        .await?
+        .is_empty());
+    assert!(
+        !app.database
+            .delete_reminder_image(reminder_id, "logo")
+            .await?
+    );
+
+    Ok(())
+}
@@ -0,0 +1,46 @@
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::create_actix_app;
+
+/// Test that send attempts are recorded in the reminder log, newest first, and
+/// that old entries get purged.
+#[test_log::test(actix_web::test)]
+async fn test_reminder_log() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let timestamp = Utc::now();
+
+    app.database
+        .add_reminder_log_entry(1, timestamp, "!room:example.com", Some("$event"), None)
+        .await?;
+    app.database
+        .add_reminder_log_entry(1, timestamp, "!room:example.com", None, Some("not in room"))
+        .await?;
+    app.database
+        .add_reminder_log_entry(2, timestamp, "!other:example.com", Some("$other"), None)
+        .await?;
+
+    let log = app.database.get_reminder_log(1, 10).await?;
+    assert_eq!(log.len(), 2);
+
+    assert_eq!(log[0].room, "!room:example.com");
+    assert_eq!(log[0].matrix_event_id, None);
+    assert_eq!(log[0].error.as_deref(), Some("not in room"));
+
+    assert_eq!(log[1].matrix_event_id.as_deref(), Some("$event"));
+    assert_eq!(log[1].error, None);
+
+    assert_eq!(app.database.get_reminder_log(1, 1).await?.len(), 1);
+
+    let purged = app
+        .database
+        .delete_old_reminder_log(Utc::now() + Duration::minutes(1))
+        .await?;
+    assert_eq!(purged, 3);
+    assert!(app.database.get_reminder_log(1, 10).await?.is_empty());
+
+    Ok(())
+}
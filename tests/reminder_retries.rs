@@ -0,0 +1,79 @@
+use anyhow::{Context, Error};
+use calendar_bot::app::reminder_retry_backoff;
+use chrono::{Duration, DurationRound, Utc};
+
+pub mod common;
+
+use common::create_actix_app;
+
+#[test]
+fn test_reminder_retry_backoff() {
+    assert_eq!(reminder_retry_backoff(1), Duration::seconds(30));
+    assert_eq!(reminder_retry_backoff(2), Duration::seconds(60));
+    assert_eq!(reminder_retry_backoff(4), Duration::seconds(240));
+
+    // Doesn't overflow for absurd numbers of attempts.
+    assert!(reminder_retry_backoff(1000) > Duration::days(1));
+}
+
+/// Test that failed reminders are retried with backoff until the retry window
+/// passes, after which they're dropped from the queue.
+#[test_log::test(actix_web::test)]
+async fn test_reminder_retries() -> Result<(), Error> {
+    // The test app has no homeserver, so sending reminders always fails.
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let now = Utc::now()
+        .duration_trunc(Duration::seconds(1))
+        .context("truncating")?;
+
+    let remind_at = now + Duration::minutes(5);
+    let reminder_id = app
+        .database
+        .add_adhoc_reminder(user_id, "#team:example.com", "Release today", remind_at)
+        .await?;
+
+    assert_eq!(
+        app.database.get_reminder_owner(reminder_id).await?,
+        Some(user_id)
+    );
+
+    app.database
+        .add_reminder_retry(reminder_id, remind_at, now, "failed")
+        .await?;
+
+    // Queueing the same instance again does nothing.
+    app.database
+        .add_reminder_retry(reminder_id, remind_at, now, "failed again")
+        .await?;
+
+    app.retry_failed_reminders(now).await?;
+
+    let retries = app.database.get_due_reminder_retries(now).await?;
+    assert!(retries.is_empty());
+
+    let later = now + Duration::minutes(5);
+    let retries = app.database.get_due_reminder_retries(later).await?;
+    assert_eq!(retries.len(), 1);
+    assert_eq!(retries[0].attempts, 2);
+    assert_eq!(retries[0].next_attempt_at, now + reminder_retry_backoff(2));
+
+    // Every attempt is recorded in the reminder log.
+    let log = app.database.get_reminder_log(reminder_id, 10).await?;
+    assert_eq!(log.len(), 1);
+    assert!(log[0].error.is_some());
+
+    // Once we're past the retry window we give up.
+    let much_later = now + Duration::hours(1);
+    app.retry_failed_reminders(much_later).await?;
+
+    assert!(app
+        .database
+        .get_due_reminder_retries(much_later)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Error};
+use calendar_bot::{
+    database::{CalendarKind, ReminderRule},
+    rules::compile_summary_pattern,
+};
+use chrono::{Duration, Utc};
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app};
+
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:standup\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T110000Z\r
+DTEND:20211124T113000Z\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Team Standup\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+UID:retro\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:20211124T140000Z\r
+DTEND:20211124T150000Z\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Retro\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+#[test]
+fn test_compile_summary_pattern() -> Result<(), Error> {
+    assert!(compile_summary_pattern("/standup/i")?.is_match("Team Standup"));
+    assert!(!compile_summary_pattern("/standup/")?.is_match("Team Standup"));
+    assert!(compile_summary_pattern("Stand.p")?.is_match("Team Standup"));
+    assert!(compile_summary_pattern("/a/b/i")?.is_match("A/B"));
+
+    assert!(compile_summary_pattern("/standup/q").is_err());
+    assert!(compile_summary_pattern("(").is_err());
+
+    Ok(())
+}
+
+/// Test that syncing a calendar adds reminders to the events matching a rule,
+/// and that deleting the rule removes them.
+#[test_log::test(actix_web::test)]
+async fn test_reminder_rules() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .times(2)
+            .respond_with(status_code(200).body(ICS)),
+    );
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "feed".to_string(),
+            server.url("/feed.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let rule_id = app
+        .database
+        .add_reminder_rule(&ReminderRule {
+            rule_id: -1,
+            user_id,
+            calendar_id: Some(calendar_id),
+            summary_pattern: "/standup/i".to_string(),
+            room: "!room:example.com".to_string(),
+            minutes_before: 5,
+        })
+        .await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+
+    app.update_calendar(calendar.clone()).await?;
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "standup")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+    assert_eq!(reminders[0].room, "!room:example.com");
+    assert_eq!(reminders[0].minutes_before, 5);
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "retro")
+        .await?;
+    assert!(reminders.is_empty());
+
+    // Syncing again shouldn't add a second reminder.
+    app.update_calendar(calendar).await?;
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "standup")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+
+    assert!(app.database.delete_reminder_rule(user_id, rule_id).await?);
+    assert!(!app.database.delete_reminder_rule(user_id, rule_id).await?);
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "standup")
+        .await?;
+    assert!(reminders.is_empty());
+
+    Ok(())
+}
+
+/// Test that purging a deleted calendar also deletes the rules for it, but
+/// not the rules for all calendars.
+#[test_log::test(actix_web::test)]
+async fn test_purge_calendar_with_rule() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    for calendar_id in [Some(calendar_id), None] {
+        app.database
+            .add_reminder_rule(&ReminderRule {
+                rule_id: -1,
+                user_id,
+                calendar_id,
+                summary_pattern: "/standup/i".to_string(),
+                room: "!room:example.com".to_string(),
+                minutes_before: 5,
+            })
+            .await?;
+    }
+
+    app.database.delete_calendar(calendar_id).await?;
+
+    let (num_calendars, _) = app
+        .database
+        .purge_deleted(Utc::now() + Duration::minutes(1))
+        .await?;
+    assert_eq!(num_calendars, 1);
+
+    let rules = app.database.get_reminder_rules_for_user(user_id).await?;
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].calendar_id, None);
+
+    Ok(())
+}
@@ -0,0 +1,110 @@
+use actix_web::test::read_body_json;
+use anyhow::Error;
+use calendar_bot::database::CalendarKind;
+use chrono::{Duration, Utc};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance};
+
+/// Test that a room's default template is used for reminders without their
+/// own template.
+#[test_log::test(actix_web::test)]
+async fn test_room_templates() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23standup:example.com/template")
+        .cookie(cookie.clone())
+        .set_json(json!({ "template": "{{#if }}" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/api/v1/rooms/%23standup:example.com/template")
+        .cookie(cookie.clone())
+        .set_json(json!({ "template": "{{ summary }} now!" }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert_eq!(
+        app.database
+            .get_room_template("#standup:example.com")
+            .await?
+            .as_deref(),
+        Some("{{ summary }} now!")
+    );
+
+    let preview = |template: Option<&str>, room: &str| {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+            .cookie(cookie.clone())
+            .set_json(json!({
+                "template": template,
+                "minutes_before": 5,
+                "room": room,
+            }))
+            .to_request()
+    };
+
+    // The room's template is used if the reminder doesn't have one...
+    let resp =
+        actix_web::test::call_service(&actix_app, preview(None, "#standup:example.com")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["markdown"], "Standup now!");
+
+    // ... but not if it does ...
+    let resp = actix_web::test::call_service(
+        &actix_app,
+        preview(Some("{{ summary }} soon"), "#standup:example.com"),
+    )
+    .await;
+    let body: Value = read_body_json(resp).await;
+    assert_eq!(body["markdown"], "Standup soon");
+
+    // ... and only for that room.
+    let resp = actix_web::test::call_service(&actix_app, preview(None, "#other:example.com")).await;
+    let body: Value = read_body_json(resp).await;
+    assert_ne!(body["markdown"], "Standup now!");
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri("/api/v1/rooms/%23standup:example.com/template")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    assert_eq!(
+        app.database
+            .get_room_template("#standup:example.com")
+            .await?,
+        None
+    );
+
+    Ok(())
+}
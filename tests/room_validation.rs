@@ -0,0 +1,130 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use chrono::{Duration, Utc};
+use httptest::{
+    matchers::{all_of, matches, request},
+    responders::{json_encoded, status_code},
+    Expectation,
+};
+use serde_json::json;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app_with_homeserver, create_user_and_login, test_event,
+    test_instance,
+};
+
+/// Test that saving a reminder checks the bot can join and send messages in
+/// the room.
+#[test_log::test(actix_web::test)]
+async fn test_reminder_room_validation() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    let (app, _db, actix_app) = create_actix_app_with_homeserver(&server.url_str(""), "").await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    app.database
+        .insert_events(
+            calendar_id,
+            vec![test_event(calendar_id, "event1")],
+            vec![test_instance("event1", Utc::now() + Duration::days(1))],
+        )
+        .await?;
+
+    let join = |alias: &str| {
+        Expectation::matching(all_of![
+            request::method("POST"),
+            request::path(matches(&format!(
+                "^/_matrix/client/r0/join/%23{alias}%3Aexample.com$"
+            ))),
+        ])
+        .times(..)
+    };
+    server.expect(join("forbidden").respond_with(status_code(403)));
+    server.expect(join("missing").respond_with(status_code(404)));
+    server
+        .expect(join("muted").respond_with(json_encoded(json!({"room_id": "!muted:example.com"}))));
+    server.expect(join("team").respond_with(json_encoded(json!({"room_id": "!team:example.com"}))));
+
+    // Only moderators can talk in the muted room, but the bot is one in the
+    // team room.
+    server.expect(
+        Expectation::matching(request::path(matches("^/_matrix/client/r0/rooms/%21muted")))
+            .times(..)
+            .respond_with(json_encoded(json!({
+                "users": {"@bot:example.com": 0},
+                "events": {"m.room.message": 50},
+            }))),
+    );
+    server.expect(
+        Expectation::matching(request::path(matches("^/_matrix/client/r0/rooms/%21team")))
+            .times(..)
+            .respond_with(json_encoded(json!({
+                "users": {"@bot:example.com": 50},
+                "events": {"m.room.message": 50},
+            }))),
+    );
+    server.expect(
+        Expectation::matching(request::method_path(
+            "GET",
+            "/_matrix/client/r0/account/whoami",
+        ))
+        .times(..)
+        .respond_with(json_encoded(json!({"user_id": "@bot:example.com"}))),
+    );
+
+    for (room, expected_status, expected_error) in [
+        (
+            "#forbidden:example.com",
+            400,
+            Some("The bot isn't allowed to join #forbidden:example.com. Try inviting it first."),
+        ),
+        (
+            "#missing:example.com",
+            400,
+            Some("Couldn't find the room #missing:example.com."),
+        ),
+        (
+            "#muted:example.com",
+            400,
+            Some("The bot doesn't have permission to send messages in #muted:example.com."),
+        ),
+        ("#team:example.com", 303, None),
+    ] {
+        let req = actix_web::test::TestRequest::post()
+            .uri(&format!("/event/{calendar_id}/event1/reminder"))
+            .cookie(cookie.clone())
+            .set_form([
+                ("room", room),
+                ("minutes_before", "5"),
+                ("weekday_mon", "on"),
+                ("weekday_tue", "on"),
+                ("weekday_wed", "on"),
+                ("weekday_thu", "on"),
+                ("weekday_fri", "on"),
+                ("weekday_sat", "on"),
+                ("weekday_sun", "on"),
+            ])
+            .to_request();
+        let resp = actix_web::test::call_service(&actix_app, req).await;
+        assert_eq!(resp.status().as_u16(), expected_status, "room: {room}");
+
+        let body = String::from_utf8(read_body(resp).await.to_vec())?;
+        if let Some(expected_error) = expected_error {
+            assert_eq!(body, expected_error);
+        }
+    }
+
+    // Only the valid reminder was saved.
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "event1")
+        .await?;
+    assert_eq!(reminders.len(), 1);
+    assert_eq!(reminders[0].room, "#team:example.com");
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use anyhow::{Context, Error};
+use calendar_bot::database::{CalendarKind, Reminder};
+use chrono::{Duration, Utc};
+use serde_json::json;
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login, test_event, test_instance, test_reminder};
+
+/// Test that saved templates can be shared, and that reminders using one
+/// render its text until it's deleted.
+#[test_log::test(actix_web::test)]
+async fn test_saved_templates() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let other_user_id = app.database.upsert_account("alice").await?;
+    let other_cookie = create_user_and_login(&app, "alice").await?;
+
+    let template_id = app
+        .database
+        .add_template(user_id, "Standup", "{{ summary }} now!", false)
+        .await?;
+
+    let templates = app.database.get_templates_for_user(user_id).await?;
+    assert_eq!(templates.len(), 1);
+    assert_eq!(templates[0].name, "Standup");
+
+    // Other users can't see or use it until it's shared.
+    assert!(app
+        .database
+        .get_templates_for_user(other_user_id)
+        .await?
+        .is_empty());
+    assert!(
+        !app.database
+            .update_template(other_user_id, template_id, "Mine", "", true)
+            .await?
+    );
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            other_user_id,
+            "Team".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    let preview = || {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/api/v1/event/{calendar_id}/event1/preview"))
+            .cookie(other_cookie.clone())
+            .set_json(json!({
+                "template_id": template_id,
+                "minutes_before": 5,
+            }))
+            .to_request()
+    };
+
+    let resp = actix_web::test::call_service(&actix_app, preview()).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    assert!(
+        app.database
+            .update_template(user_id, template_id, "Standup", "{{ summary }} now!", true)
+            .await?
+    );
+
+    let templates = app.database.get_templates_for_user(other_user_id).await?;
+    assert_eq!(templates.len(), 1);
+    assert!(templates[0].shared);
+
+    let resp = actix_web::test::call_service(&actix_app, preview()).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    app.database
+        .add_reminder(&Reminder {
+            template: Some("{{ summary }} soon".to_string()),
+            template_id: Some(template_id),
+            ..test_reminder(other_user_id, calendar_id, "event1")
+        })
+        .await?;
+
+    // The saved template takes precedence over the reminder's own...
+    let reminders = app.database.get_next_reminders().await?;
+    let (_, reminder) = reminders.first().context("missing reminder")?;
+    assert_eq!(reminder.template.as_deref(), Some("{{ summary }} now!"));
+
+    // ... only the owner can delete it ...
+    assert!(
+        !app.database
+            .delete_template(other_user_id, template_id)
+            .await?
+    );
+    assert!(app.database.delete_template(user_id, template_id).await?);
+
+    // ... and once it's gone the reminder falls back to its own template.
+    let reminders = app.database.get_next_reminders().await?;
+    let (_, reminder) = reminders.first().context("missing reminder")?;
+    assert_eq!(reminder.template.as_deref(), Some("{{ summary }} soon"));
+
+    let reminders = app
+        .database
+        .get_reminders_for_event(calendar_id, "event1")
+        .await?;
+    assert_eq!(reminders[0].template_id, None);
+
+    Ok(())
+}
+
+/// Test that invalid templates can't be saved.
+#[test_log::test(actix_web::test)]
+async fn test_saved_templates_page() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/templates")
+        .cookie(cookie.clone())
+        .set_form([("name", "Broken"), ("template", "{{#if }}")])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/templates")
+        .cookie(cookie.clone())
+        .set_form([
+            ("name", "Standup"),
+            ("template", "{{ summary }} now!"),
+            ("shared", "on"),
+        ])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 303);
+
+    let templates = app.database.get_templates_for_user(user_id).await?;
+    assert_eq!(templates.len(), 1);
+    assert!(templates[0].shared);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/templates")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    Ok(())
+}
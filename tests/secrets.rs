@@ -0,0 +1,143 @@
+use anyhow::{Context, Error};
+use calendar_bot::{
+    config::EncryptionConfig,
+    database::{CalendarAuthentication, CalendarKind},
+    secrets::Secrets,
+};
+use tokio_postgres::NoTls;
+
+pub mod common;
+
+use common::create_actix_app_with_config;
+
+const KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+const OLD_KEY: &str = "1f1e1d1c1b1a191817161514131211100f0e0d0c0b0a09080706050403020100";
+
+fn secrets(key: &str, previous_keys: &[&str]) -> Result<Secrets, Error> {
+    Secrets::from_config(Some(&EncryptionConfig {
+        key: Some(key.to_string()),
+        key_file: None,
+        previous_keys: previous_keys.iter().map(|key| key.to_string()).collect(),
+    }))
+}
+
+/// Test that secrets can be decrypted with the current or a previous key,
+/// and that plaintext values are passed through.
+#[test]
+fn test_encrypt_decrypt() -> Result<(), Error> {
+    let old = secrets(OLD_KEY, &[])?;
+    let new = secrets(KEY, &[OLD_KEY])?;
+
+    let encrypted = old.encrypt("hunter2")?;
+    assert!(encrypted.starts_with("enc:"));
+    assert!(!encrypted.contains("hunter2"));
+    assert_ne!(encrypted, old.encrypt("hunter2")?);
+
+    assert_eq!(old.decrypt(&encrypted)?, "hunter2");
+    assert_eq!(new.decrypt(&encrypted)?, "hunter2");
+    assert!(old.is_current(&encrypted));
+    assert!(!new.is_current(&encrypted));
+
+    // Values encrypted with a key we don't have can't be read.
+    assert!(secrets(KEY, &[])?.decrypt(&encrypted).is_err());
+
+    assert_eq!(new.decrypt("plaintext")?, "plaintext");
+    assert!(!new.is_current("plaintext"));
+
+    // Without a key nothing is encrypted.
+    let none = Secrets::from_config(None)?;
+    assert_eq!(none.encrypt("hunter2")?, "hunter2");
+
+    assert!(secrets("not hex", &[]).is_err());
+    assert!(secrets("0001", &[]).is_err());
+
+    Ok(())
+}
+
+/// Test that calendar passwords are encrypted in the database, and that
+/// existing plaintext passwords are encrypted by rotating.
+#[test_log::test(actix_web::test)]
+async fn test_calendar_passwords_encrypted() -> Result<(), Error> {
+    let (app, db, _actix_app) = create_actix_app_with_config(&format!(
+        r#"
+        [encryption]
+        key = "{KEY}"
+    "#
+    ))
+    .await?;
+
+    let (client, connection) = tokio_postgres::connect(&db.connection_string(), NoTls).await?;
+    tokio::spawn(connection);
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "test calendar".to_string(),
+            "https://caldav.example.com".to_string(),
+            CalendarKind::CalDav,
+            Some("user".to_string()),
+            Some("hunter2".to_string()),
+            false,
+        )
+        .await?;
+
+    let stored: String = client
+        .query_one(
+            "SELECT password FROM calendar_passwords WHERE calendar_id = $1",
+            &[&calendar_id],
+        )
+        .await?
+        .get(0);
+    assert!(stored.starts_with("enc:"));
+
+    let password = |calendar: calendar_bot::database::Calendar| match calendar.authentication {
+        CalendarAuthentication::Basic { password, .. } => Some(password),
+        _ => None,
+    };
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    assert_eq!(password(calendar).as_deref(), Some("hunter2"));
+
+    // Passwords stored before encryption was turned on still work, and get
+    // encrypted by rotating.
+    client
+        .execute(
+            "UPDATE calendar_passwords SET password = 'hunter3' WHERE calendar_id = $1",
+            &[&calendar_id],
+        )
+        .await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    assert_eq!(password(calendar).as_deref(), Some("hunter3"));
+
+    assert_eq!(app.database.reencrypt_secrets().await?, 1);
+    assert_eq!(app.database.reencrypt_secrets().await?, 0);
+
+    let stored: String = client
+        .query_one(
+            "SELECT password FROM calendar_passwords WHERE calendar_id = $1",
+            &[&calendar_id],
+        )
+        .await?
+        .get(0);
+    assert!(stored.starts_with("enc:"));
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    assert_eq!(password(calendar).as_deref(), Some("hunter3"));
+
+    Ok(())
+}
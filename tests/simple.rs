@@ -3,7 +3,12 @@ use anyhow::Error;
 
 pub mod common;
 
-use common::{create_actix_app, create_user_and_login};
+use common::{create_actix_app, create_actix_app_with_homeserver, create_user_and_login};
+use httptest::{
+    matchers::{all_of, contains, matches, request, url_decoded},
+    responders::json_encoded,
+    Expectation,
+};
 use scraper::Html;
 use tracing::{error, info};
 
@@ -19,6 +24,23 @@ async fn test_start_app() -> Result<(), Error> {
     Ok(())
 }
 
+/// Test that `/version` returns the build info without needing to log in
+#[test_log::test(actix_web::test)]
+async fn test_version() -> Result<(), Error> {
+    let (_app, _db, actix_app) = create_actix_app().await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/version")
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: serde_json::Value = serde_json::from_slice(&read_body(resp).await)?;
+    assert_eq!(body["version"], env!("CARGO_PKG_VERSION"));
+
+    Ok(())
+}
+
 /// Test that calling simple endpoints work with a blank account
 #[test_log::test(actix_web::test)]
 async fn test_endpoints() -> Result<(), Error> {
@@ -29,10 +51,12 @@ async fn test_endpoints() -> Result<(), Error> {
     for path in &[
         "/events",
         "/reminders",
+        "/reminders/move_room",
         "/calendars",
         "/calendar/new",
         "/change_password",
         "/change_matrix_id",
+        "/emails",
     ] {
         let req = actix_web::test::TestRequest::get()
             .uri(path)
@@ -53,3 +77,162 @@ async fn test_endpoints() -> Result<(), Error> {
 
     Ok(())
 }
+
+/// Test that the appservice transaction endpoint isn't available when not
+/// running as an appservice.
+#[test_log::test(actix_web::test)]
+async fn test_appservice_transaction_not_configured() -> Result<(), Error> {
+    let (_app, _db, actix_app) = create_actix_app().await?;
+
+    let req = actix_web::test::TestRequest::put()
+        .uri("/_matrix/app/v1/transactions/1")
+        .set_json(serde_json::json!({ "events": [] }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+
+    assert_eq!(resp.status().as_u16(), 404);
+
+    Ok(())
+}
+
+/// Test that the space rooms API 404s if no space is configured.
+#[test_log::test(actix_web::test)]
+async fn test_space_rooms_not_configured() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/space/rooms")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+
+    assert_eq!(resp.status().as_u16(), 404);
+
+    Ok(())
+}
+
+/// Test that users can opt out of being mentioned in reminders.
+#[test_log::test(actix_web::test)]
+async fn test_disable_mentions() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    assert!(!app.database.get_mentions_disabled(user_id).await?);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/change_matrix_id/mentions")
+        .cookie(cookie.clone())
+        .set_form([("mentions_disabled", "on")])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    assert!(app.database.get_mentions_disabled(user_id).await?);
+
+    // Unchecking the box re-enables mentions.
+    let req = actix_web::test::TestRequest::post()
+        .uri("/change_matrix_id/mentions")
+        .cookie(cookie)
+        .set_form(Vec::<(String, String)>::new())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    assert!(!app.database.get_mentions_disabled(user_id).await?);
+
+    Ok(())
+}
+
+const APPSERVICE_CONFIG: &str = r#"
+    [matrix.appservice]
+    id = "calbot"
+    hs_token = "hs_secret"
+    sender_localpart = "calbot"
+    url = "http://localhost:8080"
+
+    [[matrix.senders]]
+    name = "alerts"
+    user_id = "@calbot_alerts:example.com"
+"#;
+
+/// Test that the appservice checks the `hs_token`, claims its senders in the
+/// registration, and masquerades as them.
+#[test_log::test(actix_web::test)]
+async fn test_appservice() -> Result<(), Error> {
+    let server = httptest::Server::run();
+    let (app, _db, actix_app) =
+        create_actix_app_with_homeserver(&server.url_str(""), APPSERVICE_CONFIG).await?;
+
+    for (token, expected_status) in [(None, 401), (Some("wrong"), 403), (Some("hs_secret"), 200)] {
+        let mut req = actix_web::test::TestRequest::put()
+            .uri("/_matrix/app/v1/transactions/1")
+            .set_json(serde_json::json!({ "events": [] }));
+        if let Some(token) = token {
+            req = req.insert_header(("Authorization", format!("Bearer {token}")));
+        }
+        let resp = actix_web::test::call_service(&actix_app, req.to_request()).await;
+        assert_eq!(resp.status().as_u16(), expected_status, "token: {token:?}");
+    }
+
+    let registration = app
+        .config
+        .matrix
+        .appservice
+        .as_ref()
+        .expect("appservice")
+        .registration(&app.config.matrix);
+    assert!(
+        registration.contains(
+            "  users:\n    - exclusive: true\n      regex: \"^@calbot_alerts:example\\\\.com$\"\n"
+        ),
+        "{registration}"
+    );
+
+    // Every request for the sender masquerades as its user.
+    let masquerading = || {
+        request::query(url_decoded(contains((
+            "user_id",
+            "@calbot_alerts:example.com",
+        ))))
+    };
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("POST", "/_matrix/client/r0/join/%23team%3Aexample.com"),
+            masquerading(),
+        ])
+        .respond_with(json_encoded(
+            serde_json::json!({"room_id": "!team:example.com"}),
+        )),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method("GET"),
+            request::path(matches("^/_matrix/client/r0/rooms/%21team")),
+            masquerading(),
+        ])
+        .respond_with(json_encoded(serde_json::json!({
+            "users": {"@calbot_alerts:example.com": 50},
+            "events": {"m.room.message": 50},
+        }))),
+    );
+    server.expect(
+        Expectation::matching(all_of![
+            request::method_path("GET", "/_matrix/client/r0/account/whoami"),
+            masquerading(),
+        ])
+        .respond_with(json_encoded(
+            serde_json::json!({"user_id": "@calbot_alerts:example.com"}),
+        )),
+    );
+
+    let problem = app
+        .validate_room(Some("alerts"), "#team:example.com")
+        .await?;
+    assert_eq!(problem, None);
+
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use calendar_bot::database::CalendarKind;
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+END:VCALENDAR\r
+";
+
+/// Test that syncing a calendar reports success and failure inline.
+#[test_log::test(actix_web::test)]
+async fn test_sync_calendar_now() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "feed".to_string(),
+            server.url("/feed.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .respond_with(status_code(200).body(ICS)),
+    );
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/calendar/{calendar_id}/sync"))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Calendar synced."));
+    server.verify_and_clear();
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .respond_with(status_code(401)),
+    );
+
+    let req = actix_web::test::TestRequest::post()
+        .uri(&format!("/calendar/{calendar_id}/sync"))
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body = String::from_utf8(read_body(resp).await.to_vec())?;
+    assert!(body.contains("Failed to sync calendar: Got 401 result from ICS feed"));
+
+    Ok(())
+}
+
+/// Test that the calendar can be synced through the API with a personal
+/// access token that has the `calendars:write` scope.
+#[test_log::test(actix_web::test)]
+async fn test_sync_calendar_api() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    let other_user_id = app.database.upsert_account("alice").await?;
+
+    let server = httptest::Server::run();
+
+    let calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "feed".to_string(),
+            server.url("/feed.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let (_, token) = app
+        .add_api_token(user_id, "ci", &["calendars:write".to_string()])
+        .await?;
+    let (_, read_token) = app
+        .add_api_token(user_id, "dashboard", &["calendars:read".to_string()])
+        .await?;
+    let (_, other_token) = app
+        .add_api_token(other_user_id, "ci", &["calendars:write".to_string()])
+        .await?;
+
+    let sync = |token: &str| {
+        actix_web::test::TestRequest::post()
+            .uri(&format!("/api/v1/calendars/{calendar_id}/sync"))
+            .insert_header(("Authorization", format!("Bearer {}", token)))
+            .to_request()
+    };
+
+    // Needs the right scope, and to own the calendar.
+    let resp = actix_web::test::call_service(&actix_app, sync(&read_token)).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let resp = actix_web::test::call_service(&actix_app, sync(&other_token)).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .respond_with(status_code(200).body(ICS)),
+    );
+
+    let resp = actix_web::test::call_service(&actix_app, sync(&token)).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    server.verify_and_clear();
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/feed.ics"))
+            .respond_with(status_code(401)),
+    );
+
+    let resp = actix_web::test::call_service(&actix_app, sync(&token)).await;
+    assert_eq!(resp.status().as_u16(), 502);
+    server.verify_and_clear();
+
+    // Paused calendars aren't synced.
+    app.database
+        .set_calendar_enabled(calendar_id, false)
+        .await?;
+
+    let resp = actix_web::test::call_service(&actix_app, sync(&token)).await;
+    assert_eq!(resp.status().as_u16(), 409);
+
+    Ok(())
+}
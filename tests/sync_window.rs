@@ -0,0 +1,87 @@
+use anyhow::{Context, Error};
+use calendar_bot::calendar::{
+    parse_calendars_to_events, parse_ics_file, SyncWindow, DEFAULT_SYNC_LOOKAHEAD_DAYS,
+    DEFAULT_SYNC_LOOKBACK_DAYS,
+};
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app};
+
+/// A weekly event, starting today.
+fn weekly_ics() -> String {
+    format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:weekly\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Planning\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = Utc::now().format("%Y%m%d"),
+    )
+}
+
+/// Test that only instances within the sync window are returned.
+#[test]
+fn test_sync_window_lookahead() -> Result<(), Error> {
+    let fetched = parse_ics_file(&weekly_ics());
+
+    let now = Utc::now();
+    for lookahead_days in [30, 90] {
+        let window = SyncWindow::new(now, 180, lookahead_days);
+
+        let (_, instances) = parse_calendars_to_events(1, &fetched, None, &window, false)?;
+
+        assert!(!instances.is_empty());
+        assert!(instances.iter().all(|i| i.date < window.until));
+        assert!(instances
+            .iter()
+            .any(|i| i.date > now + Duration::days(lookahead_days - 7)));
+    }
+
+    Ok(())
+}
+
+/// Test that calendars use their own sync window if set, falling back to the
+/// config's.
+#[test_log::test(actix_web::test)]
+async fn test_calendar_sync_window() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    let window = app.sync_window(&calendar);
+    assert_eq!(
+        (window.until - window.fetch_from).num_days(),
+        DEFAULT_SYNC_LOOKAHEAD_DAYS + DEFAULT_SYNC_LOOKBACK_DAYS
+    );
+
+    app.database
+        .set_calendar_sync_window_days(calendar_id, Some(90), Some(30))
+        .await?;
+
+    let calendar = app
+        .database
+        .get_calendar(calendar_id)
+        .await?
+        .context("calendar")?;
+    let window = app.sync_window(&calendar);
+    assert_eq!((window.until - window.fetch_from).num_days(), 120);
+
+    Ok(())
+}
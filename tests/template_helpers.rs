@@ -0,0 +1,66 @@
+use anyhow::Error;
+use calendar_bot::{
+    locale::default_locale,
+    template_helpers::{reminder_handlebars, truncate},
+};
+use chrono_tz::Tz;
+use serde_json::json;
+
+#[test]
+fn test_truncate() {
+    assert_eq!(truncate("short", 10), "short");
+    assert_eq!(truncate("a long description", 6), "a long…");
+    assert_eq!(truncate("trailing space", 9), "trailing…");
+    assert_eq!(truncate("ünïcödé", 3), "ünï…");
+}
+
+/// Test that the helpers are available when rendering reminder templates.
+#[test]
+fn test_reminder_helpers() -> Result<(), Error> {
+    let handlebars = reminder_handlebars(Tz::Europe__London, default_locale(), None);
+
+    let context = json!({
+        "start": "2024-07-01T09:30:00Z",
+        "minutes_before": 90,
+        "attendee_count": 3,
+        "summary": "Quarterly planning meeting",
+    });
+
+    let render = |template: &str| handlebars.render_template(template, &context);
+
+    assert_eq!(render("{{format_time start}}")?, "10:30");
+    assert_eq!(render(r#"{{format_time start "%H:%M %Z"}}"#)?, "10:30 BST");
+    assert_eq!(
+        render("{{humanize_duration minutes_before}}")?,
+        "1 hour and 30 minutes"
+    );
+    assert_eq!(
+        render(r#"{{pluralize attendee_count "person" "people"}}"#)?,
+        "3 people"
+    );
+    assert_eq!(render(r#"{{pluralize 1 "person" "people"}}"#)?, "1 person");
+    assert_eq!(render("{{truncate summary 9}}")?, "Quarterly…");
+
+    assert!(render(r#"{{format_time summary}}"#).is_err());
+
+    Ok(())
+}
+
+/// Test that truncating the description placeholder truncates the actual
+/// description.
+#[test]
+fn test_truncate_description() -> Result<(), Error> {
+    let handlebars = reminder_handlebars(
+        Tz::UTC,
+        default_locale(),
+        Some(("TOKEN".to_string(), "A very long description".to_string())),
+    );
+
+    let rendered = handlebars.render_template(
+        "{{truncate description 6}}",
+        &json!({"description": "TOKEN"}),
+    )?;
+    assert_eq!(rendered, "A very…");
+
+    Ok(())
+}
@@ -0,0 +1,99 @@
+use calendar_bot::{
+    calendar::{decode_calendars, parse_calendars_to_events, SyncWindow},
+    timezones::{normalize_tzids, resolve_tzid, WINDOWS_TIMEZONES},
+};
+use chrono::{Duration, Timelike, Utc};
+use chrono_tz::{
+    America::New_York,
+    Europe::{Berlin, London},
+    Tz,
+};
+
+/// Test that every Windows timezone maps to a timezone we know about.
+#[test]
+fn test_windows_timezones_resolve() {
+    for (windows, iana) in WINDOWS_TIMEZONES {
+        assert!(
+            iana.parse::<Tz>().is_ok(),
+            "{} maps to unknown timezone {}",
+            windows,
+            iana
+        );
+    }
+}
+
+/// Test that IANA, Windows and vendor prefixed TZIDs are recognised.
+#[test]
+fn test_resolve_tzid() {
+    assert_eq!(resolve_tzid("Europe/London"), Some(London));
+    assert_eq!(resolve_tzid("GMT Standard Time"), Some(London));
+    assert_eq!(resolve_tzid("\"W. Europe Standard Time\""), Some(Berlin));
+    assert_eq!(
+        resolve_tzid("/mozilla.org/20050126_1/America/New_York"),
+        Some(New_York)
+    );
+    assert_eq!(resolve_tzid("Customized Time Zone"), None);
+}
+
+/// Test that unrecognised TZIDs are only replaced with the default if the
+/// calendar doesn't define them.
+#[test]
+fn test_normalize_tzids() {
+    let body = "BEGIN:VCALENDAR\r
+BEGIN:VTIMEZONE\r
+TZID:Customized Time Zone\r
+END:VTIMEZONE\r
+BEGIN:VEVENT\r
+DTSTART;TZID=GMT Standard Time:20240101T100000\r
+DTEND;TZID=Customized Time Zone:20240101T103000\r
+RECURRENCE-ID;TZID=Unknown:20240101T100000\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    let normalized = normalize_tzids(body, Some(Berlin));
+    assert!(normalized.contains("DTSTART;TZID=Europe/London:20240101T100000\r\n"));
+    assert!(normalized.contains("DTEND;TZID=Customized Time Zone:20240101T103000\r\n"));
+    assert!(normalized.contains("RECURRENCE-ID;TZID=Europe/Berlin:20240101T100000\r\n"));
+
+    // Nothing to change, so the body is returned as is.
+    let body = "BEGIN:VEVENT\r\nDTSTART;TZID=Europe/London:20240101T100000\r\nEND:VEVENT\r\n";
+    assert_eq!(normalize_tzids(body, Some(Berlin)), body);
+}
+
+/// Test that events using Outlook-style TZIDs without a `VTIMEZONE` get the
+/// right instance times.
+#[test]
+fn test_windows_tzid_instances() -> Result<(), anyhow::Error> {
+    let start = (Utc::now() - Duration::days(3)).format("%Y%m%d");
+    let ics = format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:outlook\r
+DTSTAMP:20211124T100000Z\r
+DTSTART;TZID=GMT Standard Time:{start}T100000\r
+DTEND;TZID=GMT Standard Time:{start}T103000\r
+RRULE:FREQ=DAILY\r
+SUMMARY:Standup\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = start,
+    );
+
+    let fetched = decode_calendars(std::iter::once(ics.as_str()), None);
+    assert!(fetched.errors.is_empty());
+
+    let (_, instances) =
+        parse_calendars_to_events(1, &fetched, None, &SyncWindow::default(), false)?;
+    assert!(!instances.is_empty());
+
+    for instance in instances {
+        let local = instance.date.with_timezone(&London);
+        assert_eq!((local.hour(), local.minute()), (10, 0));
+    }
+
+    Ok(())
+}
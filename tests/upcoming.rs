@@ -0,0 +1,55 @@
+use actix_web::test::read_body;
+use anyhow::Error;
+use chrono::{Duration, Utc};
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test that the upcoming events API returns events with reminders in the
+/// room.
+#[test_log::test(actix_web::test)]
+async fn test_upcoming_room_events() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "event1");
+    let instance = test_instance("event1", Utc::now() + Duration::days(1));
+    app.database
+        .insert_events(calendar_id, vec![event], vec![instance])
+        .await?;
+
+    app.database
+        .add_reminder(&test_reminder(user_id, calendar_id, "event1"))
+        .await?;
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/rooms/%21room%3Aexample.com/upcoming")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: serde_json::Value = serde_json::from_slice(&read_body(resp).await)?;
+    assert_eq!(body["room"], "!room:example.com");
+    assert_eq!(body["events"].as_array().map(Vec::len), Some(1));
+    assert_eq!(body["events"][0]["summary"], "Standup");
+
+    // Other rooms shouldn't see the event.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/api/v1/rooms/%21other%3Aexample.com/upcoming")
+        .cookie(cookie)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    let body: serde_json::Value = serde_json::from_slice(&read_body(resp).await)?;
+    assert_eq!(body["events"].as_array().map(Vec::len), Some(0));
+
+    Ok(())
+}
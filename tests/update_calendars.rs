@@ -0,0 +1,92 @@
+use anyhow::Error;
+use calendar_bot::database::CalendarKind;
+use chrono::Utc;
+use httptest::{matchers::request, responders::status_code};
+
+pub mod common;
+
+use common::create_actix_app;
+
+/// A weekly event, starting today.
+fn weekly_ics() -> String {
+    format!(
+        "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:weekly\r
+DTSTAMP:20211124T100000Z\r
+DTSTART:{start}T100000Z\r
+DTEND:{start}T103000Z\r
+RRULE:FREQ=WEEKLY\r
+SUMMARY:Planning\r
+END:VEVENT\r
+END:VCALENDAR\r
+",
+        start = Utc::now().format("%Y%m%d"),
+    )
+}
+
+/// Test that a failing calendar doesn't stop the others from updating, and
+/// that its failure is recorded against it.
+#[test_log::test(actix_web::test)]
+async fn test_update_calendars_isolates_errors() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/broken.ics"))
+            .respond_with(status_code(500)),
+    );
+    server.expect(
+        httptest::Expectation::matching(request::method_path("GET", "/working.ics"))
+            .respond_with(status_code(200).body(weekly_ics())),
+    );
+
+    let broken_calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "broken".to_string(),
+            server.url("/broken.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    let working_calendar_id = app
+        .database
+        .add_calendar_basic_auth(
+            user_id,
+            "working".to_string(),
+            server.url("/working.ics").to_string(),
+            CalendarKind::Ics,
+            None,
+            None,
+            false,
+        )
+        .await?;
+
+    app.update_calendars().await?;
+
+    let errors = app.database.get_calendar_errors(broken_calendar_id).await?;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].message.contains("Failed to fetch calendar"));
+
+    let events = app
+        .database
+        .get_events_in_calendar(working_calendar_id)
+        .await?;
+    assert_eq!(events.len(), 1);
+    assert!(app
+        .database
+        .get_calendar_errors(working_calendar_id)
+        .await?
+        .is_empty());
+
+    Ok(())
+}
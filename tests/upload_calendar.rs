@@ -0,0 +1,79 @@
+use anyhow::Error;
+use calendar_bot::database::CalendarKind;
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+const BOUNDARY: &str = "calbotboundary";
+
+const ICS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//test//EN\r
+BEGIN:VEVENT\r
+UID:event1\r
+DTSTAMP:20240101T000000Z\r
+DTSTART:20990101T100000Z\r
+DTEND:20990101T110000Z\r
+SUMMARY:Standup\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+/// Build a multipart form body with the given name and file.
+fn multipart_body(name: &str, file: &str) -> String {
+    format!(
+        "--{b}\r\n\
+        Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+        {name}\r\n\
+        --{b}\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"calendar.ics\"\r\n\
+        Content-Type: text/calendar\r\n\r\n\
+        {file}\r\n\
+        --{b}--\r\n",
+        b = BOUNDARY,
+        name = name,
+        file = file,
+    )
+}
+
+/// Test that uploading an .ics file creates a static calendar.
+#[test_log::test(actix_web::test)]
+async fn test_upload_calendar() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/upload")
+        .cookie(cookie.clone())
+        .insert_header((
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        ))
+        .set_payload(multipart_body("Uploaded", ICS))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    let calendars = app.database.get_calendars_for_user(user_id).await?;
+    assert_eq!(calendars.len(), 1);
+    assert_eq!(calendars[0].name, "Uploaded");
+    assert_eq!(calendars[0].kind, CalendarKind::Static);
+
+    // Files that aren't calendars are rejected.
+    let req = actix_web::test::TestRequest::post()
+        .uri("/calendar/upload")
+        .cookie(cookie)
+        .insert_header((
+            "Content-Type",
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        ))
+        .set_payload(multipart_body("Not a calendar", "hello"))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    Ok(())
+}
@@ -0,0 +1,271 @@
+use anyhow::{Context, Error};
+use calendar_bot::webhooks::{sign_webhook_payload, webhook_retry_backoff};
+use chrono::{Duration, DurationRound, Utc};
+use httptest::{
+    cycle,
+    matchers::{all_of, contains, request},
+    responders::status_code,
+};
+use serde_json::{json, Value};
+
+pub mod common;
+
+use common::{create_actix_app, create_actix_app_with_config, create_user_and_login};
+
+#[test]
+fn test_sign_webhook_payload() {
+    assert_eq!(
+        sign_webhook_payload("secret", r#"{"type":"reminder.sent"}"#),
+        "sha256=1bd9e0103eec90b3b5650ef910655c12cfd74e1b46cd6f50d90ed750b4729a9b"
+    );
+}
+
+#[test]
+fn test_webhook_retry_backoff() {
+    assert_eq!(webhook_retry_backoff(1), Duration::seconds(30));
+    assert_eq!(webhook_retry_backoff(3), Duration::seconds(120));
+
+    // Doesn't overflow for absurd numbers of attempts.
+    assert!(webhook_retry_backoff(1000) > Duration::days(1));
+}
+
+/// Test that a failed reminder queues a signed payload for the webhooks that
+/// subscribe to failures, and that failed deliveries are retried.
+#[test_log::test(actix_web::test)]
+async fn test_webhook_delivery() -> Result<(), Error> {
+    // The test app has no homeserver, so sending reminders always fails. The
+    // test server is on localhost, so needs to be allowed explicitly.
+    let (app, _db, _actix_app) =
+        create_actix_app_with_config("[webhooks]\nallowed_hosts = [\"127.0.0.1\"]").await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+    let url = server.url("/hook").to_string();
+
+    let (failed_webhook_id, secret) = app
+        .add_webhook(user_id, &url, &["reminder.failed".to_string()])
+        .await?;
+    let (sent_webhook_id, _) = app
+        .add_webhook(user_id, &url, &["reminder.sent".to_string()])
+        .await?;
+
+    let now = Utc::now()
+        .duration_trunc(Duration::seconds(1))
+        .context("truncating")?;
+
+    let remind_at = now + Duration::minutes(5);
+    let reminder_id = app
+        .database
+        .add_adhoc_reminder(user_id, "#team:example.com", "Release today", remind_at)
+        .await?;
+    app.database
+        .add_reminder_retry(reminder_id, remind_at, now, "failed")
+        .await?;
+
+    app.retry_failed_reminders(now).await?;
+
+    let deliveries = app.database.get_due_webhook_deliveries(now, 10).await?;
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0].webhook_id, failed_webhook_id);
+    assert_eq!(deliveries[0].event_type, "reminder.failed");
+
+    let payload: Value = serde_json::from_str(&deliveries[0].payload)?;
+    assert_eq!(payload["type"], "reminder.failed");
+    assert_eq!(payload["data"]["reminder_id"], json!(reminder_id));
+    assert!(payload["data"]["error"].is_string());
+
+    // The first delivery fails, and the retry succeeds.
+    let body = deliveries[0].payload.as_str();
+    let signature = sign_webhook_payload(&secret, body);
+    server.expect(
+        httptest::Expectation::matching(all_of![
+            request::method_path("POST", "/hook"),
+            request::headers(contains(("x-calbot-event", "reminder.failed"))),
+            request::headers(contains(("x-calbot-signature", signature.as_str()))),
+            request::body(body),
+        ])
+        .times(2)
+        .respond_with(cycle![status_code(500), status_code(200)]),
+    );
+
+    app.deliver_webhooks(now).await?;
+
+    let webhooks = app.database.get_webhooks(user_id).await?;
+    let failed_webhook = webhooks
+        .iter()
+        .find(|webhook| webhook.webhook_id == failed_webhook_id)
+        .context("missing webhook")?;
+    assert_eq!(failed_webhook.pending_deliveries, 1);
+    assert!(failed_webhook.last_error.is_some());
+
+    let sent_webhook = webhooks
+        .iter()
+        .find(|webhook| webhook.webhook_id == sent_webhook_id)
+        .context("missing webhook")?;
+    assert_eq!(sent_webhook.pending_deliveries, 0);
+
+    // Not due again yet.
+    assert!(app
+        .database
+        .get_due_webhook_deliveries(now, 10)
+        .await?
+        .is_empty());
+
+    let later = now + webhook_retry_backoff(1);
+    app.deliver_webhooks(later).await?;
+
+    let webhooks = app.database.get_webhooks(user_id).await?;
+    assert!(webhooks
+        .iter()
+        .all(|webhook| webhook.pending_deliveries == 0));
+
+    Ok(())
+}
+
+/// Test that webhooks aren't delivered to internal addresses unless they've
+/// been allowed, and that redirects aren't followed.
+#[test_log::test(actix_web::test)]
+async fn test_webhook_internal_addresses() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let server = httptest::Server::run();
+    let (webhook_id, _) = app
+        .add_webhook(
+            user_id,
+            &server.url("/hook").to_string(),
+            &["reminder.sent".to_string()],
+        )
+        .await?;
+
+    app.database
+        .queue_webhook_deliveries(user_id, "reminder.sent", "{}")
+        .await?;
+
+    // The server expects no requests.
+    app.deliver_webhooks(Utc::now()).await?;
+
+    let webhooks = app.database.get_webhooks(user_id).await?;
+    assert_eq!(webhooks.len(), 1);
+    assert_eq!(webhooks[0].webhook_id, webhook_id);
+    assert_eq!(webhooks[0].pending_deliveries, 1);
+    assert!(webhooks[0]
+        .last_error
+        .as_deref()
+        .is_some_and(|error| error.contains("private or link-local")));
+
+    // Redirects count as failures, rather than being followed.
+    let (app, _db, _actix_app) =
+        create_actix_app_with_config("[webhooks]\nallowed_hosts = [\"127.0.0.1\"]").await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+    app.add_webhook(
+        user_id,
+        &server.url("/hook").to_string(),
+        &["reminder.sent".to_string()],
+    )
+    .await?;
+
+    server.expect(
+        httptest::Expectation::matching(request::method_path("POST", "/hook"))
+            .respond_with(status_code(307).insert_header("Location", "http://169.254.169.254/")),
+    );
+
+    app.database
+        .queue_webhook_deliveries(user_id, "reminder.sent", "{}")
+        .await?;
+    app.deliver_webhooks(Utc::now()).await?;
+
+    let webhooks = app.database.get_webhooks(user_id).await?;
+    assert_eq!(webhooks[0].pending_deliveries, 1);
+    assert!(webhooks[0].last_error.is_some());
+
+    Ok(())
+}
+
+/// Test managing webhooks through the API.
+#[test_log::test(actix_web::test)]
+async fn test_webhooks_api() -> Result<(), Error> {
+    // Allow the example host so that the test doesn't depend on DNS.
+    let (app, _db, actix_app) =
+        create_actix_app_with_config("[webhooks]\nallowed_hosts = [\"example.com\"]").await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    // Internal addresses are rejected.
+    for url in [
+        "http://127.0.0.1/hook",
+        "http://169.254.169.254/latest/meta-data",
+        "http://[::1]/hook",
+        "http://10.0.0.1/hook",
+    ] {
+        let req = actix_web::test::TestRequest::post()
+            .uri("/api/v1/webhooks")
+            .cookie(cookie.clone())
+            .set_json(json!({
+                "url": url,
+                "event_types": ["reminder.sent"],
+            }))
+            .to_request();
+        let resp = actix_web::test::call_service(&actix_app, req).await;
+        assert_eq!(resp.status().as_u16(), 400, "url: {url}");
+    }
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/webhooks")
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "url": "ftp://example.com/hook",
+            "event_types": ["reminder.sent"],
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/webhooks")
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "url": "https://example.com/hook",
+            "event_types": ["reminder.deleted"],
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 400);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/api/v1/webhooks")
+        .cookie(cookie.clone())
+        .set_json(json!({
+            "url": "https://example.com/hook",
+            "event_types": ["reminder.sent", "event.changed"],
+        }))
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let body: Value = actix_web::test::read_body_json(resp).await;
+    let webhook_id = body["webhook_id"].as_i64().context("missing ID")?;
+    assert!(body["secret"].is_string());
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/webhooks")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let req = actix_web::test::TestRequest::delete()
+        .uri(&format!("/api/v1/webhooks/{}", webhook_id))
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    let user_id = app.database.upsert_account("bob").await?;
+    assert!(app.database.get_webhooks(user_id).await?.is_empty());
+
+    Ok(())
+}
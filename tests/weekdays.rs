@@ -0,0 +1,58 @@
+use anyhow::Error;
+use calendar_bot::database::{weekday_in_mask, Reminder, ALL_WEEKDAYS};
+use chrono::{Datelike, Duration, Utc, Weekday};
+
+pub mod common;
+
+use common::{add_test_calendar, create_actix_app, test_event, test_instance, test_reminder};
+
+#[test]
+fn test_weekday_in_mask() {
+    let mon_wed_fri = 0b001_0101;
+
+    assert!(weekday_in_mask(Some(mon_wed_fri), Weekday::Mon));
+    assert!(!weekday_in_mask(Some(mon_wed_fri), Weekday::Tue));
+    assert!(weekday_in_mask(Some(mon_wed_fri), Weekday::Fri));
+    assert!(!weekday_in_mask(Some(mon_wed_fri), Weekday::Sun));
+
+    assert!(weekday_in_mask(None, Weekday::Sun));
+    assert!(weekday_in_mask(Some(ALL_WEEKDAYS), Weekday::Sun));
+}
+
+/// Test that reminders are only queued for the events on the reminder's
+/// weekdays.
+#[test_log::test(actix_web::test)]
+async fn test_weekday_filter() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    let event = test_event(calendar_id, "standup");
+    let instances = (1..=7)
+        .map(|days| test_instance("standup", Utc::now() + Duration::days(days)))
+        .collect();
+    app.database
+        .insert_events(calendar_id, vec![event], instances)
+        .await?;
+
+    app.database
+        .add_reminder(&Reminder {
+            // Monday, Wednesday and Friday
+            weekdays: Some(0b001_0101),
+            ..test_reminder(user_id, calendar_id, "standup")
+        })
+        .await?;
+
+    let reminders = app.database.get_next_reminders().await?;
+    assert_eq!(reminders.len(), 3);
+    for (_, reminder) in reminders {
+        assert!(matches!(
+            reminder.timestamp.weekday(),
+            Weekday::Mon | Weekday::Wed | Weekday::Fri
+        ));
+    }
+
+    Ok(())
+}
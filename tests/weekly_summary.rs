@@ -0,0 +1,105 @@
+use anyhow::Error;
+use calendar_bot::database::Event;
+use chrono::{Datelike, Duration, NaiveTime, Utc};
+use chrono_tz::Tz;
+
+pub mod common;
+
+use common::{
+    add_test_calendar, create_actix_app, create_user_and_login, test_event, test_instance,
+    test_reminder,
+};
+
+/// Test that the weekly summary lists the week's events with reminders,
+/// grouped by day.
+#[test_log::test(actix_web::test)]
+async fn test_render_weekly_summary() -> Result<(), Error> {
+    let (app, _db, _actix_app) = create_actix_app().await?;
+
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let calendar_id = add_test_calendar(&app, user_id).await?;
+
+    // Next week's Monday, so that the event is in the future.
+    let today = Utc::now().date_naive();
+    let week_start = today + Duration::days(7 - today.weekday().num_days_from_monday() as i64);
+    let at = |days: i64, hour: u32| {
+        (week_start + Duration::days(days))
+            .and_time(NaiveTime::from_hms_opt(hour, 0, 0).unwrap())
+            .and_utc()
+    };
+
+    let mut events = Vec::new();
+    let mut instances = Vec::new();
+    for (event_id, summary) in [("standup", "Standup"), ("retro", "Retro")] {
+        events.push(Event {
+            summary: Some(summary.to_string()),
+            ..test_event(calendar_id, event_id)
+        });
+    }
+    instances.push(test_instance("standup", at(0, 10)));
+    instances.push(test_instance("standup", at(2, 10)));
+    // Not in this week.
+    instances.push(test_instance("retro", at(8, 15)));
+    app.database
+        .insert_events(calendar_id, events, instances)
+        .await?;
+
+    for event_id in ["standup", "retro"] {
+        app.database
+            .add_reminder(&test_reminder(user_id, calendar_id, event_id))
+            .await?;
+    }
+
+    let markdown = app
+        .render_weekly_summary(user_id, week_start, Tz::UTC)
+        .await?;
+
+    assert_eq!(markdown.matches("**Standup**").count(), 2, "{}", markdown);
+    assert!(markdown.contains("Monday"), "{}", markdown);
+    assert!(markdown.contains("Wednesday"), "{}", markdown);
+    assert!(!markdown.contains("Retro"), "{}", markdown);
+
+    // The week after only has the retro.
+    let markdown = app
+        .render_weekly_summary(user_id, week_start + Duration::days(7), Tz::UTC)
+        .await?;
+    assert!(markdown.contains("Retro"), "{}", markdown);
+    assert!(!markdown.contains("Standup"), "{}", markdown);
+
+    Ok(())
+}
+
+/// Test that users can opt in to weekly summaries.
+#[test_log::test(actix_web::test)]
+async fn test_weekly_summary_settings() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let settings = app.database.get_weekly_summary_settings(user_id).await?;
+    assert!(!settings.enabled);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/change_matrix_id/weekly_summary")
+        .cookie(cookie.clone())
+        .set_form([("weekly_summary", "on"), ("timezone", "Europe/London")])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    let settings = app.database.get_weekly_summary_settings(user_id).await?;
+    assert!(settings.enabled);
+    assert_eq!(settings.timezone.as_deref(), Some("Europe/London"));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/change_matrix_id/weekly_summary")
+        .cookie(cookie)
+        .set_form([("timezone", "Nowhere/Special")])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status(), 400);
+
+    Ok(())
+}
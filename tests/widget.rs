@@ -0,0 +1,110 @@
+use actix_web::test::read_body;
+use anyhow::{Context, Error};
+
+pub mod common;
+
+use common::{create_actix_app, create_user_and_login};
+
+/// Test that a widget token can be created and used to view the widget
+/// without logging in.
+#[test_log::test(actix_web::test)]
+async fn test_widget_token() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+
+    // Can't view the widget without logging in or a token.
+    let req = actix_web::test::TestRequest::get()
+        .uri("/widget/%21room%3Aexample.com")
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/widget/%21room%3Aexample.com/token")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    let location = resp
+        .headers()
+        .get("Location")
+        .expect("redirect location")
+        .to_str()?
+        .to_string();
+    assert!(location.contains("token="), "location: {}", location);
+
+    let req = actix_web::test::TestRequest::get()
+        .uri(&location)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+
+    // The token is only valid for that room.
+    let other_room = location.replace("%21room", "%21other");
+    let req = actix_web::test::TestRequest::get()
+        .uri(&other_room)
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    Ok(())
+}
+
+/// Test that each room only gets one widget token, which can be listed and
+/// revoked.
+#[test_log::test(actix_web::test)]
+async fn test_widget_token_reuse_and_revoke() -> Result<(), Error> {
+    let (app, _db, actix_app) = create_actix_app().await?;
+
+    let cookie = create_user_and_login(&app, "bob").await?;
+    let user_id = app.database.upsert_account("bob").await?;
+
+    let create_token = || {
+        actix_web::test::TestRequest::post()
+            .uri("/widget/%21room%3Aexample.com/token")
+            .cookie(cookie.clone())
+            .to_request()
+    };
+
+    let mut locations = Vec::new();
+    for _ in 0..2 {
+        let resp = actix_web::test::call_service(&actix_app, create_token()).await;
+        assert!(resp.status().is_redirection(), "status: {}", resp.status());
+        let location = resp.headers().get("Location").context("location")?;
+        locations.push(location.to_str()?.to_string());
+    }
+    assert_eq!(locations[0], locations[1]);
+
+    let tokens = app.database.get_widget_tokens(user_id).await?;
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].room, "!room:example.com");
+
+    let req = actix_web::test::TestRequest::get()
+        .uri("/widgets")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_success(), "status: {}", resp.status());
+    let body = read_body(resp).await;
+    assert!(std::str::from_utf8(&body)?.contains(&tokens[0].token));
+
+    let req = actix_web::test::TestRequest::post()
+        .uri("/widget/%21room%3Aexample.com/token/delete")
+        .cookie(cookie.clone())
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert!(resp.status().is_redirection(), "status: {}", resp.status());
+
+    assert!(app.database.get_widget_tokens(user_id).await?.is_empty());
+
+    // The old link no longer works.
+    let req = actix_web::test::TestRequest::get()
+        .uri(&locations[0])
+        .to_request();
+    let resp = actix_web::test::call_service(&actix_app, req).await;
+    assert_eq!(resp.status().as_u16(), 403);
+
+    Ok(())
+}